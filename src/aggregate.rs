@@ -0,0 +1,922 @@
+use crate::cell::{CellValue, ErrorKind, checked_value};
+use crate::expr_parser::{Criterion, Function};
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+
+/// Computes a range function without storing the result, e.g. for `SUM(A1:A5)+10`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `function` - The function; a `SUMIF` carries its criterion.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CellValue` - The function's value, or `Error` as the matching `eval_*` would set
+pub fn range_value(
+    sheet: &Spreadsheet,
+    function: Function,
+    parent1: i32,
+    parent2: i32,
+) -> CellValue {
+    match function {
+        Function::Sum => range_sum(sheet, parent1, parent2),
+        Function::Avg => range_avg(sheet, parent1, parent2),
+        Function::Min => range_min(sheet, parent1, parent2),
+        Function::Max => range_max(sheet, parent1, parent2),
+        Function::Stdev => range_stdev(sheet, parent1, parent2),
+        Function::Median => range_median(sheet, parent1, parent2),
+        Function::Product => range_product(sheet, parent1, parent2),
+        Function::Spread => range_spread(sheet, parent1, parent2),
+        Function::CountA => CellValue::Integer(count_assigned(sheet, parent1, parent2)),
+        Function::CountBlank => range_count_blank(sheet, parent1, parent2),
+        Function::SumIf(criterion) => range_sum_if(sheet, Some(criterion), parent1, parent2),
+    }
+}
+
+/// Adds up a range in an `i64`, which cannot overflow on a sheet's worth of `i32`s.
+///
+/// Text and empty cells are skipped.
+///
+/// # Returns
+///
+/// * `Ok((i64, i64))` - The total and how many numbers went into it.
+/// * `Err(ErrorKind)` - The kind of the first error cell
+fn range_total(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> Result<(i64, i64), ErrorKind> {
+    let (mut sum, mut count): (i64, i64) = (0, 0);
+    let (start_row, start_col) = sheet.get_row_col(parent1);
+    let (end_row, end_col) = sheet.get_row_col(parent2);
+    for i in start_row..=end_row {
+        for j in start_col..=end_col {
+            match sheet.get_cell(i, j) {
+                CellValue::Integer(value) => {
+                    sum += i64::from(*value);
+                    count += 1;
+                }
+                CellValue::Text(_) | CellValue::Empty => {}
+                CellValue::Error(kind) => return Err(*kind),
+            }
+        }
+    }
+    Ok((sum, count))
+}
+
+/// Adds up a range; an error cell or a sum that does not fit in an `i32` makes the sum
+/// an error.
+fn range_sum(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> CellValue {
+    match range_total(sheet, parent1, parent2) {
+        Ok((sum, _)) => checked_value(i32::try_from(sum).ok()),
+        Err(kind) => CellValue::Error(kind),
+    }
+}
+
+/// Calculates the sum of a range and sets the cell value.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always, even if errors occur (sets `Error`)
+pub fn sum_value(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    parent1: i32,
+    parent2: i32,
+) -> CommandStatus {
+    *sheet.get_mut_cell(row, col) = range_sum(sheet, parent1, parent2);
+    CommandStatus::CmdOk
+}
+
+/// Multiplies a range, skipping text and empty cells, giving `Error` for an error cell, an
+/// overflow or a range without numbers.
+fn range_product(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> CellValue {
+    let mut product: Option<i32> = Some(1);
+    let mut found = false;
+    let (start_row, start_col) = sheet.get_row_col(parent1);
+    let (end_row, end_col) = sheet.get_row_col(parent2);
+    for i in start_row..=end_row {
+        for j in start_col..=end_col {
+            match sheet.get_cell(i, j) {
+                // Keep scanning after an overflow: an error cell still makes the result `Error`
+                CellValue::Integer(value) => {
+                    product = product.and_then(|product| product.checked_mul(*value));
+                    found = true;
+                }
+                CellValue::Text(_) | CellValue::Empty => {}
+                CellValue::Error(kind) => return CellValue::Error(*kind),
+            }
+        }
+    }
+    if !found {
+        return CellValue::Error(ErrorKind::NoValue);
+    }
+    checked_value(product)
+}
+
+/// Multiplies the values of a range and sets the cell value.
+///
+/// A product that does not fit in an `i32` sets `Error` rather than saturating, so an
+/// overflow never passes for a real value.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always, even if errors occur (sets `Error`)
+pub fn eval_product(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    parent1: i32,
+    parent2: i32,
+) -> CommandStatus {
+    *sheet.get_mut_cell(row, col) = range_product(sheet, parent1, parent2);
+    CommandStatus::CmdOk
+}
+
+/// Takes the largest value of a range minus the smallest, skipping text and empty cells.
+fn range_spread(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> CellValue {
+    let (mut min_value, mut max_value) = (i32::MAX, i32::MIN);
+    let (start_row, start_col) = sheet.get_row_col(parent1);
+    let (end_row, end_col) = sheet.get_row_col(parent2);
+    for i in start_row..=end_row {
+        for j in start_col..=end_col {
+            match sheet.get_cell(i, j) {
+                CellValue::Integer(value) => {
+                    min_value = min_value.min(*value);
+                    max_value = max_value.max(*value);
+                }
+                CellValue::Text(_) | CellValue::Empty => {}
+                CellValue::Error(kind) => return CellValue::Error(*kind),
+            }
+        }
+    }
+
+    // A range without numbers has nothing to spread
+    if min_value > max_value {
+        return CellValue::Error(ErrorKind::NoValue);
+    }
+    let spread = i64::from(max_value) - i64::from(min_value);
+    checked_value(i32::try_from(spread).ok())
+}
+
+/// Sets the cell value to the largest value of a range minus the smallest, in one pass.
+///
+/// A spread that does not fit in an `i32` sets `Error`.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always, even if errors occur (sets `Error`)
+pub fn eval_spread(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    parent1: i32,
+    parent2: i32,
+) -> CommandStatus {
+    *sheet.get_mut_cell(row, col) = range_spread(sheet, parent1, parent2);
+    CommandStatus::CmdOk
+}
+
+/// Counts the cells of a range that were ever assigned, whatever their value.
+fn count_assigned(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> i32 {
+    let (start_row, start_col) = sheet.get_row_col(parent1);
+    let (end_row, end_col) = sheet.get_row_col(parent2);
+    let mut count = 0;
+    for i in start_row..=end_row {
+        for j in start_col..=end_col {
+            if sheet.assigned.contains(&sheet.get_key(i, j)) {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// Sets the cell value to the number of assigned cells in a range.
+///
+/// A cell counts once it has been given a value or formula, even `0` or an error; cells
+/// never assigned are empty and are not counted.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always
+pub fn eval_counta(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    parent1: i32,
+    parent2: i32,
+) -> CommandStatus {
+    let count = count_assigned(sheet, parent1, parent2);
+    *sheet.get_mut_cell(row, col) = CellValue::Integer(count);
+    CommandStatus::CmdOk
+}
+
+/// Counts the cells of a range that were never assigned.
+fn range_count_blank(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> CellValue {
+    let (start_row, start_col) = sheet.get_row_col(parent1);
+    let (end_row, end_col) = sheet.get_row_col(parent2);
+    let cells = (end_row - start_row + 1) as i32 * (end_col - start_col + 1) as i32;
+    CellValue::Integer(cells - count_assigned(sheet, parent1, parent2))
+}
+
+/// Sets the cell value to the number of cells in a range that were never assigned.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always
+pub fn eval_countblank(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    parent1: i32,
+    parent2: i32,
+) -> CommandStatus {
+    *sheet.get_mut_cell(row, col) = range_count_blank(sheet, parent1, parent2);
+    CommandStatus::CmdOk
+}
+
+/// Sums the cells of a range that satisfy a criterion; without one nothing is summed.
+fn range_sum_if(
+    sheet: &Spreadsheet,
+    criterion: Option<Criterion>,
+    parent1: i32,
+    parent2: i32,
+) -> CellValue {
+    let mut sum: i64 = 0;
+    let (start_row, start_col) = sheet.get_row_col(parent1);
+    let (end_row, end_col) = sheet.get_row_col(parent2);
+    for i in start_row..=end_row {
+        for j in start_col..=end_col {
+            match sheet.get_cell(i, j) {
+                CellValue::Integer(value) => {
+                    if criterion.is_some_and(|criterion| criterion.matches(*value)) {
+                        sum += i64::from(*value);
+                    }
+                }
+                // A label or an empty cell never matches a numeric criterion
+                CellValue::Text(_) | CellValue::Empty => {}
+                // An error cell cannot be compared, so it poisons the sum as in SUM
+                CellValue::Error(kind) => return CellValue::Error(*kind),
+            }
+        }
+    }
+    checked_value(i32::try_from(sum).ok())
+}
+
+/// Sums the cells of a range that satisfy the cell's `SUMIF` criterion.
+///
+/// The criterion is read from `sheet.criteria`; a formula without one sums nothing.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always, even if errors occur (sets `Error`)
+pub fn sum_if(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    parent1: i32,
+    parent2: i32,
+) -> CommandStatus {
+    let criterion = sheet.criteria.get(&sheet.get_key(row, col)).copied();
+    *sheet.get_mut_cell(row, col) = range_sum_if(sheet, criterion, parent1, parent2);
+    CommandStatus::CmdOk
+}
+
+/// Counts the cells of a range.
+fn range_count(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> i32 {
+    let (start_row, start_col) = sheet.get_row_col(parent1);
+    let (end_row, end_col) = sheet.get_row_col(parent2);
+    ((end_row - start_row + 1) as i32) * ((end_col - start_col + 1) as i32)
+}
+
+/// Takes the standard deviation of the numbers of a range, rounded to the nearest integer.
+fn range_stdev(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> CellValue {
+    let (sum, count) = match range_total(sheet, parent1, parent2) {
+        Ok((_, 0)) => return CellValue::Error(ErrorKind::DivZero),
+        Ok(total) => total,
+        Err(kind) => return CellValue::Error(kind),
+    };
+    let mean_value = (sum / count) as f64;
+
+    let mut variance = 0.0;
+    let (start_row, start_col) = sheet.get_row_col(parent1);
+    let (end_row, end_col) = sheet.get_row_col(parent2);
+    for i in start_row..=end_row {
+        for j in start_col..=end_col {
+            if let CellValue::Integer(value) = *sheet.get_cell(i, j) {
+                variance += ((value as f64) - (mean_value)).powi(2);
+            }
+        }
+    }
+
+    variance /= count as f64;
+    CellValue::Integer((variance.sqrt() + 0.5) as i32)
+}
+
+/// Calculates the standard deviation of a range (integer part).
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always, even if errors occur (sets `Error`).
+pub fn eval_variance(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    parent1: i32,
+    parent2: i32,
+) -> CommandStatus {
+    *sheet.get_mut_cell(row, col) = range_stdev(sheet, parent1, parent2);
+    CommandStatus::CmdOk
+}
+
+/// Finds the smallest number of a range, skipping text and empty cells.
+fn range_min(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> CellValue {
+    let mut min_value = None;
+    let (start_row, start_col) = sheet.get_row_col(parent1);
+    let (end_row, end_col) = sheet.get_row_col(parent2);
+    for r in start_row..=end_row {
+        for c in start_col..=end_col {
+            match sheet.get_cell(r, c) {
+                CellValue::Integer(value) => {
+                    min_value =
+                        Some(min_value.map_or(*value, |min_value: i32| min_value.min(*value)));
+                }
+                CellValue::Text(_) | CellValue::Empty => {}
+                CellValue::Error(kind) => return CellValue::Error(*kind),
+            }
+        }
+    }
+    // A range without numbers has no smallest one
+    min_value.map_or(CellValue::Error(ErrorKind::NoValue), CellValue::Integer)
+}
+
+/// Finds the minimum value in a range.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always, even if errors occur (sets `Error`)
+pub fn eval_min(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    parent1: i32,
+    parent2: i32,
+) -> CommandStatus {
+    *sheet.get_mut_cell(row, col) = range_min(sheet, parent1, parent2);
+    CommandStatus::CmdOk
+}
+
+/// Finds the largest number of a range, skipping text and empty cells.
+fn range_max(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> CellValue {
+    let mut max_value = None;
+    let (start_row, start_col) = sheet.get_row_col(parent1);
+    let (end_row, end_col) = sheet.get_row_col(parent2);
+    for r in start_row..=end_row {
+        for c in start_col..=end_col {
+            match sheet.get_cell(r, c) {
+                CellValue::Integer(value) => {
+                    max_value =
+                        Some(max_value.map_or(*value, |max_value: i32| max_value.max(*value)));
+                }
+                CellValue::Text(_) | CellValue::Empty => {}
+                CellValue::Error(kind) => return CellValue::Error(*kind),
+            }
+        }
+    }
+    // A range without numbers has no largest one
+    max_value.map_or(CellValue::Error(ErrorKind::NoValue), CellValue::Integer)
+}
+
+/// Finds the maximum value in a range.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always, even if errors occur (sets `Error`)
+pub fn eval_max(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    parent1: i32,
+    parent2: i32,
+) -> CommandStatus {
+    *sheet.get_mut_cell(row, col) = range_max(sheet, parent1, parent2);
+    CommandStatus::CmdOk
+}
+
+/// Takes the mean of the numbers of a range, truncated toward zero; text and empty cells
+/// are skipped, so they do not count toward the divisor.
+fn range_avg(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> CellValue {
+    // The mean of i32s is an i32 even when their sum is not
+    match range_total(sheet, parent1, parent2) {
+        Ok((sum, count)) if count > 0 => CellValue::Integer((sum / count) as i32),
+        Ok(_) => CellValue::Error(ErrorKind::DivZero),
+        Err(kind) => CellValue::Error(kind),
+    }
+}
+
+/// Calculates the average of a range.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always, even if errors occur (sets `Error`)
+pub fn eval_avg(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    parent1: i32,
+    parent2: i32,
+) -> CommandStatus {
+    *sheet.get_mut_cell(row, col) = range_avg(sheet, parent1, parent2);
+    CommandStatus::CmdOk
+}
+
+/// Finds the middle number of a range, averaging the two middle numbers for an even count;
+/// text and empty cells are skipped.
+fn range_median(sheet: &Spreadsheet, parent1: i32, parent2: i32) -> CellValue {
+    let mut values = Vec::with_capacity(range_count(sheet, parent1, parent2) as usize);
+    let (start_row, start_col) = sheet.get_row_col(parent1);
+    let (end_row, end_col) = sheet.get_row_col(parent2);
+    for r in start_row..=end_row {
+        for c in start_col..=end_col {
+            match sheet.get_cell(r, c) {
+                CellValue::Integer(value) => values.push(*value),
+                CellValue::Text(_) | CellValue::Empty => {}
+                CellValue::Error(kind) => return CellValue::Error(*kind),
+            }
+        }
+    }
+
+    if values.is_empty() {
+        return CellValue::Error(ErrorKind::NoValue);
+    }
+    values.sort_unstable();
+    let middle = values.len() / 2;
+    let median = if values.len() % 2 == 1 {
+        values[middle]
+    } else {
+        // Widen so two large values do not overflow before halving
+        ((i64::from(values[middle - 1]) + i64::from(values[middle])) / 2) as i32
+    };
+    CellValue::Integer(median)
+}
+
+/// Finds the median of a range: the middle value, or for an even count the mean of the
+/// two middle values truncated toward zero.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `parent1` - Key of the range’s start cell.
+/// * `parent2` - Key of the range’s end cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always, even if errors occur (sets `Error`)
+pub fn eval_median(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    parent1: i32,
+    parent2: i32,
+) -> CommandStatus {
+    *sheet.get_mut_cell(row, col) = range_median(sheet, parent1, parent2);
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::CellValue;
+    use crate::expr_parser::{Comparison, Criterion};
+    use crate::spreadsheet::{CommandStatus, Spreadsheet};
+
+    fn create_test_spreadsheet(rows: i16, cols: i16) -> Spreadsheet {
+        Spreadsheet::create(rows, cols).unwrap()
+    }
+
+    #[test]
+    fn test_sum_value() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(1);
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(2);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(0, 1);
+        assert_eq!(
+            sum_value(&mut sheet, 1, 1, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(3));
+    }
+
+    #[test]
+    fn test_sum_value_error() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(1);
+        *sheet.get_mut_cell(0, 1) = CellValue::Error(ErrorKind::DivZero);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(0, 1);
+        assert_eq!(
+            sum_value(&mut sheet, 1, 1, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_sum_overflow_is_error() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(i32::MAX);
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(1);
+        *sheet.get_mut_cell(0, 2) = CellValue::Integer(-1);
+        let parent1 = sheet.get_key(0, 0);
+        let (pair, triple) = (sheet.get_key(0, 1), sheet.get_key(0, 2));
+        // A sum that only overflows part way through still fits at the end
+        sum_value(&mut sheet, 1, 1, parent1, triple);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(i32::MAX));
+        sum_value(&mut sheet, 1, 1, parent1, pair);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::Overflow));
+        // The average of the same cells fits, so it is not an error
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(i32::MAX - 2);
+        eval_avg(&mut sheet, 1, 1, parent1, pair);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(i32::MAX - 1));
+        eval_variance(&mut sheet, 1, 1, parent1, pair);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(1));
+    }
+
+    #[test]
+    fn test_eval_variance() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(2);
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(4);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(0, 1);
+        assert_eq!(
+            eval_variance(&mut sheet, 1, 1, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(1));
+    }
+
+    #[test]
+    fn test_eval_variance_error() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Error(ErrorKind::DivZero);
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(4);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(0, 1);
+        assert_eq!(
+            eval_variance(&mut sheet, 1, 1, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_eval_min() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(1);
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(3);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(0, 1);
+        assert_eq!(
+            eval_min(&mut sheet, 1, 1, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(1));
+    }
+
+    #[test]
+    fn test_eval_max() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(1);
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(3);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(0, 1);
+        assert_eq!(
+            eval_max(&mut sheet, 1, 2, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 2), CellValue::Integer(3));
+    }
+
+    #[test]
+    fn test_eval_min_max_error() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(1);
+        *sheet.get_mut_cell(0, 1) = CellValue::Error(ErrorKind::DivZero);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(0, 1);
+        assert_eq!(
+            eval_min(&mut sheet, 1, 1, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero));
+        assert_eq!(
+            eval_max(&mut sheet, 1, 2, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 2), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_eval_avg() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(2);
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(4);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(0, 1);
+        assert_eq!(
+            eval_avg(&mut sheet, 1, 1, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(3));
+    }
+
+    #[test]
+    fn test_eval_avg_divides_by_filled_cells() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(2);
+        *sheet.get_mut_cell(0, 4) = CellValue::Integer(4);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(0, 4);
+        // Three empty cells sit between the two numbers and do not count toward the divisor
+        assert_eq!(
+            eval_avg(&mut sheet, 1, 1, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(3));
+
+        // An assigned 0 is a number and does count
+        *sheet.get_mut_cell(0, 2) = CellValue::Integer(0);
+        eval_avg(&mut sheet, 1, 1, parent1, parent2);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(2));
+
+        // A range of only empty cells has no mean
+        let parent1 = sheet.get_key(2, 0);
+        let parent2 = sheet.get_key(2, 4);
+        eval_avg(&mut sheet, 1, 1, parent1, parent2);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_range_functions_skip_empty_cells() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(5);
+        *sheet.get_mut_cell(0, 3) = CellValue::Integer(9);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(0, 4);
+        // Counting the empty cells as 0 would give a minimum of 0 and a spread of 9
+        eval_min(&mut sheet, 1, 0, parent1, parent2);
+        eval_max(&mut sheet, 1, 1, parent1, parent2);
+        sum_value(&mut sheet, 1, 2, parent1, parent2);
+        eval_variance(&mut sheet, 1, 3, parent1, parent2);
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(5));
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(9));
+        assert_eq!(*sheet.get_cell(1, 2), CellValue::Integer(14));
+        assert_eq!(*sheet.get_cell(1, 3), CellValue::Integer(2));
+
+        // A range with no numbers at all has no extreme and no mean, which is not a bad
+        // reference
+        let parent1 = sheet.get_key(2, 0);
+        let parent2 = sheet.get_key(2, 4);
+        eval_min(&mut sheet, 3, 0, parent1, parent2);
+        eval_max(&mut sheet, 3, 1, parent1, parent2);
+        eval_avg(&mut sheet, 3, 2, parent1, parent2);
+        assert_eq!(*sheet.get_cell(3, 0), CellValue::Error(ErrorKind::NoValue));
+        assert_eq!(*sheet.get_cell(3, 1), CellValue::Error(ErrorKind::NoValue));
+        assert_eq!(*sheet.get_cell(3, 2), CellValue::Error(ErrorKind::DivZero));
+        assert_eq!(ErrorKind::NoValue.code(), "#VALUE");
+    }
+
+    /// Writes `values` into row 0 and returns the median of that row, computed into B2.
+    fn median_of(values: &[i32]) -> CellValue {
+        let mut sheet = create_test_spreadsheet(5, 10);
+        for (col, &value) in values.iter().enumerate() {
+            *sheet.get_mut_cell(0, col as i16) = CellValue::Integer(value);
+        }
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(0, values.len() as i16 - 1);
+        assert_eq!(
+            eval_median(&mut sheet, 1, 1, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        sheet.get_cell(1, 1).clone()
+    }
+
+    #[test]
+    fn test_eval_median_odd_and_even_counts() {
+        assert_eq!(median_of(&[7]), CellValue::Integer(7));
+        assert_eq!(median_of(&[9, 1, 5]), CellValue::Integer(5));
+        assert_eq!(median_of(&[4, 1, 3, 2]), CellValue::Integer(2));
+        assert_eq!(median_of(&[10, 1, 4, 8, 3, 3]), CellValue::Integer(3));
+        assert_eq!(
+            median_of(&[i32::MAX, i32::MAX - 2]),
+            CellValue::Integer(i32::MAX - 1)
+        );
+    }
+
+    #[test]
+    fn test_eval_median_negative_values() {
+        assert_eq!(median_of(&[-5, 3, -1]), CellValue::Integer(-1));
+        // The mean of the middle values is truncated toward zero
+        assert_eq!(median_of(&[-3, -2, 0, -9]), CellValue::Integer(-2));
+        assert_eq!(median_of(&[-4, 1]), CellValue::Integer(-1));
+    }
+
+    #[test]
+    fn test_eval_product() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(2);
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(-3);
+        *sheet.get_mut_cell(1, 0) = CellValue::Integer(4);
+        *sheet.get_mut_cell(1, 1) = CellValue::Integer(5);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(1, 1);
+        assert_eq!(
+            eval_product(&mut sheet, 2, 2, parent1, parent2),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Integer(-120));
+
+        // Overflow is an error, even though a later zero would bring it back in range
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(i32::MAX);
+        *sheet.get_mut_cell(1, 1) = CellValue::Integer(0);
+        eval_product(&mut sheet, 2, 2, parent1, parent2);
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Error(ErrorKind::Overflow));
+
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(1);
+        *sheet.get_mut_cell(1, 0) = CellValue::Error(ErrorKind::DivZero);
+        eval_product(&mut sheet, 2, 2, parent1, parent2);
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_eval_spread() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let key = |sheet: &Spreadsheet, row, col| sheet.get_key(row, col);
+
+        // A single cell has no spread
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(-7);
+        let a1 = key(&sheet, 0, 0);
+        eval_spread(&mut sheet, 4, 4, a1, a1);
+        assert_eq!(*sheet.get_cell(4, 4), CellValue::Integer(0));
+
+        for (row, value) in [-7, 12, -20, 3].into_iter().enumerate() {
+            *sheet.get_mut_cell(row as i16, 0) = CellValue::Integer(value);
+        }
+        let a4 = key(&sheet, 3, 0);
+        assert_eq!(eval_spread(&mut sheet, 4, 4, a1, a4), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(4, 4), CellValue::Integer(32));
+
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(i32::MIN);
+        eval_spread(&mut sheet, 4, 4, a1, a4);
+        assert_eq!(*sheet.get_cell(4, 4), CellValue::Error(ErrorKind::Overflow));
+
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(0);
+        *sheet.get_mut_cell(2, 0) = CellValue::Error(ErrorKind::DivZero);
+        eval_spread(&mut sheet, 4, 4, a1, a4);
+        assert_eq!(*sheet.get_cell(4, 4), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_eval_counta_and_countblank() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(2, 1);
+        // No cell was assigned
+        eval_counta(&mut sheet, 4, 4, parent1, parent2);
+        assert_eq!(*sheet.get_cell(4, 4), CellValue::Integer(0));
+        eval_countblank(&mut sheet, 4, 3, parent1, parent2);
+        assert_eq!(*sheet.get_cell(4, 3), CellValue::Integer(6));
+
+        // An assigned zero or error counts; assignments outside the range do not
+        *sheet.get_mut_cell(1, 1) = CellValue::Error(ErrorKind::DivZero);
+        for (row, col) in [(0, 0), (1, 1), (3, 3)] {
+            let key = sheet.get_key(row, col);
+            sheet.assigned.insert(key);
+        }
+        eval_counta(&mut sheet, 4, 4, parent1, parent2);
+        assert_eq!(*sheet.get_cell(4, 4), CellValue::Integer(2));
+        eval_countblank(&mut sheet, 4, 3, parent1, parent2);
+        assert_eq!(*sheet.get_cell(4, 3), CellValue::Integer(4));
+    }
+
+    #[test]
+    fn test_sum_if() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        for (row, value) in [3, -2, 8, 0].into_iter().enumerate() {
+            *sheet.get_mut_cell(row as i16, 0) = CellValue::Integer(value);
+        }
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(3, 0);
+        let key = sheet.get_key(0, 1);
+        for (op, value, expected) in [
+            (Comparison::Greater, 0, 11),
+            (Comparison::LessEqual, 0, -2),
+            (Comparison::Equal, 8, 8),
+            (Comparison::NotEqual, 3, 6),
+            (Comparison::Less, -5, 0),
+        ] {
+            sheet.criteria.insert(key, Criterion { op, value });
+            sum_if(&mut sheet, 0, 1, parent1, parent2);
+            assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(expected));
+        }
+
+        *sheet.get_mut_cell(2, 0) = CellValue::Error(ErrorKind::DivZero);
+        sum_if(&mut sheet, 0, 1, parent1, parent2);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_eval_median_error() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(2);
+        *sheet.get_mut_cell(1, 0) = CellValue::Error(ErrorKind::DivZero);
+        let parent1 = sheet.get_key(0, 0);
+        let parent2 = sheet.get_key(2, 0);
+        eval_median(&mut sheet, 0, 2, parent1, parent2);
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Error(ErrorKind::DivZero));
+    }
+}
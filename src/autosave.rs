@@ -0,0 +1,267 @@
+//! Autosave: `autosave on <file>`, `autosave off` and `autosave`.
+//!
+//! While autosave is on, the command loop counts the successful commands that may change
+//! the sheet, the ones read-only mode would refuse, and saves after every
+//! `set autosave_every` of them (10 unless set) and once more when the session ends.
+//! Nothing is written while the sheet has no unsaved changes. A save that fails is
+//! reported once and pauses autosave until it is turned on again, so a full disk does not
+//! print the same warning every few commands; the end of the session still tries one last time.
+
+use crate::read_only::is_read_only_command;
+use crate::save_load::{save_spreadsheet, save_tracked};
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+
+/// Changing commands between automatic saves unless `set autosave_every` says otherwise.
+pub const DEFAULT_AUTOSAVE_EVERY: usize = 10;
+
+/// Where the sheet is saved automatically, and how far the next save is.
+///
+/// # Fields
+///
+/// * `file` - The file saves go to.
+/// * `pending` - Changing commands since the last save.
+/// * `paused` - Whether a save failed, stopping autosave until it is turned on again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Autosave {
+    pub file: String,
+    pending: usize,
+    paused: bool,
+}
+
+impl Autosave {
+    /// Creates an autosave with nothing pending.
+    fn new(file: &str) -> Autosave {
+        Autosave {
+            file: file.to_string(),
+            pending: 0,
+            paused: false,
+        }
+    }
+}
+
+/// Handles `autosave on <file>`, `autosave off`, and `autosave`, which prints the current
+/// setting.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `autosave`.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If the arguments are malformed
+pub fn handle_autosave(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    match args.trim() {
+        "" => {
+            let line = match &sheet.autosave {
+                None => "autosave is off".to_string(),
+                Some(autosave) => format!(
+                    "autosave to '{}' every {} command(s){}",
+                    autosave.file,
+                    sheet.autosave_every,
+                    if autosave.paused {
+                        ", paused after a failed save"
+                    } else {
+                        ""
+                    }
+                ),
+            };
+            sheet.output.line(line);
+        }
+        "off" => sheet.autosave = None,
+        args => match args.strip_prefix("on ").map(str::trim) {
+            // Like other file arguments, the name is the rest of the line
+            Some(file) if !file.is_empty() => sheet.autosave = Some(Autosave::new(file)),
+            _ => return CommandStatus::Unrecognized,
+        },
+    }
+    CommandStatus::CmdOk
+}
+
+/// Counts a command the loop ran and saves when enough changing commands have succeeded.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `command` - The command, as it was run.
+/// * `status` - What it returned; failed commands are not counted.
+pub fn after_command(sheet: &mut Spreadsheet, command: &str, status: &CommandStatus) {
+    let Some(autosave) = sheet.autosave.as_mut() else {
+        return;
+    };
+    if *status != CommandStatus::CmdOk || is_read_only_command(command) {
+        return;
+    }
+    // A paused autosave still counts, for the last try at the end of the session
+    autosave.pending += 1;
+    if !autosave.paused && autosave.pending >= sheet.autosave_every {
+        save_now(sheet);
+    }
+}
+
+/// Saves any changes still pending when the session ends, by `q` or the end of input,
+/// even if autosave is paused.
+pub fn at_session_end(sheet: &mut Spreadsheet) {
+    if let Some(autosave) = sheet.autosave.as_mut()
+        && autosave.pending > 0
+    {
+        autosave.paused = false;
+        save_now(sheet);
+    }
+}
+
+/// Saves the sheet to the autosave file if it has unsaved changes.
+///
+/// Saving to the sheet's own file goes through `save_tracked`, so a later `save` still
+/// notices changes made by another program; any other file is written as a copy and the
+/// sheet keeps its unsaved changes.
+fn save_now(sheet: &mut Spreadsheet) {
+    let Some(autosave) = sheet.autosave.as_mut() else {
+        return;
+    };
+    autosave.pending = 0;
+    if !sheet.dirty {
+        return;
+    }
+    let file = autosave.file.clone();
+    let status = if sheet.current_file.as_deref() == Some(file.as_str()) {
+        save_tracked(sheet, &file, false)
+    } else {
+        save_spreadsheet(sheet, &file)
+    };
+    if status != CommandStatus::CmdOk {
+        eprintln!(
+            "Autosave to '{}' failed ({}); autosave is paused until `autosave on` is given again",
+            file,
+            status.as_str()
+        );
+        if let Some(autosave) = sheet.autosave.as_mut() {
+            autosave.paused = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repl::{SessionOptions, run_session};
+    use crate::spreadsheet::{CommandStatus, Spreadsheet};
+    use crate::test_utils::ScriptedSheet;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_autosave_setting() {
+        ScriptedSheet::new(5, 5)
+            .run(&["autosave"])
+            .assert_output(&["autosave is off"])
+            .run(&["autosave on work.sheet", "autosave"])
+            .assert_status_ok()
+            .assert_output(&["autosave to 'work.sheet' every 10 command(s)"])
+            .run(&[
+                "set autosave_every 3",
+                "autosave on my work.sheet",
+                "autosave",
+            ])
+            .assert_status_ok()
+            .assert_output(&["autosave to 'my work.sheet' every 3 command(s)"])
+            .run(&["set autosave_every 0"])
+            .assert_status(CommandStatus::Unrecognized)
+            .run(&["autosave off", "autosave"])
+            .assert_output(&["autosave is off"]);
+    }
+
+    #[test]
+    fn test_autosave_every_few_changes_and_at_session_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auto.sheet");
+        let file = path.display().to_string();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+
+        // Queries and failed commands do not count towards a save
+        let script = format!(
+            "set autosave_every 2\nautosave on {}\nA1=4\nformula A1\nB1=A1+\nB1=A1*2\nC1=1\n",
+            file
+        );
+        let mut input = Cursor::new(script);
+        run_session(&mut sheet, &mut input, &SessionOptions::default(), 0.0);
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("CELL,B1,8,FORMULA,A1*2"));
+        // C1 was pending when the input ended
+        assert!(saved.contains("CELL,C1,1"));
+        // The sheet's own unsaved changes are left for a real save
+        assert!(sheet.dirty);
+        assert_eq!(sheet.current_file, None);
+
+        // A command that changes nothing leaves the sheet clean, so nothing is written
+        std::fs::remove_file(&path).unwrap();
+        sheet.dirty = false;
+        let mut input = Cursor::new(format!(
+            "set autosave_every 1\nautosave on {}\nunlock_all\n",
+            file
+        ));
+        let (summary, _) = run_session(&mut sheet, &mut input, &SessionOptions::default(), 0.0);
+        assert_eq!(summary.failures, 0);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_autosave_when_quitting() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quit.sheet");
+        let file = path.display().to_string();
+
+        // Declining to save on `q` still writes the pending changes
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut input = Cursor::new(format!("autosave on {}\nA1=6\nq\nn\n", file));
+        run_session(&mut sheet, &mut input, &SessionOptions::default(), 0.0);
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("CELL,A1,6")
+        );
+
+        // Agreeing without a filename saves to the autosave file
+        std::fs::remove_file(&path).unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut input = Cursor::new(format!("autosave on {}\nA1=7\nq\ny\n\n", file));
+        run_session(&mut sheet, &mut input, &SessionOptions::default(), 0.0);
+        assert_eq!(sheet.current_file.as_deref(), Some(file.as_str()));
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("CELL,A1,7")
+        );
+    }
+
+    #[test]
+    fn test_autosave_to_own_file_and_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("own.sheet");
+        let file = path.display().to_string();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut input = Cursor::new(format!(
+            "save {0}\nset autosave_every 1\nautosave on {0}\nA1=3\n",
+            file
+        ));
+        run_session(&mut sheet, &mut input, &SessionOptions::default(), 0.0);
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("CELL,A1,3")
+        );
+        // Saving to the sheet's file counts as saving it
+        assert!(!sheet.dirty);
+
+        // A failed save pauses autosave instead of failing again on every command
+        let missing = dir.path().join("missing").join("x.sheet");
+        let mut input = Cursor::new(format!(
+            "autosave on {}\nA1=4\nA1=5\nautosave\n",
+            missing.display()
+        ));
+        sheet.output = crate::output::OutputSink::capture();
+        run_session(&mut sheet, &mut input, &SessionOptions::default(), 0.0);
+        assert!(sheet.autosave.as_ref().unwrap().paused);
+        assert_eq!(sheet.autosave.as_ref().unwrap().pending, 0);
+        assert!(sheet.output.take()[0].ends_with("every 1 command(s), paused after a failed save"));
+    }
+}
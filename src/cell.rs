@@ -1,4 +1,84 @@
-use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use crate::spreadsheet::{CommandStatus, MAX_COLUMN_NAME_LEN, Spreadsheet};
+
+/// Status returned for a well-formed cell reference that lies outside the sheet.
+#[cfg(feature = "extensions")]
+const OFF_SHEET: CommandStatus = CommandStatus::InvalidCell;
+#[cfg(not(feature = "extensions"))]
+const OFF_SHEET: CommandStatus = CommandStatus::Unrecognized;
+
+/// Why a cell holds an error.
+///
+/// # Variants
+///
+/// * `DivZero` - A division, modulo or `ROUNDM` by zero, or the mean or standard
+///   deviation of a range with no numbers in it.
+/// * `BadRef` - A formula read a row that `delete_row` removed, or a `SUMPRODUCT` whose
+///   ranges it left different in shape.
+/// * `Cycle` - The cell is part of a circular reference, found by `recalc_all`.
+/// * `Overflow` - A result that does not fit in an `i32`.
+/// * `ParseErr` - A value that could not be read as a number, such as a label in
+///   arithmetic or an `ERR` entered directly.
+/// * `NoValue` - Any other range function read a range with no numbers in it, e.g. `MIN`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    DivZero,
+    BadRef,
+    #[cfg(feature = "extensions")]
+    Cycle,
+    Overflow,
+    ParseErr,
+    NoValue,
+}
+
+impl ErrorKind {
+    /// Every kind, in declaration order.
+    #[cfg(feature = "extensions")]
+    pub const ALL: [ErrorKind; 6] = [
+        ErrorKind::DivZero,
+        ErrorKind::BadRef,
+        ErrorKind::Cycle,
+        ErrorKind::Overflow,
+        ErrorKind::ParseErr,
+        ErrorKind::NoValue,
+    ];
+
+    /// Returns the name a saved file records the kind by, e.g. `DIV0`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::DivZero => "DIV0",
+            ErrorKind::BadRef => "REF",
+            #[cfg(feature = "extensions")]
+            ErrorKind::Cycle => "CYCLE",
+            ErrorKind::Overflow => "OVF",
+            ErrorKind::ParseErr => "PARSE",
+            ErrorKind::NoValue => "VALUE",
+        }
+    }
+
+    /// Returns the code shown in the grid, e.g. `#DIV0`.
+    pub fn code(self) -> String {
+        format!("#{}", self.name())
+    }
+
+    /// Reads a kind back from its `name`.
+    #[cfg(feature = "extensions")]
+    pub fn from_name(name: &str) -> Option<ErrorKind> {
+        ErrorKind::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// Returns a short explanation of the kind, as printed by `error`.
+    #[cfg(feature = "extensions")]
+    pub fn explanation(self) -> &'static str {
+        match self {
+            ErrorKind::DivZero => "division by zero",
+            ErrorKind::BadRef => "it read a row that was deleted",
+            ErrorKind::Cycle => "part of a circular reference",
+            ErrorKind::Overflow => "the result does not fit in a 32-bit integer",
+            ErrorKind::ParseErr => "a value could not be read as a number",
+            ErrorKind::NoValue => "a range it reads holds no numbers",
+        }
+    }
+}
 
 /// Represents the possible values a cell in the spreadsheet can hold.
 ///
@@ -7,11 +87,160 @@ use crate::spreadsheet::{CommandStatus, Spreadsheet};
 /// # Variants
 ///
 /// * `Integer(i32)` - A 32-bit integer value.
-/// * `Error` - An error state, typically resulting from invalid operations (e.g., division by zero).
+/// * `Error(ErrorKind)` - An error state, with the reason it started (e.g., division by zero).
+/// * `Text(String)` - A label such as `Revenue`, entered as `A1="Revenue"`.
+/// * `Empty` - A cell never given a value; arithmetic reads it as 0 and range functions
+///   skip it.
 #[derive(Debug, Clone, PartialEq)]
 pub enum CellValue {
     Integer(i32),
-    Error,
+    Error(ErrorKind),
+    Text(String),
+    Empty,
+}
+
+impl CellValue {
+    /// Reads the value as an operand of arithmetic, where an empty cell counts as 0.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(i32)` - The number, or 0 for an empty cell.
+    /// * `Err(ErrorKind)` - The kind of an error, or `ParseErr` for text
+    pub fn number(&self) -> Result<i32, ErrorKind> {
+        match self {
+            CellValue::Integer(value) => Ok(*value),
+            CellValue::Empty => Ok(0),
+            CellValue::Error(kind) => Err(*kind),
+            CellValue::Text(_) => Err(ErrorKind::ParseErr),
+        }
+    }
+
+    /// Checks whether the value is an error of any kind.
+    pub fn is_error(&self) -> bool {
+        matches!(self, CellValue::Error(_))
+    }
+}
+
+/// Turns the result of checked arithmetic into a value.
+///
+/// # Returns
+///
+/// * `CellValue::Integer` - The result.
+/// * `CellValue::Error` - `Overflow` for `None`
+pub fn checked_value(result: Option<i32>) -> CellValue {
+    result.map_or(CellValue::Error(ErrorKind::Overflow), CellValue::Integer)
+}
+
+/// Returns the value a plain reference such as `B1=A1` takes from its cell: the same
+/// value, except that an empty cell gives 0 so the formula cell shows a number.
+pub fn copied_value(value: &CellValue) -> CellValue {
+    match value {
+        CellValue::Empty => CellValue::Integer(0),
+        value => value.clone(),
+    }
+}
+
+/// Computes `left / right`, truncated toward zero.
+///
+/// # Returns
+///
+/// * `CellValue::Integer` - The quotient.
+/// * `CellValue::Error` - `DivZero` if `right` is 0, `Overflow` for `i32::MIN / -1`
+pub fn divide_value(left: i32, right: i32) -> CellValue {
+    if right == 0 {
+        CellValue::Error(ErrorKind::DivZero)
+    } else {
+        checked_value(left.checked_div(right))
+    }
+}
+
+/// Computes `left % right`, the remainder with the sign of `left`.
+///
+/// # Returns
+///
+/// * `CellValue::Integer` - The remainder.
+/// * `CellValue::Error` - `DivZero` if `right` is 0
+pub fn modulo_value(left: i32, right: i32) -> CellValue {
+    if right == 0 {
+        CellValue::Error(ErrorKind::DivZero)
+    } else {
+        // Wrapping only matters for i32::MIN % -1, whose remainder is 0
+        CellValue::Integer(left.wrapping_rem(right))
+    }
+}
+
+/// Computes `base ^ exponent` for integers.
+///
+/// # Returns
+///
+/// * `CellValue::Integer` - The power; `0^0` is 1.
+/// * `CellValue::Error` - `Overflow` if `exponent` is negative or the result does not fit
+///   in an `i32`
+pub fn power_value(base: i32, exponent: i32) -> CellValue {
+    checked_value(
+        u32::try_from(exponent)
+            .ok()
+            .and_then(|exponent| base.checked_pow(exponent)),
+    )
+}
+
+/// Computes the absolute value of an integer.
+///
+/// # Returns
+///
+/// * `CellValue::Integer` - The absolute value.
+/// * `CellValue::Error` - `Overflow` for `i32::MIN`, whose absolute value does not fit in
+///   an `i32`
+pub fn abs_value(value: i32) -> CellValue {
+    checked_value(value.checked_abs())
+}
+
+/// Rounds an integer to the nearest multiple of another, halves away from zero.
+///
+/// The sign of `multiple` is ignored, so `37` rounds to `40` for both `10` and `-10`.
+///
+/// # Returns
+///
+/// * `CellValue::Integer` - The rounded value.
+/// * `CellValue::Error` - `DivZero` if `multiple` is 0, `Overflow` if the result does not
+///   fit in an `i32`
+pub fn round_to_multiple(value: i32, multiple: i32) -> CellValue {
+    if multiple == 0 {
+        return CellValue::Error(ErrorKind::DivZero);
+    }
+    let multiple = i64::from(multiple).abs();
+    let magnitude = (i64::from(value).abs() * 2 + multiple) / (2 * multiple) * multiple;
+    let rounded = if value < 0 { -magnitude } else { magnitude };
+    checked_value(i32::try_from(rounded).ok())
+}
+
+/// Orders two cell values: integers numerically, then text alphabetically, then errors.
+///
+/// # Arguments
+///
+/// * `a` - The first value.
+/// * `b` - The second value.
+///
+/// # Returns
+///
+/// The `Ordering` of `a` relative to `b`
+#[cfg(feature = "extensions")]
+pub fn compare_cell_values(a: &CellValue, b: &CellValue) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+    match (a, b) {
+        (CellValue::Integer(x), CellValue::Integer(y)) => x.cmp(y),
+        (CellValue::Text(x), CellValue::Text(y)) => x.cmp(y),
+        (CellValue::Error(_), CellValue::Error(_)) | (CellValue::Empty, CellValue::Empty) => {
+            Ordering::Equal
+        }
+        // Empty cells go last, after errors
+        (CellValue::Integer(_), _)
+        | (CellValue::Text(_), CellValue::Error(_) | CellValue::Empty)
+        | (CellValue::Error(_), CellValue::Empty) => Ordering::Less,
+        (CellValue::Empty, _)
+        | (CellValue::Error(_), _)
+        | (CellValue::Text(_), CellValue::Integer(_)) => Ordering::Greater,
+    }
 }
 
 /// Parses a cell reference string (e.g., "A1") into zero-based row and column indices.
@@ -21,19 +250,25 @@ pub enum CellValue {
 /// # Arguments
 ///
 /// * `sheet` - A reference to the `Spreadsheet` for column name conversion.
-/// * `cell_ref` - The cell reference string (e.g., "A1", "ZZ10").
+/// * `cell_ref` - The cell reference string (e.g., "A1", "ZZ10"); the column may be in
+///   either case.
 ///
 /// # Returns
 ///
 /// * `Ok((row, col))` - A tuple of zero-based `(row, col)` indices if parsing succeeds.
-/// * `Err(CommandStatus::Unrecognized)` - If the reference is invalid (e.g., empty, malformed, or out of bounds).
+/// * `Err(CommandStatus::Unrecognized)` - If the reference is empty or malformed.
+/// * `Err(CommandStatus::InvalidCell)` - If the reference lies outside the sheet; the
+///   core build reports this as `Unrecognized` too.
 ///
 /// # Examples
 ///
 /// ```rust
+/// # use spreadsheet::cell::parse_cell_reference;
+/// # use spreadsheet::spreadsheet::Spreadsheet;
 /// let sheet = Spreadsheet::create(10, 10).unwrap();
 /// assert_eq!(parse_cell_reference(&sheet, "A1"), Ok((0, 0)));
 /// assert_eq!(parse_cell_reference(&sheet, "B2"), Ok((1, 1)));
+/// assert_eq!(parse_cell_reference(&sheet, "b2"), Ok((1, 1)));
 /// ```
 pub fn parse_cell_reference(
     sheet: &Spreadsheet,
@@ -48,10 +283,10 @@ pub fn parse_cell_reference(
     let mut split_idx = 0;
     let mut col_length = 0;
 
-    while split_idx < cell_ref.len() && cell_ref[split_idx] >= b'A' && cell_ref[split_idx] <= b'Z' {
+    while split_idx < cell_ref.len() && cell_ref[split_idx].is_ascii_alphabetic() {
         col_length += 1;
-        if col_length > 3 {
-            // Max column length (e.g., "ZZZ")
+        if col_length > MAX_COLUMN_NAME_LEN {
+            // Longer than the name of the last column
             return Err(CommandStatus::Unrecognized);
         }
         split_idx += 1;
@@ -73,9 +308,13 @@ pub fn parse_cell_reference(
     let col_name =
         std::str::from_utf8(&cell_ref[0..split_idx]).map_err(|_| CommandStatus::Unrecognized)?;
 
-    // Parse row directly from bytes (avoid string allocation)
+    // Parse row directly from bytes (avoid string allocation), bailing out
+    // before the value can overflow
     let mut row: i16 = 0;
     for &byte in &cell_ref[split_idx..] {
+        if row > sheet.rows {
+            return Err(OFF_SHEET);
+        }
         row = row * 10 + (byte - b'0') as i16;
     }
 
@@ -84,20 +323,111 @@ pub fn parse_cell_reference(
 
     // Convert column name to index
     let col = sheet.column_name_to_index(col_name);
-    // Check row and column bounds
+    // A well-formed reference that is off this sheet is an invalid cell
     if row < 0 || col < 0 || row >= sheet.rows || col >= sheet.cols {
-        return Err(CommandStatus::Unrecognized);
+        return Err(OFF_SHEET);
     }
     Ok((row, col))
 }
 
+/// Flag marking a formula's `parent1` as written in relative form.
+#[cfg(feature = "extensions")]
+pub const RELATIVE_PARENT1: u8 = 1;
+/// Flag marking a formula's `parent2` as written in relative form.
+#[cfg(feature = "extensions")]
+pub const RELATIVE_PARENT2: u8 = 2;
+
+/// Flag marking the column of a formula's `parent1` as pinned with `$` (e.g. `$A1`).
+#[cfg(feature = "extensions")]
+pub const PINNED_COL_PARENT1: u8 = 4;
+/// Flag marking the column of a formula's `parent2` as pinned with `$`.
+#[cfg(feature = "extensions")]
+pub const PINNED_COL_PARENT2: u8 = 8;
+/// Flag marking the row of a formula's `parent1` as pinned with `$` (e.g. `A$1`).
+#[cfg(feature = "extensions")]
+pub const PINNED_ROW_PARENT1: u8 = 16;
+/// Flag marking the row of a formula's `parent2` as pinned with `$`.
+#[cfg(feature = "extensions")]
+pub const PINNED_ROW_PARENT2: u8 = 32;
+
+/// Formats a reference with `$` before its pinned parts (e.g. "$A$1", "B$3").
+///
+/// # Arguments
+///
+/// * `column` - The column name.
+/// * `row` - The zero-based row.
+/// * `pin_col` - Whether the column is pinned.
+/// * `pin_row` - Whether the row is pinned
+#[cfg(feature = "extensions")]
+pub fn format_pinned_reference(column: &str, row: i16, pin_col: bool, pin_row: bool) -> String {
+    let dollar = |pinned: bool| if pinned { "$" } else { "" };
+    format!(
+        "{}{}{}{}",
+        dollar(pin_col),
+        column,
+        dollar(pin_row),
+        row + 1
+    )
+}
+
+/// Resolves a relative reference against the cell the formula belongs to.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet for bounds checking.
+/// * `row` - Row of the formula's own cell.
+/// * `col` - Column of the formula's own cell.
+/// * `row_offset` - Rows from the formula's cell to the target (e.g. -1 for `R[-1]C`).
+/// * `col_offset` - Columns from the formula's cell to the target.
+///
+/// # Returns
+///
+/// * `Ok((row, col))` - The zero-based target coordinates.
+/// * `Err(CommandStatus::InvalidCell)` - If the target is outside the sheet
+#[cfg(feature = "extensions")]
+pub fn resolve_relative_reference(
+    sheet: &Spreadsheet,
+    row: i16,
+    col: i16,
+    row_offset: i32,
+    col_offset: i32,
+) -> Result<(i16, i16), CommandStatus> {
+    let target_row = row as i64 + row_offset as i64;
+    let target_col = col as i64 + col_offset as i64;
+    if target_row < 0
+        || target_col < 0
+        || target_row >= sheet.rows as i64
+        || target_col >= sheet.cols as i64
+    {
+        return Err(CommandStatus::InvalidCell);
+    }
+    Ok((target_row as i16, target_col as i16))
+}
+
+/// Formats the reference from one cell to another in relative form (e.g., "RC[-2]").
+///
+/// # Arguments
+///
+/// * `row`, `col` - The formula's own cell.
+/// * `target_row`, `target_col` - The referenced cell
+#[cfg(feature = "extensions")]
+pub fn format_relative_reference(row: i16, col: i16, target_row: i16, target_col: i16) -> String {
+    let offset = |delta: i16| {
+        if delta == 0 {
+            String::new()
+        } else {
+            format!("[{}]", delta)
+        }
+    };
+    format!("R{}C{}", offset(target_row - row), offset(target_col - col))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::spreadsheet::{CommandStatus, Spreadsheet};
 
     /// Creates a test spreadsheet with the given dimensions
-
     fn create_test_spreadsheet(rows: i16, cols: i16) -> Spreadsheet {
         Spreadsheet::create(rows, cols).unwrap()
     }
@@ -107,14 +437,69 @@ mod tests {
         let sheet = create_test_spreadsheet(10, 10);
         assert_eq!(parse_cell_reference(&sheet, "A1"), Ok((0, 0)));
         assert_eq!(parse_cell_reference(&sheet, "B2"), Ok((1, 1)));
+        assert_eq!(parse_cell_reference(&sheet, "b2"), Ok((1, 1)));
+        assert_eq!(parse_cell_reference(&sheet, "j10"), Ok((9, 9)));
+        assert_eq!(parse_cell_reference(&sheet, "AA10"), Err(OFF_SHEET));
+        assert_eq!(parse_cell_reference(&sheet, "ZZZ999"), Err(OFF_SHEET));
+    }
+
+    #[test]
+    fn test_modulo_and_power_values() {
+        assert_eq!(modulo_value(17, 5), CellValue::Integer(2));
+        assert_eq!(modulo_value(-17, 5), CellValue::Integer(-2));
+        assert_eq!(modulo_value(17, -5), CellValue::Integer(2));
+        assert_eq!(modulo_value(i32::MIN, -1), CellValue::Integer(0));
+        assert_eq!(modulo_value(3, 0), CellValue::Error(ErrorKind::DivZero));
+
+        assert_eq!(power_value(2, 10), CellValue::Integer(1024));
+        assert_eq!(power_value(-3, 3), CellValue::Integer(-27));
+        assert_eq!(power_value(0, 0), CellValue::Integer(1));
+        assert_eq!(power_value(2, -1), CellValue::Error(ErrorKind::Overflow));
+        assert_eq!(power_value(2, 31), CellValue::Error(ErrorKind::Overflow));
+        assert_eq!(power_value(-2, 31), CellValue::Integer(i32::MIN));
+
+        assert_eq!(abs_value(-5), CellValue::Integer(5));
+        assert_eq!(abs_value(i32::MIN), CellValue::Error(ErrorKind::Overflow));
+    }
+
+    #[test]
+    fn test_round_to_multiple() {
+        assert_eq!(round_to_multiple(37, 10), CellValue::Integer(40));
+        assert_eq!(round_to_multiple(34, 10), CellValue::Integer(30));
+        assert_eq!(round_to_multiple(35, 10), CellValue::Integer(40));
+        assert_eq!(round_to_multiple(40, 10), CellValue::Integer(40));
+        assert_eq!(round_to_multiple(-37, 10), CellValue::Integer(-40));
+        assert_eq!(round_to_multiple(-35, 10), CellValue::Integer(-40));
+        assert_eq!(round_to_multiple(-30, 10), CellValue::Integer(-30));
+        assert_eq!(round_to_multiple(37, -10), CellValue::Integer(40));
+        assert_eq!(round_to_multiple(5, 3), CellValue::Integer(6));
+        assert_eq!(round_to_multiple(0, 7), CellValue::Integer(0));
         assert_eq!(
-            parse_cell_reference(&sheet, "AA10"),
-            Err(CommandStatus::Unrecognized)
+            round_to_multiple(37, 0),
+            CellValue::Error(ErrorKind::DivZero)
         );
+        assert_eq!(round_to_multiple(i32::MIN, 1), CellValue::Integer(i32::MIN));
+        assert_eq!(
+            round_to_multiple(i32::MAX, 10),
+            CellValue::Error(ErrorKind::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_parse_cell_reference_at_the_limits() {
+        use crate::spreadsheet::{MAX_COLS, MAX_ROWS};
+        let sheet = create_test_spreadsheet(MAX_ROWS, MAX_COLS);
+        // The last row and column resolve; one past either does not
         assert_eq!(
             parse_cell_reference(&sheet, "ZZZ999"),
+            Ok((MAX_ROWS - 1, MAX_COLS - 1))
+        );
+        assert_eq!(parse_cell_reference(&sheet, "A1000"), Err(OFF_SHEET));
+        assert_eq!(
+            parse_cell_reference(&sheet, "AAAA1"),
             Err(CommandStatus::Unrecognized)
         );
+        assert_eq!(sheet.get_column_name(MAX_COLS), "AAAA");
     }
 
     #[test]
@@ -144,25 +529,105 @@ mod tests {
 
     #[test]
     fn test_parse_cell_reference_bounds() {
+        // Well-formed references off the sheet are invalid cells, not unrecognized
         let sheet = create_test_spreadsheet(10, 10);
+        assert_eq!(parse_cell_reference(&sheet, "A1000"), Err(OFF_SHEET));
+        assert_eq!(parse_cell_reference(&sheet, "ZZZ1000"), Err(OFF_SHEET));
+        assert_eq!(parse_cell_reference(&sheet, "A0"), Err(OFF_SHEET));
+        assert_eq!(parse_cell_reference(&sheet, "K1"), Err(OFF_SHEET));
+        assert_eq!(parse_cell_reference(&sheet, "a11"), Err(OFF_SHEET));
+    }
+
+    #[test]
+    fn test_cell_value_equality() {
+        assert_eq!(CellValue::Integer(42), CellValue::Integer(42));
         assert_eq!(
-            parse_cell_reference(&sheet, "A1000"),
-            Err(CommandStatus::Unrecognized)
+            CellValue::Error(ErrorKind::Overflow),
+            CellValue::Error(ErrorKind::Overflow)
+        );
+        assert_ne!(
+            CellValue::Error(ErrorKind::Overflow),
+            CellValue::Error(ErrorKind::DivZero)
+        );
+        assert_ne!(
+            CellValue::Integer(42),
+            CellValue::Error(ErrorKind::Overflow)
         );
+    }
+}
+
+#[cfg(all(test, feature = "extensions"))]
+mod extension_tests {
+    use super::*;
+    use crate::spreadsheet::{CommandStatus, Spreadsheet};
+
+    #[test]
+    fn test_error_kind_names_and_division() {
+        for kind in ErrorKind::ALL {
+            assert_eq!(ErrorKind::from_name(kind.name()), Some(kind));
+        }
+        assert_eq!(ErrorKind::from_name("ERR"), None);
+        assert_eq!(ErrorKind::DivZero.code(), "#DIV0");
+        assert_eq!(divide_value(7, 2), CellValue::Integer(3));
+        assert_eq!(divide_value(7, 0), CellValue::Error(ErrorKind::DivZero));
         assert_eq!(
-            parse_cell_reference(&sheet, "ZZZ1000"),
-            Err(CommandStatus::Unrecognized)
+            divide_value(i32::MIN, -1),
+            CellValue::Error(ErrorKind::Overflow)
         );
         assert_eq!(
-            parse_cell_reference(&sheet, "A0"),
-            Err(CommandStatus::Unrecognized)
+            CellValue::Text("x".to_string()).number(),
+            Err(ErrorKind::ParseErr)
+        );
+    }
+    #[test]
+    fn test_compare_cell_values() {
+        use std::cmp::Ordering;
+        let one = CellValue::Integer(1);
+        let two = CellValue::Integer(2);
+        assert_eq!(compare_cell_values(&one, &two), Ordering::Less);
+        assert_eq!(compare_cell_values(&two, &two), Ordering::Equal);
+        assert_eq!(
+            compare_cell_values(&CellValue::Error(ErrorKind::DivZero), &two),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare_cell_values(
+                &CellValue::Error(ErrorKind::DivZero),
+                &CellValue::Error(ErrorKind::Cycle)
+            ),
+            Ordering::Equal
         );
     }
 
     #[test]
-    fn test_cell_value_equality() {
-        assert_eq!(CellValue::Integer(42), CellValue::Integer(42));
-        assert_eq!(CellValue::Error, CellValue::Error);
-        assert_ne!(CellValue::Integer(42), CellValue::Error);
+    fn test_resolve_relative_reference_bounds() {
+        let sheet = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(resolve_relative_reference(&sheet, 1, 2, 0, -2), Ok((1, 0)));
+        assert_eq!(
+            resolve_relative_reference(&sheet, 1, 2, 0, -3),
+            Err(CommandStatus::InvalidCell)
+        );
+        assert_eq!(
+            resolve_relative_reference(&sheet, 1, 2, 4, 0),
+            Err(CommandStatus::InvalidCell)
+        );
+        assert_eq!(
+            resolve_relative_reference(&sheet, 1, 2, i32::MAX, 0),
+            Err(CommandStatus::InvalidCell)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_format_pinned_reference() {
+        assert_eq!(format_pinned_reference("C", 2, false, true), "C$3");
+        assert_eq!(format_pinned_reference("AB", 0, true, true), "$AB$1");
+    }
+
+    #[test]
+    fn test_format_relative_reference() {
+        assert_eq!(format_relative_reference(1, 2, 1, 0), "RC[-2]");
+        assert_eq!(format_relative_reference(3, 0, 2, 0), "R[-1]C");
+        assert_eq!(format_relative_reference(0, 0, 1, 3), "R[1]C[3]");
     }
 }
@@ -0,0 +1,551 @@
+//! The per-cell value history behind `history` and `show_history`.
+//!
+//! Every assignment pushes the value it replaces onto the cell's history, together with
+//! the formula that value came from. `history A1` takes the most recent entry off and
+//! puts it back in the cell, formula and all, and `history A1 3` goes back three entries
+//! in one step. `show_history A1` only lists the entries with their formulas, numbered
+//! the way `history` counts them, so looking never changes anything.
+//!
+//! Each cell keeps at most `history_limit` entries, dropping the oldest first, so a cell
+//! a script rewrites thousands of times does not grow without bound. `set history_limit 0`
+//! turns history off, for bulk loads.
+
+use crate::cell::CellValue;
+use crate::evaluator::resolve_cell_reference;
+use crate::expr_parser::Criterion;
+use crate::extended_formula::ExtendedFormula;
+use crate::extensions::{history_formula_string, set_cell_to_value};
+use crate::reevaluate_topo::{reevaluate_formula, toposort_reval_detect_cycle};
+use crate::sort::CellSnapshot;
+use crate::spreadsheet::{CellMeta, CommandStatus, Spreadsheet};
+use crate::table::{Align, Table};
+use std::mem::size_of;
+
+/// How many entries each cell keeps unless `set history_limit` says otherwise.
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
+
+/// A formula a history entry recorded, with the parts of it kept in side tables.
+///
+/// # Fields
+///
+/// * `meta` - The formula's code and parents.
+/// * `relative` - Its relative and pinned reference flags.
+/// * `criterion` - The criterion of a `SUMIF`-style formula.
+/// * `extended` - Its extended formula, such as `A1+B1+1` or `IF(...)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryFormula {
+    pub meta: CellMeta,
+    pub relative: Option<u8>,
+    pub criterion: Option<Criterion>,
+    pub extended: Option<ExtendedFormula>,
+}
+
+/// A value a cell held, and the formula it came from.
+///
+/// A formula of two literals such as `1/0` has no parents to follow, so it is recorded as
+/// its value only, as a literal cell is.
+pub type HistoryEntry = (CellValue, Option<HistoryFormula>);
+
+/// Turns a snapshot into the history entry the cell would have recorded.
+pub fn snapshot_entry(snapshot: &CellSnapshot) -> HistoryEntry {
+    let formula = snapshot
+        .meta
+        .filter(|meta| meta.formula != -1 && !snapshot.literals)
+        .map(|meta| HistoryFormula {
+            meta,
+            relative: snapshot.relative,
+            criterion: snapshot.criterion,
+            extended: snapshot.extended.clone(),
+        });
+    (snapshot.value.clone(), formula)
+}
+
+impl Spreadsheet {
+    /// Captures what a cell holds now as a history entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The cell's key.
+    pub fn history_entry(&self, key: i32) -> HistoryEntry {
+        let (row, col) = self.get_row_col(key);
+        snapshot_entry(&CellSnapshot::capture(self, row, col))
+    }
+
+    /// Records what a cell held before a change.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The cell's key.
+    /// * `entry` - The cell's old value and formula.
+    pub fn push_history(&mut self, key: i32, entry: HistoryEntry) {
+        if self.history_limit == 0 {
+            return;
+        }
+        self.journal_history(key);
+        let history = self.cell_history.entry(key).or_default();
+        history.push(entry);
+        if history.len() > self.history_limit {
+            history.drain(..history.len() - self.history_limit);
+        }
+    }
+
+    /// Changes how many entries each cell keeps, dropping the oldest ones over the limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The new limit; 0 drops all history and records no more.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+        self.journal_all_history();
+        self.cell_history.retain(|_, history| {
+            if history.len() > limit {
+                history.drain(..history.len() - limit);
+            }
+            !history.is_empty()
+        });
+    }
+}
+
+/// Formats a history entry, e.g. `5`, `#DIV0` or `"Total"`.
+fn describe_entry(value: &CellValue) -> String {
+    match value {
+        CellValue::Integer(value) => value.to_string(),
+        CellValue::Error(kind) => kind.code(),
+        CellValue::Text(text) => format!("\"{}\"", text),
+        CellValue::Empty => "empty".to_string(),
+    }
+}
+
+/// Lists a cell's history as a table, most recent first and numbered as `history` counts,
+/// with the formula each value came from, e.g. `=A1*2`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `row` - The cell's row.
+/// * `col` - The cell's column.
+///
+/// # Returns
+///
+/// The lines to print; empty if the cell has no history
+pub fn history_lines(sheet: &Spreadsheet, row: i16, col: i16) -> Vec<String> {
+    let Some(history) = sheet.cell_history.get(&sheet.get_key(row, col)) else {
+        return Vec::new();
+    };
+    let mut table = Table::new(&[
+        ("#", Align::Right),
+        ("value", Align::Right),
+        ("formula", Align::Left),
+    ]);
+    for (index, (value, formula)) in history.iter().rev().enumerate() {
+        let formula = formula.as_ref().map_or(String::new(), |formula| {
+            format!("={}", history_formula_string(sheet, row, col, formula))
+        });
+        table.push_row(vec![
+            (index + 1).to_string(),
+            describe_entry(value),
+            formula,
+        ]);
+    }
+    table.render()
+}
+
+/// Writes a history entry back into a cell, re-registering its formula's parents.
+///
+/// A formula is recomputed from its parents as they are now, so the cell follows them
+/// again; one that would close a cycle leaves the cell as it was.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The cell's row.
+/// * `col` - The cell's column.
+/// * `entry` - The value and formula to put back.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::CircularRef` - If the formula would close a cycle.
+/// * `CommandStatus` - As for `set_cell_to_value`
+pub fn restore_entry(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    entry: HistoryEntry,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    let (value, formula) = match entry {
+        (value, Some(formula)) => (value, formula),
+        (value, None) => return set_cell_to_value(sheet, row, col, value, sleep_time),
+    };
+    if sheet.is_cell_locked(row, col) {
+        return CommandStatus::LockedCell;
+    }
+    if sheet.check_owner(row, col) == CommandStatus::NotOwner {
+        return CommandStatus::NotOwner;
+    }
+    let cell_key = sheet.get_key(row, col);
+    sheet.note_undo(cell_key);
+    let before = sheet.cell_contribution(cell_key);
+    let old = CellSnapshot::capture(sheet, row, col);
+    sheet.clear_formula(row, col);
+    let restored = CellSnapshot {
+        value,
+        meta: Some(formula.meta),
+        relative: formula.relative,
+        criterion: formula.criterion,
+        extended: formula.extended,
+        literals: false,
+        assigned: true,
+    };
+    restored.write(sheet, row, col);
+    // The parents may have changed since the entry was recorded
+    reevaluate_formula(sheet, row, col, sleep_time);
+    if toposort_reval_detect_cycle(sheet, row, col, sleep_time) {
+        sheet.clear_formula(row, col);
+        old.write(sheet, row, col);
+        return CommandStatus::CircularRef;
+    }
+    sheet.record_cell_change(cell_key, before);
+    sheet.set_last_edited(row, col);
+    CommandStatus::CmdOk
+}
+
+/// Puts a cell back to the value and formula it had `steps` assignments ago.
+///
+/// The entries gone back over are dropped, as if `history` had been run `steps` times.
+/// Nothing changes if the cell cannot be written.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The cell's row.
+/// * `col` - The cell's column.
+/// * `steps` - How many entries to go back; 1 is the previous value.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `Ok(CommandStatus)` - From writing the value.
+/// * `Err(usize)` - The number of entries the cell has, if fewer than `steps`
+pub fn revert_cell(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    steps: usize,
+    sleep_time: &mut f64,
+) -> Result<CommandStatus, usize> {
+    let cell_key = sheet.get_key(row, col);
+    let len = sheet.cell_history.get(&cell_key).map_or(0, Vec::len);
+    if steps > len {
+        return Err(len);
+    }
+    let keep = len - steps;
+    let entry = sheet.cell_history[&cell_key][keep].clone();
+    let status = restore_entry(sheet, row, col, entry, sleep_time);
+    sheet.journal_history(cell_key);
+    if status == CommandStatus::CmdOk
+        && let Some(history) = sheet.cell_history.get_mut(&cell_key)
+    {
+        history.truncate(keep);
+    }
+    Ok(status)
+}
+
+/// Handles `history <cell> [n]`: puts a cell back to its previous value, or the one `n`
+/// entries back.
+///
+/// A cell with no history is left alone, as before `n` existed; asking for more
+/// entries than the cell has is refused.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `args` - The text after `history `.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success, or if the cell has no history.
+/// * `CommandStatus::Unrecognized` - If `n` is malformed or beyond the history.
+/// * `CommandStatus` - From parsing the cell reference or writing the cell
+pub fn handle_history(sheet: &mut Spreadsheet, args: &str, sleep_time: &mut f64) -> CommandStatus {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let (cell_ref, steps) = match parts[..] {
+        [cell_ref] => (cell_ref, None),
+        [cell_ref, steps] => match steps.parse::<usize>() {
+            Ok(steps) if steps > 0 => (cell_ref, Some(steps)),
+            _ => return CommandStatus::Unrecognized,
+        },
+        _ => return CommandStatus::Unrecognized,
+    };
+    let (row, col) = match resolve_cell_reference(sheet, cell_ref) {
+        Ok(cell) => cell,
+        Err(status) => return status,
+    };
+    match revert_cell(sheet, row, col, steps.unwrap_or(1), sleep_time) {
+        Ok(status) => status,
+        Err(0) if steps.is_none() => CommandStatus::CmdOk,
+        Err(len) => {
+            sheet.output.line(format!(
+                "{} has {} history entr{}",
+                sheet.get_cell_name(row, col),
+                len,
+                if len == 1 { "y" } else { "ies" }
+            ));
+            CommandStatus::Unrecognized
+        }
+    }
+}
+
+/// Estimates the memory the history takes, in bytes.
+///
+/// Counts the entries the vectors have room for, the map's keys and the text of labels;
+/// the map's own hashing overhead is left out.
+fn history_bytes(sheet: &Spreadsheet) -> usize {
+    sheet
+        .cell_history
+        .values()
+        .map(|history| {
+            let text: usize = history
+                .iter()
+                .map(|(value, _)| match value {
+                    CellValue::Text(text) => text.capacity(),
+                    _ => 0,
+                })
+                .sum();
+            size_of::<i32>()
+                + size_of::<Vec<HistoryEntry>>()
+                + history.capacity() * size_of::<HistoryEntry>()
+                + text
+        })
+        .sum()
+}
+
+/// Handles `history_stats`: prints how many entries the history holds, roughly how much
+/// memory they take, and the limit per cell.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always
+pub fn handle_history_stats(sheet: &mut Spreadsheet) -> CommandStatus {
+    let entries: usize = sheet.cell_history.values().map(Vec::len).sum();
+    let limit = match sheet.history_limit {
+        0 => "history is off".to_string(),
+        limit => format!("limit {} per cell", limit),
+    };
+    let line = format!(
+        "{} entr{} in {} cell(s), about {} bytes; {}",
+        entries,
+        if entries == 1 { "y" } else { "ies" },
+        sheet.cell_history.len(),
+        history_bytes(sheet),
+        limit
+    );
+    sheet.output.line(line);
+    CommandStatus::CmdOk
+}
+
+/// Handles `show_history <cell>`: prints a cell's history without changing it.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `show_history `.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus` - From parsing the cell reference
+pub fn handle_show_history(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let (row, col) = match resolve_cell_reference(sheet, args.trim()) {
+        Ok(cell) => cell,
+        Err(status) => return status,
+    };
+    let lines = history_lines(sheet, row, col);
+    if lines.is_empty() {
+        let name = sheet.get_cell_name(row, col);
+        sheet.output.line(format!("{} has no history", name));
+    } else {
+        sheet.output.lines(lines);
+    }
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cell::CellValue;
+    use crate::spreadsheet::CommandStatus;
+    use crate::test_utils::ScriptedSheet;
+
+    #[test]
+    fn test_show_history_leaves_history_alone() {
+        let scripted = ScriptedSheet::new(10, 10)
+            .run(&["A1=1", "A1=2/0", "A1=3", "show_history A1"])
+            .assert_status_ok()
+            .assert_output(&[
+                "#  value  formula",
+                "-  -----  -------",
+                "1  #DIV0",
+                "2      1",
+                "3  empty",
+            ])
+            .run(&["show_history A1"])
+            .assert_output(&[
+                "#  value  formula",
+                "-  -----  -------",
+                "1  #DIV0",
+                "2      1",
+                "3  empty",
+            ])
+            .run(&["show_history B1"])
+            .assert_output(&["B1 has no history"]);
+        assert_eq!(scripted.sheet.cell_history[&0].len(), 3);
+    }
+
+    #[test]
+    fn test_show_history_lists_formulas() {
+        ScriptedSheet::new(10, 10)
+            .run(&["A1=5", "B1=A1*2", "B1=MAX(A1:A3)+1", "B1=A1+1", "B1=7"])
+            .run(&["show_history B1"])
+            .assert_output(&[
+                "#  value  formula",
+                "-  -----  -------------",
+                "1      6  =A1+1",
+                "2      6  =MAX(A1:A3)+1",
+                "3     10  =A1*2",
+                "4  empty",
+            ])
+            .run(&["history B1 2"])
+            .assert_status_ok()
+            .assert_formula("B1", "MAX(A1:A3)+1")
+            .run(&["A3=9"])
+            .assert_cell("B1", 10)
+            .run(&["history B1"])
+            .assert_formula("B1", "A1*2")
+            .assert_cell("B1", 10);
+    }
+
+    #[test]
+    fn test_history_restores_formulas_kept_in_side_tables() {
+        ScriptedSheet::new(10, 10)
+            .run(&["A1=1", "B1=2", "C1=A1+B1+1", "C1=5", "history C1"])
+            .assert_status_ok()
+            .assert_formula("C1", "A1+B1+1")
+            .assert_cell("C1", 4)
+            .run(&["A1=10"])
+            .assert_cell("C1", 13)
+            .run(&["A2=-3", "D1=SUMIF(A1:A2,>=0)", "D1=0", "history D1"])
+            .assert_status_ok()
+            .assert_formula("D1", "SUMIF(A1:A2,>=0)")
+            .run(&["A2=3"])
+            .assert_cell("D1", 13)
+            .run(&["E1=A$1*2", "E1=0", "history E1"])
+            .assert_formula("E1", "A$1*2")
+            .assert_cell("E1", 20);
+    }
+
+    #[test]
+    fn test_history_jumps_back_several_entries() {
+        ScriptedSheet::new(10, 10)
+            .run(&["A1=1", "A1=2", "A1=3", "A1=4", "B1=A1*10", "history A1 3"])
+            .assert_status_ok()
+            .assert_cell("A1", 1)
+            .assert_cell("B1", 10)
+            .run(&["show_history A1"])
+            .assert_output(&["#  value  formula", "-  -----  -------", "1  empty"])
+            .run(&["history A1 2"])
+            .assert_status(CommandStatus::Unrecognized)
+            .assert_output(&["A1 has 1 history entry"])
+            .assert_cell("A1", 1)
+            .run(&["history A1 0"])
+            .assert_status(CommandStatus::Unrecognized)
+            .run(&["history A1"])
+            .assert_status_ok()
+            .assert_value("A1", CellValue::Empty)
+            .run(&["history A1"])
+            .assert_status_ok();
+    }
+
+    #[test]
+    fn test_history_restores_formula_and_dependency() {
+        ScriptedSheet::new(10, 10)
+            .run(&["A1=1", "B1=2", "C1=A1+B1", "D1=C1*10", "C1=5", "A1=4"])
+            .assert_cell("C1", 5)
+            .run(&["history C1"])
+            .assert_status_ok()
+            .assert_formula("C1", "A1+B1")
+            .assert_cell("C1", 6)
+            .assert_cell("D1", 60)
+            .run(&["A1=10"])
+            .assert_cell("C1", 12)
+            .assert_cell("D1", 120);
+    }
+
+    #[test]
+    fn test_history_refuses_formula_closing_a_cycle() {
+        ScriptedSheet::new(10, 10)
+            .run(&["B1=C1+1", "B1=7", "C1=B1*2", "history B1"])
+            .assert_status(CommandStatus::CircularRef)
+            .assert_cell("B1", 7)
+            .assert_formula("B1", "")
+            .assert_cell("C1", 14)
+            .run(&["B1=8"])
+            .assert_cell("C1", 16);
+    }
+
+    #[test]
+    fn test_history_limit_drops_oldest_entries() {
+        let mut scripted = ScriptedSheet::new(10, 10).run(&["set history_limit 3"]);
+        for value in 1..=10 {
+            scripted = scripted.run(&[&format!("A1={}", value)]);
+        }
+        scripted = scripted
+            .run(&["A2=1", "show_history A1"])
+            .assert_status_ok()
+            .assert_output(&[
+                "#  value  formula",
+                "-  -----  -------",
+                "1      9",
+                "2      8",
+                "3      7",
+            ])
+            .run(&["set history_limit 2", "show_history A1"])
+            .assert_output(&[
+                "#  value  formula",
+                "-  -----  -------",
+                "1      9",
+                "2      8",
+            ]);
+        assert_eq!(scripted.sheet.cell_history.len(), 2);
+        scripted = scripted
+            .run(&["set history_limit 0", "A1=11", "history_stats"])
+            .assert_status_ok()
+            .assert_output(&["0 entries in 0 cell(s), about 0 bytes; history is off"]);
+        assert!(scripted.sheet.cell_history.is_empty());
+    }
+
+    #[test]
+    fn test_history_stats_counts_entries() {
+        let scripted = ScriptedSheet::new(10, 10)
+            .run(&["A1=1", "A1=2", "history_stats"])
+            .assert_status_ok();
+        let bytes = super::history_bytes(&scripted.sheet);
+        assert!(bytes >= 2 * std::mem::size_of::<super::HistoryEntry>());
+        let expected = format!(
+            "2 entries in 1 cell(s), about {} bytes; limit 100 per cell",
+            bytes
+        );
+        scripted.assert_output(&[expected.as_str()]);
+    }
+
+    #[test]
+    fn test_history_keeps_entries_of_locked_cell() {
+        let scripted = ScriptedSheet::new(10, 10)
+            .run(&["A1=1", "A1=2", "lock_cell A1", "history A1"])
+            .assert_status(CommandStatus::LockedCell)
+            .assert_cell("A1", 2);
+        assert_eq!(scripted.sheet.cell_history[&0].len(), 2);
+    }
+}
@@ -0,0 +1,384 @@
+//! `changes`: what changed since the sheet was last saved or opened.
+//!
+//! Saving or opening a file records a baseline: the value and formula of every populated
+//! cell (see `Spreadsheet::is_touched`), so a sparse sheet keeps a small baseline. Formulas
+//! are kept in the form `formula` prints them, which names parents by cell and keeps
+//! `$` pins and relative references, so two cells compare equal exactly when they read
+//! the same.
+
+use crate::cell::CellValue;
+use crate::extensions::get_formula_string;
+use crate::range::{Range, resolve_range};
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use std::collections::HashMap;
+
+/// A populated cell as it was at the last save or open.
+///
+/// # Fields
+///
+/// * `value` - The cell's value.
+/// * `formula` - The formula as `formula` prints it, if the cell has one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaselineCell {
+    pub value: CellValue,
+    pub formula: Option<String>,
+}
+
+/// The populated cells at the last save or open, by cell key.
+pub type Baseline = HashMap<i32, BaselineCell>;
+
+/// Differences between the sheet and its baseline, each list in row-major order.
+///
+/// # Fields
+///
+/// * `added` - Cells populated now but empty at the baseline.
+/// * `removed` - Cells populated at the baseline but empty now.
+/// * `value_changed` - Cells with the same formula (or none) and a different value.
+/// * `formula_changed` - Cells whose formula was added, removed or rewritten.
+#[derive(Debug, Default, PartialEq)]
+pub struct ChangeReport {
+    pub added: Vec<i32>,
+    pub removed: Vec<i32>,
+    pub value_changed: Vec<i32>,
+    pub formula_changed: Vec<i32>,
+}
+
+impl ChangeReport {
+    /// Returns the number of changed cells.
+    pub fn total(&self) -> usize {
+        self.added.len()
+            + self.removed.len()
+            + self.value_changed.len()
+            + self.formula_changed.len()
+    }
+}
+
+impl Spreadsheet {
+    /// Iterates over the keys of populated cells in row-major order.
+    pub fn touched_cells(&self) -> impl Iterator<Item = i32> + '_ {
+        (0..self.grid.len() as i32).filter(|&key| self.is_touched(key))
+    }
+
+    /// Returns a populated cell in its baseline form.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The cell key.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(BaselineCell)` - If the cell is populated.
+    /// * `None` - Otherwise
+    fn baseline_cell(&self, key: i32) -> Option<BaselineCell> {
+        if !self.is_touched(key) {
+            return None;
+        }
+        let (row, col) = self.get_row_col(key);
+        let has_formula = self
+            .cell_meta
+            .get(&key)
+            .is_some_and(|meta| meta.formula != -1);
+        Some(BaselineCell {
+            value: self.get_key_cell(key).clone(),
+            formula: has_formula.then(|| get_formula_string(self, row, col)),
+        })
+    }
+
+    /// Records the current populated cells as the state `changes` compares against.
+    pub fn capture_baseline(&mut self) {
+        let baseline = self
+            .touched_cells()
+            .filter_map(|key| Some((key, self.baseline_cell(key)?)))
+            .collect();
+        self.saved_baseline = baseline;
+    }
+
+    /// Compares the sheet with its baseline.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - Only cells inside it are compared; `None` compares the whole sheet.
+    ///
+    /// # Returns
+    ///
+    /// * `ChangeReport` - The changed cells by kind
+    pub fn changes_since_save(&self, range: Option<&Range>) -> ChangeReport {
+        let in_scope = |key: i32| {
+            let (row, col) = self.get_row_col(key);
+            range.is_none_or(|range| range.contains(row, col))
+        };
+        let mut report = ChangeReport::default();
+        for key in self.touched_cells().filter(|&key| in_scope(key)) {
+            let Some(now) = self.baseline_cell(key) else {
+                continue;
+            };
+            match self.saved_baseline.get(&key) {
+                None => report.added.push(key),
+                Some(saved) if saved.formula != now.formula => report.formula_changed.push(key),
+                Some(saved) if saved.value != now.value => report.value_changed.push(key),
+                Some(_) => {}
+            }
+        }
+        report.removed = self
+            .saved_baseline
+            .keys()
+            .copied()
+            .filter(|&key| in_scope(key) && !self.is_touched(key))
+            .collect();
+        report.removed.sort_unstable();
+        report
+    }
+}
+
+/// Formats a value and formula, e.g. `22 (=SUM(A1:A3))`.
+fn describe(value: &CellValue, formula: Option<&str>) -> String {
+    let value = match value {
+        CellValue::Integer(value) => value.to_string(),
+        CellValue::Error(kind) => kind.code(),
+        CellValue::Text(text) => format!("\"{}\"", text),
+        CellValue::Empty => "empty".to_string(),
+    };
+    match formula {
+        Some(formula) => format!("{} (={})", value, formula),
+        None => value,
+    }
+}
+
+/// Builds the totals line, e.g. `3 change(s) since the last save: 1 added, 0 removed, ...`.
+fn summary_line(report: &ChangeReport) -> String {
+    format!(
+        "{} change(s) since the last save: {} added, {} removed, {} value changed, {} formula changed",
+        report.total(),
+        report.added.len(),
+        report.removed.len(),
+        report.value_changed.len(),
+        report.formula_changed.len()
+    )
+}
+
+/// Handles `changes`, `changes <range>` and `changes count`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `args` - The text after `changes`.
+///
+/// # Returns
+///
+/// * `CommandStatus` - `CmdOk`, or the range parse error
+pub fn handle_changes(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let args = args.trim();
+    if args == "count" {
+        let line = summary_line(&sheet.changes_since_save(None));
+        sheet.output.line(line);
+        return CommandStatus::CmdOk;
+    }
+    let range = if args.is_empty() {
+        None
+    } else {
+        match resolve_range(sheet, args) {
+            Ok(range) => Some(range),
+            Err(status) => return status,
+        }
+    };
+    let report = sheet.changes_since_save(range.as_ref());
+    if report.total() == 0 {
+        sheet.output.line("No changes since the last save");
+        return CommandStatus::CmdOk;
+    }
+
+    let mut lines = vec![summary_line(&report)];
+    let name = |key: i32| {
+        let (row, col) = sheet.get_row_col(key);
+        sheet.get_cell_name(row, col)
+    };
+    let now = |key: i32| {
+        sheet.baseline_cell(key).unwrap_or(BaselineCell {
+            value: CellValue::Empty,
+            formula: None,
+        })
+    };
+    let saved = |key: i32| &sheet.saved_baseline[&key];
+    let sections: [(&str, &[i32]); 4] = [
+        ("added", &report.added),
+        ("removed", &report.removed),
+        ("value changed", &report.value_changed),
+        ("formula changed", &report.formula_changed),
+    ];
+    for (title, keys) in sections {
+        if keys.is_empty() {
+            continue;
+        }
+        lines.push(format!("{}:", title));
+        for &key in keys {
+            let line = match title {
+                "added" => {
+                    let now = now(key);
+                    describe(&now.value, now.formula.as_deref())
+                }
+                "removed" => {
+                    let saved = saved(key);
+                    format!("was {}", describe(&saved.value, saved.formula.as_deref()))
+                }
+                _ => {
+                    let (saved, now) = (saved(key), now(key));
+                    format!(
+                        "{} -> {}",
+                        describe(&saved.value, saved.formula.as_deref()),
+                        describe(&now.value, now.formula.as_deref())
+                    )
+                }
+            };
+            lines.push(format!("  {}: {}", name(key), line));
+        }
+    }
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::handle_command;
+    use crate::output::OutputSink;
+    use tempfile::tempdir;
+
+    fn run(sheet: &mut Spreadsheet, command: &str) -> CommandStatus {
+        let mut sleep_time = 0.0;
+        handle_command(sheet, command, &mut sleep_time)
+    }
+
+    /// A1 = 5, A2 = A1*2, A3 = 7 and B1 = SUM(A1:A3), saved to a temporary file.
+    fn saved_fixture(path: &str) -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(20, 5).unwrap();
+        sheet.output = OutputSink::capture();
+        for command in ["A1=5", "A2=A1*2", "A3=7", "B1=SUM(A1:A3)"] {
+            assert_eq!(run(&mut sheet, command), CommandStatus::CmdOk);
+        }
+        assert_eq!(
+            run(&mut sheet, &format!("save {}", path)),
+            CommandStatus::CmdOk
+        );
+        sheet.output.take();
+        sheet
+    }
+
+    fn keys(sheet: &Spreadsheet, cells: &[&str]) -> Vec<i32> {
+        cells
+            .iter()
+            .map(|cell| {
+                let (row, col) = crate::cell::parse_cell_reference(sheet, cell).unwrap();
+                sheet.get_key(row, col)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_changes_detects_each_category() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("sheet.txt");
+        let mut sheet = saved_fixture(path.to_str().unwrap());
+        assert_eq!(sheet.changes_since_save(None), ChangeReport::default());
+
+        run(&mut sheet, "C5=3");
+        // A 0 is a value, so only emptying the cell, as the vim cut does, removes it
+        *sheet.get_mut_cell(2, 0) = CellValue::Empty;
+        run(&mut sheet, "A1=6");
+        run(&mut sheet, "B1=A1+A2");
+        let report = sheet.changes_since_save(None);
+        assert_eq!(report.added, keys(&sheet, &["C5"]));
+        assert_eq!(report.removed, keys(&sheet, &["A3"]));
+        // A2 keeps its formula and only its value follows A1
+        assert_eq!(report.value_changed, keys(&sheet, &["A1", "A2"]));
+        assert_eq!(report.formula_changed, keys(&sheet, &["B1"]));
+
+        assert_eq!(run(&mut sheet, "changes"), CommandStatus::CmdOk);
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                "5 change(s) since the last save: 1 added, 1 removed, 2 value changed, 1 formula changed",
+                "added:",
+                "  C5: 3",
+                "removed:",
+                "  A3: was 7",
+                "value changed:",
+                "  A1: 5 -> 6",
+                "  A2: 10 (=A1*2) -> 12 (=A1*2)",
+                "formula changed:",
+                "  B1: 22 (=SUM(A1:A3)) -> 18 (=A1+A2)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_save_refreshes_baseline() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("sheet.txt");
+        let path = path.to_str().unwrap();
+        let mut sheet = saved_fixture(path);
+        run(&mut sheet, "A1=6");
+        assert_eq!(sheet.changes_since_save(None).total(), 3);
+
+        assert_eq!(run(&mut sheet, "save"), CommandStatus::CmdOk);
+        sheet.output.take();
+        run(&mut sheet, "changes");
+        assert_eq!(sheet.output.take(), vec!["No changes since the last save"]);
+
+        // Opening a file makes it the baseline too
+        let mut other = Spreadsheet::create(20, 5).unwrap();
+        run(&mut other, "E20=1");
+        assert_eq!(
+            run(&mut other, &format!("open {}", path)),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(other.changes_since_save(None).total(), 0);
+    }
+
+    #[test]
+    fn test_changes_in_range_and_count() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("sheet.txt");
+        let mut sheet = saved_fixture(path.to_str().unwrap());
+        run(&mut sheet, "A1=6");
+        run(&mut sheet, "D4=1");
+
+        run(&mut sheet, "changes D1:E5");
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                "1 change(s) since the last save: 1 added, 0 removed, 0 value changed, 0 formula changed",
+                "added:",
+                "  D4: 1",
+            ]
+        );
+        run(&mut sheet, "changes count");
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                "4 change(s) since the last save: 1 added, 0 removed, 3 value changed, 0 formula changed"
+            ]
+        );
+        assert_eq!(run(&mut sheet, "changes A1:"), CommandStatus::Unrecognized);
+    }
+
+    #[test]
+    fn test_baseline_stores_only_populated_cells() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("sparse.txt");
+        let mut sheet = Spreadsheet::create(999, 1000).unwrap();
+        sheet.output = OutputSink::capture();
+        run(&mut sheet, "A1=1");
+        run(&mut sheet, "ALL999=A1+1");
+        run(&mut sheet, "B2=0");
+        run(&mut sheet, &format!("save {}", path.to_str().unwrap()));
+        // The assigned 0 in B2 is populated too
+        assert_eq!(sheet.saved_baseline.len(), 3);
+        assert_eq!(
+            sheet.saved_baseline[&keys(&sheet, &["ALL999"])[0]],
+            BaselineCell {
+                value: CellValue::Integer(2),
+                formula: Some("A1+1".to_string()),
+            }
+        );
+    }
+}
@@ -0,0 +1,511 @@
+//! Column formulas: one formula definition applied to every row of a column.
+//!
+//! `colformula C = A-B rows 2:100` writes `C2=A2-B2` through `C100=A100-B100`. Bare
+//! column letters in the definition mean "this row of that column". Each row goes
+//! through `set_cell_value`, so locks and cycle checks apply cell by cell, and a row
+//! that fails is reported without stopping the others. Definitions are kept on the
+//! sheet so `colformula refresh` can apply them again.
+
+use crate::evaluator::set_cell_value;
+use crate::spreadsheet::{CommandStatus, MAX_COLUMN_NAME_LEN, Spreadsheet};
+
+/// A stored column formula.
+///
+/// # Fields
+///
+/// * `template` - The right-hand side with bare column letters, e.g. "A-B".
+/// * `rows` - First and last row (0-based), or `None` to cover rows 1 through the last
+///   row where a referenced column has data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnFormula {
+    pub template: String,
+    pub rows: Option<(i16, i16)>,
+}
+
+/// What applying a column formula did.
+///
+/// # Fields
+///
+/// * `applied` - Rows whose formula was set.
+/// * `failed` - Rows that were skipped, with the status `set_cell_value` returned.
+#[derive(Debug, Default, PartialEq)]
+pub struct ColumnFormulaReport {
+    pub applied: usize,
+    pub failed: Vec<(i16, CommandStatus)>,
+}
+
+/// Finds the bare column tokens of a template as byte spans.
+///
+/// A run of capital letters is a column unless it is followed by a digit (a normal
+/// cell reference) or by `(` (a function name).
+fn column_spans(template: &str) -> Vec<(usize, usize)> {
+    let bytes = template.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_uppercase() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && bytes[i].is_ascii_uppercase() {
+            i += 1;
+        }
+        // Letters followed by a row, a `$` row pin, or a function call are not bare columns
+        let next = bytes.get(i).copied();
+        if !next.is_some_and(|b| b.is_ascii_digit() || b == b'$' || b == b'(') {
+            spans.push((start, i));
+        }
+    }
+    spans
+}
+
+/// Lists the columns a template reads from.
+fn template_columns(sheet: &Spreadsheet, template: &str) -> Vec<i16> {
+    column_spans(template)
+        .into_iter()
+        .map(|(start, end)| sheet.column_name_to_index(&template[start..end]))
+        .collect()
+}
+
+/// Turns a template into the concrete expression for one row.
+///
+/// # Arguments
+///
+/// * `template` - The right-hand side with bare column letters.
+/// * `row` - The target row (0-based).
+///
+/// # Returns
+///
+/// * `String` - The expression with each bare column followed by the row number
+pub fn expand_template(template: &str, row: i16) -> String {
+    let mut expr = String::with_capacity(template.len() + 8);
+    let mut last = 0;
+    for (_, end) in column_spans(template) {
+        expr.push_str(&template[last..end]);
+        expr.push_str(&(row + 1).to_string());
+        last = end;
+    }
+    expr.push_str(&template[last..]);
+    expr
+}
+
+/// Works out the rows a definition covers right now.
+///
+/// # Returns
+///
+/// * `Some((first, last))` - The row span (0-based, inclusive).
+/// * `None` - If the definition has no fixed rows and its columns hold no data
+fn covered_rows(sheet: &Spreadsheet, formula: &ColumnFormula) -> Option<(i16, i16)> {
+    if formula.rows.is_some() {
+        return formula.rows;
+    }
+    let columns = template_columns(sheet, &formula.template);
+    (0..sheet.rows)
+        .rev()
+        .find(|&row| {
+            columns
+                .iter()
+                .any(|&col| sheet.is_touched(sheet.get_key(row, col)))
+        })
+        .map(|last| (0, last))
+}
+
+/// Writes a column formula into every row it covers.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `col` - The target column.
+/// * `formula` - The definition.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `ColumnFormulaReport` - Rows set and rows skipped
+pub fn apply_column_formula(
+    sheet: &mut Spreadsheet,
+    col: i16,
+    formula: &ColumnFormula,
+    sleep_time: &mut f64,
+) -> ColumnFormulaReport {
+    let mut report = ColumnFormulaReport::default();
+    let Some((first, last)) = covered_rows(sheet, formula) else {
+        return report;
+    };
+    for row in first..=last {
+        let expr = expand_template(&formula.template, row);
+        match set_cell_value(sheet, row, col, &expr, sleep_time) {
+            CommandStatus::CmdOk => report.applied += 1,
+            status => report.failed.push((row, status)),
+        }
+    }
+    report
+}
+
+/// Parses `<col> = <template> [rows <first>:<last>]`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for checking columns and rows.
+/// * `args` - The definition text.
+///
+/// # Returns
+///
+/// * `Ok((col, formula))` - The target column and definition.
+/// * `Err(CommandStatus::InvalidCell)` - If a column or row is outside the sheet.
+/// * `Err(CommandStatus::Unrecognized)` - If the syntax is wrong
+pub fn parse_definition(
+    sheet: &Spreadsheet,
+    args: &str,
+) -> Result<(i16, ColumnFormula), CommandStatus> {
+    let (definition, rows) = match args.rsplit_once(" rows ") {
+        Some((definition, rows_str)) => {
+            let (first, last) = rows_str
+                .trim()
+                .split_once(':')
+                .ok_or(CommandStatus::Unrecognized)?;
+            let first: i16 = first.parse().map_err(|_| CommandStatus::Unrecognized)?;
+            let last: i16 = last.parse().map_err(|_| CommandStatus::Unrecognized)?;
+            if first < 1 || first > last {
+                return Err(CommandStatus::Unrecognized);
+            }
+            if last > sheet.rows {
+                return Err(CommandStatus::InvalidCell);
+            }
+            (definition, Some((first - 1, last - 1)))
+        }
+        None => (args, None),
+    };
+
+    let (col_name, template) = definition
+        .split_once('=')
+        .ok_or(CommandStatus::Unrecognized)?;
+    let col_name = col_name.trim();
+    let template: String = template.split_whitespace().collect();
+    if template.is_empty()
+        || col_name.is_empty()
+        || col_name.len() > MAX_COLUMN_NAME_LEN
+        || !col_name.bytes().all(|b| b.is_ascii_uppercase())
+    {
+        return Err(CommandStatus::Unrecognized);
+    }
+
+    if column_spans(&template)
+        .iter()
+        .any(|(start, end)| end - start > MAX_COLUMN_NAME_LEN)
+    {
+        return Err(CommandStatus::Unrecognized);
+    }
+    let col = sheet.column_name_to_index(col_name);
+    let columns = template_columns(sheet, &template);
+    if col >= sheet.cols || columns.iter().any(|&c| c < 0 || c >= sheet.cols) {
+        return Err(CommandStatus::InvalidCell);
+    }
+    // Without fixed rows the used rows of the referenced columns decide
+    if rows.is_none() && columns.is_empty() {
+        return Err(CommandStatus::Unrecognized);
+    }
+    Ok((col, ColumnFormula { template, rows }))
+}
+
+/// Formats a definition the way it is entered, e.g. "C = A-B rows 2:100".
+fn describe(sheet: &Spreadsheet, col: i16, formula: &ColumnFormula) -> String {
+    let mut text = format!("{} = {}", sheet.get_column_name(col), formula.template);
+    if let Some((first, last)) = formula.rows {
+        text.push_str(&format!(" rows {}:{}", first + 1, last + 1));
+    }
+    text
+}
+
+/// Applies one stored definition and writes its report to the output sink.
+fn apply_and_report(
+    sheet: &mut Spreadsheet,
+    col: i16,
+    sleep_time: &mut f64,
+) -> ColumnFormulaReport {
+    let formula = sheet.column_formulas[&col].clone();
+    let report = apply_column_formula(sheet, col, &formula, sleep_time);
+    let name = sheet.get_column_name(col);
+    let mut lines = vec![format!(
+        "{}: {} formula(s) set, {} row(s) skipped",
+        describe(sheet, col, &formula),
+        report.applied,
+        report.failed.len()
+    )];
+    for (row, status) in &report.failed {
+        lines.push(format!("{}{} skipped ({})", name, row + 1, status.as_str()));
+    }
+    sheet.output.lines(lines);
+    report
+}
+
+/// Handles `colformula <col> = <expr> [rows a:b]`, `colformula refresh [<col>]`,
+/// `colformula list`, and `colformula clear <col>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `args` - The text after `colformula `.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success, even if some rows were skipped.
+/// * `CommandStatus::Unrecognized` - For bad syntax, an unknown column formula, or a
+///   definition that could not be set on any row.
+/// * `CommandStatus::InvalidCell` - If a column or row is outside the sheet
+pub fn handle_colformula(
+    sheet: &mut Spreadsheet,
+    args: &str,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    let args = args.trim();
+    if args == "list" {
+        let lines: Vec<String> = if sheet.column_formulas.is_empty() {
+            vec!["No column formulas".to_string()]
+        } else {
+            sheet
+                .column_formulas
+                .iter()
+                .map(|(&col, formula)| describe(sheet, col, formula))
+                .collect()
+        };
+        sheet.output.lines(lines);
+        return CommandStatus::CmdOk;
+    }
+
+    if let Some(target) = args.strip_prefix("refresh") {
+        let cols: Vec<i16> = match target.trim() {
+            "" => sheet.column_formulas.keys().copied().collect(),
+            name => {
+                let col = sheet.column_name_to_index(name);
+                if !sheet.column_formulas.contains_key(&col) {
+                    return CommandStatus::Unrecognized;
+                }
+                vec![col]
+            }
+        };
+        for col in cols {
+            apply_and_report(sheet, col, sleep_time);
+        }
+        return CommandStatus::CmdOk;
+    }
+
+    if let Some(name) = args.strip_prefix("clear ") {
+        let col = sheet.column_name_to_index(name.trim());
+        return match sheet.column_formulas.remove(&col) {
+            Some(_) => CommandStatus::CmdOk,
+            None => CommandStatus::Unrecognized,
+        };
+    }
+
+    let (col, formula) = match parse_definition(sheet, args) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    let previous = sheet.column_formulas.insert(col, formula);
+    let report = apply_and_report(sheet, col, sleep_time);
+
+    // A definition no row accepts is a typo, not something to keep
+    if report.applied == 0
+        && !report.failed.is_empty()
+        && report
+            .failed
+            .iter()
+            .all(|(_, status)| *status == CommandStatus::Unrecognized)
+    {
+        match previous {
+            Some(old) => sheet.column_formulas.insert(col, old),
+            None => sheet.column_formulas.remove(&col),
+        };
+        return CommandStatus::Unrecognized;
+    }
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::CellValue;
+    use crate::evaluator::handle_command;
+    use crate::extensions::get_formula_string;
+    use crate::output::OutputSink;
+    use crate::save_load::{load_spreadsheet, save_spreadsheet};
+
+    fn sheet_with_data() -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(10, 4).unwrap();
+        sheet.output = OutputSink::capture();
+        let mut sleep_time = 0.0;
+        for command in ["A1=10", "B1=3", "A2=20", "B2=5", "A3=30"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        sheet
+    }
+
+    #[test]
+    fn test_expand_template() {
+        assert_eq!(expand_template("A-B", 4), "A5-B5");
+        assert_eq!(expand_template("SUM(A1:B2)", 0), "SUM(A1:B2)");
+        assert_eq!(expand_template("AB*2", 9), "AB10*2");
+        assert_eq!(expand_template("A1+B", 1), "A1+B2");
+    }
+
+    #[test]
+    fn test_colformula_initial_application() {
+        let mut sheet = sheet_with_data();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "colformula C = A - B rows 1:3", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(7));
+        assert_eq!(*sheet.get_cell(1, 2), CellValue::Integer(15));
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Integer(30));
+        assert_eq!(get_formula_string(&sheet, 2, 2), "A3-B3");
+        assert_eq!(
+            sheet.output.take(),
+            vec!["C = A-B rows 1:3: 3 formula(s) set, 0 row(s) skipped"]
+        );
+
+        assert_eq!(
+            handle_command(&mut sheet, "colformula list", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.output.take(), vec!["C = A-B rows 1:3"]);
+
+        assert_eq!(
+            handle_command(&mut sheet, "colformula clear C", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert!(sheet.column_formulas.is_empty());
+        // Clearing the definition keeps the formulas it wrote
+        assert_eq!(get_formula_string(&sheet, 0, 2), "A1-B1");
+        assert_eq!(
+            handle_command(&mut sheet, "colformula clear C", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_colformula_keeps_pinned_references() {
+        let mut sheet = sheet_with_data();
+        let mut sleep_time = 0.0;
+        assert_eq!(expand_template("$A$1+B", 1), "$A$1+B2");
+        assert_eq!(expand_template("A$1*B", 2), "A$1*B3");
+        assert_eq!(
+            handle_command(
+                &mut sheet,
+                "colformula D = $A$1+B rows 1:2",
+                &mut sleep_time
+            ),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(get_formula_string(&sheet, 1, 3), "$A$1+B2");
+        assert_eq!(*sheet.get_cell(1, 3), CellValue::Integer(15));
+    }
+
+    #[test]
+    fn test_colformula_refresh_extends_to_new_rows() {
+        let mut sheet = sheet_with_data();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "colformula D = A*2", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(2, 3), CellValue::Integer(60));
+        assert_eq!(*sheet.get_cell(3, 3), CellValue::Empty);
+        assert!(!sheet.cell_meta.contains_key(&sheet.get_key(3, 3)));
+
+        // New data, and a formula overwritten by hand
+        assert_eq!(
+            handle_command(&mut sheet, "A5=7", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "D1=0", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "colformula refresh", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(20));
+        assert_eq!(*sheet.get_cell(4, 3), CellValue::Integer(14));
+        assert_eq!(get_formula_string(&sheet, 3, 3), "A4*2");
+        assert_eq!(
+            handle_command(&mut sheet, "colformula refresh B", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_colformula_cycle_row_is_skipped_and_reported() {
+        let mut sheet = sheet_with_data();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "B2=C2", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        sheet.output.take();
+
+        assert_eq!(
+            handle_command(&mut sheet, "colformula C = A+B rows 1:3", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                "C = A+B rows 1:3: 2 formula(s) set, 1 row(s) skipped",
+                "C2 skipped (circular_ref)",
+            ]
+        );
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(13));
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Integer(30));
+        assert!(!sheet.cell_meta.contains_key(&sheet.get_key(1, 2)));
+    }
+
+    #[test]
+    fn test_colformula_rejects_bad_definitions() {
+        let mut sheet = sheet_with_data();
+        let mut sleep_time = 0.0;
+        for (command, status) in [
+            ("colformula C = A-B rows 3:1", CommandStatus::Unrecognized),
+            ("colformula C = A-B rows 1:11", CommandStatus::InvalidCell),
+            ("colformula Z = A-B", CommandStatus::InvalidCell),
+            ("colformula C = 5", CommandStatus::Unrecognized),
+            ("colformula C = A-*B rows 1:2", CommandStatus::Unrecognized),
+            ("colformula c = A", CommandStatus::Unrecognized),
+        ] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                status,
+                "{}",
+                command
+            );
+        }
+        assert!(sheet.column_formulas.is_empty());
+    }
+
+    #[test]
+    fn test_colformula_persistence_round_trip() {
+        let filename = "test_colformula_round_trip.sheet";
+        let mut sheet = sheet_with_data();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "colformula C = A-B rows 2:9", &mut sleep_time);
+        handle_command(&mut sheet, "colformula D = MAX(A1:A3)+A", &mut sleep_time);
+        assert_eq!(save_spreadsheet(&sheet, filename), CommandStatus::CmdOk);
+
+        let mut loaded = Spreadsheet::create(10, 4).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded, filename),
+            CommandStatus::CmdOk
+        );
+        let _ = std::fs::remove_file(filename);
+        assert_eq!(loaded.column_formulas, sheet.column_formulas);
+        assert_eq!(get_formula_string(&loaded, 1, 2), "A2-B2");
+    }
+}
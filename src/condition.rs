@@ -0,0 +1,143 @@
+//! Comparisons of a cell value against a literal, such as `> 100` or `!= 0`.
+//!
+//! Commands that select cells by value parse their condition here, so every command
+//! accepts the same operators and treats error cells the same way.
+
+use crate::cell::CellValue;
+use crate::spreadsheet::CommandStatus;
+
+/// A comparison operator.
+///
+/// # Variants
+///
+/// * `Less` - `<`
+/// * `LessEqual` - `<=`
+/// * `Greater` - `>`
+/// * `GreaterEqual` - `>=`
+/// * `Equal` - `=` or `==`
+/// * `NotEqual` - `!=` or `<>`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl CompareOp {
+    /// Returns the operator as it is written.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompareOp::Less => "<",
+            CompareOp::LessEqual => "<=",
+            CompareOp::Greater => ">",
+            CompareOp::GreaterEqual => ">=",
+            CompareOp::Equal => "=",
+            CompareOp::NotEqual => "!=",
+        }
+    }
+}
+
+/// A comparison against a literal value.
+///
+/// # Fields
+///
+/// * `op` - The operator.
+/// * `value` - The literal the cell value is compared with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub op: CompareOp,
+    pub value: i32,
+}
+
+impl Condition {
+    /// Checks a cell value against the condition. Error and text cells never match; an
+    /// empty cell is compared as 0.
+    pub fn matches(&self, cell: &CellValue) -> bool {
+        let Ok(v) = cell.number() else {
+            return false;
+        };
+        match self.op {
+            CompareOp::Less => v < self.value,
+            CompareOp::LessEqual => v <= self.value,
+            CompareOp::Greater => v > self.value,
+            CompareOp::GreaterEqual => v >= self.value,
+            CompareOp::Equal => v == self.value,
+            CompareOp::NotEqual => v != self.value,
+        }
+    }
+
+    /// Formats the condition the way it is entered, e.g. "> 100".
+    pub fn describe(&self) -> String {
+        format!("{} {}", self.op.as_str(), self.value)
+    }
+}
+
+/// Parses a condition such as `> 100`, `>=-5`, or `!= 0`.
+///
+/// # Arguments
+///
+/// * `text` - The operator followed by an integer, with optional spaces.
+///
+/// # Returns
+///
+/// * `Ok(Condition)` - The parsed condition.
+/// * `Err(CommandStatus::Unrecognized)` - If the operator or the value is invalid
+pub fn parse_condition(text: &str) -> Result<Condition, CommandStatus> {
+    let text = text.trim();
+    // Two-character operators first so `<=` is not read as `<`
+    let operators = [
+        ("<=", CompareOp::LessEqual),
+        (">=", CompareOp::GreaterEqual),
+        ("==", CompareOp::Equal),
+        ("!=", CompareOp::NotEqual),
+        ("<>", CompareOp::NotEqual),
+        ("<", CompareOp::Less),
+        (">", CompareOp::Greater),
+        ("=", CompareOp::Equal),
+    ];
+    let (op, rest) = operators
+        .iter()
+        .find_map(|(symbol, op)| text.strip_prefix(symbol).map(|rest| (*op, rest)))
+        .ok_or(CommandStatus::Unrecognized)?;
+    let value = rest
+        .trim()
+        .parse::<i32>()
+        .map_err(|_| CommandStatus::Unrecognized)?;
+    Ok(Condition { op, value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::ErrorKind;
+
+    #[test]
+    fn test_parse_condition() {
+        let parsed = |text| parse_condition(text).map(|c| (c.op, c.value));
+        assert_eq!(parsed("> 100"), Ok((CompareOp::Greater, 100)));
+        assert_eq!(parsed(">=-5"), Ok((CompareOp::GreaterEqual, -5)));
+        assert_eq!(parsed("<> 0"), Ok((CompareOp::NotEqual, 0)));
+        assert_eq!(parsed("== 3"), Ok((CompareOp::Equal, 3)));
+        assert_eq!(parsed("= 3"), Ok((CompareOp::Equal, 3)));
+        for bad in ["100", "> x", ">", "=> 1", ""] {
+            assert_eq!(
+                parse_condition(bad),
+                Err(CommandStatus::Unrecognized),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_condition_matches() {
+        let condition = parse_condition("<= 2").unwrap();
+        assert!(condition.matches(&CellValue::Integer(2)));
+        assert!(!condition.matches(&CellValue::Integer(3)));
+        assert!(!condition.matches(&CellValue::Error(ErrorKind::DivZero)));
+        assert_eq!(condition.describe(), "<= 2");
+    }
+}
@@ -0,0 +1,893 @@
+//! Copying and moving a rectangle of cells with `copy <range> <cell>` and `move <range> <cell>`,
+//! and copying it with rows and columns swapped with `transpose <range> <cell>`.
+//!
+//! The destination is the rectangle of the source's shape (turned on its side for
+//! `transpose`) whose top-left cell is given.
+//! `copy` writes values as they are and formulas with their plain references moved by the
+//! same offset, as a range assignment fills them. `move` carries the cells themselves:
+//! their formulas keep reading the same data, except that relative references (e.g.
+//! `RC[-1]`) keep their offsets as with `copy`; every formula elsewhere that reads a moved
+//! cell, or a range lying wholly inside the source, follows it to its new place, and the
+//! source cells left behind become empty. `transpose` copies the cells themselves the same
+//! way, so its formulas keep reading the same data except where they read the source,
+//! which they now read in its transposed copy.
+//!
+//! `copy` and `move` read the whole source before writing, so the two rectangles may
+//! overlap; `transpose` refuses an overlap. All three check every cell they write first,
+//! so a locked cell stops the whole operation.
+
+use crate::cell::{RELATIVE_PARENT1, RELATIVE_PARENT2};
+use crate::cell_history::{HistoryEntry, snapshot_entry};
+use crate::evaluator::{resolve_cell_reference, set_cell_value};
+use crate::extensions::{bulk_set_values, get_formula_string};
+use crate::graph::parents_are_cells;
+use crate::range::{Range, resolve_target};
+use crate::range_assign::shift_references;
+use crate::reevaluate_topo::{reevaluate_formula, toposort_reval_many};
+use crate::sort::CellSnapshot;
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use std::collections::HashMap;
+
+/// Where cells of a source rectangle go, and with them the references to those cells.
+///
+/// # Fields
+///
+/// * `source` - The cells that go somewhere else.
+/// * `dest` - Where they go.
+/// * `transpose` - Whether rows and columns swap on the way.
+/// * `cols` - Columns in the sheet, to turn keys into positions.
+#[derive(Debug, Clone, Copy)]
+struct Relocation {
+    source: Range,
+    dest: Range,
+    transpose: bool,
+    cols: i16,
+}
+
+impl Relocation {
+    /// Returns where a cell of the source goes.
+    fn place(self, row: i16, col: i16) -> (i16, i16) {
+        let (row_index, col_index) = (row - self.source.start_row, col - self.source.start_col);
+        if self.transpose {
+            (
+                self.dest.start_row + col_index,
+                self.dest.start_col + row_index,
+            )
+        } else {
+            (
+                self.dest.start_row + row_index,
+                self.dest.start_col + col_index,
+            )
+        }
+    }
+
+    /// Returns the new key of a cell, which only changes for cells of the source.
+    fn key(self, key: i32) -> i32 {
+        let cols = self.cols as i32;
+        let (row, col) = ((key / cols) as i16, (key % cols) as i16);
+        if self.source.contains(row, col) {
+            let (row, col) = self.place(row, col);
+            row as i32 * cols + col as i32
+        } else {
+            key
+        }
+    }
+
+    /// Returns the new place of a range, which only changes for a range inside the source.
+    fn range(self, range: Range) -> Range {
+        if self.source.contains(range.start_row, range.start_col)
+            && self.source.contains(range.end_row, range.end_col)
+        {
+            let (start_row, start_col) = self.place(range.start_row, range.start_col);
+            let (end_row, end_col) = self.place(range.end_row, range.end_col);
+            Range {
+                start_row,
+                start_col,
+                end_row,
+                end_col,
+            }
+        } else {
+            range
+        }
+    }
+
+    /// Points a snapshot's references at their new places.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The cell to update.
+    /// * `cells` - Whether both parents of a `formula % 10 == 0` formula are cells.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if any reference changed
+    fn follow(self, snapshot: &mut CellSnapshot, cells: bool) -> bool {
+        let parents = snapshot.meta.map(|meta| (meta.parent1, meta.parent2));
+        let extended = snapshot.extended.clone();
+        let cols = self.cols as i32;
+        if let Some(meta) = snapshot.meta.as_mut() {
+            match meta.formula % 10 {
+                _ if meta.formula == -1 => {}
+                0 if cells => {
+                    meta.parent1 = self.key(meta.parent1);
+                    meta.parent2 = self.key(meta.parent2);
+                }
+                0 | 4 => {}
+                2 => meta.parent1 = self.key(meta.parent1),
+                3 => meta.parent2 = self.key(meta.parent2),
+                _ => {
+                    let range = self.range(Range {
+                        start_row: (meta.parent1 / cols) as i16,
+                        start_col: (meta.parent1 % cols) as i16,
+                        end_row: (meta.parent2 / cols) as i16,
+                        end_col: (meta.parent2 % cols) as i16,
+                    });
+                    meta.parent1 = range.start_row as i32 * cols + range.start_col as i32;
+                    meta.parent2 = range.end_row as i32 * cols + range.end_col as i32;
+                }
+            }
+        }
+        if let Some(formula) = snapshot.extended.as_mut() {
+            formula.remap_cells(|key, _| self.key(key));
+            formula.remap_ranges(|range, _| self.range(range));
+        }
+        snapshot.meta.map(|meta| (meta.parent1, meta.parent2)) != parents
+            || snapshot.extended != extended
+    }
+}
+
+/// Parses `<cell|range> <cell>` into the source and the destination's top-left cell.
+///
+/// # Returns
+///
+/// * `Ok((source, corner))` - The source and the destination's top-left cell.
+/// * `Err(CommandStatus::InvalidCell)` - If a reference is outside the sheet.
+/// * `Err(CommandStatus::Unrecognized)` - If the arguments are malformed
+fn parse_args(sheet: &Spreadsheet, args: &str) -> Result<(Range, (i16, i16)), CommandStatus> {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let [source, destination] = parts[..] else {
+        return Err(CommandStatus::Unrecognized);
+    };
+    let source = resolve_target(sheet, source)?;
+    Ok((source, resolve_cell_reference(sheet, destination)?))
+}
+
+/// Builds the rectangle of `height` rows and `width` columns whose top-left cell is `corner`.
+///
+/// # Returns
+///
+/// * `Ok(Range)` - The rectangle.
+/// * `Err(CommandStatus::InvalidCell)` - If it would reach past the edge of the sheet
+fn block_at(
+    sheet: &Spreadsheet,
+    (row, col): (i16, i16),
+    height: i16,
+    width: i16,
+) -> Result<Range, CommandStatus> {
+    let end_row = row as i32 + height as i32 - 1;
+    let end_col = col as i32 + width as i32 - 1;
+    if end_row >= sheet.rows as i32 || end_col >= sheet.cols as i32 {
+        return Err(CommandStatus::InvalidCell);
+    }
+    Ok(Range {
+        start_row: row,
+        start_col: col,
+        end_row: end_row as i16,
+        end_col: end_col as i16,
+    })
+}
+
+/// Returns the number of rows and columns of a range.
+fn shape(range: &Range) -> (i16, i16) {
+    (
+        range.end_row - range.start_row + 1,
+        range.end_col - range.start_col + 1,
+    )
+}
+
+/// Checks that every cell of a range may be written.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - If none is locked or owned by someone else.
+/// * `CommandStatus::LockedCell` - If a cell is locked.
+/// * `CommandStatus::NotOwner` - If a cell is owned by someone else
+fn check_writable(sheet: &Spreadsheet, range: &Range) -> CommandStatus {
+    if range
+        .cells()
+        .any(|(row, col)| sheet.is_cell_locked(row, col))
+    {
+        return CommandStatus::LockedCell;
+    }
+    if range
+        .cells()
+        .any(|(row, col)| sheet.check_owner(row, col) != CommandStatus::CmdOk)
+    {
+        return CommandStatus::NotOwner;
+    }
+    CommandStatus::CmdOk
+}
+
+/// Copies a rectangle of cells, shifting the plain references of copied formulas.
+///
+/// Values are written first and formulas after them, one at a time; a formula that would
+/// close a cycle is skipped and reported, and the others are still copied.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `source` - The cells to copy.
+/// * `dest` - The cells to write, of the source's shape.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::LockedCell` - If a destination cell is locked; nothing is copied.
+/// * `CommandStatus::NotOwner` - If a destination cell is owned by someone else.
+/// * `CommandStatus::InvalidCell` - If a shifted reference would leave the sheet
+pub fn copy_range(
+    sheet: &mut Spreadsheet,
+    source: &Range,
+    dest: &Range,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    let status = check_writable(sheet, dest);
+    if status != CommandStatus::CmdOk {
+        return status;
+    }
+    let row_offset = dest.start_row - source.start_row;
+    let col_offset = dest.start_col - source.start_col;
+
+    // Read the whole source first, so an overlapping destination cannot feed itself
+    let mut values = Vec::new();
+    let mut assigned = Vec::new();
+    let mut formulas = Vec::new();
+    for (row, col) in source.cells() {
+        let (to_row, to_col) = (row + row_offset, col + col_offset);
+        let key = sheet.get_key(row, col);
+        if sheet
+            .cell_meta
+            .get(&key)
+            .is_some_and(|meta| meta.formula != -1)
+        {
+            let formula = get_formula_string(sheet, row, col);
+            match shift_references(sheet, &formula, row_offset, col_offset) {
+                Ok(shifted) => formulas.push(((to_row, to_col), shifted)),
+                Err(status) => return status,
+            }
+        } else {
+            values.push((to_row, to_col, sheet.get_cell(row, col).clone()));
+            assigned.push(sheet.assigned.contains(&key));
+        }
+    }
+
+    bulk_set_values(sheet, &values, sleep_time);
+    for ((row, col, _), assigned) in values.iter().zip(assigned) {
+        let key = sheet.get_key(*row, *col);
+        if assigned {
+            sheet.assigned.insert(key);
+        } else {
+            sheet.assigned.remove(&key);
+        }
+    }
+    let mut skipped = Vec::new();
+    for ((row, col), formula) in &formulas {
+        let status = set_cell_value(sheet, *row, *col, formula, sleep_time);
+        if status != CommandStatus::CmdOk {
+            skipped.push(format!(
+                "{} skipped ({})",
+                sheet.get_cell_name(*row, *col),
+                status.as_str()
+            ));
+        }
+    }
+    sheet.output.lines(skipped);
+    sheet.set_last_edited(dest.start_row, dest.start_col);
+    sheet.dirty = true;
+    CommandStatus::CmdOk
+}
+
+/// Copies a rectangle of cells into a block with rows and columns swapped, so the source's
+/// first row becomes the destination's first column.
+///
+/// Copied formulas keep reading the same cells, except that references to cells of the
+/// source, and ranges lying wholly inside it, point at the transposed copies.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `source` - The cells to copy.
+/// * `dest` - The cells to write, of the source's shape turned on its side.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If the two rectangles overlap.
+/// * `CommandStatus::LockedCell` - If a destination cell is locked; nothing is written.
+/// * `CommandStatus::NotOwner` - If a destination cell is owned by someone else.
+/// * `CommandStatus::CircularRef` - If the copies would create a cycle; the sheet is
+///   unchanged
+pub fn transpose_range(
+    sheet: &mut Spreadsheet,
+    source: &Range,
+    dest: &Range,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    if dest.cells().any(|(row, col)| source.contains(row, col)) {
+        sheet
+            .output
+            .line("the destination of transpose overlaps the source".to_string());
+        return CommandStatus::Unrecognized;
+    }
+    let status = check_writable(sheet, dest);
+    if status != CommandStatus::CmdOk {
+        return status;
+    }
+    let relocation = Relocation {
+        source: *source,
+        dest: *dest,
+        transpose: true,
+        cols: sheet.cols,
+    };
+
+    let after: Vec<((i16, i16), CellSnapshot)> = source
+        .cells()
+        .map(|(row, col)| {
+            let key = sheet.get_key(row, col);
+            let mut snapshot = CellSnapshot::capture(sheet, row, col);
+            let cells = snapshot
+                .meta
+                .is_some_and(|meta| parents_are_cells(&sheet.children, key, &meta));
+            relocation.follow(&mut snapshot, cells);
+            (relocation.place(row, col), snapshot)
+        })
+        .collect();
+    let before: Vec<((i16, i16), CellSnapshot)> = dest
+        .cells()
+        .map(|(row, col)| ((row, col), CellSnapshot::capture(sheet, row, col)))
+        .collect();
+    let dest_keys: Vec<i32> = dest
+        .cells()
+        .map(|(row, col)| sheet.get_key(row, col))
+        .collect();
+
+    apply_cells(sheet, &after);
+    if toposort_reval_many(sheet, &dest_keys, sleep_time) {
+        apply_cells(sheet, &before);
+        toposort_reval_many(sheet, &dest_keys, sleep_time);
+        return CommandStatus::CircularRef;
+    }
+    for ((row, col), old) in before {
+        if *sheet.get_cell(row, col) != old.value {
+            let key = sheet.get_key(row, col);
+            sheet.push_history(key, snapshot_entry(&old));
+        }
+    }
+
+    sheet.cell_counts = sheet.scan_cell_counts();
+    sheet.set_last_edited(dest.start_row, dest.start_col);
+    sheet.dirty = true;
+    CommandStatus::CmdOk
+}
+
+/// Clears cells and writes snapshots into them, dropping every old edge first.
+fn apply_cells(sheet: &mut Spreadsheet, cells: &[((i16, i16), CellSnapshot)]) {
+    for &((row, col), _) in cells {
+        sheet.clear_formula(row, col);
+    }
+    for ((row, col), snapshot) in cells {
+        snapshot.write(sheet, *row, *col);
+    }
+}
+
+/// Points a moved formula's relative references at the cells the same offsets from its
+/// new place, as `copy` does, instead of the cells they read before.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `relocation` - Where the moved cells go.
+/// * `snapshot` - The moved cell, with its other references already followed.
+/// * `original` - The cell as it was before the move.
+/// * `from` - Where it was.
+/// * `to` - Where it goes.
+///
+/// # Returns
+///
+/// * `Ok(())` - On success.
+/// * `Err(CommandStatus::InvalidCell)` - If a reference would leave the sheet
+fn keep_relative_offsets(
+    sheet: &Spreadsheet,
+    relocation: Relocation,
+    snapshot: &mut CellSnapshot,
+    original: &CellSnapshot,
+    from: (i16, i16),
+    to: (i16, i16),
+) -> Result<(), CommandStatus> {
+    let shift = |key: i32| {
+        let (row, col) = sheet.get_row_col(key);
+        let row = to.0 as i32 + (row - from.0) as i32;
+        let col = to.1 as i32 + (col - from.1) as i32;
+        if row < 0 || col < 0 || row >= sheet.rows as i32 || col >= sheet.cols as i32 {
+            return Err(CommandStatus::InvalidCell);
+        }
+        Ok(sheet.get_key(row as i16, col as i16))
+    };
+    if let (Some(flags), Some(meta), Some(parents)) =
+        (snapshot.relative, snapshot.meta.as_mut(), original.meta)
+    {
+        if flags & RELATIVE_PARENT1 != 0 {
+            meta.parent1 = shift(parents.parent1)?;
+        }
+        if flags & RELATIVE_PARENT2 != 0 {
+            meta.parent2 = shift(parents.parent2)?;
+        }
+        let ((start_row, start_col), (end_row, end_col)) = (
+            sheet.get_row_col(meta.parent1),
+            sheet.get_row_col(meta.parent2),
+        );
+        if meta.formula % 10 >= 5 && (start_row > end_row || start_col > end_col) {
+            return Err(CommandStatus::InvalidCell);
+        }
+    }
+    let Some(mut formula) = original.extended.clone() else {
+        return Ok(());
+    };
+    // Start again from the references before the move: relative ones keep their offsets
+    // and the others follow the moved cells; the first failure is kept and reported
+    let mut failure = Ok(());
+    let mut relative_shift = |key: i32| {
+        shift(key).unwrap_or_else(|status| {
+            failure = Err(status);
+            key
+        })
+    };
+    formula.remap_cells(|key, flags| {
+        if flags & RELATIVE_PARENT1 != 0 {
+            relative_shift(key)
+        } else {
+            relocation.key(key)
+        }
+    });
+    formula.remap_ranges(|range, flags| {
+        let mut followed = relocation.range(range);
+        if flags & RELATIVE_PARENT1 != 0 {
+            let start = relative_shift(sheet.get_key(range.start_row, range.start_col));
+            (followed.start_row, followed.start_col) = sheet.get_row_col(start);
+        }
+        if flags & RELATIVE_PARENT2 != 0 {
+            let end = relative_shift(sheet.get_key(range.end_row, range.end_col));
+            (followed.end_row, followed.end_col) = sheet.get_row_col(end);
+        }
+        followed
+    });
+    failure?;
+    if formula
+        .ranges()
+        .iter()
+        .any(|range| range.start_row > range.end_row || range.start_col > range.end_col)
+    {
+        return Err(CommandStatus::InvalidCell);
+    }
+    snapshot.extended = Some(formula);
+    Ok(())
+}
+
+/// Moves a rectangle of cells, pointing every reference to them at their new place.
+///
+/// A destination cell outside the source loses what it held; formulas that read it now
+/// read the moved cell instead.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `source` - The cells to move.
+/// * `dest` - Where they go, of the source's shape.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::LockedCell` - If a cell of either rectangle is locked; nothing moves.
+/// * `CommandStatus::NotOwner` - If a cell of either rectangle is owned by someone else.
+/// * `CommandStatus::InvalidCell` - If a moved relative reference would leave the sheet.
+/// * `CommandStatus::CircularRef` - If the new layout would create a cycle; the sheet is
+///   unchanged
+pub fn move_range(
+    sheet: &mut Spreadsheet,
+    source: &Range,
+    dest: &Range,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    for range in [source, dest] {
+        let status = check_writable(sheet, range);
+        if status != CommandStatus::CmdOk {
+            return status;
+        }
+    }
+    if source == dest {
+        return CommandStatus::CmdOk;
+    }
+    let relocation = Relocation {
+        source: *source,
+        dest: *dest,
+        transpose: false,
+        cols: sheet.cols,
+    };
+
+    let area: Vec<(i16, i16)> = source
+        .cells()
+        .chain(
+            dest.cells()
+                .filter(|&(row, col)| !source.contains(row, col)),
+        )
+        .collect();
+    let mut moved: HashMap<(i16, i16), CellSnapshot> = HashMap::new();
+    // Moved formulas whose relative references now read other cells
+    let mut shifted = Vec::new();
+    for (row, col) in source.cells() {
+        let key = sheet.get_key(row, col);
+        let mut snapshot = CellSnapshot::capture(sheet, row, col);
+        let cells = snapshot
+            .meta
+            .is_some_and(|meta| parents_are_cells(&sheet.children, key, &meta));
+        let original = snapshot.clone();
+        relocation.follow(&mut snapshot, cells);
+        let place = relocation.place(row, col);
+        if snapshot
+            .relative
+            .is_some_and(|flags| flags & (RELATIVE_PARENT1 | RELATIVE_PARENT2) != 0)
+            || snapshot
+                .extended
+                .as_ref()
+                .is_some_and(|formula| formula.is_relative())
+        {
+            if let Err(status) = keep_relative_offsets(
+                sheet,
+                relocation,
+                &mut snapshot,
+                &original,
+                (row, col),
+                place,
+            ) {
+                return status;
+            }
+            shifted.push(place);
+        }
+        moved.insert(place, snapshot);
+    }
+
+    let mut before: Vec<((i16, i16), CellSnapshot)> = area
+        .iter()
+        .map(|&(row, col)| ((row, col), CellSnapshot::capture(sheet, row, col)))
+        .collect();
+    let mut after: Vec<((i16, i16), CellSnapshot)> = area
+        .iter()
+        .map(|cell| (*cell, moved.remove(cell).unwrap_or(CellSnapshot::EMPTY)))
+        .collect();
+    // Formulas elsewhere that read a moved cell follow it
+    for (&key, meta) in &sheet.cell_meta {
+        let (row, col) = sheet.get_row_col(key);
+        if meta.formula == -1 || source.contains(row, col) || dest.contains(row, col) {
+            continue;
+        }
+        let original = CellSnapshot::capture(sheet, row, col);
+        let mut followed = original.clone();
+        if relocation.follow(&mut followed, parents_are_cells(&sheet.children, key, meta)) {
+            before.push(((row, col), original));
+            after.push(((row, col), followed));
+        }
+    }
+
+    let area_keys: Vec<i32> = area
+        .iter()
+        .map(|&(row, col)| sheet.get_key(row, col))
+        .collect();
+    apply_cells(sheet, &after);
+    for &(row, col) in &shifted {
+        reevaluate_formula(sheet, row, col, sleep_time);
+    }
+    if toposort_reval_many(sheet, &area_keys, sleep_time) {
+        apply_cells(sheet, &before);
+        toposort_reval_many(sheet, &area_keys, sleep_time);
+        return CommandStatus::CircularRef;
+    }
+
+    // History and names travel with the cells
+    for (row, col) in source.cells().chain(dest.cells()) {
+        let key = sheet.get_key(row, col);
+        sheet.journal_history(key);
+    }
+    let histories: Vec<(i32, Vec<HistoryEntry>)> = source
+        .cells()
+        .filter_map(|(row, col)| {
+            let key = sheet.get_key(row, col);
+            sheet
+                .cell_history
+                .remove(&key)
+                .map(|history| (key, history))
+        })
+        .collect();
+    for (row, col) in dest.cells() {
+        let key = sheet.get_key(row, col);
+        sheet.cell_history.remove(&key);
+    }
+    for (key, history) in histories {
+        sheet.cell_history.insert(relocation.key(key), history);
+    }
+    for range in sheet.named_ranges.values_mut() {
+        *range = relocation.range(*range);
+    }
+
+    sheet.cell_counts = sheet.scan_cell_counts();
+    sheet.set_last_edited(dest.start_row, dest.start_col);
+    sheet.dirty = true;
+    CommandStatus::CmdOk
+}
+
+/// Handles `copy <cell|range> <cell>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `copy `.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * The status of `copy_range`, or of parsing the arguments
+pub fn handle_copy(sheet: &mut Spreadsheet, args: &str, sleep_time: &mut f64) -> CommandStatus {
+    let (source, corner) = match parse_args(sheet, args) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    let (height, width) = shape(&source);
+    match block_at(sheet, corner, height, width) {
+        Ok(dest) => copy_range(sheet, &source, &dest, sleep_time),
+        Err(status) => status,
+    }
+}
+
+/// Handles `move <cell|range> <cell>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `move `.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * The status of `move_range`, or of parsing the arguments
+pub fn handle_move(sheet: &mut Spreadsheet, args: &str, sleep_time: &mut f64) -> CommandStatus {
+    let (source, corner) = match parse_args(sheet, args) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    let (height, width) = shape(&source);
+    match block_at(sheet, corner, height, width) {
+        Ok(dest) => move_range(sheet, &source, &dest, sleep_time),
+        Err(status) => status,
+    }
+}
+
+/// Handles `transpose <range> <cell>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `transpose `.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * The status of `transpose_range`, or of parsing the arguments
+pub fn handle_transpose(
+    sheet: &mut Spreadsheet,
+    args: &str,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    let (source, corner) = match parse_args(sheet, args) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    let (height, width) = shape(&source);
+    match block_at(sheet, corner, width, height) {
+        Ok(dest) => transpose_range(sheet, &source, &dest, sleep_time),
+        Err(status) => status,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spreadsheet::CommandStatus;
+    use crate::test_utils::ScriptedSheet;
+
+    #[test]
+    fn test_copy_shifts_formulas_and_keeps_values() {
+        ScriptedSheet::new(10, 6)
+            .run(&["A1=1", "A2=2", "B1=A1*10", "B2=SUM(A1:A2)", "copy A1:B2 D5"])
+            .assert_status_ok()
+            .assert_cell("D5", 1)
+            .assert_cell("D6", 2)
+            .assert_formula("E5", "D5*10")
+            .assert_formula("E6", "SUM(D5:D6)")
+            .assert_cell("E6", 3)
+            // The source is untouched
+            .assert_formula("B1", "A1*10");
+    }
+
+    #[test]
+    fn test_copy_overlapping_reads_the_source_first() {
+        ScriptedSheet::new(10, 5)
+            .run(&["A1=1", "A2=2", "A3=3", "copy A1:A3 A2"])
+            .assert_status_ok()
+            .assert_cell("A1", 1)
+            .assert_cell("A2", 1)
+            .assert_cell("A3", 2)
+            .assert_cell("A4", 3);
+    }
+
+    #[test]
+    fn test_copy_refuses_locked_destination_without_writing() {
+        ScriptedSheet::new(10, 5)
+            .run(&["A1=1", "A2=2", "lock_cell C2", "copy A1:A2 C1"])
+            .assert_status(CommandStatus::LockedCell)
+            .assert_formula("C1", "")
+            .run(&["copy A1:A2 C10"])
+            .assert_status(CommandStatus::InvalidCell)
+            .run(&["B1=A1-1", "copy B1 A1"])
+            .assert_status(CommandStatus::InvalidCell);
+    }
+
+    #[test]
+    fn test_transpose_swaps_rows_and_columns() {
+        ScriptedSheet::new(10, 8)
+            .run(&[
+                "A1=1",
+                "B1=2",
+                "C1=3",
+                "A2=A1*10",
+                "B2=B1*10",
+                "C2=C1*10",
+                "G1=SUM(E1:F3)",
+                "transpose A1:C2 E1",
+            ])
+            .assert_status_ok()
+            .assert_cell("E1", 1)
+            .assert_cell("E2", 2)
+            .assert_cell("E3", 3)
+            .assert_cell("F3", 30)
+            // References into the source follow it to the transposed copy
+            .assert_formula("F3", "E3*10")
+            .assert_formula("F1", "E1*10")
+            .assert_cell("G1", 66)
+            .run(&["transpose A1:C2 B1"])
+            .assert_status(CommandStatus::Unrecognized)
+            .assert_output(&["the destination of transpose overlaps the source"])
+            .run(&["transpose A1:C2 H1"])
+            .assert_status(CommandStatus::InvalidCell)
+            .run(&["lock_cell B10", "transpose A1:C2 A8"])
+            .assert_status(CommandStatus::LockedCell)
+            .assert_formula("A8", "");
+    }
+
+    #[test]
+    fn test_move_rewrites_references_elsewhere() {
+        ScriptedSheet::new(10, 6)
+            .run(&[
+                "A1=1",
+                "A2=2",
+                "B1=A1+A2",
+                "C1=SUM(A1:A2)",
+                "C2=B1*2",
+                "C3=SUM(A1:A5)",
+                "move A1:B2 D4",
+            ])
+            .assert_status_ok()
+            .assert_formula("E4", "D4+D5")
+            .assert_formula("C1", "SUM(D4:D5)")
+            .assert_formula("C2", "E4*2")
+            // A range reaching outside the source stays where it is
+            .assert_formula("C3", "SUM(A1:A5)")
+            .assert_cell("C3", 0)
+            .assert_formula("A1", "")
+            .run(&["D4=10"])
+            .assert_cell("E4", 12)
+            .assert_cell("C2", 24);
+    }
+
+    #[test]
+    fn test_move_overlapping_and_refusals() {
+        ScriptedSheet::new(10, 5)
+            .run(&["A1=1", "A2=2", "A3=A1+A2", "B1=A3", "move A1:A3 A2"])
+            .assert_status_ok()
+            .assert_cell("A2", 1)
+            .assert_cell("A3", 2)
+            .assert_formula("A4", "A2+A3")
+            .assert_formula("B1", "A4")
+            .assert_cell("B1", 3)
+            .run(&["C5=SUM(C1:C3)", "move C5 C2"])
+            .assert_status(CommandStatus::CircularRef)
+            .assert_formula("C5", "SUM(C1:C3)")
+            .run(&["lock_cell E1", "move A2 E1"])
+            .assert_status(CommandStatus::LockedCell)
+            .assert_cell("A2", 1);
+    }
+
+    #[test]
+    fn test_move_keeps_relative_offsets() {
+        ScriptedSheet::new(10, 8)
+            .run(&["A1=3", "A5=7", "F1=RC[-5]*2", "G1=A1+1", "move F1:H1 F5"])
+            .assert_status_ok()
+            .assert_formula("F5", "RC[-5]*2")
+            .assert_cell("F5", 14)
+            // Plain references keep reading the same cell
+            .assert_formula("G5", "A1+1")
+            .run(&["A5=8"])
+            .assert_cell("F5", 16)
+            .run(&["move F5 B5"])
+            .assert_status(CommandStatus::InvalidCell)
+            .assert_formula("F5", "RC[-5]*2");
+    }
+
+    #[test]
+    fn test_copy_keeps_reference_flags_of_extended_formulas() {
+        ScriptedSheet::new(10, 8)
+            .run(&[
+                "A1=2",
+                "B1=3",
+                "B5=4",
+                "C1=$A$1+B1*2",
+                "C3=-$A$1",
+                "E1=IF(RC[-1]>0,RC[-1],0)",
+                "copy C1:C4 D5",
+                "copy E1 E5",
+            ])
+            .assert_status_ok()
+            .assert_formula("C1", "$A$1+B1*2")
+            .assert_formula("D5", "$A$1+C5*2")
+            .assert_formula("D7", "-$A$1")
+            .assert_cell("D7", -2)
+            .assert_formula("E1", "IF(RC[-1]>0,RC[-1],0)")
+            // E5 reads the copy of C1 in D5
+            .assert_formula("E5", "IF(RC[-1]>0,RC[-1],0)")
+            .assert_cell("E5", 2);
+    }
+
+    #[test]
+    fn test_copy_keeps_pinned_operands_in_precedence_chains() {
+        ScriptedSheet::new(10, 8)
+            .run(&[
+                "A1=2",
+                "B1=3",
+                "B3=5",
+                "C1=B1-$A$1*B1^2",
+                "D1=A1+RC[-1]*2",
+                "copy C1:D1 C3",
+            ])
+            .assert_status_ok()
+            .assert_formula("C3", "B3-$A$1*B3^2")
+            .assert_cell("C3", -45)
+            .assert_formula("D3", "A3+RC[-1]*2")
+            .assert_cell("D3", -90);
+    }
+
+    #[test]
+    fn test_move_keeps_relative_offsets_of_extended_formulas() {
+        ScriptedSheet::new(10, 8)
+            .run(&["A1=2", "A5=6", "C1=IF(RC[-2]>0,RC[-2],$A$1)", "move C1 C5"])
+            .assert_status_ok()
+            .assert_formula("C5", "IF(RC[-2]>0,RC[-2],$A$1)")
+            .assert_cell("C5", 6)
+            .run(&["move C5 A5"])
+            .assert_status(CommandStatus::InvalidCell)
+            .assert_formula("C5", "IF(RC[-2]>0,RC[-2],$A$1)")
+            // A relative corner keeping its offset would lie past the pinned one
+            .run(&["D1=SUM(RC[-3]:$A$2)*2", "move D1 D5"])
+            .assert_status(CommandStatus::InvalidCell)
+            .assert_formula("D1", "SUM(RC[-3]:$A$2)*2");
+    }
+}
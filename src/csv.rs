@@ -0,0 +1,371 @@
+//! `export_csv` and `import_csv`: cell values as comma-separated text.
+//!
+//! Unlike the sheet's own save format, a CSV file holds values only: a formula cell is
+//! written as its current value, and an imported file always gives plain values. Rows
+//! are lines and fields are separated by commas; a field with a comma, a quote or a line
+//! break is quoted, with quotes inside it doubled.
+//!
+//! On import, an unquoted integer becomes a number and an error code such as `#DIV0` an
+//! error; any other field, and every quoted one, becomes a label. Empty fields leave their
+//! cell as it was.
+
+use crate::cell::{CellValue, ErrorKind};
+use crate::extensions::bulk_set_values;
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+/// One field of a CSV record.
+///
+/// # Fields
+///
+/// * `text` - The field's text, without its quotes.
+/// * `quoted` - Whether the field was quoted.
+#[derive(Debug, PartialEq)]
+struct Field {
+    text: String,
+    quoted: bool,
+}
+
+/// Checks whether a file name has the `.csv` extension, in any case.
+pub fn is_csv_file(file: &str) -> bool {
+    file.to_ascii_lowercase().ends_with(".csv")
+}
+
+/// Reads the value of an imported field.
+///
+/// # Returns
+///
+/// * `Some(CellValue)` - The value to write.
+/// * `None` - If the field is empty
+fn field_value(field: &Field) -> Option<CellValue> {
+    if field.quoted {
+        return (!field.text.is_empty()).then(|| CellValue::Text(field.text.clone()));
+    }
+    let text = field.text.trim();
+    if text.is_empty() {
+        None
+    } else if let Ok(number) = text.parse::<i32>() {
+        Some(CellValue::Integer(number))
+    } else if let Some(kind) = text.strip_prefix('#').and_then(ErrorKind::from_name) {
+        Some(CellValue::Error(kind))
+    } else {
+        Some(CellValue::Text(text.to_string()))
+    }
+}
+
+/// Writes a cell value as a CSV field.
+///
+/// A label is quoted when it holds a separator or quote, or when unquoted it would read
+/// back as something else, such as a number.
+fn csv_field(value: &CellValue) -> String {
+    match value {
+        CellValue::Integer(number) => number.to_string(),
+        CellValue::Error(kind) => kind.code(),
+        CellValue::Empty => String::new(),
+        CellValue::Text(text) => {
+            let plain = Field {
+                text: text.clone(),
+                quoted: false,
+            };
+            if text.contains([',', '"', '\n', '\r']) || field_value(&plain) != Some(value.clone()) {
+                format!("\"{}\"", text.replace('"', "\"\""))
+            } else {
+                text.clone()
+            }
+        }
+    }
+}
+
+/// Writes the values of the sheet as CSV, from A1 to the last row and column holding a
+/// value.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `writer` - Where the rows go.
+///
+/// # Returns
+///
+/// * `io::Result<usize>` - The number of rows written, or an error if writing fails
+pub fn write_csv<W: Write>(sheet: &Spreadsheet, writer: &mut W) -> io::Result<usize> {
+    let cols = sheet.cols as usize;
+    let mut rows = 0;
+    let mut last_col = 0;
+    for (index, value) in sheet.grid.iter().enumerate() {
+        if !matches!(value, CellValue::Empty) {
+            rows = index / cols + 1;
+            last_col = last_col.max(index % cols);
+        }
+    }
+    for row in 0..rows as i16 {
+        let fields: Vec<String> = (0..=last_col as i16)
+            .map(|col| csv_field(sheet.get_cell(row, col)))
+            .collect();
+        writeln!(writer, "{}", fields.join(","))?;
+    }
+    writer.flush()?;
+    Ok(rows)
+}
+
+/// Splits CSV text into records of fields.
+///
+/// Line breaks may be `\n` or `\r\n`, and a quoted field may span lines.
+///
+/// # Returns
+///
+/// * `Some(Vec<Vec<Field>>)` - The records.
+/// * `None` - If a quoted field is not closed
+fn parse_csv(text: &str) -> Option<Vec<Vec<Field>>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                quoted = true;
+                loop {
+                    match chars.next()? {
+                        '"' if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        '"' => break,
+                        c => field.push(c),
+                    }
+                }
+            }
+            ',' => record.push(Field {
+                text: std::mem::take(&mut field),
+                quoted: std::mem::take(&mut quoted),
+            }),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(Field {
+                    text: std::mem::take(&mut field),
+                    quoted: std::mem::take(&mut quoted),
+                });
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    // The last line may have no line break
+    if !field.is_empty() || quoted || !record.is_empty() {
+        record.push(Field {
+            text: field,
+            quoted,
+        });
+        records.push(record);
+    }
+    Some(records)
+}
+
+/// Handles `export_csv <file>`, writing the values of the sheet to a CSV file.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `file` - The file to write.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If no file is given or writing fails
+pub fn export_csv(sheet: &mut Spreadsheet, file: &str) -> CommandStatus {
+    if file.is_empty() {
+        return CommandStatus::Unrecognized;
+    }
+    let written = File::create(file).and_then(|out| write_csv(sheet, &mut BufWriter::new(out)));
+    match written {
+        Ok(rows) => {
+            sheet
+                .output
+                .line(format!("Wrote {} row(s) to '{}'", rows, file));
+            CommandStatus::CmdOk
+        }
+        Err(err) => {
+            eprintln!("Failed to write '{}': {}", file, err);
+            CommandStatus::Unrecognized
+        }
+    }
+}
+
+/// Handles `import_csv <file>`, writing the values of a CSV file into the sheet from A1.
+///
+/// Nothing is written if the file does not fit in the sheet or a cell it would write is
+/// locked or owned by another user. Formulas in the written cells are dropped and their
+/// dependents reevaluated, as for a range fill.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `file` - The file to read.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::InvalidCell` - If the file has more rows or columns than the sheet.
+/// * `CommandStatus::LockedCell` - If a cell to write is locked.
+/// * `CommandStatus::NotOwner` - If a cell to write is owned by someone else.
+/// * `CommandStatus::Unrecognized` - If the file cannot be read or a quote is not closed
+pub fn import_csv(sheet: &mut Spreadsheet, file: &str, sleep_time: &mut f64) -> CommandStatus {
+    if file.is_empty() {
+        return CommandStatus::Unrecognized;
+    }
+    let text = match fs::read_to_string(file) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("Failed to read '{}': {}", file, err);
+            return CommandStatus::Unrecognized;
+        }
+    };
+    let Some(records) = parse_csv(&text) else {
+        sheet
+            .output
+            .line(format!("'{}' has a quoted field that is not closed", file));
+        return CommandStatus::Unrecognized;
+    };
+    let cols = records.iter().map(Vec::len).max().unwrap_or(0);
+    if records.len() > sheet.rows as usize || cols > sheet.cols as usize {
+        sheet.output.line(format!(
+            "'{}' has {} row(s) and {} column(s); the sheet has {} and {}",
+            file,
+            records.len(),
+            cols,
+            sheet.rows,
+            sheet.cols
+        ));
+        return CommandStatus::InvalidCell;
+    }
+
+    let writes: Vec<(i16, i16, CellValue)> = records
+        .iter()
+        .enumerate()
+        .flat_map(|(row, record)| {
+            record.iter().enumerate().filter_map(move |(col, field)| {
+                field_value(field).map(|value| (row as i16, col as i16, value))
+            })
+        })
+        .collect();
+    if writes
+        .iter()
+        .any(|&(row, col, _)| sheet.is_cell_locked(row, col))
+    {
+        return CommandStatus::LockedCell;
+    }
+    if writes
+        .iter()
+        .any(|&(row, col, _)| sheet.check_owner(row, col) != CommandStatus::CmdOk)
+    {
+        return CommandStatus::NotOwner;
+    }
+
+    bulk_set_values(sheet, &writes, sleep_time);
+    for &(row, col, _) in &writes {
+        let key = sheet.get_key(row, col);
+        sheet.assigned.insert(key);
+    }
+    sheet.dirty = true;
+    sheet
+        .output
+        .line(format!("Imported {} cell(s) from '{}'", writes.len(), file));
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::ScriptedSheet;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_export_and_import_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("values.csv");
+        let file = path.to_str().unwrap();
+        let export = format!("export_csv {}", file);
+        let scripted = ScriptedSheet::new(10, 10)
+            .run(&[
+                "A1=3",
+                "B1=A1*4",
+                "C1=A1/0",
+                "A2=\"Smith, J\"",
+                "B2=\"say \"\"hi\"\"\"",
+                "C3=\"42\"",
+                &export,
+            ])
+            .assert_status_ok()
+            .assert_output(&[&format!("Wrote 3 row(s) to '{}'", file)]);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "3,12,#DIV0\n\"Smith, J\",\"say \"\"hi\"\"\",\n,,\"42\"\n"
+        );
+
+        let import = format!("import_csv {}", file);
+        let imported = ScriptedSheet::new(5, 5)
+            .run(&["E5=SUM(A1:B1)", &import])
+            .assert_status_ok()
+            .assert_output(&[&format!("Imported 6 cell(s) from '{}'", file)])
+            .assert_cell("B1", 12)
+            .assert_formula("B1", "")
+            .assert_error_cell("C1", ErrorKind::DivZero)
+            .assert_value("A2", CellValue::Text("Smith, J".to_string()))
+            .assert_value("B2", CellValue::Text("say \"hi\"".to_string()))
+            .assert_value("C3", CellValue::Text("42".to_string()))
+            .assert_cell("E5", 15);
+        for row in 0..3 {
+            for col in 0..3 {
+                assert_eq!(
+                    imported.sheet.get_cell(row, col),
+                    scripted.sheet.get_cell(row, col)
+                );
+            }
+        }
+        imported
+            .run(&["undo"])
+            .assert_status_ok()
+            .assert_value("A1", CellValue::Empty)
+            .assert_cell("E5", 0);
+    }
+
+    #[test]
+    fn test_import_refusals() {
+        let dir = tempdir().unwrap();
+        let wide = dir.path().join("wide.csv");
+        fs::write(&wide, "1,2,3,4\r\n5,6,7,8\r\n").unwrap();
+        let open_quote = dir.path().join("open_quote.csv");
+        fs::write(&open_quote, "1,\"never closed\n2\n").unwrap();
+        let sparse = dir.path().join("sparse.csv");
+        fs::write(&sparse, "1,,3\n\n,,9").unwrap();
+
+        ScriptedSheet::new(3, 3)
+            .run(&[&format!("import_csv {}", wide.display())])
+            .assert_status(CommandStatus::InvalidCell)
+            .assert_output(&[&format!(
+                "'{}' has 2 row(s) and 4 column(s); the sheet has 3 and 3",
+                wide.display()
+            )])
+            .assert_value("A1", CellValue::Empty)
+            .run(&[&format!("import_csv {}", open_quote.display())])
+            .assert_status(CommandStatus::Unrecognized)
+            .run(&["lock_cell C3", &format!("import_csv {}", sparse.display())])
+            .assert_status(CommandStatus::LockedCell)
+            .assert_value("A1", CellValue::Empty)
+            // Empty fields leave their cells alone
+            .run(&[
+                "unlock_cell C3",
+                "B1=5",
+                &format!("import_csv {}", sparse.display()),
+            ])
+            .assert_status_ok()
+            .assert_cell("A1", 1)
+            .assert_cell("B1", 5)
+            .assert_cell("C3", 9)
+            .run(&["import_csv"])
+            .assert_status(CommandStatus::Unrecognized);
+    }
+}
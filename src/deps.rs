@@ -0,0 +1,182 @@
+//! Plain lists of what a cell reads and what reads it, as in `deps A1` and `precedents A1`.
+//!
+//! Where `visual` draws trees, these commands print one reference per line, sorted in
+//! reading order, so their output is easy to use from scripts. `deps* A1` and
+//! `precedents* A1` (or a trailing `--transitive`) follow the references all the way.
+
+use crate::evaluator::resolve_cell_reference;
+use crate::graph::{Precedent, direct_dependents, direct_precedents};
+use crate::reevaluate_topo::transitive_dependents;
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use std::collections::BTreeSet;
+
+/// Formats a key as a reference such as `B3`.
+fn key_ref(sheet: &Spreadsheet, key: i32) -> String {
+    let (row, col) = sheet.get_row_col(key);
+    format!("{}{}", sheet.get_column_name(col), row + 1)
+}
+
+/// Formats a precedent as `B3` or `A1:B5`.
+fn precedent_ref(sheet: &Spreadsheet, precedent: Precedent) -> String {
+    match precedent {
+        Precedent::Cell(key) => key_ref(sheet, key),
+        Precedent::Range(start, end) => {
+            format!("{}:{}", key_ref(sheet, start), key_ref(sheet, end))
+        }
+    }
+}
+
+/// Lists what a cell reads, following the formulas it reads if `transitive` is set.
+///
+/// A range is listed as one range; with `transitive`, the formulas inside it are followed
+/// too.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `key` - The cell's key.
+/// * `transitive` - Whether to include the precedents of precedents.
+///
+/// # Returns
+///
+/// * `BTreeSet<Precedent>` - The precedents: cells in reading order, then ranges
+pub fn precedents(sheet: &Spreadsheet, key: i32, transitive: bool) -> BTreeSet<Precedent> {
+    let mut found = BTreeSet::new();
+    let mut pending = vec![key];
+    while let Some(current) = pending.pop() {
+        for precedent in direct_precedents(sheet, current) {
+            if !found.insert(precedent) || !transitive {
+                continue;
+            }
+            match precedent {
+                Precedent::Cell(cell) => pending.push(cell),
+                Precedent::Range(start, end) => pending.extend(
+                    sheet
+                        .cell_meta
+                        .keys()
+                        .filter(|&&cell| sheet.is_cell_in_range(cell, start, end)),
+                ),
+            }
+        }
+    }
+    found
+}
+
+/// Lists the cells that read a cell, directly or, with `transitive`, through other cells.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `key` - The cell's key.
+/// * `transitive` - Whether to include the dependents of dependents.
+///
+/// # Returns
+///
+/// * `Vec<i32>` - Keys of the dependents, sorted
+pub fn dependents(sheet: &Spreadsheet, key: i32, transitive: bool) -> Vec<i32> {
+    if transitive {
+        transitive_dependents(sheet, key)
+    } else {
+        direct_dependents(sheet, key)
+    }
+}
+
+/// Splits `<cell> [--transitive]` into the cell's key and the flag.
+fn parse_args(sheet: &Spreadsheet, args: &str) -> Result<(i32, bool), CommandStatus> {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let (cell, transitive) = match parts[..] {
+        [cell] => (cell, false),
+        [cell, "--transitive"] => (cell, true),
+        _ => return Err(CommandStatus::Unrecognized),
+    };
+    let (row, col) = resolve_cell_reference(sheet, cell)?;
+    Ok((sheet.get_key(row, col), transitive))
+}
+
+/// Handles `deps <cell> [--transitive]`, printing one dependent per line.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `deps `.
+/// * `transitive` - True for `deps*`, which follows dependents like `--transitive`.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus` - From parsing the cell reference
+pub fn handle_deps(sheet: &mut Spreadsheet, args: &str, transitive: bool) -> CommandStatus {
+    let (key, flag) = match parse_args(sheet, args) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    let lines: Vec<String> = dependents(sheet, key, transitive || flag)
+        .into_iter()
+        .map(|dependent| key_ref(sheet, dependent))
+        .collect();
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
+/// Handles `precedents <cell> [--transitive]`, printing one precedent per line.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `precedents `.
+/// * `transitive` - True for `precedents*`, which follows precedents like `--transitive`.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus` - From parsing the cell reference
+pub fn handle_precedents(sheet: &mut Spreadsheet, args: &str, transitive: bool) -> CommandStatus {
+    let (key, flag) = match parse_args(sheet, args) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    let lines: Vec<String> = precedents(sheet, key, transitive || flag)
+        .into_iter()
+        .map(|precedent| precedent_ref(sheet, precedent))
+        .collect();
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spreadsheet::CommandStatus;
+    use crate::test_utils::ScriptedSheet;
+
+    const CHAIN: [&str; 5] = ["A1=1", "B1=A1+2", "C1=SUM(A1:B1)", "D1=C1*B1", "E1=4+5"];
+
+    #[test]
+    fn test_deps_direct_and_transitive() {
+        ScriptedSheet::new(10, 10)
+            .run(&CHAIN)
+            .run(&["deps A1"])
+            .assert_status_ok()
+            .assert_output(&["B1", "C1"])
+            .run(&["deps* A1"])
+            .assert_output(&["B1", "C1", "D1"])
+            .run(&["deps B1 --transitive"])
+            .assert_output(&["C1", "D1"])
+            .run(&["deps E1"])
+            .assert_output(&[]);
+    }
+
+    #[test]
+    fn test_precedents_tell_literals_from_cells() {
+        ScriptedSheet::new(10, 10)
+            .run(&CHAIN)
+            .run(&["precedents D1"])
+            .assert_status_ok()
+            .assert_output(&["B1", "C1"])
+            .run(&["precedents* D1"])
+            .assert_output(&["A1", "B1", "C1", "A1:B1"])
+            .run(&["precedents E1"])
+            .assert_output(&[])
+            .run(&["precedents B1 --all"])
+            .assert_status(CommandStatus::Unrecognized);
+    }
+}
@@ -0,0 +1,900 @@
+//! `dry <command>`: runs a command, reports what it would change, then puts everything back.
+//!
+//! The command runs on the real sheet, so cycle detection, locks and every other check
+//! behave exactly as they would for real. What it changes is journaled as it happens, so
+//! a dry run over a few cells of a large sheet copies only those cells:
+//!
+//! * Cells are captured before their first change into an undo step of the dry run's own
+//!   (see `undo`), which is put back afterwards as `undo` would. A command that moves or
+//!   drops every cell saves the whole layout there instead, as it does for `undo`.
+//! * Grid writes also record the old value (see `Spreadsheet::get_mut_cell`), which the
+//!   report compares against.
+//! * A cell's history is copied before it changes; `undo` and `redo` copy the undo
+//!   history before they change it.
+//!
+//! The rest of the state a command can change (names, locks, settings) is small next to
+//! the grid and is copied whole when the dry run starts.
+//!
+//! Commands that read or write files cannot be dry-run, nor can a script line that does;
+//! dry runs do not nest.
+
+use crate::cell::CellValue;
+use crate::cell_history::HistoryEntry;
+use crate::column_formula::ColumnFormula;
+use crate::evaluator::handle_command;
+use crate::filter::RowFilter;
+use crate::history_file::HistoryConfig;
+use crate::ownership::Ownership;
+use crate::range::Range;
+use crate::scripting::handle_scripting_command;
+use crate::search::Search;
+use crate::spreadsheet::{CellCounts, CellMeta, CommandStatus, HighlightType, Spreadsheet};
+use crate::undo::{UndoLog, UndoStep, put_back};
+use std::collections::{BTreeMap, HashMap};
+
+/// What a dry run journals besides its undo step.
+///
+/// # Fields
+///
+/// * `values` - Old values of the grid cells written, by cell key.
+/// * `history` - Each changed history as it was, by cell key; `None` if the cell had none.
+/// * `all_history` - Every cell's history, if the command changed them all.
+/// * `undo_log` - The undo history, if `undo` or `redo` changed it.
+#[derive(Default)]
+pub struct DryRunJournal {
+    values: HashMap<i32, CellValue>,
+    history: HashMap<i32, Option<Vec<HistoryEntry>>>,
+    all_history: Option<HashMap<i32, Vec<HistoryEntry>>>,
+    undo_log: Option<UndoLog>,
+}
+
+impl DryRunJournal {
+    /// Puts back what the journal and the dry run's undo step hold.
+    ///
+    /// The saved layout and whole history come first: entries journaled after them are
+    /// not recorded, and those journaled before are put back over them.
+    fn put_back(self, sheet: &mut Spreadsheet, step: Option<UndoStep>) {
+        if let Some(step) = step {
+            put_back(sheet, step);
+        }
+        if let Some(all_history) = self.all_history {
+            sheet.cell_history = all_history;
+        }
+        for (key, history) in self.history {
+            match history {
+                Some(history) => sheet.cell_history.insert(key, history),
+                None => sheet.cell_history.remove(&key),
+            };
+        }
+        if let Some(undo_log) = self.undo_log {
+            sheet.undo_log = undo_log;
+        }
+    }
+}
+
+/// Commands that work on files, which a dry run could not undo.
+const FILE_COMMANDS: [&str; 12] = [
+    "save",
+    "autosave",
+    "saveas",
+    "open",
+    "reload",
+    "reload!",
+    "session",
+    "restore_backup",
+    "restore_backup!",
+    "export_csv",
+    "import_csv",
+    "visual",
+];
+
+/// Checks whether a command reads or writes files.
+///
+/// # Arguments
+///
+/// * `command` - The command, trimmed.
+///
+/// # Returns
+///
+/// * `bool` - True for the file commands, `set history_file` and `graph_stats --csv`
+pub fn works_on_files(command: &str) -> bool {
+    let name = command.split_whitespace().next().unwrap_or("");
+    FILE_COMMANDS.contains(&name)
+        || command.starts_with("set history_file")
+        || command.starts_with("graph_stats --csv")
+}
+
+/// How many changed cells a dry run lists.
+const SAMPLE_SIZE: usize = 5;
+
+/// The state besides the cells that a command may change, copied when a dry run starts.
+///
+/// `save` names every field of the sheet and `restore` every field of this copy, so a
+/// field added to either does not compile until the dry run decides what to do with it.
+struct SavedState {
+    viewport: (i16, i16),
+    output_enabled: bool,
+    page_size: usize,
+    locked_ranges: Vec<Range>,
+    named_ranges: HashMap<String, Range>,
+    history_limit: usize,
+    last_edited: Option<(i16, i16)>,
+    highlight: (i32, HighlightType),
+    limits: (i64, usize),
+    dirty: bool,
+    column_formulas: BTreeMap<i16, ColumnFormula>,
+    vars: BTreeMap<String, i32>,
+    cell_counts: CellCounts,
+    prompt_stats: bool,
+    history: HistoryConfig,
+    row_filter: Option<RowFilter>,
+    confirm_overwrite_bulk: bool,
+    backups: usize,
+    ownership: Ownership,
+    search: Option<Search>,
+    read_only: bool,
+    autosave_every: usize,
+}
+
+impl SavedState {
+    /// Copies the state of a sheet a command may change.
+    fn save(sheet: &Spreadsheet) -> Self {
+        // Every field is named, so a new one does not compile until it is decided here
+        let Spreadsheet {
+            // Journaled cell by cell as they change, or put back by the undo step
+            grid: _,
+            children: _,
+            range_children: _,
+            cell_meta: _,
+            criteria: _,
+            extended_formulas: _,
+            assigned: _,
+            relative_refs: _,
+            cell_history: _,
+            undo_log: _,
+            // Only file commands, which are not dry-run, change these
+            rows: _,
+            cols: _,
+            current_file: _,
+            file_stamp: _,
+            saved_baseline: _,
+            autosave: _,
+            viewport_row,
+            viewport_col,
+            output_enabled,
+            // Scratch space, rebuilt by every evaluation
+            topo_scratch: _,
+            locked_ranges,
+            named_ranges,
+            history_limit,
+            last_edited,
+            highlight_cell,
+            highlight_type,
+            range_cell_limit,
+            line_byte_limit,
+            output,
+            dirty,
+            column_formulas,
+            script,
+            cell_counts,
+            prompt_stats,
+            history,
+            row_filter,
+            confirm_overwrite_bulk,
+            // Never pending when a command starts; dropped in `restore`
+            pending_overwrite: _,
+            // The dry run's own journal
+            dry_run: _,
+            backups,
+            ownership,
+            search,
+            read_only,
+            autosave_every,
+        } = sheet;
+        SavedState {
+            viewport: (*viewport_row, *viewport_col),
+            output_enabled: *output_enabled,
+            page_size: output.page_size,
+            locked_ranges: locked_ranges.clone(),
+            named_ranges: named_ranges.clone(),
+            history_limit: *history_limit,
+            last_edited: *last_edited,
+            highlight: (*highlight_cell, *highlight_type),
+            limits: (*range_cell_limit, *line_byte_limit),
+            dirty: *dirty,
+            column_formulas: column_formulas.clone(),
+            vars: script.vars.clone(),
+            cell_counts: *cell_counts,
+            prompt_stats: *prompt_stats,
+            history: history.clone(),
+            row_filter: row_filter.clone(),
+            confirm_overwrite_bulk: *confirm_overwrite_bulk,
+            backups: *backups,
+            ownership: ownership.clone(),
+            search: search.clone(),
+            read_only: *read_only,
+            autosave_every: *autosave_every,
+        }
+    }
+
+    /// Puts the copied state back after the command ran.
+    fn restore(self, sheet: &mut Spreadsheet) {
+        // Named in full too, so a field copied in `save` cannot be forgotten here
+        let SavedState {
+            viewport,
+            output_enabled,
+            page_size,
+            locked_ranges,
+            named_ranges,
+            history_limit,
+            last_edited,
+            highlight,
+            limits,
+            dirty,
+            column_formulas,
+            vars,
+            cell_counts,
+            prompt_stats,
+            history,
+            row_filter,
+            confirm_overwrite_bulk,
+            backups,
+            ownership,
+            search,
+            read_only,
+            autosave_every,
+        } = self;
+        (sheet.viewport_row, sheet.viewport_col) = viewport;
+        sheet.output_enabled = output_enabled;
+        sheet.output.page_size = page_size;
+        sheet.locked_ranges = locked_ranges;
+        sheet.named_ranges = named_ranges;
+        sheet.history_limit = history_limit;
+        sheet.last_edited = last_edited;
+        (sheet.highlight_cell, sheet.highlight_type) = highlight;
+        (sheet.range_cell_limit, sheet.line_byte_limit) = limits;
+        sheet.dirty = dirty;
+        sheet.column_formulas = column_formulas;
+        sheet.script.vars = vars;
+        sheet.cell_counts = cell_counts;
+        sheet.prompt_stats = prompt_stats;
+        sheet.history = history;
+        sheet.row_filter = row_filter;
+        sheet.confirm_overwrite_bulk = confirm_overwrite_bulk;
+        // A bulk write the command left waiting for answers would otherwise run for real
+        sheet.pending_overwrite = None;
+        sheet.backups = backups;
+        sheet.ownership = ownership;
+        sheet.search = search;
+        sheet.read_only = read_only;
+        sheet.autosave_every = autosave_every;
+    }
+}
+
+impl Spreadsheet {
+    /// Records a cell's value before its first write during a dry run, and the whole cell
+    /// for `undo` while a command is recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The cell's index in the grid, which is also its key.
+    pub fn journal_write(&mut self, index: usize) {
+        self.note_undo(index as i32);
+        if let Some(journal) = &mut self.dry_run {
+            // Rows past the end of the grid have not been written yet and are still empty
+            journal
+                .values
+                .entry(index as i32)
+                .or_insert_with(|| self.grid.get(index).cloned().unwrap_or(CellValue::Empty));
+        }
+    }
+
+    /// Checks whether a saved layout or whole history already holds every history.
+    fn history_saved(&self, journal: &DryRunJournal) -> bool {
+        journal.all_history.is_some()
+            || self
+                .undo_log
+                .recording
+                .as_ref()
+                .is_some_and(|step| step.layout.is_some())
+    }
+
+    /// Records a cell's history before its first change during a dry run.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The cell's key.
+    pub fn journal_history(&mut self, key: i32) {
+        let Some(journal) = &self.dry_run else {
+            return;
+        };
+        if self.history_saved(journal) || journal.history.contains_key(&key) {
+            return;
+        }
+        let history = self.cell_history.get(&key).cloned();
+        if let Some(journal) = &mut self.dry_run {
+            journal.history.insert(key, history);
+        }
+    }
+
+    /// Records every cell's history during a dry run, before a change to all of them.
+    pub fn journal_all_history(&mut self) {
+        let Some(journal) = &self.dry_run else {
+            return;
+        };
+        if self.history_saved(journal) {
+            return;
+        }
+        let all_history = self.cell_history.clone();
+        if let Some(journal) = &mut self.dry_run {
+            journal.all_history = Some(all_history);
+        }
+    }
+
+    /// Records the undo history during a dry run, before `undo` or `redo` changes it.
+    pub fn journal_undo_log(&mut self) {
+        if let Some(journal) = &mut self.dry_run
+            && journal.undo_log.is_none()
+        {
+            // The dry run's own step is not part of it
+            journal.undo_log = Some(UndoLog {
+                undo: self.undo_log.undo.clone(),
+                redo: self.undo_log.redo.clone(),
+                recording: None,
+            });
+        }
+    }
+}
+
+/// Checks that a command can be dry-run, printing why not.
+///
+/// Scripts run by `dry run` have each line checked the same way.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `command` - The command, trimmed.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the command can run during a dry run.
+/// * `Err(CommandStatus::Unrecognized)` - For a nested dry run or a file command
+pub fn check_dry_runnable(sheet: &mut Spreadsheet, command: &str) -> Result<(), CommandStatus> {
+    let name = command.split_whitespace().next().unwrap_or("");
+    if name == "dry" {
+        sheet.output.line("dry run: dry runs cannot be nested");
+        return Err(CommandStatus::Unrecognized);
+    }
+    if works_on_files(command) {
+        sheet.output.line(format!(
+            "dry run: '{}' works on files and cannot be dry-run",
+            name
+        ));
+        return Err(CommandStatus::Unrecognized);
+    }
+    Ok(())
+}
+
+/// Formats a value for the dry run report.
+fn describe_value(value: &CellValue) -> String {
+    match value {
+        CellValue::Integer(value) => value.to_string(),
+        CellValue::Error(kind) => kind.code(),
+        CellValue::Text(text) => format!("\"{}\"", text),
+        CellValue::Empty => "empty".to_string(),
+    }
+}
+
+/// Handles `dry <command>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `command` - The command to dry-run.
+///
+/// # Returns
+///
+/// * The command's own status - It is reported as usual, but nothing it did is kept.
+/// * `CommandStatus::Unrecognized` - For a missing, nested or file command
+pub fn handle_dry(sheet: &mut Spreadsheet, command: &str) -> CommandStatus {
+    let command = command.trim();
+    if command.is_empty() || sheet.dry_run.is_some() {
+        return CommandStatus::Unrecognized;
+    }
+    if let Err(status) = check_dry_runnable(sheet, command) {
+        return status;
+    }
+
+    let saved = SavedState::save(sheet);
+    sheet.dry_run = Some(DryRunJournal::default());
+    // The commands it runs, such as the lines of a script, record into this step too
+    sheet.undo_log.recording = Some(UndoStep {
+        command: command.to_string(),
+        cells: HashMap::new(),
+        layout: None,
+    });
+    // SLEEP formulas are evaluated, but a dry run never sleeps
+    let mut sleep_time = 0.0;
+    let status = match handle_scripting_command(sheet, command, &mut sleep_time) {
+        Some(status) => status,
+        None => handle_command(sheet, command, &mut sleep_time),
+    };
+    let journal = sheet.dry_run.take().unwrap_or_default();
+    let step = sheet.undo_log.recording.take();
+
+    // Compare each written cell with its old value, and its formula with the one put back
+    let mut keys: Vec<i32> = journal.values.keys().copied().collect();
+    keys.sort_unstable();
+    let formula = |meta: Option<&CellMeta>| meta.map(|m| (m.formula, m.parent1, m.parent2));
+    let mut changed = Vec::new();
+    let mut formulas = Vec::with_capacity(keys.len());
+    for &key in &keys {
+        let old = &journal.values[&key];
+        if sheet.get_key_cell(key) != old {
+            let (row, col) = sheet.get_row_col(key);
+            changed.push(format!(
+                "  {}: {} -> {}",
+                sheet.get_cell_name(row, col),
+                describe_value(old),
+                describe_value(sheet.get_key_cell(key))
+            ));
+        }
+        formulas.push(formula(sheet.cell_meta.get(&key)));
+    }
+    journal.put_back(sheet, step);
+    saved.restore(sheet);
+    let rewritten = keys
+        .iter()
+        .zip(formulas)
+        .filter(|&(key, new)| formula(sheet.cell_meta.get(key)) != new)
+        .count();
+
+    if status != CommandStatus::CmdOk {
+        sheet.output.line(format!(
+            "dry run: the command would fail ({}); nothing was changed",
+            status.as_str()
+        ));
+        return status;
+    }
+    sheet.output.line(format!(
+        "dry run: {} cell(s) would change, {} formula(s) would be rewritten; nothing was changed",
+        changed.len(),
+        rewritten
+    ));
+    let more = changed.len().saturating_sub(SAMPLE_SIZE);
+    for line in changed.into_iter().take(SAMPLE_SIZE) {
+        sheet.output.line(line);
+    }
+    if more > 0 {
+        sheet.output.line(format!("  ... and {} more", more));
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::OutputSink;
+
+    /// A1 = 5, A2 = A1*2, A3 = 7, B1 = SUM(A1:A3) and a lock on C1.
+    fn fixture() -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(10, 4).unwrap();
+        sheet.output = OutputSink::capture();
+        let mut sleep_time = 0.0;
+        for command in ["A1=5", "A2=A1*2", "A3=7", "B1=SUM(A1:A3)", "lock_cell C1"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        sheet.output.take();
+        sheet
+    }
+
+    /// Everything a dry run must leave as it was, in a comparable form.
+    fn fingerprint(sheet: &Spreadsheet) -> String {
+        let mut meta: Vec<_> = sheet
+            .cell_meta
+            .iter()
+            .map(|(key, m)| (*key, m.formula, m.parent1, m.parent2))
+            .collect();
+        meta.sort_unstable();
+        let mut children: Vec<_> = sheet
+            .children
+            .iter()
+            .map(|(key, set)| {
+                let mut set: Vec<_> = set.iter().copied().collect();
+                set.sort_unstable();
+                (*key, set)
+            })
+            .collect();
+        children.sort_unstable();
+        let mut history: Vec<_> = sheet.cell_history.iter().collect();
+        history.sort_unstable_by_key(|(key, _)| **key);
+        let mut relative: Vec<_> = sheet.relative_refs.iter().collect();
+        relative.sort_unstable();
+        let mut range_children: Vec<_> = sheet
+            .range_children
+            .iter()
+            .map(|rc| (rc.child_key, format!("{:?}", rc.range)))
+            .collect();
+        range_children.sort_unstable();
+        let mut assigned: Vec<_> = sheet.assigned.iter().collect();
+        assigned.sort_unstable();
+        let undo: Vec<_> = (sheet.undo_log.undo.iter())
+            .chain(&sheet.undo_log.redo)
+            .map(|step| step.command.clone())
+            .collect();
+        format!(
+            "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+            sheet.cell_values().collect::<Vec<_>>(),
+            meta,
+            children,
+            range_children,
+            history,
+            relative,
+            assigned,
+            (undo, sheet.undo_log.undo.len()),
+            sheet.undo_log.recording.is_some(),
+            sheet.dirty,
+            sheet.last_edited,
+            sheet.locked_ranges.len()
+        )
+    }
+
+    fn dry(sheet: &mut Spreadsheet, command: &str) -> (CommandStatus, Vec<String>) {
+        let mut sleep_time = 0.0;
+        let status = handle_command(sheet, &format!("dry {}", command), &mut sleep_time);
+        (status, sheet.output.take())
+    }
+
+    #[test]
+    fn test_dry_range_fill_changes_nothing() {
+        let mut sheet = fixture();
+        let before = fingerprint(&sheet);
+        let (status, lines) = dry(&mut sheet, "A1:A3=0");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(fingerprint(&sheet), before);
+        assert!(lines.contains(
+            &"dry run: 4 cell(s) would change, 1 formula(s) would be rewritten; nothing was changed"
+                .to_string()
+        ));
+        assert!(lines.contains(&"  A1: 5 -> 0".to_string()));
+        assert!(lines.contains(&"  B1: 22 -> 0".to_string()));
+
+        // The real command still does what the dry run said
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A1:A3=0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(0));
+    }
+
+    #[test]
+    fn test_dry_replace_and_sort_change_nothing() {
+        let mut sheet = fixture();
+        let before = fingerprint(&sheet);
+        let (status, lines) = dry(&mut sheet, "replace 7 0");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(fingerprint(&sheet), before);
+        assert!(lines.iter().any(|line| line == "  A3: 7 -> 0"));
+
+        let (status, _) = dry(&mut sheet, "sort A1:A3 by A desc");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(fingerprint(&sheet), before);
+    }
+
+    #[test]
+    fn test_dry_clear_sheet_changes_nothing() {
+        let mut sheet = fixture();
+        let before = fingerprint(&sheet);
+        let (status, lines) = dry(&mut sheet, "clear_sheet");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(fingerprint(&sheet), before);
+        assert_eq!(
+            lines[0],
+            "dry run: 4 cell(s) would change, 2 formula(s) would be rewritten; nothing was changed"
+        );
+    }
+
+    #[test]
+    fn test_dry_delete_row_changes_nothing() {
+        let mut sheet = fixture();
+        let before = fingerprint(&sheet);
+        let (status, lines) = dry(&mut sheet, "delete_row 2");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(fingerprint(&sheet), before);
+        assert!(lines[0].starts_with("dry run: 3 cell(s) would change"));
+    }
+
+    #[test]
+    fn test_dry_run_samples_long_reports() {
+        let mut sheet = fixture();
+        let (_, lines) = dry(&mut sheet, "D1:D8=1");
+        // The command's own summary comes first, then the dry run report
+        assert_eq!(lines[0], "D1:D8: 8 cell(s) set, 0 skipped");
+        assert_eq!(
+            lines[1],
+            "dry run: 8 cell(s) would change, 0 formula(s) would be rewritten; nothing was changed"
+        );
+        assert_eq!(lines.len(), 2 + SAMPLE_SIZE + 1);
+        assert_eq!(lines.last().unwrap(), "  ... and 3 more");
+        assert_eq!(*sheet.get_cell(7, 3), CellValue::Empty);
+    }
+
+    #[test]
+    fn test_dry_cycle_is_reported() {
+        let mut sheet = fixture();
+        let before = fingerprint(&sheet);
+        let (status, lines) = dry(&mut sheet, "A1=B1");
+        assert_eq!(status, CommandStatus::CircularRef);
+        assert_eq!(fingerprint(&sheet), before);
+        assert_eq!(
+            lines,
+            vec!["dry run: the command would fail (circular_ref); nothing was changed"]
+        );
+        let (status, _) = dry(&mut sheet, "C1=1");
+        assert_eq!(status, CommandStatus::LockedCell);
+        assert_eq!(fingerprint(&sheet), before);
+    }
+
+    #[test]
+    fn test_dry_rejects_nested_empty_and_file_commands() {
+        let mut sheet = fixture();
+        let before = fingerprint(&sheet);
+        let (status, lines) = dry(&mut sheet, "dry A1=1");
+        assert_eq!(status, CommandStatus::Unrecognized);
+        assert_eq!(lines, vec!["dry run: dry runs cannot be nested"]);
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "dry", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+        let (status, _) = dry(&mut sheet, "save out.sheet");
+        assert_eq!(status, CommandStatus::Unrecognized);
+        assert_eq!(fingerprint(&sheet), before);
+        assert!(sheet.dry_run.is_none());
+    }
+
+    #[test]
+    fn test_dry_csv_commands_touch_no_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+        let path = path.to_str().unwrap();
+        let mut sheet = fixture();
+        let before = fingerprint(&sheet);
+        let (status, lines) = dry(&mut sheet, &format!("export_csv {}", path));
+        assert_eq!(status, CommandStatus::Unrecognized);
+        assert_eq!(
+            lines,
+            vec!["dry run: 'export_csv' works on files and cannot be dry-run"]
+        );
+        assert!(!std::path::Path::new(path).exists());
+
+        std::fs::write(path, "1,2\n").unwrap();
+        let (status, _) = dry(&mut sheet, &format!("import_csv {}", path));
+        assert_eq!(status, CommandStatus::Unrecognized);
+        assert_eq!(fingerprint(&sheet), before);
+    }
+
+    #[test]
+    fn test_dry_graph_stats_csv_writes_no_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.csv");
+        let path = path.to_str().unwrap();
+        let mut sheet = fixture();
+        let (status, lines) = dry(&mut sheet, &format!("graph_stats --csv {}", path));
+        assert_eq!(status, CommandStatus::Unrecognized);
+        assert_eq!(
+            lines,
+            vec!["dry run: 'graph_stats' works on files and cannot be dry-run"]
+        );
+        assert!(!std::path::Path::new(path).exists());
+
+        // Without a file it only displays, so it runs as usual
+        let (status, _) = dry(&mut sheet, "graph_stats");
+        assert_eq!(status, CommandStatus::CmdOk);
+    }
+
+    #[test]
+    fn test_dry_setting_keeps_history_limit() {
+        let mut sheet = fixture();
+        let (status, _) = dry(&mut sheet, "set history_limit 0");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(
+            sheet.history_limit,
+            crate::cell_history::DEFAULT_HISTORY_LIMIT
+        );
+        // History is still recorded
+        let key = sheet.get_key(2, 0);
+        let entries = sheet.cell_history.get(&key).map_or(0, Vec::len);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A3=8", &mut sleep_time);
+        assert_eq!(sheet.cell_history[&key].len(), entries + 1);
+    }
+
+    #[test]
+    fn test_dry_setting_keeps_autosave_every() {
+        let mut sheet = fixture();
+        let (status, _) = dry(&mut sheet, "set autosave_every 1");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(
+            sheet.autosave_every,
+            crate::autosave::DEFAULT_AUTOSAVE_EVERY
+        );
+        // Turning autosave on or off works on files, so it is not dry-run at all
+        let (status, _) = dry(&mut sheet, "autosave on work.sheet");
+        assert_eq!(status, CommandStatus::Unrecognized);
+        assert!(sheet.autosave.is_none());
+    }
+
+    #[test]
+    fn test_dry_search_keeps_the_cursor() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "find 7", &mut sleep_time);
+        sheet.output.take();
+        let before = sheet.search.clone();
+        let (status, _) = dry(&mut sheet, "find 10");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(sheet.search, before);
+        let (status, _) = dry(&mut sheet, "find_next");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(sheet.search, before);
+    }
+
+    /// The settings and session state a command may change, in a comparable form.
+    fn settings(sheet: &Spreadsheet) -> String {
+        let mut names: Vec<_> = sheet.named_ranges.iter().collect();
+        names.sort_unstable_by_key(|(name, _)| name.as_str());
+        format!(
+            "{:?}",
+            (
+                (
+                    sheet.output_enabled,
+                    sheet.prompt_stats,
+                    sheet.confirm_overwrite_bulk,
+                    sheet.output.page_size,
+                    sheet.read_only,
+                ),
+                (
+                    sheet.history_limit,
+                    &sheet.history,
+                    sheet.backups,
+                    sheet.autosave_every,
+                    sheet.range_cell_limit,
+                    sheet.line_byte_limit,
+                ),
+                (&sheet.ownership, &sheet.script.vars, &sheet.row_filter),
+                (sheet.viewport_row, sheet.viewport_col, &sheet.search),
+                (names, &sheet.locked_ranges),
+            )
+        )
+    }
+
+    #[test]
+    fn test_dry_session_settings_change_nothing() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        let before = (fingerprint(&sheet), settings(&sheet));
+        for command in [
+            "readonly on",
+            "set prompt_stats on",
+            "set confirm_overwrite_bulk on",
+            "set page_size 3",
+            "set history_limit 0",
+            "set history_size 5",
+            "set backups 4",
+            "set user bob",
+            "set enforce_ownership on",
+            "set autosave_every 2",
+            "set_limit range_cells 9",
+            "set_limit line_bytes 99",
+            "disable_output",
+            "filter A > 6",
+            "scroll_to C5",
+            "find 7",
+            "name A1:A2 pair",
+            "own A1:A2 bob",
+        ] {
+            let (status, _) = dry(&mut sheet, command);
+            assert_eq!(status, CommandStatus::CmdOk, "{}", command);
+            assert_eq!(
+                (fingerprint(&sheet), settings(&sheet)),
+                before,
+                "{}",
+                command
+            );
+        }
+        // The sheet still takes changes after a dry `readonly on`
+        assert_eq!(
+            handle_command(&mut sheet, "A3=1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+    }
+
+    #[test]
+    fn test_dry_history_changes_change_nothing() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A3=8", &mut sleep_time);
+        sheet.output.take();
+        let before = fingerprint(&sheet);
+        for command in [
+            "A3=9",
+            "history A3",
+            "set history_limit 0",
+            "move A1:A3 D4",
+            "delete_row 2",
+            "clear_sheet",
+        ] {
+            let (status, _) = dry(&mut sheet, command);
+            assert_eq!(status, CommandStatus::CmdOk, "{}", command);
+            assert_eq!(fingerprint(&sheet), before, "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_dry_undo_and_redo_change_nothing() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        for command in ["delete_row 5", "A1=4", "undo"] {
+            handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        sheet.output.take();
+        let before = fingerprint(&sheet);
+        // Undoing the row shift puts back the whole sheet
+        let (status, lines) = dry(&mut sheet, "undo");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(lines[0], "undid delete_row 5 (whole sheet)");
+        assert_eq!(fingerprint(&sheet), before);
+        let (status, lines) = dry(&mut sheet, "redo");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert!(lines[0].starts_with("redid A1=4"));
+        assert_eq!(fingerprint(&sheet), before);
+
+        // A dry run is no step of its own, so undo still takes back the last real command
+        dry(&mut sheet, "A3=1");
+        handle_command(&mut sheet, "undo", &mut sleep_time);
+        assert_eq!(
+            sheet.output.take(),
+            vec!["undid delete_row 5 (whole sheet)"]
+        );
+    }
+
+    #[test]
+    fn test_dry_scripting_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("s.txt");
+        let saved = dir.path().join("out.sheet");
+        let mut sheet = fixture();
+        let before = (fingerprint(&sheet), settings(&sheet));
+        let (status, lines) = dry(&mut sheet, "let rate = 4");
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(
+            lines,
+            vec![
+                "dry run: 0 cell(s) would change, 0 formula(s) would be rewritten; nothing was changed"
+            ]
+        );
+        assert_eq!((fingerprint(&sheet), settings(&sheet)), before);
+
+        std::fs::write(&script, "A1=1\nlet rate = 2\nA2=$rate\n").unwrap();
+        let (status, lines) = dry(&mut sheet, &format!("run {}", script.display()));
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert_eq!(
+            lines[1],
+            "dry run: 3 cell(s) would change, 1 formula(s) would be rewritten; nothing was changed"
+        );
+        assert_eq!((fingerprint(&sheet), settings(&sheet)), before);
+
+        // A line that works on files fails the script instead of writing
+        std::fs::write(&script, format!("A1=1\nsave {}\n", saved.display())).unwrap();
+        let (status, lines) = dry(&mut sheet, &format!("run {}", script.display()));
+        assert_eq!(status, CommandStatus::Unrecognized);
+        assert_eq!(
+            lines[0],
+            "dry run: 'save' works on files and cannot be dry-run"
+        );
+        assert!(!saved.exists());
+        assert_eq!((fingerprint(&sheet), settings(&sheet)), before);
+    }
+}
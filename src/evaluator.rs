@@ -1,15 +1,89 @@
-use crate::cell::{CellValue, parse_cell_reference};
+use crate::aggregate::{
+    eval_avg, eval_counta, eval_countblank, eval_max, eval_median, eval_min, eval_product,
+    eval_spread, eval_variance, sum_if, sum_value,
+};
 #[cfg(feature = "extensions")]
-use crate::extensions::{get_formula_string, set_cell_to_value};
+use crate::autosave::handle_autosave;
+use crate::cell::{CellValue, abs_value, copied_value, parse_cell_reference, round_to_multiple};
 #[cfg(feature = "extensions")]
-use crate::formula::Range;
-use crate::formula::parse_range;
-use crate::formula::{eval_avg, eval_max, eval_min, eval_variance, sum_value};
+use crate::cell::{
+    ErrorKind, PINNED_COL_PARENT1, PINNED_ROW_PARENT1, RELATIVE_PARENT1, resolve_relative_reference,
+};
+#[cfg(feature = "extensions")]
+use crate::cell_history::{handle_history, handle_history_stats, handle_show_history};
+#[cfg(feature = "extensions")]
+use crate::changes::handle_changes;
+#[cfg(feature = "extensions")]
+use crate::column_formula::handle_colformula;
+#[cfg(feature = "extensions")]
+use crate::copy_move::{handle_copy, handle_move, handle_transpose};
+#[cfg(feature = "extensions")]
+use crate::csv::{export_csv, import_csv};
+#[cfg(feature = "extensions")]
+use crate::deps::{handle_deps, handle_precedents};
+#[cfg(feature = "extensions")]
+use crate::dry_run::{check_dry_runnable, handle_dry};
+use crate::expr_parser::{
+    ArithExpr, BinaryOp, Expr, Function, IfExpr, Operand, RangeArg, Reference, ScalarFunction,
+    parse_expression,
+};
+use crate::extended_formula::{
+    ArithNode, EXTENDED_FORMULA, ExtendedFormula, IfFormula, Term, eval_extended,
+};
+#[cfg(feature = "extensions")]
+use crate::extensions::{
+    delete_cells, explain_error, get_formula_string, replace_values, trace_error,
+};
+#[cfg(feature = "extensions")]
+use crate::filter::handle_filter;
 use crate::graph::{add_children, remove_all_parents};
+#[cfg(feature = "extensions")]
+use crate::graph_stats::handle_graph_stats;
+#[cfg(feature = "extensions")]
+use crate::integrity::handle_verify;
+#[cfg(feature = "extensions")]
+use crate::locks::{
+    handle_is_locked, handle_lock, handle_locked, handle_unlock, handle_unlock_all,
+};
+#[cfg(feature = "extensions")]
+use crate::names::{handle_name, handle_names, handle_rename, handle_unname};
+#[cfg(feature = "extensions")]
+use crate::ownership::{handle_disown, handle_own, handle_owners, is_valid_user};
+#[cfg(feature = "extensions")]
+use crate::process_command::{check_arguments, handle_help};
+use crate::range::{Range, parse_range};
+#[cfg(feature = "extensions")]
+use crate::range::{check_range_limit, resolve_range, resolve_target};
+#[cfg(feature = "extensions")]
+use crate::range_assign::{assignment_range, handle_range_assignment};
+#[cfg(feature = "extensions")]
+use crate::read_only::{handle_readonly, is_read_only_command};
+#[cfg(feature = "extensions")]
+use crate::recalc::handle_recalc_all;
 use crate::reevaluate_topo::{sleep_fn, toposort_reval_detect_cycle};
 #[cfg(feature = "extensions")]
-use crate::spreadsheet::HighlightType;
+use crate::row_shift::{handle_delete_row, handle_insert_row};
+#[cfg(feature = "extensions")]
+use crate::sample::{handle_count, handle_sample, handle_stats};
+#[cfg(feature = "extensions")]
+use crate::save_load::handle_file_command;
+#[cfg(feature = "extensions")]
+use crate::search::{handle_find, handle_find_next};
+#[cfg(feature = "extensions")]
+use crate::series::handle_series;
+#[cfg(feature = "extensions")]
+use crate::session::handle_session;
+#[cfg(feature = "extensions")]
+use crate::sort::{parse_sort_args, sort_range};
 use crate::spreadsheet::{CommandStatus, Spreadsheet};
+#[cfg(feature = "extensions")]
+use crate::spreadsheet::{HighlightType, MAX_BACKUPS};
+#[cfg(feature = "extensions")]
+use crate::text_width::{terminal_width, truncate_display, wrap_display};
+#[cfg(feature = "extensions")]
+use crate::undo::{handle_redo, handle_undo};
+#[cfg(feature = "extensions")]
+use crate::visualize_cells::{DEFAULT_TREE_DEPTH, MAX_TREE_DEPTH};
 
 /// Resolves a cell reference or named range to its coordinates.
 ///
@@ -23,547 +97,792 @@ use crate::spreadsheet::{CommandStatus, Spreadsheet};
 /// * `Ok((row, col))` - The zero-based coordinates.
 /// * `Err(CommandStatus::Unrecognized)` - If resolution fails
 #[cfg(feature = "extensions")]
-fn resolve_cell_reference(sheet: &Spreadsheet, s: &str) -> Result<(i16, i16), CommandStatus> {
+pub fn resolve_cell_reference(sheet: &Spreadsheet, s: &str) -> Result<(i16, i16), CommandStatus> {
     if let Some(range) = sheet.named_ranges.get(s) {
         if range.start_row == range.end_row && range.start_col == range.end_col {
             Ok((range.start_row, range.start_col))
         } else {
             Err(CommandStatus::Unrecognized)
         }
+    } else if let Some(address) = s.strip_prefix('@') {
+        resolve_viewport_address(sheet, address)
     } else {
         parse_cell_reference(sheet, s)
     }
 }
 
-/// Handles the `SLEEP` command, setting a cell value and accumulating sleep time.
+/// Checks whether an expression holds an `@` outside quoted text, which can only be a
+/// viewport address.
+///
+/// A doubled quote inside text flips the state twice, so it needs no special case.
+#[cfg(feature = "extensions")]
+fn has_viewport_address(expr: &str) -> bool {
+    let mut quoted = false;
+    expr.chars().any(|c| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        c == '@' && !quoted
+    })
+}
+
+/// Resolves a viewport address such as `r3c2`: the third displayed row and the second
+/// displayed column, counted from the viewport origin.
+///
+/// Only commands take viewport addresses. Formulas do not, since a formula must not
+/// change meaning when the view scrolls.
 ///
 /// # Arguments
 ///
-/// * `sheet` - The mutable spreadsheet.
-/// * `row` - The target row.
-/// * `col` - The target column.
-/// * `expr` - The expression (cell reference or literal).
-/// * `sleep_time` - Accumulates sleep duration in seconds.
+/// * `sheet` - The spreadsheet, for its viewport.
+/// * `address` - The address after the `@`.
 ///
 /// # Returns
 ///
-/// * `CommandStatus::CmdOk` - On success.
-/// * `CommandStatus::CircularRef` - If self-referencing.
-/// * `CommandStatus::Unrecognized` - If expression is invalid.
-pub fn handle_sleep(
-    sheet: &mut Spreadsheet,
+/// * `Ok((row, col))` - The zero-based coordinates of the displayed cell.
+/// * `Err(CommandStatus::InvalidCell)` - If the position is outside the displayed area
+/// * `Err(CommandStatus::Unrecognized)` - If the address is not of the form `rNcM`
+#[cfg(feature = "extensions")]
+fn resolve_viewport_address(
+    sheet: &Spreadsheet,
+    address: &str,
+) -> Result<(i16, i16), CommandStatus> {
+    let (row, col) = address
+        .strip_prefix('r')
+        .and_then(|rest| rest.split_once('c'))
+        .and_then(|(row, col)| Some((row.parse::<usize>().ok()?, col.parse::<usize>().ok()?)))
+        .filter(|&(row, col)| row > 0 && col > 0)
+        .ok_or(CommandStatus::Unrecognized)?;
+    match sheet.displayed_rows().get(row - 1) {
+        Some(&row) if col <= sheet.displayed_cols() as usize => {
+            Ok((row, sheet.viewport_col + col as i16 - 1))
+        }
+        _ => Err(CommandStatus::InvalidCell),
+    }
+}
+
+/// Resolves a reference in a formula to coordinates.
+///
+/// Without extensions only plain cell references resolve.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `reference` - The parsed reference.
+/// * `row` - Row of the formula's own cell.
+/// * `col` - Column of the formula's own cell.
+/// * `named` - Whether the reference may also be a single-cell named range; it may
+///   anywhere but at the corner of a range.
+///
+/// # Returns
+///
+/// * `Ok((row, col, flags))` - The coordinates and the reference's `RELATIVE_PARENT1` and
+///   `PINNED_*_PARENT1` flags; shift them left by one for `parent2`.
+/// * `Err(CommandStatus)` - If resolution fails
+#[cfg(feature = "extensions")]
+fn resolve_reference(
+    sheet: &Spreadsheet,
+    reference: &Reference,
     row: i16,
     col: i16,
-    expr: &str,
-    sleep_time: &mut f64,
-) -> CommandStatus {
-    let cell_key = sheet.get_key(row, col);
-
-    // Handle cell reference case
-    if let Ok((target_row, target_col)) = parse_cell_reference(sheet, expr) {
-        // Get parent key before any borrowing
-        let pkey = sheet.get_key(target_row, target_col);
-
-        // Check for self-reference early (optimization)
-        if row == target_row && col == target_col {
-            return CommandStatus::CircularRef;
+    named: bool,
+) -> Result<(i16, i16, u8), CommandStatus> {
+    match *reference {
+        Reference::Relative {
+            row_offset,
+            col_offset,
+        } => resolve_relative_reference(sheet, row, col, row_offset, col_offset)
+            .map(|(r, c)| (r, c, RELATIVE_PARENT1)),
+        Reference::Cell {
+            text,
+            pin_col: false,
+            pin_row: false,
+            ..
+        } => {
+            let resolved = if named {
+                resolve_cell_reference(sheet, text)
+            } else {
+                parse_cell_reference(sheet, text)
+            };
+            resolved.map(|(r, c)| (r, c, 0))
         }
-
-        // Remove parents and update cell in one block
-        remove_all_parents(sheet, row, col);
-
-        // Set up the new cell metadata
-        let meta = sheet.get_cell_meta(row, col);
-        meta.parent1 = pkey;
-        meta.parent2 = -1;
-        meta.formula = 102; // Custom formula code for sleep
-
-        // Add children and update sleep time
-        add_children(sheet, pkey, -1, 102, row, col);
-        // Add to sleep time if integer
-        // Get the value from parent cell
-        let parent_value = sheet.get_cell(target_row, target_col);
-        if let CellValue::Integer(val) = parent_value {
-            // Update cell value and sleep time
-            sleep_fn(sheet, row, col, *val, sleep_time);
-        } else {
-            *sheet.get_mut_cell(row, col) = CellValue::Error;
+        Reference::Cell {
+            column,
+            row: row_digits,
+            pin_col,
+            pin_row,
+            ..
+        } => {
+            let mut pins = 0;
+            if pin_col {
+                pins |= PINNED_COL_PARENT1;
+            }
+            if pin_row {
+                pins |= PINNED_ROW_PARENT1;
+            }
+            parse_cell_reference(sheet, &format!("{}{}", column, row_digits))
+                .map(|(r, c)| (r, c, pins))
         }
+        Reference::Name(name) if named => {
+            resolve_cell_reference(sheet, name).map(|(r, c)| (r, c, 0))
+        }
+        Reference::Name(_) => Err(CommandStatus::Unrecognized),
     }
-    // Handle numeric literal case
-    else if let Ok(val) = expr.parse::<i32>() {
-        // Remove all parents and update cell in one sequence
-        remove_all_parents(sheet, row, col);
-        // Update cell value and sleep_time
-        // Delete the cell meta entry
-        sheet.cell_meta.remove(&cell_key);
-        sleep_fn(sheet, row, col, val, sleep_time);
-    } else {
-        return CommandStatus::Unrecognized;
-    }
-
-    CommandStatus::CmdOk
 }
 
-/// Evaluates an arithmetic expression and updates a cell.
+/// Resolves a reference in a formula to coordinates.
 ///
-/// Supports literals, cell references, and operations (+, -, *, /).
+/// Without extensions only plain cell references resolve.
 ///
 /// # Arguments
 ///
-/// * `sheet` - The mutable spreadsheet.
-/// * `row` - The target row.
-/// * `col` - The target column.
-/// * `expr` - The arithmetic expression.
+/// * `sheet` - The spreadsheet.
+/// * `reference` - The parsed reference.
 ///
 /// # Returns
 ///
-/// * `CommandStatus::CmdOk` - On success.
-/// * `CommandStatus::Unrecognized` - If expression is invalid.
+/// * `Ok((row, col, 0))` - The coordinates; there are no reference flags.
+/// * `Err(CommandStatus::Unrecognized)` - If resolution fails
 #[cfg(not(feature = "extensions"))]
-pub fn evaluate_arithmetic(
-    sheet: &mut Spreadsheet,
+fn resolve_reference(
+    sheet: &Spreadsheet,
+    reference: &Reference,
+    _row: i16,
+    _col: i16,
+    _named: bool,
+) -> Result<(i16, i16, u8), CommandStatus> {
+    match *reference {
+        Reference::Cell {
+            text,
+            pin_col: false,
+            pin_row: false,
+            ..
+        } => parse_cell_reference(sheet, text).map(|(r, c)| (r, c, 0)),
+        _ => Err(CommandStatus::Unrecognized),
+    }
+}
+
+/// Resolves the argument of a range function.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `arg` - The parsed range or range name.
+/// * `row` - Row of the formula's own cell.
+/// * `col` - Column of the formula's own cell.
+///
+/// # Returns
+///
+/// * `Ok((range, flags))` - The range and its reference flags; plain corners may come in
+///   any order.
+/// * `Err(CommandStatus::InvalidCell)` - If a range with relative or pinned corners is
+///   reversed, as each corner's flags belong to it.
+/// * `Err(CommandStatus)` - If a corner or name does not resolve
+#[cfg(feature = "extensions")]
+fn resolve_range_arg(
+    sheet: &Spreadsheet,
+    arg: &RangeArg,
     row: i16,
     col: i16,
-    expr: &str,
-) -> CommandStatus {
-    let cell_key = sheet.get_key(row, col);
-
-    // Case 1: Integer literal
-    if let Ok(number) = expr.parse::<i32>() {
-        remove_all_parents(sheet, row, col);
-        // As no parents and formula remove the meta data from the set and map
-        // to avoid memory leaks
-        sheet.cell_meta.remove(&cell_key);
-        *sheet.get_mut_cell(row, col) = CellValue::Integer(number);
-
-        return CommandStatus::CmdOk;
+) -> Result<(Range, u8), CommandStatus> {
+    let span = match arg {
+        RangeArg::Name(name) => return resolve_range(sheet, name).map(|range| (range, 0)),
+        RangeArg::Lines(text) => return parse_range(sheet, text).map(|range| (range, 0)),
+        RangeArg::Span(span) => span,
+    };
+    let is_plain = |reference: &Reference| {
+        matches!(
+            reference,
+            Reference::Cell {
+                pin_col: false,
+                pin_row: false,
+                ..
+            }
+        )
+    };
+    if is_plain(&span.start) && is_plain(&span.end) {
+        return resolve_range(sheet, span.text).map(|range| (range, 0));
     }
+    let (start_row, start_col, start_flags) =
+        resolve_reference(sheet, &span.start, row, col, false)?;
+    let (end_row, end_col, end_flags) = resolve_reference(sheet, &span.end, row, col, false)?;
+    if start_row > end_row || start_col > end_col {
+        return Err(CommandStatus::InvalidCell);
+    }
+    let range = Range {
+        start_row,
+        start_col,
+        end_row,
+        end_col,
+    };
+    Ok((range, start_flags | end_flags << 1))
+}
 
-    // Case 2: Simple cell reference - check using bytes for better performance
-    let mut all_alnum = true;
-    for &b in expr.as_bytes() {
-        if !(b.is_ascii_alphanumeric() || b == b'_') {
-            all_alnum = false;
-            break;
-        }
+/// Resolves the argument of a range function.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `arg` - The parsed range.
+///
+/// # Returns
+///
+/// * `Ok((range, 0))` - The range; there are no reference flags.
+/// * `Err(CommandStatus::Unrecognized)` - If the range is invalid or a name
+#[cfg(not(feature = "extensions"))]
+fn resolve_range_arg(
+    sheet: &Spreadsheet,
+    arg: &RangeArg,
+    _row: i16,
+    _col: i16,
+) -> Result<(Range, u8), CommandStatus> {
+    match arg {
+        RangeArg::Span(span) => parse_range(sheet, span.text).map(|range| (range, 0)),
+        RangeArg::Lines(text) => parse_range(sheet, text).map(|range| (range, 0)),
+        RangeArg::Name(_) => Err(CommandStatus::Unrecognized),
     }
+}
 
-    if all_alnum {
-        match parse_cell_reference(sheet, expr) {
-            Ok((target_row, target_col)) => {
-                // Get reference cell key and value
-                let ref_cell_key = sheet.get_key(target_row, target_col);
-                // Remove old dependencies and set new ones
-                remove_all_parents(sheet, row, col);
+/// Records a formula's reference flags, if it has any.
+#[cfg(feature = "extensions")]
+fn record_reference_flags(sheet: &mut Spreadsheet, row: i16, col: i16, flags: u8) {
+    if flags != 0 {
+        let cell_key = sheet.get_key(row, col);
+        sheet.relative_refs.insert(cell_key, flags);
+    }
+}
 
-                // Update metadata
-                let meta = sheet.get_cell_meta(row, col);
-                meta.parent1 = ref_cell_key;
-                meta.parent2 = -1;
-                meta.formula = 82; // Code for simple cell reference
+/// Records a formula's reference flags; without extensions there are none.
+#[cfg(not(feature = "extensions"))]
+fn record_reference_flags(_sheet: &mut Spreadsheet, _row: i16, _col: i16, _flags: u8) {}
 
-                // Add dependency
-                add_children(sheet, ref_cell_key, -1, 82, row, col);
+/// Sets a cell to `SLEEP` of a literal or a plain cell reference.
+fn sleep_operand(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    operand: &Operand,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    match *operand {
+        // Handle cell reference case; a plain cell or a name, but not a relative or pinned
+        // reference
+        Operand::Ref(
+            ref reference @ (Reference::Cell {
+                pin_col: false,
+                pin_row: false,
+                ..
+            }
+            | Reference::Name(_)),
+        ) => {
+            let Ok((target_row, target_col, _)) =
+                resolve_reference(sheet, reference, row, col, true)
+            else {
+                return CommandStatus::Unrecognized;
+            };
+            // Get parent key before any borrowing
+            let pkey = sheet.get_key(target_row, target_col);
 
-                // Update cell value
-                *sheet.get_mut_cell(row, col) = match sheet.get_cell(target_row, target_col) {
-                    CellValue::Integer(val) => CellValue::Integer(*val),
-                    _ => CellValue::Error,
-                };
-                return CommandStatus::CmdOk;
+            // Check for self-reference early (optimization)
+            if row == target_row && col == target_col {
+                return CommandStatus::CircularRef;
             }
-            Err(status) => return status,
-        }
-    }
 
-    // Case 3: Binary arithmetic expression
-    // Find operator starting at index 1 (like C code, to handle leading minus sign)
-    let bytes = expr.as_bytes();
-    let mut op_idx = 0;
-    let mut op = 0u8;
+            // Remove parents and update cell in one block
+            remove_all_parents(sheet, row, col);
 
-    // Start at index 1 to handle leading minus sign
-    for (i, &byte) in bytes.iter().enumerate().skip(1) {
-        match byte {
-            b'+' | b'-' | b'*' | b'/' => {
-                op = byte;
-                op_idx = i;
-                break;
+            // Set up the new cell metadata
+            let meta = sheet.get_cell_meta(row, col);
+            meta.parent1 = pkey;
+            meta.parent2 = -1;
+            meta.formula = 102; // Custom formula code for sleep
+
+            // Add children and update sleep time
+            add_children(sheet, pkey, -1, 102, row, col);
+            // Add to sleep time if integer
+            // Get the value from parent cell
+            let parent_value = sheet.get_cell(target_row, target_col);
+            match parent_value.number() {
+                // Update cell value and sleep time
+                Ok(val) => sleep_fn(sheet, row, col, val, sleep_time),
+                Err(kind) => *sheet.get_mut_cell(row, col) = CellValue::Error(kind),
             }
-            _ => {}
         }
+        // Handle numeric literal case
+        Operand::Number(val) => {
+            // Drop the old formula, then update cell value and sleep_time
+            sheet.clear_formula(row, col);
+            sleep_fn(sheet, row, col, val, sleep_time);
+        }
+        _ => return CommandStatus::Unrecognized,
     }
 
-    if op_idx == 0 {
-        return CommandStatus::Unrecognized;
-    }
-
-    // Split into left and right parts
-    let left = &expr[..op_idx];
-    let right = &expr[op_idx + 1..];
-
-    if left.is_empty() || right.is_empty() {
-        return CommandStatus::Unrecognized;
-    }
+    CommandStatus::CmdOk
+}
 
-    // Variables to track cell references and values
-    let mut left_val = 0;
-    let mut right_val = 0;
-    let mut left_is_cell = false;
-    let mut right_is_cell = false;
-    let mut error_found = false;
-    let mut left_cell_key = -1;
-    let mut right_cell_key = -1;
+/// Sets a cell to a literal or to a single reference.
+fn assign_operand(sheet: &mut Spreadsheet, row: i16, col: i16, operand: &Operand) -> CommandStatus {
+    let reference = match operand {
+        // Case 1: Integer literal
+        Operand::Number(number) => {
+            // As no parents and formula remove the meta data to avoid memory leaks
+            sheet.clear_formula(row, col);
+            *sheet.get_mut_cell(row, col) = CellValue::Integer(*number);
 
-    // Parse left operand
-    if let Ok(num) = left.parse::<i32>() {
-        left_val = num;
-    } else {
-        // Try as cell reference
-        match parse_cell_reference(sheet, left) {
-            Ok((left_row, left_col)) => {
-                left_is_cell = true;
-                left_cell_key = sheet.get_key(left_row, left_col);
-
-                // Get reference cell value
-                let left_cell = sheet.get_cell(left_row, left_col);
-                match left_cell {
-                    CellValue::Integer(val) => left_val = *val,
-                    _ => {
-                        error_found = true;
-                    }
-                }
-            }
-            Err(status) => return status,
+            return CommandStatus::CmdOk;
         }
-    }
+        Operand::Ref(reference) => reference,
+    };
 
-    // Parse right operand
-    if let Ok(num) = right.parse::<i32>() {
-        right_val = num;
-    } else {
-        // Try as cell reference
-        match parse_cell_reference(sheet, right) {
-            Ok((right_row, right_col)) => {
-                right_is_cell = true;
-                right_cell_key = sheet.get_key(right_row, right_col);
-
-                // Get reference cell value
-                let right_cell = sheet.get_cell(right_row, right_col);
-                match right_cell {
-                    CellValue::Integer(val) => right_val = *val,
-                    _ => {
-                        error_found = true;
-                    }
-                }
-            }
-            Err(status) => return status,
-        }
-    }
+    // Case 2: Simple cell reference
+    let (target_row, target_col, flags) = match resolve_reference(sheet, reference, row, col, true)
+    {
+        Ok(resolved) => resolved,
+        Err(status) => return status,
+    };
+    // Get reference cell key and value
+    let ref_cell_key = sheet.get_key(target_row, target_col);
 
-    // Remove old dependencies
+    // Remove old dependencies and set new ones
     remove_all_parents(sheet, row, col);
 
-    // Determine formula type based on operator and operand types
-    let mut formula_type = match op {
-        b'+' => 10,
-        b'-' => 20,
-        b'*' => 40,
-        b'/' => 30,
-        _ => unreachable!(),
+    // Update metadata
+    let meta = sheet.get_cell_meta(row, col);
+    meta.parent1 = ref_cell_key;
+    meta.parent2 = -1;
+    meta.formula = 82; // Code for simple cell reference
+
+    // Add dependency
+    add_children(sheet, ref_cell_key, -1, 82, row, col);
+    record_reference_flags(sheet, row, col, flags);
+
+    // Update cell value
+    *sheet.get_mut_cell(row, col) = copied_value(sheet.get_cell(target_row, target_col));
+
+    CommandStatus::CmdOk
+}
+
+/// Sets a cell to a binary arithmetic expression.
+fn assign_binary(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    left: &Operand,
+    op: BinaryOp,
+    right: &Operand,
+) -> CommandStatus {
+    // Determine formula type based on operator
+    let formula_type = match op {
+        BinaryOp::Add => 10,
+        BinaryOp::Sub => 20,
+        BinaryOp::Mul => 40,
+        BinaryOp::Div => 30,
+        BinaryOp::Mod => 50,
+        BinaryOp::Pow => 60,
     };
+    assign_pair(
+        sheet,
+        row,
+        col,
+        left,
+        right,
+        formula_type,
+        |left_val, right_val| op.apply(left_val, right_val),
+    )
+}
 
-    // Adjust formula type based on cell references (like C code)
-    if left_is_cell && right_is_cell {
-        formula_type += 0; // Both are cells, no adjustment needed
-    } else if left_is_cell {
-        formula_type += 2;
-    } else if right_is_cell {
-        formula_type += 3;
+/// Sets a cell to a scalar function: `ABS` of one operand, or `MIN2`/`MAX2`/`ROUNDM` of two.
+fn assign_scalar(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    function: ScalarFunction,
+    first: &Operand,
+    second: Option<&Operand>,
+) -> CommandStatus {
+    match (function, second) {
+        (ScalarFunction::Min2, Some(second)) => {
+            assign_pair(sheet, row, col, first, second, 70, |left_val, right_val| {
+                CellValue::Integer(left_val.min(right_val))
+            })
+        }
+        (ScalarFunction::Max2, Some(second)) => assign_pair(
+            sheet,
+            row,
+            col,
+            first,
+            second,
+            110,
+            |left_val, right_val| CellValue::Integer(left_val.max(right_val)),
+        ),
+        (ScalarFunction::RoundM, Some(second)) => {
+            assign_pair(sheet, row, col, first, second, 130, round_to_multiple)
+        }
+        (ScalarFunction::Abs, None) => assign_abs(sheet, row, col, first),
+        // The parser gives ABS one operand and MIN2/MAX2/ROUNDM two
+        _ => CommandStatus::Unrecognized,
     }
+}
 
-    // Set metadata
-    let meta = sheet.get_cell_meta(row, col);
-    meta.formula = formula_type;
-    meta.parent1 = if left_is_cell {
-        left_cell_key
-    } else {
-        left_val
+/// Sets a cell to `ABS` of a literal or a cell.
+fn assign_abs(sheet: &mut Spreadsheet, row: i16, col: i16, operand: &Operand) -> CommandStatus {
+    let reference = match operand {
+        // The absolute value of a literal is just another literal
+        Operand::Number(number) => {
+            sheet.clear_formula(row, col);
+            *sheet.get_mut_cell(row, col) = abs_value(*number);
+            return CommandStatus::CmdOk;
+        }
+        Operand::Ref(reference) => reference,
     };
-    meta.parent2 = if right_is_cell {
-        right_cell_key
-    } else {
-        right_val
+    let (target_row, target_col, flags) = match resolve_reference(sheet, reference, row, col, true)
+    {
+        Ok(resolved) => resolved,
+        Err(status) => return status,
     };
+    let ref_cell_key = sheet.get_key(target_row, target_col);
+    remove_all_parents(sheet, row, col);
 
-    // Add dependencies
-    if left_is_cell && right_is_cell {
-        // Add dependencies for both cells
-        add_children(sheet, left_cell_key, right_cell_key, formula_type, row, col);
-    } else if left_is_cell {
-        // Ordering of Cells matters
-        add_children(sheet, left_cell_key, -1, formula_type, row, col);
-    } else if right_is_cell {
-        // Ordering of Cells matters
-        add_children(sheet, -1, right_cell_key, formula_type, row, col);
-    }
+    let meta = sheet.get_cell_meta(row, col);
+    meta.parent1 = ref_cell_key;
+    meta.parent2 = -1;
+    meta.formula = 122; // ABS of a cell, evaluated like the copy code 82
 
-    // Calculate result
-    let cell = sheet.get_mut_cell(row, col);
-
-    if error_found {
-        *cell = CellValue::Error;
-    } else {
-        match op {
-            b'+' => *cell = CellValue::Integer(left_val + right_val),
-            b'-' => *cell = CellValue::Integer(left_val - right_val),
-            b'*' => *cell = CellValue::Integer(left_val * right_val),
-            b'/' => {
-                if right_val == 0 {
-                    *cell = CellValue::Error;
-                } else {
-                    *cell = CellValue::Integer(left_val / right_val);
-                }
-            }
-            _ => unreachable!(),
-        }
-    }
+    add_children(sheet, ref_cell_key, -1, 122, row, col);
+    record_reference_flags(sheet, row, col, flags);
 
+    *sheet.get_mut_cell(row, col) = match sheet.get_cell(target_row, target_col).number() {
+        Ok(value) => abs_value(value),
+        Err(kind) => CellValue::Error(kind),
+    };
     CommandStatus::CmdOk
 }
 
-/// Evaluates an arithmetic expression and updates a cell.
-///
-/// Supports literals, cell references, and operations (+, -, *, /).
+/// Sets a cell to a formula of two operands, such as `A1+B1` or `MIN2(A1,5)`.
 ///
 /// # Arguments
 ///
 /// * `sheet` - The mutable spreadsheet.
 /// * `row` - The target row.
 /// * `col` - The target column.
-/// * `expr` - The arithmetic expression.
+/// * `left` - The first operand.
+/// * `right` - The second operand.
+/// * `formula_type` - The formula code when both operands are cells or both literals; 2
+///   is added when only the left is a cell, 3 when only the right is.
+/// * `apply` - Computes the value from the two operands' values.
 ///
 /// # Returns
 ///
 /// * `CommandStatus::CmdOk` - On success.
-/// * `CommandStatus::Unrecognized` - If expression is invalid.
-#[cfg(feature = "extensions")]
-pub fn evaluate_arithmetic(
+/// * `CommandStatus` - From resolving a reference that is not on the sheet
+fn assign_pair(
     sheet: &mut Spreadsheet,
     row: i16,
     col: i16,
-    expr: &str,
+    left: &Operand,
+    right: &Operand,
+    mut formula_type: i16,
+    apply: impl Fn(i32, i32) -> CellValue,
 ) -> CommandStatus {
-    let cell_key = sheet.get_key(row, col);
+    // Each operand is a value, or a cell's key with its value (the error kind for an error
+    // or text)
+    let mut relative_flags = 0;
+    let mut resolve = |operand: &Operand, shift: u8| match operand {
+        Operand::Number(num) => Ok((None, Ok(*num))),
+        Operand::Ref(reference) => {
+            let (target_row, target_col, flags) =
+                resolve_reference(sheet, reference, row, col, true)?;
+            relative_flags |= flags << shift;
+            let value = sheet.get_cell(target_row, target_col).number();
+            Ok((Some(sheet.get_key(target_row, target_col)), value))
+        }
+    };
+    let (left_key, left_val) = match resolve(left, 0) {
+        Ok(resolved) => resolved,
+        Err(status) => return status,
+    };
+    let (right_key, right_val) = match resolve(right, 1) {
+        Ok(resolved) => resolved,
+        Err(status) => return status,
+    };
 
-    // Case 1: Integer literal
-    if let Ok(number) = expr.parse::<i32>() {
-        remove_all_parents(sheet, row, col);
-        // As no parents and formula remove the meta data from the set and map
-        // to avoid memory leaks
-        sheet.cell_meta.remove(&cell_key);
-        *sheet.get_mut_cell(row, col) = CellValue::Integer(number);
+    // Remove old dependencies
+    remove_all_parents(sheet, row, col);
 
-        return CommandStatus::CmdOk;
+    // Adjust formula type based on cell references (like C code)
+    match (left_key, right_key) {
+        (Some(_), None) => formula_type += 2,
+        (None, Some(_)) => formula_type += 3,
+        _ => {}
     }
 
-    // Case 2: Simple cell reference - check using bytes for better performance
-    let mut all_alnum = true;
-    for &b in expr.as_bytes() {
-        if !(b.is_ascii_alphanumeric() || b == b'_') {
-            all_alnum = false;
-            break;
-        }
+    // Set metadata; a literal operand is stored in place of a key
+    let parent1 = left_key.unwrap_or(left_val.unwrap_or(0));
+    let parent2 = right_key.unwrap_or(right_val.unwrap_or(0));
+    let meta = sheet.get_cell_meta(row, col);
+    meta.formula = formula_type;
+    meta.parent1 = parent1;
+    meta.parent2 = parent2;
+
+    // Add dependencies; ordering of cells matters
+    if left_key.is_some() || right_key.is_some() {
+        add_children(
+            sheet,
+            left_key.unwrap_or(-1),
+            right_key.unwrap_or(-1),
+            formula_type,
+            row,
+            col,
+        );
     }
+    record_reference_flags(sheet, row, col, relative_flags);
 
-    if all_alnum {
-        match resolve_cell_reference(sheet, expr) {
-            Ok((target_row, target_col)) => {
-                // Get reference cell key and value
-                let ref_cell_key = sheet.get_key(target_row, target_col);
-
-                // Remove old dependencies and set new ones
-                remove_all_parents(sheet, row, col);
+    // Calculate result
+    *sheet.get_mut_cell(row, col) = match (left_val, right_val) {
+        (Ok(left_val), Ok(right_val)) => apply(left_val, right_val),
+        (Err(kind), _) | (_, Err(kind)) => CellValue::Error(kind),
+    };
 
-                // Update metadata
-                let meta = sheet.get_cell_meta(row, col);
-                meta.parent1 = ref_cell_key;
-                meta.parent2 = -1;
-                meta.formula = 82; // Code for simple cell reference
+    CommandStatus::CmdOk
+}
 
-                // Add dependency
-                add_children(sheet, ref_cell_key, -1, 82, row, col);
+/// Resolves a literal or a reference into a term of an extended formula.
+fn resolve_term(
+    sheet: &Spreadsheet,
+    row: i16,
+    col: i16,
+    operand: &Operand,
+) -> Result<Term, CommandStatus> {
+    match operand {
+        Operand::Number(number) => Ok(Term::Number(*number)),
+        Operand::Ref(reference) => resolve_reference(sheet, reference, row, col, true).map(
+            |(target_row, target_col, flags)| {
+                Term::Cell(sheet.get_key(target_row, target_col), flags)
+            },
+        ),
+    }
+}
 
-                // Update cell value
-                *sheet.get_mut_cell(row, col) = sheet.get_cell(target_row, target_col).clone();
+/// Resolves the operands of an `IF` into the formula kept for it.
+fn resolve_if(
+    sheet: &Spreadsheet,
+    row: i16,
+    col: i16,
+    expr: &IfExpr,
+) -> Result<IfFormula, CommandStatus> {
+    Ok(IfFormula {
+        left: resolve_term(sheet, row, col, &expr.left)?,
+        op: expr.op,
+        right: resolve_term(sheet, row, col, &expr.right)?,
+        then: resolve_term(sheet, row, col, &expr.then)?,
+        otherwise: resolve_term(sheet, row, col, &expr.otherwise)?,
+    })
+}
 
-                return CommandStatus::CmdOk;
-            }
-            Err(status) => return status,
+/// Resolves the operands and ranges of an arithmetic tree.
+fn resolve_arith(
+    sheet: &Spreadsheet,
+    row: i16,
+    col: i16,
+    expr: &ArithExpr,
+) -> Result<ArithNode, CommandStatus> {
+    match expr {
+        ArithExpr::Operand(operand) => resolve_term(sheet, row, col, operand).map(ArithNode::Term),
+        ArithExpr::Function(function, arg) => {
+            let (range, flags) = resolve_range_arg(sheet, arg, row, col)?;
+            Ok(ArithNode::Function(*function, range, flags))
+        }
+        ArithExpr::Binary(left, op, right) => Ok(ArithNode::Binary(
+            Box::new(resolve_arith(sheet, row, col, left)?),
+            *op,
+            Box::new(resolve_arith(sheet, row, col, right)?),
+        )),
+        ArithExpr::Negate(node) => {
+            resolve_arith(sheet, row, col, node).map(|node| ArithNode::Negate(Box::new(node)))
         }
     }
+}
 
-    // Case 3: Binary arithmetic expression
-    // Find operator starting at index 1 (like C code, to handle leading minus sign)
-    let bytes = expr.as_bytes();
-    let mut op_idx = 0;
-    let mut op = 0u8;
-
-    // Start at index 1 to handle leading minus sign
-    for (i, &byte) in bytes.iter().enumerate().skip(1) {
-        match byte {
-            b'+' | b'-' | b'*' | b'/' => {
-                op = byte;
-                op_idx = i;
-                break;
+/// Resolves an expression that is kept in `sheet.extended_formulas`: `IF`, `SUMPRODUCT`
+/// or arithmetic that does not fit a `CellMeta`.
+///
+/// References are stored as the cells and ranges they point to, each with its relative
+/// and pinned flags, so the formula is written back the way it was typed.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `row` - Row of the formula's own cell.
+/// * `col` - Column of the formula's own cell.
+/// * `expr` - The parsed expression.
+///
+/// # Returns
+///
+/// * `Ok(ExtendedFormula)` - The formula to keep.
+/// * `Err(CommandStatus::Unrecognized)` - If the expression is of another kind, or the
+///   ranges of a `SUMPRODUCT` differ in shape.
+/// * `Err(CommandStatus)` - From resolving a reference or range that is not on the sheet
+pub fn resolve_extended(
+    sheet: &Spreadsheet,
+    row: i16,
+    col: i16,
+    expr: &Expr,
+) -> Result<ExtendedFormula, CommandStatus> {
+    match expr {
+        Expr::If(if_expr) => resolve_if(sheet, row, col, if_expr).map(ExtendedFormula::If),
+        Expr::SumProduct(first, second) => {
+            let (first, first_flags) = resolve_range_arg(sheet, first, row, col)?;
+            let (second, second_flags) = resolve_range_arg(sheet, second, row, col)?;
+            if first.end_row - first.start_row != second.end_row - second.start_row
+                || first.end_col - first.start_col != second.end_col - second.start_col
+            {
+                return Err(CommandStatus::Unrecognized);
             }
-            _ => {}
+            Ok(ExtendedFormula::SumProduct(
+                first,
+                first_flags,
+                second,
+                second_flags,
+            ))
+        }
+        Expr::Arithmetic(arith) => {
+            resolve_arith(sheet, row, col, arith).map(ExtendedFormula::Arithmetic)
         }
+        Expr::Sleep(arith) => resolve_arith(sheet, row, col, arith).map(ExtendedFormula::Sleep),
+        Expr::Compare(left, op, right) => Ok(ExtendedFormula::Compare(
+            resolve_arith(sheet, row, col, left)?,
+            *op,
+            resolve_arith(sheet, row, col, right)?,
+        )),
+        _ => Err(CommandStatus::Unrecognized),
     }
+}
 
-    if op_idx == 0 {
-        return CommandStatus::Unrecognized;
+/// Sets a cell to an `IF`, a `SUMPRODUCT`, an arithmetic tree, a `SLEEP` of one or a
+/// comparison of two, whose parents are kept in `sheet.extended_formulas`.
+fn assign_extended(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    expr: &Expr,
+    sleep_time: &mut f64,
+    #[cfg(feature = "extensions")] confirmed: bool,
+) -> CommandStatus {
+    let formula = match resolve_extended(sheet, row, col, expr) {
+        Ok(formula) => formula,
+        Err(status) => return status,
+    };
+    // A formula reading its own cell is a cycle; catch it before a SLEEP sleeps
+    let cell_key = sheet.get_key(row, col);
+    if formula.cells().contains(&cell_key)
+        || formula
+            .ranges()
+            .iter()
+            .any(|range| range.contains(row, col))
+    {
+        return CommandStatus::CircularRef;
     }
 
-    // Split into left and right parts
-    let left = &expr[..op_idx];
-    let right = &expr[op_idx + 1..];
-
-    if left.is_empty() || right.is_empty() {
-        return CommandStatus::Unrecognized;
+    // Refuse huge ranges before touching the sheet unless the user confirmed
+    #[cfg(feature = "extensions")]
+    if !confirmed
+        && let Some(status) = formula
+            .ranges()
+            .iter()
+            .map(|range| check_range_limit(sheet, range))
+            .find(|status| *status != CommandStatus::CmdOk)
+    {
+        return status;
     }
+    remove_all_parents(sheet, row, col);
 
-    // Variables to track cell references and values
-    let mut left_val = 0;
-    let mut right_val = 0;
-    let mut left_is_cell = false;
-    let mut right_is_cell = false;
-    let mut error_found = false;
-    let mut left_cell_key = -1;
-    let mut right_cell_key = -1;
-
-    // Parse left operand
-    if let Ok(num) = left.parse::<i32>() {
-        left_val = num;
-    } else {
-        // Try as cell reference
-        match parse_cell_reference(sheet, left) {
-            Ok((left_row, left_col)) => {
-                left_is_cell = true;
-                left_cell_key = sheet.get_key(left_row, left_col);
-
-                // Get reference cell value
-                let left_cell = sheet.get_cell(left_row, left_col);
-                match left_cell {
-                    CellValue::Integer(val) => left_val = *val,
-                    _ => {
-                        error_found = true;
-                    }
-                }
-            }
-            Err(status) => return status,
-        }
-    }
+    let meta = sheet.get_cell_meta(row, col);
+    meta.formula = EXTENDED_FORMULA;
+    meta.parent1 = -1;
+    meta.parent2 = -1;
 
-    // Parse right operand
-    if let Ok(num) = right.parse::<i32>() {
-        right_val = num;
-    } else {
-        // Try as cell reference
-        match parse_cell_reference(sheet, right) {
-            Ok((right_row, right_col)) => {
-                right_is_cell = true;
-                right_cell_key = sheet.get_key(right_row, right_col);
-
-                // Get reference cell value
-                let right_cell = sheet.get_cell(right_row, right_col);
-                match right_cell {
-                    CellValue::Integer(val) => right_val = *val,
-                    _ => {
-                        error_found = true;
-                    }
-                }
-            }
-            Err(status) => return status,
-        }
-    }
+    // The side table comes first: add_children reads the parents from it
+    sheet.extended_formulas.insert(cell_key, formula);
+    add_children(sheet, -1, -1, EXTENDED_FORMULA, row, col);
 
-    // Remove old dependencies
-    remove_all_parents(sheet, row, col);
+    eval_extended(sheet, row, col, sleep_time);
+    CommandStatus::CmdOk
+}
 
-    // Determine formula type based on operator and operand types
-    let mut formula_type = match op {
-        b'+' => 10,
-        b'-' => 20,
-        b'*' => 40,
-        b'/' => 30,
-        _ => unreachable!(),
+/// Sets a cell to a function over a range.
+fn assign_function(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    function: Function,
+    arg: &RangeArg,
+    #[cfg(feature = "extensions")] confirmed: bool,
+) -> CommandStatus {
+    // Parse range and validate early to avoid unnecessary work
+    let (range, flags) = match resolve_range_arg(sheet, arg, row, col) {
+        Ok(resolved) => resolved,
+        Err(status) => return status,
     };
 
-    // Adjust formula type based on cell references (like C code)
-    if left_is_cell && right_is_cell {
-        formula_type += 0; // Both are cells, no adjustment needed
-    } else if left_is_cell {
-        formula_type += 2;
-    } else if right_is_cell {
-        formula_type += 3;
+    // Refuse huge ranges before touching the sheet unless the user confirmed
+    #[cfg(feature = "extensions")]
+    if !confirmed {
+        let status = check_range_limit(sheet, &range);
+        if status != CommandStatus::CmdOk {
+            return status;
+        }
     }
 
-    // Set metadata
+    let formula_type = match function {
+        Function::Sum => 5,
+        Function::Avg => 6,
+        Function::Min => 7,
+        Function::Max => 8,
+        Function::Stdev => 9,
+        // The tens digit tells MEDIAN from SUM; the ones digit keeps it a range function
+        Function::Median => 15,
+        Function::SumIf(_) => 25,
+        Function::Product => 35,
+        Function::Spread => 45,
+        Function::CountA => 55,
+        Function::CountBlank => 65,
+    };
+    let parent1 = sheet.get_key(range.start_row, range.start_col);
+    let parent2 = sheet.get_key(range.end_row, range.end_col);
+    remove_all_parents(sheet, row, col);
+    // Update metadata
     let meta = sheet.get_cell_meta(row, col);
+    meta.parent1 = parent1;
+    meta.parent2 = parent2;
     meta.formula = formula_type;
-    meta.parent1 = if left_is_cell {
-        left_cell_key
-    } else {
-        left_val
-    };
-    meta.parent2 = if right_is_cell {
-        right_cell_key
-    } else {
-        right_val
-    };
 
-    // Check for circular references
-
-    // Add dependencies
-    if left_is_cell && right_is_cell {
-        // Add dependencies for both cells
-        add_children(sheet, left_cell_key, right_cell_key, formula_type, row, col);
-    } else if left_is_cell {
-        // Ordering of Cells matters
-        add_children(sheet, left_cell_key, -1, formula_type, row, col);
-    } else if right_is_cell {
-        // Ordering of Cells matters
-        add_children(sheet, -1, right_cell_key, formula_type, row, col);
+    // Add children and evaluate the appropriate function
+    add_children(sheet, parent1, parent2, formula_type, row, col);
+    record_reference_flags(sheet, row, col, flags);
+    if let Function::SumIf(criterion) = function {
+        let cell_key = sheet.get_key(row, col);
+        sheet.criteria.insert(cell_key, criterion);
     }
 
-    // Calculate result
-    let cell = sheet.get_mut_cell(row, col);
-
-    if error_found {
-        *cell = CellValue::Error;
-    } else {
-        match op {
-            b'+' => *cell = CellValue::Integer(left_val + right_val),
-            b'-' => *cell = CellValue::Integer(left_val - right_val),
-            b'*' => *cell = CellValue::Integer(left_val * right_val),
-            b'/' => {
-                if right_val == 0 {
-                    *cell = CellValue::Error;
-                } else {
-                    *cell = CellValue::Integer(left_val / right_val);
-                }
-            }
-            _ => unreachable!(),
-        }
+    match function {
+        Function::Stdev => eval_variance(sheet, row, col, parent1, parent2),
+        Function::Max => eval_max(sheet, row, col, parent1, parent2),
+        Function::Min => eval_min(sheet, row, col, parent1, parent2),
+        Function::Avg => eval_avg(sheet, row, col, parent1, parent2),
+        Function::Sum => sum_value(sheet, row, col, parent1, parent2),
+        Function::Median => eval_median(sheet, row, col, parent1, parent2),
+        Function::SumIf(_) => sum_if(sheet, row, col, parent1, parent2),
+        Function::Product => eval_product(sheet, row, col, parent1, parent2),
+        Function::Spread => eval_spread(sheet, row, col, parent1, parent2),
+        Function::CountA => eval_counta(sheet, row, col, parent1, parent2),
+        Function::CountBlank => eval_countblank(sheet, row, col, parent1, parent2),
     }
-
-    CommandStatus::CmdOk
 }
 
 /// Evaluates a formula, supporting arithmetic and range functions.
 ///
+/// The formula is read with `parse_expression`, which documents the grammar.
+///
 /// # Arguments
 ///
 /// * `sheet` - The mutable spreadsheet.
@@ -583,92 +902,64 @@ pub fn evaluate_formula(
     expr: &str,
     sleep_time: &mut f64,
 ) -> CommandStatus {
-    // Fast fail for empty expression
-    if expr.is_empty() {
-        return CommandStatus::Unrecognized;
-    }
-
-    // Optimize function checks by using bytes for prefix matching
-    let bytes = expr.as_bytes();
-
-    // Check for range-based functions with a single pass
-    let (is_formula, formula_type, prefix_len) = match bytes.get(0..3) {
-        Some(b"AVG") if bytes.get(3) == Some(&b'(') => (true, 6, 4),
-        Some(b"MIN") if bytes.get(3) == Some(&b'(') => (true, 7, 4),
-        Some(b"MAX") if bytes.get(3) == Some(&b'(') => (true, 8, 4),
-        Some(b"SUM") if bytes.get(3) == Some(&b'(') => (true, 5, 4),
-        Some(b"SLE")
-            if bytes.len() > 5
-                && bytes[3] == b'E'
-                && bytes[4] == b'P'
-                && bytes.get(5) == Some(&b'(') =>
-        {
-            // Handle sleep function separately
-            if !expr.ends_with(')') {
-                return CommandStatus::Unrecognized;
-            }
-            return handle_sleep(sheet, row, col, &expr[6..expr.len() - 1], sleep_time);
-        }
-        Some(b"STD")
-            if bytes.len() > 5
-                && bytes[3] == b'E'
-                && bytes[4] == b'V'
-                && bytes.get(5) == Some(&b'(') =>
-        {
-            (true, 9, 6)
-        }
-        _ => (false, -1, 0),
+    // A trailing `!` confirms a formula over an unusually large range
+    #[cfg(feature = "extensions")]
+    let (expr, confirmed) = match expr.strip_suffix('!') {
+        Some(stripped) => (stripped.trim_end(), true),
+        None => (expr, false),
     };
 
-    if is_formula {
-        // Validate formula format
-        if !expr.ends_with(')') {
-            return CommandStatus::Unrecognized;
+    match parse_expression(expr) {
+        Ok(Expr::Text(text)) => {
+            // A label is a constant like a literal: no parents and no formula
+            sheet.clear_formula(row, col);
+            *sheet.get_mut_cell(row, col) = CellValue::Text(text.replace("\"\"", "\""));
+            CommandStatus::CmdOk
         }
-
-        // Extract the range string without allocating extra memory
-        let range_str: &str = &expr[prefix_len..expr.len() - 1];
-
-        #[cfg(not(feature = "extensions"))]
-        let range = match parse_range(sheet, range_str) {
-            Ok(r) => r,
-            Err(status) => return status,
-        };
-
-        // Parse range and validate early to avoid unnecessary work
-        #[cfg(feature = "extensions")]
-        let range: Range = if let Some(named_range) = sheet.named_ranges.get(range_str) {
-            named_range.clone()
-        } else {
-            match parse_range(sheet, range_str) {
-                Ok(r) => r,
-                Err(status) => return status,
-            }
-        };
-
-        // let cell_key = sheet.get_key(row, col);
-        let parent1 = sheet.get_key(range.start_row, range.start_col);
-        let parent2 = sheet.get_key(range.end_row, range.end_col);
-        remove_all_parents(sheet, row, col);
-        // Update metadata
-        let meta = sheet.get_cell_meta(row, col);
-        meta.parent1 = parent1;
-        meta.parent2 = parent2;
-        meta.formula = formula_type;
-
-        // Add children and evaluate the appropriate function
-        add_children(sheet, parent1, parent2, formula_type, row, col);
-
-        match formula_type {
-            9 => eval_variance(sheet, row, col, parent1, parent2),
-            8 => eval_max(sheet, row, col, parent1, parent2),
-            7 => eval_min(sheet, row, col, parent1, parent2),
-            6 => eval_avg(sheet, row, col, parent1, parent2),
-            _ => sum_value(sheet, row, col, parent1, parent2), // SUM case
+        Ok(Expr::Operand(operand)) => assign_operand(sheet, row, col, &operand),
+        Ok(Expr::Binary(left, op, right)) => assign_binary(sheet, row, col, &left, op, &right),
+        Ok(Expr::Function(function, arg)) => assign_function(
+            sheet,
+            row,
+            col,
+            function,
+            &arg,
+            #[cfg(feature = "extensions")]
+            confirmed,
+        ),
+        Ok(Expr::Scalar(function, first, second)) => {
+            assign_scalar(sheet, row, col, function, &first, second.as_ref())
         }
-    } else {
-        // Handle arithmetic expressions
-        evaluate_arithmetic(sheet, row, col, expr)
+        // A literal or single cell keeps the compact SLEEP formula
+        Ok(Expr::Sleep(ArithExpr::Operand(operand))) => {
+            sleep_operand(sheet, row, col, &operand, sleep_time)
+        }
+        // So does a comparison of two literals or cells
+        Ok(Expr::Compare(ArithExpr::Operand(left), op, ArithExpr::Operand(right))) => assign_pair(
+            sheet,
+            row,
+            col,
+            &left,
+            &right,
+            op.formula_msb() * 10,
+            |l, r| op.apply(l, r),
+        ),
+        Ok(
+            expr @ (Expr::If(_)
+            | Expr::SumProduct(..)
+            | Expr::Arithmetic(_)
+            | Expr::Sleep(_)
+            | Expr::Compare(..)),
+        ) => assign_extended(
+            sheet,
+            row,
+            col,
+            &expr,
+            sleep_time,
+            #[cfg(feature = "extensions")]
+            confirmed,
+        ),
+        Err(_) => CommandStatus::Unrecognized,
     }
 }
 
@@ -687,6 +978,8 @@ pub fn evaluate_formula(
 /// * `CommandStatus::CmdOk` - On success.
 /// * `CommandStatus::CircularRef` - If a cycle is detected.
 /// * `CommandStatus::LockedCell` - If the cell is locked.
+/// * `CommandStatus::NotOwner` - If the cell is owned by another user and ownership is enforced.
+/// * `CommandStatus::ReadOnly` - If the sheet is read-only.
 /// * `CommandStatus::Unrecognized` - If expression is invalid.
 pub fn set_cell_value(
     sheet: &mut Spreadsheet,
@@ -695,54 +988,207 @@ pub fn set_cell_value(
     expr: &str,
     sleep_time: &mut f64,
 ) -> CommandStatus {
-    #[cfg(feature = "extensions")]
-    if sheet.is_cell_locked(row, col) {
-        return CommandStatus::LockedCell;
-    }
+    #[cfg(feature = "extensions")]
+    if sheet.read_only {
+        return CommandStatus::ReadOnly;
+    }
+    #[cfg(feature = "extensions")]
+    if sheet.is_cell_locked(row, col) {
+        return CommandStatus::LockedCell;
+    }
+    #[cfg(feature = "extensions")]
+    if sheet.check_owner(row, col) == CommandStatus::NotOwner {
+        return CommandStatus::NotOwner;
+    }
+    #[cfg(feature = "extensions")]
+    sheet.note_undo(sheet.get_key(row, col));
+    #[cfg(feature = "extensions")]
+    let before = sheet.cell_contribution(sheet.get_key(row, col));
+    let status = assign_cell_value(sheet, row, col, expr, sleep_time);
+    #[cfg(feature = "extensions")]
+    sheet.record_cell_change(sheet.get_key(row, col), before);
+    status
+}
+
+/// Evaluates `expr` into a cell, rolling back to the old formula and value on a cycle.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The target row.
+/// * `col` - The target column.
+/// * `expr` - The expression to evaluate.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus` - As for `set_cell_value`, except that locks and owners are not checked
+fn assign_cell_value(
+    sheet: &mut Spreadsheet,
+    row: i16,
+    col: i16,
+    expr: &str,
+    sleep_time: &mut f64,
+) -> CommandStatus {
     let cell_key = sheet.get_key(row, col);
 
-    // Save old state
-    let old_meta = sheet.cell_meta.get(&cell_key).cloned();
-    let old_value = match sheet.get_cell(row, col) {
-        CellValue::Integer(val) => CellValue::Integer(*val),
-        _ => CellValue::Error,
-    };
+    // Save old state; CellMeta is Copy, so this is a plain stack copy
+    let old_meta = sheet.cell_meta.get(&cell_key).copied();
+    let old_criterion = sheet.criteria.get(&cell_key).copied();
+    let old_extended = sheet.extended_formulas.get(&cell_key).cloned();
+    let was_assigned = sheet.assigned.contains(&cell_key);
+    #[cfg(feature = "extensions")]
+    let old_relative = sheet.relative_refs.get(&cell_key).copied();
+    #[cfg(feature = "extensions")]
+    let old_entry = sheet.history_entry(cell_key);
+    let old_value = sheet.get_cell(row, col).clone();
     let status: CommandStatus = evaluate_formula(sheet, row, col, expr, sleep_time);
     if let CommandStatus::CmdOk = status {
+        // Mark the cell before its dependents run, so COUNTA over it sees the change
+        sheet.assigned.insert(cell_key);
         // Reevaluate the cell dependents graphs i.e. all of its children
         // Also at same time check for cycle in the graph as it will save time and memory
         let has_cycle = toposort_reval_detect_cycle(sheet, row, col, sleep_time);
         if has_cycle {
             // If a cycle is detected, restore the old parents and formula
             // Remove the new parents and formula
-            remove_all_parents(sheet, row, col);
+            sheet.clear_formula(row, col);
             // Restore the old value
             *sheet.get_mut_cell(row, col) = old_value;
+            if !was_assigned {
+                sheet.assigned.remove(&cell_key);
+            }
             // Old meta
             if let Some(old) = old_meta {
                 let (parent1, parent2, formula) = (old.parent1, old.parent2, old.formula);
                 sheet.cell_meta.insert(cell_key, old);
+                if let Some(extended) = old_extended {
+                    sheet.extended_formulas.insert(cell_key, extended);
+                }
                 add_children(sheet, parent1, parent2, formula, row, col);
-            } else {
-                sheet.cell_meta.remove(&cell_key);
+                if let Some(criterion) = old_criterion {
+                    sheet.criteria.insert(cell_key, criterion);
+                }
+                #[cfg(feature = "extensions")]
+                if let Some(flags) = old_relative {
+                    sheet.relative_refs.insert(cell_key, flags);
+                }
             }
 
             return CommandStatus::CircularRef;
         }
         #[cfg(feature = "extensions")]
         if !has_cycle {
-            // If no cycle, update the cell history with the old value
-            sheet
-                .cell_history
-                .entry(cell_key)
-                .or_default()
-                .push(old_value);
+            // If no cycle, update the cell history with the old value and formula
+            sheet.push_history(cell_key, old_entry);
             sheet.set_last_edited(row, col);
         }
     }
     status
 }
 
+/// Parses a literal cell value token: an integer or `ERR`, which `replace` matches against
+/// errors of every kind.
+#[cfg(feature = "extensions")]
+pub fn parse_value_token(token: &str) -> Option<CellValue> {
+    if token == "ERR" {
+        Some(CellValue::Error(ErrorKind::ParseErr))
+    } else {
+        token.parse::<i32>().ok().map(CellValue::Integer)
+    }
+}
+
+/// Handles `replace <old> <new> [[in] range] [--dry]`, printing a summary of the changes.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `args` - The arguments after `replace `.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If the arguments are malformed, or the new value is
+///   `ERR`, which only matches errors
+#[cfg(feature = "extensions")]
+fn handle_replace(sheet: &mut Spreadsheet, args: &str, sleep_time: &mut f64) -> CommandStatus {
+    let mut dry_run = false;
+    let mut tokens: Vec<&str> = Vec::with_capacity(3);
+    for token in args.split_whitespace() {
+        if token == "--dry" {
+            dry_run = true;
+        } else {
+            tokens.push(token);
+        }
+    }
+    // `replace 0 -1 in A1:C10` reads the same as `replace 0 -1 A1:C10`
+    if tokens.len() == 4 && tokens[2] == "in" {
+        tokens.remove(2);
+    }
+    if tokens.len() < 2 || tokens.len() > 3 {
+        return CommandStatus::Unrecognized;
+    }
+    // `ERR` stands for errors of every kind, so it can be matched but not written
+    if tokens[1] == "ERR" {
+        sheet
+            .output
+            .line("replace: ERR only matches errors; the new value must be a number");
+        return CommandStatus::Unrecognized;
+    }
+    let (Some(old), Ok(new)) = (parse_value_token(tokens[0]), tokens[1].parse::<i32>()) else {
+        return CommandStatus::Unrecognized;
+    };
+    let new = CellValue::Integer(new);
+    let range = match tokens.get(2) {
+        Some(range_str) => match resolve_range(sheet, range_str) {
+            Ok(r) => Some(r),
+            Err(status) => return status,
+        },
+        None => None,
+    };
+
+    let report = replace_values(sheet, &old, new, range.as_ref(), dry_run, sleep_time);
+    let cell_name = |(row, col): (i16, i16)| format!("{}{}", sheet.get_column_name(col), row + 1);
+    let mut lines = Vec::new();
+    if dry_run {
+        for &cell in &report.changed {
+            lines.push(format!("{} would change", cell_name(cell)));
+        }
+    }
+    for &cell in &report.skipped_formula {
+        lines.push(format!("{} skipped (formula)", cell_name(cell)));
+    }
+    for &cell in &report.skipped_locked {
+        lines.push(format!("{} skipped (locked)", cell_name(cell)));
+    }
+    for &cell in &report.skipped_owned {
+        lines.push(format!("{} skipped (owned)", cell_name(cell)));
+    }
+    lines.push(format!(
+        "{} {} cell(s), skipped {} formula cell(s), {} locked cell(s) and {} owned cell(s)",
+        if dry_run { "Would change" } else { "Changed" },
+        report.changed.len(),
+        report.skipped_formula.len(),
+        report.skipped_locked.len(),
+        report.skipped_owned.len()
+    ));
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
+/// Splits a command into its first word and the rest, trimmed.
+///
+/// Matching the word whole, rather than a prefix of a known length, keeps a command
+/// such as `unlock_cell` from being read as `lock_cell` or cut in the wrong place.
+#[cfg(feature = "extensions")]
+fn split_command(line: &str) -> (&str, &str) {
+    match line.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (line, ""),
+    }
+}
+
 /// Processes user commands, updating the spreadsheet accordingly.
 ///
 /// Supports cell assignments, scrolling, locking, and more.
@@ -761,6 +1207,26 @@ pub fn handle_command(
     trimmed: &str,
     sleep_time: &mut f64,
 ) -> CommandStatus {
+    #[cfg(feature = "extensions")]
+    if sheet.read_only && !is_read_only_command(trimmed) {
+        return CommandStatus::ReadOnly;
+    }
+    // The lines of a script run by `dry run` are held to what `dry` allows
+    #[cfg(feature = "extensions")]
+    if sheet.dry_run.is_some()
+        && let Err(status) = check_dry_runnable(sheet, trimmed)
+    {
+        return status;
+    }
+
+    // The cells a command changes, including through commands it runs, form one undo step
+    #[cfg(feature = "extensions")]
+    if sheet.begin_undo_step(trimmed) {
+        let status = handle_command(sheet, trimmed, sleep_time);
+        sheet.end_undo_step(&status);
+        return status;
+    }
+
     // Fast path for single-character commands to avoid string comparisons
     if trimmed.len() == 1 {
         match trimmed.as_bytes()[0] {
@@ -789,6 +1255,12 @@ pub fn handle_command(
             sheet.scroll_to_last_edited();
             return CommandStatus::CmdOk;
         }
+        #[cfg(feature = "extensions")]
+        "clear_sheet" => {
+            sheet.clear();
+            sheet.dirty = true;
+            return CommandStatus::CmdOk;
+        }
         _ => {}
     }
 
@@ -798,9 +1270,37 @@ pub fn handle_command(
         && trimmed.as_bytes()[9] == b' '
     {
         let cell_ref = &trimmed[10..];
+        #[cfg(feature = "extensions")]
+        if let Some(address) = cell_ref.trim().strip_prefix('@') {
+            return match resolve_viewport_address(sheet, address) {
+                Ok((row, col)) => {
+                    sheet.viewport_row = row;
+                    sheet.viewport_col = col;
+                    CommandStatus::CmdOk
+                }
+                Err(status) => status,
+            };
+        }
         return sheet.scroll_to_cell(cell_ref);
     }
 
+    // A dry run may wrap an assignment, so it is recognized before them
+    #[cfg(feature = "extensions")]
+    if let Some(command) = trimmed.strip_prefix("dry ") {
+        return handle_dry(sheet, command);
+    }
+
+    // Column formula definitions and filters such as `A >= 5` contain '=' too, so they come
+    // before assignments
+    #[cfg(feature = "extensions")]
+    if let Some(args) = trimmed.strip_prefix("colformula ") {
+        return handle_colformula(sheet, args, sleep_time);
+    }
+    #[cfg(feature = "extensions")]
+    if let Some(args) = trimmed.strip_prefix("filter ") {
+        return handle_filter(sheet, args);
+    }
+
     // Check for cell assignment using byte search for '='
     let bytes = trimmed.as_bytes();
     let mut eq_pos = None;
@@ -817,6 +1317,33 @@ pub fn handle_command(
         let cell_ref = trimmed[..pos].trim();
         let expr = trimmed[pos + 1..].trim();
 
+        // A formula must not change meaning when the view scrolls
+        #[cfg(feature = "extensions")]
+        if has_viewport_address(expr) {
+            sheet.output.line(
+                "viewport addresses (@rNcM) are only allowed in commands, not in formulas; use a cell reference such as A1",
+            );
+            return CommandStatus::Unrecognized;
+        }
+
+        // A range or named range on the left fills every cell in it
+        #[cfg(feature = "extensions")]
+        if let Some(range) = assignment_range(sheet, cell_ref) {
+            return match range {
+                Ok(range) => handle_range_assignment(sheet, cell_ref, &range, expr, sleep_time),
+                Err(status) => status,
+            };
+        }
+
+        // A viewport address on the left names the cell by its place on screen
+        #[cfg(feature = "extensions")]
+        if let Some(address) = cell_ref.strip_prefix('@') {
+            return match resolve_viewport_address(sheet, address) {
+                Ok((row, col)) => set_cell_value(sheet, row, col, expr, sleep_time),
+                Err(status) => status,
+            };
+        }
+
         // Parse the cell reference with direct result handling
         return match parse_cell_reference(sheet, cell_ref) {
             Ok((row, col)) => {
@@ -828,38 +1355,49 @@ pub fn handle_command(
     }
     #[cfg(feature = "extensions")]
     {
-        if trimmed.starts_with("lock_cell ") {
-            let lock_target = trimmed.get(10..).unwrap_or("").trim();
-            if lock_target.contains(':') {
-                match parse_range(sheet, lock_target) {
-                    Ok(range) => {
-                        sheet.lock_range(range);
-                        return CommandStatus::CmdOk;
-                    }
-                    Err(_) => return CommandStatus::Unrecognized,
-                }
-            } else {
-                match resolve_cell_reference(sheet, lock_target) {
-                    Ok((row, col)) => {
-                        let range = Range {
-                            start_row: row,
-                            start_col: col,
-                            end_row: row,
-                            end_col: col,
-                        };
-                        sheet.lock_range(range);
-                        return CommandStatus::CmdOk;
-                    }
-                    Err(status) => return status,
-                }
-            }
+        // Commands in the argument table have their arguments checked before they run
+        if let Err(error) = check_arguments(sheet, trimmed) {
+            eprintln!("{}", error.message);
+            return error.status;
+        }
+
+        if let Some(args) = trimmed.strip_prefix("help")
+            && (args.is_empty() || args.starts_with(' '))
+        {
+            return handle_help(sheet, args);
+        }
+
+        match split_command(trimmed) {
+            ("lock_cell", target) => return handle_lock(sheet, target),
+            ("unlock_cell", target) => return handle_unlock(sheet, target),
+            ("unlock_all", "") => return handle_unlock_all(sheet),
+            ("is_locked", target) => return handle_is_locked(sheet, target),
+            ("locked", "") => return handle_locked(sheet),
+            ("readonly", args) => return handle_readonly(sheet, args),
+            ("autosave", args) => return handle_autosave(sheet, args),
+            ("names", "") => return handle_names(sheet),
+            ("unname", name) => return handle_unname(sheet, name),
+            ("rename", args) => return handle_rename(sheet, args),
+            ("export_csv", file) => return export_csv(sheet, file),
+            ("import_csv", file) => return import_csv(sheet, file, sleep_time),
+            _ => {}
         }
 
         // Check for cell dependency visualization command
-        if let Some(cell_ref) = trimmed.strip_prefix("visual ") {
+        if let Some(args) = trimmed.strip_prefix("visual ") {
+            let mut parts = args.split_whitespace();
+            let cell_ref = parts.next().unwrap_or("");
+            let depth = match parts.next().map(str::parse::<usize>) {
+                None => DEFAULT_TREE_DEPTH,
+                Some(Ok(depth)) if depth > 0 => depth.min(MAX_TREE_DEPTH),
+                Some(_) => return CommandStatus::Unrecognized,
+            };
+            if parts.next().is_some() {
+                return CommandStatus::Unrecognized;
+            }
             match parse_cell_reference(sheet, cell_ref) {
                 Ok((row, col)) => {
-                    return sheet.visualize_cell_relationships(row, col);
+                    return sheet.visualize_cell_relationships(row, col, depth);
                 }
                 Err(status) => {
                     return status;
@@ -867,98 +1405,291 @@ pub fn handle_command(
             }
         }
 
-        if trimmed.starts_with("unlock_cell ") {
-            let unlock_target = trimmed.get(11..).unwrap_or("").trim();
-            if unlock_target.contains(':') {
-                match parse_range(sheet, unlock_target) {
-                    Ok(range) => {
-                        sheet.unlock_range(range);
-                        return CommandStatus::CmdOk;
-                    }
-                    Err(_) => return CommandStatus::Unrecognized,
-                }
-            } else {
-                match resolve_cell_reference(sheet, unlock_target) {
-                    Ok((row, col)) => {
-                        let range = Range {
-                            start_row: row,
-                            start_col: col,
-                            end_row: row,
-                            end_col: col,
-                        };
-                        sheet.unlock_range(range);
-                        return CommandStatus::CmdOk;
-                    }
-                    Err(status) => return status,
-                }
-            }
+        if let Some(target) = trimmed.strip_prefix("delete ") {
+            return match resolve_target(sheet, target.trim()) {
+                Ok(range) => delete_cells(sheet, &range, sleep_time),
+                Err(status) => status,
+            };
+        }
+
+        if let Some(args) = trimmed.strip_prefix("name ") {
+            return handle_name(sheet, args);
         }
 
-        if let Some(cell_ref) = trimmed.strip_prefix("is_locked ") {
-            let cell_ref = cell_ref.trim();
+        if let Some(args) = trimmed.strip_prefix("history ") {
+            return handle_history(sheet, args, sleep_time);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("show_history ") {
+            return handle_show_history(sheet, args);
+        }
+
+        if trimmed == "history_stats" {
+            return handle_history_stats(sheet);
+        }
+
+        if let Some(stripped) = trimmed.strip_prefix("formula ") {
+            // Long formulas are cut to the terminal width; --full wraps them through the pager
+            let (cell_ref, full) = match stripped.trim().strip_suffix("--full") {
+                Some(cell_ref) => (cell_ref.trim(), true),
+                None => (stripped.trim(), false),
+            };
             match resolve_cell_reference(sheet, cell_ref) {
                 Ok((row, col)) => {
-                    if sheet.is_cell_locked(row, col) {
-                        return CommandStatus::LockedCell;
+                    let formula_str = get_formula_string(sheet, row, col);
+                    let width = terminal_width();
+                    if full {
+                        sheet.output.lines(wrap_display(&formula_str, width));
                     } else {
-                        return CommandStatus::NotLockedCell;
+                        sheet.output.line(truncate_display(&formula_str, width));
                     }
+                    return CommandStatus::CmdOk;
                 }
                 Err(status) => return status,
             }
         }
 
-        if let Some(name_arg) = trimmed.strip_prefix("name ") {
-            let parts: Vec<&str> = name_arg.split_whitespace().collect();
-            if parts.len() == 2 {
-                let target = parts[0];
-                let name = parts[1];
-                if let Ok(range) = parse_range(sheet, target) {
-                    sheet.named_ranges.insert(name.to_string(), range);
-                    return CommandStatus::CmdOk;
-                } else if let Ok((row, col)) = parse_cell_reference(sheet, target) {
-                    let range = Range {
-                        start_row: row,
-                        start_col: col,
-                        end_row: row,
-                        end_col: col,
-                    };
-                    sheet.named_ranges.insert(name.to_string(), range);
-                    return CommandStatus::CmdOk;
+        if let Some(args) = trimmed.strip_prefix("set_limit ") {
+            let parts: Vec<&str> = args.split_whitespace().collect();
+            return match parts.as_slice() {
+                ["range_cells", value] => match value.parse::<i64>() {
+                    Ok(limit) if limit > 0 => {
+                        sheet.range_cell_limit = limit;
+                        CommandStatus::CmdOk
+                    }
+                    _ => CommandStatus::Unrecognized,
+                },
+                ["line_bytes", value] => match value.parse::<usize>() {
+                    Ok(limit) if limit > 0 => {
+                        sheet.line_byte_limit = limit;
+                        CommandStatus::CmdOk
+                    }
+                    _ => CommandStatus::Unrecognized,
+                },
+                _ => CommandStatus::Unrecognized,
+            };
+        }
+
+        if let Some(args) = trimmed.strip_prefix("set ") {
+            // The path is the rest of the line, so it may contain spaces
+            if let Some(path) = args.strip_prefix("history_file ") {
+                let path = path.trim();
+                if path.is_empty() {
+                    return CommandStatus::Unrecognized;
                 }
+                sheet.history.set_path(path);
+                return CommandStatus::CmdOk;
             }
-            return CommandStatus::Unrecognized;
+            let parts: Vec<&str> = args.split_whitespace().collect();
+            return match parts.as_slice() {
+                ["prompt_stats", "on"] => {
+                    sheet.prompt_stats = true;
+                    CommandStatus::CmdOk
+                }
+                ["prompt_stats", "off"] => {
+                    sheet.prompt_stats = false;
+                    CommandStatus::CmdOk
+                }
+                ["confirm_overwrite_bulk", "on"] => {
+                    sheet.confirm_overwrite_bulk = true;
+                    CommandStatus::CmdOk
+                }
+                ["confirm_overwrite_bulk", "off"] => {
+                    sheet.confirm_overwrite_bulk = false;
+                    CommandStatus::CmdOk
+                }
+                ["page_size", value] => match value.parse::<usize>() {
+                    Ok(size) => {
+                        sheet.output.page_size = size;
+                        CommandStatus::CmdOk
+                    }
+                    Err(_) => CommandStatus::Unrecognized,
+                },
+                ["history_limit", value] => match value.parse::<usize>() {
+                    Ok(limit) => {
+                        sheet.set_history_limit(limit);
+                        CommandStatus::CmdOk
+                    }
+                    Err(_) => CommandStatus::Unrecognized,
+                },
+                ["history_size", value] => match value.parse::<usize>() {
+                    Ok(size) if size > 0 => {
+                        sheet.history.max_entries = size;
+                        CommandStatus::CmdOk
+                    }
+                    _ => CommandStatus::Unrecognized,
+                },
+                ["backups", value] => match value.parse::<usize>() {
+                    Ok(count) if count <= MAX_BACKUPS => {
+                        sheet.backups = count;
+                        CommandStatus::CmdOk
+                    }
+                    _ => CommandStatus::Unrecognized,
+                },
+                ["user", name] if is_valid_user(name) => {
+                    sheet.ownership.user = Some(name.to_string());
+                    CommandStatus::CmdOk
+                }
+                ["enforce_ownership", "on"] => {
+                    sheet.ownership.enforce = true;
+                    CommandStatus::CmdOk
+                }
+                ["enforce_ownership", "off"] => {
+                    sheet.ownership.enforce = false;
+                    CommandStatus::CmdOk
+                }
+                ["autosave_every", value] => match value.parse::<usize>() {
+                    Ok(count) if count > 0 => {
+                        sheet.autosave_every = count;
+                        CommandStatus::CmdOk
+                    }
+                    _ => CommandStatus::Unrecognized,
+                },
+                _ => CommandStatus::Unrecognized,
+            };
+        }
+
+        if trimmed == "verify" {
+            return handle_verify(sheet);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("recalc_all")
+            && (args.is_empty() || args.starts_with(' '))
+        {
+            return handle_recalc_all(sheet, args);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("changes")
+            && (args.is_empty() || args.starts_with(' '))
+        {
+            return handle_changes(sheet, args);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("graph_stats")
+            && (args.is_empty() || args.starts_with(' '))
+        {
+            return handle_graph_stats(sheet, args);
+        }
+
+        if let Some(status) = handle_file_command(sheet, trimmed) {
+            return status;
+        }
+
+        if let Some(args) = trimmed.strip_prefix("sort ") {
+            return match parse_sort_args(sheet, args) {
+                Ok((range, keys, header)) => sort_range(sheet, &range, &keys, header, sleep_time),
+                Err(status) => status,
+            };
+        }
+
+        if let Some(args) = trimmed.strip_prefix("copy ") {
+            return handle_copy(sheet, args, sleep_time);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("move ") {
+            return handle_move(sheet, args, sleep_time);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("transpose ") {
+            return handle_transpose(sheet, args, sleep_time);
+        }
+
+        if trimmed == "undo" {
+            return handle_undo(sheet, sleep_time);
+        }
+
+        if trimmed == "redo" {
+            return handle_redo(sheet, sleep_time);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("find ") {
+            return handle_find(sheet, args);
+        }
+
+        if trimmed == "find_next" {
+            return handle_find_next(sheet);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("series ") {
+            return handle_series(sheet, args, sleep_time);
         }
 
-        if let Some(stripped) = trimmed.strip_prefix("history ") {
-            let cell_ref = stripped.trim();
-            return match resolve_cell_reference(sheet, cell_ref) {
+        if let Some(args) = trimmed.strip_prefix("insert_row ") {
+            return handle_insert_row(sheet, args, sleep_time);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("delete_row ") {
+            return handle_delete_row(sheet, args, sleep_time);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("replace ") {
+            return handle_replace(sheet, args, sleep_time);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("deps ") {
+            return handle_deps(sheet, args, false);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("deps* ") {
+            return handle_deps(sheet, args, true);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("precedents ") {
+            return handle_precedents(sheet, args, false);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("precedents* ") {
+            return handle_precedents(sheet, args, true);
+        }
+
+        if let Some(cell_ref) = trimmed.strip_prefix("trace_error ") {
+            return match resolve_cell_reference(sheet, cell_ref.trim()) {
                 Ok((row, col)) => {
-                    let cell_key = sheet.get_key(row, col);
-                    if let Some(history) = sheet.cell_history.get_mut(&cell_key) {
-                        if let Some(prev_value) = history.pop() {
-                            set_cell_to_value(sheet, row, col, prev_value, sleep_time)
-                        } else {
-                            CommandStatus::CmdOk
-                        }
-                    } else {
-                        CommandStatus::CmdOk
-                    }
+                    let lines = trace_error(sheet, row, col);
+                    sheet.output.lines(lines);
+                    CommandStatus::CmdOk
                 }
                 Err(status) => status,
             };
         }
 
-        if let Some(stripped) = trimmed.strip_prefix("formula ") {
-            let cell_ref = stripped.trim();
-            match resolve_cell_reference(sheet, cell_ref) {
+        if let Some(cell_ref) = trimmed.strip_prefix("error ") {
+            return match resolve_cell_reference(sheet, cell_ref.trim()) {
                 Ok((row, col)) => {
-                    let formula_str = get_formula_string(sheet, row, col);
-                    println!("{}", formula_str);
-                    return CommandStatus::CmdOk;
+                    let line = explain_error(sheet, row, col);
+                    sheet.output.line(line);
+                    CommandStatus::CmdOk
                 }
-                Err(status) => return status,
-            }
+                Err(status) => status,
+            };
+        }
+
+        if let Some(args) = trimmed.strip_prefix("sample ") {
+            return handle_sample(sheet, args);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("count ") {
+            return handle_count(sheet, args);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("stats ") {
+            return handle_stats(sheet, args);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("session ") {
+            return handle_session(sheet, args);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("own ") {
+            return handle_own(sheet, args);
+        }
+
+        if let Some(args) = trimmed.strip_prefix("disown ") {
+            return handle_disown(sheet, args);
+        }
+
+        if trimmed == "owners" {
+            return handle_owners(sheet);
         }
 
         // Check for highlight commands
@@ -1001,13 +1732,43 @@ pub fn handle_command(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::cell::CellValue;
+    use crate::cell::{CellValue, ErrorKind};
     use crate::spreadsheet::{CommandStatus, Spreadsheet};
 
     fn create_test_spreadsheet(rows: i16, cols: i16) -> Spreadsheet {
         Spreadsheet::create(rows, cols).unwrap()
     }
 
+    /// Sets a cell to `SLEEP` of a literal or a plain cell reference, as the `SLEEP` command
+    /// did before formulas went through the expression parser.
+    fn handle_sleep(
+        sheet: &mut Spreadsheet,
+        row: i16,
+        col: i16,
+        expr: &str,
+        sleep_time: &mut f64,
+    ) -> CommandStatus {
+        match parse_expression(expr) {
+            Ok(Expr::Operand(operand)) => sleep_operand(sheet, row, col, &operand, sleep_time),
+            _ => CommandStatus::Unrecognized,
+        }
+    }
+
+    /// Sets a cell to a literal, a reference or one binary operation, refusing anything
+    /// longer, as plain arithmetic did before formulas went through the expression parser.
+    fn evaluate_arithmetic(
+        sheet: &mut Spreadsheet,
+        row: i16,
+        col: i16,
+        expr: &str,
+    ) -> CommandStatus {
+        match parse_expression(expr) {
+            Ok(Expr::Operand(operand)) => assign_operand(sheet, row, col, &operand),
+            Ok(Expr::Binary(left, op, right)) => assign_binary(sheet, row, col, &left, op, &right),
+            _ => CommandStatus::Unrecognized,
+        }
+    }
+
     #[test]
     fn test_handle_sleep_with_reference() {
         let mut sheet = create_test_spreadsheet(5, 5);
@@ -1102,10 +1863,123 @@ mod tests {
         let mut sheet = create_test_spreadsheet(5, 5);
         *sheet.get_mut_cell(0, 0) = CellValue::Integer(5);
         assert_eq!(
-            evaluate_arithmetic(&mut sheet, 1, 1, "A1/0"),
-            CommandStatus::CmdOk
+            evaluate_arithmetic(&mut sheet, 1, 1, "A1/0"),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_modulo_and_power_operators() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "B1=17", &mut sleep_time);
+        handle_command(&mut sheet, "B2=3", &mut sleep_time);
+        // Cell and cell, cell and literal, literal and cell, two literals
+        for (command, code, value, formula) in [
+            ("A1=B1%B2", 50, 2, "B1%B2"),
+            ("A2=B1%7", 52, 3, "B1%7"),
+            ("A3=40%B1", 53, 6, "40%B1"),
+            ("A4=B2^3", 62, 27, "B2^3"),
+            ("A5=2^B2", 63, 8, "2^B2"),
+            ("C1=B2^B2", 60, 27, "B2^B2"),
+            ("C2=B1%-5", 52, 2, "B1%-5"),
+        ] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                command
+            );
+            let (row, col) = parse_cell_reference(&sheet, &command[..2]).unwrap();
+            assert_eq!(sheet.get_cell_meta(row, col).formula, code, "{}", command);
+            assert_eq!(
+                *sheet.get_cell(row, col),
+                CellValue::Integer(value),
+                "{}",
+                command
+            );
+            #[cfg(feature = "extensions")]
+            assert_eq!(get_formula_string(&sheet, row, col), formula);
+            #[cfg(not(feature = "extensions"))]
+            let _ = formula;
+        }
+
+        // Dependents follow their parents, and a zero divisor or negative exponent is ERR
+        handle_command(&mut sheet, "B1=20", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(2));
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(6));
+        assert_eq!(*sheet.get_cell(2, 0), CellValue::Integer(0));
+        handle_command(&mut sheet, "B2=0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Error(ErrorKind::DivZero));
+        assert_eq!(*sheet.get_cell(3, 0), CellValue::Integer(0));
+        handle_command(&mut sheet, "B2=-1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(3, 0), CellValue::Integer(-1));
+        assert_eq!(*sheet.get_cell(4, 0), CellValue::Error(ErrorKind::Overflow));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Error(ErrorKind::Overflow));
+        handle_command(&mut sheet, "B1=1/0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Error(ErrorKind::DivZero));
+        assert_eq!(*sheet.get_cell(2, 0), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_scalar_functions() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "B1=-4", &mut sleep_time);
+        handle_command(&mut sheet, "C1=9", &mut sleep_time);
+        for (command, code, value, formula) in [
+            ("A1=ABS(B1)", 122, 4, "ABS(B1)"),
+            ("A2=MIN2(B1,C1)", 70, -4, "MIN2(B1,C1)"),
+            ("A3=MAX2(B1,7)", 112, 7, "MAX2(B1,7)"),
+            ("A4=MIN2(-10,C1)", 73, -10, "MIN2(-10,C1)"),
+            ("A5=MAX2(B1,C1)", 110, 9, "MAX2(B1,C1)"),
+        ] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                command
+            );
+            let (row, col) = parse_cell_reference(&sheet, &command[..2]).unwrap();
+            assert_eq!(sheet.get_cell_meta(row, col).formula, code, "{}", command);
+            assert_eq!(
+                *sheet.get_cell(row, col),
+                CellValue::Integer(value),
+                "{}",
+                command
+            );
+            #[cfg(feature = "extensions")]
+            assert_eq!(get_formula_string(&sheet, row, col), formula);
+            #[cfg(not(feature = "extensions"))]
+            let _ = formula;
+        }
+
+        // Dependents follow their parents, and errors pass through
+        handle_command(&mut sheet, "B1=12", &mut sleep_time);
+        let column: Vec<_> = (0..5).map(|row| sheet.get_cell(row, 0).clone()).collect();
+        assert_eq!(
+            column,
+            [12, 9, 12, -10, 12].map(CellValue::Integer).to_vec()
+        );
+        handle_command(&mut sheet, "B1=1/0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Error(ErrorKind::DivZero));
+        assert_eq!(*sheet.get_cell(2, 0), CellValue::Error(ErrorKind::DivZero));
+        assert_eq!(*sheet.get_cell(3, 0), CellValue::Integer(-10));
+
+        // ABS of a literal is a plain value
+        handle_command(&mut sheet, "D1=ABS(-3)", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(3));
+        assert!(!sheet.cell_meta.contains_key(&sheet.get_key(0, 3)));
+        assert_eq!(
+            handle_command(&mut sheet, "D2=ABS(B1:C1)", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "D2=MIN2(D2,1)", &mut sleep_time),
+            CommandStatus::CircularRef
         );
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error);
     }
 
     #[test]
@@ -1206,7 +2080,7 @@ mod tests {
             set_cell_value(&mut sheet, 0, 0, "A1", &mut sleep_time),
             CommandStatus::CircularRef
         );
-        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Empty);
     }
 
     #[test]
@@ -1248,6 +2122,194 @@ mod tests {
         assert_eq!(sleep_time, 1.0);
     }
 
+    #[test]
+    fn test_sleep_of_arithmetic() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "B1=1", &mut sleep_time);
+        handle_command(&mut sheet, "C1=3", &mut sleep_time);
+        sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "A1=SLEEP(B1+2*C1)", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(7));
+        assert_eq!(sleep_time, 7.0);
+
+        // Changing any input recomputes the duration and sleeps again
+        sleep_time = 0.0;
+        handle_command(&mut sheet, "C1=1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(3));
+        assert_eq!(sleep_time, 3.0);
+
+        // A negative duration does not sleep, an error neither
+        sleep_time = 0.0;
+        handle_command(&mut sheet, "B1=-10", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(-8));
+        handle_command(&mut sheet, "C1=1/0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Error(ErrorKind::DivZero));
+        assert_eq!(sleep_time, 0.0);
+
+        // Reading itself anywhere is a cycle, and the old formula stays
+        assert_eq!(
+            handle_command(&mut sheet, "A1=SLEEP(B1+A1)", &mut sleep_time),
+            CommandStatus::CircularRef
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "A1=SLEEP(SUM(A1:B1)+1)", &mut sleep_time),
+            CommandStatus::CircularRef
+        );
+        assert_eq!(sleep_time, 0.0);
+        handle_command(&mut sheet, "C1=2", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(-6));
+    }
+
+    #[test]
+    fn test_text_values() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "A1=\"Revenue\"", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            *sheet.get_cell(0, 0),
+            CellValue::Text("Revenue".to_string())
+        );
+        handle_command(&mut sheet, "A2=\"say \"\"hi\"\"\"", &mut sleep_time);
+        assert_eq!(
+            *sheet.get_cell(1, 0),
+            CellValue::Text("say \"hi\"".to_string())
+        );
+        // Text needs its quotes: a bare word reads as a name, which is not defined here
+        assert_eq!(
+            handle_command(&mut sheet, "A3=hello", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(*sheet.get_cell(2, 0), CellValue::Empty);
+
+        // Ranges skip labels, arithmetic on one is an error
+        handle_command(&mut sheet, "A3=4", &mut sleep_time);
+        handle_command(&mut sheet, "A4=8", &mut sleep_time);
+        handle_command(&mut sheet, "B1=SUM(A1:A4)", &mut sleep_time);
+        handle_command(&mut sheet, "B2=AVG(A1:A4)", &mut sleep_time);
+        handle_command(&mut sheet, "B3=A1+1", &mut sleep_time);
+        handle_command(&mut sheet, "B4=AVG(A1:A2)", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(12));
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(6));
+        assert_eq!(*sheet.get_cell(2, 1), CellValue::Error(ErrorKind::ParseErr));
+        assert_eq!(*sheet.get_cell(3, 1), CellValue::Error(ErrorKind::DivZero));
+
+        // A copy carries the label and follows it
+        handle_command(&mut sheet, "C1=A1", &mut sleep_time);
+        handle_command(&mut sheet, "A1=\"Costs\"", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Text("Costs".to_string()));
+        handle_command(&mut sheet, "A1=3", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(3));
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(15));
+
+        // An unterminated label is not a formula
+        assert_eq!(
+            handle_command(&mut sheet, "A1=\"open", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_comparison_formulas() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "B1=4", &mut sleep_time);
+        handle_command(&mut sheet, "C1=4", &mut sleep_time);
+        for (command, value) in [
+            ("A1=B1>C1", 0),
+            ("A2=B1=C1", 1),
+            ("A3=B1!=C1", 0),
+            ("A4=B1<=5", 1),
+            ("A5=SUM(B1:C1)>=B1*2", 1),
+        ] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                command
+            );
+            let (row, col) = parse_cell_reference(&sheet, &command[..2]).unwrap();
+            assert_eq!(
+                *sheet.get_cell(row, col),
+                CellValue::Integer(value),
+                "{}",
+                command
+            );
+        }
+
+        // Both operands are parents, so the results follow either
+        handle_command(&mut sheet, "C1=3", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(1));
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(2, 0), CellValue::Integer(1));
+        assert_eq!(*sheet.get_cell(4, 0), CellValue::Integer(0));
+        handle_command(&mut sheet, "B1=9", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(3, 0), CellValue::Integer(0));
+
+        // An error or text on either side is an error, and a cycle is refused
+        handle_command(&mut sheet, "C1=1/0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Error(ErrorKind::DivZero));
+        handle_command(&mut sheet, "B1=\"x\"", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(3, 0), CellValue::Error(ErrorKind::ParseErr));
+        assert_eq!(
+            handle_command(&mut sheet, "B1=A1>0", &mut sleep_time),
+            CommandStatus::CircularRef
+        );
+
+        #[cfg(feature = "extensions")]
+        for (row, formula) in [
+            (0, "B1>C1"),
+            (1, "B1=C1"),
+            (3, "B1<=5"),
+            (4, "SUM(B1:C1)>=B1*2"),
+        ] {
+            assert_eq!(get_formula_string(&sheet, row, 0), formula);
+        }
+    }
+
+    #[test]
+    fn test_arithmetic_overflow_is_error() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A1=2147483647", &mut sleep_time);
+        handle_command(&mut sheet, "B1=1", &mut sleep_time);
+        handle_command(&mut sheet, "C1=A1+B1", &mut sleep_time);
+        handle_command(&mut sheet, "D1=C1*2", &mut sleep_time);
+        handle_command(&mut sheet, "E1=(A1+B1)/2", &mut sleep_time);
+        handle_command(&mut sheet, "A2=0-A1", &mut sleep_time);
+        handle_command(&mut sheet, "B2=A2-B1", &mut sleep_time);
+        handle_command(&mut sheet, "C2=B2/-1", &mut sleep_time);
+        handle_command(&mut sheet, "D2=SUM(A1:B1)", &mut sleep_time);
+        for col in 2..5 {
+            assert_eq!(
+                *sheet.get_cell(0, col),
+                CellValue::Error(ErrorKind::Overflow)
+            );
+        }
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(i32::MIN));
+        assert_eq!(*sheet.get_cell(1, 2), CellValue::Error(ErrorKind::Overflow));
+        assert_eq!(*sheet.get_cell(1, 3), CellValue::Error(ErrorKind::Overflow));
+        assert_eq!(
+            handle_command(&mut sheet, "E2=2000000000+2000000000", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 4), CellValue::Error(ErrorKind::Overflow));
+
+        // Back in range, every dependent recovers
+        handle_command(&mut sheet, "B1=-1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(i32::MAX - 1));
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Error(ErrorKind::Overflow));
+        assert_eq!(*sheet.get_cell(0, 4), CellValue::Integer(i32::MAX / 2));
+        assert_eq!(*sheet.get_cell(1, 2), CellValue::Integer(i32::MAX - 1));
+        assert_eq!(*sheet.get_cell(1, 3), CellValue::Integer(i32::MAX - 1));
+    }
+
     #[test]
     fn test_evaluate_arithmetic_both_cells() {
         let mut sheet = create_test_spreadsheet(5, 5);
@@ -1320,7 +2382,7 @@ mod tests {
             evaluate_arithmetic(&mut sheet, 1, 1, "A1/B1"),
             CommandStatus::CmdOk
         );
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero));
     }
 
     #[test]
@@ -1331,7 +2393,7 @@ mod tests {
             set_cell_value(&mut sheet, 0, 0, "A1+B1", &mut sleep_time),
             CommandStatus::CircularRef
         );
-        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Empty);
     }
 
     #[test]
@@ -1350,13 +2412,13 @@ mod tests {
     fn test_evaluate_formula_min_with_error() {
         let mut sheet = create_test_spreadsheet(5, 5);
         *sheet.get_mut_cell(0, 0) = CellValue::Integer(5);
-        *sheet.get_mut_cell(0, 1) = CellValue::Error;
+        *sheet.get_mut_cell(0, 1) = CellValue::Error(ErrorKind::DivZero);
         let mut sleep_time = 0.0;
         assert_eq!(
             evaluate_formula(&mut sheet, 1, 1, "MIN(A1:B1)", &mut sleep_time),
             CommandStatus::CmdOk
         );
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero));
     }
 
     #[test]
@@ -1365,30 +2427,658 @@ mod tests {
         *sheet.get_mut_cell(0, 0) = CellValue::Integer(-5);
         *sheet.get_mut_cell(0, 1) = CellValue::Integer(-3);
         let mut sleep_time = 0.0;
-        assert_eq!(
-            evaluate_formula(&mut sheet, 1, 1, "MAX(A1:B1)", &mut sleep_time),
-            CommandStatus::CmdOk
-        );
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(-3));
+        assert_eq!(
+            evaluate_formula(&mut sheet, 1, 1, "MAX(A1:B1)", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(-3));
+    }
+
+    #[test]
+    fn test_evaluate_formula_stdev_large_range() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(1);
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(3);
+        *sheet.get_mut_cell(1, 0) = CellValue::Integer(5);
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            evaluate_formula(&mut sheet, 2, 2, "STDEV(A1:B2)", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+    }
+
+    #[test]
+    fn test_median_recomputes_when_range_changes() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in ["A1=4", "A2=-7", "A3=10", "B1=MEDIAN(A1:A3)"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(4));
+        assert_eq!(sheet.get_cell_meta(0, 1).formula, 15);
+
+        handle_command(&mut sheet, "A1=1/0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Error(ErrorKind::DivZero));
+
+        handle_command(&mut sheet, "A1=-1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(-1));
+
+        #[cfg(feature = "extensions")]
+        assert_eq!(get_formula_string(&sheet, 0, 1), "MEDIAN(A1:A3)");
+    }
+
+    #[test]
+    fn test_product_recomputes_when_range_changes() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in ["A1=3", "B1=-2", "A2=5", "B2=1", "C3=PRODUCT(A1:B2)"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Integer(-30));
+        assert_eq!(sheet.get_cell_meta(2, 2).formula, 35);
+
+        handle_command(&mut sheet, "B2=4", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Integer(-120));
+        handle_command(&mut sheet, "A1=2147483647", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Error(ErrorKind::Overflow));
+        handle_command(&mut sheet, "A1=0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Integer(0));
+
+        #[cfg(feature = "extensions")]
+        assert_eq!(get_formula_string(&sheet, 2, 2), "PRODUCT(A1:B2)");
+    }
+
+    #[test]
+    fn test_spread_recomputes_when_range_changes() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in [
+            "A1=4",
+            "A2=-6",
+            "A3=9",
+            "B1=SPREAD(A1:A3)",
+            "B2=SPREAD(A2:A2)",
+        ] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(sheet.get_cell_meta(0, 1).formula, 45);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(15));
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(0));
+
+        handle_command(&mut sheet, "A2=-30", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(39));
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(0));
+        handle_command(&mut sheet, "A3=1/0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Error(ErrorKind::DivZero));
+
+        #[cfg(feature = "extensions")]
+        assert_eq!(get_formula_string(&sheet, 0, 1), "SPREAD(A1:A3)");
+    }
+
+    #[test]
+    fn test_counta_counts_assigned_cells() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        // Nothing in a fresh sheet is filled
+        for command in ["D1=COUNTA(A1:B3)", "D2=COUNTBLANK(A1:B3)"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Empty);
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(1, 3), CellValue::Integer(6));
+        assert_eq!(sheet.get_cell_meta(0, 3).formula, 55);
+        assert_eq!(sheet.get_cell_meta(1, 3).formula, 65);
+
+        // Zeros, errors and formulas all count as filled
+        for command in ["A1=0", "B2=1/0", "A3=B2+1", "C1=5"] {
+            handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(3));
+        assert_eq!(*sheet.get_cell(1, 3), CellValue::Integer(3));
+
+        // A rejected cycle leaves the cell blank
+        assert_eq!(
+            handle_command(&mut sheet, "B1=COUNTA(A1:B3)", &mut sleep_time),
+            CommandStatus::CircularRef
+        );
+        assert!(!sheet.assigned.contains(&sheet.get_key(0, 1)));
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(3));
+
+        #[cfg(feature = "extensions")]
+        assert_eq!(get_formula_string(&sheet, 1, 3), "COUNTBLANK(A1:B3)");
+    }
+
+    #[test]
+    fn test_sumif_recomputes_when_range_changes() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in ["A1=4", "A2=-7", "A3=10", "B1=SUMIF(A1:A3,>=4)"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(14));
+        let key = sheet.get_key(0, 1);
+        assert_eq!(sheet.criteria[&key].to_string(), ">=4");
+
+        handle_command(&mut sheet, "A2=5", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(19));
+        handle_command(&mut sheet, "A3=1/0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Error(ErrorKind::DivZero));
+
+        #[cfg(feature = "extensions")]
+        assert_eq!(get_formula_string(&sheet, 0, 1), "SUMIF(A1:A3,>=4)");
+
+        // A cycle keeps the old criterion; a different formula drops it
+        assert_eq!(
+            handle_command(&mut sheet, "B1=SUMIF(A1:B1,<0)", &mut sleep_time),
+            CommandStatus::CircularRef
+        );
+        assert_eq!(sheet.criteria[&key].to_string(), ">=4");
+        handle_command(&mut sheet, "B1=SUM(A1:A2)", &mut sleep_time);
+        assert!(sheet.criteria.is_empty());
+    }
+
+    #[test]
+    fn test_roundm_function() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "B1=37", &mut sleep_time);
+        handle_command(&mut sheet, "C1=10", &mut sleep_time);
+        for (command, code, value, formula) in [
+            ("A1=ROUNDM(B1,10)", 132, 40, "ROUNDM(B1,10)"),
+            ("A2=ROUNDM(B1,C1)", 130, 40, "ROUNDM(B1,C1)"),
+            ("A3=ROUNDM(-44,C1)", 133, -40, "ROUNDM(-44,C1)"),
+            ("A4=ROUNDM(B1,-5)", 132, 35, "ROUNDM(B1,-5)"),
+        ] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                command
+            );
+            let (row, col) = parse_cell_reference(&sheet, &command[..2]).unwrap();
+            assert_eq!(sheet.get_cell_meta(row, col).formula, code, "{}", command);
+            assert_eq!(
+                *sheet.get_cell(row, col),
+                CellValue::Integer(value),
+                "{}",
+                command
+            );
+            #[cfg(feature = "extensions")]
+            assert_eq!(get_formula_string(&sheet, row, col), formula);
+            #[cfg(not(feature = "extensions"))]
+            let _ = formula;
+        }
+
+        // Negative values round away from zero and exact multiples stay put
+        handle_command(&mut sheet, "B1=-35", &mut sleep_time);
+        let column: Vec<_> = (0..4).map(|row| sheet.get_cell(row, 0).clone()).collect();
+        assert_eq!(
+            column,
+            [-40, -40, -40, -35].map(CellValue::Integer).to_vec()
+        );
+        handle_command(&mut sheet, "B1=-30", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(-30));
+
+        // A multiple of zero is an error until it changes
+        handle_command(&mut sheet, "C1=0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Error(ErrorKind::DivZero));
+        assert_eq!(*sheet.get_cell(2, 0), CellValue::Error(ErrorKind::DivZero));
+        handle_command(&mut sheet, "C1=4", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(-32));
+        assert_eq!(*sheet.get_cell(2, 0), CellValue::Integer(-44));
+        handle_command(&mut sheet, "A5=ROUNDM(B1,0)", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(4, 0), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_sumproduct_function() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in ["A1=2", "A2=3", "A3=-4", "B1=5", "B2=6", "B3=7"] {
+            handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        assert_eq!(
+            handle_command(&mut sheet, "D1=SUMPRODUCT(A1:A3,B1:B3)", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.get_cell_meta(0, 3).formula, EXTENDED_FORMULA);
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(0));
+        #[cfg(feature = "extensions")]
+        assert_eq!(get_formula_string(&sheet, 0, 3), "SUMPRODUCT(A1:A3,B1:B3)");
+
+        // Edits in either range recompute it, and an error cell makes it an error
+        handle_command(&mut sheet, "A3=4", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(56));
+        handle_command(&mut sheet, "B2=-1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(35));
+        handle_command(&mut sheet, "B1=1/0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Error(ErrorKind::DivZero));
+        handle_command(&mut sheet, "B1=1073741824", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Error(ErrorKind::Overflow));
+        handle_command(&mut sheet, "B1=5", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(35));
+
+        // Ranges of different shapes are rejected, and cycles through a range are caught
+        assert_eq!(
+            handle_command(&mut sheet, "D2=SUMPRODUCT(A1:A3,B1:B2)", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "D2=SUMPRODUCT(A1:B1,C1:D1)", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "A1=D2", &mut sleep_time),
+            CommandStatus::CircularRef
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(2));
+
+        // Another formula drops both range dependencies
+        handle_command(&mut sheet, "D1=1", &mut sleep_time);
+        handle_command(&mut sheet, "D2=1", &mut sleep_time);
+        assert!(sheet.range_children.is_empty());
+        assert!(sheet.extended_formulas.is_empty());
+    }
+
+    #[test]
+    fn test_range_function_in_arithmetic() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in [
+            "B1=1",
+            "B2=2",
+            "B3=3",
+            "C1=4",
+            "A1=SUM(B1:B3)+10",
+            "A2=C1*MAX(B1:B3)",
+        ] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(sheet.get_cell_meta(0, 0).formula, EXTENDED_FORMULA);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(16));
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(12));
+        #[cfg(feature = "extensions")]
+        {
+            assert_eq!(get_formula_string(&sheet, 0, 0), "SUM(B1:B3)+10");
+            assert_eq!(get_formula_string(&sheet, 1, 0), "C1*MAX(B1:B3)");
+        }
+
+        // The range and the other operand both recompute it
+        handle_command(&mut sheet, "B3=7", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(20));
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(28));
+        handle_command(&mut sheet, "C1=-1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(-7));
+        handle_command(&mut sheet, "B2=1/0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Error(ErrorKind::DivZero));
+        handle_command(&mut sheet, "B2=2", &mut sleep_time);
+        assert_eq!(
+            handle_command(&mut sheet, "A3=100/COUNTBLANK(D1:D2)", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(2, 0), CellValue::Integer(50));
+        handle_command(&mut sheet, "D1=0", &mut sleep_time);
+        handle_command(&mut sheet, "D2=0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(2, 0), CellValue::Error(ErrorKind::DivZero));
+
+        // Cycles through the range or the operand are refused and leave the formula intact
+        for command in ["B1=A1", "C1=A2+1"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CircularRef
+            );
+        }
+        handle_command(&mut sheet, "B1=2", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(21));
+
+        // Another formula drops every dependency
+        for command in ["A1=1", "A2=1", "A3=1"] {
+            handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        assert!(sheet.range_children.is_empty());
+        assert!(sheet.extended_formulas.is_empty());
+    }
+
+    #[test]
+    fn test_arithmetic_precedence_and_parentheses() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in ["B1=6", "C1=4", "D1=3"] {
+            handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        for (expr, expected) in [
+            ("B1+C1*2", 14),
+            ("(B1+C1)/2", 5),
+            ("B1-C1-D1", -1),
+            ("B1-(C1-D1)", 5),
+            ("2^D1^2", 512),
+            ("(2^D1)^2", 64),
+            ("B1*(C1+D1)%5", 2),
+            ("-10/(B1-C1)", -5),
+        ] {
+            assert_eq!(
+                set_cell_value(&mut sheet, 0, 0, expr, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                expr
+            );
+            assert_eq!(
+                *sheet.get_cell(0, 0),
+                CellValue::Integer(expected),
+                "{}",
+                expr
+            );
+            // The formula reads back as it was typed, parentheses included
+            #[cfg(feature = "extensions")]
+            assert_eq!(get_formula_string(&sheet, 0, 0), expr);
+        }
+
+        // Every referenced cell is a parent, and each one recomputes the formula
+        handle_command(&mut sheet, "A1=(B1+C1)*D1-B1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(24));
+        let key = sheet.get_key(0, 0);
+        for parent in [(0, 1), (0, 2), (0, 3)] {
+            let parent_key = sheet.get_key(parent.0, parent.1);
+            assert!(sheet.get_cell_children(parent_key).unwrap().contains(&key));
+        }
+        handle_command(&mut sheet, "D1=1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(4));
+        handle_command(&mut sheet, "C1=0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(0));
+        handle_command(&mut sheet, "A2=B1/(C1*D1)", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Error(ErrorKind::DivZero));
+
+        // A cycle through any of the parents is refused, however deep in the tree
+        for command in ["D1=A1", "C1=A2+1", "E1=(B1+1)*(2+E1)"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CircularRef,
+                "{}",
+                command
+            );
+        }
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(0));
+        assert!(sheet.get_cell_children(key).is_none());
+    }
+
+    #[test]
+    fn test_chained_operators_restore_on_cycle() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in ["B1=1", "C1=2", "D1=3", "A1=B1+C1+D1-1", "E1=A1*2"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(5));
+        assert_eq!(*sheet.get_cell(0, 4), CellValue::Integer(10));
+        let key = sheet.get_key(0, 0);
+        let children_of = |sheet: &Spreadsheet, col: i16| {
+            sheet
+                .get_cell_children(sheet.get_key(0, col))
+                .is_some_and(|children| children.contains(&key))
+        };
+
+        // One cyclic parent among many refuses the whole formula and restores the old one
+        for command in ["A1=B1+E1+D1", "A1=E1+1", "A1=SUM(B1:E1)-B1"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CircularRef,
+                "{}",
+                command
+            );
+            assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(5));
+            assert!((1..=3).all(|col| children_of(&sheet, col)), "{}", command);
+            assert!(!children_of(&sheet, 4), "{}", command);
+            assert!(sheet.range_children.is_empty(), "{}", command);
+            #[cfg(feature = "extensions")]
+            assert_eq!(get_formula_string(&sheet, 0, 0), "B1+C1+D1-1");
+        }
+
+        // The restored formula still follows every parent
+        handle_command(&mut sheet, "D1=13", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(15));
+        assert_eq!(*sheet.get_cell(0, 4), CellValue::Integer(30));
+    }
+
+    #[test]
+    fn test_unary_minus() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in ["B1=6", "C1=4"] {
+            handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        for (expr, expected) in [
+            ("-B1", -6),
+            ("-5+B1", 1),
+            ("B1*-2", -12),
+            ("B1*-C1", -24),
+            ("C1--B1", 10),
+            ("-(B1+C1)", -10),
+            ("-(-B1)", 6),
+            ("-B1^2", 36),
+            ("-SUM(B1:C1)/2", -5),
+        ] {
+            assert_eq!(
+                set_cell_value(&mut sheet, 0, 0, expr, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                expr
+            );
+            assert_eq!(
+                *sheet.get_cell(0, 0),
+                CellValue::Integer(expected),
+                "{}",
+                expr
+            );
+            #[cfg(feature = "extensions")]
+            assert_eq!(get_formula_string(&sheet, 0, 0), expr);
+        }
+
+        // The negation is kept with the formula and reapplied when the cell changes
+        handle_command(&mut sheet, "A1=-B1", &mut sleep_time);
+        handle_command(&mut sheet, "B1=-7", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(7));
+        handle_command(&mut sheet, "B1=-2147483648", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Error(ErrorKind::Overflow));
+        assert_eq!(
+            handle_command(&mut sheet, "B1=-A1", &mut sleep_time),
+            CommandStatus::CircularRef
+        );
+    }
+
+    #[test]
+    fn test_lowercase_functions_and_cells() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in ["b1=2", "C1=3"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(
+            handle_command(&mut sheet, "a1=sum(b1:c1)", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(5));
+        handle_command(&mut sheet, "c1=10", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(12));
+        #[cfg(feature = "extensions")]
+        {
+            assert_eq!(get_formula_string(&sheet, 0, 0), "SUM(B1:C1)");
+            // Names keep their case
+            handle_command(&mut sheet, "name B1:C1 Data", &mut sleep_time);
+            assert_eq!(
+                handle_command(&mut sheet, "A2=max(Data)", &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+            assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(10));
+            assert_eq!(
+                handle_command(&mut sheet, "A2=max(data)", &mut sleep_time),
+                CommandStatus::Unrecognized
+            );
+        }
+    }
+
+    #[test]
+    fn test_whole_column_and_row_ranges() {
+        let mut sheet = create_test_spreadsheet(999, 5);
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "A1=SUM(B:B)", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(0));
+        // Values entered anywhere in the column, down to the last row, reach the sum
+        handle_command(&mut sheet, "B500=7", &mut sleep_time);
+        handle_command(&mut sheet, "B999=3", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(10));
+
+        assert_eq!(
+            handle_command(&mut sheet, "A2=MAX(3:3)+1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        handle_command(&mut sheet, "E3=41", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(42));
+        assert_eq!(
+            handle_command(&mut sheet, "C3=COUNTA(3:3)", &mut sleep_time),
+            CommandStatus::CircularRef
+        );
+        // A column off the sheet fails as a cell off the sheet does
+        assert_eq!(
+            handle_command(&mut sheet, "C1=SUM(B:F)", &mut sleep_time),
+            handle_command(&mut sheet, "C1=SUM(B1:F1)", &mut sleep_time)
+        );
+        #[cfg(feature = "extensions")]
+        {
+            assert_eq!(get_formula_string(&sheet, 0, 0), "SUM(B1:B999)");
+            // A named range may stand for a whole column
+            handle_command(&mut sheet, "name B:B totals", &mut sleep_time);
+            handle_command(&mut sheet, "D1=AVG(totals)", &mut sleep_time);
+            handle_command(&mut sheet, "B1=989", &mut sleep_time);
+            // Only the three filled cells of the column count toward the mean
+            assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(333));
+            assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(999));
+        }
+    }
+
+    #[test]
+    fn test_ranges_in_any_corner_order() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in ["A1=1", "B2=2", "C3=3"] {
+            handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        for (command, expected) in [("D1=SUM(C3:A1)", 6), ("D1=MAX(A3:C1)", 3)] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+            assert_eq!(
+                *sheet.get_cell(0, 3),
+                CellValue::Integer(expected),
+                "{}",
+                command
+            );
+        }
+        handle_command(&mut sheet, "A3=4", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(4));
+        #[cfg(feature = "extensions")]
+        {
+            assert_eq!(get_formula_string(&sheet, 0, 3), "MAX(A1:C3)");
+            assert_eq!(
+                handle_command(&mut sheet, "lock_cell B5:A1", &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+            assert!(sheet.is_cell_locked(0, 0) && sheet.is_cell_locked(4, 1));
+            assert!(!sheet.is_cell_locked(0, 2));
+        }
     }
 
     #[test]
-    fn test_evaluate_formula_stdev_large_range() {
+    fn test_if_function() {
         let mut sheet = create_test_spreadsheet(5, 5);
-        *sheet.get_mut_cell(0, 0) = CellValue::Integer(1);
-        *sheet.get_mut_cell(0, 1) = CellValue::Integer(3);
-        *sheet.get_mut_cell(1, 0) = CellValue::Integer(5);
         let mut sleep_time = 0.0;
+        for command in ["B1=12", "C1=7", "A1=IF(B1>10,100,C1)"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        let key = sheet.get_key(0, 0);
+        assert_eq!(sheet.get_cell_meta(0, 0).formula, EXTENDED_FORMULA);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(100));
+        #[cfg(feature = "extensions")]
+        assert_eq!(get_formula_string(&sheet, 0, 0), "IF(B1>10,100,C1)");
+
+        // Every cell the IF reads, in the condition or an outcome, updates it
+        handle_command(&mut sheet, "B1=10", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(7));
+        handle_command(&mut sheet, "C1=-3", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(-3));
+        handle_command(&mut sheet, "C1=1/0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Error(ErrorKind::DivZero));
+        // An error in the outcome not taken is ignored, one in the condition is not
+        handle_command(&mut sheet, "B1=11", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(100));
+        handle_command(&mut sheet, "B1=1/0", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Error(ErrorKind::DivZero));
+
+        // A cycle through any input keeps the old formula and its edges
+        handle_command(&mut sheet, "B1=11", &mut sleep_time);
+        handle_command(&mut sheet, "D1=A1+1", &mut sleep_time);
         assert_eq!(
-            evaluate_formula(&mut sheet, 2, 2, "STDEV(A1:B2)", &mut sleep_time),
+            handle_command(&mut sheet, "A1=IF(B1=C1,0,D1)", &mut sleep_time),
+            CommandStatus::CircularRef
+        );
+        assert!(matches!(
+            sheet.extended_formulas[&key],
+            ExtendedFormula::If(IfFormula {
+                then: Term::Number(100),
+                ..
+            })
+        ));
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(101));
+        handle_command(&mut sheet, "C1=5", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(100));
+        handle_command(&mut sheet, "B1=1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(6));
+
+        // Another formula drops the IF and its edges
+        handle_command(&mut sheet, "A1=C1*2", &mut sleep_time);
+        assert!(sheet.extended_formulas.is_empty());
+        assert!(sheet.get_cell_children(sheet.get_key(0, 1)).is_none());
+        assert_ne!(
+            handle_command(&mut sheet, "A1=IF(B1>0,Z9,0)", &mut sleep_time),
             CommandStatus::CmdOk
         );
+        assert_eq!(sheet.get_cell_meta(0, 0).formula, 42);
     }
 
     #[test]
     fn test_sleep_error() {
         let mut sheet = create_test_spreadsheet(5, 5);
-        *sheet.get_mut_cell(0, 0) = CellValue::Error;
+        *sheet.get_mut_cell(0, 0) = CellValue::Error(ErrorKind::DivZero);
         let mut sleep_time = 0.0;
         assert_eq!(
             handle_sleep(&mut sheet, 1, 1, "A1", &mut sleep_time),
@@ -1399,8 +3089,37 @@ mod tests {
     #[test]
     fn test_wrong_cell_reference() {
         let mut sheet = create_test_spreadsheet(5, 5);
+        // A reference off the sheet is an invalid cell where the build can say so
+        #[cfg(feature = "extensions")]
+        let off_sheet = CommandStatus::InvalidCell;
+        #[cfg(not(feature = "extensions"))]
+        let off_sheet = CommandStatus::Unrecognized;
+        assert_eq!(evaluate_arithmetic(&mut sheet, 0, 0, "ZZZ999",), off_sheet);
+        assert_eq!(evaluate_arithmetic(&mut sheet, 0, 0, "E6+1"), off_sheet);
+        assert_eq!(evaluate_arithmetic(&mut sheet, 0, 0, "F1"), off_sheet);
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "F1=5", &mut sleep_time),
+            off_sheet
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "A999=5", &mut sleep_time),
+            off_sheet
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "A1=SUM(A1:E6)", &mut sleep_time),
+            off_sheet
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "A1=MAX(F1:F2)", &mut sleep_time),
+            off_sheet
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "scroll_to Z9", &mut sleep_time),
+            off_sheet
+        );
         assert_eq!(
-            evaluate_arithmetic(&mut sheet, 0, 0, "ZZZ999",),
+            handle_command(&mut sheet, "A1+1", &mut sleep_time),
             CommandStatus::Unrecognized
         );
     }
@@ -1408,26 +3127,476 @@ mod tests {
     #[test]
     fn left_and_right_val() {
         let mut sheet = create_test_spreadsheet(5, 5);
-        *sheet.get_mut_cell(0, 1) = CellValue::Error;
+        *sheet.get_mut_cell(0, 1) = CellValue::Error(ErrorKind::DivZero);
         let mut sleep_time = 0.0;
         assert_eq!(
             evaluate_formula(&mut sheet, 1, 1, "4+B1", &mut sleep_time),
             CommandStatus::CmdOk
         );
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero));
     }
 
     #[test]
     fn left_error_right_cell() {
         let mut sheet = create_test_spreadsheet(5, 5);
-        *sheet.get_mut_cell(0, 0) = CellValue::Error;
+        *sheet.get_mut_cell(0, 0) = CellValue::Error(ErrorKind::DivZero);
         *sheet.get_mut_cell(0, 1) = CellValue::Integer(1);
         let mut sleep_time = 0.0;
         assert_eq!(
             evaluate_formula(&mut sheet, 1, 1, "A1+B1", &mut sleep_time),
             CommandStatus::CmdOk
         );
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_failed_assignments_leave_no_meta() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for expr in [
+            "A1+",
+            "A1+Z9",
+            "SUM(A1:Z9)",
+            "MAX(A1",
+            "B2",
+            "B2*2",
+            "SLEEP(B2)",
+        ] {
+            assert_ne!(
+                set_cell_value(&mut sheet, 1, 1, expr, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                expr
+            );
+            assert!(
+                !sheet.cell_meta.contains_key(&sheet.get_key(1, 1)),
+                "{}",
+                expr
+            );
+        }
+        assert!(sheet.cell_meta.is_empty());
+        assert!(sheet.children.is_empty());
+
+        // A failed edit keeps the old formula exactly
+        set_cell_value(&mut sheet, 1, 1, "A1*3", &mut sleep_time);
+        set_cell_value(&mut sheet, 1, 1, "B2+1", &mut sleep_time);
+        let meta = sheet.cell_meta[&sheet.get_key(1, 1)];
+        assert_eq!((meta.formula, meta.parent1, meta.parent2), (42, 0, 3));
+        assert_eq!(sheet.cell_meta.len(), 1);
+    }
+
+    /// Times 100k assignments feeding a small dependent graph.
+    ///
+    /// Run with `cargo test --release bench_assignments -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_assignments() {
+        let mut sheet = create_test_spreadsheet(100, 10);
+        let mut sleep_time = 0.0;
+        for (row, col, expr) in [(0, 1, "A1+1"), (0, 2, "SUM(A1:A100)"), (0, 3, "B1*2")] {
+            set_cell_value(&mut sheet, row, col, expr, &mut sleep_time);
+        }
+        let exprs: Vec<String> = (0..997).map(|i| i.to_string()).collect();
+        let start = std::time::Instant::now();
+        for i in 0..100_000 {
+            let status = set_cell_value(
+                &mut sheet,
+                (i % 100) as i16,
+                0,
+                &exprs[i % 997],
+                &mut sleep_time,
+            );
+            assert_eq!(status, CommandStatus::CmdOk);
+        }
+        println!("100k assignments: {:?}", start.elapsed());
+    }
+
+    /// One expression's outcome when assigned to E5 of `golden_sheet`: the status, the
+    /// cell's value, its formula metadata, its reference flags and the sleep time.
+    type Golden = (
+        &'static str,
+        &'static str,
+        &'static str,
+        Option<(i16, i32, i32)>,
+        u8,
+        f64,
+    );
+
+    /// Every expression form from the unit tests, as the hand-written parsers evaluated
+    /// them before `expr_parser` replaced them.
+    const GOLDENS: &[Golden] = &[
+        (" MAX(A1:B1) ", "ok", "5", Some((8, 0, 1)), 0, 0.0),
+        ("\"Revenue\"", "ok", "\"Revenue\"", None, 0, 0.0),
+        ("\"open", "unrecognized_cmd", "", None, 0, 0.0),
+        ("$$A1+1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("$AB", "unrecognized_cmd", "", None, 0, 0.0),
+        ("$missing+1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("$row_", "unrecognized_cmd", "", None, 0, 0.0),
+        ("$x", "unrecognized_cmd", "", None, 0, 0.0),
+        ("(5)", "ok", "5", None, 0, 0.0),
+        ("(A1+B1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("(A1+B1)/2", "ok", "4", Some((4, -1, -1)), 0, 0.0),
+        ("*5", "unrecognized_cmd", "", None, 0, 0.0),
+        ("+5", "ok", "5", None, 0, 0.0),
+        ("+B1", "ok", "3", Some((82, 1, -1)), 0, 0.0),
+        ("- B1", "ok", "-3", Some((4, -1, -1)), 0, 0.0),
+        ("--5", "ok", "5", Some((4, -1, -1)), 0, 0.0),
+        ("--B1", "ok", "3", Some((4, -1, -1)), 0, 0.0),
+        ("-+1", "ok", "-1", Some((4, -1, -1)), 0, 0.0),
+        ("-5", "ok", "-5", None, 0, 0.0),
+        ("-5-3", "ok", "-8", Some((20, -5, 3)), 0, 0.0),
+        ("-A1", "ok", "-5", Some((4, -1, -1)), 0, 0.0),
+        ("0", "ok", "0", None, 0, 0.0),
+        ("1", "ok", "1", None, 0, 0.0),
+        ("1/0", "ok", "#DIV0", Some((30, 1, 0)), 0, 0.0),
+        ("10", "ok", "10", None, 0, 0.0),
+        ("100", "ok", "100", None, 0, 0.0),
+        ("101", "ok", "101", None, 0, 0.0),
+        ("120", "ok", "120", None, 0, 0.0),
+        ("123456789", "ok", "123456789", None, 0, 0.0),
+        ("15", "ok", "15", None, 0, 0.0),
+        ("150", "ok", "150", None, 0, 0.0),
+        ("2", "ok", "2", None, 0, 0.0),
+        ("20", "ok", "20", None, 0, 0.0),
+        ("200", "ok", "200", None, 0, 0.0),
+        ("2^3^2", "ok", "512", Some((4, -1, -1)), 0, 0.0),
+        ("3", "ok", "3", None, 0, 0.0),
+        ("30", "ok", "30", None, 0, 0.0),
+        ("3<=B1", "ok", "1", Some((173, 3, 1)), 0, 0.0),
+        ("4", "ok", "4", None, 0, 0.0),
+        ("4+B1", "ok", "7", Some((13, 4, 1)), 0, 0.0),
+        ("42", "ok", "42", None, 0, 0.0),
+        ("5", "ok", "5", None, 0, 0.0),
+        ("5 6", "unrecognized_cmd", "", None, 0, 0.0),
+        ("5*", "unrecognized_cmd", "", None, 0, 0.0),
+        ("5*+3", "ok", "15", Some((40, 5, 3)), 0, 0.0),
+        ("5+", "unrecognized_cmd", "", None, 0, 0.0),
+        ("5-", "unrecognized_cmd", "", None, 0, 0.0),
+        ("5--3", "ok", "8", Some((20, 5, -3)), 0, 0.0),
+        ("5/0", "ok", "#DIV0", Some((30, 5, 0)), 0, 0.0),
+        ("50", "ok", "50", None, 0, 0.0),
+        ("6", "ok", "6", None, 0, 0.0),
+        ("7", "ok", "7", None, 0, 0.0),
+        ("777", "ok", "777", None, 0, 0.0),
+        ("80", "ok", "80", None, 0, 0.0),
+        ("888", "ok", "888", None, 0, 0.0),
+        ("9", "ok", "9", None, 0, 0.0),
+        ("90", "ok", "90", None, 0, 0.0),
+        ("999", "ok", "999", None, 0, 0.0),
+        ("99999999999", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A 1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A1", "ok", "5", Some((82, 0, -1)), 0, 0.0),
+        ("A1 + B1", "ok", "8", Some((10, 0, 1)), 0, 0.0),
+        ("A1!=B1", "ok", "1", Some((190, 0, 1)), 0, 0.0),
+        ("A1$", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A1*-3", "ok", "-15", Some((42, 0, -3)), 0, 0.0),
+        ("A1*10", "ok", "50", Some((42, 0, 10)), 0, 0.0),
+        ("A1*2", "ok", "10", Some((42, 0, 2)), 0, 0.0),
+        ("A1*3", "ok", "15", Some((42, 0, 3)), 0, 0.0),
+        ("A1*B1", "ok", "15", Some((40, 0, 1)), 0, 0.0),
+        ("A1*B1*C1", "ok", "0", Some((4, -1, -1)), 0, 0.0),
+        ("A1+ 3", "ok", "8", Some((12, 0, 3)), 0, 0.0),
+        ("A1+$threshold", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A1++1", "ok", "6", Some((12, 0, 1)), 0, 0.0),
+        ("A1+1", "ok", "6", Some((12, 0, 1)), 0, 0.0),
+        ("A1+1>B1*2", "ok", "0", Some((4, -1, -1)), 0, 0.0),
+        ("A1+3", "ok", "8", Some((12, 0, 3)), 0, 0.0),
+        ("A1+99999999999", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A1+A10", "ok", "5", Some((10, 0, 54)), 0, 0.0),
+        ("A1+A2", "ok", "3", Some((10, 0, 6)), 0, 0.0),
+        ("A1+B1", "ok", "8", Some((10, 0, 1)), 0, 0.0),
+        ("A1+B1*2", "ok", "11", Some((4, -1, -1)), 0, 0.0),
+        ("A1--5", "ok", "10", Some((22, 0, -5)), 0, 0.0),
+        ("A1-4", "ok", "1", Some((22, 0, 4)), 0, 0.0),
+        ("A1-5", "ok", "0", Some((22, 0, 5)), 0, 0.0),
+        ("A1.5", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A1/", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A1/0", "ok", "#DIV0", Some((32, 0, 0)), 0, 0.0),
+        ("A1/B1", "ok", "1", Some((30, 0, 1)), 0, 0.0),
+        ("A1/B2", "ok", "#DIV0", Some((30, 0, 7)), 0, 0.0),
+        ("A1:B2", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A1=+B1", "ok", "0", Some((180, 0, 1)), 0, 0.0),
+        ("A1=5", "ok", "1", Some((182, 0, 5)), 0, 0.0),
+        ("A1>B1", "ok", "1", Some((140, 0, 1)), 0, 0.0),
+        ("A1>B1>0", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A2*10", "ok", "-20", Some((42, 6, 10)), 0, 0.0),
+        ("A2*2", "ok", "-4", Some((42, 6, 2)), 0, 0.0),
+        ("A2-B2", "ok", "-2", Some((20, 6, 7)), 0, 0.0),
+        ("A2>=0", "ok", "0", Some((162, 6, 0)), 0, 0.0),
+        ("A3", "ok", "#DIV0", Some((82, 12, -1)), 0, 0.0),
+        ("A3*10", "ok", "#DIV0", Some((42, 12, 10)), 0, 0.0),
+        ("A3+1", "ok", "#DIV0", Some((12, 12, 1)), 0, 0.0),
+        ("A3-B3", "ok", "#DIV0", Some((20, 12, 13)), 0, 0.0),
+        ("A3<1", "ok", "#DIV0", Some((152, 12, 1)), 0, 0.0),
+        ("A4-B4", "ok", "0", Some((20, 18, 19)), 0, 0.0),
+        ("ABCD1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("AVG(A1:A1)", "ok", "5", Some((6, 0, 0)), 0, 0.0),
+        ("AVG(A1:B1)", "ok", "4", Some((6, 0, 1)), 0, 0.0),
+        ("B1", "ok", "3", Some((82, 1, -1)), 0, 0.0),
+        ("B1*2", "ok", "6", Some((42, 1, 2)), 0, 0.0),
+        ("B1+1", "ok", "4", Some((12, 1, 1)), 0, 0.0),
+        ("B1+A1", "ok", "8", Some((10, 1, 0)), 0, 0.0),
+        ("B1+E1", "ok", "3", Some((10, 1, 4)), 0, 0.0),
+        ("B1/B2", "ok", "#DIV0", Some((30, 1, 7)), 0, 0.0),
+        ("B2", "ok", "0", Some((82, 7, -1)), 0, 0.0),
+        ("B2+1", "ok", "1", Some((12, 7, 1)), 0, 0.0),
+        ("C1", "ok", "0", Some((82, 2, -1)), 0, 0.0),
+        ("C1*2", "ok", "0", Some((42, 2, 2)), 0, 0.0),
+        ("C1+1", "ok", "1", Some((12, 2, 1)), 0, 0.0),
+        ("C2", "ok", "0", Some((82, 8, -1)), 0, 0.0),
+        ("C2+C3", "ok", "7", Some((10, 8, 14)), 0, 0.0),
+        ("E5", "circular_ref", "", None, 0, 0.0),
+        ("E5+1", "circular_ref", "", None, 0, 0.0),
+        ("E5>1", "circular_ref", "", None, 0, 0.0),
+        ("INVALID", "unrecognized_cmd", "", None, 0, 0.0),
+        ("MAX(A1:B1)", "ok", "5", Some((8, 0, 1)), 0, 0.0),
+        ("MIN(A1:B1)", "ok", "3", Some((7, 0, 1)), 0, 0.0),
+        ("MIN(A3:B3)", "ok", "#DIV0", Some((7, 12, 13)), 0, 0.0),
+        ("R", "unrecognized_cmd", "", None, 0, 0.0),
+        ("R1C1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC[-4]:A1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC[1]x", "unrecognized_cmd", "", None, 0, 0.0),
+        ("R[ 1]C", "unrecognized_cmd", "", None, 0, 0.0),
+        ("R[-1C+1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SLEEP($A$1)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SLEEP()", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SLEEP(-1)", "ok", "-1", None, 0, 0.0),
+        ("SLEEP(5)", "ok", "5", None, 0, 5.0),
+        ("SLEEP(5+1)", "ok", "6", Some((4, -1, -1)), 0, 6.0),
+        ("SLEEP(A1)", "ok", "5", Some((102, 0, -1)), 0, 5.0),
+        ("SLEEP(A1+B1)", "ok", "8", Some((4, -1, -1)), 0, 8.0),
+        ("SLEEP(A2*3)", "ok", "-6", Some((4, -1, -1)), 0, 0.0),
+        ("SLEEP(E5)", "circular_ref", "", None, 0, 0.0),
+        ("SLEEP(E5+1)", "circular_ref", "", None, 0, 0.0),
+        ("SLEEP(RC)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SLEEP(SUM(A1:B2))", "ok", "6", Some((4, -1, -1)), 0, 6.0),
+        ("SLEEP(SUM(A1:E5))", "circular_ref", "", None, 0, 0.0),
+        ("STDEV(A1:B1)", "ok", "1", Some((9, 0, 1)), 0, 0.0),
+        ("STDEV(A1:B2)", "ok", "3", Some((9, 0, 7)), 0, 0.0),
+        ("SUM (A1:B2)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM( A1 : B2 )", "ok", "6", Some((5, 0, 7)), 0, 0.0),
+        ("SUM((A1:B2))", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM()", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(A1)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(A1:A3)", "ok", "#DIV0", Some((5, 0, 12)), 0, 0.0),
+        ("SUM(A1:A6)", "ok", "#DIV0", Some((5, 0, 30)), 0, 0.0),
+        ("SUM(A1:B1)", "ok", "8", Some((5, 0, 1)), 0, 0.0),
+        ("SUM(A1:B2", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(A1:B2)+1", "ok", "7", Some((4, -1, -1)), 0, 0.0),
+        ("SUM(A1:B2)=6", "ok", "1", Some((4, -1, -1)), 0, 0.0),
+        ("SUM(A1:B2:C3)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(A1:B3)", "ok", "#DIV0", Some((5, 0, 13)), 0, 0.0),
+        ("SUM(A1:E5)<1", "circular_ref", "", None, 0, 0.0),
+        ("SUM(A1:J10)x", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(A2:A4)", "ok", "#DIV0", Some((5, 6, 18)), 0, 0.0),
+        ("SUM(B2:A1)", "ok", "6", Some((5, 0, 7)), 0, 0.0),
+        ("SUM(C1:C3)", "ok", "7", Some((5, 2, 14)), 0, 0.0),
+        ("SUM(RC[-1])", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(R[-4]C[-4]:one)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUMX(A1:B2)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("a1", "ok", "5", Some((82, 0, -1)), 0, 0.0),
+        ("data", "unrecognized_cmd", "", None, 0, 0.0),
+        ("sum(A1:B2)", "ok", "6", Some((5, 0, 7)), 0, 0.0),
+    ];
+
+    /// Goldens whose outcome depends on whether extensions are built in.
+    #[cfg(not(feature = "extensions"))]
+    const BUILD_GOLDENS: &[Golden] = &[
+        ("$A$1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("$A$1*RC[-4]", "unrecognized_cmd", "", None, 0, 0.0),
+        ("$A$1+$A1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("$A$1+B1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("$A1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("$A1+1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("$A1+B$1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("1/Y1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("5*R[-2]C[-3]", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A$1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A1+1!", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A1-RC[-1]", "unrecognized_cmd", "", None, 0, 0.0),
+        ("A1/Y1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("AN25+1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("AVG(R[-2]C:R[-1]C)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("AVG(data)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("G1-F1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("MAX($A$1:B1)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("MAX(A1:J10)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC[-1]", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC[-1]+1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC[-2]*2", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC[-2]+B1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC[-2]+R[-1]C", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC[-2]+R[-1]C[-1]", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC[-3]-1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("RC[-9]", "unrecognized_cmd", "", None, 0, 0.0),
+        ("R[+1]C", "unrecognized_cmd", "", None, 0, 0.0),
+        ("R[-1]C-1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("R[-4]C[-4]", "unrecognized_cmd", "", None, 0, 0.0),
+        ("R[-9]C+1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("R[5]C+1", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SLEEP(one)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("STDEV(data)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM($A$1:B$2)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(A1:A100)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(A1:B2)!", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(A1:J10)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(A1:J10)!", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(A1:L15)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(A1:S19)", "unrecognized_cmd", "", None, 0, 0.0),
+        (
+            "SUM(R[-1]C[-2]:R[-1]C[-1])",
+            "unrecognized_cmd",
+            "",
+            None,
+            0,
+            0.0,
+        ),
+        ("SUM(R[1]C:RC)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(data)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("SUM(one)", "unrecognized_cmd", "", None, 0, 0.0),
+        ("ZZZ999", "unrecognized_cmd", "", None, 0, 0.0),
+        ("one", "unrecognized_cmd", "", None, 0, 0.0),
+        ("one+1", "unrecognized_cmd", "", None, 0, 0.0),
+    ];
+
+    /// Goldens whose outcome depends on whether extensions are built in.
+    #[cfg(feature = "extensions")]
+    const BUILD_GOLDENS: &[Golden] = &[
+        ("$A$1", "ok", "5", Some((82, 0, -1)), 20, 0.0),
+        ("$A$1*RC[-4]", "ok", "0", Some((40, 0, 24)), 22, 0.0),
+        ("$A$1+$A1", "ok", "10", Some((10, 0, 0)), 28, 0.0),
+        ("$A$1+B1", "ok", "8", Some((10, 0, 1)), 20, 0.0),
+        ("$A1", "ok", "5", Some((82, 0, -1)), 4, 0.0),
+        ("$A1+1", "ok", "6", Some((12, 0, 1)), 4, 0.0),
+        ("$A1+B$1", "ok", "8", Some((10, 0, 1)), 36, 0.0),
+        ("1/Y1", "invalid_cell", "", None, 0, 0.0),
+        ("5*R[-2]C[-3]", "ok", "0", Some((43, 5, 13)), 2, 0.0),
+        ("A$1", "ok", "5", Some((82, 0, -1)), 16, 0.0),
+        ("A1+1!", "ok", "6", Some((12, 0, 1)), 0, 0.0),
+        ("A1-RC[-1]", "ok", "5", Some((20, 0, 27)), 2, 0.0),
+        ("A1/Y1", "invalid_cell", "", None, 0, 0.0),
+        ("AN25+1", "invalid_cell", "", None, 0, 0.0),
+        (
+            "AVG(R[-2]C:R[-1]C)",
+            "ok",
+            "#DIV0",
+            Some((6, 16, 22)),
+            3,
+            0.0,
+        ),
+        ("AVG(data)", "ok", "1", Some((6, 0, 7)), 0, 0.0),
+        ("G1-F1", "invalid_cell", "", None, 0, 0.0),
+        ("MAX($A$1:B1)", "ok", "5", Some((8, 0, 1)), 20, 0.0),
+        ("MAX(A1:J10)", "invalid_cell", "", None, 0, 0.0),
+        ("RC", "circular_ref", "", None, 0, 0.0),
+        ("RC1", "invalid_cell", "", None, 0, 0.0),
+        ("RC[-1]", "ok", "0", Some((82, 27, -1)), 1, 0.0),
+        ("RC[-1]+1", "ok", "1", Some((12, 27, 1)), 1, 0.0),
+        ("RC[-2]*2", "ok", "0", Some((42, 26, 2)), 1, 0.0),
+        ("RC[-2]+B1", "ok", "3", Some((10, 26, 1)), 1, 0.0),
+        ("RC[-2]+R[-1]C", "ok", "0", Some((10, 26, 22)), 3, 0.0),
+        ("RC[-2]+R[-1]C[-1]", "ok", "0", Some((10, 26, 21)), 3, 0.0),
+        ("RC[-3]-1", "ok", "-1", Some((22, 25, 1)), 1, 0.0),
+        ("RC[-9]", "invalid_cell", "", None, 0, 0.0),
+        ("R[+1]C", "ok", "0", Some((82, 34, -1)), 1, 0.0),
+        ("R[-1]C-1", "ok", "-1", Some((22, 22, 1)), 1, 0.0),
+        ("R[-4]C[-4]", "ok", "5", Some((82, 0, -1)), 1, 0.0),
+        ("R[-9]C+1", "invalid_cell", "", None, 0, 0.0),
+        ("R[5]C+1", "ok", "1", Some((12, 58, 1)), 1, 0.0),
+        ("SLEEP(one)", "ok", "3", Some((102, 1, -1)), 0, 3.0),
+        ("STDEV(data)", "ok", "3", Some((9, 0, 7)), 0, 0.0),
+        ("SUM($A$1:B$2)", "ok", "6", Some((5, 0, 7)), 52, 0.0),
+        ("SUM(A1:A100)", "invalid_cell", "", None, 0, 0.0),
+        ("SUM(A1:B2)!", "ok", "6", Some((5, 0, 7)), 0, 0.0),
+        ("SUM(A1:J10)", "invalid_cell", "", None, 0, 0.0),
+        ("SUM(A1:J10)!", "invalid_cell", "", None, 0, 0.0),
+        ("SUM(A1:L15)", "invalid_cell", "", None, 0, 0.0),
+        ("SUM(A1:S19)", "invalid_cell", "", None, 0, 0.0),
+        (
+            "SUM(R[-1]C[-2]:R[-1]C[-1])",
+            "ok",
+            "0",
+            Some((5, 20, 21)),
+            3,
+            0.0,
+        ),
+        ("SUM(R[1]C:RC)", "invalid_cell", "", None, 0, 0.0),
+        ("SUM(data)", "ok", "6", Some((5, 0, 7)), 0, 0.0),
+        ("SUM(one)", "ok", "3", Some((5, 1, 1)), 0, 0.0),
+        ("ZZZ999", "invalid_cell", "", None, 0, 0.0),
+        ("one", "ok", "3", Some((82, 1, -1)), 0, 0.0),
+        ("one+1", "ok", "4", Some((12, 1, 1)), 0, 0.0),
+    ];
+
+    /// A1=5, B1=3, A2=-2, B2=0, A3=1/0, C3=7, plus the named ranges `data` (A1:B2)
+    /// and `one` (B1) with extensions.
+    fn golden_sheet() -> Spreadsheet {
+        let mut sheet = create_test_spreadsheet(10, 6);
+        let mut sleep_time = 0.0;
+        for (row, col, expr) in [
+            (0, 0, "5"),
+            (0, 1, "3"),
+            (1, 0, "-2"),
+            (1, 1, "0"),
+            (2, 0, "1/0"),
+            (2, 2, "7"),
+        ] {
+            set_cell_value(&mut sheet, row, col, expr, &mut sleep_time);
+        }
+        #[cfg(feature = "extensions")]
+        {
+            sheet.output = crate::output::OutputSink::capture();
+            sheet.named_ranges.insert(
+                "data".to_string(),
+                Range {
+                    start_row: 0,
+                    start_col: 0,
+                    end_row: 1,
+                    end_col: 1,
+                },
+            );
+            sheet
+                .named_ranges
+                .insert("one".to_string(), Range::single(0, 1));
+        }
+        sheet
+    }
+
+    #[test]
+    fn test_expression_goldens() {
+        for &(expr, status, value, meta, flags, sleep) in GOLDENS.iter().chain(BUILD_GOLDENS) {
+            let mut sheet = golden_sheet();
+            let mut sleep_time = 0.0;
+            let actual = set_cell_value(&mut sheet, 4, 4, expr, &mut sleep_time);
+            let key = sheet.get_key(4, 4);
+            let actual_value = match sheet.get_cell(4, 4) {
+                CellValue::Integer(v) => v.to_string(),
+                CellValue::Text(text) => format!("\"{}\"", text),
+                CellValue::Error(kind) => kind.code(),
+                CellValue::Empty => String::new(),
+            };
+            #[cfg(feature = "extensions")]
+            let actual_flags = sheet.relative_refs.get(&key).copied().unwrap_or(0);
+            #[cfg(not(feature = "extensions"))]
+            let actual_flags = 0u8;
+            assert_eq!(
+                (
+                    actual.as_str(),
+                    actual_value.as_str(),
+                    sheet
+                        .cell_meta
+                        .get(&key)
+                        .map(|m| (m.formula, m.parent1, m.parent2)),
+                    actual_flags,
+                    sleep_time,
+                ),
+                (status, value, meta, flags, sleep),
+                "{}",
+                expr
+            );
+        }
     }
 }
 
@@ -1449,6 +3618,18 @@ mod extension_tests {
         );
         assert_eq!(
             handle_command(&mut sheet, "visual Z9", &mut sleep_time),
+            CommandStatus::InvalidCell
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "visual A1 5", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "visual A1 0", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "visual A1 two", &mut sleep_time),
             CommandStatus::Unrecognized
         );
     }
@@ -1549,7 +3730,7 @@ mod extension_tests {
             handle_command(&mut sheet, "history A1", &mut sleep_time),
             CommandStatus::CmdOk
         );
-        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Empty);
     }
 
     #[test]
@@ -1562,6 +3743,21 @@ mod extension_tests {
         );
     }
 
+    #[test]
+    fn test_formula_full() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        sheet.output = crate::output::OutputSink::capture();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "B1=A1*2", &mut sleep_time);
+        handle_command(&mut sheet, "formula B1", &mut sleep_time);
+        handle_command(&mut sheet, "formula B1 --full", &mut sleep_time);
+        assert_eq!(sheet.output.take(), vec!["A1*2", "A1*2"]);
+        assert_eq!(
+            handle_command(&mut sheet, "formula --full", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+    }
+
     #[test]
     fn test_hlp_hlpc() {
         let mut sheet = create_test_spreadsheet(5, 5);
@@ -1584,9 +3780,60 @@ mod extension_tests {
             handle_command(&mut sheet, "name A1 cop", &mut sleep_time),
             CommandStatus::CmdOk
         );
+        // A formula would read `cop290` as a cell, even one past the sheet
         assert_eq!(
             handle_command(&mut sheet, "name A1 cop290", &mut sleep_time),
-            CommandStatus::CmdOk
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_single_cell_name_as_operand() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in ["B2=12", "name B2 total"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        for (command, expected) in [
+            ("A1=total+5", 17),
+            ("A1=20-total", 8),
+            ("A1=total*total", 144),
+            ("A1=total/4", 3),
+            ("A1=SLEEP(total)", 12),
+        ] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                command
+            );
+            assert_eq!(
+                *sheet.get_cell(0, 0),
+                CellValue::Integer(expected),
+                "{}",
+                command
+            );
+        }
+        assert_eq!(sleep_time, 12.0);
+
+        // The name's cell is a parent like any other
+        handle_command(&mut sheet, "A1=total-1", &mut sleep_time);
+        handle_command(&mut sheet, "B2=3", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(2));
+        assert_eq!(
+            handle_command(&mut sheet, "B2=total+1", &mut sleep_time),
+            CommandStatus::CircularRef
+        );
+
+        // A name for more than one cell is still not a value
+        handle_command(&mut sheet, "name A1:B2 block", &mut sleep_time);
+        assert_eq!(
+            handle_command(&mut sheet, "C1=block+1", &mut sleep_time),
+            CommandStatus::Unrecognized
         );
     }
 
@@ -1649,4 +3896,337 @@ mod extension_tests {
         );
         assert_eq!(resolve_cell_reference(&sheet, "test"), Ok((1, 1)));
     }
+
+    #[test]
+    fn test_viewport_address_assignment() {
+        let mut sheet = create_test_spreadsheet(30, 30);
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "@r3c2=99", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(2, 1), CellValue::Integer(99));
+
+        // The same address follows the viewport
+        handle_command(&mut sheet, "scroll_to K11", &mut sleep_time);
+        handle_command(&mut sheet, "@r3c2=5", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(12, 11), CellValue::Integer(5));
+        assert_eq!(resolve_cell_reference(&sheet, "@r1c1"), Ok((10, 10)));
+
+        // Any command taking a cell accepts one, and scroll_to moves relative to the view
+        handle_command(&mut sheet, "lock_cell @r1c1", &mut sleep_time);
+        assert!(sheet.is_cell_locked(10, 10));
+        handle_command(&mut sheet, "scroll_to @r2c3", &mut sleep_time);
+        assert_eq!((sheet.viewport_row, sheet.viewport_col), (11, 12));
+    }
+
+    #[test]
+    fn test_viewport_address_bounds() {
+        let mut sheet = create_test_spreadsheet(30, 30);
+        let mut sleep_time = 0.0;
+        for (command, status) in [
+            ("@r11c1=1", CommandStatus::InvalidCell),
+            ("@r1c11=1", CommandStatus::InvalidCell),
+            ("@r0c1=1", CommandStatus::Unrecognized),
+            ("@r1=1", CommandStatus::Unrecognized),
+            ("@x1c1=1", CommandStatus::Unrecognized),
+        ] {
+            assert_eq!(handle_command(&mut sheet, command, &mut sleep_time), status);
+        }
+
+        // Near the sheet's edge fewer rows and columns are displayed
+        handle_command(&mut sheet, "scroll_to Z25", &mut sleep_time);
+        assert_eq!(resolve_cell_reference(&sheet, "@r6c5"), Ok((29, 29)));
+        assert_eq!(
+            resolve_cell_reference(&sheet, "@r7c1"),
+            Err(CommandStatus::InvalidCell)
+        );
+        assert_eq!(
+            resolve_cell_reference(&sheet, "@r1c6"),
+            Err(CommandStatus::InvalidCell)
+        );
+
+        // With a filter, positions count the rows actually shown
+        handle_command(&mut sheet, "scroll_to A1", &mut sleep_time);
+        handle_command(&mut sheet, "A2=5", &mut sleep_time);
+        handle_command(&mut sheet, "A4=7", &mut sleep_time);
+        handle_command(&mut sheet, "filter A > 0", &mut sleep_time);
+        assert_eq!(resolve_cell_reference(&sheet, "@r2c1"), Ok((3, 0)));
+        assert_eq!(
+            resolve_cell_reference(&sheet, "@r3c1"),
+            Err(CommandStatus::InvalidCell)
+        );
+    }
+
+    #[test]
+    fn test_viewport_address_rejected_in_formulas() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        sheet.output = crate::output::OutputSink::capture();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A1=3", &mut sleep_time);
+        for command in ["B1=@r1c1+1", "B1=@r1c1", "B1=SUM(@r1c1:A2)", "@r1c2=@r1c1"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::Unrecognized
+            );
+            assert_eq!(
+                sheet.output.take(),
+                vec![
+                    "viewport addresses (@rNcM) are only allowed in commands, not in formulas; use a cell reference such as A1"
+                ]
+            );
+        }
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Empty);
+        assert!(sheet.cell_meta.is_empty());
+    }
+
+    #[test]
+    fn test_at_sign_in_quoted_text() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        for command in ["B1=\"me@x.com\"", "@r1c3=\"say \"\"@\"\" here\""] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(
+            *sheet.get_cell(0, 1),
+            CellValue::Text("me@x.com".to_string())
+        );
+        assert_eq!(
+            *sheet.get_cell(0, 2),
+            CellValue::Text("say \"@\" here".to_string())
+        );
+    }
+
+    #[test]
+    fn test_relative_reference_entry_and_evaluation() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A2=7", &mut sleep_time);
+        handle_command(&mut sheet, "B2=3", &mut sleep_time);
+
+        // Same row, two columns left, plus one row up in the same column
+        assert_eq!(
+            handle_command(&mut sheet, "C2=RC[-2]+R[-1]C[-1]", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 2), CellValue::Integer(7));
+        handle_command(&mut sheet, "B1=5", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(1, 2), CellValue::Integer(12));
+
+        // Negative offsets do not confuse operator detection
+        assert_eq!(
+            handle_command(&mut sheet, "D2=RC[-3]-1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 3), CellValue::Integer(6));
+
+        // Simple reference and range function corners
+        assert_eq!(
+            handle_command(&mut sheet, "E2=RC[-1]", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 4), CellValue::Integer(6));
+        assert_eq!(
+            handle_command(&mut sheet, "C3=SUM(R[-1]C[-2]:R[-1]C[-1])", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Integer(10));
+
+        // Overwriting with a plain value drops the relative flags
+        handle_command(&mut sheet, "C2=1", &mut sleep_time);
+        assert!(!sheet.relative_refs.contains_key(&sheet.get_key(1, 2)));
+    }
+
+    #[test]
+    fn test_relative_reference_out_of_bounds() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "A1=RC[-1]", &mut sleep_time),
+            CommandStatus::InvalidCell
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "A1=R[5]C+1", &mut sleep_time),
+            CommandStatus::InvalidCell
+        );
+        assert!(!sheet.cell_meta.contains_key(&sheet.get_key(0, 0)));
+    }
+
+    #[test]
+    fn test_relative_reference_cycle_restores_flags() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "B1=RC[-1]+1", &mut sleep_time);
+        assert_eq!(
+            handle_command(&mut sheet, "B1=RC", &mut sleep_time),
+            CommandStatus::CircularRef
+        );
+        assert_eq!(get_formula_string(&sheet, 0, 1), "RC[-1]+1");
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_pinned_references() {
+        use crate::cell::{PINNED_COL_PARENT1, PINNED_ROW_PARENT1, PINNED_ROW_PARENT2};
+        use crate::extensions::get_formula_string;
+
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A1=2", &mut sleep_time);
+        handle_command(&mut sheet, "B1=3", &mut sleep_time);
+        handle_command(&mut sheet, "B2=4", &mut sleep_time);
+
+        assert_eq!(
+            handle_command(&mut sheet, "C1=$A$1+B1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(5));
+        assert_eq!(
+            sheet.relative_refs.get(&sheet.get_key(0, 2)),
+            Some(&(PINNED_COL_PARENT1 | PINNED_ROW_PARENT1))
+        );
+        assert_eq!(get_formula_string(&sheet, 0, 2), "$A$1+B1");
+
+        assert_eq!(
+            handle_command(&mut sheet, "C2=SUM($A$1:B$2)", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 2), CellValue::Integer(9));
+        assert_eq!(
+            sheet.relative_refs.get(&sheet.get_key(1, 2)),
+            Some(&(PINNED_COL_PARENT1 | PINNED_ROW_PARENT1 | PINNED_ROW_PARENT2))
+        );
+        assert_eq!(get_formula_string(&sheet, 1, 2), "SUM($A$1:B$2)");
+
+        assert_eq!(
+            handle_command(&mut sheet, "D1=A$1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(get_formula_string(&sheet, 0, 3), "A$1");
+        assert_eq!(
+            handle_command(&mut sheet, "D2=$$A1+1", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+
+        // Re-entering without pins drops them
+        handle_command(&mut sheet, "C1=A1+B1", &mut sleep_time);
+        assert!(!sheet.relative_refs.contains_key(&sheet.get_key(0, 2)));
+    }
+
+    #[test]
+    fn test_handle_command_replace() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A1=3", &mut sleep_time);
+        handle_command(&mut sheet, "B2=3", &mut sleep_time);
+        assert_eq!(
+            handle_command(&mut sheet, "replace 3 4 A1:A5 --dry", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(3));
+        assert_eq!(
+            handle_command(&mut sheet, "replace 3 4 A1:A5", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(4));
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(3));
+        assert_eq!(
+            handle_command(&mut sheet, "replace 4 -1 in A1:B2", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(-1));
+        handle_command(&mut sheet, "history A1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(4));
+        assert_eq!(
+            handle_command(&mut sheet, "replace 3 4 in", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "replace 3", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "replace x 4", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_handle_command_replace_err_only_matches() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        sheet.output = crate::output::OutputSink::capture();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A1=0", &mut sleep_time);
+        *sheet.get_mut_cell(1, 0) = CellValue::Error(ErrorKind::DivZero);
+        assert_eq!(
+            handle_command(&mut sheet, "replace 0 ERR", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            sheet.output.take(),
+            vec!["replace: ERR only matches errors; the new value must be a number"]
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(0));
+        // As the value to find it still matches errors of every kind
+        assert_eq!(
+            handle_command(&mut sheet, "replace ERR 7 A1:A2", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(7));
+    }
+
+    #[test]
+    fn test_range_limit_rejects_and_confirms() {
+        let mut sheet = create_test_spreadsheet(20, 20);
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A1=2", &mut sleep_time);
+        assert_eq!(
+            handle_command(&mut sheet, "set_limit range_cells 50", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.range_cell_limit, 50);
+
+        // A function, an extended formula and a range assignment give the same message
+        sheet.output = crate::output::OutputSink::capture();
+        for command in ["T1=SUM(A1:J10)", "T1=SUM(A1:J10)+1", "A11:J20=0"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::RangeTooLarge
+            );
+            assert_eq!(
+                sheet.output.take(),
+                [
+                    "Range covers 100 cells, above the limit of 50; end the command with ! to confirm"
+                ]
+            );
+        }
+        assert!(!sheet.cell_meta.contains_key(&sheet.get_key(0, 19)));
+
+        // Confirming with a trailing `!` enters the formula
+        assert_eq!(
+            handle_command(&mut sheet, "T1=SUM(A1:J10)!", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 19), CellValue::Integer(2));
+        handle_command(&mut sheet, "B2=3", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 19), CellValue::Integer(5));
+
+        // Raising the limit at runtime lets the same formula through
+        handle_command(&mut sheet, "set_limit range_cells 100", &mut sleep_time);
+        assert_eq!(
+            handle_command(&mut sheet, "T2=MAX(A1:J10)", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "set_limit range_cells 0", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "set_limit other 5", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+    }
 }
@@ -0,0 +1,1910 @@
+//! The grammar of cell expressions and its parser.
+//!
+//! Everything typed after `=` is read here before it is evaluated, so this module is the
+//! one place that says what a valid expression looks like. Whitespace may appear
+//! between tokens, as in `SUM( A1 : B2 ) + 3`, but not inside a number, a word or a
+//! relative reference, and not between a function's name and its `(`. In EBNF:
+//!
+//! ```text
+//! expression     = text | scalar | if | sumproduct | sleep
+//!                | arithmetic [ comparison arithmetic ] ;  (* B1>C1 gives 1 or 0 *)
+//! text           = '"' { character } '"' ;                (* "Revenue"; "" is one quote *)
+//! arithmetic     = term { operator term } ;               (* see below for precedence *)
+//! term           = "(" arithmetic ")" | function | operand | ( "-" | "+" ) term ;
+//! operator       = "+" | "-" | "*" | "/" | "%" | "^" ;
+//! operand        = number | reference ;
+//! number         = [ "+" | "-" ] digit { digit } ;       (* fits in 32 bits *)
+//! reference      = relative | cell | name ;
+//! relative       = "R" [ offset ] "C" [ offset ] ;        (* RC[-2], R[1]C, RC *)
+//! offset         = "[" number "]" ;
+//! cell           = [ "$" ] column [ "$" ] row ;            (* A1, $A$1, B$3 *)
+//! column         = letter { letter } ;                   (* either case, A1 or a1 *)
+//! row            = digit { digit } ;
+//! name           = word ;                                  (* any other word *)
+//! word           = word-char { word-char } ;
+//! word-char      = letter | digit | "_" ;
+//! function       = ( "SUM" | "AVG" | "MIN" | "MAX" | "STDEV" | "MEDIAN" | "PRODUCT"
+//!                  | "SPREAD" | "COUNTA" | "COUNTBLANK" ) "(" range ")"
+//!                | "SUMIF" "(" range "," criterion ")" ;
+//! criterion      = comparison number ;                     (* >10, !=0, <=-3 *)
+//! comparison     = ">" | "<" | ">=" | "<=" | "=" | "!=" ;
+//! scalar         = "ABS" "(" operand ")"
+//!                | ( "MIN2" | "MAX2" | "ROUNDM" ) "(" operand "," operand ")" ;
+//! if             = "IF" "(" operand comparison operand "," operand "," operand ")" ;
+//! sumproduct     = "SUMPRODUCT" "(" range "," range ")" ;
+//! sleep          = "SLEEP" "(" arithmetic ")" ;
+//! range          = corner ":" corner | lines | name ;
+//! lines          = column ":" column | row ":" row ;      (* A:C, 3:5; not RC:RC *)
+//! corner         = relative | cell ;
+//!
+//! vector         = vector-operand [ operator vector-operand ] ;
+//! vector-operand = corner ":" corner | operand ;
+//! ```
+//!
+//! In `arithmetic`, `^` binds tightest and groups to the right; `* / %` come next and
+//! `+ -` last, both grouping to the left, so `A1-B1*2^2` is `A1-(B1*(2^2))`. A leading
+//! minus belongs to its term and binds tightest of all: `-B1^2` is `(-B1)^2`, as `-2^2`
+//! is `(-2)^2`. A sign right before digits is part of the number; any other sign applies
+//! to the term after it, spaces allowed, so `--5` is 5, `- B1` is `-B1` and `+B1` is `B1`.
+//!
+//! Words are read whole: a word of digits is a number, letters followed by digits are a
+//! cell if there are no more letters than in the longest column name, and any other
+//! word is a name. Cells and function names may be written in either case, so
+//! `sum(a1:b2)` is `SUM(A1:B2)`; names are matched exactly, so `data` and `Data` differ. A relative reference must not run
+//! into a word, so `RC1` is the cell in column `RC`. The `vector` form is the right-hand
+//! side of an elementwise range assignment such as `C1:C3=A1:A3+B1:B3`.
+//!
+//! The parser only checks the shape of an expression. Whether a cell is on the sheet, a
+//! name is defined, or the build supports relative and pinned references is decided when
+//! the expression is evaluated.
+
+use std::fmt;
+
+use crate::cell::{CellValue, checked_value, divide_value, modulo_value, power_value};
+use crate::spreadsheet::MAX_COLUMN_NAME_LEN;
+
+/// A reference to one cell.
+///
+/// # Variants
+///
+/// * `Cell` - An A1-style reference, as typed (`text`), split into its column and row
+///   with any `$` pins.
+/// * `Relative` - An R1C1-style offset from the formula's own cell.
+/// * `Name` - A word that is not a cell, such as a named range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference<'a> {
+    Cell {
+        text: &'a str,
+        column: &'a str,
+        row: &'a str,
+        pin_col: bool,
+        pin_row: bool,
+    },
+    Relative {
+        row_offset: i32,
+        col_offset: i32,
+    },
+    Name(&'a str),
+}
+
+impl Reference<'_> {
+    /// Checks whether the reference can be a corner of a range.
+    fn is_corner(&self) -> bool {
+        !matches!(self, Reference::Name(_))
+    }
+}
+
+/// One side of an arithmetic expression.
+///
+/// # Variants
+///
+/// * `Number(i32)` - An integer literal.
+/// * `Ref(Reference)` - A cell reference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand<'a> {
+    Number(i32),
+    Ref(Reference<'a>),
+}
+
+/// An arithmetic operator.
+///
+/// # Variants
+///
+/// * `Mod` - The remainder of integer division, `%`.
+/// * `Pow` - Integer power, `^`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+}
+
+impl BinaryOp {
+    /// Returns the operator as typed.
+    #[cfg(feature = "extensions")]
+    pub fn symbol(&self) -> char {
+        match self {
+            BinaryOp::Add => '+',
+            BinaryOp::Sub => '-',
+            BinaryOp::Mul => '*',
+            BinaryOp::Div => '/',
+            BinaryOp::Mod => '%',
+            BinaryOp::Pow => '^',
+        }
+    }
+
+    /// Returns how tightly the operator binds: `^` before `* / %` before `+ -`.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Sub => 1,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 2,
+            BinaryOp::Pow => 3,
+        }
+    }
+
+    /// Applies the operator to two values; division by zero or a result that does not fit
+    /// in an `i32` gives `Error`.
+    pub fn apply(&self, left: i32, right: i32) -> CellValue {
+        let result = match self {
+            BinaryOp::Add => left.checked_add(right),
+            BinaryOp::Sub => left.checked_sub(right),
+            BinaryOp::Mul => left.checked_mul(right),
+            BinaryOp::Div => return divide_value(left, right),
+            BinaryOp::Mod => return modulo_value(left, right),
+            BinaryOp::Pow => return power_value(left, right),
+        };
+        checked_value(result)
+    }
+
+    /// Reads an operator character.
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            b'+' => Some(BinaryOp::Add),
+            b'-' => Some(BinaryOp::Sub),
+            b'*' => Some(BinaryOp::Mul),
+            b'/' => Some(BinaryOp::Div),
+            b'%' => Some(BinaryOp::Mod),
+            b'^' => Some(BinaryOp::Pow),
+            _ => None,
+        }
+    }
+}
+
+/// A comparison operator in a criterion, an `IF` condition or a comparison formula.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Greater,
+    Less,
+    GreaterEqual,
+    LessEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    /// Returns the operator as typed.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Greater => ">",
+            Comparison::Less => "<",
+            Comparison::GreaterEqual => ">=",
+            Comparison::LessEqual => "<=",
+            Comparison::Equal => "=",
+            Comparison::NotEqual => "!=",
+        }
+    }
+
+    /// Returns the tens of the formula code of a comparison of two operands, as
+    /// `BinaryOp` forms use 1 to 6.
+    pub fn formula_msb(&self) -> i16 {
+        match self {
+            Comparison::Greater => 14,
+            Comparison::Less => 15,
+            Comparison::GreaterEqual => 16,
+            Comparison::LessEqual => 17,
+            Comparison::Equal => 18,
+            Comparison::NotEqual => 19,
+        }
+    }
+
+    /// Reads back the tens of a formula code written by `formula_msb`.
+    pub fn from_formula_msb(msb: i16) -> Option<Self> {
+        match msb {
+            14 => Some(Comparison::Greater),
+            15 => Some(Comparison::Less),
+            16 => Some(Comparison::GreaterEqual),
+            17 => Some(Comparison::LessEqual),
+            18 => Some(Comparison::Equal),
+            19 => Some(Comparison::NotEqual),
+            _ => None,
+        }
+    }
+
+    /// Compares two values as a formula does: 1 if the comparison holds, 0 if not.
+    pub fn apply(&self, left: i32, right: i32) -> CellValue {
+        CellValue::Integer(i32::from(self.holds(left, right)))
+    }
+
+    /// Checks whether `left` compares to `right` this way.
+    pub fn holds(&self, left: i32, right: i32) -> bool {
+        match self {
+            Comparison::Greater => left > right,
+            Comparison::Less => left < right,
+            Comparison::GreaterEqual => left >= right,
+            Comparison::LessEqual => left <= right,
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+        }
+    }
+}
+
+/// A condition on a cell value, such as `>10`.
+///
+/// # Fields
+///
+/// * `op` - How the value is compared.
+/// * `value` - The value compared against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Criterion {
+    pub op: Comparison,
+    pub value: i32,
+}
+
+impl Criterion {
+    /// Checks whether `value` satisfies the criterion.
+    pub fn matches(&self, value: i32) -> bool {
+        self.op.holds(value, self.value)
+    }
+}
+
+impl fmt::Display for Criterion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.op.symbol(), self.value)
+    }
+}
+
+/// A function over a range.
+///
+/// # Variants
+///
+/// * `Spread` - The largest value minus the smallest.
+/// * `CountA` - The number of cells that were assigned.
+/// * `CountBlank` - The number of cells that never were.
+/// * `SumIf(Criterion)` - The sum of the cells that satisfy the criterion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Stdev,
+    Median,
+    Product,
+    Spread,
+    CountA,
+    CountBlank,
+    SumIf(Criterion),
+}
+
+impl Function {
+    /// Returns the function's name as typed.
+    #[cfg(feature = "extensions")]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Sum => "SUM",
+            Function::Avg => "AVG",
+            Function::Min => "MIN",
+            Function::Max => "MAX",
+            Function::Stdev => "STDEV",
+            Function::Median => "MEDIAN",
+            Function::Product => "PRODUCT",
+            Function::Spread => "SPREAD",
+            Function::CountA => "COUNTA",
+            Function::CountBlank => "COUNTBLANK",
+            Function::SumIf(_) => "SUMIF",
+        }
+    }
+}
+
+/// A function of one or two values, as opposed to a range.
+///
+/// # Variants
+///
+/// * `Abs` - The absolute value of one operand.
+/// * `Min2` - The smaller of two operands.
+/// * `Max2` - The larger of two operands.
+/// * `RoundM` - The first operand rounded to the nearest multiple of the second
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarFunction {
+    Abs,
+    Min2,
+    Max2,
+    RoundM,
+}
+
+/// An `IF` as written, e.g. `IF(B1>10,100,C1)`.
+///
+/// # Fields
+///
+/// * `left` - The left side of the condition.
+/// * `op` - The comparison.
+/// * `right` - The right side of the condition.
+/// * `then` - The value when the condition holds.
+/// * `otherwise` - The value when it does not
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IfExpr<'a> {
+    pub left: Operand<'a>,
+    pub op: Comparison,
+    pub right: Operand<'a>,
+    pub then: Operand<'a>,
+    pub otherwise: Operand<'a>,
+}
+
+/// A range written as two corners, e.g. `A1:B3` or `R[-3]C:R[-1]C`.
+///
+/// # Fields
+///
+/// * `text` - The range as typed.
+/// * `start` - The top-left corner.
+/// * `end` - The bottom-right corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span<'a> {
+    pub text: &'a str,
+    pub start: Reference<'a>,
+    pub end: Reference<'a>,
+}
+
+/// The argument of a range function.
+///
+/// # Variants
+///
+/// * `Span(Span)` - Two corners.
+/// * `Lines(&str)` - Whole columns or rows as typed, e.g. `A:C` or `3:5`.
+/// * `Name(&str)` - A named range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeArg<'a> {
+    Span(Span<'a>),
+    Lines(&'a str),
+    Name(&'a str),
+}
+
+/// Arithmetic that does not fit `Expr::Binary`, e.g. `SUM(A1:A5)+10` or `(B1+C1)/2`.
+///
+/// Parentheses are not kept: they only shape the tree.
+///
+/// # Variants
+///
+/// * `Operand(Operand)` - A literal or a single reference.
+/// * `Function(Function, RangeArg)` - A function over a range.
+/// * `Binary(Box<ArithExpr>, BinaryOp, Box<ArithExpr>)` - Two expressions and an operator.
+/// * `Negate(Box<ArithExpr>)` - A minus before any term but digits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArithExpr<'a> {
+    Operand(Operand<'a>),
+    Function(Function, RangeArg<'a>),
+    Binary(Box<ArithExpr<'a>>, BinaryOp, Box<ArithExpr<'a>>),
+    Negate(Box<ArithExpr<'a>>),
+}
+
+/// A parsed cell expression.
+///
+/// # Variants
+///
+/// * `Operand(Operand)` - A literal or a single reference.
+/// * `Binary(Operand, BinaryOp, Operand)` - Two operands and an operator.
+/// * `Function(Function, RangeArg)` - A function over a range.
+/// * `Scalar(ScalarFunction, Operand, Option<Operand>)` - A function of one operand, or
+///   of two for `MIN2`, `MAX2` and `ROUNDM`.
+/// * `If(IfExpr)` - A choice between two operands by a comparison.
+/// * `SumProduct(RangeArg, RangeArg)` - The sum of the products of corresponding cells.
+/// * `Arithmetic(ArithExpr)` - Arithmetic that `Binary` cannot hold.
+/// * `Sleep(ArithExpr)` - `SLEEP` of a literal, a cell or arithmetic.
+/// * `Text(&str)` - A label, as written between its quotes.
+/// * `Compare(ArithExpr, Comparison, ArithExpr)` - Two sides compared, giving 1 or 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr<'a> {
+    Operand(Operand<'a>),
+    Binary(Operand<'a>, BinaryOp, Operand<'a>),
+    Function(Function, RangeArg<'a>),
+    Scalar(ScalarFunction, Operand<'a>, Option<Operand<'a>>),
+    If(IfExpr<'a>),
+    SumProduct(RangeArg<'a>, RangeArg<'a>),
+    Arithmetic(ArithExpr<'a>),
+    Sleep(ArithExpr<'a>),
+    Text(&'a str),
+    Compare(ArithExpr<'a>, Comparison, ArithExpr<'a>),
+}
+
+/// One side of an elementwise range assignment.
+///
+/// # Variants
+///
+/// * `Span(Span)` - A range, applied cell by cell.
+/// * `Scalar(&str, Operand)` - A literal or single cell as typed, used for every cell
+#[cfg(feature = "extensions")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorOperand<'a> {
+    Span(Span<'a>),
+    Scalar(&'a str, Operand<'a>),
+}
+
+/// A parsed elementwise right-hand side: one operand, or two joined by an operator.
+#[cfg(feature = "extensions")]
+pub type VectorExpr<'a> = (VectorOperand<'a>, Option<(BinaryOp, VectorOperand<'a>)>);
+
+/// Why an expression could not be parsed.
+///
+/// # Fields
+///
+/// * `position` - Byte offset of the offending character; the length of the input if
+///   it ended too early.
+/// * `expected` - What would have been valid there
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub position: usize,
+    pub expected: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected {} at column {}",
+            self.expected,
+            self.position + 1
+        )
+    }
+}
+
+const EXPECTED_OPERAND: &str = "a number, cell reference or name";
+const EXPECTED_REFERENCE: &str = "a cell reference or name";
+const EXPECTED_CORNER: &str = "a cell reference";
+const EXPECTED_PINNED: &str = "a cell reference such as $A$1";
+const EXPECTED_DIGIT: &str = "a digit";
+const EXPECTED_SMALL_NUMBER: &str = "a number that fits in 32 bits";
+const EXPECTED_FUNCTION: &str = "a function: SUM, AVG, MIN, MAX, STDEV, MEDIAN, PRODUCT, SPREAD, COUNTA, COUNTBLANK, SUMIF, SUMPRODUCT, ABS, MIN2, MAX2, ROUNDM, IF or SLEEP";
+const EXPECTED_COMPARISON: &str = "a comparison: > < >= <= = !=";
+const EXPECTED_TERM: &str = "a number, cell reference, name, range function or '('";
+const EXPECTED_OPERATOR: &str = "an operator (+ - * / % ^), a comparison or the end";
+const EXPECTED_OPERATOR_ONLY: &str = "an operator (+ - * / % ^) or the end";
+const EXPECTED_END: &str = "the end of the expression";
+const EXPECTED_QUOTE: &str = "'\"' to close the text";
+
+/// Checks whether a byte can be part of a word; `$` is included so that pinned
+/// references are read whole.
+fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$'
+}
+
+/// Splits a word of letters followed by digits into column and row.
+fn split_cell(word: &str) -> Option<(&str, &str)> {
+    let letters = word.bytes().take_while(u8::is_ascii_alphabetic).count();
+    let (column, row) = word.split_at(letters);
+    (!column.is_empty()
+        && column.len() <= MAX_COLUMN_NAME_LEN
+        && !row.is_empty()
+        && row.bytes().all(|b| b.is_ascii_digit()))
+    .then_some((column, row))
+}
+
+/// A cursor over the expression being parsed.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn error<T>(&self, position: usize, expected: &'static str) -> Result<T, ParseError> {
+        Err(ParseError { position, expected })
+    }
+
+    fn expect(&mut self, byte: u8, expected: &'static str) -> Result<(), ParseError> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(self.pos, expected)
+        }
+    }
+
+    /// Moves past any whitespace, which may separate tokens but not split one.
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Looks at the next byte that is not whitespace, moving past the whitespace.
+    fn peek_token(&mut self) -> Option<u8> {
+        self.skip_spaces();
+        self.peek()
+    }
+
+    /// Expects a punctuation byte, possibly after whitespace.
+    fn expect_token(&mut self, byte: u8, expected: &'static str) -> Result<(), ParseError> {
+        self.skip_spaces();
+        self.expect(byte, expected)
+    }
+
+    fn finish(&mut self, expected: &'static str) -> Result<(), ParseError> {
+        self.skip_spaces();
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => self.error(self.pos, expected),
+        }
+    }
+
+    /// Reads the longest word at the cursor, possibly empty.
+    fn word(&mut self) -> &'a str {
+        let start = self.pos;
+        while self.peek().is_some_and(is_word_byte) {
+            self.pos += 1;
+        }
+        &self.text[start..self.pos]
+    }
+
+    /// Reads a number with an optional sign.
+    fn number(&mut self) -> Result<i32, ParseError> {
+        let start = self.pos;
+        if matches!(self.peek(), Some(b'+' | b'-')) {
+            self.pos += 1;
+        }
+        let digits = self.pos;
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if self.pos == digits {
+            return self.error(self.pos, EXPECTED_DIGIT);
+        }
+        match self.text[start..self.pos].parse::<i32>() {
+            Ok(value) => Ok(value),
+            Err(_) => self.error(start, EXPECTED_SMALL_NUMBER),
+        }
+    }
+
+    /// Reads an optional bracketed offset.
+    fn offset(&mut self) -> Result<i32, ParseError> {
+        if self.peek() != Some(b'[') {
+            return Ok(0);
+        }
+        self.pos += 1;
+        let offset = self.number()?;
+        self.expect(b']', "']'")?;
+        Ok(offset)
+    }
+
+    /// Reads a relative reference, leaving the cursor alone if there is none.
+    fn relative(&mut self) -> Result<Option<Reference<'a>>, ParseError> {
+        let start = self.pos;
+        // `R` then `C` or `[`, so that words like `RATE` or `R2` are left to `word`
+        if !matches!(self.text.as_bytes()[start..], [b'R', b'C' | b'[', ..]) {
+            return Ok(None);
+        }
+        self.pos += 1;
+        let row_offset = self.offset()?;
+        self.expect(b'C', "'C'")?;
+        let col_offset = self.offset()?;
+        if self.peek().is_some_and(is_word_byte) {
+            // `RC1` and the like are words, not relative references
+            self.pos = start;
+            return Ok(None);
+        }
+        Ok(Some(Reference::Relative {
+            row_offset,
+            col_offset,
+        }))
+    }
+
+    /// Classifies a word that is not a number.
+    fn classify(&self, word: &'a str, start: usize) -> Result<Reference<'a>, ParseError> {
+        if word.contains('$') {
+            let (pin_col, rest) = match word.strip_prefix('$') {
+                Some(rest) => (true, rest),
+                None => (false, word),
+            };
+            let letters = rest.bytes().take_while(u8::is_ascii_alphabetic).count();
+            let (pin_row, digits) = match rest[letters..].strip_prefix('$') {
+                Some(digits) => (true, digits),
+                None => (false, &rest[letters..]),
+            };
+            if letters == 0 || digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return self.error(start, EXPECTED_PINNED);
+            }
+            return Ok(Reference::Cell {
+                text: word,
+                column: &rest[..letters],
+                row: digits,
+                pin_col,
+                pin_row,
+            });
+        }
+        Ok(match split_cell(word) {
+            Some((column, row)) => Reference::Cell {
+                text: word,
+                column,
+                row,
+                pin_col: false,
+                pin_row: false,
+            },
+            None => Reference::Name(word),
+        })
+    }
+
+    /// Reads a reference.
+    fn reference(&mut self) -> Result<Reference<'a>, ParseError> {
+        self.skip_spaces();
+        if let Some(relative) = self.relative()? {
+            return Ok(relative);
+        }
+        let start = self.pos;
+        let word = self.word();
+        if word.is_empty() || word.bytes().all(|b| b.is_ascii_digit()) {
+            return self.error(start, EXPECTED_REFERENCE);
+        }
+        self.classify(word, start)
+    }
+
+    /// Reads an operand, returning it with its text.
+    fn operand(&mut self) -> Result<(Operand<'a>, &'a str), ParseError> {
+        self.skip_spaces();
+        let start = self.pos;
+        if matches!(self.peek(), Some(b'+' | b'-')) {
+            let value = self.number()?;
+            return Ok((Operand::Number(value), &self.text[start..self.pos]));
+        }
+        if let Some(relative) = self.relative()? {
+            return Ok((Operand::Ref(relative), &self.text[start..self.pos]));
+        }
+        let word = self.word();
+        if word.is_empty() {
+            return self.error(start, EXPECTED_OPERAND);
+        }
+        let operand = if word.bytes().all(|b| b.is_ascii_digit()) {
+            self.pos = start;
+            Operand::Number(self.number()?)
+        } else {
+            Operand::Ref(self.classify(word, start)?)
+        };
+        Ok((operand, word))
+    }
+
+    /// Reads a range after its first reference, which must be followed by `:`.
+    fn span(&mut self, start: usize, first: Reference<'a>) -> Result<Span<'a>, ParseError> {
+        if !first.is_corner() {
+            return self.error(start, EXPECTED_CORNER);
+        }
+        self.expect_token(b':', "':'")?;
+        let end_start = self.pos;
+        let end = self.reference()?;
+        if !end.is_corner() {
+            return self.error(end_start, EXPECTED_CORNER);
+        }
+        Ok(Span {
+            text: &self.text[start..self.pos],
+            start: first,
+            end,
+        })
+    }
+
+    /// Reads whole columns such as `A:C` or whole rows such as `3:5`, if they are next.
+    ///
+    /// `RC:RC` is left to be read as a relative range.
+    fn lines(&mut self) -> Option<&'a str> {
+        let start = self.pos;
+        let is_column = |word: &str| word.bytes().all(|b| b.is_ascii_alphabetic()) && word != "RC";
+        let is_row = |word: &str| word.bytes().all(|b| b.is_ascii_digit());
+        let first = self.word();
+        if !first.is_empty()
+            && (is_column(first) || is_row(first))
+            && self.peek_token() == Some(b':')
+        {
+            self.pos += 1;
+            self.skip_spaces();
+            let second = self.word();
+            if !second.is_empty()
+                && (is_column(first) && is_column(second) || is_row(first) && is_row(second))
+            {
+                return Some(&self.text[start..self.pos]);
+            }
+        }
+        self.pos = start;
+        None
+    }
+
+    /// Reads the argument of a range function.
+    fn range(&mut self) -> Result<RangeArg<'a>, ParseError> {
+        self.skip_spaces();
+        let start = self.pos;
+        if let Some(lines) = self.lines() {
+            return Ok(RangeArg::Lines(lines));
+        }
+        let first = self.reference()?;
+        match first {
+            Reference::Name(name) if self.peek_token() != Some(b':') => Ok(RangeArg::Name(name)),
+            _ => Ok(RangeArg::Span(self.span(start, first)?)),
+        }
+    }
+
+    /// Reads a comparison operator.
+    fn comparison(&mut self) -> Result<Comparison, ParseError> {
+        self.skip_spaces();
+        let rest = &self.text.as_bytes()[self.pos..];
+        // Two-character operators first, so `>=` is not read as `>` followed by `=`
+        let (op, len) = match rest {
+            [b'>', b'=', ..] => (Comparison::GreaterEqual, 2),
+            [b'<', b'=', ..] => (Comparison::LessEqual, 2),
+            [b'!', b'=', ..] => (Comparison::NotEqual, 2),
+            [b'>', ..] => (Comparison::Greater, 1),
+            [b'<', ..] => (Comparison::Less, 1),
+            [b'=', ..] => (Comparison::Equal, 1),
+            _ => return self.error(self.pos, EXPECTED_COMPARISON),
+        };
+        self.pos += len;
+        Ok(op)
+    }
+
+    /// Reads a comparison operator and the number after it.
+    fn criterion(&mut self) -> Result<Criterion, ParseError> {
+        let op = self.comparison()?;
+        self.skip_spaces();
+        let value = self.number()?;
+        Ok(Criterion { op, value })
+    }
+
+    /// Reads the operator after an operand, if any.
+    #[cfg(feature = "extensions")]
+    fn operator(&mut self) -> Option<BinaryOp> {
+        let op = BinaryOp::from_byte(self.peek_token()?)?;
+        self.pos += 1;
+        Some(op)
+    }
+
+    /// Reads a function call whose name is `name` in any case, with the cursor on its `(`.
+    fn call(&mut self, name: &str, start: usize) -> Result<Expr<'a>, ParseError> {
+        let name = name.to_ascii_uppercase();
+        let name = name.as_str();
+        let function = match name {
+            "SUM" => Function::Sum,
+            "AVG" => Function::Avg,
+            "MIN" => Function::Min,
+            "MAX" => Function::Max,
+            "STDEV" => Function::Stdev,
+            "MEDIAN" => Function::Median,
+            "PRODUCT" => Function::Product,
+            "SPREAD" => Function::Spread,
+            "COUNTA" => Function::CountA,
+            "COUNTBLANK" => Function::CountBlank,
+            "SLEEP" => {
+                self.pos += 1;
+                let duration = self.arithmetic(0)?;
+                self.expect_token(b')', "')'")?;
+                return Ok(Expr::Sleep(duration));
+            }
+            "ABS" | "MIN2" | "MAX2" | "ROUNDM" => {
+                self.pos += 1;
+                let (first, _) = self.operand()?;
+                let (function, second) = match name {
+                    "ABS" => (ScalarFunction::Abs, None),
+                    _ => {
+                        self.expect_token(b',', "','")?;
+                        let (second, _) = self.operand()?;
+                        let function = match name {
+                            "MIN2" => ScalarFunction::Min2,
+                            "MAX2" => ScalarFunction::Max2,
+                            _ => ScalarFunction::RoundM,
+                        };
+                        (function, Some(second))
+                    }
+                };
+                self.expect_token(b')', "')'")?;
+                return Ok(Expr::Scalar(function, first, second));
+            }
+            "IF" => {
+                self.pos += 1;
+                let (left, _) = self.operand()?;
+                let op = self.comparison()?;
+                let (right, _) = self.operand()?;
+                self.expect_token(b',', "','")?;
+                let (then, _) = self.operand()?;
+                self.expect_token(b',', "','")?;
+                let (otherwise, _) = self.operand()?;
+                self.expect_token(b')', "')'")?;
+                return Ok(Expr::If(IfExpr {
+                    left,
+                    op,
+                    right,
+                    then,
+                    otherwise,
+                }));
+            }
+            "SUMPRODUCT" => {
+                self.pos += 1;
+                let first = self.range()?;
+                self.expect_token(b',', "','")?;
+                let second = self.range()?;
+                self.expect_token(b')', "')'")?;
+                return Ok(Expr::SumProduct(first, second));
+            }
+            "SUMIF" => {
+                self.pos += 1;
+                let range = self.range()?;
+                self.expect_token(b',', "','")?;
+                let criterion = self.criterion()?;
+                self.expect_token(b')', "')'")?;
+                return Ok(Expr::Function(Function::SumIf(criterion), range));
+            }
+            _ => return self.error(start, EXPECTED_FUNCTION),
+        };
+        self.pos += 1;
+        let range = self.range()?;
+        self.expect_token(b')', "')'")?;
+        Ok(Expr::Function(function, range))
+    }
+
+    /// Reads a parenthesized expression, a range function or an operand, with any leading
+    /// signs.
+    fn term(&mut self) -> Result<ArithExpr<'a>, ParseError> {
+        self.skip_spaces();
+        let start = self.pos;
+        // A sign right before digits belongs to the number; otherwise it applies to the term
+        if let Some(sign @ (b'-' | b'+')) = self.peek()
+            && !self
+                .text
+                .as_bytes()
+                .get(self.pos + 1)
+                .is_some_and(u8::is_ascii_digit)
+        {
+            self.pos += 1;
+            let term = self.term()?;
+            return Ok(match sign {
+                b'-' => ArithExpr::Negate(Box::new(term)),
+                _ => term,
+            });
+        }
+        if self.peek() == Some(b'(') {
+            self.pos += 1;
+            let inner = self.arithmetic(0)?;
+            self.expect_token(b')', "')'")?;
+            return Ok(inner);
+        }
+        let name = self.word();
+        if self.peek() == Some(b'(') {
+            return match arith_term(self.call(name, start)?) {
+                Some(term) => Ok(term),
+                None => self.error(start, EXPECTED_TERM),
+            };
+        }
+        self.pos = start;
+        let (operand, _) = self.operand()?;
+        Ok(ArithExpr::Operand(operand))
+    }
+
+    /// Reads terms joined by operators that bind at least as tightly as `min_precedence`.
+    fn arithmetic(&mut self, min_precedence: u8) -> Result<ArithExpr<'a>, ParseError> {
+        let mut left = self.term()?;
+        while let Some(op) = self.peek_token().and_then(BinaryOp::from_byte)
+            && op.precedence() >= min_precedence
+        {
+            self.pos += 1;
+            // `^` groups to the right, the other operators to the left
+            let next = match op {
+                BinaryOp::Pow => op.precedence(),
+                _ => op.precedence() + 1,
+            };
+            let right = self.arithmetic(next)?;
+            left = ArithExpr::Binary(Box::new(left), op, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    /// Reads a quoted label, with the cursor on its opening quote.
+    fn text(&mut self) -> Result<&'a str, ParseError> {
+        self.pos += 1;
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                // A doubled quote stands for one quote and does not end the text
+                Some(b'"') if self.text.as_bytes().get(self.pos + 1) == Some(&b'"') => {
+                    self.pos += 2
+                }
+                Some(b'"') => break,
+                Some(_) => self.pos += 1,
+                None => return self.error(self.pos, EXPECTED_QUOTE),
+            }
+        }
+        let text = &self.text[start..self.pos];
+        self.pos += 1;
+        Ok(text)
+    }
+
+    /// Reads a whole expression.
+    fn expression(&mut self) -> Result<Expr<'a>, ParseError> {
+        self.skip_spaces();
+        if self.peek() == Some(b'"') {
+            let text = self.text()?;
+            self.finish(EXPECTED_END)?;
+            return Ok(Expr::Text(text));
+        }
+        let start = self.pos;
+        let name = self.word();
+        // Calls other than range functions cannot take part in arithmetic
+        if !name.is_empty() && self.peek() == Some(b'(') {
+            let expr = self.call(name, start)?;
+            if !matches!(expr, Expr::Function(..)) {
+                self.finish(EXPECTED_END)?;
+                return Ok(expr);
+            }
+        }
+        self.pos = start;
+
+        let arith = self.arithmetic(0)?;
+        if matches!(self.peek_token(), Some(b'>' | b'<' | b'=' | b'!')) {
+            let op = self.comparison()?;
+            let right = self.arithmetic(0)?;
+            // A comparison gives a number but cannot be compared again
+            self.finish(EXPECTED_OPERATOR_ONLY)?;
+            return Ok(Expr::Compare(arith, op, right));
+        }
+        self.finish(EXPECTED_OPERATOR)?;
+        // The forms that fit in a `CellMeta` keep their own variants
+        Ok(match arith {
+            ArithExpr::Operand(operand) => Expr::Operand(operand),
+            ArithExpr::Function(function, range) => Expr::Function(function, range),
+            ArithExpr::Binary(left, op, right) => match (*left, *right) {
+                (ArithExpr::Operand(left), ArithExpr::Operand(right)) => {
+                    Expr::Binary(left, op, right)
+                }
+                (left, right) => {
+                    Expr::Arithmetic(ArithExpr::Binary(Box::new(left), op, Box::new(right)))
+                }
+            },
+            negate @ ArithExpr::Negate(_) => Expr::Arithmetic(negate),
+        })
+    }
+
+    /// Reads one side of an elementwise expression.
+    #[cfg(feature = "extensions")]
+    fn vector_operand(&mut self) -> Result<VectorOperand<'a>, ParseError> {
+        self.skip_spaces();
+        let start = self.pos;
+        if let Ok(first) = self.reference()
+            && first.is_corner()
+            && self.peek_token() == Some(b':')
+        {
+            return Ok(VectorOperand::Span(self.span(start, first)?));
+        }
+        self.pos = start;
+        let (operand, text) = self.operand()?;
+        Ok(VectorOperand::Scalar(text, operand))
+    }
+}
+
+/// Turns an operand or a range function into an arithmetic term; any other call cannot
+/// take part in arithmetic.
+fn arith_term(expr: Expr<'_>) -> Option<ArithExpr<'_>> {
+    match expr {
+        Expr::Operand(operand) => Some(ArithExpr::Operand(operand)),
+        Expr::Function(function, range) => Some(ArithExpr::Function(function, range)),
+        _ => None,
+    }
+}
+
+/// Parses a cell expression.
+///
+/// # Arguments
+///
+/// * `text` - The expression, without the target cell and `=`.
+///
+/// # Returns
+///
+/// * `Ok(Expr)` - The parsed expression.
+/// * `Err(ParseError)` - Where the expression stops following the grammar
+pub fn parse_expression(text: &str) -> Result<Expr<'_>, ParseError> {
+    Parser { text, pos: 0 }.expression()
+}
+
+/// The functions a formula can call.
+#[cfg(feature = "extensions")]
+const FUNCTION_NAMES: [&str; 18] = [
+    "SUM",
+    "AVG",
+    "MIN",
+    "MAX",
+    "STDEV",
+    "MEDIAN",
+    "PRODUCT",
+    "SPREAD",
+    "COUNTA",
+    "COUNTBLANK",
+    "SUMIF",
+    "SUMPRODUCT",
+    "ABS",
+    "MIN2",
+    "MAX2",
+    "ROUNDM",
+    "IF",
+    "SLEEP",
+];
+
+/// Checks whether a word reads as a name in formulas whatever its case, so that it is not
+/// a number, a cell, a relative reference or a function, and does not look like `R1C1`.
+///
+/// # Arguments
+///
+/// * `word` - The word to check.
+///
+/// # Returns
+///
+/// * `bool` - Whether the word may name a range
+#[cfg(feature = "extensions")]
+pub fn reads_as_name(word: &str) -> bool {
+    let upper = word.to_ascii_uppercase();
+    if FUNCTION_NAMES.contains(&upper.as_str()) {
+        return false;
+    }
+    // `R1C1` parses as a word, but reads as a reference to anyone used to that notation
+    if let Some((row, col)) = upper
+        .strip_prefix('R')
+        .and_then(|rest| rest.split_once('C'))
+        && row.bytes().all(|b| b.is_ascii_digit())
+        && col.bytes().all(|b| b.is_ascii_digit())
+    {
+        return false;
+    }
+    matches!(
+        parse_expression(&upper),
+        Ok(Expr::Operand(Operand::Ref(Reference::Name(_))))
+    )
+}
+
+/// Parses a criterion on its own, as written after the comma of `SUMIF`.
+///
+/// # Arguments
+///
+/// * `text` - The criterion, e.g. `>=10`.
+///
+/// # Returns
+///
+/// * `Ok(Criterion)` - The comparison and value.
+/// * `Err(ParseError)` - Where the text stops following the `criterion` rule
+#[cfg(feature = "extensions")]
+pub fn parse_criterion(text: &str) -> Result<Criterion, ParseError> {
+    let mut parser = Parser { text, pos: 0 };
+    let criterion = parser.criterion()?;
+    parser.finish(EXPECTED_END)?;
+    Ok(criterion)
+}
+
+/// Parses the right-hand side of an elementwise range assignment.
+///
+/// # Arguments
+///
+/// * `text` - The right-hand side, without a trailing `!`.
+///
+/// # Returns
+///
+/// * `Ok(VectorExpr)` - The operands and operator.
+/// * `Err(ParseError)` - Where the text stops following the `vector` rule
+#[cfg(feature = "extensions")]
+pub fn parse_vector_expression(text: &str) -> Result<VectorExpr<'_>, ParseError> {
+    let mut parser = Parser { text, pos: 0 };
+    let left = parser.vector_operand()?;
+    let Some(op) = parser.operator() else {
+        parser.finish(EXPECTED_OPERATOR)?;
+        return Ok((left, None));
+    };
+    let right = parser.vector_operand()?;
+    parser.finish(EXPECTED_END)?;
+    Ok((left, Some((op, right))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(text: &str) -> Reference<'_> {
+        let (column, row) = split_cell(text).unwrap();
+        Reference::Cell {
+            text,
+            column,
+            row,
+            pin_col: false,
+            pin_row: false,
+        }
+    }
+
+    fn error(position: usize, expected: &'static str) -> ParseError {
+        ParseError { position, expected }
+    }
+
+    #[test]
+    fn test_parse_forms() {
+        assert_eq!(
+            parse_expression("-42"),
+            Ok(Expr::Operand(Operand::Number(-42)))
+        );
+        assert_eq!(parse_expression("\"Revenue\""), Ok(Expr::Text("Revenue")));
+        assert_eq!(
+            parse_expression("B1>=C1"),
+            Ok(Expr::Compare(
+                ArithExpr::Operand(Operand::Ref(cell("B1"))),
+                Comparison::GreaterEqual,
+                ArithExpr::Operand(Operand::Ref(cell("C1")))
+            ))
+        );
+        assert_eq!(
+            parse_expression("B1+1 != 5"),
+            Ok(Expr::Compare(
+                ArithExpr::Binary(
+                    Box::new(ArithExpr::Operand(Operand::Ref(cell("B1")))),
+                    BinaryOp::Add,
+                    Box::new(ArithExpr::Operand(Operand::Number(1)))
+                ),
+                Comparison::NotEqual,
+                ArithExpr::Operand(Operand::Number(5))
+            ))
+        );
+        assert_eq!(
+            parse_expression("\"Q1, \"\"net\"\"\""),
+            Ok(Expr::Text("Q1, \"\"net\"\""))
+        );
+        assert_eq!(
+            parse_expression("A1*-3"),
+            Ok(Expr::Binary(
+                Operand::Ref(cell("A1")),
+                BinaryOp::Mul,
+                Operand::Number(-3)
+            ))
+        );
+        assert_eq!(
+            parse_expression("B1%7"),
+            Ok(Expr::Binary(
+                Operand::Ref(cell("B1")),
+                BinaryOp::Mod,
+                Operand::Number(7)
+            ))
+        );
+        assert_eq!(
+            parse_expression("2^-1"),
+            Ok(Expr::Binary(
+                Operand::Number(2),
+                BinaryOp::Pow,
+                Operand::Number(-1)
+            ))
+        );
+        assert_eq!(
+            parse_expression("ABS(B1)"),
+            Ok(Expr::Scalar(
+                ScalarFunction::Abs,
+                Operand::Ref(cell("B1")),
+                None
+            ))
+        );
+        assert_eq!(
+            parse_expression("MAX2(B1,-7)"),
+            Ok(Expr::Scalar(
+                ScalarFunction::Max2,
+                Operand::Ref(cell("B1")),
+                Some(Operand::Number(-7))
+            ))
+        );
+        assert_eq!(
+            parse_expression("ROUNDM(B1,10)"),
+            Ok(Expr::Scalar(
+                ScalarFunction::RoundM,
+                Operand::Ref(cell("B1")),
+                Some(Operand::Number(10))
+            ))
+        );
+        assert_eq!(
+            parse_expression("IF(B1>=-5,100,C1)"),
+            Ok(Expr::If(IfExpr {
+                left: Operand::Ref(cell("B1")),
+                op: Comparison::GreaterEqual,
+                right: Operand::Number(-5),
+                then: Operand::Number(100),
+                otherwise: Operand::Ref(cell("C1")),
+            }))
+        );
+        assert_eq!(
+            parse_expression("SUMPRODUCT(A1:A5,data)"),
+            Ok(Expr::SumProduct(
+                RangeArg::Span(Span {
+                    text: "A1:A5",
+                    start: cell("A1"),
+                    end: cell("A5"),
+                }),
+                RangeArg::Name("data")
+            ))
+        );
+        assert_eq!(
+            parse_expression("data"),
+            Ok(Expr::Operand(Operand::Ref(Reference::Name("data"))))
+        );
+        assert_eq!(
+            parse_expression("STDEV(data)"),
+            Ok(Expr::Function(Function::Stdev, RangeArg::Name("data")))
+        );
+        assert_eq!(
+            parse_expression("SUM(A1:B2)"),
+            Ok(Expr::Function(
+                Function::Sum,
+                RangeArg::Span(Span {
+                    text: "A1:B2",
+                    start: cell("A1"),
+                    end: cell("B2"),
+                })
+            ))
+        );
+        assert_eq!(
+            parse_expression("SLEEP(A1)"),
+            Ok(Expr::Sleep(ArithExpr::Operand(Operand::Ref(cell("A1")))))
+        );
+        assert_eq!(
+            parse_expression("SLEEP(A1+2)"),
+            Ok(Expr::Sleep(ArithExpr::Binary(
+                Box::new(ArithExpr::Operand(Operand::Ref(cell("A1")))),
+                BinaryOp::Add,
+                Box::new(ArithExpr::Operand(Operand::Number(2)))
+            )))
+        );
+        // `*` binds before `+`, `^` groups to the right, parentheses regroup
+        let leaf = |text| Box::new(ArithExpr::Operand(Operand::Ref(cell(text))));
+        let number = |value| Box::new(ArithExpr::Operand(Operand::Number(value)));
+        assert_eq!(
+            parse_expression("A1+B1*2"),
+            Ok(Expr::Arithmetic(ArithExpr::Binary(
+                leaf("A1"),
+                BinaryOp::Add,
+                Box::new(ArithExpr::Binary(leaf("B1"), BinaryOp::Mul, number(2)))
+            )))
+        );
+        assert_eq!(
+            parse_expression("(A1-B1)^2^-1"),
+            Ok(Expr::Arithmetic(ArithExpr::Binary(
+                Box::new(ArithExpr::Binary(leaf("A1"), BinaryOp::Sub, leaf("B1"))),
+                BinaryOp::Pow,
+                Box::new(ArithExpr::Binary(number(2), BinaryOp::Pow, number(-1)))
+            )))
+        );
+        // A minus before a word or `(` negates it; before digits it is part of the number
+        assert_eq!(
+            parse_expression("B1*-A1"),
+            Ok(Expr::Arithmetic(ArithExpr::Binary(
+                leaf("B1"),
+                BinaryOp::Mul,
+                Box::new(ArithExpr::Negate(leaf("A1")))
+            )))
+        );
+        // Signs nest, and one that does not touch digits applies to the term after it
+        let negate = |term| Box::new(ArithExpr::Negate(term));
+        for (text, expected) in [
+            ("--1", ArithExpr::Negate(number(-1))),
+            ("- -1", ArithExpr::Negate(number(-1))),
+            ("--B1", ArithExpr::Negate(negate(leaf("B1")))),
+            ("-+1", ArithExpr::Negate(number(1))),
+            ("-+B1", ArithExpr::Negate(leaf("B1"))),
+            ("- - B1", ArithExpr::Negate(negate(leaf("B1")))),
+        ] {
+            assert_eq!(
+                parse_expression(text),
+                Ok(Expr::Arithmetic(expected)),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(
+            parse_expression("+B1"),
+            Ok(Expr::Operand(Operand::Ref(cell("B1"))))
+        );
+        assert_eq!(
+            parse_expression("-(A1-2)"),
+            Ok(Expr::Arithmetic(ArithExpr::Negate(Box::new(
+                ArithExpr::Binary(leaf("A1"), BinaryOp::Sub, number(2))
+            ))))
+        );
+        assert_eq!(
+            parse_expression("-5+B1"),
+            Ok(Expr::Binary(
+                Operand::Number(-5),
+                BinaryOp::Add,
+                Operand::Ref(cell("B1"))
+            ))
+        );
+        assert_eq!(
+            parse_expression("((A1))"),
+            Ok(Expr::Operand(Operand::Ref(cell("A1"))))
+        );
+        assert_eq!(
+            parse_expression("10-MAX(data)"),
+            Ok(Expr::Arithmetic(ArithExpr::Binary(
+                Box::new(ArithExpr::Operand(Operand::Number(10))),
+                BinaryOp::Sub,
+                Box::new(ArithExpr::Function(Function::Max, RangeArg::Name("data")))
+            )))
+        );
+        assert_eq!(
+            parse_expression("SUMIF(data,>=-5)"),
+            Ok(Expr::Function(
+                Function::SumIf(Criterion {
+                    op: Comparison::GreaterEqual,
+                    value: -5,
+                }),
+                RangeArg::Name("data")
+            ))
+        );
+        // A word that is not a few letters then digits is a name
+        for name in ["data1", "R1C1", "R", "row_2", "1st"] {
+            assert_eq!(
+                parse_expression(name),
+                Ok(Expr::Operand(Operand::Ref(Reference::Name(name)))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_case_of_functions_and_cells() {
+        for (text, upper) in [
+            ("sum(A1:B2)", "SUM(A1:B2)"),
+            ("Sumif(data,>0)", "SUMIF(data,>0)"),
+            ("max2(B1,2)", "MAX2(B1,2)"),
+            ("sumProduct(data,other)", "SUMPRODUCT(data,other)"),
+            ("-avg(data)+1", "-AVG(data)+1"),
+        ] {
+            assert_eq!(parse_expression(text), parse_expression(upper), "{}", text);
+        }
+        assert_eq!(
+            parse_expression("zz9"),
+            Ok(Expr::Operand(Operand::Ref(cell("zz9"))))
+        );
+        assert_eq!(
+            parse_expression("Data"),
+            Ok(Expr::Operand(Operand::Ref(Reference::Name("Data"))))
+        );
+    }
+
+    #[test]
+    fn test_parse_whole_lines() {
+        for (text, lines) in [
+            ("SUM(A:A)", "A:A"),
+            ("MAX(b:C)", "b:C"),
+            ("AVG( 3 : 5 )", "3 : 5"),
+            ("COUNTA(10:12)", "10:12"),
+        ] {
+            let text = format!("{}+1", text);
+            let Ok(Expr::Arithmetic(ArithExpr::Binary(function, ..))) = parse_expression(&text)
+            else {
+                panic!("{}", text);
+            };
+            assert!(
+                matches!(*function, ArithExpr::Function(_, RangeArg::Lines(found)) if found == lines),
+                "{}",
+                text
+            );
+        }
+        // `RC:RC` is a relative range, and a column cannot end at a cell
+        assert!(matches!(
+            parse_expression("SUM(RC:RC)"),
+            Ok(Expr::Function(Function::Sum, RangeArg::Span(_)))
+        ));
+        assert_eq!(
+            parse_expression("SUM(A:A1)"),
+            Err(error(4, EXPECTED_CORNER))
+        );
+        assert_eq!(
+            parse_expression("SUM(3:A)"),
+            Err(error(4, EXPECTED_REFERENCE))
+        );
+        assert_eq!(
+            parse_expression("SUMIF(A:A,>0)"),
+            Ok(Expr::Function(
+                Function::SumIf(Criterion {
+                    op: Comparison::Greater,
+                    value: 0,
+                }),
+                RangeArg::Lines("A:A")
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_whitespace_between_tokens() {
+        for (spaced, compact) in [
+            ("A1+ 3", "A1+3"),
+            (" MAX(A1:B1) ", "MAX(A1:B1)"),
+            (" B1 * ( C1 - 2 ) ^ 2 ", "B1*(C1-2)^2"),
+            ("IF( B1 >= 10 , 1 , C1 )", "IF(B1>=10,1,C1)"),
+            ("SUMIF( data , >= -5 )", "SUMIF(data,>=-5)"),
+            ("ABS( B1 )", "ABS(B1)"),
+            ("MIN2( B1 , -7 )", "MIN2(B1,-7)"),
+            ("SUMPRODUCT( data , other )", "SUMPRODUCT(data,other)"),
+            ("SLEEP(\t2 )", "SLEEP(2)"),
+        ] {
+            assert_eq!(
+                parse_expression(spaced),
+                parse_expression(compact),
+                "{}",
+                spaced
+            );
+        }
+        // A sign may stand apart from what it applies to, like an operator
+        assert_eq!(parse_expression("- B1"), parse_expression("-B1"));
+        // A range keeps its text as typed, spaces included, for `parse_range` to trim
+        assert_eq!(
+            parse_expression("SUM( A1 : B2 )"),
+            Ok(Expr::Function(
+                Function::Sum,
+                RangeArg::Span(Span {
+                    text: "A1 : B2",
+                    start: cell("A1"),
+                    end: cell("B2"),
+                })
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_relative_references() {
+        let relative = |text| match parse_expression(text) {
+            Ok(Expr::Operand(Operand::Ref(Reference::Relative {
+                row_offset,
+                col_offset,
+            }))) => Some((row_offset, col_offset)),
+            _ => None,
+        };
+        assert_eq!(relative("RC[-2]"), Some((0, -2)));
+        assert_eq!(relative("R[-1]C"), Some((-1, 0)));
+        assert_eq!(relative("R[1]C[3]"), Some((1, 3)));
+        assert_eq!(relative("R[+1]C"), Some((1, 0)));
+        assert_eq!(relative("RC"), Some((0, 0)));
+        // `RC1` is the cell in column RC
+        assert_eq!(
+            parse_expression("RC1"),
+            Ok(Expr::Operand(Operand::Ref(cell("RC1"))))
+        );
+        assert_eq!(parse_expression("RC[-2"), Err(error(5, "']'")));
+        assert_eq!(parse_expression("RC[x]"), Err(error(3, EXPECTED_DIGIT)));
+        assert_eq!(parse_expression("R[1]X"), Err(error(4, "'C'")));
+        assert_eq!(parse_expression("RC[1]X"), Err(error(2, EXPECTED_OPERATOR)));
+        assert_eq!(
+            parse_expression("A1-RC[-1]"),
+            Ok(Expr::Binary(
+                Operand::Ref(cell("A1")),
+                BinaryOp::Sub,
+                Operand::Ref(Reference::Relative {
+                    row_offset: 0,
+                    col_offset: -1
+                })
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_pinned_references() {
+        let pins = |text| match parse_expression(text) {
+            Ok(Expr::Operand(Operand::Ref(Reference::Cell {
+                column,
+                row,
+                pin_col,
+                pin_row,
+                ..
+            }))) => Some((format!("{}{}", column, row), pin_col, pin_row)),
+            _ => None,
+        };
+        assert_eq!(pins("A1"), Some(("A1".to_string(), false, false)));
+        assert_eq!(pins("$A$1"), Some(("A1".to_string(), true, true)));
+        assert_eq!(pins("$AB12"), Some(("AB12".to_string(), true, false)));
+        assert_eq!(pins("C$3"), Some(("C3".to_string(), false, true)));
+        assert_eq!(pins("$b$2"), Some(("b2".to_string(), true, true)));
+        for bad in ["$$A1", "A1$", "$1", "A$"] {
+            assert_eq!(
+                parse_expression(bad),
+                Err(error(0, EXPECTED_PINNED)),
+                "{}",
+                bad
+            );
+        }
+        assert_eq!(parse_expression("$A$1+B$"), Err(error(5, EXPECTED_PINNED)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (text, position, expected) in [
+            ("", 0, EXPECTED_OPERAND),
+            ("5+", 2, EXPECTED_OPERAND),
+            ("*5", 0, EXPECTED_OPERAND),
+            ("-", 1, EXPECTED_OPERAND),
+            ("- -", 3, EXPECTED_OPERAND),
+            ("-(B1", 4, "')'"),
+            ("5 6", 2, EXPECTED_OPERATOR),
+            ("5 - * 6", 4, EXPECTED_OPERAND),
+            ("R[ 1]C", 2, EXPECTED_DIGIT),
+            ("SUM (A1:B2)", 4, EXPECTED_OPERATOR),
+            ("A1>B1>0", 5, EXPECTED_OPERATOR_ONLY),
+            ("A1=>B1", 3, EXPECTED_OPERAND),
+            ("A1!B1", 2, EXPECTED_COMPARISON),
+            ("A1<", 3, EXPECTED_OPERAND),
+            ("(A1+B1", 6, "')'"),
+            ("(A1+B1))", 7, EXPECTED_OPERATOR),
+            ("()", 1, EXPECTED_OPERAND),
+            ("A1*(ABS(B1))", 4, EXPECTED_TERM),
+            ("A1.5", 2, EXPECTED_OPERATOR),
+            ("99999999999", 0, EXPECTED_SMALL_NUMBER),
+            ("A1+-2147483649", 3, EXPECTED_SMALL_NUMBER),
+            ("SUMX(A1:B2)", 0, EXPECTED_FUNCTION),
+            ("SUM(A1)", 6, "':'"),
+            ("SUM()", 4, EXPECTED_REFERENCE),
+            ("SUM(A1:B2", 9, "')'"),
+            ("SUM(A1:B2:C3)", 9, "')'"),
+            ("SUM(A1:data)", 7, EXPECTED_CORNER),
+            ("SUM(A1:B2)x", 10, EXPECTED_OPERATOR),
+            ("ABS(B1)+1", 7, EXPECTED_END),
+            ("1+ABS(B1)", 2, EXPECTED_TERM),
+            ("SUM(A1:B2)+", 11, EXPECTED_OPERAND),
+            ("SLEEP(5+1", 9, "')'"),
+            ("SUMIF(A1:A3)", 11, "','"),
+            ("ABS(B1,C1)", 6, "')'"),
+            ("MIN2(B1)", 7, "','"),
+            ("MAX2(B1,C1,D1)", 10, "')'"),
+            ("MIN2(B1:B2,C1)", 7, "','"),
+            ("ROUNDM(B1)", 9, "','"),
+            ("SUMPRODUCT(A1:A3)", 16, "','"),
+            ("SUMPRODUCT(A1:A3,B1)", 19, "':'"),
+            ("SUMIF(A1:A3,10)", 12, EXPECTED_COMPARISON),
+            ("SUMIF(A1:A3,=>1)", 13, EXPECTED_DIGIT),
+            ("SUMIF(A1:A3,>B1)", 13, EXPECTED_DIGIT),
+            ("SUMIF(A1:A3,<1", 14, "')'"),
+            ("IF(B1,1,0)", 5, EXPECTED_COMPARISON),
+            ("IF(B1>10,1)", 10, "','"),
+            ("IF(B1>10,1,0,2)", 12, "')'"),
+            ("IF(B1>=,1,0)", 7, EXPECTED_OPERAND),
+            ("IF(B1>A1:A2,1,0)", 8, "','"),
+            ("A1:B2", 2, EXPECTED_OPERATOR),
+            ("A1+é", 3, EXPECTED_OPERAND),
+        ] {
+            assert_eq!(
+                parse_expression(text),
+                Err(error(position, expected)),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(
+            parse_expression("SUM(A1)").unwrap_err().to_string(),
+            "expected ':' at column 7"
+        );
+        assert_eq!(
+            parse_expression("-2147483648"),
+            Ok(Expr::Operand(Operand::Number(i32::MIN)))
+        );
+    }
+
+    #[test]
+    fn test_parse_criterion_operators() {
+        for (text, op) in [
+            (">", Comparison::Greater),
+            ("<", Comparison::Less),
+            (">=", Comparison::GreaterEqual),
+            ("<=", Comparison::LessEqual),
+            ("=", Comparison::Equal),
+            ("!=", Comparison::NotEqual),
+        ] {
+            let expr = format!("SUMIF(A1:A3,{}7)", text);
+            let Ok(Expr::Function(Function::SumIf(criterion), _)) = parse_expression(&expr) else {
+                panic!("{} did not parse", expr);
+            };
+            assert_eq!(criterion, Criterion { op, value: 7 });
+            assert_eq!(criterion.to_string(), format!("{}7", text));
+        }
+        let at_least_two = Criterion {
+            op: Comparison::GreaterEqual,
+            value: 2,
+        };
+        assert!(at_least_two.matches(2) && at_least_two.matches(3));
+        assert!(!at_least_two.matches(1));
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_parse_criterion() {
+        assert_eq!(
+            parse_criterion("!=-4"),
+            Ok(Criterion {
+                op: Comparison::NotEqual,
+                value: -4,
+            })
+        );
+        assert_eq!(parse_criterion("<5)"), Err(error(2, EXPECTED_END)));
+        assert_eq!(parse_criterion(""), Err(error(0, EXPECTED_COMPARISON)));
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_parse_vector_expression() {
+        let span = |text, start, end| {
+            VectorOperand::Span(Span {
+                text,
+                start: cell(start),
+                end: cell(end),
+            })
+        };
+        assert_eq!(
+            parse_vector_expression("A1:A3+B1:B3"),
+            Ok((
+                span("A1:A3", "A1", "A3"),
+                Some((BinaryOp::Add, span("B1:B3", "B1", "B3")))
+            ))
+        );
+        assert_eq!(
+            parse_vector_expression("-2-B1:B3"),
+            Ok((
+                VectorOperand::Scalar("-2", Operand::Number(-2)),
+                Some((BinaryOp::Sub, span("B1:B3", "B1", "B3")))
+            ))
+        );
+        assert_eq!(
+            parse_vector_expression("A1:A3"),
+            Ok((span("A1:A3", "A1", "A3"), None))
+        );
+        assert_eq!(
+            parse_vector_expression(" A1:A3 * 2 "),
+            Ok((
+                span("A1:A3", "A1", "A3"),
+                Some((
+                    BinaryOp::Mul,
+                    VectorOperand::Scalar("2", Operand::Number(2))
+                ))
+            ))
+        );
+        assert_eq!(
+            parse_vector_expression("A1:A3*B1*C1"),
+            Err(error(8, EXPECTED_END))
+        );
+        assert!(parse_vector_expression("SUM(A1:A3)").is_err());
+    }
+
+    /// Checks that an error points inside the text, at a character boundary.
+    fn assert_error_in_bounds(text: &str) {
+        if let Err(error) = parse_expression(text) {
+            assert!(error.position <= text.len(), "{:?}", text);
+            assert!(text.is_char_boundary(error.position), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_malformed_corpus_never_panics() {
+        let long_word = "A".repeat(10_000);
+        let long_number = "9".repeat(10_000);
+        let deep = "SUM(".repeat(1_000);
+        let corpus = [
+            "",
+            " ",
+            "(",
+            ")",
+            "()",
+            ":",
+            "::",
+            "[",
+            "]",
+            "[]",
+            "$",
+            "$$",
+            "!",
+            "=",
+            "+",
+            "-",
+            "*",
+            "/",
+            "++",
+            "+-",
+            "R",
+            "R[",
+            "R[]",
+            "R[-]",
+            "R[-1",
+            "R[-1]",
+            "RC[",
+            "RC[]",
+            "RC[1]]",
+            "R[1]C[2]C",
+            "RR",
+            "RCRC",
+            "R[99999999999]C",
+            "R[-2147483648]C",
+            "A",
+            "A$",
+            "$A",
+            "1A",
+            "A1:",
+            ":A1",
+            "A1::B2",
+            "A1:B2:",
+            "SUM",
+            "SUM(",
+            "SUM)",
+            "SUM(:)",
+            "SUM(A1:)",
+            "SUM(:B2)",
+            "SUM(A1:B2))",
+            "SUM(A1:B2)(",
+            "SLEEP",
+            "SLEEP(",
+            "SLEEP())",
+            "SLEEP(SLEEP(1))",
+            "STDEV(",
+            "A1+",
+            "A1+(",
+            "A1+)",
+            "é",
+            "Aé1",
+            "A1é",
+            "ÅÄÖ",
+            "\u{0}",
+            "\t",
+            "\n",
+            "A1\n",
+            "1e9",
+            "0x10",
+            "1.5",
+            "½",
+            "SUM(A1:B2)!",
+            "A1 ",
+            " A1",
+            "A 1",
+        ];
+        for text in
+            corpus
+                .iter()
+                .copied()
+                .chain([long_word.as_str(), long_number.as_str(), deep.as_str()])
+        {
+            assert_error_in_bounds(text);
+        }
+    }
+
+    /// Generates a random expression that follows the grammar.
+    #[cfg(feature = "extensions")]
+    fn valid_expression(rng: &mut crate::random::Rng) -> String {
+        let mut pick = |n: u64| rng.below(n) as usize;
+        let columns = ["A", "B", "ZZ", "RC", "AAA"];
+        let reference = |pick: &mut dyn FnMut(u64) -> usize| match pick(4) {
+            0 => format!("{}{}", columns[pick(5)], pick(1000)),
+            1 => format!(
+                "{}{}{}{}",
+                ["", "$"][pick(2)],
+                columns[pick(5)],
+                ["", "$"][pick(2)],
+                pick(1000)
+            ),
+            2 => format!(
+                "R{}C{}",
+                ["", "[-3]", "[+2]", "[0]"][pick(4)],
+                ["", "[-1]", "[4]"][pick(3)]
+            ),
+            _ => ["data", "one", "x_1", "R1C1", "data1"][pick(5)].to_string(),
+        };
+        let operand = |pick: &mut dyn FnMut(u64) -> usize| match pick(3) {
+            0 => format!("{}{}", ["", "+", "-"][pick(3)], pick(100_000)),
+            _ => reference(&mut *pick),
+        };
+        match pick(5) {
+            0 => operand(&mut pick),
+            1 => {
+                // A chain of operators, some of its operands wrapped in parentheses or negated
+                let mut expr = operand(&mut pick);
+                for _ in 0..=pick(3) {
+                    let op = ["+", "-", "*", "/", "%", "^"][pick(6)];
+                    expr = match pick(4) {
+                        0 => format!("({}){}{}", expr, op, operand(&mut pick)),
+                        1 => format!("{}{}({})", operand(&mut pick), op, expr),
+                        2 => format!("-({}){}-{}", expr, op, reference(&mut pick)),
+                        _ => format!("{}{}{}", expr, op, operand(&mut pick)),
+                    };
+                }
+                expr
+            }
+            2 => match pick(5) {
+                0 => format!("ABS({})", operand(&mut pick)),
+                4 => format!("SLEEP({})", operand(&mut pick)),
+                function => {
+                    let first = operand(&mut pick);
+                    let name = ["MIN2", "MAX2", "ROUNDM"][function - 1];
+                    format!("{}({},{})", name, first, operand(&mut pick))
+                }
+            },
+            3 => {
+                let comparison = [">", "<", ">=", "<=", "=", "!="][pick(6)];
+                if pick(2) == 0 {
+                    format!(
+                        "SUMIF(A1:B{},{}{})",
+                        pick(100),
+                        comparison,
+                        ["", "+", "-"][pick(3)].to_string() + &pick(1000).to_string()
+                    )
+                } else {
+                    let left = operand(&mut pick);
+                    let right = operand(&mut pick);
+                    let then = operand(&mut pick);
+                    format!(
+                        "IF({}{}{},{},{})",
+                        left,
+                        comparison,
+                        right,
+                        then,
+                        operand(&mut pick)
+                    )
+                }
+            }
+            _ => {
+                let function = [
+                    "SUM",
+                    "AVG",
+                    "MIN",
+                    "MAX",
+                    "STDEV",
+                    "MEDIAN",
+                    "PRODUCT",
+                    "SPREAD",
+                    "COUNTA",
+                    "COUNTBLANK",
+                    "SUMPRODUCT",
+                ][pick(11)];
+                let corners = ["A1", "$A$1", "B$2", "RC[-1]", "R[2]C"];
+                let start = corners[pick(5)];
+                let range = format!("{}:{}", start, corners[pick(5)]);
+                if function == "SUMPRODUCT" {
+                    format!(
+                        "SUMPRODUCT({},{}:{})",
+                        range,
+                        corners[pick(5)],
+                        corners[pick(5)]
+                    )
+                } else {
+                    let call = format!("{}({})", function, range);
+                    let op = ["+", "-", "*", "/", "%", "^"][pick(6)];
+                    match pick(3) {
+                        0 => call,
+                        1 => format!("{}{}{}", call, op, operand(&mut pick)),
+                        _ => format!("{}{}{}", operand(&mut pick), op, call),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_fuzz_never_panics() {
+        let mut rng = crate::random::Rng::new(239);
+        let alphabet = b"AZaz09$_[]():,<>=+-*/%^!R C.\xc3\xa9";
+        for _ in 0..20_000 {
+            let expr = valid_expression(&mut rng);
+            assert!(parse_expression(&expr).is_ok(), "{}", expr);
+
+            // Every prefix and every single-byte change must fail cleanly, never panic
+            for end in 0..expr.len() {
+                assert_error_in_bounds(&expr[..end]);
+            }
+            let mut bytes = expr.clone().into_bytes();
+            let at = rng.below(bytes.len() as u64) as usize;
+            bytes[at] = alphabet[rng.below(alphabet.len() as u64) as usize];
+            assert_error_in_bounds(&String::from_utf8_lossy(&bytes));
+
+            // Random byte soup from the same alphabet
+            let len = rng.below(16) as usize;
+            let soup: Vec<u8> = (0..len)
+                .map(|_| alphabet[rng.below(alphabet.len() as u64) as usize])
+                .collect();
+            assert_error_in_bounds(&String::from_utf8_lossy(&soup));
+        }
+    }
+}
@@ -0,0 +1,538 @@
+//! Formulas that read more cells than the two parents of a `CellMeta`, such as `IF`,
+//! `SUMPRODUCT`, arithmetic like `(B1+C1)/2` or `SUM(A1:A5)+10`, `SLEEP(B1+2)` and
+//! comparisons like `SUM(A1:A5)>B1`.
+//!
+//! A cell with such a formula has formula code `EXTENDED_FORMULA` and parents of -1. The
+//! formula itself is kept in `Spreadsheet::extended_formulas` under the cell's key, where
+//! the dependency graph, the evaluator and the save file look it up.
+
+#[cfg(feature = "extensions")]
+use std::cmp::Ordering;
+
+use crate::aggregate::range_value;
+use crate::cell::{CellValue, ErrorKind, checked_value};
+#[cfg(feature = "extensions")]
+use crate::cell::{RELATIVE_PARENT1, RELATIVE_PARENT2};
+use crate::expr_parser::{BinaryOp, Comparison, Function};
+#[cfg(feature = "extensions")]
+use crate::extensions::parent_name;
+use crate::range::Range;
+use crate::reevaluate_topo::sleep_fn;
+use crate::spreadsheet::Spreadsheet;
+
+/// Formula code of a cell whose formula is in `Spreadsheet::extended_formulas`.
+pub const EXTENDED_FORMULA: i16 = 4;
+
+/// One input of an extended formula.
+///
+/// # Variants
+///
+/// * `Number(i32)` - A literal.
+/// * `Cell(i32, u8)` - The key of a cell and its reference flags, as `RELATIVE_PARENT1`
+///   and `PINNED_*_PARENT1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Term {
+    Number(i32),
+    Cell(i32, u8),
+}
+
+impl Term {
+    /// Reads the term's value: 0 for an empty cell, the error kind for an error or text.
+    fn value(&self, sheet: &Spreadsheet) -> Result<i32, ErrorKind> {
+        match self {
+            Term::Number(value) => Ok(*value),
+            Term::Cell(key, _) => sheet.get_key_cell(*key).number(),
+        }
+    }
+
+    /// Writes the term as it would be typed in the cell at `row`, `col`.
+    #[cfg(feature = "extensions")]
+    fn name(&self, sheet: &Spreadsheet, row: i16, col: i16) -> String {
+        match self {
+            Term::Number(value) => value.to_string(),
+            Term::Cell(key, flags) => parent_name(sheet, row, col, *key, RELATIVE_PARENT1, *flags),
+        }
+    }
+}
+
+/// An `IF` formula: `IF(left op right, then, otherwise)`.
+///
+/// # Fields
+///
+/// * `left` - The left side of the condition.
+/// * `op` - The comparison.
+/// * `right` - The right side of the condition.
+/// * `then` - The value when the condition holds.
+/// * `otherwise` - The value when it does not
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IfFormula {
+    pub left: Term,
+    pub op: Comparison,
+    pub right: Term,
+    pub then: Term,
+    pub otherwise: Term,
+}
+
+impl IfFormula {
+    /// Computes the formula's value.
+    ///
+    /// An error on either side of the condition makes the result an error, as does an
+    /// error in the chosen outcome; an error in the other outcome is ignored.
+    fn evaluate(&self, sheet: &Spreadsheet) -> CellValue {
+        let (left, right) = match (self.left.value(sheet), self.right.value(sheet)) {
+            (Ok(left), Ok(right)) => (left, right),
+            (Err(kind), _) | (_, Err(kind)) => return CellValue::Error(kind),
+        };
+        let chosen = if self.op.holds(left, right) {
+            self.then
+        } else {
+            self.otherwise
+        };
+        match chosen.value(sheet) {
+            Ok(value) => CellValue::Integer(value),
+            Err(kind) => CellValue::Error(kind),
+        }
+    }
+
+    /// Writes the formula as it would be typed, e.g. `IF(B1>10,100,0)`.
+    #[cfg(feature = "extensions")]
+    fn formula_string(&self, sheet: &Spreadsheet, row: i16, col: i16) -> String {
+        format!(
+            "IF({}{}{},{},{})",
+            self.left.name(sheet, row, col),
+            self.op.symbol(),
+            self.right.name(sheet, row, col),
+            self.then.name(sheet, row, col),
+            self.otherwise.name(sheet, row, col)
+        )
+    }
+
+    /// Returns the four terms in the order written.
+    fn terms(&self) -> [Term; 4] {
+        [self.left, self.right, self.then, self.otherwise]
+    }
+
+    /// Returns the four terms in the order written, for changing them.
+    #[cfg(feature = "extensions")]
+    fn terms_mut(&mut self) -> [&mut Term; 4] {
+        [
+            &mut self.left,
+            &mut self.right,
+            &mut self.then,
+            &mut self.otherwise,
+        ]
+    }
+}
+
+/// Multiplies corresponding cells of two ranges of the same shape and sums the products.
+///
+/// An error or text cell in either range, or a product or sum that does not fit in an `i32`,
+/// makes the result an error.
+fn sum_product(sheet: &Spreadsheet, first: &Range, second: &Range) -> CellValue {
+    let mut sum: i32 = 0;
+    for row in 0..=first.end_row - first.start_row {
+        for col in 0..=first.end_col - first.start_col {
+            let left = sheet.get_cell(first.start_row + row, first.start_col + col);
+            let right = sheet.get_cell(second.start_row + row, second.start_col + col);
+            let (left, right) = match (left.number(), right.number()) {
+                (Ok(left), Ok(right)) => (left, right),
+                (Err(kind), _) | (_, Err(kind)) => return CellValue::Error(kind),
+            };
+            match left
+                .checked_mul(right)
+                .and_then(|product| sum.checked_add(product))
+            {
+                Some(total) => sum = total,
+                None => return CellValue::Error(ErrorKind::Overflow),
+            }
+        }
+    }
+    CellValue::Integer(sum)
+}
+
+/// Writes a range as it would be typed in the cell at `row`, `col`, e.g. `A1:B3` or
+/// `$A$1:RC[-1]`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `row` - The formula cell's row.
+/// * `col` - The formula cell's column.
+/// * `range` - The range.
+/// * `flags` - The flags of its corners, as `*_PARENT1` for the start and `*_PARENT2` for
+///   the end
+#[cfg(feature = "extensions")]
+fn range_name(sheet: &Spreadsheet, row: i16, col: i16, range: &Range, flags: u8) -> String {
+    let start = sheet.get_key(range.start_row, range.start_col);
+    let end = sheet.get_key(range.end_row, range.end_col);
+    format!(
+        "{}:{}",
+        parent_name(sheet, row, col, start, RELATIVE_PARENT1, flags),
+        parent_name(sheet, row, col, end, RELATIVE_PARENT2, flags)
+    )
+}
+
+/// An arithmetic formula with more than one operator or a range function among its
+/// operands, e.g. `(B1+C1)/2` or `SUM(A1:A5)+10`.
+///
+/// # Variants
+///
+/// * `Term(Term)` - A literal or a cell.
+/// * `Function(Function, Range, u8)` - A function over a range, with the reference flags
+///   of its corners.
+/// * `Binary(Box<ArithNode>, BinaryOp, Box<ArithNode>)` - Two nodes and an operator.
+/// * `Negate(Box<ArithNode>)` - The node with its sign flipped
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArithNode {
+    Term(Term),
+    Function(Function, Range, u8),
+    Binary(Box<ArithNode>, BinaryOp, Box<ArithNode>),
+    Negate(Box<ArithNode>),
+}
+
+impl ArithNode {
+    /// Appends the single cells the node reads with their flags, in the order written.
+    fn cells(&self, cells: &mut Vec<(i32, u8)>) {
+        match self {
+            ArithNode::Term(Term::Cell(key, flags)) => cells.push((*key, *flags)),
+            ArithNode::Term(Term::Number(_)) | ArithNode::Function(..) => {}
+            ArithNode::Binary(left, _, right) => {
+                left.cells(cells);
+                right.cells(cells);
+            }
+            ArithNode::Negate(node) => node.cells(cells),
+        }
+    }
+
+    /// Appends the ranges the node reads with their flags, in the order written.
+    fn ranges(&self, ranges: &mut Vec<(Range, u8)>) {
+        match self {
+            ArithNode::Term(_) => {}
+            ArithNode::Function(_, range, flags) => ranges.push((*range, *flags)),
+            ArithNode::Binary(left, _, right) => {
+                left.ranges(ranges);
+                right.ranges(ranges);
+            }
+            ArithNode::Negate(node) => node.ranges(ranges),
+        }
+    }
+
+    /// Computes the node's value; an error anywhere below it makes the result an error.
+    fn evaluate(&self, sheet: &Spreadsheet) -> CellValue {
+        match self {
+            ArithNode::Term(term) => match term.value(sheet) {
+                Ok(value) => CellValue::Integer(value),
+                Err(kind) => CellValue::Error(kind),
+            },
+            ArithNode::Function(function, range, _) => range_value(
+                sheet,
+                *function,
+                sheet.get_key(range.start_row, range.start_col),
+                sheet.get_key(range.end_row, range.end_col),
+            ),
+            ArithNode::Binary(left, op, right) => {
+                match (
+                    left.evaluate(sheet).number(),
+                    right.evaluate(sheet).number(),
+                ) {
+                    (Ok(left), Ok(right)) => op.apply(left, right),
+                    (Err(kind), _) | (_, Err(kind)) => CellValue::Error(kind),
+                }
+            }
+            // Negating i32::MIN overflows, like any other result out of range
+            ArithNode::Negate(node) => match node.evaluate(sheet).number() {
+                Ok(value) => checked_value(value.checked_neg()),
+                Err(kind) => CellValue::Error(kind),
+            },
+        }
+    }
+
+    /// Writes the node as it would be typed in the cell at `row`, `col`.
+    #[cfg(feature = "extensions")]
+    fn formula_string(&self, sheet: &Spreadsheet, row: i16, col: i16) -> String {
+        match self {
+            ArithNode::Term(term) => term.name(sheet, row, col),
+            ArithNode::Function(Function::SumIf(criterion), range, flags) => format!(
+                "SUMIF({},{})",
+                range_name(sheet, row, col, range, *flags),
+                criterion
+            ),
+            ArithNode::Function(function, range, flags) => format!(
+                "{}({})",
+                function.name(),
+                range_name(sheet, row, col, range, *flags)
+            ),
+            ArithNode::Binary(left, op, right) => format!(
+                "{}{}{}",
+                left.side_string(sheet, row, col, *op, false),
+                op.symbol(),
+                right.side_string(sheet, row, col, *op, true)
+            ),
+            // Only a cell, a function or a plain number can follow the minus directly
+            ArithNode::Negate(node) => match node.as_ref() {
+                ArithNode::Term(Term::Cell(..)) | ArithNode::Function(..) => {
+                    format!("-{}", node.formula_string(sheet, row, col))
+                }
+                ArithNode::Term(Term::Number(value)) if *value >= 0 => format!("-{}", value),
+                _ => format!("-({})", node.formula_string(sheet, row, col)),
+            },
+        }
+    }
+
+    /// Writes the node as one side of `parent`, in parentheses where reading it back
+    /// would otherwise group it differently.
+    #[cfg(feature = "extensions")]
+    fn side_string(
+        &self,
+        sheet: &Spreadsheet,
+        row: i16,
+        col: i16,
+        parent: BinaryOp,
+        is_right: bool,
+    ) -> String {
+        let text = self.formula_string(sheet, row, col);
+        let ArithNode::Binary(_, op, _) = self else {
+            return text;
+        };
+        // `^` groups to the right and the other operators to the left
+        let bare = match op.precedence().cmp(&parent.precedence()) {
+            Ordering::Less => false,
+            Ordering::Equal => is_right == (parent == BinaryOp::Pow),
+            Ordering::Greater => true,
+        };
+        if bare { text } else { format!("({})", text) }
+    }
+
+    /// Replaces the key of every single cell the node reads.
+    #[cfg(feature = "extensions")]
+    fn remap_cells(&mut self, remap: &mut impl FnMut(i32, u8) -> i32) {
+        match self {
+            ArithNode::Term(Term::Cell(key, flags)) => *key = remap(*key, *flags),
+            ArithNode::Term(Term::Number(_)) | ArithNode::Function(..) => {}
+            ArithNode::Binary(left, _, right) => {
+                left.remap_cells(remap);
+                right.remap_cells(remap);
+            }
+            ArithNode::Negate(node) => node.remap_cells(remap),
+        }
+    }
+
+    /// Replaces every range the node reads.
+    #[cfg(feature = "extensions")]
+    fn remap_ranges(&mut self, remap: &mut impl FnMut(Range, u8) -> Range) {
+        match self {
+            ArithNode::Function(_, range, flags) => *range = remap(*range, *flags),
+            ArithNode::Term(_) => {}
+            ArithNode::Binary(left, _, right) => {
+                left.remap_ranges(remap);
+                right.remap_ranges(remap);
+            }
+            ArithNode::Negate(node) => node.remap_ranges(remap),
+        }
+    }
+}
+
+/// A formula kept in `Spreadsheet::extended_formulas`.
+///
+/// # Variants
+///
+/// * `If(IfFormula)` - `IF(left op right, then, otherwise)`.
+/// * `SumProduct(Range, u8, Range, u8)` - `SUMPRODUCT` of two ranges with the same shape,
+///   each with the reference flags of its corners.
+/// * `Arithmetic(ArithNode)` - Arithmetic that does not fit the two parents of a `CellMeta`.
+/// * `Sleep(ArithNode)` - `SLEEP` of arithmetic, sleeping for the value it computes.
+/// * `Compare(ArithNode, Comparison, ArithNode)` - 1 if the comparison holds, else 0
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtendedFormula {
+    If(IfFormula),
+    SumProduct(Range, u8, Range, u8),
+    Arithmetic(ArithNode),
+    Sleep(ArithNode),
+    Compare(ArithNode, Comparison, ArithNode),
+}
+
+impl ExtendedFormula {
+    /// Lists the keys of the single cells the formula reads, in the order written; a cell
+    /// read twice is listed twice.
+    pub fn cells(&self) -> Vec<i32> {
+        self.cell_refs().into_iter().map(|(key, _)| key).collect()
+    }
+
+    /// Lists the ranges the formula reads.
+    pub fn ranges(&self) -> Vec<Range> {
+        self.range_refs()
+            .into_iter()
+            .map(|(range, _)| range)
+            .collect()
+    }
+
+    /// Lists the single cells the formula reads with their reference flags, in the order
+    /// written.
+    pub fn cell_refs(&self) -> Vec<(i32, u8)> {
+        match self {
+            ExtendedFormula::If(formula) => formula
+                .terms()
+                .into_iter()
+                .filter_map(|term| match term {
+                    Term::Cell(key, flags) => Some((key, flags)),
+                    Term::Number(_) => None,
+                })
+                .collect(),
+            ExtendedFormula::SumProduct(..) => Vec::new(),
+            ExtendedFormula::Arithmetic(node) | ExtendedFormula::Sleep(node) => {
+                let mut cells = Vec::new();
+                node.cells(&mut cells);
+                cells
+            }
+            ExtendedFormula::Compare(left, _, right) => {
+                let mut cells = Vec::new();
+                left.cells(&mut cells);
+                right.cells(&mut cells);
+                cells
+            }
+        }
+    }
+
+    /// Lists the ranges the formula reads with the reference flags of their corners.
+    pub fn range_refs(&self) -> Vec<(Range, u8)> {
+        match self {
+            ExtendedFormula::If(_) => Vec::new(),
+            ExtendedFormula::SumProduct(first, first_flags, second, second_flags) => {
+                vec![(*first, *first_flags), (*second, *second_flags)]
+            }
+            ExtendedFormula::Arithmetic(node) | ExtendedFormula::Sleep(node) => {
+                let mut ranges = Vec::new();
+                node.ranges(&mut ranges);
+                ranges
+            }
+            ExtendedFormula::Compare(left, _, right) => {
+                let mut ranges = Vec::new();
+                left.ranges(&mut ranges);
+                right.ranges(&mut ranges);
+                ranges
+            }
+        }
+    }
+
+    /// Computes the formula's value.
+    pub fn evaluate(&self, sheet: &Spreadsheet) -> CellValue {
+        match self {
+            ExtendedFormula::If(formula) => formula.evaluate(sheet),
+            ExtendedFormula::SumProduct(first, _, second, _) => sum_product(sheet, first, second),
+            ExtendedFormula::Arithmetic(node) | ExtendedFormula::Sleep(node) => {
+                node.evaluate(sheet)
+            }
+            ExtendedFormula::Compare(left, op, right) => {
+                match (
+                    left.evaluate(sheet).number(),
+                    right.evaluate(sheet).number(),
+                ) {
+                    (Ok(left), Ok(right)) => op.apply(left, right),
+                    (Err(kind), _) | (_, Err(kind)) => CellValue::Error(kind),
+                }
+            }
+        }
+    }
+
+    /// Tells whether any reference of the formula was written in relative form.
+    #[cfg(feature = "extensions")]
+    pub fn is_relative(&self) -> bool {
+        self.cell_refs()
+            .iter()
+            .any(|(_, flags)| flags & RELATIVE_PARENT1 != 0)
+            || self
+                .range_refs()
+                .iter()
+                .any(|(_, flags)| flags & (RELATIVE_PARENT1 | RELATIVE_PARENT2) != 0)
+    }
+
+    /// Writes the formula as it would be typed in the cell at `row`, `col`, e.g.
+    /// `SUMPRODUCT(A1:A5,B1:B5)`.
+    #[cfg(feature = "extensions")]
+    pub fn formula_string(&self, sheet: &Spreadsheet, row: i16, col: i16) -> String {
+        match self {
+            ExtendedFormula::If(formula) => formula.formula_string(sheet, row, col),
+            ExtendedFormula::SumProduct(first, first_flags, second, second_flags) => format!(
+                "SUMPRODUCT({},{})",
+                range_name(sheet, row, col, first, *first_flags),
+                range_name(sheet, row, col, second, *second_flags)
+            ),
+            ExtendedFormula::Arithmetic(node) => node.formula_string(sheet, row, col),
+            ExtendedFormula::Sleep(node) => {
+                format!("SLEEP({})", node.formula_string(sheet, row, col))
+            }
+            // Comparisons bind loosest, so neither side needs parentheses
+            ExtendedFormula::Compare(left, op, right) => format!(
+                "{}{}{}",
+                left.formula_string(sheet, row, col),
+                op.symbol(),
+                right.formula_string(sheet, row, col)
+            ),
+        }
+    }
+
+    /// Replaces the key of every single cell the formula reads; `remap` gets each key with
+    /// its reference flags. The flags themselves stay, as the relative and pinned flags of
+    /// other formulas do.
+    #[cfg(feature = "extensions")]
+    pub fn remap_cells(&mut self, mut remap: impl FnMut(i32, u8) -> i32) {
+        match self {
+            ExtendedFormula::If(formula) => {
+                for term in formula.terms_mut() {
+                    if let Term::Cell(key, flags) = term {
+                        *key = remap(*key, *flags);
+                    }
+                }
+            }
+            ExtendedFormula::SumProduct(..) => {}
+            ExtendedFormula::Arithmetic(node) | ExtendedFormula::Sleep(node) => {
+                node.remap_cells(&mut remap)
+            }
+            ExtendedFormula::Compare(left, _, right) => {
+                left.remap_cells(&mut remap);
+                right.remap_cells(&mut remap);
+            }
+        }
+    }
+
+    /// Replaces every range the formula reads; `remap` gets each range with the reference
+    /// flags of its corners.
+    #[cfg(feature = "extensions")]
+    pub fn remap_ranges(&mut self, mut remap: impl FnMut(Range, u8) -> Range) {
+        match self {
+            ExtendedFormula::If(_) => {}
+            ExtendedFormula::SumProduct(first, first_flags, second, second_flags) => {
+                *first = remap(*first, *first_flags);
+                *second = remap(*second, *second_flags);
+            }
+            ExtendedFormula::Arithmetic(node) | ExtendedFormula::Sleep(node) => {
+                node.remap_ranges(&mut remap)
+            }
+            ExtendedFormula::Compare(left, _, right) => {
+                left.remap_ranges(&mut remap);
+                right.remap_ranges(&mut remap);
+            }
+        }
+    }
+}
+
+/// Recomputes the extended formula of a cell; a cell without one is left alone.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `row` - The cell's row.
+/// * `col` - The cell's column.
+/// * `sleep_val` - Accumulates the sleep time of a `SLEEP` formula.
+pub fn eval_extended(sheet: &mut Spreadsheet, row: i16, col: i16, sleep_val: &mut f64) {
+    let key = sheet.get_key(row, col);
+    if let Some(formula) = sheet.extended_formulas.get(&key) {
+        let value = formula.evaluate(sheet);
+        match (formula, value) {
+            (ExtendedFormula::Sleep(_), CellValue::Integer(seconds)) => {
+                sleep_fn(sheet, row, col, seconds, sleep_val)
+            }
+            (_, value) => *sheet.get_mut_cell(row, col) = value,
+        }
+    }
+}
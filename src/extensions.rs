@@ -1,7 +1,56 @@
-use crate::cell::CellValue;
-use crate::graph::remove_all_parents;
-use crate::reevaluate_topo::toposort_reval_detect_cycle;
-use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use crate::cell::{
+    CellValue, PINNED_COL_PARENT1, PINNED_COL_PARENT2, PINNED_ROW_PARENT1, PINNED_ROW_PARENT2,
+    RELATIVE_PARENT1, RELATIVE_PARENT2, format_pinned_reference, format_relative_reference,
+};
+use crate::cell_history::HistoryFormula;
+use crate::expr_parser::{Comparison, Criterion};
+use crate::extended_formula::ExtendedFormula;
+use crate::graph::{error_paths, parents_are_cells};
+use crate::range::Range;
+use crate::reevaluate_topo::{toposort_reval_detect_cycle, toposort_reval_many};
+use crate::spreadsheet::{CellMeta, CommandStatus, Spreadsheet};
+
+/// Renders a formula parent by name, in relative form if it was entered that way, or
+/// with `$` before the parts that were pinned.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `row` - The formula cell's row.
+/// * `col` - The formula cell's column.
+/// * `parent` - Key of the parent cell.
+/// * `flag` - Which parent this is (`RELATIVE_PARENT1` or `RELATIVE_PARENT2`).
+/// * `flags` - The formula's relative and pinned reference flags
+pub fn parent_name(
+    sheet: &Spreadsheet,
+    row: i16,
+    col: i16,
+    parent: i32,
+    flag: u8,
+    flags: u8,
+) -> String {
+    let (parent_row, parent_col) = sheet.get_row_col(parent);
+    let (col_pin, row_pin) = if flag == RELATIVE_PARENT1 {
+        (PINNED_COL_PARENT1, PINNED_ROW_PARENT1)
+    } else {
+        (PINNED_COL_PARENT2, PINNED_ROW_PARENT2)
+    };
+    let pin_col = flags & col_pin != 0;
+    let pin_row = flags & row_pin != 0;
+    if flags & flag != 0 {
+        format_relative_reference(row, col, parent_row, parent_col)
+    } else if pin_col || pin_row {
+        let column = sheet.get_column_name(parent_col);
+        format_pinned_reference(&column, parent_row, pin_col, pin_row)
+    } else {
+        sheet.get_cell_name(parent_row, parent_col)
+    }
+}
+
+/// Writes the operator of a comparison formula from the tens of its code.
+fn comparison_symbol(msb: i16) -> &'static str {
+    Comparison::from_formula_msb(msb).map_or("?", |op| op.symbol())
+}
 
 /// Generates a string representation of a cell’s formula.
 ///
@@ -15,7 +64,78 @@ use crate::spreadsheet::{CommandStatus, Spreadsheet};
 ///
 /// A string like "A1+B1" or "SUM(A1:B2)", or "No formula" if none
 pub fn get_formula_string(sheet: &Spreadsheet, row: i16, col: i16) -> String {
+    let key = sheet.get_key(row, col);
     let meta = sheet.get_cell_meta_ref(row, col);
+    let parts = FormulaParts {
+        meta,
+        cells: parents_are_cells(&sheet.children, key, meta),
+        flags: sheet.relative_refs.get(&key).copied().unwrap_or(0),
+        criterion: sheet.criteria.get(&key).copied(),
+        extended: sheet.extended_formulas.get(&key),
+    };
+    formula_text(sheet, row, col, parts)
+}
+
+/// Writes the formula of a cell's history entry.
+///
+/// Entries never keep formulas of two literals, so the parents are cells.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `row` - The cell's row.
+/// * `col` - The cell's column.
+/// * `formula` - The formula the entry recorded.
+pub fn history_formula_string(
+    sheet: &Spreadsheet,
+    row: i16,
+    col: i16,
+    formula: &HistoryFormula,
+) -> String {
+    let parts = FormulaParts {
+        meta: &formula.meta,
+        cells: true,
+        flags: formula.relative.unwrap_or(0),
+        criterion: formula.criterion,
+        extended: formula.extended.as_ref(),
+    };
+    formula_text(sheet, row, col, parts)
+}
+
+/// A formula with the parts of it kept outside its metadata.
+///
+/// # Fields
+///
+/// * `meta` - The formula's code and parents.
+/// * `cells` - Whether the parents of a `formula % 10 == 0` formula are cells rather than
+///   two literals.
+/// * `flags` - The formula's relative and pinned reference flags.
+/// * `criterion` - The criterion of a `SUMIF`-style formula.
+/// * `extended` - The extended formula, for code 4.
+struct FormulaParts<'a> {
+    meta: &'a CellMeta,
+    cells: bool,
+    flags: u8,
+    criterion: Option<Criterion>,
+    extended: Option<&'a ExtendedFormula>,
+}
+
+/// Writes a formula from its parts.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for cell names.
+/// * `row` - The cell's row.
+/// * `col` - The cell's column.
+/// * `parts` - The formula.
+fn formula_text(sheet: &Spreadsheet, row: i16, col: i16, parts: FormulaParts) -> String {
+    let FormulaParts {
+        meta,
+        cells,
+        flags,
+        criterion,
+        extended,
+    } = parts;
     if meta.formula == -1 {
         return "No formula".to_string();
     }
@@ -26,87 +146,212 @@ pub fn get_formula_string(sheet: &Spreadsheet, row: i16, col: i16) -> String {
 
     match rem {
         0 => {
-            let (left, right) = {
-                let (left_row, left_col) = sheet.get_row_col(parent1);
-                let (right_row, right_col) = sheet.get_row_col(parent2);
-                let left_name = sheet.get_cell_name(left_row, left_col);
-                let right_name = sheet.get_cell_name(right_row, right_col);
-                (left_name, right_name)
+            // Two literals share the code of two cells
+            let (left, right) = if cells {
+                (
+                    parent_name(sheet, row, col, parent1, RELATIVE_PARENT1, flags),
+                    parent_name(sheet, row, col, parent2, RELATIVE_PARENT2, flags),
+                )
+            } else {
+                (parent1.to_string(), parent2.to_string())
             };
             match msb {
                 1 => format!("{}+{}", left, right),
                 2 => format!("{}-{}", left, right),
                 3 => format!("{}/{}", left, right),
+                5 => format!("{}%{}", left, right),
+                6 => format!("{}^{}", left, right),
+                7 => format!("MIN2({},{})", left, right),
+                11 => format!("MAX2({},{})", left, right),
+                13 => format!("ROUNDM({},{})", left, right),
+                14..=19 => format!("{}{}{}", left, comparison_symbol(msb), right),
                 _ => format!("{}*{}", left, right),
             }
         }
         2 => {
-            let (left, right) = {
-                let (left_row, left_col) = sheet.get_row_col(parent1);
-                let left_name = sheet.get_cell_name(left_row, left_col);
-                (left_name, parent2.to_string())
-            };
+            let left = parent_name(sheet, row, col, parent1, RELATIVE_PARENT1, flags);
+            let right = parent2.to_string();
             match msb {
                 1 => format!("{}+{}", left, right),
                 2 => format!("{}-{}", left, right),
                 4 => format!("{}*{}", left, right),
                 3 => format!("{}/{}", left, right),
+                5 => format!("{}%{}", left, right),
+                6 => format!("{}^{}", left, right),
+                7 => format!("MIN2({},{})", left, right),
+                11 => format!("MAX2({},{})", left, right),
+                13 => format!("ROUNDM({},{})", left, right),
+                14..=19 => format!("{}{}{}", left, comparison_symbol(msb), right),
+                12 => format!("ABS({})", left),
                 8 => left.to_string(),
                 _ => format!("SLEEP({})", left),
             }
         }
         3 => {
-            let (left, right) = {
-                let (right_row, right_col) = sheet.get_row_col(parent2);
-                let right_name = sheet.get_cell_name(right_row, right_col);
-                (parent1.to_string(), right_name)
-            };
+            let left = parent1.to_string();
+            let right = parent_name(sheet, row, col, parent2, RELATIVE_PARENT2, flags);
             match msb {
                 1 => format!("{}+{}", left, right),
                 2 => format!("{}-{}", left, right),
                 3 => format!("{}/{}", left, right),
+                5 => format!("{}%{}", left, right),
+                6 => format!("{}^{}", left, right),
+                7 => format!("MIN2({},{})", left, right),
+                11 => format!("MAX2({},{})", left, right),
+                13 => format!("ROUNDM({},{})", left, right),
+                14..=19 => format!("{}{}{}", left, comparison_symbol(msb), right),
                 _ => format!("{}*{}", left, right),
             }
         }
+        4 => match extended {
+            Some(formula) => formula.formula_string(sheet, row, col),
+            None => "Unknown formula".to_string(),
+        },
         5 => {
-            let (start_row, start_col) = sheet.get_row_col(parent1);
-            let (end_row, end_col) = sheet.get_row_col(parent2);
-            let start_name = sheet.get_cell_name(start_row, start_col);
-            let end_name = sheet.get_cell_name(end_row, end_col);
-            format!("SUM({}:{})", start_name, end_name)
+            let start_name = parent_name(sheet, row, col, parent1, RELATIVE_PARENT1, flags);
+            let end_name = parent_name(sheet, row, col, parent2, RELATIVE_PARENT2, flags);
+            match msb {
+                1 => format!("MEDIAN({}:{})", start_name, end_name),
+                2 => match criterion {
+                    Some(criterion) => format!("SUMIF({}:{},{})", start_name, end_name, criterion),
+                    None => format!("SUMIF({}:{})", start_name, end_name),
+                },
+                3 => format!("PRODUCT({}:{})", start_name, end_name),
+                4 => format!("SPREAD({}:{})", start_name, end_name),
+                5 => format!("COUNTA({}:{})", start_name, end_name),
+                6 => format!("COUNTBLANK({}:{})", start_name, end_name),
+                _ => format!("SUM({}:{})", start_name, end_name),
+            }
         }
         6 => {
-            let (start_row, start_col) = sheet.get_row_col(parent1);
-            let (end_row, end_col) = sheet.get_row_col(parent2);
-            let start_name = sheet.get_cell_name(start_row, start_col);
-            let end_name = sheet.get_cell_name(end_row, end_col);
+            let start_name = parent_name(sheet, row, col, parent1, RELATIVE_PARENT1, flags);
+            let end_name = parent_name(sheet, row, col, parent2, RELATIVE_PARENT2, flags);
             format!("AVG({}:{})", start_name, end_name)
         }
         7 => {
-            let (start_row, start_col) = sheet.get_row_col(parent1);
-            let (end_row, end_col) = sheet.get_row_col(parent2);
-            let start_name = sheet.get_cell_name(start_row, start_col);
-            let end_name = sheet.get_cell_name(end_row, end_col);
+            let start_name = parent_name(sheet, row, col, parent1, RELATIVE_PARENT1, flags);
+            let end_name = parent_name(sheet, row, col, parent2, RELATIVE_PARENT2, flags);
             format!("MIN({}:{})", start_name, end_name)
         }
         8 => {
-            let (start_row, start_col) = sheet.get_row_col(parent1);
-            let (end_row, end_col) = sheet.get_row_col(parent2);
-            let start_name = sheet.get_cell_name(start_row, start_col);
-            let end_name = sheet.get_cell_name(end_row, end_col);
+            let start_name = parent_name(sheet, row, col, parent1, RELATIVE_PARENT1, flags);
+            let end_name = parent_name(sheet, row, col, parent2, RELATIVE_PARENT2, flags);
             format!("MAX({}:{})", start_name, end_name)
         }
         9 => {
-            let (start_row, start_col) = sheet.get_row_col(parent1);
-            let (end_row, end_col) = sheet.get_row_col(parent2);
-            let start_name = sheet.get_cell_name(start_row, start_col);
-            let end_name = sheet.get_cell_name(end_row, end_col);
+            let start_name = parent_name(sheet, row, col, parent1, RELATIVE_PARENT1, flags);
+            let end_name = parent_name(sheet, row, col, parent2, RELATIVE_PARENT2, flags);
             format!("STDEV({}:{})", start_name, end_name)
         }
         _ => "Unknown formula".to_string(),
     }
 }
 
+/// Explains why a cell with no erroneous parents is in error.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `key` - The cell's key.
+///
+/// # Returns
+///
+/// A description such as "division by zero: B1=A1/Y1, Y1=0"
+fn error_origin(sheet: &Spreadsheet, key: i32) -> String {
+    let (row, col) = sheet.get_row_col(key);
+    let name = sheet.get_cell_name(row, col);
+    let meta = sheet.get_cell_meta_ref(row, col);
+    let CellValue::Error(kind) = *sheet.get_cell(row, col) else {
+        return format!("{} is not in error", name);
+    };
+    if meta.formula == -1 {
+        return format!("{} entered as a value in {}", kind.code(), name);
+    }
+    let formula = format!("{}={}", name, get_formula_string(sheet, row, col));
+    let generic = || format!("{}: {}", kind.explanation(), formula);
+    let cause = match meta.formula / 10 {
+        3 => "division by zero",
+        5 => "modulo by zero",
+        _ => return generic(),
+    };
+    match meta.formula % 10 {
+        2 => format!("{}: {}", cause, formula),
+        // Two literals, as in `1/0`
+        0 if !parents_are_cells(&sheet.children, key, meta) => format!("{}: {}", cause, formula),
+        0 | 3 => {
+            let (divisor_row, divisor_col) = sheet.get_row_col(meta.parent2);
+            format!(
+                "{}: {}, {}=0",
+                cause,
+                formula,
+                sheet.get_cell_name(divisor_row, divisor_col)
+            )
+        }
+        _ => generic(),
+    }
+}
+
+/// Traces an ERR cell back to the cells where its error started.
+///
+/// Each chain is printed on one line, e.g. `D1 <- C1 <- B1 (division by zero: B1=A1/Y1, Y1=0)`.
+/// When several parents are in error, each branch gets its own line.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `row` - The cell's row.
+/// * `col` - The cell's column.
+///
+/// # Returns
+///
+/// The lines to print
+pub fn trace_error(sheet: &Spreadsheet, row: i16, col: i16) -> Vec<String> {
+    if !sheet.get_cell(row, col).is_error() {
+        return vec![format!("{} is not in error", sheet.get_cell_name(row, col))];
+    }
+    error_paths(sheet, sheet.get_key(row, col))
+        .into_iter()
+        .map(|path| {
+            let chain: Vec<String> = path
+                .keys
+                .iter()
+                .map(|&key| {
+                    let (r, c) = sheet.get_row_col(key);
+                    sheet.get_cell_name(r, c)
+                })
+                .collect();
+            let origin = *path.keys.last().unwrap();
+            let note = if path.cycle {
+                "cycle".to_string()
+            } else {
+                error_origin(sheet, origin)
+            };
+            format!("{} ({})", chain.join(" <- "), note)
+        })
+        .collect()
+}
+
+/// Explains in one line why a cell holds an error, e.g. `B1: #DIV0 (division by zero)`.
+///
+/// A cell that inherited its error from a parent keeps the kind the error started with.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `row` - The cell's row.
+/// * `col` - The cell's column.
+///
+/// # Returns
+///
+/// The line to print
+pub fn explain_error(sheet: &Spreadsheet, row: i16, col: i16) -> String {
+    let name = sheet.get_cell_name(row, col);
+    match sheet.get_cell(row, col) {
+        CellValue::Error(kind) => format!("{}: {} ({})", name, kind.code(), kind.explanation()),
+        _ => format!("{} is not in error", name),
+    }
+}
+
 /// Sets a cell's value directly, bypassing formula evaluation.
 ///
 /// # Arguments
@@ -121,6 +366,8 @@ pub fn get_formula_string(sheet: &Spreadsheet, row: i16, col: i16) -> String {
 ///
 /// * `CommandStatus::CmdOk` - On success.
 /// * `CommandStatus::LockedCell` - If the cell is locked.
+/// * `CommandStatus::NotOwner` - If the cell is owned by another user and ownership is enforced.
+/// * `CommandStatus::ReadOnly` - If the sheet is read-only.
 pub fn set_cell_to_value(
     sheet: &mut Spreadsheet,
     row: i16,
@@ -128,24 +375,230 @@ pub fn set_cell_to_value(
     value: CellValue,
     sleep_time: &mut f64,
 ) -> CommandStatus {
+    if sheet.read_only {
+        return CommandStatus::ReadOnly;
+    }
     // Check if the cell is locked before setting the value
     if sheet.is_cell_locked(row, col) {
         return CommandStatus::LockedCell;
     }
+    if sheet.check_owner(row, col) == CommandStatus::NotOwner {
+        return CommandStatus::NotOwner;
+    }
     // Check if the value is a valid integer
     let cell_key = sheet.get_key(row, col);
-    // remove all parents and set the value
-    remove_all_parents(sheet, row, col);
-    sheet.cell_meta.remove(&cell_key);
+    sheet.note_undo(cell_key);
+    let before = sheet.cell_contribution(cell_key);
+    // drop the formula and set the value
+    sheet.clear_formula(row, col);
     *sheet.get_mut_cell(row, col) = value;
+    sheet.assigned.insert(cell_key);
+    sheet.record_cell_change(cell_key, before);
     toposort_reval_detect_cycle(sheet, row, col, sleep_time);
     sheet.set_last_edited(row, col);
     CommandStatus::CmdOk
 }
 
+/// Counts of what a bulk write did.
+///
+/// # Fields
+///
+/// * `written` - Cells that received a new value.
+/// * `skipped_locked` - Cells left untouched because they are locked.
+/// * `skipped_owned` - Cells left untouched because another user owns them.
+#[derive(Debug, Default, PartialEq)]
+pub struct BulkWriteReport {
+    pub written: usize,
+    pub skipped_locked: usize,
+    pub skipped_owned: usize,
+}
+
+/// Writes literal values to many cells, then reevaluates their dependents once.
+///
+/// Each write replaces any formula in the target cell and records the previous
+/// value in the cell history, like a regular assignment. Locked cells, and cells owned by
+/// another user while ownership is enforced, are skipped.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `writes` - The `(row, col, value)` triples to apply.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// A `BulkWriteReport` with the number of cells written and skipped
+pub fn bulk_set_values(
+    sheet: &mut Spreadsheet,
+    writes: &[(i16, i16, CellValue)],
+    sleep_time: &mut f64,
+) -> BulkWriteReport {
+    let mut report = BulkWriteReport::default();
+    let mut updated_keys = Vec::with_capacity(writes.len());
+
+    for (row, col, value) in writes {
+        let (row, col) = (*row, *col);
+        if sheet.is_cell_locked(row, col) {
+            report.skipped_locked += 1;
+            continue;
+        }
+        if sheet.check_owner(row, col) != CommandStatus::CmdOk {
+            report.skipped_owned += 1;
+            continue;
+        }
+        let cell_key = sheet.get_key(row, col);
+        let before = sheet.cell_contribution(cell_key);
+        let old_entry = sheet.history_entry(cell_key);
+        sheet.push_history(cell_key, old_entry);
+        sheet.clear_formula(row, col);
+        *sheet.get_mut_cell(row, col) = value.clone();
+        sheet.record_cell_change(cell_key, before);
+        sheet.set_last_edited(row, col);
+        updated_keys.push(cell_key);
+        report.written += 1;
+    }
+
+    toposort_reval_many(sheet, &updated_keys, sleep_time);
+    report
+}
+
+/// Returns every cell in a range to its pristine state, as if it had never been set.
+///
+/// Values, formulas and history are dropped and the cells no longer count as filled;
+/// their dependents are reevaluated once at the end. Nothing is deleted if any cell in
+/// the range is locked or owned by another user.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `range` - The cells to delete.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::LockedCell` - If a cell in the range is locked.
+/// * `CommandStatus::NotOwner` - If a cell is owned by another user and ownership is enforced.
+pub fn delete_cells(sheet: &mut Spreadsheet, range: &Range, sleep_time: &mut f64) -> CommandStatus {
+    if range
+        .cells()
+        .any(|(row, col)| sheet.is_cell_locked(row, col))
+    {
+        return CommandStatus::LockedCell;
+    }
+    if range
+        .cells()
+        .any(|(row, col)| sheet.check_owner(row, col) == CommandStatus::NotOwner)
+    {
+        return CommandStatus::NotOwner;
+    }
+
+    let mut deleted_keys = Vec::new();
+    for (row, col) in range.cells() {
+        let cell_key = sheet.get_key(row, col);
+        let before = sheet.cell_contribution(cell_key);
+        sheet.clear_formula(row, col);
+        if *sheet.get_cell(row, col) != CellValue::Empty {
+            *sheet.get_mut_cell(row, col) = CellValue::Empty;
+        }
+        sheet.assigned.remove(&cell_key);
+        sheet.journal_history(cell_key);
+        sheet.cell_history.remove(&cell_key);
+        sheet.record_cell_change(cell_key, before);
+        deleted_keys.push(cell_key);
+    }
+
+    toposort_reval_many(sheet, &deleted_keys, sleep_time);
+    sheet.set_last_edited(range.start_row, range.start_col);
+    CommandStatus::CmdOk
+}
+
+/// Outcome of a `replace` command.
+///
+/// # Fields
+///
+/// * `changed` - Literal cells that were (or, in a dry run, would be) replaced.
+/// * `skipped_formula` - Formula cells whose value matched; never modified.
+/// * `skipped_locked` - Locked literal cells whose value matched.
+/// * `skipped_owned` - Literal cells whose value matched, owned by another user while
+///   ownership is enforced.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReplaceReport {
+    pub changed: Vec<(i16, i16)>,
+    pub skipped_formula: Vec<(i16, i16)>,
+    pub skipped_locked: Vec<(i16, i16)>,
+    pub skipped_owned: Vec<(i16, i16)>,
+}
+
+/// Replaces every literal cell holding `old` with `new`.
+///
+/// Formula cells that evaluate to `old` are reported but left alone, since overwriting
+/// them would silently detach them from their inputs.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `old` - The value to look for; an error stands for errors of every kind.
+/// * `new` - The replacement value.
+/// * `range` - Optional range restricting the search.
+/// * `dry_run` - If true, only report matches without modifying the sheet.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// A `ReplaceReport` listing the affected cells
+pub fn replace_values(
+    sheet: &mut Spreadsheet,
+    old: &CellValue,
+    new: CellValue,
+    range: Option<&Range>,
+    dry_run: bool,
+    sleep_time: &mut f64,
+) -> ReplaceReport {
+    let (start_row, start_col, end_row, end_col) = match range {
+        Some(r) => (r.start_row, r.start_col, r.end_row, r.end_col),
+        None => (0, 0, sheet.rows - 1, sheet.cols - 1),
+    };
+
+    let mut report = ReplaceReport::default();
+    for row in start_row..=end_row {
+        for col in start_col..=end_col {
+            // An error matches an error of any kind
+            let value = sheet.get_cell(row, col);
+            if value != old && !(value.is_error() && old.is_error()) {
+                continue;
+            }
+            let has_formula = sheet
+                .cell_meta
+                .get(&sheet.get_key(row, col))
+                .is_some_and(|meta| meta.formula != -1);
+            if has_formula {
+                report.skipped_formula.push((row, col));
+            } else if sheet.is_cell_locked(row, col) {
+                report.skipped_locked.push((row, col));
+            } else if sheet.check_owner(row, col) != CommandStatus::CmdOk {
+                report.skipped_owned.push((row, col));
+            } else {
+                report.changed.push((row, col));
+            }
+        }
+    }
+
+    if !dry_run {
+        let writes: Vec<(i16, i16, CellValue)> = report
+            .changed
+            .iter()
+            .map(|&(row, col)| (row, col, new.clone()))
+            .collect();
+        bulk_set_values(sheet, &writes, sleep_time);
+    }
+    report
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cell::ErrorKind;
     use crate::save_load::{load_spreadsheet, save_spreadsheet};
     use crate::spreadsheet::Spreadsheet;
     use std::fs::{self, File};
@@ -162,6 +615,9 @@ mod tests {
         meta.formula = 20; // 2 (subtraction) * 10 + 0 (both are cell refs)
         meta.parent1 = a1_pos;
         meta.parent2 = b2_pos;
+        // Until the cells have the formula as a child, they read as two literals
+        assert_eq!(get_formula_string(&sheet, 0, 1), "0-11");
+        crate::graph::add_children(&mut sheet, a1_pos, b2_pos, 20, 0, 1);
         assert_eq!(get_formula_string(&sheet, 0, 1), "A1-B2");
 
         // Test multiplication (A1 * 5)
@@ -262,6 +718,187 @@ mod tests {
         assert_eq!(get_formula_string(&sheet, 3, 0), "No formula");
     }
 
+    #[test]
+    fn test_get_formula_string_relative_references() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        let mut sleep_time = 0.0;
+        for command in [
+            "C3=RC[-2]+R[-1]C",
+            "D3=5*R[-2]C[-3]",
+            "E3=AVG(R[-2]C:R[-1]C)",
+            "F3=A1-RC[-1]",
+        ] {
+            crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        assert_eq!(get_formula_string(&sheet, 2, 2), "RC[-2]+R[-1]C");
+        assert_eq!(get_formula_string(&sheet, 2, 3), "5*R[-2]C[-3]");
+        assert_eq!(get_formula_string(&sheet, 2, 4), "AVG(R[-2]C:R[-1]C)");
+        // Mixed forms keep each operand as written
+        assert_eq!(get_formula_string(&sheet, 2, 5), "A1-RC[-1]");
+    }
+
+    #[test]
+    fn test_bulk_set_values_single_reevaluation() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut sleep_time = 0.0;
+        crate::evaluator::handle_command(&mut sheet, "C1=SUM(A1:B1)", &mut sleep_time);
+        crate::evaluator::handle_command(&mut sheet, "lock_cell B2", &mut sleep_time);
+
+        let report = bulk_set_values(
+            &mut sheet,
+            &[
+                (0, 0, CellValue::Integer(2)),
+                (0, 1, CellValue::Integer(3)),
+                (1, 1, CellValue::Integer(9)),
+            ],
+            &mut sleep_time,
+        );
+        assert_eq!(
+            report,
+            BulkWriteReport {
+                written: 2,
+                skipped_locked: 1,
+                skipped_owned: 0
+            }
+        );
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(5));
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Empty);
+        assert_eq!(
+            sheet.cell_history.get(&sheet.get_key(0, 0)),
+            Some(&vec![(CellValue::Empty, None)])
+        );
+    }
+
+    #[test]
+    fn test_replace_values_literals_and_dependents() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut sleep_time = 0.0;
+        for command in ["A1=999", "A2=999", "A3=1", "B1=A1+A2", "C1=A1"] {
+            crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time);
+        }
+
+        let report = replace_values(
+            &mut sheet,
+            &CellValue::Integer(999),
+            CellValue::Integer(0),
+            None,
+            false,
+            &mut sleep_time,
+        );
+        assert_eq!(report.changed, vec![(0, 0), (1, 0)]);
+        // C1 evaluated to 999 through its formula and is left alone
+        assert_eq!(report.skipped_formula, vec![(0, 2)]);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(0));
+        assert_eq!(get_formula_string(&sheet, 0, 2), "A1");
+    }
+
+    #[test]
+    fn test_replace_values_locked_range_and_dry_run() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut sleep_time = 0.0;
+        for command in ["A1=7", "A2=7", "B1=7", "lock_cell A2"] {
+            crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        let column_a = Range {
+            start_row: 0,
+            start_col: 0,
+            end_row: 4,
+            end_col: 0,
+        };
+
+        let dry = replace_values(
+            &mut sheet,
+            &CellValue::Integer(7),
+            CellValue::Integer(1),
+            Some(&column_a),
+            true,
+            &mut sleep_time,
+        );
+        assert_eq!(dry.changed, vec![(0, 0)]);
+        assert_eq!(dry.skipped_locked, vec![(1, 0)]);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(7));
+
+        replace_values(
+            &mut sheet,
+            &CellValue::Integer(7),
+            CellValue::Integer(1),
+            Some(&column_a),
+            false,
+            &mut sleep_time,
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(1));
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(7));
+        // Outside the range
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(7));
+    }
+
+    #[test]
+    fn test_delete_cells_resets_to_never_set() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut sleep_time = 0.0;
+        for command in ["A1=5", "A1=7", "B1=A1+1", "C1=AVG(A1:A2)", "A2=0"] {
+            crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        assert_eq!(
+            crate::evaluator::handle_command(&mut sheet, "delete A1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        let a1 = sheet.get_key(0, 0);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Empty);
+        assert!(!sheet.assigned.contains(&a1));
+        assert!(!sheet.cell_history.contains_key(&a1));
+        // Dependents recompute: a reference reads 0, the average only sees A2
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(1));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(0));
+
+        // A formula cell loses its formula and its parent edges
+        crate::evaluator::handle_command(&mut sheet, "delete A2:B1", &mut sleep_time);
+        let b1 = sheet.get_key(0, 1);
+        assert!(!sheet.cell_meta.contains_key(&b1));
+        assert!(!sheet.children.contains_key(&a1));
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Empty);
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_delete_cells_refuses_locked_cells() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut sleep_time = 0.0;
+        for command in ["A1=1", "A2=2", "lock_cell A2"] {
+            crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        assert_eq!(
+            crate::evaluator::handle_command(&mut sheet, "delete A1:A3", &mut sleep_time),
+            CommandStatus::LockedCell
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(1));
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(2));
+    }
+
+    #[test]
+    fn test_replace_values_clears_errors() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut sleep_time = 0.0;
+        *sheet.get_mut_cell(0, 0) = CellValue::Error(ErrorKind::Overflow);
+        crate::evaluator::handle_command(&mut sheet, "B1=A1+1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Error(ErrorKind::Overflow));
+
+        let report = replace_values(
+            &mut sheet,
+            &CellValue::Error(ErrorKind::ParseErr),
+            CellValue::Integer(0),
+            None,
+            false,
+            &mut sleep_time,
+        );
+        assert_eq!(report.changed, vec![(0, 0)]);
+        assert_eq!(report.skipped_formula, vec![(0, 1)]);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(1));
+    }
+
     #[test]
     fn test_save_spreadsheet_invalid_path() {
         let sheet = Spreadsheet::create(5, 5).unwrap();
@@ -364,4 +1001,165 @@ mod tests {
         // Clean up
         let _ = fs::remove_file(filename);
     }
+
+    /// Builds a 5x26 sheet where B1 = A1/Y1 divides by zero.
+    fn error_chain_sheet() -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(5, 26).unwrap();
+        let mut sleep_time = 0.0;
+        for command in ["Y1=0", "A1=5", "B1=A1/Y1", "C1=B1+1", "D1=C1*2"] {
+            assert_eq!(
+                crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        sheet
+    }
+
+    #[test]
+    fn test_trace_error_linear_chain() {
+        let mut sheet = error_chain_sheet();
+        assert_eq!(
+            trace_error(&sheet, 0, 3),
+            vec!["D1 <- C1 <- B1 (division by zero: B1=A1/Y1, Y1=0)"]
+        );
+        assert_eq!(
+            trace_error(&sheet, 0, 1),
+            vec!["B1 (division by zero: B1=A1/Y1, Y1=0)"]
+        );
+
+        // Through the command, output goes to the sink
+        sheet.output = crate::output::OutputSink::capture();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            crate::evaluator::handle_command(&mut sheet, "trace_error D1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.output.take().len(), 1);
+    }
+
+    #[test]
+    fn test_trace_error_branching_chain() {
+        let mut sheet = error_chain_sheet();
+        let mut sleep_time = 0.0;
+        for command in ["E1=1/Y1", "F1=B1+E1", "G1=A1/0", "H1=G1-F1"] {
+            assert_eq!(
+                crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(
+            trace_error(&sheet, 0, 7),
+            vec![
+                "H1 <- G1 (division by zero: G1=A1/0)",
+                "H1 <- F1 <- B1 (division by zero: B1=A1/Y1, Y1=0)",
+                "H1 <- F1 <- E1 (division by zero: E1=1/Y1, Y1=0)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_error_literal_divisor() {
+        let mut sheet = error_chain_sheet();
+        let mut sleep_time = 0.0;
+        for command in ["C3=1/0", "C4=7%0", "C5=C3+1"] {
+            assert_eq!(
+                crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(
+            trace_error(&sheet, 2, 2),
+            vec!["C3 (division by zero: C3=1/0)"]
+        );
+        assert_eq!(
+            trace_error(&sheet, 3, 2),
+            vec!["C4 (modulo by zero: C4=7%0)"]
+        );
+        assert_eq!(
+            trace_error(&sheet, 4, 2),
+            vec!["C5 <- C3 (division by zero: C3=1/0)"]
+        );
+    }
+
+    #[test]
+    fn test_trace_error_modulo_and_power() {
+        let mut sheet = error_chain_sheet();
+        let mut sleep_time = 0.0;
+        for command in ["E1=A1%Y1", "F1=A1%0", "G1=A1^-1"] {
+            assert_eq!(
+                crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(
+            trace_error(&sheet, 0, 4),
+            vec!["E1 (modulo by zero: E1=A1%Y1, Y1=0)"]
+        );
+        assert_eq!(
+            trace_error(&sheet, 0, 5),
+            vec!["F1 (modulo by zero: F1=A1%0)"]
+        );
+        assert_eq!(
+            trace_error(&sheet, 0, 6),
+            vec!["G1 (the result does not fit in a 32-bit integer: G1=A1^-1)"]
+        );
+    }
+
+    #[test]
+    fn test_trace_error_cause_inside_range() {
+        let mut sheet = error_chain_sheet();
+        let mut sleep_time = 0.0;
+        for command in ["A2=5", "A3=B1", "A4=7", "C5=SUM(A2:A4)"] {
+            assert_eq!(
+                crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(*sheet.get_cell(4, 2), CellValue::Error(ErrorKind::DivZero));
+        assert_eq!(
+            trace_error(&sheet, 4, 2),
+            vec!["C5 <- A3 <- B1 (division by zero: B1=A1/Y1, Y1=0)"]
+        );
+
+        // An error typed in as a value is its own origin
+        let key = sheet.get_key(1, 0);
+        *sheet.get_mut_key_cell(key) = CellValue::Error(ErrorKind::DivZero);
+        assert_eq!(
+            trace_error(&sheet, 4, 2),
+            vec!["C5 <- A2 (#DIV0 entered as a value in A2)"]
+        );
+    }
+
+    #[test]
+    fn test_explain_error_keeps_first_kind() {
+        let mut sheet = error_chain_sheet();
+        let mut sleep_time = 0.0;
+        for command in ["G1=A1^-1", "H1=G1+B1", "I1=B1+G1"] {
+            assert_eq!(
+                crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert_eq!(explain_error(&sheet, 0, 3), "D1: #DIV0 (division by zero)");
+        assert_eq!(
+            explain_error(&sheet, 0, 7),
+            "H1: #OVF (the result does not fit in a 32-bit integer)"
+        );
+        assert_eq!(explain_error(&sheet, 0, 8), "I1: #DIV0 (division by zero)");
+        assert_eq!(explain_error(&sheet, 0, 0), "A1 is not in error");
+
+        sheet.output = crate::output::OutputSink::capture();
+        assert_eq!(
+            crate::evaluator::handle_command(&mut sheet, "error C1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.output.take(), vec!["C1: #DIV0 (division by zero)"]);
+    }
+
+    #[test]
+    fn test_trace_error_on_cell_without_error() {
+        let sheet = error_chain_sheet();
+        assert_eq!(trace_error(&sheet, 0, 0), vec!["A1 is not in error"]);
+        assert_eq!(trace_error(&sheet, 4, 4), vec!["E5 is not in error"]);
+    }
 }
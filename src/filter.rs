@@ -0,0 +1,228 @@
+//! Row filters that hide rows from the display without touching their data.
+//!
+//! A filter only changes what is shown and what `stats --visible` reads. Formulas keep
+//! reading every row, filtered or not, as in other spreadsheets. Filters are not saved.
+
+use crate::condition::{Condition, parse_condition};
+use crate::spreadsheet::{CommandStatus, MAX_COLUMN_NAME_LEN, Spreadsheet};
+
+/// The active row filter: rows whose value in `col` fails `condition` are hidden.
+///
+/// # Fields
+///
+/// * `col` - The column tested.
+/// * `condition` - The test a row must pass to stay visible.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowFilter {
+    pub col: i16,
+    pub condition: Condition,
+}
+
+impl Spreadsheet {
+    /// Checks whether a row passes the active filter; every row does when none is set.
+    pub fn is_row_visible(&self, row: i16) -> bool {
+        match &self.row_filter {
+            Some(filter) => filter.condition.matches(self.get_cell(row, filter.col)),
+            None => true,
+        }
+    }
+
+    /// Counts the rows of the sheet that pass the active filter.
+    pub fn visible_row_count(&self) -> usize {
+        (0..self.rows)
+            .filter(|&row| self.is_row_visible(row))
+            .count()
+    }
+
+    /// Describes the active filter for display, e.g. "filter B > 100: 3 of 20 rows shown".
+    ///
+    /// # Returns
+    ///
+    /// * `Some(String)` - The description, if a filter is active.
+    /// * `None` - If no filter is set
+    pub fn filter_banner(&self) -> Option<String> {
+        let filter = self.row_filter.as_ref()?;
+        Some(format!(
+            "filter {} {}: {} of {} rows shown",
+            self.get_column_name(filter.col),
+            filter.condition.describe(),
+            self.visible_row_count(),
+            self.rows
+        ))
+    }
+}
+
+/// Handles `filter <col> <condition>`, `filter clear`, and `filter status`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `args` - The text after `filter `.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::InvalidCell` - If the column is outside the sheet.
+/// * `CommandStatus::Unrecognized` - For bad syntax
+pub fn handle_filter(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    match args.trim() {
+        "clear" => {
+            sheet.row_filter = None;
+            return CommandStatus::CmdOk;
+        }
+        "status" => {
+            let line = sheet
+                .filter_banner()
+                .unwrap_or_else(|| "No filter".to_string());
+            sheet.output.line(line);
+            return CommandStatus::CmdOk;
+        }
+        _ => {}
+    }
+
+    let args = args.trim();
+    let split = args
+        .find(|c: char| !c.is_ascii_uppercase())
+        .unwrap_or(args.len());
+    let (col_name, condition) = args.split_at(split);
+    if col_name.is_empty() || col_name.len() > MAX_COLUMN_NAME_LEN {
+        return CommandStatus::Unrecognized;
+    }
+    let col = sheet.column_name_to_index(col_name);
+    if col >= sheet.cols {
+        return CommandStatus::InvalidCell;
+    }
+    let condition = match parse_condition(condition) {
+        Ok(condition) => condition,
+        Err(status) => return status,
+    };
+    sheet.row_filter = Some(RowFilter { col, condition });
+    let line = sheet.filter_banner().unwrap_or_default();
+    sheet.output.line(line);
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::CellValue;
+    use crate::evaluator::handle_command;
+    use crate::output::OutputSink;
+
+    /// B1:B6 hold 50, 150, 100, 200, 1/0, 120; C1 sums them.
+    fn fixture() -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(6, 3).unwrap();
+        sheet.output = OutputSink::capture();
+        let mut sleep_time = 0.0;
+        for command in [
+            "B1=50", "B2=150", "B3=100", "B4=200", "B5=1/0", "B6=120", "A1=1", "A2=2",
+        ] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        sheet
+    }
+
+    #[test]
+    fn test_filter_and_status() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "filter B > 100", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            sheet.output.take(),
+            vec!["filter B > 100: 3 of 6 rows shown"]
+        );
+        assert!(sheet.is_row_visible(1));
+        assert!(!sheet.is_row_visible(2));
+        // Error rows are always filtered out
+        assert!(!sheet.is_row_visible(4));
+
+        // The filter follows the data as it changes
+        handle_command(&mut sheet, "B3=101", &mut sleep_time);
+        handle_command(&mut sheet, "filter status", &mut sleep_time);
+        assert_eq!(
+            sheet.output.take(),
+            vec!["filter B > 100: 4 of 6 rows shown"]
+        );
+
+        assert_eq!(
+            handle_command(&mut sheet, "filter clear", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert!(sheet.is_row_visible(4));
+        handle_command(&mut sheet, "filter status", &mut sleep_time);
+        assert_eq!(sheet.output.take(), vec!["No filter"]);
+    }
+
+    #[test]
+    fn test_filter_rejects_bad_input() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        for (command, status) in [
+            ("filter B", CommandStatus::Unrecognized),
+            ("filter B ~ 3", CommandStatus::Unrecognized),
+            ("filter b > 3", CommandStatus::Unrecognized),
+            ("filter Z > 3", CommandStatus::InvalidCell),
+        ] {
+            assert_eq!(handle_command(&mut sheet, command, &mut sleep_time), status);
+        }
+        assert!(sheet.row_filter.is_none());
+    }
+
+    #[test]
+    fn test_formulas_ignore_filter() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "C1=SUM(A1:A6)", &mut sleep_time);
+        assert_eq!(
+            handle_command(&mut sheet, "filter B >= 150", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert!(!sheet.is_row_visible(2));
+        handle_command(&mut sheet, "A3=4", &mut sleep_time);
+        // Row 3 is hidden, but SUM still reads it
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(7));
+    }
+
+    #[test]
+    fn test_filter_with_operators_containing_equals() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        for (command, shown) in [
+            ("filter B = 150", "1 of 6"),
+            ("filter B >= 150", "2 of 6"),
+            ("filter B <= 120", "3 of 6"),
+            ("filter B != 150", "4 of 6"),
+        ] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                command
+            );
+            let banner = format!("{}: {} rows shown", command, shown);
+            assert_eq!(sheet.output.take(), vec![banner]);
+        }
+    }
+
+    #[test]
+    fn test_render_skips_filtered_rows() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "filter B > 100", &mut sleep_time);
+        let snapshot = sheet.snapshot_viewport();
+        assert_eq!(snapshot.row_numbers, vec![1, 3, 5]);
+        let rendered = snapshot.render();
+        let labels: Vec<&str> = rendered
+            .lines()
+            .skip(1)
+            .map(|line| line.split_whitespace().next().unwrap())
+            .collect();
+        assert_eq!(labels, vec!["2", "4", "6"]);
+    }
+}
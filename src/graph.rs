@@ -30,6 +30,22 @@ pub fn add_children(
         sheet.add_child(&cell1, &child_key);
     } else if rem == 3 {
         sheet.add_child(&cell2, &child_key);
+    } else if rem == 4 {
+        // An extended formula's parents are in its side table, filled in before this call
+        let parents = sheet
+            .extended_formulas
+            .get(&child_key)
+            .map(|formula| (formula.cells(), formula.ranges()));
+        if let Some((cells, ranges)) = parents {
+            for cell in cells {
+                sheet.add_child(&cell, &child_key);
+            }
+            for range in ranges {
+                let start_key = sheet.get_key(range.start_row, range.start_col);
+                let end_key = sheet.get_key(range.end_row, range.end_col);
+                sheet.add_range_child(start_key, end_key, child_key);
+            }
+        }
     } else {
         // For range operations, use the optimized range_children structure
         sheet.add_range_child(cell1, cell2, child_key);
@@ -46,6 +62,18 @@ pub fn add_children(
 pub fn remove_all_parents(sheet: &mut Spreadsheet, row: i16, col: i16) {
     // This removes the child row, col from its parent cells
     let child_key = sheet.get_key(row, col);
+    // The relative-reference flags belong to the formula being removed
+    #[cfg(feature = "extensions")]
+    sheet.relative_refs.remove(&child_key);
+    sheet.criteria.remove(&child_key);
+    if let Some(formula) = sheet.extended_formulas.remove(&child_key) {
+        for cell in formula.cells() {
+            sheet.remove_child(cell, child_key);
+        }
+        if !formula.ranges().is_empty() {
+            sheet.remove_range_child(child_key);
+        }
+    }
 
     let meta = match sheet.cell_meta.get(&child_key) {
         Some(meta) => meta,
@@ -73,10 +101,217 @@ pub fn remove_all_parents(sheet: &mut Spreadsheet, row: i16, col: i16) {
     }
 }
 
+/// A direct precedent of a cell: either a single cell or a whole range.
+///
+/// # Variants
+///
+/// * `Cell` - Key of a cell the formula reads.
+/// * `Range` - Start and end keys of a range the formula aggregates over.
+#[cfg(feature = "extensions")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Precedent {
+    Cell(i32),
+    Range(i32, i32),
+}
+
+/// Lists what a cell's formula reads from, using the formula code to tell keys from literals.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `key` - The cell's key.
+///
+/// # Returns
+///
+/// * `Vec<Precedent>` - The cell's direct precedents in formula order (empty for plain values)
+#[cfg(feature = "extensions")]
+pub fn direct_precedents(sheet: &Spreadsheet, key: i32) -> Vec<Precedent> {
+    let meta = match sheet.cell_meta.get(&key) {
+        Some(meta) if meta.formula != -1 => meta,
+        _ => return Vec::new(),
+    };
+    match meta.formula % 10 {
+        0 if !parents_are_cells(&sheet.children, key, meta) => Vec::new(),
+        0 if meta.parent1 == meta.parent2 => vec![Precedent::Cell(meta.parent1)],
+        0 => vec![Precedent::Cell(meta.parent1), Precedent::Cell(meta.parent2)],
+        2 => vec![Precedent::Cell(meta.parent1)],
+        3 => vec![Precedent::Cell(meta.parent2)],
+        4 => {
+            let Some(formula) = sheet.extended_formulas.get(&key) else {
+                return Vec::new();
+            };
+            let mut precedents = Vec::new();
+            for cell in formula.cells() {
+                if !precedents.contains(&Precedent::Cell(cell)) {
+                    precedents.push(Precedent::Cell(cell));
+                }
+            }
+            for range in formula.ranges() {
+                precedents.push(Precedent::Range(
+                    sheet.get_key(range.start_row, range.start_col),
+                    sheet.get_key(range.end_row, range.end_col),
+                ));
+            }
+            precedents
+        }
+        5..=9 => vec![Precedent::Range(meta.parent1, meta.parent2)],
+        _ => Vec::new(),
+    }
+}
+
+/// Checks whether both parents of a `formula % 10 == 0` cell are cells.
+///
+/// Two literals are stored in the same fields, but only cells have the formula registered
+/// as their child.
+#[cfg(feature = "extensions")]
+pub fn parents_are_cells(
+    children: &std::collections::HashMap<i32, std::collections::HashSet<i32>>,
+    key: i32,
+    meta: &crate::spreadsheet::CellMeta,
+) -> bool {
+    children
+        .get(&meta.parent1)
+        .is_some_and(|set| set.contains(&key))
+}
+
+/// Lists the cells whose formulas read a cell, directly or through a range.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `key` - The cell's key.
+///
+/// # Returns
+///
+/// * `Vec<i32>` - Keys of the direct dependents, sorted and without duplicates
+#[cfg(feature = "extensions")]
+pub fn direct_dependents(sheet: &Spreadsheet, key: i32) -> Vec<i32> {
+    let mut dependents: Vec<i32> = sheet
+        .get_cell_children(key)
+        .map(|children| children.iter().copied().collect())
+        .unwrap_or_default();
+    let (row, col) = sheet.get_row_col(key);
+    for rc in &sheet.range_children {
+        if rc.reads(row, col) {
+            dependents.push(rc.child_key);
+        }
+    }
+    dependents.sort_unstable();
+    dependents.dedup();
+    dependents
+}
+
+/// Upper bound on the paths `error_paths` returns, so wide diamonds stay readable.
+#[cfg(feature = "extensions")]
+const MAX_ERROR_PATHS: usize = 20;
+
+/// One chain of errors from a cell back to where the error started.
+///
+/// # Fields
+///
+/// * `keys` - Cell keys from the traced cell to the origin.
+/// * `cycle` - True if the walk stopped because the last cell was already on the chain.
+#[cfg(feature = "extensions")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorPath {
+    pub keys: Vec<i32>,
+    pub cycle: bool,
+}
+
+/// Lists the parents that pass an error into a cell.
+///
+/// A range parent contributes the first error cell inside it, in row-major order.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `key` - The cell's key.
+///
+/// # Returns
+///
+/// * `Vec<i32>` - Keys of parents holding an error
+#[cfg(feature = "extensions")]
+pub fn error_parents(sheet: &Spreadsheet, key: i32) -> Vec<i32> {
+    let is_error = |k: i32| sheet.get_key_cell(k).is_error();
+    let mut parents = Vec::new();
+    for precedent in direct_precedents(sheet, key) {
+        match precedent {
+            Precedent::Cell(k) if is_error(k) && !parents.contains(&k) => parents.push(k),
+            Precedent::Cell(_) => {}
+            Precedent::Range(start, end) => {
+                let (start_row, start_col) = sheet.get_row_col(start);
+                let (end_row, end_col) = sheet.get_row_col(end);
+                let first = (start_row..=end_row)
+                    .flat_map(|r| (start_col..=end_col).map(move |c| (r, c)))
+                    .map(|(r, c)| sheet.get_key(r, c))
+                    .find(|&k| is_error(k));
+                if let Some(k) = first
+                    && !parents.contains(&k)
+                {
+                    parents.push(k);
+                }
+            }
+        }
+    }
+    parents
+}
+
+/// Follows error parents from a cell to the cells whose error is original.
+///
+/// Branches where several parents are in error become separate paths. The walk only
+/// reads the sheet.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `key` - The cell to trace.
+///
+/// # Returns
+///
+/// * `Vec<ErrorPath>` - At most `MAX_ERROR_PATHS` paths, each starting at `key`
+#[cfg(feature = "extensions")]
+pub fn error_paths(sheet: &Spreadsheet, key: i32) -> Vec<ErrorPath> {
+    fn walk(sheet: &Spreadsheet, chain: &mut Vec<i32>, paths: &mut Vec<ErrorPath>) {
+        if paths.len() == MAX_ERROR_PATHS {
+            return;
+        }
+        let current = *chain.last().unwrap();
+        let parents = error_parents(sheet, current);
+        if parents.is_empty() {
+            paths.push(ErrorPath {
+                keys: chain.clone(),
+                cycle: false,
+            });
+            return;
+        }
+        for parent in parents {
+            let cycle = chain.contains(&parent);
+            chain.push(parent);
+            if cycle {
+                if paths.len() < MAX_ERROR_PATHS {
+                    paths.push(ErrorPath {
+                        keys: chain.clone(),
+                        cycle: true,
+                    });
+                }
+            } else {
+                walk(sheet, chain, paths);
+            }
+            chain.pop();
+        }
+    }
+
+    let mut paths = Vec::new();
+    walk(sheet, &mut vec![key], &mut paths);
+    paths
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cell::{CellValue, ErrorKind};
     use crate::spreadsheet::Spreadsheet;
+    use std::collections::BTreeSet;
 
     fn create_test_spreadsheet(rows: i16, cols: i16) -> Spreadsheet {
         Spreadsheet::create(rows, cols).unwrap()
@@ -144,4 +379,275 @@ mod tests {
         meta.formula = 5;
         remove_all_parents(&mut sheet, 2, 2);
     }
+
+    #[cfg(feature = "extensions")]
+    #[test]
+    fn test_direct_precedents_and_dependents() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let a1 = sheet.get_key(0, 0);
+        let b1 = sheet.get_key(0, 1);
+        let c1 = sheet.get_key(0, 2);
+        let a2 = sheet.get_key(1, 0);
+
+        // A2 = A1 + 5 keeps the literal out of the precedents
+        let meta = sheet.get_cell_meta(1, 0);
+        meta.formula = 12;
+        meta.parent1 = a1;
+        meta.parent2 = 5;
+        add_children(&mut sheet, a1, 5, 12, 1, 0);
+        assert_eq!(direct_precedents(&sheet, a2), vec![Precedent::Cell(a1)]);
+
+        // C1 = SUM(A1:B1)
+        let meta = sheet.get_cell_meta(0, 2);
+        meta.formula = 5;
+        meta.parent1 = a1;
+        meta.parent2 = b1;
+        add_children(&mut sheet, a1, b1, 5, 0, 2);
+        assert_eq!(
+            direct_precedents(&sheet, c1),
+            vec![Precedent::Range(a1, b1)]
+        );
+
+        assert_eq!(direct_dependents(&sheet, a1), vec![c1, a2]);
+        assert_eq!(direct_dependents(&sheet, b1), vec![c1]);
+        assert!(direct_precedents(&sheet, a1).is_empty());
+    }
+
+    #[cfg(feature = "extensions")]
+    #[test]
+    fn test_error_paths_stop_at_cycles() {
+        let mut sheet = create_test_spreadsheet(3, 3);
+        let a1 = sheet.get_key(0, 0);
+        let b1 = sheet.get_key(0, 1);
+        // A hand-built A1 -> B1 -> A1 loop that the evaluator would never allow
+        for (row, col, parent) in [(0, 0, b1), (0, 1, a1)] {
+            let meta = sheet.get_cell_meta(row, col);
+            meta.formula = 82;
+            meta.parent1 = parent;
+            *sheet.get_mut_cell(row, col) = CellValue::Error(ErrorKind::DivZero);
+        }
+        assert_eq!(
+            error_paths(&sheet, a1),
+            vec![ErrorPath {
+                keys: vec![a1, b1, a1],
+                cycle: true,
+            }]
+        );
+    }
+
+    /// The cell edges the formulas in `cell_meta` call for, as `(parent, child)` pairs.
+    fn edges_from_meta(sheet: &Spreadsheet) -> BTreeSet<(i32, i32)> {
+        let mut edges = BTreeSet::new();
+        for (&child, meta) in &sheet.cell_meta {
+            match meta.formula % 10 {
+                _ if meta.formula == -1 => {}
+                0 => {
+                    edges.insert((meta.parent1, child));
+                    edges.insert((meta.parent2, child));
+                }
+                2 => {
+                    edges.insert((meta.parent1, child));
+                }
+                3 => {
+                    edges.insert((meta.parent2, child));
+                }
+                _ => {}
+            }
+        }
+        edges
+    }
+
+    /// The cell edges stored in `children`.
+    fn stored_edges(sheet: &Spreadsheet) -> BTreeSet<(i32, i32)> {
+        sheet
+            .children
+            .iter()
+            .flat_map(|(&parent, children)| children.iter().map(move |&child| (parent, child)))
+            .collect()
+    }
+
+    fn run(sheet: &mut Spreadsheet, command: &str) -> crate::spreadsheet::CommandStatus {
+        let mut sleep_time = 0.0;
+        crate::evaluator::handle_command(sheet, command, &mut sleep_time)
+    }
+
+    #[test]
+    fn test_same_parent_twice_entry_and_propagation() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let (a1, b1) = (sheet.get_key(0, 0), sheet.get_key(0, 1));
+        run(&mut sheet, "A1=6");
+        for (formula, expected) in [("A1+A1", 12), ("A1*A1", 36), ("A1/A1", 1), ("A1-A1", 0)] {
+            run(&mut sheet, &format!("B1={}", formula));
+            assert_eq!(
+                *sheet.get_cell(0, 1),
+                CellValue::Integer(expected),
+                "{}",
+                formula
+            );
+            assert_eq!(stored_edges(&sheet), BTreeSet::from([(a1, b1)]));
+        }
+        run(&mut sheet, "B1=A1*A1");
+        run(&mut sheet, "A1=-3");
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(9));
+        run(&mut sheet, "A1=0");
+        run(&mut sheet, "B1=A1/A1");
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Error(ErrorKind::DivZero));
+        run(&mut sheet, "A1=2");
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(1));
+
+        // Switching to and from a formula with distinct parents keeps the edges exact
+        run(&mut sheet, "B1=A1+C1");
+        run(&mut sheet, "B1=A1+A1");
+        assert_eq!(stored_edges(&sheet), edges_from_meta(&sheet));
+        run(&mut sheet, "B1=5");
+        assert!(stored_edges(&sheet).is_empty());
+    }
+
+    #[test]
+    fn test_same_parent_twice_cycle_rollback() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        run(&mut sheet, "A1=4");
+        run(&mut sheet, "B1=A1+A1");
+        run(&mut sheet, "C1=B1*B1");
+        let edges = stored_edges(&sheet);
+        // Each of these closes a loop through a same-parent formula
+        for command in ["A1=C1*C1", "A1=B1-B1", "B1=B1+B1"] {
+            assert_eq!(
+                run(&mut sheet, command),
+                crate::spreadsheet::CommandStatus::CircularRef,
+                "{}",
+                command
+            );
+            assert_eq!(stored_edges(&sheet), edges, "{}", command);
+            assert_eq!(stored_edges(&sheet), edges_from_meta(&sheet));
+        }
+        run(&mut sheet, "A1=5");
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(10));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(100));
+    }
+
+    #[test]
+    fn test_same_parent_twice_sleep_reassignment() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        run(&mut sheet, "A1=0");
+        run(&mut sheet, "C1=A1+A1");
+        // B1 reads A1 as both operands, then as SLEEP's argument, then both again
+        for command in [
+            "B1=A1+A1",
+            "B1=SLEEP(A1)",
+            "B1=A1*A1",
+            "B1=SLEEP(A1)",
+            "B1=A1-A1",
+        ] {
+            assert_eq!(
+                run(&mut sheet, command),
+                crate::spreadsheet::CommandStatus::CmdOk
+            );
+            assert_eq!(stored_edges(&sheet), edges_from_meta(&sheet), "{}", command);
+        }
+        run(&mut sheet, "B1=SLEEP(A1)");
+        run(&mut sheet, "A1=0");
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(0));
+        run(&mut sheet, "B1=A1*A1");
+        run(&mut sheet, "A1=-2");
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(4));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(-4));
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_same_parent_twice_save_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("same.txt");
+        let path = path.to_str().unwrap();
+        let mut sheet = create_test_spreadsheet(5, 5);
+        run(&mut sheet, "A1=7");
+        run(&mut sheet, "B1=A1*A1");
+        run(&mut sheet, "C1=A1-A1");
+        crate::save_load::save_spreadsheet(&sheet, path);
+
+        let mut loaded = create_test_spreadsheet(5, 5);
+        crate::save_load::load_spreadsheet(&mut loaded, path);
+        assert_eq!(stored_edges(&loaded), stored_edges(&sheet));
+        assert_eq!(stored_edges(&loaded), edges_from_meta(&loaded));
+        run(&mut loaded, "A1=3");
+        assert_eq!(*loaded.get_cell(0, 1), CellValue::Integer(9));
+        assert_eq!(*loaded.get_cell(0, 2), CellValue::Integer(0));
+    }
+
+    /// Asserts no formula bookkeeping is left for `key` and the stored edges match the formulas.
+    fn assert_detached(sheet: &Spreadsheet, key: i32) {
+        assert!(!sheet.cell_meta.contains_key(&key));
+        assert!(
+            sheet
+                .children
+                .values()
+                .all(|children| !children.contains(&key))
+        );
+        assert!(sheet.range_children.iter().all(|rc| rc.child_key != key));
+        assert_eq!(stored_edges(sheet), edges_from_meta(sheet));
+    }
+
+    #[test]
+    fn test_literal_replaces_formula_and_detaches() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let b1 = sheet.get_key(0, 1);
+        run(&mut sheet, "A1=3");
+        run(&mut sheet, "B1=A1+1");
+        run(&mut sheet, "C1=B1*2");
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(8));
+
+        run(&mut sheet, "B1=7");
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(7));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(14));
+        assert!(sheet.get_cell_children(sheet.get_key(0, 0)).is_none());
+        assert_detached(&sheet, b1);
+        // C1 still reads B1
+        assert!(
+            sheet
+                .get_cell_children(b1)
+                .unwrap()
+                .contains(&sheet.get_key(0, 2))
+        );
+
+        run(&mut sheet, "B1=SUM(A1:A3)");
+        run(&mut sheet, "B1=2");
+        assert_detached(&sheet, b1);
+        run(&mut sheet, "A1=10");
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(2));
+    }
+
+    #[test]
+    fn test_clear_formula_keeps_value() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let b2 = sheet.get_key(1, 1);
+        run(&mut sheet, "A1=3");
+        run(&mut sheet, "B2=MAX(A1:A4)");
+        sheet.clear_formula(1, 1);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(3));
+        assert_detached(&sheet, b2);
+        // A cell without a formula is left as it is
+        sheet.clear_formula(1, 1);
+        assert_detached(&sheet, b2);
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_history_revert_and_load_clear_detach() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        let b1 = sheet.get_key(0, 1);
+        run(&mut sheet, "B1=5");
+        run(&mut sheet, "B1=SUM(A1:A3)");
+        run(&mut sheet, "history B1");
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(5));
+        assert_detached(&sheet, b1);
+
+        run(&mut sheet, "B1=A1+C1");
+        run(&mut sheet, "D1=AVG(A1:C1)");
+        sheet.clear();
+        assert!(sheet.cell_meta.is_empty());
+        assert!(sheet.children.is_empty());
+        assert!(sheet.range_children.is_empty());
+    }
 }
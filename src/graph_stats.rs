@@ -0,0 +1,614 @@
+//! `graph_stats`: metrics of the dependency graph, printed or written to CSV.
+//!
+//! The graph's nodes are every formula cell and every cell a formula names directly.
+//! An edge runs from a cell to each formula that reads it. A range argument such as
+//! `SUM(A1:A100)` is one edge from the range to the formula, counted once per (range,
+//! formula) pair however many cells the range covers: it adds one to the formula's
+//! in-degree and to the edge count, and nothing to the out-degree of the cells in it.
+//! Value cells read only through a range are therefore not nodes.
+//!
+//! Ranges still link formulas for the other figures. A formula cell inside a range
+//! leads on to every formula reading the range (as in `recalc_all`, whose graph and
+//! ordering these figures reuse), and formulas reading the same range share a component.
+//!
+//! * Depth - Formula cells on the longest chain ending at a cell; 0 for value cells.
+//!   The longest chain of the sheet is the largest depth.
+//! * Transitive dependents - Formula cells that read a cell directly or indirectly.
+
+use crate::graph::{Precedent, direct_precedents};
+use crate::recalc::{RecalcStep, formula_graph, order_formula_graph};
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use crate::table::{Align, Table};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// How many cells the transitive dependents ranking lists.
+const TOP_DEPENDENTS: usize = 10;
+
+/// How many component sizes are listed.
+const LISTED_COMPONENTS: usize = 10;
+
+/// Per-cell figures, as written to the CSV file.
+///
+/// # Fields
+///
+/// * `key` - The cell key.
+/// * `in_degree` - Edges into the cell: its distinct cell references plus its range.
+/// * `out_degree` - Formulas naming the cell directly.
+/// * `depth` - Formula cells on the longest chain ending at the cell.
+/// * `component` - The cell's connected component, numbered from 1 in row-major order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeStats {
+    pub key: i32,
+    pub in_degree: usize,
+    pub out_degree: usize,
+    pub depth: usize,
+    pub component: usize,
+}
+
+/// Metrics of the whole dependency graph.
+///
+/// # Fields
+///
+/// * `nodes` - Every node, in row-major order.
+/// * `formula_cells` - Nodes holding a formula.
+/// * `edges` - Edges, with ranges counted once per formula reading them.
+/// * `longest_chain` - The largest depth, and the first cell reaching it.
+/// * `component_sizes` - Cells per component, largest first.
+/// * `top_dependents` - `(key, transitive dependents)` of the cells with the most, at
+///   most `TOP_DEPENDENTS`, most first and then by key.
+#[derive(Debug, Default, PartialEq)]
+pub struct GraphStats {
+    pub nodes: Vec<NodeStats>,
+    pub formula_cells: usize,
+    pub edges: usize,
+    pub longest_chain: Option<(usize, i32)>,
+    pub component_sizes: Vec<usize>,
+    pub top_dependents: Vec<(i32, usize)>,
+}
+
+/// Disjoint sets over node indices, for the connected components.
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        UnionFind {
+            parent: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, mut node: usize) -> usize {
+        while self.parent[node] != node {
+            self.parent[node] = self.parent[self.parent[node]];
+            node = self.parent[node];
+        }
+        node
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+/// Counts the formula cells reachable from a cell's direct dependents.
+///
+/// # Arguments
+///
+/// * `start` - The direct dependents.
+/// * `successors` - The formula graph.
+///
+/// # Returns
+///
+/// * `usize` - The number of distinct formula cells reached, `start` included
+fn reachable_count(start: &[i32], successors: &BTreeMap<i32, Vec<i32>>) -> usize {
+    let mut seen: HashSet<i32> = start.iter().copied().collect();
+    let mut queue: VecDeque<i32> = seen.iter().copied().collect();
+    while let Some(key) = queue.pop_front() {
+        for &succ in &successors[&key] {
+            if seen.insert(succ) {
+                queue.push_back(succ);
+            }
+        }
+    }
+    seen.len()
+}
+
+/// Computes the dependency graph metrics of a sheet.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+///
+/// # Returns
+///
+/// * `GraphStats` - The metrics
+pub fn graph_stats(sheet: &Spreadsheet) -> GraphStats {
+    let successors = formula_graph(sheet);
+    let formula_cells = successors.len();
+
+    // Nodes: formula cells and the cells they name
+    let mut precedents: HashMap<i32, Vec<Precedent>> = HashMap::new();
+    let mut keys: Vec<i32> = successors.keys().copied().collect();
+    for &key in successors.keys() {
+        let list = direct_precedents(sheet, key);
+        keys.extend(list.iter().filter_map(|precedent| match precedent {
+            Precedent::Cell(parent) => Some(*parent),
+            Precedent::Range(..) => None,
+        }));
+        precedents.insert(key, list);
+    }
+    keys.sort_unstable();
+    keys.dedup();
+    let index: HashMap<i32, usize> = keys.iter().enumerate().map(|(i, &k)| (k, i)).collect();
+
+    // Direct dependents by name; a formula naming a cell twice counts once
+    let direct = |key: i32| -> Vec<i32> {
+        let mut list: Vec<i32> = sheet
+            .get_cell_children(key)
+            .map(|children| children.iter().copied().collect())
+            .unwrap_or_default();
+        list.sort_unstable();
+        list
+    };
+
+    // Depth, in evaluation order; a cycle's members share the depth of its entry
+    let mut predecessors: HashMap<i32, Vec<i32>> = HashMap::new();
+    for (&key, list) in &successors {
+        for &succ in list {
+            predecessors.entry(succ).or_default().push(key);
+        }
+    }
+    let steps = order_formula_graph(&successors);
+    let mut depth: HashMap<i32, usize> = HashMap::new();
+    for step in &steps {
+        let members = match step {
+            RecalcStep::Evaluate(key) => std::slice::from_ref(key),
+            RecalcStep::Cycle(members) => members.as_slice(),
+        };
+        let deepest = members
+            .iter()
+            .flat_map(|key| predecessors.get(key).into_iter().flatten())
+            .filter_map(|pred| depth.get(pred))
+            .max()
+            .copied()
+            .unwrap_or(0);
+        for &key in members {
+            depth.insert(key, deepest + 1);
+        }
+    }
+
+    // Components, with each distinct range as an extra node linking its readers
+    let mut ranges: HashMap<(i32, i32), usize> = HashMap::new();
+    for list in precedents.values() {
+        for precedent in list {
+            if let Precedent::Range(start, end) = *precedent {
+                let next = keys.len() + ranges.len();
+                ranges.entry((start, end)).or_insert(next);
+            }
+        }
+    }
+    let mut sets = UnionFind::new(keys.len() + ranges.len());
+    for (&key, list) in &precedents {
+        for precedent in list {
+            let other = match *precedent {
+                Precedent::Cell(parent) => index[&parent],
+                Precedent::Range(start, end) => ranges[&(start, end)],
+            };
+            sets.union(index[&key], other);
+        }
+    }
+    for (&key, list) in &successors {
+        for &succ in list {
+            sets.union(index[&key], index[&succ]);
+        }
+    }
+    let mut component_ids: HashMap<usize, usize> = HashMap::new();
+    let mut component_sizes: Vec<usize> = Vec::new();
+
+    let mut nodes = Vec::with_capacity(keys.len());
+    for (i, &key) in keys.iter().enumerate() {
+        let root = sets.find(i);
+        let component = *component_ids.entry(root).or_insert_with(|| {
+            component_sizes.push(0);
+            component_sizes.len()
+        });
+        component_sizes[component - 1] += 1;
+        nodes.push(NodeStats {
+            key,
+            in_degree: precedents.get(&key).map_or(0, Vec::len),
+            out_degree: sheet.get_cell_children(key).map_or(0, HashSet::len),
+            depth: depth.get(&key).copied().unwrap_or(0),
+            component,
+        });
+    }
+    component_sizes.sort_unstable_by(|a, b| b.cmp(a));
+
+    let longest_chain = nodes
+        .iter()
+        .filter(|node| node.depth > 0)
+        .max_by(|a, b| a.depth.cmp(&b.depth).then(b.key.cmp(&a.key)))
+        .map(|node| (node.depth, node.key));
+
+    // Upper bounds on transitive dependents, from the last cell to the first; exact
+    // counts are then only taken while a bound could still beat the current ranking
+    let mut bound: HashMap<i32, usize> = HashMap::new();
+    let sum_bound = |list: &[i32], bound: &HashMap<i32, usize>| {
+        list.iter()
+            .fold(0usize, |total, succ| {
+                total.saturating_add(1 + bound.get(succ).copied().unwrap_or(0))
+            })
+            .min(formula_cells)
+    };
+    for step in steps.iter().rev() {
+        match step {
+            RecalcStep::Evaluate(key) => {
+                let value = sum_bound(&successors[key], &bound);
+                bound.insert(*key, value);
+            }
+            RecalcStep::Cycle(members) => {
+                for &key in members {
+                    bound.insert(key, formula_cells);
+                }
+            }
+        }
+    }
+    let mut candidates: Vec<(usize, i32)> = keys
+        .iter()
+        .map(|&key| match successors.get(&key) {
+            Some(_) => (bound[&key], key),
+            None => (sum_bound(&direct(key), &bound), key),
+        })
+        .collect();
+    candidates.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    let mut top_dependents: Vec<(i32, usize)> = Vec::new();
+    for (limit, key) in candidates {
+        if top_dependents.len() == TOP_DEPENDENTS && limit < top_dependents[TOP_DEPENDENTS - 1].1 {
+            break;
+        }
+        let start = match successors.get(&key) {
+            Some(list) => list.clone(),
+            None => direct(key),
+        };
+        let count = reachable_count(&start, &successors);
+        top_dependents.push((key, count));
+        top_dependents.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        top_dependents.truncate(TOP_DEPENDENTS);
+    }
+    top_dependents.retain(|&(_, count)| count > 0);
+
+    GraphStats {
+        edges: nodes.iter().map(|node| node.in_degree).sum(),
+        nodes,
+        formula_cells,
+        longest_chain,
+        component_sizes,
+        top_dependents,
+    }
+}
+
+/// Formats a degree distribution, e.g. `0: 3, 1: 2, 4: 1`.
+fn distribution(degrees: impl Iterator<Item = usize>) -> String {
+    let mut counts: BTreeMap<usize, usize> = BTreeMap::new();
+    for degree in degrees {
+        *counts.entry(degree).or_default() += 1;
+    }
+    counts
+        .iter()
+        .map(|(degree, count)| format!("{}: {}", degree, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Writes one CSV row per node: cell, in_degree, out_degree, depth, component_id.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for cell names.
+/// * `stats` - The metrics.
+/// * `writer` - Where the rows go.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An error if writing fails
+pub fn write_graph_csv<W: Write>(
+    sheet: &Spreadsheet,
+    stats: &GraphStats,
+    writer: &mut W,
+) -> io::Result<()> {
+    writeln!(writer, "cell,in_degree,out_degree,depth,component_id")?;
+    for node in &stats.nodes {
+        let (row, col) = sheet.get_row_col(node.key);
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            sheet.get_cell_name(row, col),
+            node.in_degree,
+            node.out_degree,
+            node.depth,
+            node.component
+        )?;
+    }
+    writer.flush()
+}
+
+/// Handles `graph_stats` and `graph_stats --csv <file>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `args` - The text after `graph_stats`.
+///
+/// # Returns
+///
+/// * `CommandStatus` - `CmdOk`, or `Unrecognized` for bad arguments or a failed write
+pub fn handle_graph_stats(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let args = args.trim();
+    let csv = match args.strip_prefix("--csv") {
+        Some(file) if !file.trim().is_empty() => Some(file.trim()),
+        Some(_) => return CommandStatus::Unrecognized,
+        None if args.is_empty() => None,
+        None => return CommandStatus::Unrecognized,
+    };
+    let stats = graph_stats(sheet);
+
+    if let Some(file) = csv {
+        let written = File::create(file)
+            .and_then(|file| write_graph_csv(sheet, &stats, &mut BufWriter::new(file)));
+        if let Err(err) = written {
+            eprintln!("Failed to write '{}': {}", file, err);
+            return CommandStatus::Unrecognized;
+        }
+        sheet
+            .output
+            .line(format!("Wrote {} cell(s) to '{}'", stats.nodes.len(), file));
+        return CommandStatus::CmdOk;
+    }
+
+    if stats.nodes.is_empty() {
+        sheet.output.line("No formulas in the sheet");
+        return CommandStatus::CmdOk;
+    }
+    let name = |key: i32| {
+        let (row, col) = sheet.get_row_col(key);
+        sheet.get_cell_name(row, col)
+    };
+    let mut lines = vec![
+        format!(
+            "formula cells: {}, cells in graph: {}, edges: {} (a range counts once per formula reading it)",
+            stats.formula_cells,
+            stats.nodes.len(),
+            stats.edges
+        ),
+        format!(
+            "in-degree: {}",
+            distribution(stats.nodes.iter().map(|node| node.in_degree))
+        ),
+        format!(
+            "out-degree: {}",
+            distribution(stats.nodes.iter().map(|node| node.out_degree))
+        ),
+    ];
+    lines.push(match stats.longest_chain {
+        Some((depth, key)) => format!(
+            "longest chain: {} formula cell(s), ending at {}",
+            depth,
+            name(key)
+        ),
+        None => "longest chain: 0".to_string(),
+    });
+    let mut sizes: Vec<String> = stats
+        .component_sizes
+        .iter()
+        .take(LISTED_COMPONENTS)
+        .map(usize::to_string)
+        .collect();
+    if stats.component_sizes.len() > LISTED_COMPONENTS {
+        sizes.push("...".to_string());
+    }
+    lines.push(format!(
+        "components: {} (sizes: {})",
+        stats.component_sizes.len(),
+        sizes.join(", ")
+    ));
+    if !stats.top_dependents.is_empty() {
+        lines.push("most transitive dependents:".to_string());
+        let mut table = Table::new(&[("cell", Align::Left), ("dependents", Align::Right)]);
+        for &(key, count) in &stats.top_dependents {
+            table.push_row(vec![name(key), count.to_string()]);
+        }
+        lines.extend(table.render());
+    }
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::handle_command;
+    use crate::output::OutputSink;
+    use tempfile::tempdir;
+
+    fn sheet_with(rows: i16, cols: i16, commands: &[&str]) -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(rows, cols).unwrap();
+        sheet.output = OutputSink::capture();
+        let mut sleep_time = 0.0;
+        for command in commands {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                command
+            );
+        }
+        sheet
+    }
+
+    fn key(sheet: &Spreadsheet, cell: &str) -> i32 {
+        let (row, col) = crate::cell::parse_cell_reference(sheet, cell).unwrap();
+        sheet.get_key(row, col)
+    }
+
+    /// Two components: A1 -> A2 -> A3 -> SUM in B1 -> C1, and D5 -> E1.
+    fn small_sheet() -> Spreadsheet {
+        sheet_with(
+            10,
+            5,
+            &[
+                "A1=1",
+                "A2=A1+1",
+                "A3=A1*A2",
+                "B1=SUM(A1:A3)",
+                "C1=B1+A2",
+                "D5=7",
+                "E1=D5+1",
+            ],
+        )
+    }
+
+    #[test]
+    fn test_small_graph_metrics() {
+        let sheet = small_sheet();
+        let stats = graph_stats(&sheet);
+        let rows: Vec<(i32, usize, usize, usize, usize)> = stats
+            .nodes
+            .iter()
+            .map(|n| (n.key, n.in_degree, n.out_degree, n.depth, n.component))
+            .collect();
+        let k = |cell| key(&sheet, cell);
+        assert_eq!(
+            rows,
+            vec![
+                (k("A1"), 0, 2, 0, 1),
+                (k("B1"), 1, 1, 3, 1),
+                (k("C1"), 2, 0, 4, 1),
+                (k("E1"), 1, 0, 1, 2),
+                (k("A2"), 1, 2, 1, 1),
+                (k("A3"), 2, 0, 2, 1),
+                (k("D5"), 0, 1, 0, 2),
+            ]
+        );
+        assert_eq!(stats.formula_cells, 5);
+        assert_eq!(stats.edges, 7);
+        assert_eq!(stats.longest_chain, Some((4, k("C1"))));
+        assert_eq!(stats.component_sizes, vec![5, 2]);
+        assert_eq!(
+            stats.top_dependents,
+            vec![
+                (k("A1"), 4),
+                (k("A2"), 3),
+                (k("A3"), 2),
+                (k("B1"), 1),
+                (k("D5"), 1)
+            ]
+        );
+
+        let mut sheet = sheet;
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "graph_stats", &mut sleep_time);
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                "formula cells: 5, cells in graph: 7, edges: 7 (a range counts once per formula reading it)",
+                "in-degree: 0: 2, 1: 3, 2: 2",
+                "out-degree: 0: 3, 1: 2, 2: 2",
+                "longest chain: 4 formula cell(s), ending at C1",
+                "components: 2 (sizes: 5, 2)",
+                "most transitive dependents:",
+                "cell  dependents",
+                "----  ----------",
+                "A1             4",
+                "A2             3",
+                "A3             2",
+                "B1             1",
+                "D5             1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_range_counts_once_per_formula() {
+        let sheet = sheet_with(
+            200,
+            3,
+            &["A5=7", "A3=C1+1", "B1=SUM(A1:A150)", "B2=MAX(A1:A150)"],
+        );
+        let stats = graph_stats(&sheet);
+        let k = |cell| key(&sheet, cell);
+        let keys: Vec<i32> = stats.nodes.iter().map(|node| node.key).collect();
+        // A5 is only read through the ranges, so it is not a node
+        assert_eq!(keys, vec![k("B1"), k("C1"), k("B2"), k("A3")]);
+        assert_eq!(stats.edges, 3);
+        assert!(stats.nodes.iter().all(|node| node.in_degree <= 1));
+        let a3 = &stats.nodes[3];
+        assert_eq!((a3.out_degree, a3.depth), (0, 1));
+        assert_eq!(stats.nodes[0].depth, 2);
+        // The shared range links both readers and the formula inside it
+        assert_eq!(stats.component_sizes, vec![4]);
+        assert_eq!(stats.top_dependents[0], (k("C1"), 3));
+        assert_eq!(stats.top_dependents[1], (k("A3"), 2));
+    }
+
+    #[test]
+    fn test_graph_csv_schema() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("graph.csv");
+        let mut sheet = small_sheet();
+        let mut sleep_time = 0.0;
+        let command = format!("graph_stats --csv {}", path.to_str().unwrap());
+        assert_eq!(
+            handle_command(&mut sheet, &command, &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "cell,in_degree,out_degree,depth,component_id");
+        assert_eq!(lines[1], "A1,0,2,0,1");
+        assert_eq!(lines[3], "C1,2,0,4,1");
+        assert_eq!(lines[4], "E1,1,0,1,2");
+        assert_eq!(lines.len(), 8);
+        assert!(lines.iter().all(|line| line.split(',').count() == 5));
+
+        assert_eq!(
+            handle_command(&mut sheet, "graph_stats --csv", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+        let missing = dir.path().join("missing/graph.csv");
+        let command = format!("graph_stats --csv {}", missing.to_str().unwrap());
+        assert_eq!(
+            handle_command(&mut sheet, &command, &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_long_chain() {
+        let (rows, cols) = (200, 100);
+        let mut sheet = Spreadsheet::create(rows, cols).unwrap();
+        let mut sleep_time = 0.0;
+        for key in 1..rows as i32 * cols as i32 {
+            let (row, col) = sheet.get_row_col(key);
+            let (prev_row, prev_col) = sheet.get_row_col(key - 1);
+            let command = format!(
+                "{}={}+1",
+                sheet.get_cell_name(row, col),
+                sheet.get_cell_name(prev_row, prev_col)
+            );
+            handle_command(&mut sheet, &command, &mut sleep_time);
+        }
+        let stats = graph_stats(&sheet);
+        let last = rows as i32 * cols as i32 - 1;
+        assert_eq!(stats.formula_cells, 19_999);
+        assert_eq!(stats.longest_chain, Some((19_999, last)));
+        assert_eq!(stats.component_sizes, vec![20_000]);
+        assert_eq!(stats.top_dependents.len(), TOP_DEPENDENTS);
+        assert_eq!(stats.top_dependents[0], (0, 19_999));
+        assert_eq!(stats.top_dependents[9], (9, 19_990));
+    }
+}
@@ -0,0 +1,300 @@
+//! Persistence of the command history between sessions.
+//!
+//! History lives in a per-user data directory by default, is capped at a configurable
+//! number of entries, and is written through a temporary file that is renamed into
+//! place, so two instances exiting at once can never leave a half-written file.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::repl::take_flag_value;
+
+/// File name of the history file inside its directory.
+const HISTORY_FILE_NAME: &str = "command_history.txt";
+/// Directory under the user's data directory that holds the history file.
+const HISTORY_DIR_NAME: &str = "ferro_spreadsheet";
+/// Number of entries kept when no cap is configured.
+pub const DEFAULT_HISTORY_ENTRIES: usize = 1000;
+
+/// Where and how much command history is kept.
+///
+/// # Fields
+///
+/// * `path` - The history file, or `None` when persistence is disabled.
+/// * `max_entries` - The most entries kept in the file; older ones are dropped on save.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryConfig {
+    pub path: Option<PathBuf>,
+    pub max_entries: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            path: Some(default_history_path()),
+            max_entries: DEFAULT_HISTORY_ENTRIES,
+        }
+    }
+}
+
+impl HistoryConfig {
+    /// Points the history at a file, or disables persistence for `none`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The new history file path, or `none`.
+    pub fn set_path(&mut self, path: &str) {
+        self.path = match path {
+            "none" => None,
+            _ => Some(PathBuf::from(path)),
+        };
+    }
+}
+
+/// Returns the default history file location.
+///
+/// Uses `$XDG_DATA_HOME`, then `$HOME/.local/share`, and falls back to the current
+/// directory when neither is set.
+///
+/// # Returns
+///
+/// * `PathBuf` - The default history file path
+pub fn default_history_path() -> PathBuf {
+    let data_dir = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(|home| PathBuf::from(home).join(".local").join("share"))
+        });
+    match data_dir {
+        Some(dir) => dir.join(HISTORY_DIR_NAME).join(HISTORY_FILE_NAME),
+        None => PathBuf::from(HISTORY_FILE_NAME),
+    }
+}
+
+/// Removes `--history <path>` from the command-line arguments.
+///
+/// # Arguments
+///
+/// * `args` - The command-line arguments; the flag and its value are removed.
+///
+/// # Returns
+///
+/// * `Some(String)` - The path given with the flag.
+/// * `None` - If the flag is absent or has no value
+pub fn take_history_flag(args: &mut Vec<String>) -> Option<String> {
+    take_flag_value(args, "--history")
+}
+
+/// Reads the saved history.
+///
+/// # Arguments
+///
+/// * `config` - Where the history is kept.
+///
+/// # Returns
+///
+/// * `Vec<String>` - The saved entries, oldest first; empty if disabled or missing
+pub fn load_history(config: &HistoryConfig) -> Vec<String> {
+    let Some(path) = &config.path else {
+        return Vec::new();
+    };
+    let Ok(file) = File::open(path) else {
+        return Vec::new();
+    };
+    let lines: Vec<String> = BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let skip = lines.len().saturating_sub(config.max_entries);
+    lines.into_iter().skip(skip).collect()
+}
+
+/// Keeps the newest `max_entries` entries, dropping older repeats of a command.
+///
+/// # Arguments
+///
+/// * `entries` - The history, oldest first.
+/// * `max_entries` - How many entries to keep.
+///
+/// # Returns
+///
+/// * `Vec<&str>` - The entries to save, oldest first
+fn entries_to_keep(entries: &[String], max_entries: usize) -> Vec<&str> {
+    let mut seen = std::collections::HashSet::new();
+    let mut kept: Vec<&str> = entries
+        .iter()
+        .rev()
+        .map(String::as_str)
+        .filter(|entry| !entry.trim().is_empty() && seen.insert(*entry))
+        .take(max_entries)
+        .collect();
+    kept.reverse();
+    kept
+}
+
+/// Writes the history, replacing the file atomically.
+///
+/// # Arguments
+///
+/// * `config` - Where the history is kept and how much of it.
+/// * `entries` - The session's history, oldest first.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the history was written or persistence is disabled.
+/// * `Err(io::Error)` - If the file could not be written
+pub fn save_history(config: &HistoryConfig, entries: &[String]) -> io::Result<()> {
+    let Some(path) = &config.path else {
+        return Ok(());
+    };
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut contents = String::new();
+    for entry in entries_to_keep(entries, config.max_entries) {
+        contents.push_str(entry);
+        contents.push('\n');
+    }
+    StagedFile::write(path, contents.as_bytes())?.commit()
+}
+
+/// A fully written temporary file waiting to be renamed over its target.
+///
+/// Dropping it without calling `commit` deletes the temporary file and leaves the
+/// target untouched.
+///
+/// # Fields
+///
+/// * `temp` - The temporary file, next to the target so the rename stays on one filesystem.
+/// * `target` - The file it replaces.
+struct StagedFile {
+    temp: PathBuf,
+    target: PathBuf,
+}
+
+impl StagedFile {
+    /// Writes `contents` to a temporary file next to `target` and flushes it to disk.
+    fn write(target: &Path, contents: &[u8]) -> io::Result<StagedFile> {
+        let mut name = target.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.tmp", std::process::id()));
+        let staged = StagedFile {
+            temp: target.with_file_name(name),
+            target: target.to_path_buf(),
+        };
+        let mut file = File::create(&staged.temp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        Ok(staged)
+    }
+
+    /// Renames the temporary file over the target.
+    fn commit(self) -> io::Result<()> {
+        fs::rename(&self.temp, &self.target)
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        // Already gone after a successful rename
+        let _ = fs::remove_file(&self.temp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::handle_command;
+    use crate::spreadsheet::{CommandStatus, Spreadsheet};
+
+    fn entries(commands: &[&str]) -> Vec<String> {
+        commands.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn test_save_caps_and_deduplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = HistoryConfig {
+            path: Some(dir.path().join("nested").join("history.txt")),
+            max_entries: 3,
+        };
+        let session = entries(&["A1=1", "B1=2", "A1=1", "C1=3", "D1=4"]);
+        save_history(&config, &session).unwrap();
+        assert_eq!(load_history(&config), vec!["A1=1", "C1=3", "D1=4"]);
+
+        // Loading also respects a cap lowered after the file was written
+        let smaller = HistoryConfig {
+            max_entries: 2,
+            ..config.clone()
+        };
+        assert_eq!(load_history(&smaller), vec!["C1=3", "D1=4"]);
+    }
+
+    #[test]
+    fn test_set_history_file_and_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("my history.txt");
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut sleep_time = 0.0;
+
+        let command = format!("set history_file {}", path.display());
+        assert_eq!(
+            handle_command(&mut sheet, &command, &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.history.path.as_deref(), Some(path.as_path()));
+        assert_eq!(
+            handle_command(&mut sheet, "set history_size 50", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.history.max_entries, 50);
+        assert_eq!(
+            handle_command(&mut sheet, "set history_size 0", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+
+        assert_eq!(
+            handle_command(&mut sheet, "set history_file none", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.history.path, None);
+        save_history(&sheet.history, &entries(&["A1=1"])).unwrap();
+        assert!(!path.exists());
+        assert!(load_history(&sheet.history).is_empty());
+    }
+
+    #[test]
+    fn test_history_flag_is_removed_from_args() {
+        let mut args = entries(&["spreadsheet", "--vim", "--history", "h.txt", "10", "10"]);
+        assert_eq!(take_history_flag(&mut args), Some("h.txt".to_string()));
+        assert_eq!(args, entries(&["spreadsheet", "--vim", "10", "10"]));
+        assert_eq!(take_history_flag(&mut args), None);
+    }
+
+    #[test]
+    fn test_interrupted_write_leaves_old_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("history.txt");
+        fs::write(&target, "old\n").unwrap();
+
+        // Fail between writing the temporary file and renaming it
+        let staged = StagedFile::write(&target, b"new\n").unwrap();
+        let temp = staged.temp.clone();
+        assert!(temp.exists());
+        drop(staged);
+        assert!(!temp.exists());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old\n");
+
+        StagedFile::write(&target, b"new\n")
+            .unwrap()
+            .commit()
+            .unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,98 @@
+//! Consistency checks between the sheet's cached bookkeeping and its actual contents.
+//!
+//! Each check recomputes something the sheet maintains incrementally and reports any
+//! mismatch, so a missed update on some edit path shows up as a readable problem.
+
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+
+/// Runs every invariant check on the sheet.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet to check.
+///
+/// # Returns
+///
+/// * `Vec<String>` - One description per violated invariant; empty when the sheet is consistent
+pub fn verify_integrity(sheet: &Spreadsheet) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let scanned = sheet.scan_cell_counts();
+    let counted = sheet.cell_counts;
+    if counted.populated != scanned.populated {
+        problems.push(format!(
+            "populated cell counter is {} but the sheet has {}",
+            counted.populated, scanned.populated
+        ));
+    }
+    if counted.formulas != scanned.formulas {
+        problems.push(format!(
+            "formula cell counter is {} but the sheet has {}",
+            counted.formulas, scanned.formulas
+        ));
+    }
+
+    problems
+}
+
+/// Handles `verify`, printing each integrity problem or a single all-clear line.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+///
+/// # Returns
+///
+/// * `CommandStatus` - Always `CmdOk`; problems are reported in the output
+pub fn handle_verify(sheet: &mut Spreadsheet) -> CommandStatus {
+    let problems = verify_integrity(sheet);
+    if problems.is_empty() {
+        sheet.output.line("Integrity check passed");
+    } else {
+        sheet.output.lines(problems);
+    }
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::handle_command;
+    use crate::output::OutputSink;
+
+    #[test]
+    fn test_counters_match_after_edits() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        sheet.output = OutputSink::capture();
+        let mut sleep_time = 0.0;
+        for command in ["A1=3", "B1=A1*2", "C1=SUM(A1:B1)", "A1=0", "B1=7"] {
+            handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        assert!(verify_integrity(&sheet).is_empty());
+        assert_eq!(
+            handle_command(&mut sheet, "verify", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.output.take(), vec!["Integrity check passed"]);
+    }
+
+    #[test]
+    fn test_desynced_counter_is_reported() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        sheet.output = OutputSink::capture();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A1=3", &mut sleep_time);
+        handle_command(&mut sheet, "B1=A1+1", &mut sleep_time);
+        sheet.cell_counts.formulas += 1;
+
+        assert_eq!(
+            verify_integrity(&sheet),
+            vec!["formula cell counter is 2 but the sheet has 1"]
+        );
+        handle_command(&mut sheet, "verify", &mut sleep_time);
+        assert_eq!(
+            sheet.output.take(),
+            vec!["formula cell counter is 2 but the sheet has 1"]
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! A simple spreadsheet application in Rust.
+//!
+//! Supports basic operations (cell assignments, formulas), advanced features (locking, named ranges),
+//! and an optional Vim-like editing mode. The `spreadsheet` binary is a thin front end over
+//! this library.
+//!
+//! # Testing sheets
+//!
+//! With the `test-utils` feature the library exports `test_utils`, a supported way to
+//! test sheets from outside the crate. `test_utils::ScriptedSheet` runs commands as if
+//! typed at the prompt and chains assertions on statuses and values and, in the extended
+//! build, on output, formulas and golden files:
+//!
+//! ```text
+//! use spreadsheet::test_utils::ScriptedSheet;
+//!
+//! ScriptedSheet::new(10, 10)
+//!     .run(&["A1=5", "B1=A1*2"])
+//!     .assert_status_ok()
+//!     .assert_cell("B1", 10);
+//! ```
+//!
+//! Enable it for tests only, e.g. `cargo test --features "extensions test-utils"`; default
+//! builds leave the module out.
+
+mod aggregate;
+#[cfg(feature = "extensions")]
+mod autosave;
+pub mod cell;
+#[cfg(feature = "extensions")]
+mod cell_history;
+#[cfg(feature = "extensions")]
+mod changes;
+#[cfg(feature = "extensions")]
+mod column_formula;
+#[cfg(feature = "extensions")]
+mod condition;
+#[cfg(feature = "extensions")]
+mod copy_move;
+#[cfg(feature = "extensions")]
+mod csv;
+#[cfg(feature = "extensions")]
+mod deps;
+#[cfg(feature = "extensions")]
+mod dry_run;
+mod evaluator;
+mod expr_parser;
+mod extended_formula;
+#[cfg(feature = "extensions")]
+mod extensions;
+#[cfg(feature = "extensions")]
+mod filter;
+mod graph;
+#[cfg(feature = "extensions")]
+mod graph_stats;
+#[cfg(feature = "extensions")]
+pub mod history_file;
+#[cfg(feature = "extensions")]
+mod integrity;
+#[cfg(feature = "extensions")]
+mod locks;
+#[cfg(feature = "extensions")]
+mod names;
+#[cfg(feature = "extensions")]
+mod output;
+#[cfg(feature = "extensions")]
+mod overwrite;
+#[cfg(feature = "extensions")]
+pub mod ownership;
+mod process_command;
+mod prompt;
+mod range;
+mod reevaluate_topo;
+pub mod repl;
+mod snapshot;
+pub mod spreadsheet;
+// The range tests draw from it without extensions too
+#[cfg(any(feature = "extensions", test))]
+mod random;
+#[cfg(feature = "extensions")]
+mod range_assign;
+#[cfg(feature = "extensions")]
+pub mod read_only;
+#[cfg(feature = "extensions")]
+mod recalc;
+#[cfg(feature = "extensions")]
+mod row_shift;
+#[cfg(feature = "extensions")]
+mod sample;
+#[cfg(feature = "extensions")]
+mod save_load;
+#[cfg(feature = "extensions")]
+mod scripting;
+#[cfg(feature = "extensions")]
+mod search;
+#[cfg(feature = "extensions")]
+mod series;
+#[cfg(feature = "extensions")]
+pub mod session;
+#[cfg(feature = "extensions")]
+mod sheet_extra_impl;
+#[cfg(feature = "extensions")]
+mod sort;
+#[cfg(feature = "extensions")]
+mod table;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+#[cfg(feature = "extensions")]
+mod text_width;
+#[cfg(feature = "extensions")]
+mod tutorial;
+#[cfg(feature = "extensions")]
+mod undo;
+#[cfg(feature = "extensions")]
+pub mod vim_mode;
+#[cfg(feature = "extensions")]
+mod visualize_cells;
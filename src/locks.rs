@@ -0,0 +1,360 @@
+//! Locking cells against edits: `lock_cell`, `unlock_cell`, `unlock_all`, `is_locked`
+//! and `locked`.
+//!
+//! Each command takes a cell, a range or a named range, or a whole row or column as
+//! `ROW 3` / `COL B` (the same as `3:3` / `B:B`). Locks are kept as ranges, as `lock_cell`
+//! was given them, so locking a row takes one entry however wide the sheet is; unlocking
+//! part of a locked range leaves the rest of it locked.
+
+use crate::range::{Range, parse_range, resolve_target};
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use crate::table::{Align, Table};
+
+/// Resolves the target of a lock command: `ROW <n>`, `COL <letters>`, or anything
+/// `resolve_target` takes.
+fn lock_target(sheet: &Spreadsheet, target: &str) -> Result<Range, CommandStatus> {
+    let parts: Vec<&str> = target.split_whitespace().collect();
+    match parts[..] {
+        ["ROW", row] if row.bytes().all(|b| b.is_ascii_digit()) => {
+            parse_range(sheet, &format!("{}:{}", row, row))
+        }
+        ["COL", col] if col.bytes().all(|b| b.is_ascii_alphabetic()) => {
+            parse_range(sheet, &format!("{}:{}", col, col))
+        }
+        [_] => resolve_target(sheet, target),
+        _ => Err(CommandStatus::Unrecognized),
+    }
+}
+
+/// Splits the locked ranges into ranges that do not overlap, covering the same cells.
+fn disjoint_locks(sheet: &Spreadsheet) -> Vec<Range> {
+    let mut disjoint: Vec<Range> = Vec::new();
+    for range in &sheet.locked_ranges {
+        let mut pieces = vec![*range];
+        for seen in &disjoint {
+            pieces = pieces
+                .into_iter()
+                .flat_map(|piece| piece.subtract(seen))
+                .collect();
+        }
+        disjoint.extend(pieces);
+    }
+    disjoint
+}
+
+/// Counts the locked cells of a range, each once however many locks cover it.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `range` - The cells to count in.
+///
+/// # Returns
+///
+/// * `i64` - The number of locked cells
+pub fn locked_cell_count(sheet: &Spreadsheet, range: &Range) -> i64 {
+    disjoint_locks(sheet)
+        .iter()
+        .filter_map(|lock| lock.intersection(range))
+        .map(|shared| shared.cell_count())
+        .sum()
+}
+
+/// Formats a locked range as `B2` for a single cell, `3:4` or `B:B` for whole rows or
+/// columns, and `A1:C3` otherwise.
+fn lock_name(sheet: &Spreadsheet, range: &Range) -> String {
+    if range.cell_count() == 1 {
+        sheet.get_cell_name(range.start_row, range.start_col)
+    } else if range.start_col == 0 && range.end_col == sheet.cols - 1 {
+        format!("{}:{}", range.start_row + 1, range.end_row + 1)
+    } else if range.start_row == 0 && range.end_row == sheet.rows - 1 {
+        format!(
+            "{}:{}",
+            sheet.get_column_name(range.start_col),
+            sheet.get_column_name(range.end_col)
+        )
+    } else {
+        sheet.get_range_name(range)
+    }
+}
+
+/// Handles `lock_cell <cell|range|name>` and `lock_cell ROW <n>` / `lock_cell COL <col>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `target` - The text after `lock_cell`.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus` - From resolving the target
+pub fn handle_lock(sheet: &mut Spreadsheet, target: &str) -> CommandStatus {
+    match lock_target(sheet, target) {
+        Ok(range) => {
+            sheet.lock_range(range);
+            // Locks are saved with the sheet
+            sheet.dirty = true;
+            CommandStatus::CmdOk
+        }
+        Err(status) => status,
+    }
+}
+
+/// Handles `unlock_cell <target>`, with the targets `lock_cell` takes, unlocking the cells wherever they were
+/// locked from.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `target` - The text after `unlock_cell`.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success, even if no cell was locked.
+/// * `CommandStatus` - From resolving the target
+pub fn handle_unlock(sheet: &mut Spreadsheet, target: &str) -> CommandStatus {
+    match lock_target(sheet, target) {
+        Ok(range) => {
+            sheet.unlock_range(range);
+            sheet.dirty = true;
+            CommandStatus::CmdOk
+        }
+        Err(status) => status,
+    }
+}
+
+/// Handles `unlock_all`, dropping every lock and printing how many ranges were locked.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always
+pub fn handle_unlock_all(sheet: &mut Spreadsheet) -> CommandStatus {
+    let count = sheet.locked_ranges.len();
+    sheet.locked_ranges.clear();
+    sheet.dirty |= count > 0;
+    sheet
+        .output
+        .line(format!("unlocked {} locked range(s)", count));
+    CommandStatus::CmdOk
+}
+
+/// Handles `is_locked <target>`, with the targets `lock_cell` takes.
+///
+/// A single cell only sets the status. For a range, a line also tells whether all of
+/// it, part of it or none of it is locked.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `target` - The text after `is_locked`.
+///
+/// # Returns
+///
+/// * `CommandStatus::LockedCell` - If the cell, or any cell of the range, is locked.
+/// * `CommandStatus::NotLockedCell` - If none is.
+/// * `CommandStatus` - From resolving the target
+pub fn handle_is_locked(sheet: &mut Spreadsheet, target: &str) -> CommandStatus {
+    let range = match lock_target(sheet, target) {
+        Ok(range) => range,
+        Err(status) => return status,
+    };
+    let locked = locked_cell_count(sheet, &range);
+    let total = range.cell_count();
+    if total > 1 {
+        let name = lock_name(sheet, &range);
+        let line = match locked {
+            0 => format!("{}: no cell is locked", name),
+            _ if locked == total => format!("{}: all {} cells are locked", name, total),
+            _ => format!("{}: {} of {} cells are locked", name, locked, total),
+        };
+        sheet.output.line(line);
+    }
+    if locked > 0 {
+        CommandStatus::LockedCell
+    } else {
+        CommandStatus::NotLockedCell
+    }
+}
+
+/// Handles `locked`: lists the locked ranges in the order they were locked, each with its
+/// size, then the number of locked cells, counting a cell under several locks once.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always
+pub fn handle_locked(sheet: &mut Spreadsheet) -> CommandStatus {
+    if sheet.locked_ranges.is_empty() {
+        sheet.output.line("no cell is locked".to_string());
+        return CommandStatus::CmdOk;
+    }
+    let mut table = Table::new(&[("range", Align::Left), ("cells", Align::Right)]);
+    for range in &sheet.locked_ranges {
+        table.push_row(vec![
+            lock_name(sheet, range),
+            range.cell_count().to_string(),
+        ]);
+    }
+    let mut lines = table.render();
+    let cells: i64 = disjoint_locks(sheet).iter().map(Range::cell_count).sum();
+    lines.push(format!(
+        "{} locked cell(s) in {} range(s)",
+        cells,
+        sheet.locked_ranges.len()
+    ));
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spreadsheet::CommandStatus;
+    use crate::test_utils::ScriptedSheet;
+
+    #[test]
+    fn test_lock_and_unlock_single_cells() {
+        ScriptedSheet::new(10, 10)
+            .run(&["lock_cell B2", "is_locked B2"])
+            .assert_status(CommandStatus::LockedCell)
+            .run(&["is_locked B3"])
+            .assert_status(CommandStatus::NotLockedCell)
+            .run(&["B2=5"])
+            .assert_status(CommandStatus::LockedCell)
+            .run(&["unlock_cell   B2", "B2=5"])
+            .assert_status_ok()
+            .assert_cell("B2", 5);
+    }
+
+    #[test]
+    fn test_unlock_carves_cells_out_of_ranges() {
+        let scripted = ScriptedSheet::new(10, 10)
+            .run(&["lock_cell A1:C3", "unlock_cell B2", "is_locked B2"])
+            .assert_status(CommandStatus::NotLockedCell);
+        assert_eq!(scripted.sheet.locked_ranges.len(), 4);
+        let scripted = scripted
+            .run(&["is_locked A1"])
+            .assert_status(CommandStatus::LockedCell)
+            .run(&["is_locked C3"])
+            .assert_status(CommandStatus::LockedCell)
+            .run(&["unlock_cell A1:C2", "is_locked B3"])
+            .assert_status(CommandStatus::LockedCell)
+            .run(&["is_locked C2"])
+            .assert_status(CommandStatus::NotLockedCell);
+        assert_eq!(scripted.sheet.locked_ranges.len(), 1);
+    }
+
+    #[test]
+    fn test_lock_commands_take_named_ranges() {
+        ScriptedSheet::new(10, 10)
+            .run(&["name A1:B2 block", "name D4 corner", "lock_cell block"])
+            .run(&["lock_cell corner", "is_locked corner"])
+            .assert_status(CommandStatus::LockedCell)
+            .run(&["is_locked B2"])
+            .assert_status(CommandStatus::LockedCell)
+            .run(&["unlock_cell block", "is_locked A1"])
+            .assert_status(CommandStatus::NotLockedCell)
+            .run(&["lock_cell nowhere"])
+            .assert_status(CommandStatus::Unrecognized);
+    }
+
+    #[test]
+    fn test_locked_lists_ranges_and_counts_cells_once() {
+        ScriptedSheet::new(10, 10)
+            .run(&["locked"])
+            .assert_status_ok()
+            .assert_output(&["no cell is locked"])
+            .run(&[
+                "lock_cell A1:C3",
+                "lock_cell B2:D4",
+                "lock_cell J10",
+                "locked",
+            ])
+            .assert_status_ok()
+            .assert_output(&[
+                "range  cells",
+                "-----  -----",
+                "A1:C3      9",
+                "B2:D4      9",
+                "J10        1",
+                "15 locked cell(s) in 3 range(s)",
+            ]);
+    }
+
+    #[test]
+    fn test_is_locked_range_reports_whole_part_or_none() {
+        ScriptedSheet::new(10, 10)
+            .run(&["lock_cell A1:B2", "lock_cell B2:C2", "is_locked A1:B2"])
+            .assert_status(CommandStatus::LockedCell)
+            .assert_output(&["A1:B2: all 4 cells are locked"])
+            .run(&["is_locked A1:C3"])
+            .assert_status(CommandStatus::LockedCell)
+            .assert_output(&["A1:C3: 5 of 9 cells are locked"])
+            .run(&["name E1:F5 side", "is_locked side"])
+            .assert_status(CommandStatus::NotLockedCell)
+            .assert_output(&["E1:F5: no cell is locked"])
+            .run(&["is_locked C2"])
+            .assert_status(CommandStatus::LockedCell)
+            .assert_output(&[]);
+    }
+
+    #[test]
+    fn test_lock_whole_rows_and_columns() {
+        let scripted = ScriptedSheet::new(999, 300)
+            .run(&[
+                "lock_cell ROW 1",
+                "lock_cell COL B",
+                "lock_cell 5:6",
+                "locked",
+            ])
+            .assert_status_ok()
+            .assert_output(&[
+                "range  cells",
+                "-----  -----",
+                "1:1      300",
+                "B:B      999",
+                "5:6      600",
+                "1896 locked cell(s) in 3 range(s)",
+            ])
+            .run(&["KN1=4"])
+            .assert_status(CommandStatus::LockedCell)
+            .run(&["B999=4"])
+            .assert_status(CommandStatus::LockedCell)
+            .run(&["is_locked ROW 6"])
+            .assert_status(CommandStatus::LockedCell)
+            .assert_output(&["6:6: all 300 cells are locked"])
+            .run(&["unlock_cell C1", "C1=4", "C2=4"])
+            .assert_status_ok()
+            .run(&["is_locked ROW 1"])
+            .assert_output(&["1:1: 299 of 300 cells are locked"])
+            .run(&["lock_cell ROW 0"])
+            .assert_status(CommandStatus::Unrecognized)
+            .run(&["lock_cell COL 3"])
+            .assert_status(CommandStatus::Unrecognized);
+        assert_eq!(scripted.sheet.locked_ranges.len(), 4);
+    }
+
+    #[test]
+    fn test_row_locks_move_with_inserted_rows() {
+        ScriptedSheet::new(10, 10)
+            .run(&["lock_cell ROW 2", "insert_row 1", "locked"])
+            .assert_status_ok()
+            .assert_output(&[
+                "range  cells",
+                "-----  -----",
+                "3:3       10",
+                "10 locked cell(s) in 1 range(s)",
+            ]);
+    }
+
+    #[test]
+    fn test_unlock_all() {
+        ScriptedSheet::new(10, 10)
+            .run(&["lock_cell A1:B2", "lock_cell 5:5", "unlock_all"])
+            .assert_status_ok()
+            .assert_output(&["unlocked 2 locked range(s)"])
+            .run(&["is_locked E5"])
+            .assert_status(CommandStatus::NotLockedCell)
+            .run(&["A1=1"])
+            .assert_status_ok();
+    }
+}
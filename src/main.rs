@@ -1,36 +1,19 @@
-//! A simple spreadsheet application in Rust.
-//!
-//! Supports basic operations (cell assignments, formulas), advanced features (locking, named ranges),
-//! and an optional Vim-like editing mode.
+//! Command-line front end of the spreadsheet: parses the arguments and runs the prompt or
+//! the Vim-like editor on a new sheet.
 
-mod cell;
-mod evaluator;
-mod formula;
-mod graph;
-mod process_command;
-mod reevaluate_topo;
-mod spreadsheet;
 use std::env;
-use std::io::{self, Write};
+use std::io;
 use std::process;
+
+use spreadsheet::repl;
 #[cfg(feature = "extensions")]
-mod extensions;
-#[cfg(feature = "extensions")]
-mod save_load;
-#[cfg(feature = "extensions")]
-mod sheet_extra_impl;
-#[cfg(feature = "extensions")]
-mod vim_mode;
+use spreadsheet::repl::DEFAULT_FILENAME;
 #[cfg(feature = "extensions")]
-mod visualize_cells;
-
-use crate::process_command::process_command;
+use spreadsheet::spreadsheet::CommandStatus;
+use spreadsheet::spreadsheet::{MAX_COLS, MAX_ROWS, Spreadsheet, dimension_error};
 #[cfg(feature = "extensions")]
-use crate::save_load::{load_spreadsheet, save_spreadsheet};
-use spreadsheet::{CommandStatus, Spreadsheet};
+use spreadsheet::{history_file, ownership, read_only, session, vim_mode};
 use std::time::Instant;
-#[cfg(feature = "extensions")]
-const DEFAULT_FILENAME: &str = "rust_spreadsheet.sheet";
 
 /// Entry point for the spreadsheet application.
 ///
@@ -38,8 +21,25 @@ const DEFAULT_FILENAME: &str = "rust_spreadsheet.sheet";
 ///
 /// # Arguments
 ///
-/// * Expects `rows` and `cols` as arguments, optionally preceded by `--vim`
+/// * Expects `rows` and `cols` as arguments, optionally preceded by `--vim`; with the
+///   extensions, `--readonly` opens the sheet in read-only mode
 fn main() {
+    #[cfg(feature = "extensions")]
+    let mut args: Vec<String> = env::args().collect();
+    #[cfg(feature = "extensions")]
+    let history_path = history_file::take_history_flag(&mut args);
+    #[cfg(feature = "extensions")]
+    let save_on_exit = repl::take_flag_value(&mut args, "--save-on-exit");
+    #[cfg(feature = "extensions")]
+    let session_path = repl::take_flag_value(&mut args, "--session");
+    #[cfg(feature = "extensions")]
+    let read_only = read_only::take_readonly_flag(&mut args);
+    #[cfg(feature = "extensions")]
+    let user = ownership::take_user_flag(&mut args).unwrap_or_else(|user| {
+        eprintln!("Invalid user name '{}'", user);
+        process::exit(1);
+    });
+    #[cfg(not(feature = "extensions"))]
     let args: Vec<String> = env::args().collect();
     let mut vim_mode_enabled = false;
     let mut rows_arg_index = 1;
@@ -57,15 +57,26 @@ fn main() {
     // }
 
     let rows: i16 = args[rows_arg_index].parse().unwrap_or_else(|_| {
-        eprintln!("Invalid number for rows");
+        eprintln!(
+            "Invalid number for rows: rows must be between 1 and {}",
+            MAX_ROWS
+        );
         process::exit(1);
     });
 
     let cols: i16 = args[cols_arg_index].parse().unwrap_or_else(|_| {
-        eprintln!("Invalid number for columns");
+        eprintln!(
+            "Invalid number for columns: columns must be between 1 and {}",
+            MAX_COLS
+        );
         process::exit(1);
     });
 
+    if let Some(message) = dimension_error(rows, cols) {
+        eprintln!("Invalid spreadsheet dimensions: {}", message);
+        process::exit(1);
+    }
+
     let start = Instant::now();
 
     let mut sheet = match Spreadsheet::create(rows, cols) {
@@ -80,9 +91,33 @@ fn main() {
         }
     };
     #[cfg(feature = "extensions")]
+    if let Some(path) = history_path {
+        sheet.history.set_path(&path);
+    }
+    #[cfg(feature = "extensions")]
+    if let Some(path) = &session_path
+        && session::restore_session(&mut sheet, path) != CommandStatus::CmdOk
+    {
+        eprintln!("Could not restore session '{}'", path);
+        process::exit(1);
+    }
+    #[cfg(feature = "extensions")]
+    if let Some(user) = user {
+        // The flag wins over a user restored from the session
+        sheet.ownership.user = Some(user);
+    }
+    #[cfg(feature = "extensions")]
+    if read_only {
+        // Set after the session is restored, so it is loaded like any other sheet
+        sheet.read_only = true;
+    }
+    #[cfg(feature = "extensions")]
     if vim_mode_enabled {
         // If args[4] exists, use it; else use default filename.
-        let filename = if args.len() > 4 {
+        let filename = if session_path.is_some() {
+            // The session already loaded the sheet
+            sheet.current_file.clone()
+        } else if args.len() > 4 {
             Some(args[4].to_string())
         } else {
             // Check if DEFAULT_FILENAME exists, if not, create it.
@@ -99,90 +134,13 @@ fn main() {
         vim_mode::run_editor(&mut sheet, filename);
     }
     if !vim_mode_enabled {
-        let mut last_time = start.elapsed().as_secs_f64(); // Update last_time with the command time
-        let mut last_status = "ok"; // Placeholder for last status
-        let mut input = String::with_capacity(128);
-        let mut status;
-        // Main loop for command input
-        loop {
-            // Print the spreadsheet
-            sheet.print_spreadsheet();
-
-            print!("[{:.1}] ({}) > ", last_time, last_status);
-            io::stdout().flush().unwrap(); // Ensure the prompt is shown
-
-            input.clear();
-            if io::stdin().read_line(&mut input).unwrap() == 0 {
-                break; // End of input
-            }
-
-            let trimmed = input.trim(); // Remove any newline characters
-            if trimmed == "q" {
-                // Add save functionality before quitting ask the user
-                // if they want to save the spreadsheet
-                #[cfg(feature = "extensions")]
-                {
-                    print!("Do you want to save the spreadsheet before quitting? (y/n): ");
-                    io::stdout().flush().unwrap(); // Ensure the prompt is shown
-                    // Take the user's response
-                    let mut response = String::new();
-                    io::stdin().read_line(&mut response).unwrap();
-                    let response = response.trim(); // Remove any newline characters
-                    if response == "y" {
-                        // Ask for the filename to save
-                        print!("Enter filename to save (default: {}): ", DEFAULT_FILENAME);
-                        io::stdout().flush().unwrap(); // Ensure the prompt is shown
-                        let mut filename = String::new();
-                        io::stdin().read_line(&mut filename).unwrap();
-                        let filename = filename.trim(); // Remove any newline characters
-
-                        // Use the default filename if the user didn't enter anything
-                        let save_filename = if filename.is_empty() {
-                            DEFAULT_FILENAME
-                        } else {
-                            filename
-                        };
-                        save_spreadsheet(&sheet, save_filename);
-                    }
-                }
-                break;
-            }
-
-            #[cfg(feature = "extensions")]
-            // Add "open" command to load a spreadsheet
-            if let Some(filename_part) = trimmed.strip_prefix("open ") {
-                let filename = filename_part;
-                if !filename.is_empty() {
-                    println!("Loading spreadsheet from '{}'...", filename);
-                    match load_spreadsheet(&mut sheet, filename) {
-                        CommandStatus::CmdOk => {
-                            println!("Spreadsheet successfully loaded from '{}'", filename);
-                            last_status = "ok";
-                        }
-                        _ => {
-                            eprintln!("Failed to load spreadsheet from '{}'", filename);
-                            last_status = "error";
-                        }
-                    }
-                    continue;
-                }
-            }
-
-            // Process the command and measure execution time
-            status = process_command(&mut sheet, trimmed, &mut last_time);
-
-            // Update last_status based on the current command status
-            last_status = match status {
-                CommandStatus::CmdOk => "ok",
-                CommandStatus::Unrecognized => "unrecognized_cmd",
-                CommandStatus::CircularRef => "circular_ref",
-                #[cfg(feature = "extensions")]
-                CommandStatus::InvalidCell => "invalid_cell",
-                #[cfg(feature = "extensions")]
-                CommandStatus::LockedCell => "locked_cell",
-                #[cfg(feature = "extensions")]
-                CommandStatus::NotLockedCell => "not_locked_cell",
-            };
-        }
+        #[cfg(feature = "extensions")]
+        let options = repl::SessionOptions { save_on_exit };
+        #[cfg(not(feature = "extensions"))]
+        let options = repl::SessionOptions::default();
+        let startup_time = start.elapsed().as_secs_f64();
+        let (_, code) =
+            repl::run_session(&mut sheet, &mut io::stdin().lock(), &options, startup_time);
+        process::exit(code);
     }
 }
@@ -0,0 +1,251 @@
+//! Named ranges: `name`, `names`, `unname` and `rename`.
+//!
+//! A name stands for a cell or range wherever a command or formula takes one. Formulas
+//! store the keys a name resolved to when they were entered, so removing or renaming a
+//! name leaves them unchanged. Names are looked up before cell references, so a name that
+//! a formula would read as something else, such as `B2`, `rc`, `R1C1`, `123` or `SUM`,
+//! is refused in any case.
+
+use crate::cell::parse_cell_reference;
+use crate::expr_parser::reads_as_name;
+use crate::range::{Range, parse_range};
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use crate::table::{Align, Table};
+
+/// Checks a new name, printing why it is refused.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the name may be defined.
+/// * `Err(CommandStatus::Unrecognized)` - If a formula would read it, in any case, as a
+///   number, a cell or relative reference or a function
+fn check_new_name(sheet: &mut Spreadsheet, name: &str) -> Result<(), CommandStatus> {
+    if !reads_as_name(name) {
+        sheet.output.line(format!(
+            "'{}' reads as a number, reference or function; choose another name",
+            name
+        ));
+        return Err(CommandStatus::Unrecognized);
+    }
+    Ok(())
+}
+
+/// Checks that a name is defined, printing a message if not.
+fn check_defined(sheet: &mut Spreadsheet, name: &str) -> Result<(), CommandStatus> {
+    if sheet.named_ranges.contains_key(name) {
+        return Ok(());
+    }
+    sheet.output.line(format!("no range is named '{}'", name));
+    Err(CommandStatus::Unrecognized)
+}
+
+/// Formats the range of a name: `A1` for one cell, `A1:B5` otherwise.
+fn range_text(sheet: &Spreadsheet, range: &Range) -> String {
+    if range.cell_count() == 1 {
+        format!(
+            "{}{}",
+            sheet.get_column_name(range.start_col),
+            range.start_row + 1
+        )
+    } else {
+        sheet.get_range_name(range)
+    }
+}
+
+/// Handles `name <cell/range> <name>`, defining or redefining a name.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `name `.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If the arguments are malformed or the name reads as
+///   something else
+pub fn handle_name(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let [target, name] = parts[..] else {
+        return CommandStatus::Unrecognized;
+    };
+    let range = match parse_range(sheet, target) {
+        Ok(range) => range,
+        Err(_) => match parse_cell_reference(sheet, target) {
+            Ok((row, col)) => Range::single(row, col),
+            Err(_) => return CommandStatus::Unrecognized,
+        },
+    };
+    if let Err(status) = check_new_name(sheet, name) {
+        return status;
+    }
+    sheet.named_ranges.insert(name.to_string(), range);
+    // Names are saved with the sheet
+    sheet.dirty = true;
+    CommandStatus::CmdOk
+}
+
+/// Handles `names`, listing each name with its range in a table, in name order.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always
+pub fn handle_names(sheet: &mut Spreadsheet) -> CommandStatus {
+    if sheet.named_ranges.is_empty() {
+        sheet.output.line("no names defined".to_string());
+        return CommandStatus::CmdOk;
+    }
+    let mut names: Vec<(&String, &Range)> = sheet.named_ranges.iter().collect();
+    names.sort_by(|a, b| a.0.cmp(b.0));
+    let mut table = Table::new(&[("name", Align::Left), ("range", Align::Left)]);
+    for (name, range) in names {
+        table.push_row(vec![name.clone(), range_text(sheet, range)]);
+    }
+    let lines = table.render();
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
+/// Handles `unname <name>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `name` - The name to remove.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If no range has the name
+pub fn handle_unname(sheet: &mut Spreadsheet, name: &str) -> CommandStatus {
+    if let Err(status) = check_defined(sheet, name) {
+        return status;
+    }
+    sheet.named_ranges.remove(name);
+    sheet.dirty = true;
+    CommandStatus::CmdOk
+}
+
+/// Handles `rename <old> <new>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `rename `.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If `old` is not defined, `new` already is, or `new`
+///   reads as something else
+pub fn handle_rename(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let [old, new] = parts[..] else {
+        return CommandStatus::Unrecognized;
+    };
+    if let Err(status) = check_defined(sheet, old) {
+        return status;
+    }
+    if old == new {
+        return CommandStatus::CmdOk;
+    }
+    if sheet.named_ranges.contains_key(new) {
+        sheet
+            .output
+            .line(format!("'{}' is already defined; unname it first", new));
+        return CommandStatus::Unrecognized;
+    }
+    if let Err(status) = check_new_name(sheet, new) {
+        return status;
+    }
+    let range = sheet.named_ranges.remove(old).expect("checked above");
+    sheet.named_ranges.insert(new.to_string(), range);
+    sheet.dirty = true;
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spreadsheet::CommandStatus;
+    use crate::test_utils::ScriptedSheet;
+
+    #[test]
+    fn test_list_remove_and_rename_names() {
+        ScriptedSheet::new(10, 10)
+            .run(&["names"])
+            .assert_output(&["no names defined"])
+            .run(&["name A1:B5 data", "name C3 total", "A1=4", "D1=SUM(data)"])
+            .run(&["names"])
+            .assert_status_ok()
+            .assert_output(&["name   range", "-----  -----", "data   A1:B5", "total  C3"])
+            .run(&[
+                "rename data sales",
+                "C1=SUM(sales)",
+                "unname total",
+                "names",
+            ])
+            .assert_status_ok()
+            .assert_output(&["name   range", "-----  -----", "sales  A1:B5"])
+            .assert_cell("C1", 4)
+            // Formulas keep the cells a removed name stood for
+            .run(&["unname sales", "A2=6"])
+            .assert_status_ok()
+            .assert_cell("D1", 10)
+            .assert_cell("C1", 10);
+    }
+
+    #[test]
+    fn test_name_errors() {
+        ScriptedSheet::new(10, 10)
+            .run(&["name A1:B2 data", "name C1 other"])
+            .assert_status_ok()
+            .run(&["unname nothing"])
+            .assert_status(CommandStatus::Unrecognized)
+            .assert_output(&["no range is named 'nothing'"])
+            .run(&["rename data other"])
+            .assert_status(CommandStatus::Unrecognized)
+            .assert_output(&["'other' is already defined; unname it first"])
+            .run(&["rename nothing x"])
+            .assert_status(CommandStatus::Unrecognized)
+            .run(&["name A1 B2"])
+            .assert_status(CommandStatus::Unrecognized)
+            .assert_output(&[
+                "no range is named 'nothing'",
+                "'B2' reads as a number, reference or function; choose another name",
+            ])
+            .run(&["rename data b2"])
+            .assert_status(CommandStatus::Unrecognized)
+            .assert_output(&["'b2' reads as a number, reference or function; choose another name"])
+            .run(&["names"])
+            .assert_output(&["name   range", "-----  -----", "data   A1:B2", "other  C1"]);
+    }
+
+    #[test]
+    fn test_names_that_read_as_something_else() {
+        let mut sheet = ScriptedSheet::new(10, 10);
+        // Out of bounds cells, relative references, R1C1 notation, numbers and functions
+        for name in [
+            "ZZ99", "RC", "rc", "R[1]C", "R1C1", "r2c", "123", "SUM", "sum", "If",
+        ] {
+            sheet = sheet
+                .run(&[format!("name A1 {}", name).as_str()])
+                .assert_status(CommandStatus::Unrecognized)
+                .assert_output(&[&format!(
+                    "'{}' reads as a number, reference or function; choose another name",
+                    name
+                )]);
+        }
+        sheet
+            .run(&["name A1 rate", "name B1 R2D2", "rename rate SUM"])
+            .assert_status(CommandStatus::Unrecognized)
+            .run(&["rename rate total", "names"])
+            .assert_status_ok()
+            .assert_output(&[
+                "'SUM' reads as a number, reference or function; choose another name",
+                "name   range",
+                "-----  -----",
+                "R2D2   B1",
+                "total  A1",
+            ]);
+    }
+}
@@ -0,0 +1,574 @@
+//! Destination for the text that commands print.
+//!
+//! Commands write their report lines through the sheet's `OutputSink` instead of
+//! calling `println!` directly, so tests can capture and inspect what a command printed.
+//!
+//! Long listings are paged when both input and output are a terminal: the sink holds
+//! them back, and the command loop shows them a page at a time with `page_pending`,
+//! reading the answers from its own input. Piped output is always written in full.
+//!
+//! The sink also decides how a failed command's message is reported with the prompt. When
+//! the same command fails the same way again, as with a script or driver retrying it, the
+//! message is shown once and then counted: a terminal shows the running count in place of the
+//! message, and piped output writes the count once, before the next different outcome.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Lines per page when no page size is configured.
+pub const DEFAULT_PAGE_SIZE: usize = 25;
+/// Prompt shown after each page.
+const MORE_PROMPT: &str = "-- more (Enter=next, a=all, q=stop) --";
+
+/// Collects or prints lines of command output.
+///
+/// # Fields
+///
+/// * `captured` - Lines written so far when capturing, `None` when writing to stdout
+/// * `page_size` - Lines per page of a long listing; 0 turns paging off.
+/// * `interactive` - Whether input and output are a terminal, so paging and other
+///   questions can prompt.
+/// * `pending` - A long listing held back until the command loop pages it.
+/// * `repeats` - The last failure reported and how often it has repeated since.
+#[derive(Debug)]
+pub struct OutputSink {
+    captured: Option<Vec<String>>,
+    pub page_size: usize,
+    pub interactive: bool,
+    pending: Vec<String>,
+    repeats: RepeatCounter,
+}
+
+/// Collapses runs of the same failure into one message and a count.
+///
+/// # Fields
+///
+/// * `last` - The status, command and message of the last failure, until a different
+///   outcome. Different commands that fail with the same generic message are not repeats.
+/// * `count` - How many times it repeated after being shown.
+#[derive(Debug, Default, PartialEq)]
+pub struct RepeatCounter {
+    last: Option<(String, String, String)>,
+    count: usize,
+}
+
+impl RepeatCounter {
+    /// Reports one command's outcome.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The command's status name.
+    /// * `command` - The command that failed.
+    /// * `message` - The failure message; `None` after success.
+    /// * `interactive` - Whether the count is shown as it grows rather than at the end of
+    ///   the run.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<String>` - The lines to show with the prompt, oldest first
+    pub fn report(
+        &mut self,
+        status: &str,
+        command: &str,
+        message: Option<&str>,
+        interactive: bool,
+    ) -> Vec<String> {
+        if let (Some(message), Some((last_status, last_command, last_message))) =
+            (message, &self.last)
+            && last_status == status
+            && last_command == command
+            && last_message == message
+        {
+            self.count += 1;
+            return if interactive {
+                vec![repeated_line(self.count)]
+            } else {
+                Vec::new()
+            };
+        }
+        // A different outcome ends the run, so its count is written before anything else
+        let mut lines: Vec<String> = self.finish(interactive).into_iter().collect();
+        if let Some(message) = message {
+            self.last = Some((status.to_string(), command.to_string(), message.to_string()));
+            lines.push(message.to_string());
+        }
+        lines
+    }
+
+    /// Ends the current run.
+    ///
+    /// # Arguments
+    ///
+    /// * `interactive` - Whether the count was already shown as it grew.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The count line, if one is still owed
+    pub fn finish(&mut self, interactive: bool) -> Option<String> {
+        let count = std::mem::take(&mut self.count);
+        self.last = None;
+        (!interactive && count > 0).then(|| repeated_line(count))
+    }
+}
+
+/// Formats the line that stands for `count` repeats of the last message.
+fn repeated_line(count: usize) -> String {
+    format!("… last message repeated {} time(s)", count)
+}
+
+/// An answer to the pager's prompt.
+///
+/// # Variants
+///
+/// * `Next` - Show the next page (Enter).
+/// * `All` - Show everything that is left (`a`).
+/// * `Stop` - Skip the rest (`q`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PagerResponse {
+    Next,
+    All,
+    Stop,
+}
+
+impl PagerResponse {
+    /// Reads an answer typed at the prompt; anything unexpected shows the next page.
+    pub fn parse(answer: &str) -> PagerResponse {
+        match answer.trim() {
+            "a" | "A" => PagerResponse::All,
+            "q" | "Q" => PagerResponse::Stop,
+            _ => PagerResponse::Next,
+        }
+    }
+}
+
+impl OutputSink {
+    /// Creates a sink that prints each line to stdout.
+    pub fn stdout() -> Self {
+        OutputSink {
+            captured: None,
+            page_size: DEFAULT_PAGE_SIZE,
+            interactive: io::stdin().is_terminal() && io::stdout().is_terminal(),
+            pending: Vec::new(),
+            repeats: RepeatCounter::default(),
+        }
+    }
+
+    /// Creates a sink that keeps lines in memory instead of printing them.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn capture() -> Self {
+        OutputSink {
+            captured: Some(Vec::new()),
+            page_size: DEFAULT_PAGE_SIZE,
+            interactive: false,
+            pending: Vec::new(),
+            repeats: RepeatCounter::default(),
+        }
+    }
+
+    /// Writes one line of output.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The line to write, without a trailing newline
+    pub fn line(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        match &mut self.captured {
+            Some(lines) => lines.push(text),
+            // Keep the order when a listing is waiting to be paged
+            None if !self.pending.is_empty() => self.pending.push(text),
+            None => println!("{}", text),
+        }
+    }
+
+    /// Writes several lines of output in order.
+    ///
+    /// A listing longer than a page is held back for `page_pending` when the sink is
+    /// interactive.
+    ///
+    /// # Arguments
+    ///
+    /// * `lines` - The lines to write
+    pub fn lines<I: IntoIterator<Item = String>>(&mut self, lines: I) {
+        let lines: Vec<String> = lines.into_iter().collect();
+        if self.captured.is_none() && should_page(lines.len(), self.page_size, self.interactive) {
+            self.pending.extend(lines);
+            return;
+        }
+        for text in lines {
+            self.line(text);
+        }
+    }
+
+    /// Shows the held-back listing a page at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - Where the answers to the prompt are read from.
+    /// * `out` - Where the pages are written.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - An error if writing fails
+    pub fn page_pending<R: BufRead, W: Write>(
+        &mut self,
+        input: &mut R,
+        out: &mut W,
+    ) -> io::Result<()> {
+        let lines = std::mem::take(&mut self.pending);
+        page(&lines, self.page_size, input, out)
+    }
+
+    /// Reports a command's outcome, coalescing repeats of the same failure.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The command's status name.
+    /// * `command` - The command that failed.
+    /// * `message` - The failure message; `None` after success.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The text to show above the prompt, if any
+    pub fn report(&mut self, status: &str, command: &str, message: Option<&str>) -> Option<String> {
+        let lines = self
+            .repeats
+            .report(status, command, message, self.interactive);
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    /// Ends a run of repeated failures, e.g. when the session ends.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The count line, if piped output still owes one
+    pub fn finish_repeats(&mut self) -> Option<String> {
+        self.repeats.finish(self.interactive)
+    }
+
+    /// Removes and returns the captured lines, leaving the sink capturing.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<String>` - The captured lines (empty for a stdout sink)
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn take(&mut self) -> Vec<String> {
+        self.captured
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+}
+
+/// Decides whether a listing is long enough to page.
+///
+/// # Arguments
+///
+/// * `line_count` - Lines in the listing.
+/// * `page_size` - Lines per page; 0 turns paging off.
+/// * `interactive` - Whether someone can answer the prompt.
+///
+/// # Returns
+///
+/// * `bool` - `true` if the listing should be paged
+pub fn should_page(line_count: usize, page_size: usize, interactive: bool) -> bool {
+    interactive && page_size > 0 && line_count > page_size
+}
+
+/// Writes lines a page at a time, prompting between pages.
+///
+/// The end of input counts as `a`, so nothing is lost when the answers run out.
+///
+/// # Arguments
+///
+/// * `lines` - The listing.
+/// * `page_size` - Lines per page; 0 writes everything at once.
+/// * `input` - Where the answers are read from.
+/// * `out` - Where the pages and prompts are written.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An error if writing fails
+pub fn page<R: BufRead, W: Write>(
+    lines: &[String],
+    page_size: usize,
+    input: &mut R,
+    out: &mut W,
+) -> io::Result<()> {
+    let page_size = if page_size == 0 {
+        lines.len().max(1)
+    } else {
+        page_size
+    };
+    let mut pages = lines.chunks(page_size).peekable();
+    while let Some(chunk) = pages.next() {
+        for text in chunk {
+            writeln!(out, "{}", text)?;
+        }
+        if pages.peek().is_none() {
+            break;
+        }
+        write!(out, "{} ", MORE_PROMPT)?;
+        out.flush()?;
+        let mut answer = String::new();
+        let response = match input.read_line(&mut answer) {
+            Ok(0) | Err(_) => PagerResponse::All,
+            Ok(_) => PagerResponse::parse(&answer),
+        };
+        match response {
+            PagerResponse::Next => {}
+            PagerResponse::All => {
+                for text in pages.flatten() {
+                    writeln!(out, "{}", text)?;
+                }
+                break;
+            }
+            PagerResponse::Stop => {
+                let skipped: usize = pages.map(<[String]>::len).sum();
+                writeln!(out, "({} more line(s) not shown)", skipped)?;
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_collects_lines_in_order() {
+        let mut sink = OutputSink::capture();
+        sink.line("first");
+        sink.lines(vec!["second".to_string(), "third".to_string()]);
+        assert_eq!(sink.take(), vec!["first", "second", "third"]);
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn test_stdout_sink_has_nothing_to_take() {
+        let mut sink = OutputSink::stdout();
+        sink.line("printed");
+        assert!(sink.take().is_empty());
+    }
+
+    fn numbered(count: usize) -> Vec<String> {
+        (1..=count).map(|n| format!("line {}", n)).collect()
+    }
+
+    fn paged(lines: &[String], page_size: usize, answers: &str) -> Vec<String> {
+        let mut out = Vec::new();
+        page(lines, page_size, &mut answers.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_should_page() {
+        assert!(should_page(26, 25, true));
+        assert!(!should_page(25, 25, true));
+        assert!(!should_page(100, 25, false));
+        assert!(!should_page(100, 0, true));
+    }
+
+    #[test]
+    fn test_page_slices_and_answers() {
+        let lines = numbered(7);
+        let prompt = format!("{} ", MORE_PROMPT);
+        // Enter shows the next page; the last page has no prompt
+        let out = paged(&lines, 3, "\n\n");
+        assert_eq!(out.len(), 7);
+        assert_eq!(out[3], format!("{}line 4", prompt));
+        assert_eq!(out[6], format!("{}line 7", prompt));
+
+        // `a` shows the rest without more prompts
+        let out = paged(&lines, 3, "a\n");
+        assert_eq!(out[3], format!("{}line 4", prompt));
+        assert_eq!(out.last().unwrap(), "line 7");
+        assert_eq!(out.iter().filter(|l| l.contains(MORE_PROMPT)).count(), 1);
+
+        // `q` stops and says how much was skipped
+        let out = paged(&lines, 3, "q\n");
+        assert_eq!(out.len(), 4);
+        assert_eq!(out[3], format!("{}(4 more line(s) not shown)", prompt));
+
+        // The end of input shows everything
+        assert_eq!(paged(&lines, 3, "").len(), 7);
+        assert_eq!(PagerResponse::parse("x\n"), PagerResponse::Next);
+    }
+
+    #[test]
+    fn test_interactive_sink_holds_long_listings() {
+        let mut sink = OutputSink::stdout();
+        sink.interactive = true;
+        sink.page_size = 2;
+        sink.lines(numbered(3));
+        sink.line("after");
+        assert_eq!(sink.pending.len(), 4);
+
+        let mut out = Vec::new();
+        sink.page_pending(&mut "a\n".as_bytes(), &mut out).unwrap();
+        assert!(sink.pending.is_empty());
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("line 1\nline 2\n-- more"));
+        assert!(text.ends_with("line 3\nafter\n"));
+    }
+
+    #[test]
+    fn test_non_interactive_sink_writes_everything() {
+        let mut sink = OutputSink::stdout();
+        sink.interactive = false;
+        sink.page_size = 2;
+        sink.lines(numbered(3));
+        assert!(sink.pending.is_empty());
+        // Capturing sinks never page either
+        let mut sink = OutputSink::capture();
+        sink.page_size = 2;
+        sink.interactive = true;
+        sink.lines(numbered(3));
+        assert_eq!(sink.take(), numbered(3));
+    }
+
+    /// Feeds `outcomes` to a fresh counter and collects everything it shows, with `|` for
+    /// each prompt that shows nothing.
+    fn coalesced(outcomes: &[(&str, Option<&str>)], interactive: bool) -> Vec<String> {
+        let mut counter = RepeatCounter::default();
+        let mut shown = Vec::new();
+        for (status, message) in outcomes {
+            let lines = counter.report(status, "A1=1+", *message, interactive);
+            if lines.is_empty() {
+                shown.push("|".to_string());
+            }
+            shown.extend(lines);
+        }
+        shown.extend(counter.finish(interactive));
+        shown
+    }
+
+    #[test]
+    fn test_repeats_coalesce_with_count() {
+        let bad = ("invalid_cell", Some("Invalid cell"));
+        let shown = coalesced(&[bad; 4], false);
+        assert_eq!(
+            shown,
+            vec![
+                "Invalid cell",
+                "|",
+                "|",
+                "|",
+                "… last message repeated 3 time(s)"
+            ]
+        );
+        // A single failure owes no count
+        assert_eq!(coalesced(&[bad], false), vec!["Invalid cell"]);
+    }
+
+    #[test]
+    fn test_distinct_messages_flush_the_count_first() {
+        let bad = ("invalid_cell", Some("Invalid cell"));
+        let other = ("unrecognized_cmd", Some("Unrecognized command"));
+        let ok = ("ok", None);
+        let shown = coalesced(&[bad, bad, other, other, other, ok, bad], false);
+        assert_eq!(
+            shown,
+            vec![
+                "Invalid cell",
+                "|",
+                "… last message repeated 1 time(s)",
+                "Unrecognized command",
+                "|",
+                "|",
+                "… last message repeated 2 time(s)",
+                "Invalid cell",
+            ]
+        );
+        // The same message under a different status is a different outcome
+        let same_text = ("not_owner", Some("Invalid cell"));
+        assert_eq!(
+            coalesced(&[bad, same_text], false),
+            vec!["Invalid cell", "Invalid cell"]
+        );
+        // Success between two failures ends the run without a count
+        assert_eq!(
+            coalesced(&[bad, ok, bad], false),
+            vec!["Invalid cell", "|", "Invalid cell"]
+        );
+    }
+
+    #[test]
+    fn test_interactive_count_replaces_the_message() {
+        let bad = ("invalid_cell", Some("Invalid cell"));
+        let shown = coalesced(&[bad, bad, bad, ("ok", None)], true);
+        assert_eq!(
+            shown,
+            vec![
+                "Invalid cell",
+                "… last message repeated 1 time(s)",
+                "… last message repeated 2 time(s)",
+                "|",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sink_report_joins_lines() {
+        let mut sink = OutputSink::capture();
+        assert_eq!(sink.report("ok", "A1=1", None), None);
+        assert_eq!(
+            sink.report("bad", "x", Some("Bad")),
+            Some("Bad".to_string())
+        );
+        assert_eq!(sink.report("bad", "x", Some("Bad")), None);
+        assert_eq!(
+            sink.report("worse", "y", Some("Worse")),
+            Some("… last message repeated 1 time(s)\nWorse".to_string())
+        );
+        assert_eq!(sink.report("worse", "y", Some("Worse")), None);
+        assert_eq!(
+            sink.finish_repeats(),
+            Some("… last message repeated 1 time(s)".to_string())
+        );
+        assert_eq!(sink.finish_repeats(), None);
+        // Nothing about the captured command output changes
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn test_different_commands_with_the_same_message_are_not_repeats() {
+        let mut sink = OutputSink::capture();
+        let message = Some("The command or formula was not understood");
+        assert!(
+            sink.report("unrecognized_cmd", "unname data", message)
+                .is_some()
+        );
+        assert_eq!(
+            sink.report("unrecognized_cmd", "name A1 B2", message),
+            message.map(str::to_string)
+        );
+        assert_eq!(sink.report("unrecognized_cmd", "name A1 B2", message), None);
+        assert_eq!(
+            sink.finish_repeats(),
+            Some("… last message repeated 1 time(s)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_set_page_size() {
+        use crate::evaluator::handle_command;
+        use crate::spreadsheet::{CommandStatus, Spreadsheet};
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut sleep_time = 0.0;
+        assert_eq!(sheet.output.page_size, DEFAULT_PAGE_SIZE);
+        assert_eq!(
+            handle_command(&mut sheet, "set page_size 0", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.output.page_size, 0);
+        assert_eq!(
+            handle_command(&mut sheet, "set page_size many", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+    }
+}
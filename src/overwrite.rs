@@ -0,0 +1,383 @@
+//! Asking before a bulk write overwrites cells that already hold something.
+//!
+//! With `set confirm_overwrite_bulk on`, a range assignment that would overwrite cells
+//! holding a value or formula is checked and then held back instead of applied. The
+//! command loop asks about each such cell through its own input and applies the
+//! assignment once every answer is in, so aborting leaves the sheet exactly as it was.
+//! Scripts and piped input never prompt: the assignment is applied and reported as usual.
+
+use crate::cell::CellValue;
+use crate::extensions::get_formula_string;
+use crate::range::Range;
+use crate::range_assign::{RangeWrite, apply_range_write};
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+
+/// The choices offered for each cell that would be overwritten.
+const CHOICES: &str = "(o)verwrite, (s)kip, (O)verwrite all, (S)kip all, (a)bort";
+
+/// An answer to the overwrite prompt.
+///
+/// # Variants
+///
+/// * `Overwrite` - Overwrite this cell.
+/// * `Skip` - Keep this cell.
+/// * `OverwriteAll` - Overwrite this cell and every later one without asking.
+/// * `SkipAll` - Keep this cell and every later one without asking.
+/// * `Abort` - Change nothing at all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverwriteAnswer {
+    Overwrite,
+    Skip,
+    OverwriteAll,
+    SkipAll,
+    Abort,
+}
+
+impl OverwriteAnswer {
+    /// Reads an answer; case matters, since `o` and `O` differ.
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim() {
+            "o" => Some(OverwriteAnswer::Overwrite),
+            "s" => Some(OverwriteAnswer::Skip),
+            "O" => Some(OverwriteAnswer::OverwriteAll),
+            "S" => Some(OverwriteAnswer::SkipAll),
+            "a" => Some(OverwriteAnswer::Abort),
+            _ => None,
+        }
+    }
+}
+
+/// A range assignment waiting for the user to confirm its overwrites.
+///
+/// # Fields
+///
+/// * `write` - The checked assignment.
+/// * `conflicts` - Target cells that hold something, in row-major order.
+#[derive(Debug, PartialEq)]
+pub struct PendingOverwrite {
+    pub write: RangeWrite,
+    pub conflicts: Vec<(i16, i16)>,
+}
+
+impl Spreadsheet {
+    /// Checks whether bulk writes should ask before overwriting: the setting is on,
+    /// someone is at the terminal, no script is running, and this is not a dry run.
+    pub fn confirms_overwrites(&self) -> bool {
+        self.confirm_overwrite_bulk
+            && self.output.interactive
+            && !self.script.is_running()
+            && self.dry_run.is_none()
+    }
+}
+
+/// Lists the cells of a range that hold a value other than 0 or a formula.
+///
+/// Locked cells are left out; the assignment skips them anyway.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `range` - The target range.
+///
+/// # Returns
+///
+/// * `Vec<(i16, i16)>` - The cells, in row-major order
+pub fn overwrite_conflicts(sheet: &Spreadsheet, range: &Range) -> Vec<(i16, i16)> {
+    range
+        .cells()
+        .filter(|&(row, col)| {
+            let key = sheet.get_key(row, col);
+            (*sheet.get_cell(row, col) != CellValue::Empty || sheet.cell_meta.contains_key(&key))
+                && !sheet.is_cell_locked(row, col)
+        })
+        .collect()
+}
+
+/// Describes what a cell holds, e.g. "A2 holds 5" or "B1 holds ERR (A1/0)".
+fn describe_cell(sheet: &Spreadsheet, row: i16, col: i16) -> String {
+    let value = match sheet.get_cell(row, col) {
+        CellValue::Integer(value) => value.to_string(),
+        CellValue::Error(kind) => kind.code(),
+        CellValue::Text(text) => format!("\"{}\"", text),
+        CellValue::Empty => "empty".to_string(),
+    };
+    let mut text = format!("{} holds {}", sheet.get_cell_name(row, col), value);
+    if sheet.cell_meta.contains_key(&sheet.get_key(row, col)) {
+        text.push_str(&format!(" ({})", get_formula_string(sheet, row, col)));
+    }
+    text
+}
+
+/// Asks about each conflicting cell until every one is decided or the user aborts.
+///
+/// An answer that is not one of the choices is asked again. The end of input aborts, so
+/// nothing is overwritten without an answer.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for describing the cells.
+/// * `conflicts` - The cells that would be overwritten.
+/// * `input` - Where answers are read from.
+/// * `out` - Where prompts are written.
+///
+/// # Returns
+///
+/// * `Ok(Some(keep))` - The cells to leave as they are.
+/// * `Ok(None)` - If the user aborted.
+/// * `Err(io::Error)` - If writing a prompt fails
+pub fn ask_overwrites<R: BufRead, W: Write>(
+    sheet: &Spreadsheet,
+    conflicts: &[(i16, i16)],
+    input: &mut R,
+    out: &mut W,
+) -> io::Result<Option<HashSet<(i16, i16)>>> {
+    let mut keep = HashSet::new();
+    let mut sticky = None;
+    for &cell in conflicts {
+        let overwrite = match sticky {
+            Some(overwrite) => overwrite,
+            None => loop {
+                write!(
+                    out,
+                    "{}; overwrite? {}: ",
+                    describe_cell(sheet, cell.0, cell.1),
+                    CHOICES
+                )?;
+                out.flush()?;
+                let mut answer = String::new();
+                if input.read_line(&mut answer)? == 0 {
+                    return Ok(None);
+                }
+                match OverwriteAnswer::parse(&answer) {
+                    Some(OverwriteAnswer::Overwrite) => break true,
+                    Some(OverwriteAnswer::Skip) => break false,
+                    Some(OverwriteAnswer::OverwriteAll) => {
+                        sticky = Some(true);
+                        break true;
+                    }
+                    Some(OverwriteAnswer::SkipAll) => {
+                        sticky = Some(false);
+                        break false;
+                    }
+                    Some(OverwriteAnswer::Abort) => return Ok(None),
+                    None => writeln!(out, "Please answer o, s, O, S or a")?,
+                }
+            },
+        };
+        if !overwrite {
+            keep.insert(cell);
+        }
+    }
+    Ok(Some(keep))
+}
+
+/// Confirms and applies the assignment left by the last command, if there is one.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; the summary goes to its sink.
+/// * `input` - Where answers are read from.
+/// * `out` - Where prompts are written.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `Some(CommandStatus)` - The assignment's status; `CmdOk` if the user aborted.
+/// * `None` - If no assignment was waiting
+pub fn resolve_pending_overwrite<R: BufRead, W: Write>(
+    sheet: &mut Spreadsheet,
+    input: &mut R,
+    out: &mut W,
+    sleep_time: &mut f64,
+) -> Option<CommandStatus> {
+    let pending = sheet.pending_overwrite.take()?;
+    match ask_overwrites(sheet, &pending.conflicts, input, out) {
+        Ok(Some(keep)) => Some(apply_range_write(sheet, &pending.write, &keep, sleep_time)),
+        Ok(None) | Err(_) => {
+            sheet.output.line(format!(
+                "{}: aborted, no cells changed",
+                pending.write.target
+            ));
+            Some(CommandStatus::CmdOk)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::handle_command;
+    use crate::output::OutputSink;
+    use crate::process_command::process_command_timed;
+
+    /// A1 = 5, A2 = A1*2 and A4 = 7 on an interactive sheet that confirms overwrites;
+    /// A3 is empty.
+    fn fixture() -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(6, 3).unwrap();
+        sheet.output = OutputSink::capture();
+        sheet.output.interactive = true;
+        let mut sleep_time = 0.0;
+        for command in ["A1=5", "A2=A1*2", "A4=7", "set confirm_overwrite_bulk on"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        sheet
+    }
+
+    /// Runs a range assignment and answers its prompts.
+    ///
+    /// # Returns
+    ///
+    /// * `(Option<CommandStatus>, String)` - The final status and the prompts written
+    fn run(
+        sheet: &mut Spreadsheet,
+        command: &str,
+        answers: &str,
+    ) -> (Option<CommandStatus>, String) {
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(sheet, command, &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        let mut out = Vec::new();
+        let status =
+            resolve_pending_overwrite(sheet, &mut answers.as_bytes(), &mut out, &mut sleep_time);
+        (status, String::from_utf8(out).unwrap())
+    }
+
+    fn column_a(sheet: &Spreadsheet) -> Vec<CellValue> {
+        (0..4).map(|row| sheet.get_cell(row, 0).clone()).collect()
+    }
+
+    #[test]
+    fn test_parse_answers() {
+        assert_eq!(
+            OverwriteAnswer::parse("o\n"),
+            Some(OverwriteAnswer::Overwrite)
+        );
+        assert_eq!(OverwriteAnswer::parse("S"), Some(OverwriteAnswer::SkipAll));
+        assert_eq!(OverwriteAnswer::parse("a"), Some(OverwriteAnswer::Abort));
+        assert_eq!(OverwriteAnswer::parse("yes"), None);
+    }
+
+    #[test]
+    fn test_overwrite_and_skip_each_cell() {
+        let mut sheet = fixture();
+        let (status, prompts) = run(&mut sheet, "A1:A4=1", "s\no\nx\ns\n");
+        assert_eq!(status, Some(CommandStatus::CmdOk));
+        assert!(prompts.starts_with(&format!(
+            "A1 holds 5; overwrite? {}: A2 holds 10 (A1*2); overwrite? {}: ",
+            CHOICES, CHOICES
+        )));
+        assert!(prompts.contains("Please answer o, s, O, S or a"));
+        // A1 and A4 kept, A2 overwritten, A3 empty so written without asking
+        assert_eq!(
+            column_a(&sheet),
+            vec![
+                CellValue::Integer(5),
+                CellValue::Integer(1),
+                CellValue::Integer(1),
+                CellValue::Integer(7)
+            ]
+        );
+        assert_eq!(get_formula_string(&sheet, 1, 0), "No formula");
+        assert_eq!(
+            sheet.output.take(),
+            vec!["A1:A4: 2 cell(s) set, 0 skipped, 2 kept"]
+        );
+    }
+
+    #[test]
+    fn test_all_answers_stick_for_the_operation() {
+        let mut sheet = fixture();
+        let (_, prompts) = run(&mut sheet, "A1:A4=1", "s\nO\n");
+        assert_eq!(prompts.matches("overwrite?").count(), 2);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(5));
+        assert_eq!(*sheet.get_cell(3, 0), CellValue::Integer(1));
+
+        let mut sheet = fixture();
+        let (_, prompts) = run(&mut sheet, "A1:A4=B1+1", "S\n");
+        assert_eq!(prompts.matches("overwrite?").count(), 1);
+        assert_eq!(
+            column_a(&sheet),
+            vec![
+                CellValue::Integer(5),
+                CellValue::Integer(10),
+                CellValue::Integer(1),
+                CellValue::Integer(7)
+            ]
+        );
+
+        // The next operation asks again
+        let (_, prompts) = run(&mut sheet, "A1:A2=3", "o\no\n");
+        assert_eq!(prompts.matches("overwrite?").count(), 2);
+    }
+
+    #[test]
+    fn test_abort_leaves_sheet_untouched() {
+        let mut sheet = fixture();
+        let before = column_a(&sheet);
+        // Abort after answering overwrite for the first cell
+        let (status, _) = run(&mut sheet, "A1:A4=1", "o\na\n");
+        assert_eq!(status, Some(CommandStatus::CmdOk));
+        assert_eq!(column_a(&sheet), before);
+        assert_eq!(get_formula_string(&sheet, 1, 0), "A1*2");
+        assert_eq!(
+            sheet.output.take(),
+            vec!["A1:A4: aborted, no cells changed"]
+        );
+
+        // Running out of input aborts too
+        let (_, _) = run(&mut sheet, "A1:A4=1", "o\n");
+        assert_eq!(column_a(&sheet), before);
+        assert!(sheet.pending_overwrite.is_none());
+    }
+
+    #[test]
+    fn test_elementwise_and_empty_targets() {
+        let mut sheet = fixture();
+        let (_, prompts) = run(&mut sheet, "B1:B2=A1:A2+1", "");
+        // Nothing to overwrite: applied at once without asking
+        assert!(prompts.is_empty());
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(11));
+        let (_, prompts) = run(&mut sheet, "B1:B2=A3:A4*2", "o\ns\n");
+        assert_eq!(prompts.matches("overwrite?").count(), 2);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(11));
+    }
+
+    #[test]
+    fn test_non_interactive_contexts_do_not_prompt() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        sheet.output.interactive = false;
+        handle_command(&mut sheet, "A1:A4=1", &mut sleep_time);
+        assert!(sheet.pending_overwrite.is_none());
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(1));
+
+        // Scripts apply at once even in an interactive session
+        let mut sheet = fixture();
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("fill.txt");
+        std::fs::write(&script, "A1:A4=2\n").unwrap();
+        let (status, _) = process_command_timed(&mut sheet, &format!("run {}", script.display()));
+        assert_eq!(status, CommandStatus::CmdOk);
+        assert!(sheet.pending_overwrite.is_none());
+        assert_eq!(*sheet.get_cell(3, 0), CellValue::Integer(2));
+
+        // With the setting off nothing is held back
+        let mut sheet = fixture();
+        handle_command(
+            &mut sheet,
+            "set confirm_overwrite_bulk off",
+            &mut sleep_time,
+        );
+        handle_command(&mut sheet, "A1:A4=3", &mut sleep_time);
+        assert!(sheet.pending_overwrite.is_none());
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(3));
+    }
+}
@@ -0,0 +1,542 @@
+//! Ownership tags: which user works on which cells, for sheets passed between people.
+//!
+//! Tags are guard rails, not security. Anyone can retag cells, change the current user,
+//! or turn enforcement off, and the save file is plain text. With `enforce_ownership`
+//! off (the default) tags only inform; with it on, writes to cells owned by another user
+//! are refused with `NotOwner`. Every command treats such a cell as it treats a locked
+//! one: those that refuse a locked cell (`copy`, `series`, `sort`, ...) refuse it, and
+//! those that skip and report a locked cell (range assignments, `replace`) skip and
+//! report it. Untagged cells are free for everyone.
+//!
+//! Tags never overlap: tagging a range takes its cells away from earlier tags, which
+//! shrink (or split into up to four rectangles) around it. So the most recent tag wins.
+
+use crate::range::{Range, resolve_target};
+use crate::repl::take_flag_value;
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use crate::table::{Align, Table};
+
+/// A range of cells and the user who owns it.
+///
+/// # Fields
+///
+/// * `range` - The owned cells.
+/// * `owner` - The owning user's name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnerTag {
+    pub range: Range,
+    pub owner: String,
+}
+
+/// Ownership tags and who is editing.
+///
+/// # Fields
+///
+/// * `tags` - Tagged ranges, oldest first; no two overlap.
+/// * `user` - The current user, from `--as` or `set user`.
+/// * `enforce` - Whether assignments to cells owned by someone else are refused.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ownership {
+    pub tags: Vec<OwnerTag>,
+    pub user: Option<String>,
+    pub enforce: bool,
+}
+
+impl Ownership {
+    /// Tags a range as owned by `owner`, taking its cells away from earlier tags.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The cells to tag.
+    /// * `owner` - The owning user's name.
+    pub fn tag(&mut self, range: Range, owner: &str) {
+        self.untag(&range);
+        self.tags.push(OwnerTag {
+            range,
+            owner: owner.to_string(),
+        });
+    }
+
+    /// Removes the tags from a range, shrinking the tags that cover part of it.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The cells to untag.
+    pub fn untag(&mut self, range: &Range) {
+        self.tags = std::mem::take(&mut self.tags)
+            .into_iter()
+            .flat_map(|tag| {
+                subtract(&tag.range, range)
+                    .into_iter()
+                    .map(move |rest| OwnerTag {
+                        range: rest,
+                        owner: tag.owner.clone(),
+                    })
+            })
+            .collect();
+    }
+
+    /// Returns the owner of a cell, if it is tagged.
+    pub fn owner_of(&self, row: i16, col: i16) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.range.contains(row, col))
+            .map(|tag| tag.owner.as_str())
+    }
+
+    /// Returns the owner of a cell if enforcement is on and the owner is not the current user.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(&str)` - The owner, if an assignment to the cell must be refused.
+    /// * `None` - If the cell may be written
+    pub fn blocking_owner(&self, row: i16, col: i16) -> Option<&str> {
+        if !self.enforce {
+            return None;
+        }
+        self.owner_of(row, col)
+            .filter(|&owner| Some(owner) != self.user.as_deref())
+    }
+}
+
+/// Splits `range` into the rectangles left after removing its overlap with `cut`.
+///
+/// # Returns
+///
+/// * `Vec<Range>` - Up to four ranges: the rows above and below the cut, then the
+///   columns left and right of it; empty if `cut` covers `range`
+fn subtract(range: &Range, cut: &Range) -> Vec<Range> {
+    let top = range.start_row.max(cut.start_row);
+    let bottom = range.end_row.min(cut.end_row);
+    let left = range.start_col.max(cut.start_col);
+    let right = range.end_col.min(cut.end_col);
+    if top > bottom || left > right {
+        return vec![*range];
+    }
+    let mut pieces = Vec::new();
+    if range.start_row < top {
+        pieces.push(Range {
+            end_row: top - 1,
+            ..*range
+        });
+    }
+    if bottom < range.end_row {
+        pieces.push(Range {
+            start_row: bottom + 1,
+            ..*range
+        });
+    }
+    if range.start_col < left {
+        pieces.push(Range {
+            start_row: top,
+            end_row: bottom,
+            start_col: range.start_col,
+            end_col: left - 1,
+        });
+    }
+    if right < range.end_col {
+        pieces.push(Range {
+            start_row: top,
+            end_row: bottom,
+            start_col: right + 1,
+            end_col: range.end_col,
+        });
+    }
+    pieces
+}
+
+/// Removes `--as <user>` from the command-line arguments.
+///
+/// # Arguments
+///
+/// * `args` - The command-line arguments; the flag and its value are removed.
+///
+/// # Returns
+///
+/// * `Ok(Some(String))` - The user given with the flag.
+/// * `Ok(None)` - If the flag is absent or has no value.
+/// * `Err(String)` - The name, if it is not a valid user name
+pub fn take_user_flag(args: &mut Vec<String>) -> Result<Option<String>, String> {
+    match take_flag_value(args, "--as") {
+        Some(user) if !is_valid_user(&user) => Err(user),
+        user => Ok(user),
+    }
+}
+
+/// Checks that a user name is one word without commas, so it fits a save file record.
+pub fn is_valid_user(name: &str) -> bool {
+    !name.is_empty() && !name.contains(',') && !name.contains(char::is_whitespace)
+}
+
+impl Spreadsheet {
+    /// Checks whether the current user may assign to a cell.
+    ///
+    /// # Returns
+    ///
+    /// * `CommandStatus::CmdOk` - If the cell is untagged, owned by the current user, or
+    ///   enforcement is off.
+    /// * `CommandStatus::NotOwner` - Otherwise, after printing who owns the cell
+    pub fn check_owner(&self, row: i16, col: i16) -> CommandStatus {
+        match self.ownership.blocking_owner(row, col) {
+            Some(owner) => {
+                eprintln!("{} is owned by {}", self.get_cell_name(row, col), owner);
+                CommandStatus::NotOwner
+            }
+            None => CommandStatus::CmdOk,
+        }
+    }
+}
+
+/// Handles `own <cell|range> <user>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `own `.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - For bad syntax or a user name with commas.
+/// * The reference's status - If the cell or range is invalid
+pub fn handle_own(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let [target, owner] = parts.as_slice() else {
+        return CommandStatus::Unrecognized;
+    };
+    if !is_valid_user(owner) {
+        return CommandStatus::Unrecognized;
+    }
+    match resolve_target(sheet, target) {
+        Ok(range) => {
+            sheet.ownership.tag(range, owner);
+            sheet.dirty = true;
+            CommandStatus::CmdOk
+        }
+        Err(status) => status,
+    }
+}
+
+/// Handles `disown <cell|range>`, leaving its cells untagged.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `disown `.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * The reference's status - If the cell or range is invalid
+pub fn handle_disown(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    match resolve_target(sheet, args.trim()) {
+        Ok(range) => {
+            sheet.ownership.untag(&range);
+            sheet.dirty = true;
+            CommandStatus::CmdOk
+        }
+        Err(status) => status,
+    }
+}
+
+/// Handles `owners`: lists the tagged ranges, the current user and whether tags are enforced.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - Always
+pub fn handle_owners(sheet: &mut Spreadsheet) -> CommandStatus {
+    let ownership = &sheet.ownership;
+    let mut lines = vec![format!(
+        "user: {}, enforce_ownership: {}",
+        ownership.user.as_deref().unwrap_or("(none)"),
+        if ownership.enforce { "on" } else { "off" }
+    )];
+    if ownership.tags.is_empty() {
+        lines.push("No ownership tags".to_string());
+    } else {
+        let mut table = Table::new(&[
+            ("range", Align::Left),
+            ("owner", Align::Left),
+            ("cells", Align::Right),
+        ]);
+        for tag in &ownership.tags {
+            table.push_row(vec![
+                sheet.get_range_name(&tag.range),
+                tag.owner.clone(),
+                tag.range.cell_count().to_string(),
+            ]);
+        }
+        lines.extend(table.render());
+    }
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::CellValue;
+    use crate::evaluator::handle_command;
+    use crate::output::OutputSink;
+    use crate::range::parse_range;
+
+    fn run(sheet: &mut Spreadsheet, command: &str) -> CommandStatus {
+        let mut sleep_time = 0.0;
+        handle_command(sheet, command, &mut sleep_time)
+    }
+
+    fn range(sheet: &Spreadsheet, text: &str) -> Range {
+        parse_range(sheet, text).unwrap()
+    }
+
+    fn tag_names(sheet: &Spreadsheet) -> Vec<(String, String)> {
+        sheet
+            .ownership
+            .tags
+            .iter()
+            .map(|tag| (sheet.get_range_name(&tag.range), tag.owner.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_tagging_and_lookup() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        assert_eq!(run(&mut sheet, "own A1:C3 alice"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "own E5 bob"), CommandStatus::CmdOk);
+        assert_eq!(sheet.ownership.owner_of(2, 2), Some("alice"));
+        assert_eq!(sheet.ownership.owner_of(4, 4), Some("bob"));
+        assert_eq!(sheet.ownership.owner_of(3, 0), None);
+        assert!(sheet.dirty);
+
+        // A range off the sheet is refused as it is by lock_cell
+        assert_eq!(
+            run(&mut sheet, "own A1:Z1 alice"),
+            run(&mut sheet, "lock_cell A1:Z1")
+        );
+        assert_eq!(sheet.ownership.owner_of(0, 9), None);
+        assert_eq!(run(&mut sheet, "own A1 a,b"), CommandStatus::Unrecognized);
+        assert_eq!(run(&mut sheet, "own A1"), CommandStatus::Unrecognized);
+
+        assert_eq!(run(&mut sheet, "disown A1:C1"), CommandStatus::CmdOk);
+        assert_eq!(sheet.ownership.owner_of(0, 1), None);
+        assert_eq!(sheet.ownership.owner_of(1, 1), Some("alice"));
+    }
+
+    #[test]
+    fn test_most_recent_tag_wins_and_earlier_tag_shrinks() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        run(&mut sheet, "own A1:C3 alice");
+        // A tag in the middle splits the earlier one around it
+        run(&mut sheet, "own B2 bob");
+        assert_eq!(
+            tag_names(&sheet),
+            vec![
+                ("A1:C1".to_string(), "alice".to_string()),
+                ("A3:C3".to_string(), "alice".to_string()),
+                ("A2:A2".to_string(), "alice".to_string()),
+                ("C2:C2".to_string(), "alice".to_string()),
+                ("B2:B2".to_string(), "bob".to_string()),
+            ]
+        );
+        // Every cell keeps exactly one owner
+        for (row, col) in range(&sheet, "A1:C3").cells() {
+            let owners = sheet
+                .ownership
+                .tags
+                .iter()
+                .filter(|tag| tag.range.contains(row, col))
+                .count();
+            assert_eq!(owners, 1);
+        }
+
+        // A tag covering an earlier one replaces it entirely
+        run(&mut sheet, "own A1:D4 carol");
+        assert_eq!(
+            tag_names(&sheet),
+            vec![("A1:D4".to_string(), "carol".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_subtract_partial_overlap() {
+        let sheet = Spreadsheet::create(10, 10).unwrap();
+        let pieces = subtract(&range(&sheet, "A1:C3"), &range(&sheet, "B2:E5"));
+        let names: Vec<String> = pieces.iter().map(|r| sheet.get_range_name(r)).collect();
+        assert_eq!(names, vec!["A1:C1", "A2:A3"]);
+        assert_eq!(
+            subtract(&range(&sheet, "A1:B2"), &range(&sheet, "D4:E5")),
+            vec![range(&sheet, "A1:B2")]
+        );
+    }
+
+    #[test]
+    fn test_enforcement() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        run(&mut sheet, "own A1:A3 alice");
+
+        // Tags are advisory while enforcement is off
+        assert_eq!(run(&mut sheet, "A1=1"), CommandStatus::CmdOk);
+
+        assert_eq!(
+            run(&mut sheet, "set enforce_ownership on"),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(run(&mut sheet, "A1=2"), CommandStatus::NotOwner);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(1));
+        // Untagged cells are free for everyone
+        assert_eq!(run(&mut sheet, "B1=A1+1"), CommandStatus::CmdOk);
+
+        assert_eq!(run(&mut sheet, "set user bob"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "A2=5"), CommandStatus::NotOwner);
+        sheet.output = OutputSink::capture();
+        assert_eq!(run(&mut sheet, "A1:B3=7"), CommandStatus::CmdOk);
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                "A1:B3: 3 cell(s) set, 3 skipped",
+                "A1 skipped (not_owner)",
+                "A2 skipped (not_owner)",
+                "A3 skipped (not_owner)",
+            ]
+        );
+
+        assert_eq!(run(&mut sheet, "set user alice"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "A2=5"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "set user a,b"), CommandStatus::Unrecognized);
+
+        run(&mut sheet, "set user bob");
+        assert_eq!(
+            run(&mut sheet, "set enforce_ownership off"),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(run(&mut sheet, "A3=9"), CommandStatus::CmdOk);
+    }
+
+    #[test]
+    fn test_enforcement_covers_replace_and_sort() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        for command in ["A1=5", "A2=9", "B1=5", "own A1:A2 alice"] {
+            run(&mut sheet, command);
+        }
+        run(&mut sheet, "set enforce_ownership on");
+        run(&mut sheet, "set user bob");
+
+        // Owned cells are skipped and reported, like locked ones
+        sheet.output = OutputSink::capture();
+        assert_eq!(run(&mut sheet, "replace 5 1"), CommandStatus::CmdOk);
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                "A1 skipped (owned)",
+                "Changed 1 cell(s), skipped 0 formula cell(s), 0 locked cell(s) and 1 owned cell(s)",
+            ]
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(5));
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(1));
+
+        // A sort moving an owned cell is refused whole, like one over a locked cell
+        assert_eq!(
+            run(&mut sheet, "sort A1:A2 by A desc"),
+            CommandStatus::NotOwner
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(5));
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(9));
+
+        run(&mut sheet, "set user alice");
+        assert_eq!(
+            run(&mut sheet, "sort A1:A2 by A desc"),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(9));
+    }
+
+    #[test]
+    fn test_current_user_from_flag_and_command() {
+        let mut args: Vec<String> = ["spreadsheet", "--as", "bob", "10", "10"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(take_user_flag(&mut args), Ok(Some("bob".to_string())));
+        assert_eq!(args, vec!["spreadsheet", "10", "10"]);
+        assert_eq!(take_user_flag(&mut args), Ok(None));
+        let mut bad = vec!["--as".to_string(), "a,b".to_string()];
+        assert_eq!(take_user_flag(&mut bad), Err("a,b".to_string()));
+
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        sheet.ownership.user = Some("bob".to_string());
+        run(&mut sheet, "own A1 bob");
+        run(&mut sheet, "set enforce_ownership on");
+        assert_eq!(run(&mut sheet, "A1=1"), CommandStatus::CmdOk);
+        // The command switches users mid-session
+        run(&mut sheet, "set user alice");
+        assert_eq!(sheet.ownership.user.as_deref(), Some("alice"));
+        assert_eq!(run(&mut sheet, "A1=2"), CommandStatus::NotOwner);
+    }
+
+    #[test]
+    fn test_tags_persist_in_save_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("owned.sheet");
+        let path = path.to_str().unwrap();
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        run(&mut sheet, "own A1:C3 alice");
+        run(&mut sheet, "own B2 bob");
+        run(&mut sheet, "A1=5");
+        assert_eq!(
+            run(&mut sheet, &format!("save {}", path)),
+            CommandStatus::CmdOk
+        );
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert!(contents.contains("\nOWN,B2:B2,bob\n"));
+
+        let mut loaded = Spreadsheet::create(10, 10).unwrap();
+        run(&mut loaded, "own J10 carol");
+        assert_eq!(
+            run(&mut loaded, &format!("open {}", path)),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(loaded.ownership.tags, sheet.ownership.tags);
+        assert_eq!(*loaded.get_cell(0, 0), CellValue::Integer(5));
+        // Settings stay with the session, not the file
+        assert!(!loaded.ownership.enforce);
+        assert_eq!(loaded.ownership.user, None);
+
+        // Bad tags are skipped with a warning
+        std::fs::write(path, "DIMS,10,10\nOWN,A1:Z99,bob\nOWN,A1:A2,a b\nOWN,A1\n").unwrap();
+        assert_eq!(
+            run(&mut loaded, &format!("open {}", path)),
+            CommandStatus::CmdOk
+        );
+        assert!(loaded.ownership.tags.is_empty());
+    }
+
+    #[test]
+    fn test_owners_listing() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        sheet.output = OutputSink::capture();
+        run(&mut sheet, "owners");
+        assert_eq!(
+            sheet.output.take(),
+            vec!["user: (none), enforce_ownership: off", "No ownership tags"]
+        );
+
+        run(&mut sheet, "own A1:C10 alice");
+        run(&mut sheet, "own D1 bob");
+        run(&mut sheet, "set user bob");
+        run(&mut sheet, "owners");
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                "user: bob, enforce_ownership: off",
+                "range   owner  cells",
+                "------  -----  -----",
+                "A1:C10  alice     30",
+                "D1:D1   bob        1",
+            ]
+        );
+    }
+}
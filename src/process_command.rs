@@ -1,10 +1,1087 @@
 use crate::evaluator::handle_command;
+#[cfg(feature = "extensions")]
+use crate::evaluator::{parse_value_token, resolve_cell_reference};
+use crate::expr_parser::parse_expression;
+#[cfg(feature = "extensions")]
+use crate::expr_parser::parse_vector_expression;
+#[cfg(feature = "extensions")]
+use crate::range::{resolve_range, resolve_target};
+#[cfg(feature = "extensions")]
+use crate::scripting::{handle_scripting_command, substitute};
+#[cfg(feature = "extensions")]
+use crate::spreadsheet::MAX_BACKUPS;
 use crate::spreadsheet::{CommandStatus, Spreadsheet};
+#[cfg(feature = "extensions")]
+use crate::table::{Align, Table};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
+/// How long a command took, split into work and requested sleep.
+///
+/// # Fields
+///
+/// * `elapsed` - Seconds spent executing the command.
+/// * `sleep` - Seconds spent sleeping on top of that for `SLEEP` formulas.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CommandTiming {
+    pub elapsed: f64,
+    pub sleep: f64,
+}
+
+impl CommandTiming {
+    /// Returns the wall-clock time of the command, sleep included.
+    #[cfg(feature = "extensions")]
+    pub fn total(&self) -> f64 {
+        self.elapsed + self.sleep
+    }
+}
+
+/// Splits a line into the commands separated by `;`.
+///
+/// Separators inside double quotes are kept, so quoted text may contain `;`. A line
+/// with no command at all is returned as is.
+///
+/// # Arguments
+///
+/// * `line` - The line as entered.
+///
+/// # Returns
+///
+/// * `Vec<&str>` - The trimmed, non-empty commands in order
+pub fn split_commands(line: &str) -> Vec<&str> {
+    let mut commands = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                commands.push(line[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    commands.push(line[start..].trim());
+    commands.retain(|command| !command.is_empty());
+    if commands.is_empty() {
+        commands.push(line.trim());
+    }
+    commands
+}
+
+/// Explains an unrecognized assignment that looks like several commands on one line.
+///
+/// # Arguments
+///
+/// * `command` - A command that came back `Unrecognized`.
+///
+/// # Returns
+///
+/// * `Some(message)` - If the right-hand side holds another `=` or a space.
+/// * `None` - Otherwise
+pub fn multi_command_hint(command: &str) -> Option<&'static str> {
+    let (target, expr) = command.split_once('=')?;
+    let target = target.trim();
+    if target.is_empty() || target.contains(char::is_whitespace) {
+        return None;
+    }
+    let expr = expr.trim();
+    if expr.contains('=') {
+        Some("unexpected '=' in expression; use ';' to separate commands")
+    } else if expr.contains(char::is_whitespace) {
+        Some("unexpected space in expression; use ';' to separate commands")
+    } else {
+        None
+    }
+}
+
+/// Explains an unrecognized assignment whose right-hand side looks like unquoted text.
+///
+/// Text is stored only when quoted, because a bare word reads as a name, e.g. `A3=hello`.
+///
+/// # Arguments
+///
+/// * `command` - A command that came back `Unrecognized`.
+///
+/// # Returns
+///
+/// * `Some(message)` - If the right-hand side is made of letters and spaces only.
+/// * `None` - Otherwise
+pub fn text_hint(command: &str) -> Option<String> {
+    let (target, expr) = command.split_once('=')?;
+    let target = target.trim();
+    let expr = expr.trim();
+    if target.is_empty() || target.contains(char::is_whitespace) || expr.is_empty() {
+        return None;
+    }
+    if !expr
+        .split_whitespace()
+        .all(|word| word.chars().all(char::is_alphabetic))
+    {
+        return None;
+    }
+    Some(format!(
+        "text must be in double quotes, as in {}=\"{}\"",
+        target, expr
+    ))
+}
+
+/// Points at the character where an unrecognized assignment's expression stops following
+/// the grammar in `expr_parser`.
+///
+/// # Arguments
+///
+/// * `command` - A command that came back `Unrecognized`.
+///
+/// # Returns
+///
+/// * `Some(message)` - The command, then a caret under the offending character and what
+///   was expected there.
+/// * `None` - If the command is not an assignment or its expression parses
+pub fn expression_hint(command: &str) -> Option<String> {
+    let (target, rest) = command.split_once('=')?;
+    let target = target.trim();
+    if target.is_empty() || target.contains(char::is_whitespace) {
+        return None;
+    }
+    let expr = rest.trim();
+    #[cfg(feature = "extensions")]
+    let expr = expr.strip_suffix('!').map_or(expr, str::trim_end);
+    // A range target may take an elementwise expression instead
+    #[cfg(feature = "extensions")]
+    if target.contains(':') && parse_vector_expression(expr).is_ok() {
+        return None;
+    }
+    let error = parse_expression(expr).err()?;
+    let offset = command.len() - rest.trim_start().len() + error.position;
+    let column = command[..offset].chars().count();
+    Some(format!(
+        "{}\n{}^ expected {}",
+        command,
+        " ".repeat(column),
+        error.expected
+    ))
+}
+
+/// The kind of value a command argument takes; each kind has one parser shared by every
+/// command using it.
+///
+/// # Variants
+///
+/// * `Cell` - A cell reference or a name for a single cell.
+/// * `Range` - A range or a named range.
+/// * `CellOrRange` - Either of the above.
+/// * `Integer` - A whole number from the first bound to the second, inclusive.
+/// * `Value` - A cell value to match: an integer, or `ERR` for any error.
+/// * `Keywords` - One of the listed words.
+/// * `Name` - Any single word.
+/// * `File` - A file path: the rest of the line, which may contain spaces.
+/// * `Text` - The rest of the line, in a syntax the command parses itself; the usage line
+///   shows the argument's name as written.
+#[cfg(feature = "extensions")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgType {
+    Cell,
+    Range,
+    CellOrRange,
+    Integer(i64, i64),
+    Value,
+    Keywords(&'static [&'static str]),
+    Name,
+    File,
+    Text,
+}
+
+/// One argument in a command form.
+///
+/// # Fields
+///
+/// * `name` - How the usage line shows it, e.g. `n` for `<n>`.
+/// * `kind` - The values it takes.
+/// * `optional` - Whether it may be left out; only trailing arguments are optional.
+#[cfg(feature = "extensions")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgType,
+    pub optional: bool,
+}
+
+/// A command with the argument forms it accepts.
+///
+/// # Fields
+///
+/// * `name` - The command word.
+/// * `forms` - Each accepted list of arguments.
+/// * `summary` - What the command does, for `help`.
+#[cfg(feature = "extensions")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub forms: &'static [&'static [ArgSpec]],
+    pub summary: &'static str,
+}
+
+/// Why a command's arguments were rejected.
+///
+/// # Fields
+///
+/// * `status` - The status the command returns, as its own parsing would have.
+/// * `message` - What was wrong, then the command's usage line.
+#[cfg(feature = "extensions")]
+#[derive(Debug, PartialEq)]
+pub struct ArgError {
+    pub status: CommandStatus,
+    pub message: String,
+}
+
+#[cfg(feature = "extensions")]
+const fn arg(name: &'static str, kind: ArgType) -> ArgSpec {
+    ArgSpec {
+        name,
+        kind,
+        optional: false,
+    }
+}
+
+#[cfg(feature = "extensions")]
+const fn optional(name: &'static str, kind: ArgType) -> ArgSpec {
+    ArgSpec {
+        name,
+        kind,
+        optional: true,
+    }
+}
+
+#[cfg(feature = "extensions")]
+const ON_OFF: ArgType = ArgType::Keywords(&["on", "off"]);
+#[cfg(feature = "extensions")]
+const POSITIVE: ArgType = ArgType::Integer(1, i64::MAX);
+#[cfg(feature = "extensions")]
+const TRANSITIVE: ArgType = ArgType::Keywords(&["--transitive"]);
+#[cfg(feature = "extensions")]
+const CELL_NUMBER: ArgType = ArgType::Integer(i32::MIN as i64, i32::MAX as i64);
+/// The targets of the lock commands: a cell or range, or `ROW <n>` / `COL <col>`.
+#[cfg(feature = "extensions")]
+const LOCK_TARGETS: &[&[ArgSpec]] = &[
+    &[arg("cell/range", ArgType::CellOrRange)],
+    &[
+        arg("ROW", ArgType::Keywords(&["ROW"])),
+        arg("row", POSITIVE),
+    ],
+    &[
+        arg("COL", ArgType::Keywords(&["COL"])),
+        arg("column", ArgType::Name),
+    ],
+];
+
+/// The cell of the highlight commands, which report any bad cell as `InvalidCell`.
+#[cfg(feature = "extensions")]
+const HIGHLIGHT_TARGET: &[&[ArgSpec]] = &[&[arg("<cell>", ArgType::Text)]];
+
+/// Every command, for `help`. The arguments of those reaching the checks in
+/// `handle_command` are checked against their forms before they run.
+#[cfg(feature = "extensions")]
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "w",
+        forms: &[&[]],
+        summary: "Scroll the view up",
+    },
+    CommandSpec {
+        name: "a",
+        forms: &[&[]],
+        summary: "Scroll the view left",
+    },
+    CommandSpec {
+        name: "s",
+        forms: &[&[]],
+        summary: "Scroll the view down",
+    },
+    CommandSpec {
+        name: "d",
+        forms: &[&[]],
+        summary: "Scroll the view right",
+    },
+    CommandSpec {
+        name: "scroll_to",
+        forms: &[&[arg("cell", ArgType::Cell)]],
+        summary: "Make a cell the top left cell of the view",
+    },
+    CommandSpec {
+        name: "last_edit",
+        forms: &[&[]],
+        summary: "Make the last edited cell the top left cell of the view",
+    },
+    CommandSpec {
+        name: "enable_output",
+        forms: &[&[]],
+        summary: "Print the grid after each command",
+    },
+    CommandSpec {
+        name: "disable_output",
+        forms: &[&[]],
+        summary: "Stop printing the grid after each command",
+    },
+    CommandSpec {
+        name: "lock_cell",
+        forms: LOCK_TARGETS,
+        summary: "Stop a cell or range from being edited",
+    },
+    CommandSpec {
+        name: "unlock_cell",
+        forms: LOCK_TARGETS,
+        summary: "Allow editing a locked cell or range again",
+    },
+    CommandSpec {
+        name: "unlock_all",
+        forms: &[&[]],
+        summary: "Remove every lock",
+    },
+    CommandSpec {
+        name: "delete",
+        forms: &[&[arg("cell/range", ArgType::CellOrRange)]],
+        summary: "Return a cell or range to its never-set state",
+    },
+    CommandSpec {
+        name: "copy",
+        forms: &[&[
+            arg("cell/range", ArgType::CellOrRange),
+            arg("cell", ArgType::Cell),
+        ]],
+        summary: "Copy cells to a new top-left cell, shifting formula references",
+    },
+    CommandSpec {
+        name: "move",
+        forms: &[&[
+            arg("cell/range", ArgType::CellOrRange),
+            arg("cell", ArgType::Cell),
+        ]],
+        summary: "Move cells to a new top-left cell; references to them follow",
+    },
+    CommandSpec {
+        name: "transpose",
+        forms: &[&[arg("range", ArgType::Range), arg("cell", ArgType::Cell)]],
+        summary: "Copy a range to a new top-left cell with rows and columns swapped",
+    },
+    CommandSpec {
+        name: "series",
+        forms: &[&[
+            arg("range", ArgType::Range),
+            arg("start", CELL_NUMBER),
+            arg("step", CELL_NUMBER),
+        ]],
+        summary: "Fill a row or column with start, start+step, start+2*step, ...",
+    },
+    CommandSpec {
+        name: "sort",
+        forms: &[
+            &[
+                arg("range", ArgType::Range),
+                arg(
+                    "by <col> [asc|desc][, <col> [asc|desc]]... [--header]",
+                    ArgType::Text,
+                ),
+            ],
+            &[
+                arg("range", ArgType::Range),
+                arg("<col> [asc|desc] [--header]", ArgType::Text),
+            ],
+        ],
+        summary: "Sort the rows of a range by one or more of its columns",
+    },
+    CommandSpec {
+        name: "replace",
+        forms: &[&[arg("<old> <new> [[in] <range>] [--dry]", ArgType::Text)]],
+        summary: "Replace a value (or ERR, any error) with a number in the sheet or a range",
+    },
+    CommandSpec {
+        name: "colformula",
+        forms: &[
+            &[arg("<col> = <expr> [rows <a>:<b>]", ArgType::Text)],
+            &[
+                arg("refresh", ArgType::Keywords(&["refresh"])),
+                optional("col", ArgType::Name),
+            ],
+            &[arg("list", ArgType::Keywords(&["list"]))],
+            &[
+                arg("clear", ArgType::Keywords(&["clear"])),
+                arg("col", ArgType::Name),
+            ],
+        ],
+        summary: "Define a formula for every row of a column, or list, refresh or clear them",
+    },
+    CommandSpec {
+        name: "undo",
+        forms: &[&[]],
+        summary: "Take back the last command that changed cells",
+    },
+    CommandSpec {
+        name: "redo",
+        forms: &[&[]],
+        summary: "Apply the last undone command again",
+    },
+    CommandSpec {
+        name: "find",
+        forms: &[&[arg("value", ArgType::Value)]],
+        summary: "List the cells holding a number, or ERR for errors, and scroll to the first",
+    },
+    CommandSpec {
+        name: "find_next",
+        forms: &[&[]],
+        summary: "Scroll to the next cell the last find matched, wrapping around",
+    },
+    CommandSpec {
+        name: "filter",
+        forms: &[
+            &[arg("clear", ArgType::Keywords(&["clear"]))],
+            &[arg("status", ArgType::Keywords(&["status"]))],
+            &[arg("<col> <condition>", ArgType::Text)],
+        ],
+        summary: "Show only the rows whose cell in a column passes a condition, e.g. A > 0",
+    },
+    CommandSpec {
+        name: "stats",
+        forms: &[&[
+            arg("range", ArgType::Range),
+            optional("--visible", ArgType::Keywords(&["--visible"])),
+        ]],
+        summary: "Print the count, sum, average, minimum and maximum of a range",
+    },
+    CommandSpec {
+        name: "sample",
+        forms: &[&[
+            arg("range", ArgType::Range),
+            arg("n", POSITIVE),
+            optional("[errors] [--seed <s>]", ArgType::Text),
+        ]],
+        summary: "Print n cells of a range picked at random, or only cells in error",
+    },
+    CommandSpec {
+        name: "insert_row",
+        forms: &[&[arg("row", POSITIVE)]],
+        summary: "Insert an empty row before a row, moving the rows below down",
+    },
+    CommandSpec {
+        name: "delete_row",
+        forms: &[&[arg("row", POSITIVE)]],
+        summary: "Remove a row, moving the rows below up",
+    },
+    CommandSpec {
+        name: "is_locked",
+        forms: LOCK_TARGETS,
+        summary: "Tell whether a cell, or all or part of a range, is locked",
+    },
+    CommandSpec {
+        name: "locked",
+        forms: &[&[]],
+        summary: "List the locked ranges and count the locked cells",
+    },
+    CommandSpec {
+        name: "own",
+        forms: &[&[
+            arg("cell/range", ArgType::CellOrRange),
+            arg("user", ArgType::Name),
+        ]],
+        summary: "Tag a cell or range as owned by a user",
+    },
+    CommandSpec {
+        name: "disown",
+        forms: &[&[arg("cell/range", ArgType::CellOrRange)]],
+        summary: "Remove the ownership tags from a cell or range",
+    },
+    CommandSpec {
+        name: "clear_sheet",
+        forms: &[&[]],
+        summary: "Wipe every cell, formula, name and lock",
+    },
+    CommandSpec {
+        name: "owners",
+        forms: &[&[]],
+        summary: "List the ownership tags and the current user",
+    },
+    CommandSpec {
+        name: "name",
+        forms: &[&[
+            arg("cell/range", ArgType::CellOrRange),
+            arg("name", ArgType::Name),
+        ]],
+        summary: "Name a cell or range for use in formulas and commands",
+    },
+    CommandSpec {
+        name: "names",
+        forms: &[&[]],
+        summary: "List the names with their ranges",
+    },
+    CommandSpec {
+        name: "unname",
+        forms: &[&[arg("name", ArgType::Name)]],
+        summary: "Remove a name; formulas that used it keep their cells",
+    },
+    CommandSpec {
+        name: "rename",
+        forms: &[&[arg("old", ArgType::Name), arg("new", ArgType::Name)]],
+        summary: "Give a named range another name",
+    },
+    CommandSpec {
+        name: "history",
+        forms: &[&[arg("cell", ArgType::Cell), optional("n", POSITIVE)]],
+        summary: "Put a cell back to its previous value, or the one n entries back",
+    },
+    CommandSpec {
+        name: "show_history",
+        forms: &[&[arg("cell", ArgType::Cell)]],
+        summary: "List a cell's previous values and formulas, most recent first, without changing it",
+    },
+    CommandSpec {
+        name: "history_stats",
+        forms: &[&[]],
+        summary: "Count the cell history's entries and estimate the memory they take",
+    },
+    CommandSpec {
+        name: "formula",
+        forms: &[&[
+            arg("cell", ArgType::Cell),
+            optional("--full", ArgType::Keywords(&["--full"])),
+        ]],
+        summary: "Print a cell's formula",
+    },
+    CommandSpec {
+        name: "visual",
+        forms: &[&[arg("cell", ArgType::Cell), optional("depth", POSITIVE)]],
+        summary: "Draw the cells a cell depends on and the cells depending on it",
+    },
+    CommandSpec {
+        name: "deps",
+        forms: &[&[
+            arg("cell", ArgType::Cell),
+            optional("--transitive", TRANSITIVE),
+        ]],
+        summary: "List the cells reading a cell, one per line",
+    },
+    CommandSpec {
+        name: "deps*",
+        forms: &[&[arg("cell", ArgType::Cell)]],
+        summary: "List every cell reading a cell, directly or not",
+    },
+    CommandSpec {
+        name: "precedents",
+        forms: &[&[
+            arg("cell", ArgType::Cell),
+            optional("--transitive", TRANSITIVE),
+        ]],
+        summary: "List the cells and ranges a cell reads, one per line",
+    },
+    CommandSpec {
+        name: "precedents*",
+        forms: &[&[arg("cell", ArgType::Cell)]],
+        summary: "List every cell and range a cell reads, directly or not",
+    },
+    CommandSpec {
+        name: "trace_error",
+        forms: &[&[arg("cell", ArgType::Cell)]],
+        summary: "Follow an ERR cell back to where the error started",
+    },
+    CommandSpec {
+        name: "error",
+        forms: &[&[arg("cell", ArgType::Cell)]],
+        summary: "Explain why a cell holds an error",
+    },
+    CommandSpec {
+        name: "count",
+        forms: &[&[arg("range", ArgType::Range)]],
+        summary: "Count the touched, error and zero cells of a range",
+    },
+    CommandSpec {
+        name: "set_limit",
+        forms: &[
+            &[
+                arg("range_cells", ArgType::Keywords(&["range_cells"])),
+                arg("n", POSITIVE),
+            ],
+            &[
+                arg("line_bytes", ArgType::Keywords(&["line_bytes"])),
+                arg("n", POSITIVE),
+            ],
+        ],
+        summary: "Change the range size or save file line length limit",
+    },
+    CommandSpec {
+        name: "set",
+        forms: &[
+            &[
+                arg("prompt_stats", ArgType::Keywords(&["prompt_stats"])),
+                arg("on/off", ON_OFF),
+            ],
+            &[
+                arg(
+                    "confirm_overwrite_bulk",
+                    ArgType::Keywords(&["confirm_overwrite_bulk"]),
+                ),
+                arg("on/off", ON_OFF),
+            ],
+            &[
+                arg("page_size", ArgType::Keywords(&["page_size"])),
+                arg("n", ArgType::Integer(0, i64::MAX)),
+            ],
+            &[
+                arg("history_limit", ArgType::Keywords(&["history_limit"])),
+                arg("n", ArgType::Integer(0, i64::MAX)),
+            ],
+            &[
+                arg("history_size", ArgType::Keywords(&["history_size"])),
+                arg("n", POSITIVE),
+            ],
+            &[
+                arg("history_file", ArgType::Keywords(&["history_file"])),
+                arg("file", ArgType::File),
+            ],
+            &[
+                arg("backups", ArgType::Keywords(&["backups"])),
+                arg("n", ArgType::Integer(0, MAX_BACKUPS as i64)),
+            ],
+            &[
+                arg("user", ArgType::Keywords(&["user"])),
+                arg("name", ArgType::Name),
+            ],
+            &[
+                arg(
+                    "enforce_ownership",
+                    ArgType::Keywords(&["enforce_ownership"]),
+                ),
+                arg("on/off", ON_OFF),
+            ],
+            &[
+                arg("autosave_every", ArgType::Keywords(&["autosave_every"])),
+                arg("n", POSITIVE),
+            ],
+        ],
+        summary: "Change a setting",
+    },
+    CommandSpec {
+        name: "verify",
+        forms: &[&[]],
+        summary: "Check the sheet's cached counters against its contents",
+    },
+    CommandSpec {
+        name: "recalc_all",
+        forms: &[&[], &[arg("--verify", ArgType::Keywords(&["--verify"]))]],
+        summary: "Reevaluate every formula, or with --verify list the cells whose value is stale",
+    },
+    CommandSpec {
+        name: "changes",
+        forms: &[
+            &[],
+            &[arg("count", ArgType::Keywords(&["count"]))],
+            &[arg("range", ArgType::Range)],
+        ],
+        summary: "List the cells changed since the last save or open, or count them",
+    },
+    CommandSpec {
+        name: "dry",
+        forms: &[&[arg("<command>", ArgType::Text)]],
+        summary: "Run a command and report what it would change, keeping none of it",
+    },
+    CommandSpec {
+        name: "let",
+        forms: &[
+            &[arg("<name> = <n>", ArgType::Text)],
+            &[arg("<name> = cell <cell>", ArgType::Text)],
+        ],
+        summary: "Define a session variable, used as $name in later commands",
+    },
+    CommandSpec {
+        name: "vars",
+        forms: &[&[]],
+        summary: "List the session variables",
+    },
+    CommandSpec {
+        name: "unset",
+        forms: &[&[arg("name", ArgType::Name)]],
+        summary: "Remove a session variable",
+    },
+    CommandSpec {
+        name: "run",
+        forms: &[&[arg("<file> [--var <name>=<n>]...", ArgType::Text)]],
+        summary: "Run the commands of a file, with extra variables if given",
+    },
+    CommandSpec {
+        name: "readonly",
+        forms: &[&[arg("on/off", ON_OFF)]],
+        summary: "Turn read-only mode on or off; it refuses every command that would change the sheet",
+    },
+    CommandSpec {
+        name: "save",
+        forms: &[
+            &[],
+            &[arg("file", ArgType::File)],
+            &[
+                arg("--force", ArgType::Keywords(&["--force"])),
+                optional("file", ArgType::File),
+            ],
+        ],
+        summary: "Save to the current file or another, refusing if the file changed since it was read",
+    },
+    CommandSpec {
+        name: "saveas",
+        forms: &[&[arg("file", ArgType::File)]],
+        summary: "Save to a file, even if it changed since it was read",
+    },
+    CommandSpec {
+        name: "open",
+        forms: &[&[arg("file", ArgType::File)]],
+        summary: "Load a saved sheet, which becomes the current file",
+    },
+    CommandSpec {
+        name: "reload",
+        forms: &[&[]],
+        summary: "Read the current file again, unless there are unsaved changes",
+    },
+    CommandSpec {
+        name: "reload!",
+        forms: &[&[]],
+        summary: "Read the current file again, discarding unsaved changes",
+    },
+    CommandSpec {
+        name: "backups",
+        forms: &[&[arg("file", ArgType::File)]],
+        summary: "List the numbered backups of a file with their time and size",
+    },
+    CommandSpec {
+        name: "restore_backup",
+        forms: &[&[arg("<file> <n>", ArgType::Text)]],
+        summary: "Load the nth backup of a file, unless there are unsaved changes",
+    },
+    CommandSpec {
+        name: "restore_backup!",
+        forms: &[&[arg("<file> <n>", ArgType::Text)]],
+        summary: "Load the nth backup of a file, discarding unsaved changes",
+    },
+    CommandSpec {
+        name: "export_csv",
+        forms: &[&[arg("file", ArgType::File)]],
+        summary: "Write the values of the sheet to a CSV file",
+    },
+    CommandSpec {
+        name: "import_csv",
+        forms: &[&[arg("file", ArgType::File)]],
+        summary: "Read a CSV file into the sheet from A1",
+    },
+    CommandSpec {
+        name: "session",
+        forms: &[
+            &[
+                arg("save", ArgType::Keywords(&["save"])),
+                arg("file", ArgType::File),
+            ],
+            &[
+                arg("restore", ArgType::Keywords(&["restore"])),
+                arg("file", ArgType::File),
+            ],
+        ],
+        summary: "Save or restore the sheet with its view, settings, filter and variables",
+    },
+    CommandSpec {
+        name: "autosave",
+        forms: &[
+            &[],
+            &[
+                arg("on", ArgType::Keywords(&["on"])),
+                arg("file", ArgType::File),
+            ],
+            &[arg("off", ArgType::Keywords(&["off"]))],
+        ],
+        summary: "Save to a file after every few commands that change the sheet and when the input ends, or stop",
+    },
+    CommandSpec {
+        name: "graph_stats",
+        forms: &[
+            &[],
+            &[
+                arg("--csv", ArgType::Keywords(&["--csv"])),
+                arg("file", ArgType::File),
+            ],
+        ],
+        summary: "Print dependency graph metrics, or write them per cell to a CSV file",
+    },
+    CommandSpec {
+        name: "HLP",
+        forms: HIGHLIGHT_TARGET,
+        summary: "Highlight the cells a cell reads",
+    },
+    CommandSpec {
+        name: "HLC",
+        forms: HIGHLIGHT_TARGET,
+        summary: "Highlight the cells reading a cell",
+    },
+    CommandSpec {
+        name: "HLPC",
+        forms: HIGHLIGHT_TARGET,
+        summary: "Highlight the cells a cell reads and the cells reading it",
+    },
+    CommandSpec {
+        name: "HLOFF",
+        forms: &[&[]],
+        summary: "Turn highlighting off",
+    },
+    CommandSpec {
+        name: "tutorial",
+        forms: &[&[], &[arg("quit", ArgType::Keywords(&["quit"]))]],
+        summary: "Start a guided walkthrough on an example sheet, or leave it",
+    },
+    CommandSpec {
+        name: "q",
+        forms: &[&[]],
+        summary: "Quit",
+    },
+    CommandSpec {
+        name: "help",
+        forms: &[&[optional("command", ArgType::Name)]],
+        summary: "List the commands, or show how to use one",
+    },
+];
+
+#[cfg(feature = "extensions")]
+impl ArgType {
+    /// Describes the values this kind takes, for error messages.
+    fn describe(&self) -> String {
+        match self {
+            ArgType::Cell => "a cell such as A1".to_string(),
+            ArgType::Range => "a range such as A1:B5".to_string(),
+            ArgType::CellOrRange => "a cell or range such as A1 or A1:B5".to_string(),
+            ArgType::Integer(min, i64::MAX) => format!("a number of at least {}", min),
+            ArgType::Integer(min, max) => format!("a number between {} and {}", min, max),
+            ArgType::Value => "a number or ERR".to_string(),
+            ArgType::Keywords(words) => words.join(" or "),
+            ArgType::Name => "a name".to_string(),
+            ArgType::File => "a file name".to_string(),
+            ArgType::Text => "arguments".to_string(),
+        }
+    }
+
+    /// Checks one argument.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the argument is of this kind.
+    /// * `Err(CommandStatus)` - The status the command's own parser gives it
+    fn check(&self, sheet: &Spreadsheet, token: &str) -> Result<(), CommandStatus> {
+        match self {
+            ArgType::Cell => resolve_cell_reference(sheet, token).map(|_| ()),
+            ArgType::Range => resolve_range(sheet, token).map(|_| ()),
+            ArgType::CellOrRange => resolve_target(sheet, token).map(|_| ()),
+            ArgType::Integer(min, max) => match token.parse::<i64>() {
+                Ok(value) if (*min..=*max).contains(&value) => Ok(()),
+                _ => Err(CommandStatus::Unrecognized),
+            },
+            ArgType::Value => parse_value_token(token)
+                .map(|_| ())
+                .ok_or(CommandStatus::Unrecognized),
+            ArgType::Keywords(words) if words.contains(&token) => Ok(()),
+            ArgType::Keywords(_) => Err(CommandStatus::Unrecognized),
+            ArgType::Name | ArgType::File | ArgType::Text => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "extensions")]
+impl ArgSpec {
+    /// Shows the argument as the usage line does: `range_cells`, `on|off`, `<n>` or `[<n>]`.
+    fn usage(&self) -> String {
+        let text = match self.kind {
+            ArgType::Keywords(words) => words.join("|"),
+            ArgType::Text => return self.name.to_string(),
+            _ => format!("<{}>", self.name),
+        };
+        if self.optional {
+            format!("[{}]", text)
+        } else {
+            text
+        }
+    }
+}
+
+#[cfg(feature = "extensions")]
+impl CommandSpec {
+    /// Builds the usage line, e.g. `usage: set_limit range_cells <n> | set_limit line_bytes <n>`.
+    pub fn usage(&self) -> String {
+        let forms: Vec<String> = self
+            .forms
+            .iter()
+            .map(|form| {
+                let mut words = vec![self.name.to_string()];
+                words.extend(form.iter().map(ArgSpec::usage));
+                words.join(" ")
+            })
+            .collect();
+        format!("usage: {}", forms.join(" | "))
+    }
+}
+
+/// Finds a command in the argument table.
+#[cfg(feature = "extensions")]
+pub fn command_spec(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+/// Where one form stopped matching: the argument position, the status, what was
+/// expected there and the token found instead, if any.
+#[cfg(feature = "extensions")]
+type Mismatch<'a> = (usize, CommandStatus, String, Option<&'a str>);
+
+/// Matches arguments against one form.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for references and names.
+/// * `form` - The form.
+/// * `args` - The text after the command word.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the arguments fit the form.
+/// * `Err(Mismatch)` - Where they stopped fitting
+#[cfg(feature = "extensions")]
+fn match_form<'a>(
+    sheet: &Spreadsheet,
+    form: &[ArgSpec],
+    args: &'a str,
+) -> Result<(), Mismatch<'a>> {
+    let mut rest = args.trim();
+    for (position, spec) in form.iter().enumerate() {
+        if rest.is_empty() {
+            if spec.optional {
+                return Ok(());
+            }
+            let expected = spec.kind.describe();
+            return Err((position, CommandStatus::Unrecognized, expected, None));
+        }
+        // A file name or text takes the rest of the line
+        if matches!(spec.kind, ArgType::File | ArgType::Text) {
+            return Ok(());
+        }
+        let (token, remaining) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if let Err(status) = spec.kind.check(sheet, token) {
+            return Err((position, status, spec.kind.describe(), Some(token)));
+        }
+        rest = remaining.trim_start();
+    }
+    match rest.split_whitespace().next() {
+        Some(token) => Err((
+            form.len(),
+            CommandStatus::Unrecognized,
+            "no more arguments".to_string(),
+            Some(token),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Checks a command's arguments against the argument table.
+///
+/// The error describes the first argument that fits none of the command's forms: forms
+/// that got furthest are reported, their expectations joined with "or".
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for references and names.
+/// * `command` - The trimmed command.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the command is not in the table or its arguments fit a form.
+/// * `Err(ArgError)` - Otherwise, e.g. `set_limit: expected a number of at least 1,
+///   got 'x'` followed by the usage line
+#[cfg(feature = "extensions")]
+pub fn check_arguments(sheet: &Spreadsheet, command: &str) -> Result<(), ArgError> {
+    let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+    let Some(spec) = command_spec(name) else {
+        return Ok(());
+    };
+    let mut mismatches = Vec::new();
+    for form in spec.forms {
+        match match_form(sheet, form, args) {
+            Ok(()) => return Ok(()),
+            Err(mismatch) => mismatches.push(mismatch),
+        }
+    }
+    let furthest = mismatches.iter().map(|m| m.0).max().unwrap_or(0);
+    mismatches.retain(|m| m.0 == furthest);
+    let mut expected: Vec<String> = Vec::new();
+    for (_, _, description, _) in &mismatches {
+        if !expected.contains(description) {
+            expected.push(description.clone());
+        }
+    }
+    // A well-formed cell or range past the edge is not a malformed one
+    if let Some((_, _, _, Some(token))) = mismatches
+        .iter()
+        .find(|m| m.1 == CommandStatus::InvalidCell)
+    {
+        return Err(ArgError {
+            status: CommandStatus::InvalidCell,
+            message: format!(
+                "{}: '{}' lies outside the sheet\n{}",
+                spec.name,
+                token,
+                spec.usage()
+            ),
+        });
+    }
+    let (_, status, _, found) = mismatches.swap_remove(0);
+    let found = match found {
+        Some(token) => format!(", got '{}'", token),
+        None => String::new(),
+    };
+    Err(ArgError {
+        status,
+        message: format!(
+            "{}: expected {}{}\n{}",
+            spec.name,
+            expected.join(" or "),
+            found,
+            spec.usage()
+        ),
+    })
+}
+
+/// Handles `help` and `help <command>`, printing entries of the argument table.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `args` - The text after `help`.
+///
+/// # Returns
+///
+/// * `CommandStatus` - `CmdOk`, or `Unrecognized` for a command not in the table
+#[cfg(feature = "extensions")]
+pub fn handle_help(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let lines = match args.trim() {
+        "" => {
+            let mut table = Table::new(&[("", Align::Left), ("", Align::Left)]);
+            // Summaries are shown whole
+            table.max_column_width = None;
+            for spec in COMMANDS {
+                table.push_row(vec![spec.name.to_string(), spec.summary.to_string()]);
+            }
+            table.render()
+        }
+        name => match command_spec(name) {
+            Some(spec) => vec![spec.summary.to_string(), spec.usage()],
+            None => return CommandStatus::Unrecognized,
+        },
+    };
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
 /// Processes a command, measuring execution time and handling sleep.
 ///
+/// With extensions, `$name` variables are substituted first and the scripting
+/// commands (`let`, `vars`, `unset`, `run`) are handled here.
+///
 /// # Arguments
 ///
 /// * `sheet` - The mutable spreadsheet.
@@ -14,11 +1091,52 @@ use std::time::{Duration, Instant};
 /// # Returns
 ///
 /// The status of command execution
+#[cfg(feature = "extensions")]
 pub fn process_command(
     sheet: &mut Spreadsheet,
     command: &str,
     last_time: &mut f64,
 ) -> CommandStatus {
+    let (status, timing) = process_command_timed(sheet, command);
+    *last_time = timing.total();
+    status
+}
+
+/// Processes a command like `process_command`, reporting work and sleep time separately.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `command` - The command string.
+///
+/// # Returns
+///
+/// * `(CommandStatus, CommandTiming)` - The status and how long the command took
+pub fn process_command_timed(
+    sheet: &mut Spreadsheet,
+    command: &str,
+) -> (CommandStatus, CommandTiming) {
+    #[cfg(feature = "extensions")]
+    let substituted = match substitute(sheet, command) {
+        Ok(substituted) => substituted,
+        Err(status) => return (status, CommandTiming::default()),
+    };
+    #[cfg(feature = "extensions")]
+    let command: &str = &substituted;
+    #[cfg(feature = "extensions")]
+    {
+        // Timed like any other command; a `run` counts its lines' sleeps as its own time
+        let start = Instant::now();
+        let mut script_time = 0.0;
+        if let Some(status) = handle_scripting_command(sheet, command, &mut script_time) {
+            let timing = CommandTiming {
+                elapsed: start.elapsed().as_secs_f64(),
+                sleep: 0.0,
+            };
+            return (status, timing);
+        }
+    }
+
     // Process the command and measure execution time
     let mut sleep_time = 0.0; // Initialize sleep_time to 0.0
     // Pass by reference instead of cloning
@@ -31,9 +1149,498 @@ pub fn process_command(
     } else {
         sleep_time -= command_time;
     }
-    *last_time = command_time + sleep_time;
+    // The lines of a script run by `dry run` never sleep, as `dry` itself does not
+    #[cfg(feature = "extensions")]
+    if sheet.dry_run.is_some() {
+        sleep_time = 0.0;
+    }
     if sleep_time > 0.0 {
         sleep(Duration::from_secs_f64(sleep_time));
     }
-    status
+    let timing = CommandTiming {
+        elapsed: command_time,
+        sleep: sleep_time,
+    };
+    (status, timing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::CellValue;
+
+    #[test]
+    fn test_split_commands() {
+        assert_eq!(split_commands("A1=5; B1=6"), vec!["A1=5", "B1=6"]);
+        assert_eq!(split_commands(" A1=5 ;; "), vec!["A1=5"]);
+        assert_eq!(split_commands("A1=5 B1=6"), vec!["A1=5 B1=6"]);
+        assert_eq!(split_commands(""), vec![""]);
+        // Separators inside quoted text stay part of the command
+        assert_eq!(
+            split_commands("A1=1; note \"a;b\"; B1=2"),
+            vec!["A1=1", "note \"a;b\"", "B1=2"]
+        );
+    }
+
+    #[test]
+    fn test_multi_command_hint() {
+        assert_eq!(
+            multi_command_hint("A1=5 B1=6"),
+            Some("unexpected '=' in expression; use ';' to separate commands")
+        );
+        assert_eq!(
+            multi_command_hint("A1=5 6"),
+            Some("unexpected space in expression; use ';' to separate commands")
+        );
+        assert_eq!(multi_command_hint("A1=5+"), None);
+        assert_eq!(multi_command_hint("scroll_to A1"), None);
+    }
+
+    #[test]
+    fn test_text_hint() {
+        assert_eq!(
+            text_hint("A3=hello").as_deref(),
+            Some("text must be in double quotes, as in A3=\"hello\"")
+        );
+        assert_eq!(
+            text_hint("A3 = Net revenue").as_deref(),
+            Some("text must be in double quotes, as in A3=\"Net revenue\"")
+        );
+        assert_eq!(text_hint("A3=B1"), None);
+        assert_eq!(text_hint("A3=SUM(B1:B2)"), None);
+        assert_eq!(text_hint("A3="), None);
+        assert_eq!(text_hint("scroll_to A1"), None);
+    }
+
+    #[test]
+    fn test_expression_hint() {
+        assert_eq!(
+            expression_hint("A1=SUM(B1)").as_deref(),
+            Some("A1=SUM(B1)\n         ^ expected ':'")
+        );
+        assert_eq!(
+            expression_hint("B2 = A1+").as_deref(),
+            Some("B2 = A1+\n        ^ expected a number, cell reference or name")
+        );
+        // Columns count characters, not bytes
+        assert_eq!(
+            expression_hint("A1=é+1").as_deref(),
+            Some("A1=é+1\n   ^ expected a number, cell reference or name")
+        );
+        // Expressions that parse fail for other reasons, e.g. a cell off the sheet
+        assert_eq!(expression_hint("A1=ZZZ999"), None);
+        assert_eq!(expression_hint("scroll_to A1"), None);
+        assert_eq!(expression_hint("colformula C=A{row}"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_scripting_commands_are_timed() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        for command in ["let threshold = 3", "vars", "unset threshold"] {
+            let (status, timing) = process_command_timed(&mut sheet, command);
+            assert_eq!(status, CommandStatus::CmdOk);
+            assert!(timing.elapsed > 0.0, "{} was not timed", command);
+            assert_eq!(timing.sleep, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_each_command_runs_after_a_failure() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let statuses: Vec<CommandStatus> = split_commands("A1=5; B1=A1+; C1=A1*2")
+            .into_iter()
+            .map(|command| process_command_timed(&mut sheet, command).0)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                CommandStatus::CmdOk,
+                CommandStatus::Unrecognized,
+                CommandStatus::CmdOk
+            ]
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(5));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(10));
+    }
+    /// Checks a command and returns the first line of its error, or "ok".
+    #[cfg(feature = "extensions")]
+    fn argument_error(sheet: &Spreadsheet, command: &str) -> (String, Option<CommandStatus>) {
+        match check_arguments(sheet, command) {
+            Ok(()) => ("ok".to_string(), None),
+            Err(error) => {
+                let first = error.message.lines().next().unwrap().to_string();
+                (first, Some(error.status))
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_argument_errors_per_command() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        sheet.named_ranges.insert(
+            "block".to_string(),
+            crate::range::parse_range(&sheet, "A1:B2").unwrap(),
+        );
+        // Every rejected argument here is Unrecognized, as the commands' own parsers return
+        let cases: &[(&str, &str)] = &[
+            ("lock_cell A1:B2", "ok"),
+            (
+                "lock_cell",
+                "lock_cell: expected a cell or range such as A1 or A1:B5 or ROW or COL",
+            ),
+            (
+                "lock_cell 12",
+                "lock_cell: expected a cell or range such as A1 or A1:B5 or ROW or COL, got '12'",
+            ),
+            (
+                "lock_cell A1 B1",
+                "lock_cell: expected no more arguments, got 'B1'",
+            ),
+            ("unlock_cell A1", "ok"),
+            ("unlock_all", "ok"),
+            ("lock_cell ROW 3", "ok"),
+            ("unlock_cell COL B", "ok"),
+            ("is_locked ROW 2", "ok"),
+            (
+                "unlock_all A1",
+                "unlock_all: expected no more arguments, got 'A1'",
+            ),
+            (
+                "unlock_cell ?",
+                "unlock_cell: expected a cell or range such as A1 or A1:B5 or ROW or COL, got '?'",
+            ),
+            ("delete A1:B2", "ok"),
+            (
+                "delete A1 B1",
+                "delete: expected no more arguments, got 'B1'",
+            ),
+            ("copy A1:B3 D5", "ok"),
+            ("copy A1:B3", "copy: expected a cell such as A1"),
+            (
+                "move A1 D5:E6",
+                "move: expected a cell such as A1, got 'D5:E6'",
+            ),
+            ("transpose A1:C2 E1", "ok"),
+            (
+                "transpose A1:C2 E1 F1",
+                "transpose: expected no more arguments, got 'F1'",
+            ),
+            ("series A1:A5 5 -3", "ok"),
+            (
+                "series A1:A5 5",
+                "series: expected a number between -2147483648 and 2147483647",
+            ),
+            (
+                "series A1:A5 5 3000000000",
+                "series: expected a number between -2147483648 and 2147483647, got '3000000000'",
+            ),
+            ("deps A1 --transitive", "ok"),
+            ("deps A1 --all", "deps: expected --transitive, got '--all'"),
+            ("precedents* A1", "ok"),
+            (
+                "precedents* A1 --transitive",
+                "precedents*: expected no more arguments, got '--transitive'",
+            ),
+            ("undo 2", "undo: expected no more arguments, got '2'"),
+            ("find ERR", "ok"),
+            ("find", "find: expected a number or ERR"),
+            ("find x", "find: expected a number or ERR, got 'x'"),
+            (
+                "find_next 2",
+                "find_next: expected no more arguments, got '2'",
+            ),
+            ("insert_row 3", "ok"),
+            ("insert_row", "insert_row: expected a number of at least 1"),
+            (
+                "insert_row 0",
+                "insert_row: expected a number of at least 1, got '0'",
+            ),
+            ("delete_row 3", "ok"),
+            (
+                "delete_row A1",
+                "delete_row: expected a number of at least 1, got 'A1'",
+            ),
+            ("is_locked A1", "ok"),
+            ("is_locked block", "ok"),
+            (
+                "is_locked blob",
+                "is_locked: expected a cell or range such as A1 or A1:B5 or ROW or COL, got 'blob'",
+            ),
+            (
+                "is_locked",
+                "is_locked: expected a cell or range such as A1 or A1:B5 or ROW or COL",
+            ),
+            ("is_locked A1:B2", "ok"),
+            ("locked", "ok"),
+            ("locked A1", "locked: expected no more arguments, got 'A1'"),
+            ("name A1:B2 total", "ok"),
+            ("name A1", "name: expected a name"),
+            (
+                "name x1x total",
+                "name: expected a cell or range such as A1 or A1:B5, got 'x1x'",
+            ),
+            (
+                "name A1 total extra",
+                "name: expected no more arguments, got 'extra'",
+            ),
+            ("names", "ok"),
+            ("names all", "names: expected no more arguments, got 'all'"),
+            ("unname block", "ok"),
+            ("unname", "unname: expected a name"),
+            ("rename block area", "ok"),
+            ("rename block", "rename: expected a name"),
+            (
+                "rename block area more",
+                "rename: expected no more arguments, got 'more'",
+            ),
+            ("history A1", "ok"),
+            (
+                "history A1:A2",
+                "history: expected a cell such as A1, got 'A1:A2'",
+            ),
+            ("history A1 3", "ok"),
+            (
+                "history A1 0",
+                "history: expected a number of at least 1, got '0'",
+            ),
+            ("show_history A1", "ok"),
+            ("history_stats", "ok"),
+            (
+                "history_stats A1",
+                "history_stats: expected no more arguments, got 'A1'",
+            ),
+            (
+                "show_history A1 2",
+                "show_history: expected no more arguments, got '2'",
+            ),
+            ("formula A1 --full", "ok"),
+            (
+                "formula A1 --wide",
+                "formula: expected --full, got '--wide'",
+            ),
+            ("formula", "formula: expected a cell such as A1"),
+            ("visual A1 3", "ok"),
+            ("visual A1", "ok"),
+            (
+                "visual A1 0",
+                "visual: expected a number of at least 1, got '0'",
+            ),
+            (
+                "visual A1 deep",
+                "visual: expected a number of at least 1, got 'deep'",
+            ),
+            ("trace_error B2", "ok"),
+            ("trace_error", "trace_error: expected a cell such as A1"),
+            ("error B2", "ok"),
+            ("error", "error: expected a cell such as A1"),
+            ("count block", "ok"),
+            (
+                "count A1",
+                "count: expected a range such as A1:B5, got 'A1'",
+            ),
+            ("set_limit range_cells 50", "ok"),
+            (
+                "set_limit foo",
+                "set_limit: expected range_cells or line_bytes, got 'foo'",
+            ),
+            (
+                "set_limit line_bytes",
+                "set_limit: expected a number of at least 1",
+            ),
+            (
+                "set_limit range_cells -5",
+                "set_limit: expected a number of at least 1, got '-5'",
+            ),
+            ("set page_size 0", "ok"),
+            ("set history_limit 0", "ok"),
+            (
+                "set history_limit -1",
+                "set: expected a number of at least 0, got '-1'",
+            ),
+            ("set history_file my history.txt", "ok"),
+            (
+                "set prompt_stats maybe",
+                "set: expected on or off, got 'maybe'",
+            ),
+            (
+                "set history_size 0",
+                "set: expected a number of at least 1, got '0'",
+            ),
+            ("set history_file", "set: expected a file name"),
+            (
+                "set colour red",
+                "set: expected prompt_stats or confirm_overwrite_bulk or page_size or history_limit or history_size or history_file or backups or user or enforce_ownership or autosave_every, got 'colour'",
+            ),
+            ("readonly off", "ok"),
+            ("readonly", "readonly: expected on or off"),
+            ("readonly yes", "readonly: expected on or off, got 'yes'"),
+            ("autosave", "ok"),
+            ("autosave on my work.sheet", "ok"),
+            ("autosave off", "ok"),
+            ("autosave on", "autosave: expected a file name"),
+            (
+                "set autosave_every 0",
+                "set: expected a number of at least 1, got '0'",
+            ),
+            ("graph_stats", "ok"),
+            ("graph_stats --csv out file.csv", "ok"),
+            (
+                "graph_stats --json",
+                "graph_stats: expected no more arguments or --csv, got '--json'",
+            ),
+            ("graph_stats --csv", "graph_stats: expected a file name"),
+            ("help set", "ok"),
+            (
+                "help set more",
+                "help: expected no more arguments, got 'more'",
+            ),
+            // Commands outside the table are left to their own parsers
+            ("sort A1:A3 by A", "ok"),
+        ];
+        for (command, message) in cases {
+            let (found, status) = argument_error(&sheet, command);
+            assert_eq!(found, *message, "{}", command);
+            let expected = (*message != "ok").then_some(CommandStatus::Unrecognized);
+            assert_eq!(status, expected, "{}", command);
+        }
+        // A cell off the sheet keeps the status the command's own parser gives it
+        let (message, status) = argument_error(&sheet, "is_locked Z99");
+        assert_eq!(message, "is_locked: 'Z99' lies outside the sheet");
+        assert_eq!(
+            status,
+            crate::cell::parse_cell_reference(&sheet, "Z99").err()
+        );
+        assert_eq!(status, Some(CommandStatus::InvalidCell));
+        let (message, status) = argument_error(&sheet, "lock_cell A1:Z99");
+        assert_eq!(message, "lock_cell: 'A1:Z99' lies outside the sheet");
+        assert_eq!(status, Some(CommandStatus::InvalidCell));
+        let (message, status) = argument_error(&sheet, "show_history Z1");
+        assert_eq!(message, "show_history: 'Z1' lies outside the sheet");
+        assert_eq!(status, Some(CommandStatus::InvalidCell));
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_argument_errors_end_with_usage_from_help() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        sheet.output = crate::output::OutputSink::capture();
+        let error = check_arguments(&sheet, "set_limit foo").unwrap_err();
+        assert_eq!(
+            error.message.lines().nth(1),
+            Some("usage: set_limit range_cells <n> | set_limit line_bytes <n>")
+        );
+        for spec in COMMANDS {
+            // The usage line in an error is the one `help <command>` prints
+            assert_eq!(handle_help(&mut sheet, spec.name), CommandStatus::CmdOk);
+            let help = sheet.output.take();
+            assert_eq!(help, vec![spec.summary.to_string(), spec.usage()]);
+            // A file name or text the command parses itself may be anything; other
+            // arguments are checked, and the error ends with the usage line
+            let free = spec.forms.iter().any(|form| {
+                form.first()
+                    .is_some_and(|arg| matches!(arg.kind, ArgType::Text | ArgType::File))
+            });
+            match check_arguments(&sheet, &format!("{} ? ? ? ?", spec.name)) {
+                Ok(()) => assert!(free, "{} took any arguments", spec.name),
+                Err(error) => assert_eq!(error.message.lines().last(), Some(help[1].as_str())),
+            }
+        }
+        assert_eq!(
+            command_spec("visual").unwrap().usage(),
+            "usage: visual <cell> [<depth>]"
+        );
+        assert_eq!(
+            handle_help(&mut sheet, "nosuch"),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(handle_help(&mut sheet, ""), CommandStatus::CmdOk);
+        let listing = sheet.output.take();
+        assert_eq!(listing.len(), COMMANDS.len());
+        for (line, spec) in listing.iter().zip(COMMANDS) {
+            assert!(line.ends_with(spec.summary), "{}", line);
+        }
+    }
+
+    /// Builds a command from the first form of a spec, with a sample of each required
+    /// argument that `dispatch_fixture` accepts; file names point into `dir`.
+    #[cfg(feature = "extensions")]
+    fn sample_command(spec: &CommandSpec, dir: &std::path::Path) -> String {
+        let file = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let mut words = vec![spec.name.to_string()];
+        for arg in spec.forms[0].iter().filter(|arg| !arg.optional) {
+            words.push(match arg.kind {
+                ArgType::Cell => "C5".to_string(),
+                ArgType::CellOrRange => "A1".to_string(),
+                ArgType::Range => "A1:A2".to_string(),
+                ArgType::Integer(min, max) => 1.clamp(min, max).to_string(),
+                ArgType::Value => "1".to_string(),
+                ArgType::Keywords(keywords) => keywords[0].to_string(),
+                ArgType::Name => "total".to_string(),
+                ArgType::File => file("sample.sheet"),
+                ArgType::Text => match arg.name {
+                    "<old> <new> [[in] <range>] [--dry]" => "1 2".to_string(),
+                    "<col> = <expr> [rows <a>:<b>]" => "C = A+1".to_string(),
+                    "<col> <condition>" => "A > 0".to_string(),
+                    "<command>" => "A1=1".to_string(),
+                    "<name> = <n>" => "rate = 1".to_string(),
+                    "<file> [--var <name>=<n>]..." => file("script.txt"),
+                    "<file> <n>" => format!("{} 1", file("sample.sheet")),
+                    "<cell>" => "A1".to_string(),
+                    name if name.starts_with("by ") => "by A".to_string(),
+                    name => panic!("no sample for {} {}", spec.name, name),
+                },
+            });
+        }
+        words.join(" ")
+    }
+
+    /// A sheet with values, a formula, a name, an undone edit and a search, saved twice to
+    /// `sample.sheet` in `dir` so it has a backup, next to a one-line script `script.txt`.
+    #[cfg(feature = "extensions")]
+    fn dispatch_fixture(dir: &std::path::Path) -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        sheet.output = crate::output::OutputSink::capture();
+        let save = format!("save {}", dir.join("sample.sheet").to_str().unwrap());
+        for command in [
+            "A1=1",
+            "A2=2",
+            "B1=A1+1",
+            "name A1:A2 total",
+            "set backups 1",
+            &save,
+            &save,
+            "A3=5",
+            "undo",
+            "find 1",
+        ] {
+            process_command(&mut sheet, command, &mut 0.0);
+        }
+        std::fs::write(dir.join("script.txt"), "A3=3\n").unwrap();
+        sheet
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_every_listed_command_is_dispatched() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut unrecognized = Vec::new();
+        for spec in COMMANDS {
+            // The command loop handles these before a command reaches `process_command`
+            if ["q", "tutorial"].contains(&spec.name) {
+                continue;
+            }
+            let mut sheet = dispatch_fixture(dir.path());
+            let command = sample_command(spec, dir.path());
+            if process_command(&mut sheet, &command, &mut 0.0) == CommandStatus::Unrecognized {
+                unrecognized.push(command);
+            }
+        }
+        assert!(
+            unrecognized.is_empty(),
+            "not dispatched: {:?}",
+            unrecognized
+        );
+    }
 }
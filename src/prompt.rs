@@ -0,0 +1,108 @@
+//! Formatting of the REPL prompt and the vim status line.
+//!
+//! Both front ends build their timing and status text here so the two stay identical.
+
+/// Formats a duration with two significant figures in µs, ms, or s.
+///
+/// # Arguments
+///
+/// * `seconds` - The duration in seconds.
+///
+/// # Returns
+///
+/// * `String` - E.g. "400µs", "3.0ms", "40ms", "2.5s"
+pub fn format_duration(seconds: f64) -> String {
+    let (value, unit) = if seconds < 999.5e-6 {
+        (seconds * 1e6, "µs")
+    } else if seconds < 0.9995 {
+        (seconds * 1e3, "ms")
+    } else {
+        (seconds, "s")
+    };
+    if value < 9.95 {
+        format!("{:.1}{}", value, unit)
+    } else {
+        format!("{:.0}{}", value, unit)
+    }
+}
+
+/// Builds the status text shared by the prompt and the vim status line.
+///
+/// # Arguments
+///
+/// * `elapsed` - Seconds the last command spent working.
+/// * `sleep` - Seconds it slept for `SLEEP` formulas; shown only when nonzero.
+/// * `status` - The last command's status name.
+/// * `stats` - Extra text for the end, such as the cell counts.
+///
+/// # Returns
+///
+/// * `String` - E.g. "[3.0ms +2.0s sleep] (ok)"
+pub fn status_line(elapsed: f64, sleep: f64, status: &str, stats: Option<&str>) -> String {
+    let mut line = format!("[{}", format_duration(elapsed));
+    if sleep > 0.0 {
+        line.push_str(&format!(" +{} sleep", format_duration(sleep)));
+    }
+    line.push_str(&format!("] ({})", status));
+    if let Some(stats) = stats {
+        line.push(' ');
+        line.push_str(stats);
+    }
+    line
+}
+
+/// Builds the REPL prompt, with the last command's message on a line above it.
+///
+/// # Arguments
+///
+/// * `elapsed` - Seconds the last command spent working.
+/// * `sleep` - Seconds it slept for `SLEEP` formulas.
+/// * `status` - The last command's status name.
+/// * `message` - Explanation of a failed command; `None` after success.
+/// * `stats` - Extra text for the status, such as the cell counts.
+///
+/// # Returns
+///
+/// * `String` - The text to print before reading the next command
+pub fn prompt_line(
+    elapsed: f64,
+    sleep: f64,
+    status: &str,
+    message: Option<&str>,
+    stats: Option<&str>,
+) -> String {
+    let status_line = status_line(elapsed, sleep, status, stats);
+    match message {
+        Some(message) => format!("{}\n{} > ", message, status_line),
+        None => format!("{} > ", status_line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration_units() {
+        assert_eq!(format_duration(0.0), "0.0µs");
+        assert_eq!(format_duration(0.000_4), "400µs");
+        assert_eq!(format_duration(0.003), "3.0ms");
+        assert_eq!(format_duration(0.04), "40ms");
+        assert_eq!(format_duration(0.999_9), "1.0s");
+        assert_eq!(format_duration(2.0), "2.0s");
+        assert_eq!(format_duration(123.4), "123s");
+    }
+
+    #[test]
+    fn test_prompt_line() {
+        assert_eq!(prompt_line(0.003, 0.0, "ok", None, None), "[3.0ms] (ok) > ");
+        assert_eq!(
+            prompt_line(0.003, 2.0, "ok", None, Some("[c:2 f:1]")),
+            "[3.0ms +2.0s sleep] (ok) [c:2 f:1] > "
+        );
+        assert_eq!(
+            prompt_line(0.000_05, 0.0, "circular_ref", Some("Cycle"), None),
+            "Cycle\n[50µs] (circular_ref) > "
+        );
+    }
+}
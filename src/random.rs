@@ -0,0 +1,99 @@
+//! A small self-contained pseudo-random number generator.
+//!
+//! Commands that pick or generate values at random use this so that a `--seed`
+//! reproduces the same result on every platform.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An xorshift64* generator.
+///
+/// # Fields
+///
+/// * `state` - The current generator state (never zero)
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a generator from a seed; equal seeds give equal sequences.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - Any value, including zero.
+    pub fn new(seed: u64) -> Self {
+        // Mix the seed so small seeds do not start with small outputs
+        let state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ 0xD1B5_4A32_D192_ED03;
+        Rng {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    /// Creates a generator seeded from the system clock.
+    pub fn from_clock() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Rng::new(nanos)
+    }
+
+    /// Returns the next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a value uniformly distributed in `0..n`.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - Exclusive upper bound; must be greater than zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        // Reject the top sliver so every value is equally likely
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % n;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let mut c = Rng::new(43);
+        let first: Vec<u64> = (0..8).map(|_| a.next_u64()).collect();
+        let second: Vec<u64> = (0..8).map(|_| b.next_u64()).collect();
+        let other: Vec<u64> = (0..8).map(|_| c.next_u64()).collect();
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn test_below_stays_in_bounds_and_covers_values() {
+        let mut rng = Rng::new(0);
+        let mut seen = [false; 7];
+        for _ in 0..1000 {
+            let value = rng.below(7) as usize;
+            assert!(value < 7);
+            seen[value] = true;
+        }
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    #[test]
+    fn test_clock_seeded_generator() {
+        let mut rng = Rng::from_clock();
+        assert!((0..100).all(|_| rng.below(3) < 3));
+    }
+}
@@ -0,0 +1,509 @@
+use crate::cell::parse_cell_reference;
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+
+/// Represents a range of cells in the spreadsheet.
+///
+/// Used for range-based operations like `SUM`, `AVG`, etc.
+///
+/// # Fields
+///
+/// * `start_row` - Starting row (zero-based).
+/// * `start_col` - Starting column (zero-based).
+/// * `end_row` - Ending row (zero-based).
+/// * `end_col` - Ending column (zero-based)
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Range {
+    pub start_row: i16,
+    pub start_col: i16,
+    pub end_row: i16,
+    pub end_col: i16,
+}
+
+impl Range {
+    /// Creates the range with two opposite corners, given in any order.
+    pub fn from_corners(row1: i16, col1: i16, row2: i16, col2: i16) -> Range {
+        Range {
+            start_row: row1.min(row2),
+            start_col: col1.min(col2),
+            end_row: row1.max(row2),
+            end_col: col1.max(col2),
+        }
+    }
+
+    /// Creates a range covering a single cell.
+    #[cfg(feature = "extensions")]
+    pub fn single(row: i16, col: i16) -> Range {
+        Range {
+            start_row: row,
+            start_col: col,
+            end_row: row,
+            end_col: col,
+        }
+    }
+
+    /// Checks whether the cell at `row`, `col` lies inside the range.
+    pub fn contains(&self, row: i16, col: i16) -> bool {
+        row >= self.start_row && row <= self.end_row && col >= self.start_col && col <= self.end_col
+    }
+
+    /// Returns the number of cells covered by the range.
+    #[cfg(feature = "extensions")]
+    pub fn cell_count(&self) -> i64 {
+        (self.end_row - self.start_row + 1) as i64 * (self.end_col - self.start_col + 1) as i64
+    }
+
+    /// Checks whether two ranges share a cell.
+    #[cfg(feature = "extensions")]
+    pub fn overlaps(&self, other: &Range) -> bool {
+        self.start_row <= other.end_row
+            && other.start_row <= self.end_row
+            && self.start_col <= other.end_col
+            && other.start_col <= self.end_col
+    }
+
+    /// Returns the cells two ranges share, if any.
+    #[cfg(feature = "extensions")]
+    pub fn intersection(&self, other: &Range) -> Option<Range> {
+        self.overlaps(other).then(|| Range {
+            start_row: self.start_row.max(other.start_row),
+            start_col: self.start_col.max(other.start_col),
+            end_row: self.end_row.min(other.end_row),
+            end_col: self.end_col.min(other.end_col),
+        })
+    }
+
+    /// Returns the cells of the range that lie outside `hole`, as at most four ranges:
+    /// the full-width bands above and below the hole, then the parts left and right of it.
+    #[cfg(feature = "extensions")]
+    pub fn subtract(&self, hole: &Range) -> Vec<Range> {
+        if !self.overlaps(hole) {
+            return vec![*self];
+        }
+        let mut pieces = Vec::new();
+        if self.start_row < hole.start_row {
+            pieces.push(Range {
+                end_row: hole.start_row - 1,
+                ..*self
+            });
+        }
+        if hole.end_row < self.end_row {
+            pieces.push(Range {
+                start_row: hole.end_row + 1,
+                ..*self
+            });
+        }
+        let band = Range {
+            start_row: self.start_row.max(hole.start_row),
+            end_row: self.end_row.min(hole.end_row),
+            ..*self
+        };
+        if self.start_col < hole.start_col {
+            pieces.push(Range {
+                end_col: hole.start_col - 1,
+                ..band
+            });
+        }
+        if hole.end_col < self.end_col {
+            pieces.push(Range {
+                start_col: hole.end_col + 1,
+                ..band
+            });
+        }
+        pieces
+    }
+
+    /// Iterates over the cells of the range in row-major order.
+    #[cfg(feature = "extensions")]
+    pub fn cells(&self) -> impl Iterator<Item = (i16, i16)> + '_ {
+        (self.start_row..=self.end_row)
+            .flat_map(move |row| (self.start_col..=self.end_col).map(move |col| (row, col)))
+    }
+}
+
+/// Parses the two sides of a whole-column range such as `A:C` or a whole-row range such
+/// as `3:5`, which reach across the rows or columns the sheet has.
+///
+/// # Returns
+///
+/// * `Ok(Some(Range))` - The range.
+/// * `Ok(None)` - If the sides are not both column names or both row numbers.
+/// * `Err(CommandStatus)` - As for `parse_range`
+fn parse_lines(
+    spreadsheet: &Spreadsheet,
+    start: &str,
+    end: &str,
+) -> Result<Option<Range>, CommandStatus> {
+    let is_column = |side: &str| side.bytes().all(|b| b.is_ascii_alphabetic());
+    let is_row = |side: &str| side.bytes().all(|b| b.is_ascii_digit());
+    // Each side is checked as a cell in the first row or column
+    let (start_cell, end_cell) = if is_column(start) && is_column(end) {
+        (format!("{}1", start), format!("{}1", end))
+    } else if is_row(start) && is_row(end) {
+        (format!("A{}", start), format!("A{}", end))
+    } else {
+        return Ok(None);
+    };
+    let (start_row, start_col) = parse_cell_reference(spreadsheet, &start_cell)?;
+    let (end_row, end_col) = parse_cell_reference(spreadsheet, &end_cell)?;
+    let range = Range::from_corners(start_row, start_col, end_row, end_col);
+    Ok(Some(if is_column(start) {
+        Range {
+            start_row: 0,
+            end_row: spreadsheet.rows - 1,
+            ..range
+        }
+    } else {
+        Range {
+            start_col: 0,
+            end_col: spreadsheet.cols - 1,
+            ..range
+        }
+    }))
+}
+
+/// Parses a range string (e.g., "A1:B2", "A:A" or "3:5") into a `Range`.
+///
+/// The corners may be given in any order, so `B2:A1` is `A1:B2`. A whole-column or
+/// whole-row range covers every row or column the sheet has.
+///
+/// # Arguments
+///
+/// * `spreadsheet` - The spreadsheet for coordinate conversion.
+/// * `range_str` - The range string to parse.
+///
+/// # Returns
+///
+/// * `Ok(Range)` - The parsed range.
+/// * `Err(CommandStatus::InvalidCell)` - If a corner is off the sheet (`Unrecognized`
+///   without extensions).
+/// * `Err(CommandStatus::Unrecognized)` - If the range is otherwise invalid
+pub fn parse_range(spreadsheet: &Spreadsheet, range_str: &str) -> Result<Range, CommandStatus> {
+    // Exactly one colon, with text on both sides; spaces around either corner are ignored
+    let (start_cell, end_cell) = range_str
+        .split_once(':')
+        .ok_or(CommandStatus::Unrecognized)?;
+    let (start_cell, end_cell) = (start_cell.trim(), end_cell.trim());
+    if start_cell.is_empty() || end_cell.is_empty() || end_cell.contains(':') {
+        return Err(CommandStatus::Unrecognized);
+    }
+    if let Some(range) = parse_lines(spreadsheet, start_cell, end_cell)? {
+        return Ok(range);
+    }
+
+    // Parse cell references and validate them in one step
+    let (start_row, start_col) = parse_cell_reference(spreadsheet, start_cell)?;
+    let (end_row, end_col) = parse_cell_reference(spreadsheet, end_cell)?;
+
+    // Any two opposite corners make the same rectangle
+    Ok(Range::from_corners(start_row, start_col, end_row, end_col))
+}
+
+/// Resolves a range string that may also be the name of a named range.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet containing named ranges.
+/// * `range_str` - A range like "A1:B2" or a range name.
+///
+/// # Returns
+///
+/// * `Ok(Range)` - The resolved range.
+/// * `Err(CommandStatus)` - As for `parse_range`
+#[cfg(feature = "extensions")]
+pub fn resolve_range(sheet: &Spreadsheet, range_str: &str) -> Result<Range, CommandStatus> {
+    match sheet.named_ranges.get(range_str) {
+        Some(range) => Ok(*range),
+        None => parse_range(sheet, range_str),
+    }
+}
+
+/// Resolves the target of a command that takes a cell, a range or a named range.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet containing named ranges.
+/// * `target` - A cell like "A1", a range like "A1:B2" or "3:3", or a name.
+///
+/// # Returns
+///
+/// * `Ok(Range)` - The cells the target covers.
+/// * `Err(CommandStatus)` - As for `parse_range` and `resolve_cell_reference`
+#[cfg(feature = "extensions")]
+pub fn resolve_target(sheet: &Spreadsheet, target: &str) -> Result<Range, CommandStatus> {
+    if let Some(range) = sheet.named_ranges.get(target) {
+        Ok(*range)
+    } else if target.contains(':') {
+        parse_range(sheet, target)
+    } else {
+        crate::evaluator::resolve_cell_reference(sheet, target)
+            .map(|(row, col)| Range::single(row, col))
+    }
+}
+
+/// Describes why a range is over the sheet's range cell limit, if it is.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet holding the limit.
+/// * `range` - The range to check.
+///
+/// # Returns
+///
+/// * `Some(message)` - If the range covers more cells than `sheet.range_cell_limit`.
+/// * `None` - Otherwise
+#[cfg(feature = "extensions")]
+pub fn range_limit_exceeded(sheet: &Spreadsheet, range: &Range) -> Option<String> {
+    let count = range.cell_count();
+    if count > sheet.range_cell_limit {
+        Some(format!(
+            "Range covers {} cells, above the limit of {}",
+            count, sheet.range_cell_limit
+        ))
+    } else {
+        None
+    }
+}
+
+/// Refuses a range over the sheet's range cell limit in a command that was not confirmed,
+/// telling the user how to confirm it.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet holding the limit.
+/// * `range` - The range to check.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - If the range is within the limit.
+/// * `CommandStatus::RangeTooLarge` - If it is over, after printing why
+#[cfg(feature = "extensions")]
+pub fn check_range_limit(sheet: &mut Spreadsheet, range: &Range) -> CommandStatus {
+    match range_limit_exceeded(sheet, range) {
+        Some(message) => {
+            sheet
+                .output
+                .line(format!("{}; end the command with ! to confirm", message));
+            CommandStatus::RangeTooLarge
+        }
+        None => CommandStatus::CmdOk,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use crate::spreadsheet::{CommandStatus, Spreadsheet};
+
+    fn create_test_spreadsheet(rows: i16, cols: i16) -> Spreadsheet {
+        Spreadsheet::create(rows, cols).unwrap()
+    }
+
+    #[test]
+    fn test_parse_range_valid() {
+        let sheet = create_test_spreadsheet(5, 5);
+        let range = parse_range(&sheet, "A1:B2").unwrap();
+        assert_eq!(range.start_row, 0);
+        assert_eq!(range.start_col, 0);
+        assert_eq!(range.end_row, 1);
+        assert_eq!(range.end_col, 1);
+        assert_eq!(
+            parse_range(&sheet, "C3:C3"),
+            Ok(Range {
+                start_row: 2,
+                start_col: 2,
+                end_row: 2,
+                end_col: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_range_ignores_spaces_around_corners() {
+        let sheet = create_test_spreadsheet(5, 5);
+        for text in [" A1:B2", "A1 :B2", "A1: B2", " A1 : B2 "] {
+            assert_eq!(
+                parse_range(&sheet, text),
+                parse_range(&sheet, "A1:B2"),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(
+            parse_range(&sheet, "A1: "),
+            Err(CommandStatus::Unrecognized)
+        );
+        assert_eq!(
+            parse_range(&sheet, "A 1:B2"),
+            Err(CommandStatus::Unrecognized)
+        );
+    }
+
+    #[test]
+    fn test_parse_whole_columns_and_rows() {
+        let sheet = create_test_spreadsheet(20, 5);
+        let range = |start_row, start_col, end_row, end_col| {
+            Ok(Range {
+                start_row,
+                start_col,
+                end_row,
+                end_col,
+            })
+        };
+        assert_eq!(parse_range(&sheet, "A:A"), range(0, 0, 19, 0));
+        assert_eq!(parse_range(&sheet, "b:D"), range(0, 1, 19, 3));
+        assert_eq!(parse_range(&sheet, " E : E "), range(0, 4, 19, 4));
+        assert_eq!(parse_range(&sheet, "3:3"), range(2, 0, 2, 4));
+        assert_eq!(parse_range(&sheet, "1:20"), range(0, 0, 19, 4));
+
+        assert_eq!(parse_range(&sheet, "D:B"), range(0, 1, 19, 3));
+        assert_eq!(parse_range(&sheet, "5:2"), range(1, 0, 4, 4));
+
+        // A side off the sheet fails as it does for cells
+        #[cfg(feature = "extensions")]
+        let off_sheet = CommandStatus::InvalidCell;
+        #[cfg(not(feature = "extensions"))]
+        let off_sheet = CommandStatus::Unrecognized;
+        for text in ["A:F", "21:21", "0:3"] {
+            assert!(
+                parse_range(&sheet, text).is_err_and(|status| status == off_sheet),
+                "{}",
+                text
+            );
+        }
+        for text in ["A:3", "3:A", "A:A1", "A1:A", "AAAA:AAAA", "$A:$A"] {
+            assert_eq!(
+                parse_range(&sheet, text),
+                Err(CommandStatus::Unrecognized),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_range_invalid() {
+        let sheet = create_test_spreadsheet(5, 5);
+        assert_eq!(parse_range(&sheet, "A"), Err(CommandStatus::Unrecognized));
+        assert_eq!(parse_range(&sheet, "A1:"), Err(CommandStatus::Unrecognized));
+        assert_eq!(parse_range(&sheet, ":A1"), Err(CommandStatus::Unrecognized));
+        assert_eq!(
+            parse_range(&sheet, "A1:B"),
+            Err(CommandStatus::Unrecognized)
+        );
+        assert_eq!(
+            parse_range(&sheet, "A1:B2:C3"),
+            Err(CommandStatus::Unrecognized)
+        );
+    }
+
+    #[test]
+    fn test_parse_range_accepts_corners_in_any_order() {
+        let sheet = create_test_spreadsheet(5, 5);
+        let expected = parse_range(&sheet, "A1:B2");
+        assert!(expected.is_ok());
+        for text in ["B2:A1", "A2:B1", "B1:A2"] {
+            assert_eq!(parse_range(&sheet, text), expected, "{}", text);
+        }
+        assert_eq!(parse_range(&sheet, "A2:A1"), parse_range(&sheet, "A1:A2"));
+        // A corner off the sheet is still an error in either order
+        assert!(parse_range(&sheet, "F2:A1").is_err());
+        assert_eq!(parse_range(&sheet, "F2:A1"), parse_range(&sheet, "A1:F2"));
+    }
+
+    #[test]
+    fn test_range_helpers() {
+        let range = Range {
+            start_row: 1,
+            start_col: 1,
+            end_row: 3,
+            end_col: 2,
+        };
+        assert!(range.contains(1, 1));
+        assert!(range.contains(3, 2));
+        assert!(!range.contains(0, 1));
+        assert!(!range.contains(2, 3));
+        #[cfg(feature = "extensions")]
+        {
+            assert_eq!(range.cell_count(), 6);
+            let cells: Vec<(i16, i16)> = range.cells().collect();
+            assert_eq!(cells, vec![(1, 1), (1, 2), (2, 1), (2, 2), (3, 1), (3, 2)]);
+        }
+    }
+
+    #[test]
+    fn test_parse_range_round_trip_property() {
+        let sheet = create_test_spreadsheet(999, 800);
+        let mut rng = Rng::new(0x9E37_79B9_7F4A_7C15);
+        for _ in 0..2000 {
+            let mut r = [rng.below(999) as i16, rng.below(999) as i16];
+            let mut c = [rng.below(800) as i16, rng.below(800) as i16];
+            r.sort();
+            c.sort();
+            let text = format!(
+                "{}{}:{}{}",
+                sheet.get_column_name(c[0]),
+                r[0] + 1,
+                sheet.get_column_name(c[1]),
+                r[1] + 1
+            );
+            let expected = Range {
+                start_row: r[0],
+                start_col: c[0],
+                end_row: r[1],
+                end_col: c[1],
+            };
+            assert_eq!(parse_range(&sheet, &text), Ok(expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_parse_range_garbage_property() {
+        let sheet = create_test_spreadsheet(20, 20);
+        let alphabet: Vec<char> = "AZaz019:$-[] ".chars().collect();
+        let mut rng = Rng::new(0xD1B5_4A32_D192_ED03);
+        for _ in 0..5000 {
+            let len = rng.below(8) as usize;
+            let text: String = (0..len)
+                .map(|_| alphabet[rng.below(alphabet.len() as u64) as usize])
+                .collect();
+            // Skip strings that happen to be well-formed ranges, of cells or of whole lines
+            let well_formed = text.split_once(':').is_some_and(|(a, b)| {
+                let (a, b) = (a.trim(), b.trim());
+                let cells = |a: &str, b: &str| {
+                    parse_cell_reference(&sheet, a).is_ok()
+                        && parse_cell_reference(&sheet, b).is_ok()
+                };
+                cells(a, b)
+                    || cells(&format!("{}1", a), &format!("{}1", b))
+                    || cells(&format!("A{}", a), &format!("A{}", b))
+            });
+            if !well_formed {
+                assert!(parse_range(&sheet, &text).is_err(), "{:?}", text);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_subtract_covers_exactly_the_cells_outside_the_hole() {
+        let mut rng = Rng::new(0x9E37_79B9_7F4A_7C15);
+        let random_range = |rng: &mut Rng| {
+            let corner = |rng: &mut Rng| rng.below(6) as i16;
+            Range::from_corners(corner(rng), corner(rng), corner(rng), corner(rng))
+        };
+        for _ in 0..500 {
+            let range = random_range(&mut rng);
+            let hole = random_range(&mut rng);
+            let pieces = range.subtract(&hole);
+            assert!(pieces.len() <= 4);
+            for row in 0..6 {
+                for col in 0..6 {
+                    let covering = pieces.iter().filter(|p| p.contains(row, col)).count();
+                    let expected = range.contains(row, col) && !hole.contains(row, col);
+                    assert_eq!(covering, expected as usize, "{:?} - {:?}", range, hole);
+                }
+            }
+        }
+    }
+}
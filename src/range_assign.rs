@@ -0,0 +1,909 @@
+//! Assignments with a range on the left-hand side, such as `A1:A10=0` or `B1:B5=A1*2`.
+//!
+//! The expression is written as if it had been entered in the range's top-left cell and
+//! filled across the rest: plain references move with the target cell, `$`-pinned parts
+//! and `RC[..]` relative references stay put, and named ranges are left alone.
+//!
+//! A right-hand side built from ranges, such as `C1:C10=A1:A10+B1:B10`, is applied cell
+//! by cell instead: each target cell gets a formula over the matching cells of the source
+//! ranges (`C1=A1+B1`, `C2=A2+B2`, ...), and a literal or single cell is used as is in
+//! every formula.
+//!
+//! With `set confirm_overwrite_bulk on`, an assignment that would overwrite cells holding
+//! something is held back and applied once the user has answered for each of them; see
+//! `overwrite`.
+
+use crate::cell::{
+    CellValue, PINNED_COL_PARENT1, PINNED_COL_PARENT2, PINNED_ROW_PARENT1, PINNED_ROW_PARENT2,
+    RELATIVE_PARENT1, RELATIVE_PARENT2, format_pinned_reference, parse_cell_reference,
+};
+use crate::cell_history::HistoryFormula;
+use crate::evaluator::set_cell_value;
+use crate::expr_parser::{
+    Operand as ExprOperand, Reference, VectorOperand, parse_vector_expression,
+};
+use crate::extensions::bulk_set_values;
+use crate::overwrite::{PendingOverwrite, overwrite_conflicts};
+use crate::range::{Range, check_range_limit, parse_range};
+use crate::spreadsheet::{CommandStatus, MAX_COLUMN_NAME_LEN, Spreadsheet};
+use std::collections::HashSet;
+
+/// What a range assignment did.
+///
+/// # Fields
+///
+/// * `applied` - Cells that were set.
+/// * `failed` - Cells that were skipped, with the reason.
+/// * `kept` - Cells the user chose not to overwrite.
+#[derive(Debug, Default, PartialEq)]
+pub struct RangeAssignReport {
+    pub applied: usize,
+    pub failed: Vec<((i16, i16), CommandStatus)>,
+    pub kept: usize,
+}
+
+/// A range assignment, parsed and checked but not yet applied.
+///
+/// # Fields
+///
+/// * `target` - The range as typed, for the summary.
+/// * `range` - The target range.
+/// * `expr` - The right-hand side as typed.
+/// * `formulas` - Each cell's formula, for an elementwise right-hand side.
+#[derive(Debug, PartialEq)]
+pub struct RangeWrite {
+    pub target: String,
+    pub range: Range,
+    pub expr: String,
+    pub formulas: Option<CellFormulas>,
+}
+
+/// Resolves the left-hand side of an assignment as a range, if it is one.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet containing named ranges.
+/// * `target` - The text before `=`.
+///
+/// # Returns
+///
+/// * `None` - If the target is neither `A1:B2` nor a named range.
+/// * `Some(Ok(range))` - The range to assign to.
+/// * `Some(Err(CommandStatus))` - If the range is invalid
+pub fn assignment_range(sheet: &Spreadsheet, target: &str) -> Option<Result<Range, CommandStatus>> {
+    if let Some(range) = sheet.named_ranges.get(target) {
+        return Some(Ok(*range));
+    }
+    target.contains(':').then(|| parse_range(sheet, target))
+}
+
+/// Moves the plain references of an expression by a row and column offset.
+///
+/// A reference is a run of one to three capitals followed by a row number, each
+/// optionally preceded by `$`. Pinned parts keep their position, and quoted text is
+/// left as it is.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for column names and bounds.
+/// * `expr` - The expression as entered for the anchor cell.
+/// * `row_offset` - Rows to move down.
+/// * `col_offset` - Columns to move right.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The shifted expression.
+/// * `Err(CommandStatus::InvalidCell)` - If a reference would leave the sheet
+pub fn shift_references(
+    sheet: &Spreadsheet,
+    expr: &str,
+    row_offset: i16,
+    col_offset: i16,
+) -> Result<String, CommandStatus> {
+    let bytes = expr.as_bytes();
+    let mut shifted = String::with_capacity(expr.len() + 4);
+    let mut last = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        // Quoted text is a label, even where it looks like a reference
+        if bytes[i] == b'"' {
+            i += expr[i + 1..]
+                .find('"')
+                .map_or(bytes.len() - i, |end| end + 2);
+            continue;
+        }
+        // A reference starts after a non-alphanumeric character
+        if i > 0 && (bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'$') {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let pin_col = bytes[i] == b'$';
+        let letters_start = start + usize::from(pin_col);
+        let mut j = letters_start;
+        while j < bytes.len() && bytes[j].is_ascii_uppercase() {
+            j += 1;
+        }
+        let letters_end = j;
+        let pin_row = bytes.get(j) == Some(&b'$');
+        let digits_start = j + usize::from(pin_row);
+        j = digits_start;
+        while j < bytes.len() && bytes[j].is_ascii_digit() {
+            j += 1;
+        }
+        let letters = letters_end - letters_start;
+        if !(1..=MAX_COLUMN_NAME_LEN).contains(&letters)
+            || j == digits_start
+            || bytes.get(j).is_some_and(|b| b.is_ascii_alphanumeric())
+        {
+            i += 1;
+            continue;
+        }
+
+        let mut col = sheet.column_name_to_index(&expr[letters_start..letters_end]) as i32;
+        let mut row = expr[digits_start..j]
+            .parse::<i32>()
+            .map_err(|_| CommandStatus::InvalidCell)?
+            - 1;
+        if !pin_col {
+            col += col_offset as i32;
+        }
+        if !pin_row {
+            row += row_offset as i32;
+        }
+        if row < 0 || col < 0 || row >= sheet.rows as i32 || col >= sheet.cols as i32 {
+            return Err(CommandStatus::InvalidCell);
+        }
+        shifted.push_str(&expr[last..start]);
+        let column = sheet.get_column_name(col as i16);
+        shifted.push_str(&format_pinned_reference(
+            &column, row as i16, pin_col, pin_row,
+        ));
+        last = j;
+        i = j;
+    }
+    shifted.push_str(&expr[last..]);
+    Ok(shifted)
+}
+
+/// Moves the references of a stored formula by a row and column offset, as
+/// `shift_references` does for its text.
+///
+/// Relative references always move, so they keep their offsets from the formula's cell.
+/// With `plain` set, plain references move too, except for their `$`-pinned parts;
+/// otherwise they keep reading the same cells. A criterion has no references and stays.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for bounds.
+/// * `formula` - The formula as stored for its cell.
+/// * `row_offset` - Rows to move down.
+/// * `col_offset` - Columns to move right.
+/// * `plain` - Whether plain references move as well.
+///
+/// # Returns
+///
+/// * `Ok(HistoryFormula)` - The shifted formula.
+/// * `Err(CommandStatus::InvalidCell)` - If a reference would leave the sheet
+pub fn shift_formula(
+    sheet: &Spreadsheet,
+    formula: &HistoryFormula,
+    row_offset: i16,
+    col_offset: i16,
+    plain: bool,
+) -> Result<HistoryFormula, CommandStatus> {
+    let shift = |key: i32, moves_row: bool, moves_col: bool| {
+        let (row, col) = sheet.get_row_col(key);
+        let row = row as i32 + if moves_row { row_offset as i32 } else { 0 };
+        let col = col as i32 + if moves_col { col_offset as i32 } else { 0 };
+        if row < 0 || col < 0 || row >= sheet.rows as i32 || col >= sheet.cols as i32 {
+            return Err(CommandStatus::InvalidCell);
+        }
+        Ok(sheet.get_key(row as i16, col as i16))
+    };
+    // A reference moves whole if relative, else its parts that are not pinned if `plain`
+    let shift_reference = |key: i32, flags: u8, relative: u8, pin_row: u8, pin_col: u8| {
+        if flags & relative != 0 {
+            shift(key, true, true)
+        } else {
+            shift(
+                key,
+                plain && flags & pin_row == 0,
+                plain && flags & pin_col == 0,
+            )
+        }
+    };
+    let flags = formula.relative.unwrap_or(0);
+    let mut shifted = formula.clone();
+    let meta = &mut shifted.meta;
+    let (first, second) = match meta.formula % 10 {
+        0 | 5..=9 => (true, true),
+        2 => (true, false),
+        3 => (false, true),
+        _ => (false, false),
+    };
+    for (parent, read, relative, pin_row, pin_col) in [
+        (
+            &mut meta.parent1,
+            first,
+            RELATIVE_PARENT1,
+            PINNED_ROW_PARENT1,
+            PINNED_COL_PARENT1,
+        ),
+        (
+            &mut meta.parent2,
+            second,
+            RELATIVE_PARENT2,
+            PINNED_ROW_PARENT2,
+            PINNED_COL_PARENT2,
+        ),
+    ] {
+        if !read {
+            continue;
+        }
+        *parent = shift_reference(*parent, flags, relative, pin_row, pin_col)?;
+    }
+    if let Some(extended) = shifted.extended.as_mut() {
+        // The remapping cannot stop halfway, so the first failure is kept and reported
+        let mut failure = Ok(());
+        extended.remap_cells(|key, flags| {
+            shift_reference(
+                key,
+                flags,
+                RELATIVE_PARENT1,
+                PINNED_ROW_PARENT1,
+                PINNED_COL_PARENT1,
+            )
+            .unwrap_or_else(|status| {
+                failure = Err(status);
+                key
+            })
+        });
+        extended.remap_ranges(|range, flags| {
+            let start = shift_reference(
+                sheet.get_key(range.start_row, range.start_col),
+                flags,
+                RELATIVE_PARENT1,
+                PINNED_ROW_PARENT1,
+                PINNED_COL_PARENT1,
+            );
+            let end = shift_reference(
+                sheet.get_key(range.end_row, range.end_col),
+                flags,
+                RELATIVE_PARENT2,
+                PINNED_ROW_PARENT2,
+                PINNED_COL_PARENT2,
+            );
+            let (Ok(start), Ok(end)) = (start, end) else {
+                failure = Err(CommandStatus::InvalidCell);
+                return range;
+            };
+            let ((start_row, start_col), (end_row, end_col)) =
+                (sheet.get_row_col(start), sheet.get_row_col(end));
+            // A pinned corner can end up past the one that moved
+            if start_row > end_row || start_col > end_col {
+                failure = Err(CommandStatus::InvalidCell);
+                return range;
+            }
+            Range {
+                start_row,
+                start_col,
+                end_row,
+                end_col,
+            }
+        });
+        failure?;
+    }
+    Ok(shifted)
+}
+
+/// One side of an elementwise expression.
+///
+/// # Variants
+///
+/// * `Range` - A range, read cell by cell.
+/// * `Scalar` - A literal or single cell, used in every cell's formula as typed.
+#[derive(Debug, PartialEq)]
+enum Operand<'a> {
+    Range(&'a str, Range),
+    Scalar(&'a str),
+}
+
+impl Operand<'_> {
+    /// Returns the operand's text for the cell at an offset from the range's corner.
+    fn at(&self, sheet: &Spreadsheet, row_offset: i16, col_offset: i16) -> String {
+        match self {
+            Operand::Range(_, range) => {
+                sheet.get_cell_name(range.start_row + row_offset, range.start_col + col_offset)
+            }
+            Operand::Scalar(text) => text.to_string(),
+        }
+    }
+}
+
+/// Resolves one side of an elementwise expression: a range, a literal, or a single
+/// (possibly pinned) cell.
+fn resolve_operand<'a>(sheet: &Spreadsheet, operand: VectorOperand<'a>) -> Option<Operand<'a>> {
+    match operand {
+        VectorOperand::Span(span) => parse_range(sheet, span.text)
+            .ok()
+            .map(|range| Operand::Range(span.text, range)),
+        VectorOperand::Scalar(text, ExprOperand::Number(_)) => Some(Operand::Scalar(text)),
+        VectorOperand::Scalar(text, ExprOperand::Ref(Reference::Cell { column, row, .. })) => {
+            parse_cell_reference(sheet, &format!("{}{}", column, row))
+                .is_ok()
+                .then_some(Operand::Scalar(text))
+        }
+        VectorOperand::Scalar(..) => None,
+    }
+}
+
+/// Formats a range's shape as rows x columns, e.g. `10x1`.
+fn shape(range: &Range) -> String {
+    format!(
+        "{}x{}",
+        range.end_row - range.start_row + 1,
+        range.end_col - range.start_col + 1
+    )
+}
+
+/// An elementwise right-hand side: one operand, or two joined by an operator.
+type Elementwise<'a> = (Operand<'a>, Option<(char, Operand<'a>)>);
+
+/// Target cells paired with the formula each one gets.
+pub type CellFormulas = Vec<((i16, i16), String)>;
+
+/// Splits a right-hand side into elementwise operands, if it uses a range as one.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for references.
+/// * `expr` - The right-hand side, without a trailing `!`.
+///
+/// # Returns
+///
+/// * `Some(Elementwise)` - The operands, at least one of them a range.
+/// * `None` - If the expression is not elementwise, e.g. `A1*2` or `SUM(A1:A3)`
+fn parse_elementwise<'a>(sheet: &Spreadsheet, expr: &'a str) -> Option<Elementwise<'a>> {
+    let (left, right) = parse_vector_expression(expr.trim()).ok()?;
+    let left = resolve_operand(sheet, left)?;
+    let right = match right {
+        Some((op, operand)) => Some((op.symbol(), resolve_operand(sheet, operand)?)),
+        None => None,
+    };
+    let has_range =
+        matches!(left, Operand::Range(..)) || matches!(right, Some((_, Operand::Range(..))));
+    has_range.then_some((left, right))
+}
+
+/// Builds the formula of every target cell for an elementwise assignment.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for cell names.
+/// * `target` - The target range as typed, for the error message.
+/// * `range` - The target range.
+/// * `operands` - The parsed right-hand side.
+///
+/// # Returns
+///
+/// * `Ok(CellFormulas)` - Each target cell with its formula.
+/// * `Err(String)` - A message naming the shapes, if a source range differs from the target
+fn elementwise_formulas(
+    sheet: &Spreadsheet,
+    target: &str,
+    range: &Range,
+    operands: &Elementwise,
+) -> Result<CellFormulas, String> {
+    let (left, right) = operands;
+    let sources = std::iter::once(left).chain(right.iter().map(|(_, operand)| operand));
+    for operand in sources {
+        if let Operand::Range(text, source) = operand
+            && shape(source) != shape(range)
+        {
+            return Err(format!(
+                "shape mismatch: {} is {} but {} is {}",
+                target,
+                shape(range),
+                text,
+                shape(source)
+            ));
+        }
+    }
+    Ok(range
+        .cells()
+        .map(|(row, col)| {
+            let (row_offset, col_offset) = (row - range.start_row, col - range.start_col);
+            let mut formula = left.at(sheet, row_offset, col_offset);
+            if let Some((op, operand)) = right {
+                formula.push(*op);
+                formula.push_str(&operand.at(sheet, row_offset, col_offset));
+            }
+            ((row, col), formula)
+        })
+        .collect())
+}
+
+/// Writes an expression into every cell of a range.
+///
+/// Literals go through `bulk_set_values`, so dependents are reevaluated once. Formulas
+/// are shifted per cell and set one at a time, so a cycle only rolls back the cell that
+/// caused it. Locked cells, and cells owned by another user while ownership is enforced,
+/// are skipped.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `range` - The target range.
+/// * `expr` - The expression as entered for the top-left cell.
+/// * `keep` - Cells to leave as they are.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `RangeAssignReport` - Cells set and cells skipped
+pub fn assign_range(
+    sheet: &mut Spreadsheet,
+    range: &Range,
+    expr: &str,
+    keep: &HashSet<(i16, i16)>,
+    sleep_time: &mut f64,
+) -> RangeAssignReport {
+    let mut report = RangeAssignReport {
+        kept: keep.len(),
+        ..Default::default()
+    };
+    let literal = expr.trim_end_matches('!').trim().parse::<i32>().ok();
+
+    if let Some(value) = literal {
+        let mut writes = Vec::new();
+        for (row, col) in range.cells() {
+            if keep.contains(&(row, col)) {
+                continue;
+            }
+            if sheet.is_cell_locked(row, col) {
+                report.failed.push(((row, col), CommandStatus::LockedCell));
+            } else if sheet.check_owner(row, col) != CommandStatus::CmdOk {
+                report.failed.push(((row, col), CommandStatus::NotOwner));
+            } else {
+                writes.push((row, col, CellValue::Integer(value)));
+            }
+        }
+        report.applied = bulk_set_values(sheet, &writes, sleep_time).written;
+        return report;
+    }
+
+    for (row, col) in range.cells().filter(|cell| !keep.contains(cell)) {
+        let status =
+            match shift_references(sheet, expr, row - range.start_row, col - range.start_col) {
+                Ok(shifted) => set_cell_value(sheet, row, col, &shifted, sleep_time),
+                Err(status) => status,
+            };
+        match status {
+            CommandStatus::CmdOk => report.applied += 1,
+            status => report.failed.push(((row, col), status)),
+        }
+    }
+    report
+}
+
+/// Sets a formula in each listed cell, one at a time.
+///
+/// A cell whose formula would refer to itself or close a cycle, and a locked cell, is
+/// skipped and reported; the others are still set.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `formulas` - Each target cell with its formula.
+/// * `keep` - Cells to leave as they are.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `RangeAssignReport` - Cells set and cells skipped
+fn assign_formulas(
+    sheet: &mut Spreadsheet,
+    formulas: &[((i16, i16), String)],
+    keep: &HashSet<(i16, i16)>,
+    sleep_time: &mut f64,
+) -> RangeAssignReport {
+    let mut report = RangeAssignReport {
+        kept: keep.len(),
+        ..Default::default()
+    };
+    for ((row, col), formula) in formulas.iter().filter(|(cell, _)| !keep.contains(cell)) {
+        match set_cell_value(sheet, *row, *col, formula, sleep_time) {
+            CommandStatus::CmdOk => report.applied += 1,
+            status => report.failed.push(((*row, *col), status)),
+        }
+    }
+    report
+}
+
+/// Handles `<range>=<expr>`, printing a summary line and one line per skipped cell.
+///
+/// When the sheet asks before overwriting (see `Spreadsheet::confirms_overwrites`) and
+/// some target cells hold something, the checked assignment is left in
+/// `sheet.pending_overwrite` for the command loop to confirm and apply.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `target` - The range as typed, for the summary.
+/// * `range` - The resolved range.
+/// * `expr` - The right-hand side; a trailing `!` confirms a range over the size limit.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - If at least one cell was set, or the assignment awaits
+///   confirmation.
+/// * `CommandStatus::RangeTooLarge` - If the range is over the limit and not confirmed.
+/// * `CommandStatus::ShapeMismatch` - If elementwise ranges differ in shape.
+/// * The first cell's status - If no cell could be set
+pub fn handle_range_assignment(
+    sheet: &mut Spreadsheet,
+    target: &str,
+    range: &Range,
+    expr: &str,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    let elementwise = parse_elementwise(sheet, expr.trim_end_matches('!'));
+    if range.cell_count() == 1 && elementwise.is_none() {
+        return set_cell_value(sheet, range.start_row, range.start_col, expr, sleep_time);
+    }
+    if !expr.ends_with('!') {
+        let status = check_range_limit(sheet, range);
+        if status != CommandStatus::CmdOk {
+            return status;
+        }
+    }
+
+    let formulas = match elementwise {
+        Some(operands) => match elementwise_formulas(sheet, target, range, &operands) {
+            Ok(formulas) => Some(formulas),
+            Err(message) => {
+                sheet.output.line(message);
+                return CommandStatus::ShapeMismatch;
+            }
+        },
+        None => None,
+    };
+    let write = RangeWrite {
+        target: target.to_string(),
+        range: *range,
+        expr: expr.to_string(),
+        formulas,
+    };
+    if sheet.confirms_overwrites() {
+        let conflicts = overwrite_conflicts(sheet, range);
+        if !conflicts.is_empty() {
+            sheet.pending_overwrite = Some(PendingOverwrite { write, conflicts });
+            return CommandStatus::CmdOk;
+        }
+    }
+    apply_range_write(sheet, &write, &HashSet::new(), sleep_time)
+}
+
+/// Applies a checked range assignment, printing a summary line and one line per
+/// skipped cell.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `write` - The assignment.
+/// * `keep` - Cells the user chose not to overwrite.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - If at least one cell was set or kept.
+/// * The first cell's status - If no cell could be set
+pub fn apply_range_write(
+    sheet: &mut Spreadsheet,
+    write: &RangeWrite,
+    keep: &HashSet<(i16, i16)>,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    let mut report = match &write.formulas {
+        Some(formulas) => assign_formulas(sheet, formulas, keep, sleep_time),
+        None => assign_range(sheet, &write.range, &write.expr, keep, sleep_time),
+    };
+    if report.applied == 0 && report.kept == 0 && !report.failed.is_empty() {
+        return report.failed.swap_remove(0).1;
+    }
+
+    let mut summary = format!(
+        "{}: {} cell(s) set, {} skipped",
+        write.target,
+        report.applied,
+        report.failed.len()
+    );
+    if report.kept > 0 {
+        summary.push_str(&format!(", {} kept", report.kept));
+    }
+    let mut lines = vec![summary];
+    for ((row, col), status) in &report.failed {
+        lines.push(format!(
+            "{} skipped ({})",
+            sheet.get_cell_name(*row, *col),
+            status.as_str()
+        ));
+    }
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::handle_command;
+    use crate::extensions::get_formula_string;
+    use crate::output::OutputSink;
+    use crate::test_utils::ScriptedSheet;
+
+    fn new_sheet() -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(10, 5).unwrap();
+        sheet.output = OutputSink::capture();
+        sheet
+    }
+
+    #[test]
+    fn test_shift_references() {
+        let sheet = new_sheet();
+        assert_eq!(
+            shift_references(&sheet, "A1+$B$2*C$3-SUM($A1:B2)", 1, 1).unwrap(),
+            "B2+$B$2*D$3-SUM($A2:C3)"
+        );
+        assert_eq!(
+            shift_references(&sheet, "RC[-1]+MAX(total)", 3, 0).unwrap(),
+            "RC[-1]+MAX(total)"
+        );
+        assert_eq!(
+            shift_references(&sheet, "A10+1", 1, 0),
+            Err(CommandStatus::InvalidCell)
+        );
+    }
+
+    #[test]
+    fn test_literal_broadcast() {
+        ScriptedSheet::new(10, 5)
+            .run(&["B1=A1+A10", "A1:A10=4"])
+            .assert_status_ok()
+            .assert_cell("A10", 4)
+            .assert_cell("B1", 8)
+            .assert_output(&["A1:A10: 10 cell(s) set, 0 skipped"])
+            .run(&["A1:A10=0"])
+            .assert_status_ok()
+            .assert_cell("B1", 0);
+    }
+
+    #[test]
+    fn test_formula_fill_shifts_references() {
+        let mut sheet = new_sheet();
+        let mut sleep_time = 0.0;
+        for row in 1..=3 {
+            handle_command(
+                &mut sheet,
+                &format!("A{}={}", row, row * 10),
+                &mut sleep_time,
+            );
+        }
+        handle_command(&mut sheet, "E1=2", &mut sleep_time);
+        assert_eq!(
+            handle_command(&mut sheet, "B1:B3=A1*$E$1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(2, 1), CellValue::Integer(60));
+        assert_eq!(get_formula_string(&sheet, 2, 1), "A3*$E$1");
+
+        // Relative references resolve against each target cell without shifting
+        assert_eq!(
+            handle_command(&mut sheet, "C1:C3=RC[-1]+1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 2), CellValue::Integer(41));
+        assert_eq!(get_formula_string(&sheet, 1, 2), "RC[-1]+1");
+    }
+
+    #[test]
+    fn test_named_range_target() {
+        let mut sheet = new_sheet();
+        let mut sleep_time = 0.0;
+        sheet.named_ranges.insert(
+            "block".to_string(),
+            Range {
+                start_row: 0,
+                start_col: 3,
+                end_row: 1,
+                end_col: 4,
+            },
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "block=7", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 4), CellValue::Integer(7));
+        assert_eq!(sheet.output.take(), vec!["block: 4 cell(s) set, 0 skipped"]);
+    }
+
+    #[test]
+    fn test_locked_and_cyclic_cells_are_skipped() {
+        let mut sheet = new_sheet();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "A2=5", &mut sleep_time);
+        handle_command(&mut sheet, "lock_cell A2", &mut sleep_time);
+        assert_eq!(
+            handle_command(&mut sheet, "A1:A3=1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(5));
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                "A1:A3: 2 cell(s) set, 1 skipped",
+                "A2 skipped (locked_cell)"
+            ]
+        );
+
+        // B2 would read itself; only that cell is rolled back
+        handle_command(&mut sheet, "B2=9", &mut sleep_time);
+        assert_eq!(
+            handle_command(&mut sheet, "B1:B2=B$2+1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(10));
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(9));
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                "B1:B2: 1 cell(s) set, 1 skipped",
+                "B2 skipped (circular_ref)"
+            ]
+        );
+
+        // Nothing settable: the status is reported as for a single cell
+        assert_eq!(
+            handle_command(&mut sheet, "A2:A2=3", &mut sleep_time),
+            CommandStatus::LockedCell
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "C1:C2=A1+", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+        assert!(sheet.output.take().is_empty());
+    }
+
+    /// The same sheet as `elementwise_sheet`, for tests written with `ScriptedSheet`.
+    fn elementwise_script() -> ScriptedSheet {
+        ScriptedSheet::new(10, 5)
+            .run(&["A1=10", "A2=20", "A3=30", "B1=1", "B2=2", "B3=3"])
+            .assert_status_ok()
+    }
+
+    /// A1:A3 hold 10, 20, 30 and B1:B3 hold 1, 2, 3.
+    fn elementwise_sheet() -> Spreadsheet {
+        let mut sheet = new_sheet();
+        let mut sleep_time = 0.0;
+        for row in 1..=3 {
+            handle_command(
+                &mut sheet,
+                &format!("A{}={}", row, row * 10),
+                &mut sleep_time,
+            );
+            handle_command(&mut sheet, &format!("B{}={}", row, row), &mut sleep_time);
+        }
+        sheet
+    }
+
+    #[test]
+    fn test_elementwise_operators() {
+        let mut sheet = elementwise_sheet();
+        let mut sleep_time = 0.0;
+        for (op, expected) in [
+            ('+', [11, 22, 33]),
+            ('-', [9, 18, 27]),
+            ('*', [10, 40, 90]),
+            ('/', [10, 10, 10]),
+        ] {
+            assert_eq!(
+                handle_command(
+                    &mut sheet,
+                    &format!("C1:C3=A1:A3{}B1:B3", op),
+                    &mut sleep_time
+                ),
+                CommandStatus::CmdOk
+            );
+            for (row, value) in expected.into_iter().enumerate() {
+                assert_eq!(
+                    *sheet.get_cell(row as i16, 2),
+                    CellValue::Integer(value),
+                    "{}",
+                    op
+                );
+            }
+        }
+        assert_eq!(get_formula_string(&sheet, 2, 2), "A3/B3");
+        assert_eq!(
+            sheet.output.take().last().unwrap(),
+            "C1:C3: 3 cell(s) set, 0 skipped"
+        );
+
+        // A lone range copies cell by cell
+        handle_command(&mut sheet, "D1:D3=B1:B3", &mut sleep_time);
+        assert_eq!(get_formula_string(&sheet, 1, 3), "B2");
+        assert_eq!(*sheet.get_cell(1, 3), CellValue::Integer(2));
+    }
+
+    #[test]
+    fn test_elementwise_scalar_broadcast() {
+        elementwise_script()
+            .run(&["E1=5", "C1:C3=A1:A3*$E$1", "D1:D3=-2-B1:B3"])
+            .assert_status_ok()
+            .assert_cell("C3", 150)
+            .assert_formula("C3", "A3*$E$1")
+            .assert_cell("D3", -5)
+            // A single-cell target still takes the elementwise path
+            .run(&["E2:E2=A1:A1+1"])
+            .assert_status_ok()
+            .assert_cell("E2", 11);
+    }
+
+    #[test]
+    fn test_elementwise_shape_mismatch() {
+        let mut sheet = elementwise_sheet();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "C1:C3=A1:A2+B1:B3", &mut sleep_time),
+            CommandStatus::ShapeMismatch
+        );
+        assert_eq!(
+            sheet.output.take(),
+            vec!["shape mismatch: C1:C3 is 3x1 but A1:A2 is 2x1"]
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "C1:C3=A1:B1", &mut sleep_time),
+            CommandStatus::ShapeMismatch
+        );
+        assert_eq!(
+            sheet.output.take(),
+            vec!["shape mismatch: C1:C3 is 3x1 but A1:B1 is 1x2"]
+        );
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Empty);
+    }
+
+    #[test]
+    fn test_elementwise_overlap_skips_only_cyclic_cell() {
+        let mut sheet = elementwise_sheet();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "C2=7", &mut sleep_time);
+        // C2 would read itself; C1 and C3 read the old C2 value
+        assert_eq!(
+            handle_command(&mut sheet, "C1:C3=A1:A3+C2", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(17));
+        assert_eq!(*sheet.get_cell(1, 2), CellValue::Integer(7));
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Integer(37));
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                "C1:C3: 2 cell(s) set, 1 skipped",
+                "C2 skipped (circular_ref)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_elementwise_updates_dependents() {
+        elementwise_script()
+            .run(&["E1=SUM(C1:C3)", "C1:C3=A1:A3+B1:B3"])
+            .assert_status_ok()
+            .assert_cell("E1", 66)
+            // The new formulas follow their sources
+            .run(&["B2=100"])
+            .assert_cell("C2", 120)
+            .assert_cell("E1", 164);
+    }
+}
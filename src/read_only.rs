@@ -0,0 +1,247 @@
+//! Read-only mode: `readonly on`, `readonly off` and the `--readonly` flag.
+//!
+//! While the mode is on, a loaded sheet can be browsed, queried and saved, but nothing
+//! changes it. The check sits where every command enters (`handle_command`), and lets
+//! through only commands known not to change cells, formulas, names, locks or history;
+//! anything else, including commands added later, is refused with `ReadOnly` until the mode
+//! is turned off. The vim mode's cut and paste write cells without a command and check the
+//! mode themselves, as do `set_cell_value` and `set_cell_to_value`.
+
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+
+/// Commands that only read the sheet, move the view or change settings, by first word.
+const READ_ONLY_COMMANDS: [&str; 34] = [
+    "w",
+    "a",
+    "s",
+    "d",
+    "scroll_to",
+    "last_edit",
+    "enable_output",
+    "disable_output",
+    "formula",
+    "visual",
+    "deps",
+    "deps*",
+    "precedents",
+    "precedents*",
+    "trace_error",
+    "error",
+    "count",
+    "stats",
+    "sample",
+    "graph_stats",
+    "help",
+    "changes",
+    "verify",
+    "owners",
+    "is_locked",
+    "locked",
+    "names",
+    "find",
+    "find_next",
+    "show_history",
+    "history_stats",
+    "filter",
+    "set_limit",
+    "readonly",
+];
+
+/// `set` keys that change the sheet's data rather than a setting, such as trimming cell
+/// history.
+const DATA_SETTINGS: [&str; 1] = ["history_limit"];
+
+/// Commands that write a file from the sheet without changing it.
+const SAVE_COMMANDS: [&str; 5] = ["save", "saveas", "backups", "export_csv", "autosave"];
+
+/// Highlight commands, which only change how the sheet is drawn.
+const HIGHLIGHT_COMMANDS: [&str; 4] = ["HLP", "HLC", "HLPC", "HLOFF"];
+
+/// Checks whether a command may run while the sheet is read-only.
+///
+/// # Arguments
+///
+/// * `command` - The trimmed command.
+///
+/// # Returns
+///
+/// * `true` - If the command cannot change the sheet.
+/// * `false` - Otherwise, including for assignments and unknown commands
+pub fn is_read_only_command(command: &str) -> bool {
+    let mut words = command.split_whitespace();
+    let Some(word) = words.next() else {
+        return true;
+    };
+    if word.contains('=') {
+        return false;
+    }
+    READ_ONLY_COMMANDS.contains(&word)
+        || SAVE_COMMANDS.contains(&word)
+        || HIGHLIGHT_COMMANDS.contains(&word)
+        || (word == "set" && !words.next().is_some_and(|key| DATA_SETTINGS.contains(&key)))
+        || (word == "session" && words.next() == Some("save"))
+}
+
+/// Handles `readonly on|off`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `readonly `.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If the argument is neither `on` nor `off`
+pub fn handle_readonly(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    match args {
+        "on" => sheet.read_only = true,
+        "off" => sheet.read_only = false,
+        _ => return CommandStatus::Unrecognized,
+    }
+    CommandStatus::CmdOk
+}
+
+/// Removes `--readonly` from the command-line arguments.
+///
+/// # Arguments
+///
+/// * `args` - The command-line arguments; the flag is removed.
+///
+/// # Returns
+///
+/// * `bool` - Whether the flag was given
+pub fn take_readonly_flag(args: &mut Vec<String>) -> bool {
+    match args.iter().position(|arg| arg == "--readonly") {
+        Some(position) => {
+            args.remove(position);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::set_cell_value;
+    use crate::extensions::set_cell_to_value;
+    use crate::test_utils::ScriptedSheet;
+
+    /// A sheet with values, formulas, a name, a lock and some history.
+    fn loaded() -> ScriptedSheet {
+        ScriptedSheet::new(10, 10).run(&[
+            "A1=1",
+            "A2=2",
+            "A3=3",
+            "B1=SUM(A1:A3)",
+            "C1=A1*10",
+            "A1=4",
+            "name A1:A3 block",
+            "lock_cell C5",
+            "readonly on",
+        ])
+    }
+
+    #[test]
+    fn test_every_mutation_is_refused() {
+        let scripted = loaded();
+        let before = scripted.serialized();
+        let mutations = [
+            "A1=9",
+            "A1:A3=0",
+            "B1=A1+A2",
+            "delete A1",
+            "copy A1:A3 D1",
+            "move A1:A3 D1",
+            "transpose A1:A3 D1",
+            "sort A1:A3 desc",
+            "series A1:A3 5 1",
+            "replace 2 7",
+            "insert_row 1",
+            "delete_row 1",
+            "colformula D=A{row}*2",
+            "name B1:B2 other",
+            "lock_cell A1",
+            "unlock_cell C5",
+            "unlock_all",
+            "history A1 1",
+            "set history_limit 0",
+            "undo",
+            "recalc_all",
+            "clear_sheet",
+            "open other.sheet",
+            "reload!",
+            "session restore other.session",
+        ];
+        let mut scripted = scripted.assert_status_ok();
+        for command in mutations {
+            scripted = scripted
+                .run(&[command])
+                .assert_status(CommandStatus::ReadOnly);
+        }
+        assert_eq!(scripted.serialized(), before);
+        scripted
+            .assert_cell("A1", 4)
+            .assert_cell("B1", 9)
+            .run(&["show_history A1"])
+            .assert_output(&[
+                "#  value  formula",
+                "-  -----  -------",
+                "1      1",
+                "2  empty",
+            ]);
+    }
+
+    #[test]
+    fn test_browsing_still_works() {
+        let scripted = loaded()
+            .run(&["s", "scroll_to B1", "formula B1", "is_locked C5"])
+            .assert_status(CommandStatus::LockedCell)
+            .run(&["find 9", "deps A1", "count A1:A3", "set page_size 20"])
+            .assert_status_ok()
+            .run(&["dry A1=5"])
+            .assert_status(CommandStatus::ReadOnly);
+        assert_eq!(
+            (scripted.sheet.viewport_row, scripted.sheet.viewport_col),
+            (0, 1)
+        );
+    }
+
+    #[test]
+    fn test_direct_writes_are_refused() {
+        let mut scripted = loaded();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            set_cell_value(&mut scripted.sheet, 0, 0, "7", &mut sleep_time),
+            CommandStatus::ReadOnly
+        );
+        assert_eq!(
+            set_cell_to_value(
+                &mut scripted.sheet,
+                1,
+                0,
+                crate::cell::CellValue::Integer(7),
+                &mut sleep_time
+            ),
+            CommandStatus::ReadOnly
+        );
+        scripted
+            .assert_cell("A1", 4)
+            .assert_cell("A2", 2)
+            .run(&["readonly off", "A1=7"])
+            .assert_status_ok()
+            .assert_cell("B1", 12);
+    }
+
+    #[test]
+    fn test_readonly_flag() {
+        let mut args: Vec<String> = ["ferro", "--readonly", "10", "10"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert!(take_readonly_flag(&mut args));
+        assert_eq!(args, ["ferro", "10", "10"]);
+        assert!(!take_readonly_flag(&mut args));
+    }
+}
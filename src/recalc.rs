@@ -0,0 +1,487 @@
+//! Sheet-wide recalculation of every formula in dependency order.
+//!
+//! An edit only reevaluates the dependents of the changed cell, so values stored by a
+//! load or a repair can go stale. `recalc_all` orders all formula cells with Kahn's
+//! algorithm and reevaluates each once. Cells Kahn cannot order sit in or behind a
+//! cycle; each cycle (a strongly connected component) is reported and set to ERR, and
+//! the cells behind it are evaluated after it.
+
+use crate::cell::{CellValue, ErrorKind};
+use crate::graph::{Precedent, direct_precedents};
+use crate::reevaluate_topo::reevaluate_formula;
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::Instant;
+
+/// One step of a recalculation pass.
+///
+/// # Variants
+///
+/// * `Evaluate` - Reevaluate the formula of this cell.
+/// * `Cycle` - These cells form a cycle and are set to ERR.
+#[derive(Debug, PartialEq)]
+pub enum RecalcStep {
+    Evaluate(i32),
+    Cycle(Vec<i32>),
+}
+
+/// Outcome of a recalculation pass.
+///
+/// # Fields
+///
+/// * `evaluated` - Formula cells processed, cycle members included.
+/// * `changed` - `(key, stored, recalculated)` for every cell whose value differs.
+/// * `errors` - Cells whose recalculated value is ERR.
+/// * `cycles` - Members of each cycle found, sorted by key.
+#[derive(Debug, Default, PartialEq)]
+pub struct RecalcReport {
+    pub evaluated: usize,
+    pub changed: Vec<(i32, CellValue, CellValue)>,
+    pub errors: usize,
+    pub cycles: Vec<Vec<i32>>,
+}
+
+/// Formats a cell key as a reference such as "B3".
+fn key_name(sheet: &Spreadsheet, key: i32) -> String {
+    let (row, col) = sheet.get_row_col(key);
+    format!("{}{}", sheet.get_column_name(col), row + 1)
+}
+
+/// Builds the dependency graph between formula cells.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+///
+/// # Returns
+///
+/// * `BTreeMap<i32, Vec<i32>>` - Every formula cell mapped to the formula cells that read it
+pub fn formula_graph(sheet: &Spreadsheet) -> BTreeMap<i32, Vec<i32>> {
+    let formulas: HashSet<i32> = sheet
+        .cell_meta
+        .iter()
+        .filter(|(_, meta)| meta.formula != -1)
+        .map(|(&key, _)| key)
+        .collect();
+    let mut successors: BTreeMap<i32, Vec<i32>> =
+        formulas.iter().map(|&key| (key, Vec::new())).collect();
+
+    for &key in &formulas {
+        for precedent in direct_precedents(sheet, key) {
+            let parents: Vec<i32> = match precedent {
+                Precedent::Cell(parent) => vec![parent],
+                Precedent::Range(start, end) => {
+                    let (start_row, start_col) = sheet.get_row_col(start);
+                    let (end_row, end_col) = sheet.get_row_col(end);
+                    let size =
+                        (end_row - start_row + 1) as usize * (end_col - start_col + 1) as usize;
+                    // Walk whichever is smaller: the range or the set of formula cells
+                    if size <= formulas.len() {
+                        (start_row..=end_row)
+                            .flat_map(|row| (start_col..=end_col).map(move |col| (row, col)))
+                            .map(|(row, col)| sheet.get_key(row, col))
+                            .collect()
+                    } else {
+                        formulas
+                            .iter()
+                            .copied()
+                            .filter(|&cell| sheet.is_cell_in_range(cell, start, end))
+                            .collect()
+                    }
+                }
+            };
+            for parent in parents {
+                if let Some(list) = successors.get_mut(&parent) {
+                    list.push(key);
+                }
+            }
+        }
+    }
+    for list in successors.values_mut() {
+        list.sort_unstable();
+        list.dedup();
+    }
+    successors
+}
+
+/// Splits the given cells into strongly connected components (Tarjan's algorithm).
+///
+/// # Arguments
+///
+/// * `nodes` - The cells to split, in ascending key order.
+/// * `successors` - The dependency graph; edges leaving `nodes` are ignored.
+///
+/// # Returns
+///
+/// * `Vec<Vec<i32>>` - The components, each sorted, in topological order
+fn strongly_connected(nodes: &[i32], successors: &BTreeMap<i32, Vec<i32>>) -> Vec<Vec<i32>> {
+    let in_set: HashSet<i32> = nodes.iter().copied().collect();
+    let mut index: HashMap<i32, usize> = HashMap::new();
+    let mut lowlink: HashMap<i32, usize> = HashMap::new();
+    let mut on_stack: HashSet<i32> = HashSet::new();
+    let mut stack: Vec<i32> = Vec::new();
+    let mut components: Vec<Vec<i32>> = Vec::new();
+
+    for &root in nodes {
+        if index.contains_key(&root) {
+            continue;
+        }
+        // Explicit call stack of (node, next successor position) to avoid recursion
+        let mut calls: Vec<(i32, usize)> = vec![(root, 0)];
+        index.insert(root, index.len());
+        lowlink.insert(root, index[&root]);
+        stack.push(root);
+        on_stack.insert(root);
+
+        while let Some(&(node, position)) = calls.last() {
+            let next = successors.get(&node).and_then(|list| list.get(position));
+            if let Some(&succ) = next {
+                calls.last_mut().unwrap().1 += 1;
+                if !in_set.contains(&succ) {
+                    continue;
+                }
+                if !index.contains_key(&succ) {
+                    index.insert(succ, index.len());
+                    lowlink.insert(succ, index[&succ]);
+                    stack.push(succ);
+                    on_stack.insert(succ);
+                    calls.push((succ, 0));
+                } else if on_stack.contains(&succ) {
+                    let low = lowlink[&node].min(index[&succ]);
+                    lowlink.insert(node, low);
+                }
+                continue;
+            }
+
+            calls.pop();
+            if let Some(&(parent, _)) = calls.last() {
+                let low = lowlink[&parent].min(lowlink[&node]);
+                lowlink.insert(parent, low);
+            }
+            if lowlink[&node] == index[&node] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack.remove(&member);
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                component.sort_unstable();
+                components.push(component);
+            }
+        }
+    }
+
+    // Tarjan finishes a component only after everything it leads to
+    components.reverse();
+    components
+}
+
+/// Orders every formula cell of the sheet for recalculation.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+///
+/// # Returns
+///
+/// * `Vec<RecalcStep>` - Steps in an order where each cell comes after everything it reads
+pub fn recalc_order(sheet: &Spreadsheet) -> Vec<RecalcStep> {
+    order_formula_graph(&formula_graph(sheet))
+}
+
+/// Orders the cells of a formula graph with Kahn's algorithm, then splits what is left
+/// into cycles.
+///
+/// # Arguments
+///
+/// * `successors` - The graph, as built by `formula_graph`.
+///
+/// # Returns
+///
+/// * `Vec<RecalcStep>` - Steps in an order where each cell comes after everything it reads
+pub fn order_formula_graph(successors: &BTreeMap<i32, Vec<i32>>) -> Vec<RecalcStep> {
+    let mut in_degree: BTreeMap<i32, usize> = successors.keys().map(|&key| (key, 0)).collect();
+    for list in successors.values() {
+        for succ in list {
+            *in_degree.get_mut(succ).unwrap() += 1;
+        }
+    }
+
+    let mut queue: VecDeque<i32> = in_degree
+        .iter()
+        .filter(|&(_, &degree)| degree == 0)
+        .map(|(&key, _)| key)
+        .collect();
+    let mut steps = Vec::with_capacity(in_degree.len());
+    while let Some(key) = queue.pop_front() {
+        steps.push(RecalcStep::Evaluate(key));
+        for succ in &successors[&key] {
+            let degree = in_degree.get_mut(succ).unwrap();
+            *degree -= 1;
+            if *degree == 0 {
+                queue.push_back(*succ);
+            }
+        }
+    }
+
+    // Whatever Kahn could not order is in a cycle or depends on one
+    let leftover: Vec<i32> = in_degree
+        .iter()
+        .filter(|&(_, &degree)| degree > 0)
+        .map(|(&key, _)| key)
+        .collect();
+    for component in strongly_connected(&leftover, successors) {
+        let key = component[0];
+        if component.len() > 1 || successors[&key].contains(&key) {
+            steps.push(RecalcStep::Cycle(component));
+        } else {
+            steps.push(RecalcStep::Evaluate(key));
+        }
+    }
+    steps
+}
+
+/// Reevaluates every formula cell in dependency order.
+///
+/// With `write` unset the pass still evaluates in place, so later cells see the
+/// recalculated values of earlier ones, but every stored value is put back afterwards.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `write` - Keep the recalculated values.
+///
+/// # Returns
+///
+/// * `RecalcReport` - What the pass found
+pub fn recalculate_all(sheet: &mut Spreadsheet, write: bool) -> RecalcReport {
+    let mut report = RecalcReport::default();
+    let mut stored_values: Vec<(i32, CellValue)> = Vec::new();
+    // SLEEP formulas are recomputed, but the pass never sleeps
+    let mut sleep_time = 0.0;
+
+    for step in recalc_order(sheet) {
+        let mut evaluated: Vec<(i32, CellValue)> = Vec::new();
+        match step {
+            RecalcStep::Evaluate(key) => {
+                let stored = sheet.get_key_cell(key).clone();
+                let (row, col) = sheet.get_row_col(key);
+                reevaluate_formula(sheet, row, col, &mut sleep_time);
+                evaluated.push((key, stored));
+            }
+            RecalcStep::Cycle(members) => {
+                for &key in &members {
+                    let stored = std::mem::replace(
+                        sheet.get_mut_key_cell(key),
+                        CellValue::Error(ErrorKind::Cycle),
+                    );
+                    evaluated.push((key, stored));
+                }
+                report.cycles.push(members);
+            }
+        }
+
+        for (key, stored) in evaluated {
+            let value = sheet.get_key_cell(key).clone();
+            report.evaluated += 1;
+            if value.is_error() {
+                report.errors += 1;
+            }
+            if value != stored {
+                report.changed.push((key, stored.clone(), value));
+            }
+            stored_values.push((key, stored));
+        }
+    }
+
+    if !write {
+        for (key, stored) in stored_values {
+            *sheet.get_mut_key_cell(key) = stored;
+        }
+    } else if !report.changed.is_empty() {
+        sheet.dirty = true;
+    }
+    report
+}
+
+/// Handles `recalc_all [--verify]`, printing cycles, changed cells (with `--verify`),
+/// and a summary line.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet; output goes to its sink.
+/// * `args` - The text after `recalc_all`.
+///
+/// # Returns
+///
+/// * `CommandStatus` - `CmdOk`, or `Unrecognized` for unknown arguments
+pub fn handle_recalc_all(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let verify = match args.trim() {
+        "" => false,
+        "--verify" => true,
+        _ => return CommandStatus::Unrecognized,
+    };
+    let start = Instant::now();
+    let report = recalculate_all(sheet, !verify);
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let show = |value: &CellValue| match value {
+        CellValue::Integer(val) => val.to_string(),
+        CellValue::Error(kind) => kind.code(),
+        CellValue::Text(text) => format!("\"{}\"", text),
+        CellValue::Empty => "empty".to_string(),
+    };
+    let mut lines = Vec::new();
+    for members in &report.cycles {
+        let names: Vec<String> = members.iter().map(|&key| key_name(sheet, key)).collect();
+        lines.push(format!("Cycle: {}", names.join(", ")));
+    }
+    if verify {
+        for (key, stored, value) in &report.changed {
+            lines.push(format!(
+                "{}: stored {}, formula gives {}",
+                key_name(sheet, *key),
+                show(stored),
+                show(value)
+            ));
+        }
+    }
+    lines.push(format!(
+        "{} {} formula cell(s) in {:.3}s: {} {}, {} error(s)",
+        if verify { "Verified" } else { "Recalculated" },
+        report.evaluated,
+        elapsed,
+        report.changed.len(),
+        if verify { "would change" } else { "changed" },
+        report.errors
+    ));
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::handle_command;
+    use crate::graph::add_children;
+    use crate::output::OutputSink;
+    use crate::spreadsheet::CellMeta;
+
+    fn sheet_with(commands: &[&str]) -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        sheet.output = OutputSink::capture();
+        let mut sleep_time = 0.0;
+        for command in commands {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        sheet
+    }
+
+    /// B1 and D1 hold stale values; C1's stored value happens to be right.
+    fn stale_sheet() -> Spreadsheet {
+        let mut sheet = sheet_with(&["A1=2", "B1=A1*3", "C1=SUM(A1:B1)", "D1=C1+1"]);
+        *sheet.get_mut_cell(0, 1) = CellValue::Integer(99);
+        *sheet.get_mut_cell(0, 3) = CellValue::Integer(0);
+        sheet
+    }
+
+    /// Summary line without the elapsed time, which varies between runs.
+    fn summary(line: &str) -> String {
+        let (head, tail) = line.split_once(" in ").unwrap();
+        format!("{}:{}", head, tail.split_once(':').unwrap().1)
+    }
+
+    #[test]
+    fn test_recalc_all_corrects_stale_values() {
+        let mut sheet = stale_sheet();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "recalc_all", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        let lines = sheet.output.take();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(
+            summary(&lines[0]),
+            "Recalculated 3 formula cell(s): 2 changed, 0 error(s)"
+        );
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(6));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(8));
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(9));
+
+        // A second pass finds nothing to do
+        let report = recalculate_all(&mut sheet, true);
+        assert_eq!(report.evaluated, 3);
+        assert!(report.changed.is_empty());
+    }
+
+    #[test]
+    fn test_recalc_all_verify_does_not_write() {
+        let mut sheet = stale_sheet();
+        let before: Vec<CellValue> = sheet.cell_values().cloned().collect();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "recalc_all --verify", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        let lines = sheet.output.take();
+        assert_eq!(
+            lines[..2],
+            [
+                "B1: stored 99, formula gives 6",
+                "D1: stored 0, formula gives 9"
+            ]
+        );
+        assert_eq!(
+            summary(&lines[2]),
+            "Verified 3 formula cell(s): 2 would change, 0 error(s)"
+        );
+        assert!(sheet.cell_values().eq(before.iter()));
+        assert_eq!(
+            handle_command(&mut sheet, "recalc_all --fast", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_recalc_all_reports_cycles() {
+        let mut sheet = sheet_with(&["A1=1", "B1=A1+1", "C1=B1+1", "D1=C1*2", "E1=A1"]);
+        // Close the loop behind the evaluator's back, as a corrupted file could
+        let (a1, c1, d1, e1) = (0, 2, 3, 4);
+        sheet.cell_meta.insert(
+            a1,
+            CellMeta {
+                formula: 82,
+                parent1: c1,
+                parent2: 0,
+            },
+        );
+        add_children(&mut sheet, c1, 0, 82, 0, 0);
+
+        // The cells behind the cycle come after it, in either order
+        let order = recalc_order(&sheet);
+        assert_eq!(order[0], RecalcStep::Cycle(vec![0, 1, 2]));
+        assert_eq!(order.len(), 3);
+        assert!(order.contains(&RecalcStep::Evaluate(d1)));
+        assert!(order.contains(&RecalcStep::Evaluate(e1)));
+
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "recalc_all", &mut sleep_time);
+        let lines = sheet.output.take();
+        assert_eq!(lines[0], "Cycle: A1, B1, C1");
+        assert_eq!(
+            summary(&lines[1]),
+            "Recalculated 5 formula cell(s): 5 changed, 5 error(s)"
+        );
+        assert!(
+            sheet.grid[..5]
+                .iter()
+                .all(|value| *value == CellValue::Error(ErrorKind::Cycle))
+        );
+    }
+}
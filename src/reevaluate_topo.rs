@@ -1,8 +1,58 @@
-use crate::cell::CellValue;
-use crate::formula::{eval_avg, eval_max, eval_min, eval_variance, sum_value};
+use crate::aggregate::{
+    eval_avg, eval_counta, eval_countblank, eval_max, eval_median, eval_min, eval_product,
+    eval_spread, eval_variance, sum_if, sum_value,
+};
+use crate::cell::{
+    CellValue, ErrorKind, abs_value, checked_value, copied_value, divide_value, modulo_value,
+    power_value, round_to_multiple,
+};
+use crate::expr_parser::Comparison;
+use crate::extended_formula::eval_extended;
 use crate::spreadsheet::Spreadsheet;
 use std::collections::HashSet;
 
+/// Computes a comparison of two operands from the tens of its formula code.
+fn compare_value(msb: i16, left: i32, right: i32) -> CellValue {
+    Comparison::from_formula_msb(msb).map_or(CellValue::Error(ErrorKind::ParseErr), |op| {
+        op.apply(left, right)
+    })
+}
+
+/// Largest capacity the toposort scratch sets keep between calls; one huge pass should
+/// not make every later clear walk a huge table.
+const SCRATCH_KEEP: usize = 4096;
+
+/// Scratch collections for `toposort_reval_many`, kept on the sheet so assignments reuse
+/// them instead of allocating fresh ones every time.
+///
+/// # Fields
+///
+/// * `fully_visited` - Cells whose dependents have all been ordered.
+/// * `in_current_path` - Cells on the current DFS path, for cycle detection.
+/// * `order` - Dependents in reverse topological order.
+/// * `dfs_stack` - Pending cells, with whether they were already expanded.
+#[derive(Debug, Default)]
+pub struct TopoScratch {
+    fully_visited: HashSet<i32>,
+    in_current_path: HashSet<i32>,
+    order: Vec<i32>,
+    dfs_stack: Vec<(i32, bool)>,
+}
+
+impl TopoScratch {
+    /// Empties the collections, shrinking any that grew unusually large.
+    fn clear(&mut self) {
+        self.fully_visited.clear();
+        self.fully_visited.shrink_to(SCRATCH_KEEP);
+        self.in_current_path.clear();
+        self.in_current_path.shrink_to(SCRATCH_KEEP);
+        self.order.clear();
+        self.order.shrink_to(SCRATCH_KEEP);
+        self.dfs_stack.clear();
+        self.dfs_stack.shrink_to(SCRATCH_KEEP);
+    }
+}
+
 /// Sets a cell value and accumulates sleep time if positive.
 ///
 /// # Arguments
@@ -33,7 +83,10 @@ pub fn reevaluate_formula(sheet: &mut Spreadsheet, row: i16, col: i16, sleep_val
     if sheet.is_cell_locked(row, col) {
         return;
     }
-    let cell_meta = sheet.get_cell_meta(row, col);
+    // Read the formula without inserting an entry; only formula cells are reevaluated
+    let Some(&cell_meta) = sheet.cell_meta.get(&sheet.get_key(row, col)) else {
+        return;
+    };
     let rem = cell_meta.formula % 10;
     let msb = cell_meta.formula / 10;
     let parent1 = cell_meta.parent1;
@@ -41,96 +94,154 @@ pub fn reevaluate_formula(sheet: &mut Spreadsheet, row: i16, col: i16, sleep_val
 
     match rem {
         0 => {
-            let par1 = sheet.get_key_cell(parent1);
-            let par2 = sheet.get_key_cell(parent2);
-            if CellValue::Error == *par1 || CellValue::Error == *par2 {
-                *sheet.get_mut_cell(row, col) = CellValue::Error;
-                return;
-            }
-            if let CellValue::Integer(p1_value) = par1 {
-                if let CellValue::Integer(p2_value) = par2 {
-                    match msb {
-                        1 => {
-                            *sheet.get_mut_cell(row, col) = CellValue::Integer(p1_value + p2_value);
-                        }
-                        2 => {
-                            *sheet.get_mut_cell(row, col) = CellValue::Integer(p1_value - p2_value);
-                        }
-                        4 => {
-                            *sheet.get_mut_cell(row, col) = CellValue::Integer(p1_value * p2_value);
-                        }
-                        _ => {
-                            if *p2_value == 0 {
-                                *sheet.get_mut_cell(row, col) = CellValue::Error;
-                            } else {
-                                *sheet.get_mut_cell(row, col) =
-                                    CellValue::Integer(p1_value / p2_value);
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        2 => {
-            let par1 = sheet.get_key_cell(parent1);
-            if CellValue::Error == *par1 {
-                *sheet.get_mut_cell(row, col) = CellValue::Error;
-                return;
-            }
-            if let CellValue::Integer(p1_value) = par1 {
-                match msb {
+            // An error or text in either parent makes the result an error of the first
+            // failing parent's kind; empty reads as 0
+            match (
+                sheet.get_key_cell(parent1).number(),
+                sheet.get_key_cell(parent2).number(),
+            ) {
+                (Ok(p1_value), Ok(p2_value)) => match msb {
                     1 => {
-                        *sheet.get_mut_cell(row, col) = CellValue::Integer(p1_value + parent2);
+                        *sheet.get_mut_cell(row, col) =
+                            checked_value(p1_value.checked_add(p2_value));
                     }
                     2 => {
-                        *sheet.get_mut_cell(row, col) = CellValue::Integer(p1_value - parent2);
+                        *sheet.get_mut_cell(row, col) =
+                            checked_value(p1_value.checked_sub(p2_value));
                     }
                     4 => {
-                        *sheet.get_mut_cell(row, col) = CellValue::Integer(p1_value * parent2);
+                        *sheet.get_mut_cell(row, col) =
+                            checked_value(p1_value.checked_mul(p2_value));
                     }
-                    3 => {
-                        if parent2 == 0 {
-                            *sheet.get_mut_cell(row, col) = CellValue::Error;
-                        } else {
-                            *sheet.get_mut_cell(row, col) = CellValue::Integer(p1_value / parent2);
-                        }
+                    5 => {
+                        *sheet.get_mut_cell(row, col) = modulo_value(p1_value, p2_value);
                     }
-                    8 => {
-                        *sheet.get_mut_cell(row, col) = CellValue::Integer(*p1_value);
+                    6 => {
+                        *sheet.get_mut_cell(row, col) = power_value(p1_value, p2_value);
                     }
-                    _ => {
-                        sleep_fn(sheet, row, col, *p1_value, sleep_val);
+                    7 => {
+                        *sheet.get_mut_cell(row, col) = CellValue::Integer(p1_value.min(p2_value));
                     }
-                }
-            }
-        }
-        3 => {
-            let par2 = sheet.get_key_cell(parent2);
-            if CellValue::Error == *par2 {
-                *sheet.get_mut_cell(row, col) = CellValue::Error;
-                return;
-            }
-            if let CellValue::Integer(p2_value) = par2 {
-                match msb {
-                    1 => {
-                        *sheet.get_mut_cell(row, col) = CellValue::Integer(parent1 + p2_value);
+                    11 => {
+                        *sheet.get_mut_cell(row, col) = CellValue::Integer(p1_value.max(p2_value));
                     }
-                    2 => {
-                        *sheet.get_mut_cell(row, col) = CellValue::Integer(parent1 - p2_value);
+                    13 => {
+                        *sheet.get_mut_cell(row, col) = round_to_multiple(p1_value, p2_value);
                     }
-                    4 => {
-                        *sheet.get_mut_cell(row, col) = CellValue::Integer(parent1 * p2_value);
+                    14..=19 => {
+                        *sheet.get_mut_cell(row, col) = compare_value(msb, p1_value, p2_value);
                     }
                     _ => {
-                        if *p2_value == 0 {
-                            *sheet.get_mut_cell(row, col) = CellValue::Error;
-                        } else {
-                            *sheet.get_mut_cell(row, col) = CellValue::Integer(parent1 / p2_value);
-                        }
+                        *sheet.get_mut_cell(row, col) = divide_value(p1_value, p2_value);
                     }
+                },
+                (Err(kind), _) | (_, Err(kind)) => {
+                    *sheet.get_mut_cell(row, col) = CellValue::Error(kind)
                 }
             }
         }
+        // A plain reference copies the value as it is, text included
+        2 if msb == 8 => {
+            let value = copied_value(sheet.get_key_cell(parent1));
+            *sheet.get_mut_cell(row, col) = value;
+        }
+        2 => match sheet.get_key_cell(parent1).number() {
+            Ok(p1_value) => match msb {
+                1 => {
+                    *sheet.get_mut_cell(row, col) = checked_value(p1_value.checked_add(parent2));
+                }
+                2 => {
+                    *sheet.get_mut_cell(row, col) = checked_value(p1_value.checked_sub(parent2));
+                }
+                4 => {
+                    *sheet.get_mut_cell(row, col) = checked_value(p1_value.checked_mul(parent2));
+                }
+                3 => {
+                    *sheet.get_mut_cell(row, col) = divide_value(p1_value, parent2);
+                }
+                5 => {
+                    *sheet.get_mut_cell(row, col) = modulo_value(p1_value, parent2);
+                }
+                6 => {
+                    *sheet.get_mut_cell(row, col) = power_value(p1_value, parent2);
+                }
+                7 => {
+                    *sheet.get_mut_cell(row, col) = CellValue::Integer(p1_value.min(parent2));
+                }
+                11 => {
+                    *sheet.get_mut_cell(row, col) = CellValue::Integer(p1_value.max(parent2));
+                }
+                12 => {
+                    *sheet.get_mut_cell(row, col) = abs_value(p1_value);
+                }
+                13 => {
+                    *sheet.get_mut_cell(row, col) = round_to_multiple(p1_value, parent2);
+                }
+                14..=19 => {
+                    *sheet.get_mut_cell(row, col) = compare_value(msb, p1_value, parent2);
+                }
+                _ => {
+                    sleep_fn(sheet, row, col, p1_value, sleep_val);
+                }
+            },
+            Err(kind) => *sheet.get_mut_cell(row, col) = CellValue::Error(kind),
+        },
+        3 => match sheet.get_key_cell(parent2).number() {
+            Ok(p2_value) => match msb {
+                1 => {
+                    *sheet.get_mut_cell(row, col) = checked_value(parent1.checked_add(p2_value));
+                }
+                2 => {
+                    *sheet.get_mut_cell(row, col) = checked_value(parent1.checked_sub(p2_value));
+                }
+                4 => {
+                    *sheet.get_mut_cell(row, col) = checked_value(parent1.checked_mul(p2_value));
+                }
+                5 => {
+                    *sheet.get_mut_cell(row, col) = modulo_value(parent1, p2_value);
+                }
+                6 => {
+                    *sheet.get_mut_cell(row, col) = power_value(parent1, p2_value);
+                }
+                7 => {
+                    *sheet.get_mut_cell(row, col) = CellValue::Integer(parent1.min(p2_value));
+                }
+                11 => {
+                    *sheet.get_mut_cell(row, col) = CellValue::Integer(parent1.max(p2_value));
+                }
+                13 => {
+                    *sheet.get_mut_cell(row, col) = round_to_multiple(parent1, p2_value);
+                }
+                14..=19 => {
+                    *sheet.get_mut_cell(row, col) = compare_value(msb, parent1, p2_value);
+                }
+                _ => {
+                    *sheet.get_mut_cell(row, col) = divide_value(parent1, p2_value);
+                }
+            },
+            Err(kind) => *sheet.get_mut_cell(row, col) = CellValue::Error(kind),
+        },
+        4 => {
+            eval_extended(sheet, row, col, sleep_val);
+        }
+        5 if msb == 1 => {
+            eval_median(sheet, row, col, parent1, parent2);
+        }
+        5 if msb == 2 => {
+            sum_if(sheet, row, col, parent1, parent2);
+        }
+        5 if msb == 3 => {
+            eval_product(sheet, row, col, parent1, parent2);
+        }
+        5 if msb == 4 => {
+            eval_spread(sheet, row, col, parent1, parent2);
+        }
+        5 if msb == 5 => {
+            eval_counta(sheet, row, col, parent1, parent2);
+        }
+        5 if msb == 6 => {
+            eval_countblank(sheet, row, col, parent1, parent2);
+        }
         5 => {
             sum_value(sheet, row, col, parent1, parent2);
         }
@@ -171,11 +282,91 @@ pub fn toposort_reval_detect_cycle(
     sleep_val: &mut f64,
 ) -> bool {
     let cell_key = sheet.get_key(row, col);
-    // These collections will be used for the topological sort and cycle detection
-    let mut fully_visited: HashSet<i32> = HashSet::new();
-    let mut result: Vec<i32> = Vec::new();
-    let mut dfs_stack: Vec<(i32, bool)> = Vec::new();
-    let mut in_current_path: HashSet<i32> = HashSet::new();
+    toposort_reval_many(sheet, &[cell_key], sleep_val)
+}
+
+/// Reevaluates the dependents of several updated cells in a single topological pass.
+///
+/// Used by bulk operations so that a cell depending on many updated cells is
+/// recomputed once instead of once per update.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `cell_keys` - Keys of the updated cells.
+/// * `sleep_val` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `true` - If a cycle is detected.
+/// * `false` - If no cycle is detected.
+pub fn toposort_reval_many(
+    sheet: &mut Spreadsheet,
+    cell_keys: &[i32],
+    sleep_val: &mut f64,
+) -> bool {
+    // Borrow the sheet's scratch collections so repeated calls reuse their allocations
+    let mut scratch = std::mem::take(&mut sheet.topo_scratch);
+    scratch.clear();
+    let has_cycle = collect_dependents_order(sheet, cell_keys, &mut scratch);
+    if !has_cycle {
+        // Reevaluate in topological order (the order was collected in reverse)
+        for &key in scratch.order.iter().rev() {
+            if key >= 0 {
+                let (row, col) = sheet.get_row_col(key);
+                reevaluate_formula(sheet, row, col, sleep_val);
+            }
+        }
+    }
+    sheet.topo_scratch = scratch;
+    has_cycle
+}
+
+/// Lists every cell that depends on a cell, directly or through other cells.
+///
+/// Walks the dependents the same way reevaluation does; a cycle, which assignments never
+/// leave behind, cuts the walk short.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `cell_key` - The cell's key.
+///
+/// # Returns
+///
+/// * `Vec<i32>` - Keys of the dependents, sorted
+#[cfg(feature = "extensions")]
+pub fn transitive_dependents(sheet: &Spreadsheet, cell_key: i32) -> Vec<i32> {
+    let mut scratch = TopoScratch::default();
+    collect_dependents_order(sheet, &[cell_key], &mut scratch);
+    let mut dependents = scratch.order;
+    dependents.sort_unstable();
+    dependents
+}
+
+/// Collects the dependents of the updated cells in reverse topological order.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `cell_keys` - Keys of the updated cells.
+/// * `scratch` - Cleared scratch collections; the order is left in `scratch.order`.
+///
+/// # Returns
+///
+/// * `true` - If a cycle is detected.
+/// * `false` - If no cycle is detected.
+fn collect_dependents_order(
+    sheet: &Spreadsheet,
+    cell_keys: &[i32],
+    scratch: &mut TopoScratch,
+) -> bool {
+    let TopoScratch {
+        fully_visited,
+        in_current_path,
+        order,
+        dfs_stack,
+    } = scratch;
 
     // Helper to push all dependents (both direct and range-based) for a given cell key
     fn push_dependents(
@@ -193,34 +384,31 @@ pub fn toposort_reval_detect_cycle(
             }
         }
 
+        let (row, col) = sheet.get_row_col(cell_key);
         for range_child in &sheet.range_children {
-            if !fully_visited.contains(&range_child.child_key)
-                && sheet.is_cell_in_range(cell_key, range_child.start_key, range_child.end_key)
-            {
+            if !fully_visited.contains(&range_child.child_key) && range_child.reads(row, col) {
                 stack.push((range_child.child_key, false));
             }
         }
     }
 
-    // Start from all direct children and range-based children of the updated cell
-    push_dependents(cell_key, sheet, &mut dfs_stack, &fully_visited);
+    // Start from all direct children and range-based children of the updated cells
+    for &cell_key in cell_keys {
+        push_dependents(cell_key, sheet, dfs_stack, fully_visited);
+    }
 
     while let Some((current, expanded)) = dfs_stack.pop() {
         if expanded {
             // If we're processing a fully expanded node:
             in_current_path.remove(&current);
-            if !result.contains(&current) {
-                result.push(current);
+            // A cell reached along two paths is expanded twice but ordered once
+            if fully_visited.insert(current) {
+                order.push(current);
             }
-            fully_visited.insert(current);
         } else {
             // If we haven't expanded this node yet:
             if in_current_path.contains(&current) {
                 // Cycle detected
-                // Debugging output
-                // println!("Cycle detected at cell: {}", current);
-                // Uncomment the following line to see the cycle path
-                // println!("Cycle path: {:?}", in_current_path);
                 return true;
             }
 
@@ -229,18 +417,7 @@ pub fn toposort_reval_detect_cycle(
             in_current_path.insert(current);
 
             // Process all its dependents (both direct and range-based)
-            push_dependents(current, sheet, &mut dfs_stack, &fully_visited);
-        }
-    }
-
-    // Reverse the result to get the correct topological order
-    result.reverse();
-
-    // Now reevaluate all cells in the topological order
-    for key in result {
-        if key >= 0 {
-            let (row, col) = sheet.get_row_col(key);
-            reevaluate_formula(sheet, row, col, sleep_val);
+            push_dependents(current, sheet, dfs_stack, fully_visited);
         }
     }
 
@@ -438,7 +615,7 @@ mod tests {
 
         let mut sleep_time = 0.0;
         reevaluate_formula(&mut sheet, 1, 1, &mut sleep_time);
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error); // 3 + 7 = 10
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero)); // 3 + 7 = 10
     }
     #[test]
     fn test_reevaluate_formula_with_constant5() {
@@ -552,14 +729,14 @@ mod tests {
 
         let mut sleep_time = 0.0;
         reevaluate_formula(&mut sheet, 1, 1, &mut sleep_time);
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error); // Division by zero
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero)); // Division by zero
     }
 
     #[test]
     fn test_reevaluate_formula_remainder1_error_reference() {
         let mut sheet = create_test_spreadsheet(5, 5);
         // Parent 2 contains an error
-        *sheet.get_mut_cell(0, 1) = CellValue::Error;
+        *sheet.get_mut_cell(0, 1) = CellValue::Error(ErrorKind::Overflow);
 
         {
             let key2 = sheet.get_key(0, 1);
@@ -571,7 +748,7 @@ mod tests {
 
         let mut sleep_time = 0.0;
         reevaluate_formula(&mut sheet, 1, 1, &mut sleep_time);
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error); // Propagates error
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::Overflow)); // Propagates error
     }
 
     #[test]
@@ -710,13 +887,13 @@ mod tests {
         meta.formula = 30; // Division
         let mut sleep_time = 0.0;
         reevaluate_formula(&mut sheet, 1, 1, &mut sleep_time);
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero));
     }
 
     #[test]
     fn test_reevaluate_formula_error() {
         let mut sheet = create_test_spreadsheet(5, 5);
-        *sheet.get_mut_cell(0, 0) = CellValue::Error;
+        *sheet.get_mut_cell(0, 0) = CellValue::Error(ErrorKind::Overflow);
         let key1 = sheet.get_key(0, 0);
         let key2 = sheet.get_key(0, 1);
         let meta = sheet.get_cell_meta(1, 1);
@@ -725,7 +902,7 @@ mod tests {
         meta.formula = 10; // Addition
         let mut sleep_time = 0.0;
         reevaluate_formula(&mut sheet, 1, 1, &mut sleep_time);
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::Overflow));
     }
 
     #[test]
@@ -794,6 +971,40 @@ mod tests {
         meta.formula = 30; // Division
         let mut sleep_time = 0.0;
         reevaluate_formula(&mut sheet, 1, 1, &mut sleep_time);
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Error(ErrorKind::DivZero));
+    }
+
+    #[test]
+    fn test_range_cycles_on_non_square_sheets() {
+        use crate::cell::parse_cell_reference;
+        // On a narrow sheet C1 lies between the keys of A1 and B2 without being in A1:B2
+        let cases = [
+            ("B1", "SUM(A1:A5)", CommandStatus::CmdOk),
+            ("A3", "B1", CommandStatus::CircularRef),
+            ("C7", "SUM(A1:A5)", CommandStatus::CmdOk),
+            ("A9", "C7", CommandStatus::CmdOk),
+            ("C1", "SUM(A1:B2)", CommandStatus::CmdOk),
+            ("C2", "C1", CommandStatus::CmdOk),
+            ("B2", "C2", CommandStatus::CircularRef),
+            ("A6", "MAX(B1:C5)", CommandStatus::CmdOk),
+            ("C3", "A6", CommandStatus::CircularRef),
+            ("C6", "A6", CommandStatus::CmdOk),
+        ];
+        for (rows, cols) in [(10, 3), (12, 4), (9, 7), (30, 26), (9, 200)] {
+            let mut sheet = create_test_spreadsheet(rows, cols);
+            let mut sleep_time = 0.0;
+            for &(cell, expr, ref expected) in &cases {
+                let (row, col) = parse_cell_reference(&sheet, cell).unwrap();
+                assert_eq!(
+                    set_cell_value(&mut sheet, row, col, expr, &mut sleep_time),
+                    *expected,
+                    "{}={} on a {}x{} sheet",
+                    cell,
+                    expr,
+                    rows,
+                    cols
+                );
+            }
+        }
     }
 }
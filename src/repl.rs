@@ -0,0 +1,484 @@
+//! The interactive command loop and what happens when it ends.
+//!
+//! The loop reads from any `BufRead`, so piped input and tests drive the same code as a
+//! terminal. Quitting with `q` and reaching the end of input both finish through
+//! `finish_session`, which reports a summary and decides the exit code.
+
+#[cfg(feature = "extensions")]
+use crate::autosave;
+#[cfg(feature = "extensions")]
+use crate::overwrite::resolve_pending_overwrite;
+use crate::process_command::{
+    CommandTiming, expression_hint, multi_command_hint, process_command_timed, split_commands,
+    text_hint,
+};
+use crate::prompt::prompt_line;
+#[cfg(feature = "extensions")]
+use crate::save_load::save_tracked;
+#[cfg(feature = "extensions")]
+use crate::spreadsheet::CellCounts;
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+#[cfg(feature = "extensions")]
+use crate::tutorial::{Tutorial, TutorialEvent};
+use std::io::{self, BufRead, Write};
+use std::time::Instant;
+
+/// File offered when saving a sheet that has no current file.
+#[cfg(feature = "extensions")]
+pub const DEFAULT_FILENAME: &str = "rust_spreadsheet.sheet";
+
+/// Settings for a REPL session.
+///
+/// # Fields
+///
+/// * `save_on_exit` - File to save the sheet to when the session ends.
+#[derive(Debug, Default)]
+pub struct SessionOptions {
+    #[cfg(feature = "extensions")]
+    pub save_on_exit: Option<String>,
+}
+
+/// What a session did, reported when it ends.
+///
+/// # Fields
+///
+/// * `commands` - Commands run; each `;`-separated part counts once.
+/// * `failures` - Commands that did not return `ok`.
+/// * `elapsed` - Seconds from the start of the session to its end.
+#[derive(Debug, Default, PartialEq)]
+pub struct SessionSummary {
+    pub commands: usize,
+    pub failures: usize,
+    pub elapsed: f64,
+}
+
+impl SessionSummary {
+    /// Returns the exit code: 0 if every command succeeded, 1 if any failed.
+    pub fn exit_code(&self) -> i32 {
+        if self.failures == 0 { 0 } else { 1 }
+    }
+
+    /// Formats the summary, e.g. "Session: 12 command(s), 2 failed, 1.3s".
+    pub fn line(&self) -> String {
+        format!(
+            "Session: {} command(s), {} failed, {:.1}s",
+            self.commands, self.failures, self.elapsed
+        )
+    }
+
+    /// Counts one command and whether it failed.
+    fn record(&mut self, status: &CommandStatus) {
+        self.commands += 1;
+        if *status != CommandStatus::CmdOk {
+            self.failures += 1;
+        }
+    }
+}
+
+/// Removes a flag and its value from the command-line arguments.
+///
+/// # Arguments
+///
+/// * `args` - The command-line arguments; the flag and its value are removed.
+/// * `flag` - The flag, e.g. `--save-on-exit`.
+///
+/// # Returns
+///
+/// * `Some(String)` - The value given with the flag.
+/// * `None` - If the flag is absent or has no value
+#[cfg(feature = "extensions")]
+pub fn take_flag_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let position = args.iter().position(|arg| arg == flag)?;
+    if position + 1 >= args.len() {
+        args.remove(position);
+        return None;
+    }
+    let value = args.remove(position + 1);
+    args.remove(position);
+    Some(value)
+}
+
+/// Reads one line, treating a read error like the end of input.
+///
+/// # Returns
+///
+/// * `Some(String)` - The line, without surrounding whitespace.
+/// * `None` - At the end of input
+fn read_trimmed<R: BufRead>(input: &mut R) -> Option<String> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim().to_string()),
+    }
+}
+
+/// Asks whether to save before quitting and saves if the user agrees.
+///
+/// # Returns
+///
+/// * `CommandStatus` - `CmdOk` if the sheet was saved or the user declined
+#[cfg(feature = "extensions")]
+fn offer_save<R: BufRead>(sheet: &mut Spreadsheet, input: &mut R) -> CommandStatus {
+    print!("Do you want to save the spreadsheet before quitting? (y/n): ");
+    io::stdout().flush().unwrap(); // Ensure the prompt is shown
+    if read_trimmed(input).as_deref() != Some("y") {
+        return CommandStatus::CmdOk;
+    }
+    // Ask for the filename to save, offering the current file first, then the autosave file
+    let default_filename = sheet
+        .current_file
+        .clone()
+        .or_else(|| {
+            sheet
+                .autosave
+                .as_ref()
+                .map(|autosave| autosave.file.clone())
+        })
+        .unwrap_or_else(|| DEFAULT_FILENAME.to_string());
+    print!("Enter filename to save (default: {}): ", default_filename);
+    io::stdout().flush().unwrap(); // Ensure the prompt is shown
+    let filename = read_trimmed(input).unwrap_or_default();
+    // Use the default filename if the user didn't enter anything
+    let save_filename = if filename.is_empty() {
+        default_filename.as_str()
+    } else {
+        filename.as_str()
+    };
+    save_tracked(sheet, save_filename, false)
+}
+
+/// Runs the command loop until `q` or the end of input, then finishes the session.
+///
+/// Blank lines are ignored. Each `;`-separated command is counted on its own.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `input` - Where commands are read from.
+/// * `options` - Session settings.
+/// * `startup_time` - Seconds spent creating the sheet, shown in the first prompt.
+///
+/// # Returns
+///
+/// * `(SessionSummary, i32)` - What the session did and the process exit code
+pub fn run_session<R: BufRead>(
+    sheet: &mut Spreadsheet,
+    input: &mut R,
+    options: &SessionOptions,
+    startup_time: f64,
+) -> (SessionSummary, i32) {
+    let start = Instant::now();
+    let mut summary = SessionSummary::default();
+    let mut timing = CommandTiming {
+        elapsed: startup_time,
+        sleep: 0.0,
+    };
+    let mut last_status = "ok"; // Placeholder for last status
+    let mut last_message: Option<String> = None;
+    // The command the last message is about, so only the same failure counts as a repeat
+    let mut last_command = String::new();
+    // What the prompt shows for the last outcome, once repeats are coalesced
+    let mut shown_message: Option<String> = None;
+    let mut new_outcome = false;
+    #[cfg(feature = "extensions")]
+    let mut tutorial: Option<Tutorial> = None;
+    #[cfg(feature = "extensions")]
+    let mut prompt_counts: Option<CellCounts> = None;
+    // Main loop for command input
+    loop {
+        // Page any long listing the last command produced before the grid is drawn
+        #[cfg(feature = "extensions")]
+        let _ = sheet.output.page_pending(input, &mut io::stdout());
+
+        // Print the spreadsheet
+        sheet.print_spreadsheet();
+
+        #[cfg(feature = "extensions")]
+        let stats = sheet.prompt_stats_label(&mut prompt_counts);
+        #[cfg(not(feature = "extensions"))]
+        let stats: Option<String> = None;
+        // An empty line shows the same prompt again without counting as a repeat
+        if new_outcome {
+            shown_message =
+                report_outcome(sheet, last_status, &last_command, last_message.as_deref());
+            new_outcome = false;
+        }
+        print!(
+            "{}",
+            prompt_line(
+                timing.elapsed,
+                timing.sleep,
+                last_status,
+                shown_message.as_deref(),
+                stats.as_deref()
+            )
+        );
+        io::stdout().flush().unwrap(); // Ensure the prompt is shown
+
+        let Some(trimmed) = read_trimmed(input) else {
+            // End of input, also where a closed terminal leaves the loop
+            break;
+        };
+        if trimmed.is_empty() {
+            continue;
+        }
+        new_outcome = true;
+
+        #[cfg(feature = "extensions")]
+        // While the tutorial is running it handles every command
+        if let Some(active) = tutorial.as_mut() {
+            let mut elapsed = 0.0;
+            let (tutorial_status, event) = active.feed(sheet, &trimmed, &mut elapsed);
+            timing = CommandTiming {
+                elapsed,
+                sleep: 0.0,
+            };
+            match event {
+                TutorialEvent::Advanced => println!("Well done!\n{}", active.instruction()),
+                TutorialEvent::Retry => println!("Not quite. {}", active.instruction()),
+                TutorialEvent::Hint(hint) => println!("Hint: {}", hint),
+                TutorialEvent::Finished => {
+                    println!("Tutorial complete! Back to your own sheet.");
+                    tutorial = None;
+                }
+                TutorialEvent::Quit => {
+                    println!("Tutorial closed. Back to your own sheet.");
+                    tutorial = None;
+                }
+            }
+            summary.record(&tutorial_status);
+            last_status = tutorial_status.as_str();
+            last_message = (tutorial_status != CommandStatus::CmdOk)
+                .then(|| tutorial_status.message().to_string());
+            last_command = trimmed;
+            continue;
+        }
+
+        #[cfg(feature = "extensions")]
+        if trimmed == "tutorial" {
+            let started = Tutorial::start(sheet);
+            println!("Tutorial started. Type 'tutorial quit' to leave at any time.");
+            println!("{}", started.instruction());
+            tutorial = Some(started);
+            last_status = "ok";
+            last_message = None;
+            continue;
+        }
+
+        if trimmed == "q" {
+            // Ask about saving unless the sheet is saved on exit anyway, and stay in
+            // the loop rather than lose work if the save is refused
+            #[cfg(feature = "extensions")]
+            if options.save_on_exit.is_none() {
+                let status = offer_save(sheet, input);
+                if status != CommandStatus::CmdOk {
+                    last_status = status.as_str();
+                    last_message = Some(status.message().to_string());
+                    last_command = trimmed;
+                    continue;
+                }
+            }
+            break;
+        }
+
+        // Run each `;`-separated command; a failure does not stop the rest, and the
+        // prompt reports the last one that failed
+        timing = CommandTiming::default();
+        last_status = "ok";
+        last_message = None;
+        for command in split_commands(&trimmed) {
+            let (status, command_timing) = process_command_timed(sheet, command);
+            timing.elapsed += command_timing.elapsed;
+            timing.sleep += command_timing.sleep;
+            // A bulk write waiting for overwrite answers is applied before the next command
+            #[cfg(feature = "extensions")]
+            let status =
+                resolve_pending_overwrite(sheet, input, &mut io::stdout(), &mut timing.sleep)
+                    .unwrap_or(status);
+            summary.record(&status);
+            #[cfg(feature = "extensions")]
+            autosave::after_command(sheet, command, &status);
+            if status != CommandStatus::CmdOk {
+                last_status = status.as_str();
+                last_command = command.to_string();
+                // Unrecognized assignments say where the expression went wrong
+                last_message = match status {
+                    CommandStatus::Unrecognized => text_hint(command)
+                        .or_else(|| multi_command_hint(command).map(str::to_string))
+                        .or_else(|| expression_hint(command)),
+                    _ => None,
+                }
+                .or_else(|| Some(status.message().to_string()));
+            }
+        }
+    }
+
+    let code = finish_session(sheet, &mut summary, options, start);
+    (summary, code)
+}
+
+/// Decides what the prompt shows for a command's outcome.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, whose output sink coalesces repeated failures.
+/// * `status` - The command's status name.
+/// * `command` - The command the message is about.
+/// * `message` - The failure message; `None` after success.
+///
+/// # Returns
+///
+/// * `Option<String>` - The text to show above the prompt
+#[cfg(feature = "extensions")]
+fn report_outcome(
+    sheet: &mut Spreadsheet,
+    status: &str,
+    command: &str,
+    message: Option<&str>,
+) -> Option<String> {
+    sheet.output.report(status, command, message)
+}
+
+/// Decides what the prompt shows for a command's outcome: the message as it is.
+#[cfg(not(feature = "extensions"))]
+fn report_outcome(
+    _sheet: &mut Spreadsheet,
+    _status: &str,
+    _command: &str,
+    message: Option<&str>,
+) -> Option<String> {
+    message.map(str::to_string)
+}
+
+/// Ends a session: autosaves and saves if asked to, flushes output, and prints the
+/// summary to stderr.
+///
+/// Both `q` and the end of input come here, so they report the same summary and exit
+/// code, and pending autosave changes are written whether or not the user saved when
+/// asked. A failed save on exit counts as a failed command.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `summary` - The session's counts; updated if the save fails.
+/// * `options` - Session settings.
+/// * `start` - When the session started.
+///
+/// # Returns
+///
+/// * `i32` - The process exit code, nonzero if any command failed
+pub fn finish_session(
+    sheet: &mut Spreadsheet,
+    summary: &mut SessionSummary,
+    options: &SessionOptions,
+    start: Instant,
+) -> i32 {
+    // Piped output still owes the count of a run of failures that ended the session
+    #[cfg(feature = "extensions")]
+    if let Some(line) = sheet.output.finish_repeats() {
+        println!("{}", line);
+    }
+    #[cfg(feature = "extensions")]
+    autosave::at_session_end(sheet);
+    #[cfg(feature = "extensions")]
+    if let Some(file) = &options.save_on_exit {
+        let status = save_tracked(sheet, file, false);
+        if status != CommandStatus::CmdOk {
+            eprintln!("Could not save '{}' on exit ({})", file, status.as_str());
+        }
+        summary.record(&status);
+    }
+    #[cfg(not(feature = "extensions"))]
+    let _ = (sheet, options);
+
+    summary.elapsed = start.elapsed().as_secs_f64();
+    println!();
+    let _ = io::stdout().flush();
+    eprintln!("{}", summary.line());
+    summary.exit_code()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::CellValue;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_summary_line_and_exit_code() {
+        let mut summary = SessionSummary::default();
+        assert_eq!(summary.exit_code(), 0);
+        summary.record(&CommandStatus::CmdOk);
+        summary.record(&CommandStatus::Unrecognized);
+        summary.elapsed = 1.25;
+        assert_eq!(summary.line(), "Session: 2 command(s), 1 failed, 1.2s");
+        assert_eq!(summary.exit_code(), 1);
+    }
+
+    #[test]
+    fn test_end_of_input_reports_failures() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut input = Cursor::new("A1=5\n\nB1=A1+\nC1=A1*2; D1=C1+1\n");
+        let (summary, code) = run_session(&mut sheet, &mut input, &SessionOptions::default(), 0.0);
+        assert_eq!((summary.commands, summary.failures), (4, 1));
+        assert_eq!(code, 1);
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(11));
+
+        let mut input = Cursor::new("A1=1\nq\nB1=2\n");
+        let (summary, code) = run_session(&mut sheet, &mut input, &SessionOptions::default(), 0.0);
+        assert_eq!((summary.commands, summary.failures), (1, 0));
+        assert_eq!(code, 0);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Empty);
+
+        // `q` reports failures the same way as the end of input
+        let mut input = Cursor::new("A1=2\nB1=A1+\nq\nn\n");
+        let (summary, code) = run_session(&mut sheet, &mut input, &SessionOptions::default(), 0.0);
+        assert_eq!((summary.commands, summary.failures), (2, 1));
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_save_on_exit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("exit.sheet");
+        let options = SessionOptions {
+            save_on_exit: Some(path.display().to_string()),
+        };
+
+        // Reaching the end of input saves
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut input = Cursor::new("A1=4\nB1=A1*2\n");
+        let (summary, code) = run_session(&mut sheet, &mut input, &options, 0.0);
+        assert_eq!(code, 0);
+        assert_eq!(summary.commands, 3);
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("CELL,B1,8,FORMULA"));
+
+        // So does `q`, without asking
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut input = Cursor::new("A1=9\nq\n");
+        let (_, code) = run_session(&mut sheet, &mut input, &options, 0.0);
+        assert_eq!(code, 0);
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("CELL,A1,9")
+        );
+
+        // A save that fails makes the exit code nonzero
+        let options = SessionOptions {
+            save_on_exit: Some(
+                dir.path()
+                    .join("missing")
+                    .join("x.sheet")
+                    .display()
+                    .to_string(),
+            ),
+        };
+        let mut input = Cursor::new("A1=1\n");
+        let (summary, code) = run_session(&mut sheet, &mut input, &options, 0.0);
+        assert_eq!(summary.failures, 1);
+        assert_eq!(code, 1);
+    }
+}
@@ -0,0 +1,591 @@
+//! Inserting and deleting whole rows with `insert_row <n>` and `delete_row <n>`.
+//!
+//! The sheet keeps its size: rows from the insertion point move down one and the last
+//! row falls off, or rows below a deleted row move up one and an empty row appears at the
+//! bottom. Everything that names cells by position moves with them, so formulas keep
+//! reading the same data. A range spanning the row grows or shrinks with it, so
+//! `SUM(A1:A10)` becomes `SUM(A1:A11)` or `SUM(A1:A9)`. A formula that reads a cell of a
+//! deleted row, or a range lying wholly inside it, loses its formula and holds `#REF`, as
+//! does a `SUMPRODUCT` whose two ranges would no longer have the same shape.
+
+use crate::cell::{CellValue, ErrorKind};
+use crate::extended_formula::ExtendedFormula;
+use crate::graph::parents_are_cells;
+use crate::range::Range;
+use crate::reevaluate_topo::{reevaluate_formula, toposort_reval_many};
+use crate::spreadsheet::{CommandStatus, HighlightType, Spreadsheet};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+/// Where rows move when one is inserted or deleted.
+///
+/// # Fields
+///
+/// * `row` - Zero-based row inserted before, or deleted.
+/// * `insert` - True for an insertion.
+/// * `rows` - Rows in the sheet.
+/// * `cols` - Columns in the sheet.
+#[derive(Debug, Clone, Copy)]
+struct RowShift {
+    row: i16,
+    insert: bool,
+    rows: i16,
+    cols: i16,
+}
+
+impl RowShift {
+    /// The row whose contents leave the sheet: the deleted row, or the last row on insertion.
+    fn lost_row(self) -> i16 {
+        if self.insert { self.rows - 1 } else { self.row }
+    }
+
+    /// Returns where a row ends up, or `None` if it leaves the sheet.
+    fn shift_row(self, row: i16) -> Option<i16> {
+        match (self.insert, row.cmp(&self.row)) {
+            (_, Ordering::Less) => Some(row),
+            (true, _) => (row + 1 < self.rows).then_some(row + 1),
+            (false, Ordering::Equal) => None,
+            (false, Ordering::Greater) => Some(row - 1),
+        }
+    }
+
+    /// Returns where a cell ends up, or `None` if it leaves the sheet.
+    fn shift_key(self, key: i32) -> Option<i32> {
+        let cols = self.cols as i32;
+        let row = self.shift_row((key / cols) as i16)?;
+        Some(row as i32 * cols + key % cols)
+    }
+
+    /// Returns where a range ends up: it grows or shrinks when it spans the row, and is
+    /// `None` when none of its rows are left.
+    fn shift_range(self, range: Range) -> Option<Range> {
+        let (start_row, end_row) = if self.insert {
+            let down = |row: i16| row + (row >= self.row) as i16;
+            (
+                down(range.start_row),
+                down(range.end_row).min(self.rows - 1),
+            )
+        } else {
+            (
+                range.start_row - (range.start_row > self.row) as i16,
+                range.end_row - (range.end_row >= self.row) as i16,
+            )
+        };
+        (start_row <= end_row).then_some(Range {
+            start_row,
+            end_row,
+            ..range
+        })
+    }
+
+    /// Shifts a range stored as its corner keys.
+    fn shift_corners(self, start_key: i32, end_key: i32) -> Option<(i32, i32)> {
+        let range = self.shift_range(corners_range(self.cols, start_key, end_key))?;
+        let cols = self.cols as i32;
+        Some((
+            range.start_row as i32 * cols + range.start_col as i32,
+            range.end_row as i32 * cols + range.end_col as i32,
+        ))
+    }
+}
+
+/// Builds the range between two corner keys.
+fn corners_range(cols: i16, start_key: i32, end_key: i32) -> Range {
+    let cols = cols as i32;
+    Range {
+        start_row: (start_key / cols) as i16,
+        start_col: (start_key % cols) as i16,
+        end_row: (end_key / cols) as i16,
+        end_col: (end_key % cols) as i16,
+    }
+}
+
+/// Checks whether a formula reads a cell of `row`, or a range lying wholly inside it.
+fn reads_row(sheet: &Spreadsheet, key: i32, row: i16) -> bool {
+    let Some(meta) = sheet.cell_meta.get(&key) else {
+        return false;
+    };
+    let in_row = |cell: i32| sheet.get_row_col(cell).0 == row;
+    let range_in_row = |range: Range| range.start_row == row && range.end_row == row;
+    match meta.formula % 10 {
+        _ if meta.formula == -1 => false,
+        0 => {
+            parents_are_cells(&sheet.children, key, meta)
+                && (in_row(meta.parent1) || in_row(meta.parent2))
+        }
+        2 => in_row(meta.parent1),
+        3 => in_row(meta.parent2),
+        4 => sheet.extended_formulas.get(&key).is_some_and(|formula| {
+            formula.cells().into_iter().any(in_row)
+                || formula.ranges().into_iter().any(range_in_row)
+        }),
+        _ => range_in_row(corners_range(sheet.cols, meta.parent1, meta.parent2)),
+    }
+}
+
+/// Checks whether a `SUMPRODUCT` would be left with ranges of different shapes, which it
+/// cannot pair up cell for cell, because only one of them spans the shift point.
+fn splits_sum_product(sheet: &Spreadsheet, key: i32, shift: RowShift) -> bool {
+    let Some(ExtendedFormula::SumProduct(first, _, second, _)) = sheet.extended_formulas.get(&key)
+    else {
+        return false;
+    };
+    let height = |range: Range| {
+        let range = shift.shift_range(range).unwrap_or(range);
+        range.end_row - range.start_row
+    };
+    height(*first) != height(*second)
+}
+
+/// Checks whether a range shift changes how many rows a range covers.
+fn resized(before: Range, after: Option<Range>) -> bool {
+    after.is_some_and(|after| after.end_row - after.start_row != before.end_row - before.start_row)
+}
+
+/// Moves every stored reference to its new position.
+///
+/// # Returns
+///
+/// * `Vec<i32>` - Range formulas, at their new keys, whose ranges grew or shrank
+fn remap_references(sheet: &mut Spreadsheet, shift: RowShift) -> Vec<i32> {
+    let mut resized_keys = Vec::new();
+    let children = std::mem::take(&mut sheet.children);
+
+    for (key, mut meta) in std::mem::take(&mut sheet.cell_meta) {
+        let Some(new_key) = shift.shift_key(key) else {
+            continue;
+        };
+        let keep = |cell: i32| shift.shift_key(cell).unwrap_or(cell);
+        match meta.formula % 10 {
+            _ if meta.formula == -1 => {}
+            0 if parents_are_cells(&children, key, &meta) => {
+                meta.parent1 = keep(meta.parent1);
+                meta.parent2 = keep(meta.parent2);
+            }
+            0 | 4 => {}
+            2 => meta.parent1 = keep(meta.parent1),
+            3 => meta.parent2 = keep(meta.parent2),
+            _ => {
+                let before = corners_range(shift.cols, meta.parent1, meta.parent2);
+                if resized(before, shift.shift_range(before)) {
+                    resized_keys.push(new_key);
+                }
+                if let Some((start_key, end_key)) = shift.shift_corners(meta.parent1, meta.parent2)
+                {
+                    meta.parent1 = start_key;
+                    meta.parent2 = end_key;
+                }
+            }
+        }
+        sheet.cell_meta.insert(new_key, meta);
+    }
+
+    sheet.children = children
+        .into_iter()
+        .filter_map(|(parent, set)| {
+            let set: HashSet<i32> = set
+                .into_iter()
+                .filter_map(|child| shift.shift_key(child))
+                .collect();
+            Some((shift.shift_key(parent)?, set)).filter(|(_, set)| !set.is_empty())
+        })
+        .collect();
+    sheet.range_children.retain_mut(|child| {
+        match (
+            shift.shift_range(child.range),
+            shift.shift_key(child.child_key),
+        ) {
+            (Some(range), Some(child_key)) => {
+                child.range = range;
+                child.child_key = child_key;
+                true
+            }
+            _ => false,
+        }
+    });
+
+    sheet.extended_formulas = std::mem::take(&mut sheet.extended_formulas)
+        .into_iter()
+        .filter_map(|(key, mut formula)| {
+            let new_key = shift.shift_key(key)?;
+            if formula
+                .ranges()
+                .into_iter()
+                .any(|range| resized(range, shift.shift_range(range)))
+            {
+                resized_keys.push(new_key);
+            }
+            // References follow their rows; their relative and pinned flags stay
+            formula.remap_cells(|cell, _| shift.shift_key(cell).unwrap_or(cell));
+            formula.remap_ranges(|range, _| shift.shift_range(range).unwrap_or(range));
+            Some((new_key, formula))
+        })
+        .collect();
+
+    fn remap_map<V>(map: &mut HashMap<i32, V>, shift: RowShift) {
+        *map = std::mem::take(map)
+            .into_iter()
+            .filter_map(|(key, value)| Some((shift.shift_key(key)?, value)))
+            .collect();
+    }
+    remap_map(&mut sheet.criteria, shift);
+    remap_map(&mut sheet.relative_refs, shift);
+    remap_map(&mut sheet.cell_history, shift);
+    sheet.assigned = std::mem::take(&mut sheet.assigned)
+        .into_iter()
+        .filter_map(|key| shift.shift_key(key))
+        .collect();
+
+    sheet.named_ranges = std::mem::take(&mut sheet.named_ranges)
+        .into_iter()
+        .filter_map(|(name, range)| Some((name, shift.shift_range(range)?)))
+        .collect();
+    sheet.locked_ranges = std::mem::take(&mut sheet.locked_ranges)
+        .into_iter()
+        .filter_map(|range| shift.shift_range(range))
+        .collect();
+    sheet
+        .ownership
+        .tags
+        .retain_mut(|tag| match shift.shift_range(tag.range) {
+            Some(range) => {
+                tag.range = range;
+                true
+            }
+            None => false,
+        });
+    sheet
+        .column_formulas
+        .retain(|_, formula| match formula.rows {
+            Some((first, last)) => {
+                match shift.shift_range(Range {
+                    start_row: first,
+                    start_col: 0,
+                    end_row: last,
+                    end_col: 0,
+                }) {
+                    Some(range) => {
+                        formula.rows = Some((range.start_row, range.end_row));
+                        true
+                    }
+                    None => false,
+                }
+            }
+            None => true,
+        });
+
+    sheet.last_edited = sheet
+        .last_edited
+        .and_then(|(row, col)| Some((shift.shift_row(row)?, col)));
+    match (sheet.highlight_cell >= 0)
+        .then(|| shift.shift_key(sheet.highlight_cell))
+        .flatten()
+    {
+        Some(key) => sheet.highlight_cell = key,
+        None => {
+            sheet.highlight_cell = -1;
+            sheet.highlight_type = HighlightType::None;
+        }
+    }
+    sheet.pending_overwrite = None;
+    resized_keys
+}
+
+/// Inserts or deletes a row and moves everything below it.
+fn shift_rows(sheet: &mut Spreadsheet, shift: RowShift, sleep_time: &mut f64) -> CommandStatus {
+    let lost_row = shift.lost_row();
+    let mut broken: Vec<i32> = sheet
+        .cell_meta
+        .keys()
+        .copied()
+        .filter(|&key| sheet.get_row_col(key).0 != lost_row && reads_row(sheet, key, lost_row))
+        .collect();
+    broken.sort_unstable();
+
+    if shift.insert {
+        let in_use = (0..sheet.cols).any(|col| {
+            let key = sheet.get_key(lost_row, col);
+            sheet.is_touched(key) || sheet.assigned.contains(&key)
+        });
+        if in_use || !broken.is_empty() {
+            sheet.output.line(format!(
+                "row {} is in use and would be pushed off the sheet",
+                lost_row + 1
+            ));
+            return CommandStatus::Unrecognized;
+        }
+    } else {
+        if (0..sheet.cols).any(|col| sheet.is_cell_locked(lost_row, col)) {
+            return CommandStatus::LockedCell;
+        }
+        if (0..sheet.cols).any(|col| sheet.check_owner(lost_row, col) != CommandStatus::CmdOk) {
+            return CommandStatus::NotOwner;
+        }
+    }
+
+    let split: Vec<i32> = sheet
+        .extended_formulas
+        .keys()
+        .copied()
+        .filter(|&key| {
+            sheet.get_row_col(key).0 != lost_row
+                && !broken.contains(&key)
+                && splits_sum_product(sheet, key, shift)
+        })
+        .collect();
+    broken.extend(split);
+    broken.sort_unstable();
+
+    // Keys below the shift point change meaning, so undo puts back the whole sheet
+    sheet.note_undo_layout();
+
+    // Every cell from the shift point down may change; record them all for a dry run
+    if sheet.dry_run.is_some() {
+        let total = sheet.rows as usize * sheet.cols as usize;
+        for index in 0..(sheet.grid.len() + sheet.cols as usize).min(total) {
+            sheet.journal_write(index);
+        }
+    }
+
+    for &key in &broken {
+        let (row, col) = sheet.get_row_col(key);
+        sheet.clear_formula(row, col);
+        *sheet.get_mut_cell(row, col) = CellValue::Error(ErrorKind::BadRef);
+    }
+    for col in 0..sheet.cols {
+        sheet.clear_formula(lost_row, col);
+    }
+
+    let width = sheet.cols as usize;
+    let start = shift.row as usize * width;
+    if start < sheet.grid.len() {
+        if shift.insert {
+            sheet
+                .grid
+                .splice(start..start, std::iter::repeat_n(CellValue::Empty, width));
+            sheet.grid.truncate(sheet.rows as usize * width);
+        } else {
+            sheet.grid.drain(start..start + width);
+        }
+    }
+
+    let resized_keys = remap_references(sheet, shift);
+    for &key in &resized_keys {
+        let (row, col) = sheet.get_row_col(key);
+        reevaluate_formula(sheet, row, col, sleep_time);
+    }
+    let broken: Vec<i32> = broken
+        .into_iter()
+        .filter_map(|key| shift.shift_key(key))
+        .collect();
+    let changed: Vec<i32> = broken.iter().chain(&resized_keys).copied().collect();
+    toposort_reval_many(sheet, &changed, sleep_time);
+
+    if !broken.is_empty() {
+        let names: Vec<String> = broken
+            .iter()
+            .map(|&key| {
+                let (row, col) = sheet.get_row_col(key);
+                format!("{}{}", sheet.get_column_name(col), row + 1)
+            })
+            .collect();
+        sheet.output.line(format!(
+            "{} formula(s) read row {} and now hold #REF: {}",
+            broken.len(),
+            shift.row + 1,
+            names.join(", ")
+        ));
+    }
+    sheet.cell_counts = sheet.scan_cell_counts();
+    sheet.dirty = true;
+    CommandStatus::CmdOk
+}
+
+/// Parses the one-based row number of `insert_row` and `delete_row`.
+fn parse_row(sheet: &Spreadsheet, args: &str) -> Result<i16, CommandStatus> {
+    let row: i64 = args
+        .trim()
+        .parse()
+        .map_err(|_| CommandStatus::Unrecognized)?;
+    if row < 1 || row > sheet.rows as i64 {
+        return Err(CommandStatus::InvalidCell);
+    }
+    Ok(row as i16 - 1)
+}
+
+/// Handles `insert_row <n>`: inserts an empty row before row `n`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `insert_row `.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::InvalidCell` - If the row is outside the sheet.
+/// * `CommandStatus::Unrecognized` - If the row number is malformed, or the last row is in
+///   use and would be pushed off the sheet
+pub fn handle_insert_row(
+    sheet: &mut Spreadsheet,
+    args: &str,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    match parse_row(sheet, args) {
+        Ok(row) => {
+            let shift = RowShift {
+                row,
+                insert: true,
+                rows: sheet.rows,
+                cols: sheet.cols,
+            };
+            shift_rows(sheet, shift, sleep_time)
+        }
+        Err(status) => status,
+    }
+}
+
+/// Handles `delete_row <n>`: removes row `n` and moves the rows below it up.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `delete_row `.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::InvalidCell` - If the row is outside the sheet.
+/// * `CommandStatus::LockedCell` - If a cell of the row is locked.
+/// * `CommandStatus::NotOwner` - If a cell of the row is owned by someone else.
+/// * `CommandStatus::Unrecognized` - If the row number is malformed
+pub fn handle_delete_row(
+    sheet: &mut Spreadsheet,
+    args: &str,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    match parse_row(sheet, args) {
+        Ok(row) => {
+            let shift = RowShift {
+                row,
+                insert: false,
+                rows: sheet.rows,
+                cols: sheet.cols,
+            };
+            shift_rows(sheet, shift, sleep_time)
+        }
+        Err(status) => status,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::ScriptedSheet;
+
+    const COLUMN_A: [&str; 10] = [
+        "A1=1", "A2=2", "A3=3", "A4=4", "A5=5", "A6=6", "A7=7", "A8=8", "A9=9", "A10=10",
+    ];
+
+    #[test]
+    fn test_sum_grows_and_shrinks_with_its_range() {
+        ScriptedSheet::new(20, 5)
+            .run(&COLUMN_A)
+            .run(&["B1=SUM(A1:A10)", "insert_row 5"])
+            .assert_status_ok()
+            .assert_formula("B1", "SUM(A1:A11)")
+            .assert_cell("B1", 55)
+            .assert_cell("A6", 5)
+            .run(&["A5=100"])
+            .assert_cell("B1", 155)
+            .run(&["delete_row 5", "delete_row 3"])
+            .assert_status_ok()
+            .assert_formula("B1", "SUM(A1:A9)")
+            .assert_cell("B1", 155 - 3 - 100)
+            .assert_cell("A3", 4);
+    }
+
+    #[test]
+    fn test_deleted_reference_becomes_ref_error() {
+        ScriptedSheet::new(10, 5)
+            .run(&["A1=1", "A2=2", "A3=3", "B5=A2+A3", "C5=B5*10", "D1=A3"])
+            .run(&["delete_row 2"])
+            .assert_status_ok()
+            .assert_output(&["1 formula(s) read row 2 and now hold #REF: B4"])
+            .assert_error_cell("B4", ErrorKind::BadRef)
+            .assert_formula("B4", "")
+            .assert_formula("C4", "B4*10")
+            .assert_error_cell("C4", ErrorKind::BadRef)
+            .assert_formula("D1", "A2")
+            .run(&["A2=30"])
+            .assert_cell("D1", 30);
+    }
+
+    #[test]
+    fn test_split_sum_product_becomes_ref_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("split.sheet").display().to_string();
+        ScriptedSheet::new(10, 5)
+            .run(&["A1=1", "A2=2", "A3=3", "D4=SUMPRODUCT(A1:A2,A2:A3)"])
+            .assert_cell("D4", 8)
+            // Only the first range spans row 2, so the ranges would no longer pair up
+            .run(&["insert_row 2"])
+            .assert_status_ok()
+            .assert_output(&["1 formula(s) read row 2 and now hold #REF: D5"])
+            .assert_formula("D5", "")
+            .assert_error_cell("D5", ErrorKind::BadRef)
+            .run(&[&format!("save {}", file), &format!("open {}", file)])
+            .assert_status_ok()
+            .assert_error_cell("D5", ErrorKind::BadRef)
+            // Both ranges span row 4, so they shrink together
+            .run(&["D1=SUMPRODUCT(A3:A4,B3:B4)", "delete_row 4"])
+            .assert_status_ok()
+            .assert_formula("D1", "SUMPRODUCT(A3:A3,B3:B3)");
+    }
+
+    #[test]
+    fn test_names_locks_and_history_move_with_their_rows() {
+        let scripted = ScriptedSheet::new(10, 5)
+            .run(&[
+                "A4=1",
+                "A4=2",
+                "name A3:B4 block",
+                "lock_cell C6",
+                "C7=SUM(block)",
+                "insert_row 2",
+            ])
+            .assert_status_ok();
+        let sheet = &scripted.sheet;
+        let block = sheet.named_ranges["block"];
+        assert_eq!((block.start_row, block.end_row), (3, 4));
+        assert!(sheet.is_cell_locked(6, 2) && !sheet.is_cell_locked(5, 2));
+        assert_eq!(
+            sheet.cell_history[&sheet.get_key(4, 0)],
+            vec![(CellValue::Empty, None), (CellValue::Integer(1), None)]
+        );
+        assert_eq!(sheet.last_edited, Some((7, 2)));
+        scripted
+            .assert_formula("C8", "SUM(A4:B5)")
+            .run(&["A5=7"])
+            .assert_cell("C8", 7)
+            .run(&["delete_row 7"])
+            .assert_status(CommandStatus::LockedCell)
+            .assert_cell("A5", 7);
+    }
+
+    #[test]
+    fn test_insert_refuses_to_push_out_a_used_row() {
+        ScriptedSheet::new(5, 3)
+            .run(&["A1=A5", "insert_row 1"])
+            .assert_status(CommandStatus::Unrecognized)
+            .assert_output(&["row 5 is in use and would be pushed off the sheet"])
+            .run(&["insert_row 6"])
+            .assert_status(CommandStatus::InvalidCell)
+            .run(&["A1=1", "insert_row 1"])
+            .assert_status_ok()
+            .assert_cell("A2", 1)
+            .assert_formula("A1", "");
+    }
+}
@@ -0,0 +1,512 @@
+//! Read-only inspection of a range: random samples of its cells and a census of their contents.
+//!
+//! Only touched cells (values or formulas, see `Spreadsheet::is_touched`) are sampled,
+//! since untouched cells are empty and say nothing about the data.
+
+use crate::cell::CellValue;
+use crate::random::Rng;
+use crate::range::{Range, resolve_range};
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use crate::table::{Align, Table};
+
+/// Parsed arguments of `sample <range> <n> [errors] [--seed <s>]`.
+///
+/// # Fields
+///
+/// * `range` - The range to sample from.
+/// * `count` - How many cells to pick.
+/// * `errors_only` - Only pick cells holding an error.
+/// * `seed` - Seed for a reproducible sample, or `None` to seed from the clock.
+#[derive(Debug, PartialEq)]
+pub struct SampleArgs {
+    pub range: Range,
+    pub count: usize,
+    pub errors_only: bool,
+    pub seed: Option<u64>,
+}
+
+/// Cell counts for a range, as printed by `count`.
+///
+/// # Fields
+///
+/// * `cells` - Cells in the range.
+/// * `touched` - Cells with a value or a formula.
+/// * `errors` - Cells holding an error.
+/// * `zeros` - Cells whose value is 0, and empty cells.
+#[derive(Debug, Default, PartialEq)]
+pub struct Census {
+    pub cells: i64,
+    pub touched: i64,
+    pub errors: i64,
+    pub zeros: i64,
+}
+
+/// Parses the arguments of a `sample` command.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for resolving the range.
+/// * `args` - The text after `sample `.
+///
+/// # Returns
+///
+/// * `Ok(SampleArgs)` - The parsed command.
+/// * `Err(CommandStatus)` - If the range is invalid or the syntax is wrong
+pub fn parse_sample_args(sheet: &Spreadsheet, args: &str) -> Result<SampleArgs, CommandStatus> {
+    let mut tokens = args.split_whitespace();
+    let range = resolve_range(sheet, tokens.next().ok_or(CommandStatus::Unrecognized)?)?;
+    let count = match tokens.next().map(str::parse::<usize>) {
+        Some(Ok(count)) if count > 0 => count,
+        _ => return Err(CommandStatus::Unrecognized),
+    };
+
+    let mut errors_only = false;
+    let mut seed = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            "errors" if !errors_only => errors_only = true,
+            "--seed" if seed.is_none() => {
+                let value = tokens.next().and_then(|s| s.parse::<u64>().ok());
+                seed = Some(value.ok_or(CommandStatus::Unrecognized)?);
+            }
+            _ => return Err(CommandStatus::Unrecognized),
+        }
+    }
+
+    Ok(SampleArgs {
+        range,
+        count,
+        errors_only,
+        seed,
+    })
+}
+
+/// Picks up to `count` touched cells of a range uniformly at random.
+///
+/// Uses reservoir sampling while walking the range, so only the picked cells are kept.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `range` - The range to sample from.
+/// * `count` - How many cells to pick.
+/// * `errors_only` - Only consider cells holding an error.
+/// * `rng` - The random number generator.
+///
+/// # Returns
+///
+/// * `Vec<(i16, i16)>` - The picked cells in row-major order; all candidates if there are fewer than `count`
+pub fn sample_cells(
+    sheet: &Spreadsheet,
+    range: &Range,
+    count: usize,
+    errors_only: bool,
+    rng: &mut Rng,
+) -> Vec<(i16, i16)> {
+    // The count comes from the user; the range bounds how many cells can be picked
+    let mut reservoir = Vec::with_capacity(count.min(range.cell_count() as usize));
+    let mut seen: u64 = 0;
+    for (row, col) in range.cells() {
+        let key = sheet.get_key(row, col);
+        let wanted = if errors_only {
+            sheet.get_key_cell(key).is_error()
+        } else {
+            sheet.is_touched(key)
+        };
+        if !wanted {
+            continue;
+        }
+
+        seen += 1;
+        if reservoir.len() < count {
+            reservoir.push((row, col));
+        } else {
+            let slot = rng.below(seen) as usize;
+            if slot < count {
+                reservoir[slot] = (row, col);
+            }
+        }
+    }
+    reservoir.sort_unstable();
+    reservoir
+}
+
+/// Counts the cells, touched cells, errors, and zeros in a range.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `range` - The range to count.
+///
+/// # Returns
+///
+/// * `Census` - The counts
+pub fn census(sheet: &Spreadsheet, range: &Range) -> Census {
+    let mut counts = Census::default();
+    for (row, col) in range.cells() {
+        let key = sheet.get_key(row, col);
+        counts.cells += 1;
+        if sheet.is_touched(key) {
+            counts.touched += 1;
+        }
+        match sheet.get_key_cell(key) {
+            CellValue::Error(_) => counts.errors += 1,
+            // An empty cell reads as 0
+            CellValue::Integer(0) | CellValue::Empty => counts.zeros += 1,
+            CellValue::Integer(_) | CellValue::Text(_) => {}
+        }
+    }
+    counts
+}
+
+/// Handles `sample <range> <n> [errors] [--seed <s>]`, printing each picked cell and its value.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `args` - The text after `sample `.
+///
+/// # Returns
+///
+/// * `CommandStatus` - `CmdOk`, or the parse error
+pub fn handle_sample(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let args = match parse_sample_args(sheet, args) {
+        Ok(args) => args,
+        Err(status) => return status,
+    };
+    let mut rng = match args.seed {
+        Some(seed) => Rng::new(seed),
+        None => Rng::from_clock(),
+    };
+    let picked = sample_cells(sheet, &args.range, args.count, args.errors_only, &mut rng);
+
+    let kind = if args.errors_only { "error" } else { "touched" };
+    let mut lines = Vec::with_capacity(picked.len() + 1);
+    if picked.len() < args.count {
+        lines.push(format!("Only {} {} cell(s) in range", picked.len(), kind));
+    }
+    for (row, col) in picked {
+        let value = match sheet.get_cell(row, col) {
+            CellValue::Integer(val) => val.to_string(),
+            CellValue::Error(kind) => kind.code(),
+            CellValue::Text(text) => format!("\"{}\"", text),
+            CellValue::Empty => "empty".to_string(),
+        };
+        lines.push(format!(
+            "{}{}: {}",
+            sheet.get_column_name(col),
+            row + 1,
+            value
+        ));
+    }
+    sheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
+
+/// Handles `count <range>`, printing the range's census on one line.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `args` - The text after `count `.
+///
+/// # Returns
+///
+/// * `CommandStatus` - `CmdOk`, or the range parse error
+pub fn handle_count(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let range = match resolve_range(sheet, args.trim()) {
+        Ok(range) => range,
+        Err(status) => return status,
+    };
+    let counts = census(sheet, &range);
+    sheet.output.line(format!(
+        "cells: {}, touched: {}, errors: {}, zeros: {}",
+        counts.cells, counts.touched, counts.errors, counts.zeros
+    ));
+    CommandStatus::CmdOk
+}
+
+/// Summary statistics of the numeric cells in a range, as printed by `stats`.
+///
+/// # Fields
+///
+/// * `count` - Numeric cells read.
+/// * `sum` - Their sum.
+/// * `min` - The smallest value, if any cell was read.
+/// * `max` - The largest value, if any cell was read.
+/// * `errors` - Error cells, which are left out of the other figures.
+#[derive(Debug, Default, PartialEq)]
+pub struct RangeStats {
+    pub count: i64,
+    pub sum: i64,
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+    pub errors: i64,
+}
+
+/// Computes statistics over a range, optionally only over rows the filter shows.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `range` - The range to read.
+/// * `visible_only` - Skip rows hidden by the active filter.
+///
+/// # Returns
+///
+/// * `RangeStats` - The statistics
+pub fn range_stats(sheet: &Spreadsheet, range: &Range, visible_only: bool) -> RangeStats {
+    let mut stats = RangeStats::default();
+    for (row, col) in range.cells() {
+        if visible_only && !sheet.is_row_visible(row) {
+            continue;
+        }
+        match *sheet.get_cell(row, col) {
+            CellValue::Error(_) => stats.errors += 1,
+            // Labels and empty cells are not numbers, so they take no part in the statistics
+            CellValue::Text(_) | CellValue::Empty => {}
+            CellValue::Integer(value) => {
+                stats.count += 1;
+                stats.sum += value as i64;
+                stats.min = Some(stats.min.map_or(value, |min| min.min(value)));
+                stats.max = Some(stats.max.map_or(value, |max| max.max(value)));
+            }
+        }
+    }
+    stats
+}
+
+/// Handles `stats <range> [--visible]`, printing count, sum, average, minimum, and maximum.
+///
+/// Without `--visible` every row is read, like a formula over the range would.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `args` - The text after `stats `.
+///
+/// # Returns
+///
+/// * `CommandStatus` - `CmdOk`, or the range parse error
+pub fn handle_stats(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let (range_str, visible_only) = match args.trim().strip_suffix("--visible") {
+        Some(range_str) => (range_str.trim(), true),
+        None => (args.trim(), false),
+    };
+    let range = match resolve_range(sheet, range_str) {
+        Ok(range) => range,
+        Err(status) => return status,
+    };
+    let stats = range_stats(sheet, &range, visible_only);
+    let mut table = Table::new(&[
+        ("count", Align::Right),
+        ("sum", Align::Right),
+        ("avg", Align::Right),
+        ("min", Align::Right),
+        ("max", Align::Right),
+        ("errors", Align::Right),
+    ]);
+    // An empty range has no sum, average or extremes to show
+    let (sum, avg, min, max) = match (stats.min, stats.max) {
+        (Some(min), Some(max)) => (
+            stats.sum.to_string(),
+            format!("{:.2}", stats.sum as f64 / stats.count as f64),
+            min.to_string(),
+            max.to_string(),
+        ),
+        _ => Default::default(),
+    };
+    table.push_row(vec![
+        stats.count.to_string(),
+        sum,
+        avg,
+        min,
+        max,
+        stats.errors.to_string(),
+    ]);
+    sheet.output.lines(table.render());
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::handle_command;
+    use crate::output::OutputSink;
+
+    /// A1:A9 holds its row number, except A4 = B1 (a formula reading 0) and A7 = 1/0; A10 is untouched.
+    fn fixture() -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(12, 3).unwrap();
+        sheet.output = OutputSink::capture();
+        let mut sleep_time = 0.0;
+        for row in 1..=9 {
+            let command = match row {
+                4 => "A4=B1".to_string(),
+                7 => "A7=1/0".to_string(),
+                _ => format!("A{}={}", row, row),
+            };
+            assert_eq!(
+                handle_command(&mut sheet, &command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        sheet
+    }
+
+    #[test]
+    fn test_sample_is_deterministic_with_seed() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        for _ in 0..2 {
+            assert_eq!(
+                handle_command(&mut sheet, "sample A1:A10 3 --seed 7", &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        let lines = sheet.output.take();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[..3], lines[3..]);
+
+        // Only touched cells are picked, and in row-major order
+        let mut rng = Rng::new(7);
+        let range = resolve_range(&sheet, "A1:A10").unwrap();
+        let picked = sample_cells(&sheet, &range, 3, false, &mut rng);
+        assert_eq!(picked.len(), 3);
+        assert!(picked.windows(2).all(|w| w[0] < w[1]));
+        assert!(picked.iter().all(|&(row, _)| row < 9));
+    }
+
+    #[test]
+    fn test_sample_errors_only() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(
+                &mut sheet,
+                "sample A1:A10 1 errors --seed 1",
+                &mut sleep_time
+            ),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.output.take(), vec!["A7: #DIV0"]);
+    }
+
+    #[test]
+    fn test_sample_fewer_candidates_than_requested() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "sample A1:A10 20 --seed 3", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        let lines = sheet.output.take();
+        assert_eq!(lines[0], "Only 9 touched cell(s) in range");
+        assert_eq!(lines[1..4], ["A1: 1", "A2: 2", "A3: 3"]);
+        assert_eq!(lines[4], "A4: 0");
+        assert_eq!(lines.len(), 10);
+
+        assert_eq!(
+            handle_command(&mut sheet, "sample B1:B5 2 errors", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.output.take(), vec!["Only 0 error cell(s) in range"]);
+    }
+
+    #[test]
+    fn test_sample_huge_count_on_small_range() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "sample A1:A3 99999999999", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            sheet.output.take(),
+            vec!["Only 3 touched cell(s) in range", "A1: 1", "A2: 2", "A3: 3"]
+        );
+    }
+
+    #[test]
+    fn test_count_census() {
+        let mut sheet = fixture();
+        let range = resolve_range(&sheet, "A1:A10").unwrap();
+        assert_eq!(
+            census(&sheet, &range),
+            Census {
+                cells: 10,
+                touched: 9,
+                errors: 1,
+                zeros: 2,
+            }
+        );
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            handle_command(&mut sheet, "count A1:B10", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            sheet.output.take(),
+            vec!["cells: 20, touched: 9, errors: 1, zeros: 12"]
+        );
+    }
+
+    #[test]
+    fn test_stats_visible_rows() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "filter A > 4", &mut sleep_time);
+        sheet.output.take();
+        for command in ["stats A1:A10", "stats A1:A10 --visible", "stats B1:B3"] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        let lines = sheet.output.take();
+        assert_eq!(
+            lines[..2],
+            [
+                "count  sum   avg  min  max  errors",
+                "-----  ---  ----  ---  ---  ------"
+            ]
+        );
+        let rows: Vec<&String> = lines.iter().skip(2).step_by(3).collect();
+        assert_eq!(
+            rows,
+            vec![
+                "    8   34  4.25    0    9       1",
+                "    4   28  7.00    5    9       0",
+                "    0                           0",
+            ]
+        );
+        // A range with no values leaves the sum, average and extremes blank
+        handle_command(&mut sheet, "stats A7:A7", &mut sleep_time);
+        assert_eq!(sheet.output.take()[2], "    0                           1");
+        assert_eq!(
+            handle_command(&mut sheet, "stats A1 --visible", &mut sleep_time),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_sample_rejects_bad_arguments() {
+        let mut sheet = fixture();
+        let mut sleep_time = 0.0;
+        for command in [
+            "sample A1:A10",
+            "sample A1:A10 0",
+            "sample A1:A10 3 --seed",
+            "sample A1:A10 3 --seed x",
+            "sample A1:A10 3 extra",
+            "count A1",
+        ] {
+            assert_eq!(
+                handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::Unrecognized,
+                "{}",
+                command
+            );
+        }
+        assert!(sheet.output.take().is_empty());
+    }
+}
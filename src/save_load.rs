@@ -1,14 +1,230 @@
-use crate::cell::{CellValue, parse_cell_reference};
+use crate::cell::{CellValue, ErrorKind, parse_cell_reference};
+use crate::column_formula::ColumnFormula;
+use crate::evaluator::{evaluate_formula, resolve_extended};
+use crate::expr_parser::{parse_criterion, parse_expression};
+use crate::extensions::get_formula_string;
 use crate::graph;
+use crate::ownership::is_valid_user;
+use crate::range::{Range, parse_range, range_limit_exceeded};
+use crate::reevaluate_topo::toposort_reval_many;
 use crate::spreadsheet::CommandStatus;
 use crate::spreadsheet::Spreadsheet;
+use crate::table::{Align, Table};
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
+use std::time::SystemTime;
+
+/// Modification time and size of a file, used to notice changes made by someone else.
+///
+/// # Fields
+///
+/// * `modified` - Last modification time.
+/// * `len` - Size in bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStamp {
+    pub modified: SystemTime,
+    pub len: u64,
+}
+
+impl FileStamp {
+    /// Reads the stamp of a file on disk.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - The file path.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(FileStamp)` - If the file exists and its metadata is readable.
+    /// * `None` - Otherwise
+    pub fn read(filename: &str) -> Option<FileStamp> {
+        let metadata = std::fs::metadata(filename).ok()?;
+        Some(FileStamp {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+/// Remembers `filename` as the sheet's file and records its current stamp and cells.
+fn track_file(sheet: &mut Spreadsheet, filename: &str) {
+    sheet.current_file = Some(filename.to_string());
+    sheet.file_stamp = FileStamp::read(filename);
+    sheet.dirty = false;
+    sheet.capture_baseline();
+}
+
+/// Writes the sheet's records: dimensions, column formulas, ownership tags, and every
+/// non-empty cell.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet to write.
+/// * `writer` - Where the records go.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An error if writing fails
+pub fn write_sheet_records<W: Write>(sheet: &Spreadsheet, writer: &mut W) -> io::Result<()> {
+    // Write header with dimensions
+    writeln!(writer, "DIMS,{},{}", sheet.rows, sheet.cols)?;
+    // Write the viewport origin: VIEW,row,col (0-based)
+    writeln!(writer, "VIEW,{},{}", sheet.viewport_row, sheet.viewport_col)?;
+
+    // Write column formula definitions: COLF,col,first,last,template (last is * when open-ended)
+    for (&col, formula) in &sheet.column_formulas {
+        let (first, last) = match formula.rows {
+            Some((first, last)) => ((first + 1).to_string(), (last + 1).to_string()),
+            None => (String::new(), "*".to_string()),
+        };
+        writeln!(
+            writer,
+            "COLF,{},{},{},{}",
+            sheet.get_column_name(col),
+            first,
+            last,
+            formula.template
+        )?;
+    }
+
+    // Write ownership tags oldest first: OWN,range,owner
+    for tag in &sheet.ownership.tags {
+        writeln!(
+            writer,
+            "OWN,{},{}",
+            sheet.get_range_name(&tag.range),
+            tag.owner
+        )?;
+    }
+
+    // Write named ranges by name: NAME,name,range
+    let mut names: Vec<(&String, &Range)> = sheet.named_ranges.iter().collect();
+    names.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, range) in names {
+        writeln!(writer, "NAME,{},{}", name, sheet.get_range_name(range))?;
+    }
+
+    // Write locks as they were given: LOCK,range
+    for range in &sheet.locked_ranges {
+        writeln!(writer, "LOCK,{}", sheet.get_range_name(range))?;
+    }
+
+    // Write cell data with formulas
+    for row in 0..sheet.rows {
+        for col in 0..sheet.cols {
+            let key = sheet.get_key(row, col);
+            let cell_value = sheet.get_cell(row, col);
+
+            // Only write cells with a value or formula; an assigned 0 is a value, so
+            // COUNTA still counts it after a load
+            let is_filled = !matches!(cell_value, CellValue::Empty);
+
+            // Check if cell has formula metadata
+            let has_metadata = sheet.cell_meta.contains_key(&key);
+
+            if is_filled || has_metadata {
+                let cell_ref = format!("{}{}", sheet.get_column_name(col), row + 1);
+
+                // Write the cell value
+                match cell_value {
+                    CellValue::Integer(val) => write!(writer, "CELL,{},{}", cell_ref, val)?,
+                    CellValue::Error(kind) => {
+                        write!(writer, "CELL,{},ERR:{}", cell_ref, kind.name())?
+                    }
+                    CellValue::Text(text) => {
+                        write!(writer, "CELL,{},{}", cell_ref, quote_field(text))?
+                    }
+                    CellValue::Empty => write!(writer, "CELL,{},", cell_ref)?,
+                }
+
+                // If the cell has formula metadata, write it too
+                if let Some(meta) = sheet.cell_meta.get(&key)
+                    && meta.formula != -1
+                {
+                    // The formula as it would be entered, last because it may hold
+                    // commas; relative and pinned references and SUMIF criteria are part
+                    // of the text
+                    write!(writer, ",FORMULA,{}", get_formula_string(sheet, row, col))?;
+                }
+
+                // End the line
+                writeln!(writer)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Tells which parents of a formula saved as its code are cells rather than literal
+/// operands.
+///
+/// # Arguments
+///
+/// * `formula` - The formula code.
+/// * `pair_of_cells` - For a code ending in 0, whether its operands are cells; two
+///   literals share the code.
+///
+/// # Returns
+///
+/// * `(bool, bool)` - Whether `parent1` and `parent2` are cells
+fn parent_roles(formula: i16, pair_of_cells: bool) -> (bool, bool) {
+    match formula % 10 {
+        0 => (pair_of_cells, pair_of_cells),
+        2 => (true, false),
+        3 => (false, true),
+        _ => (true, true),
+    }
+}
+
+/// Reads a parent field of a formula saved as its code (`FORMULA,code,parent1,parent2`).
+///
+/// A cell is resolved against the sheet being loaded, whatever its width. Literals are
+/// numbers, except in the oldest files, which hold in their place the cell whose key on
+/// the saved sheet equalled the literal; those are converted back using that sheet's width.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet being loaded.
+/// * `field` - The field: empty, a number, or a cell reference.
+/// * `is_cell` - Whether the formula reads this parent as a cell.
+/// * `saved_cols` - The number of columns of the saved sheet.
+///
+/// # Returns
+///
+/// * `Some(i32)` - The cell's key, the literal, or -1 for an empty field.
+/// * `None` - If the cell is not on this sheet or the field is malformed
+fn read_parent_field(
+    sheet: &Spreadsheet,
+    field: &str,
+    is_cell: bool,
+    saved_cols: i16,
+) -> Option<i32> {
+    if field.is_empty() {
+        return Some(-1);
+    }
+    if let Ok(number) = field.parse::<i32>() {
+        return (!is_cell).then_some(number);
+    }
+    if is_cell {
+        let (row, col) = parse_cell_reference(sheet, field).ok()?;
+        return Some(sheet.get_key(row, col));
+    }
+    let letters = field.trim_end_matches(|c: char| c.is_ascii_digit());
+    let col = sheet.column_name_to_index(letters);
+    let row = field[letters.len()..].parse::<i32>().ok()?.checked_sub(1)?;
+    if col < 0 || row < 0 {
+        return None;
+    }
+    row.checked_mul(saved_cols as i32)?.checked_add(col as i32)
+}
 
 /// Saves the spreadsheet to a file.
 ///
+/// A `.json` file is written as a JSON document when the `json` feature is on; any other
+/// file gets the sheet's own records.
+///
 /// # Arguments
 ///
 /// * `sheet` - The spreadsheet to save.
@@ -19,7 +235,34 @@ use std::path::Path;
 /// * `CommandStatus::CmdOk` - On success.
 /// * `CommandStatus::Unrecognized` - If file operations fail
 pub fn save_spreadsheet(sheet: &Spreadsheet, filename: &str) -> CommandStatus {
-    let path = Path::new(filename);
+    #[cfg(feature = "json")]
+    if is_json_file(filename) {
+        return save_json(sheet, filename);
+    }
+    save_with(sheet, filename, write_sheet_records)
+}
+
+/// Writes a file through a temporary file, keeping backups, with the given writer.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet to save.
+/// * `filename` - The target file path.
+/// * `write` - Writes the file's contents.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If file operations fail
+fn save_with(
+    sheet: &Spreadsheet,
+    filename: &str,
+    write: fn(&Spreadsheet, &mut BufWriter<File>) -> io::Result<()>,
+) -> CommandStatus {
+    // Write a temporary file next to the target and rename it over the target once it is
+    // complete, so a failed save leaves the old file (and its backups) untouched
+    let temp_name = format!("{}.tmp", filename);
+    let path = Path::new(&temp_name);
 
     // Open file for writing, always creating it if it doesn't exist
     let file = match OpenOptions::new()
@@ -38,94 +281,325 @@ pub fn save_spreadsheet(sheet: &Spreadsheet, filename: &str) -> CommandStatus {
     // Create a buffered writer
     let mut writer = BufWriter::new(file);
 
-    // Write header with dimensions
-    if let Err(e) = writeln!(writer, "DIMS,{},{}", sheet.rows, sheet.cols) {
+    if let Err(e) = write(sheet, &mut writer) {
         eprintln!("Failed to write to file '{}': {}", filename, e);
+        let _ = std::fs::remove_file(path);
         return CommandStatus::Unrecognized;
     }
 
-    // Write cell data with formulas
-    for row in 0..sheet.rows {
-        for col in 0..sheet.cols {
-            let key = sheet.get_key(row, col);
-            let cell_value = sheet.get_cell(row, col);
+    // Explicitly flush to ensure all data is written
+    if let Err(e) = writer.flush() {
+        eprintln!("Failed to flush data to '{}': {}", filename, e);
+        let _ = std::fs::remove_file(path);
+        return CommandStatus::Unrecognized;
+    }
+    drop(writer);
 
-            // Only write cells with non-zero values or formulas
-            let is_nonzero = !matches!(cell_value, CellValue::Integer(0));
+    // The new contents are safely on disk; only now do older versions move down
+    if let Err(e) = rotate_backups(filename, sheet.backups) {
+        eprintln!("Could not rotate backups of '{}': {}", filename, e);
+    }
+    if let Err(e) = std::fs::rename(path, filename) {
+        eprintln!("Failed to replace '{}': {}", filename, e);
+        let _ = std::fs::remove_file(path);
+        return CommandStatus::Unrecognized;
+    }
 
-            // Check if cell has formula metadata
-            let has_metadata = sheet.cell_meta.contains_key(&key);
+    eprintln!("Spreadsheet successfully saved to '{}'", filename);
+    CommandStatus::CmdOk
+}
 
-            if is_nonzero || has_metadata {
-                let cell_ref = format!("{}{}", sheet.get_column_name(col), row + 1);
+/// Returns the path of a file's numbered backup, `name.sheet.3` for number 3.
+fn backup_path(filename: &str, number: usize) -> String {
+    format!("{}.{}", filename, number)
+}
 
-                // Write the cell value
-                match cell_value {
-                    CellValue::Integer(val) => {
-                        if let Err(e) = write!(writer, "CELL,{},{}", cell_ref, val) {
-                            eprintln!("Failed to write cell data to '{}': {}", filename, e);
-                            return CommandStatus::Unrecognized;
-                        }
-                    }
-                    CellValue::Error => {
-                        if let Err(e) = write!(writer, "CELL,{},ERR", cell_ref) {
-                            eprintln!("Failed to write cell data to '{}': {}", filename, e);
-                            return CommandStatus::Unrecognized;
-                        }
-                    }
-                }
+/// Moves each numbered backup of a file one place down and makes the file itself backup 1.
+///
+/// Backup `keep` is deleted first, so at most `keep` backups remain. Missing numbers are
+/// skipped, and only the `keep` numbered paths are touched.
+///
+/// # Arguments
+///
+/// * `filename` - The file about to be overwritten.
+/// * `keep` - How many backups to keep; 0 keeps none and leaves existing ones alone.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An error if a backup cannot be moved or created
+fn rotate_backups(filename: &str, keep: usize) -> io::Result<()> {
+    if keep == 0 || !Path::new(filename).exists() {
+        return Ok(());
+    }
+    match std::fs::remove_file(backup_path(filename, keep)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    for number in (1..keep).rev() {
+        let from = backup_path(filename, number);
+        if Path::new(&from).exists() {
+            std::fs::rename(&from, backup_path(filename, number + 1))?;
+        }
+    }
+    // A hard link keeps the file in place until the new version is renamed over it
+    let first = backup_path(filename, 1);
+    std::fs::hard_link(filename, &first).or_else(|_| std::fs::copy(filename, &first).map(|_| ()))
+}
 
-                // If the cell has formula metadata, write it too
-                if let Some(meta) = sheet.cell_meta.get(&key) {
-                    if meta.formula != -1 {
-                        // Get the parent cells as references
-                        let parent1_ref = if meta.parent1 != -1 {
-                            let (p1_row, p1_col) = sheet.get_row_col(meta.parent1);
-                            format!("{}{}", sheet.get_column_name(p1_col), p1_row + 1)
-                        } else {
-                            String::from("")
-                        };
+/// A numbered backup found on disk.
+///
+/// # Fields
+///
+/// * `number` - Its number; 1 is the most recent.
+/// * `path` - Its path.
+/// * `modified` - When it was last written, if known.
+/// * `len` - Its size in bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backup {
+    pub number: usize,
+    pub path: String,
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+}
 
-                        let parent2_ref = if meta.parent2 != -1 {
-                            let (p2_row, p2_col) = sheet.get_row_col(meta.parent2);
-                            format!("{}{}", sheet.get_column_name(p2_col), p2_row + 1)
-                        } else {
-                            String::from("")
-                        };
+/// Lists the numbered backups of a file, most recent first.
+///
+/// # Arguments
+///
+/// * `filename` - The file whose backups are listed.
+///
+/// # Returns
+///
+/// * `Vec<Backup>` - Every `<filename>.<n>` next to the file
+pub fn list_backups(filename: &str) -> Vec<Backup> {
+    let path = Path::new(filename);
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Vec::new();
+    };
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.", name);
+    let mut backups: Vec<Backup> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let entry_name = entry.file_name();
+            let number = entry_name
+                .to_str()?
+                .strip_prefix(&prefix)?
+                .parse::<usize>()
+                .ok()
+                .filter(|&number| number > 0)?;
+            let metadata = entry.metadata().ok()?;
+            Some(Backup {
+                number,
+                path: backup_path(filename, number),
+                modified: metadata.modified().ok(),
+                len: metadata.len(),
+            })
+        })
+        .collect();
+    backups.sort_by_key(|backup| backup.number);
+    backups
+}
 
-                        // Fix: Use the correct format for formula data - no spaces after commas
-                        if let Err(e) = write!(
-                            writer,
-                            ",FORMULA,{},{},{}",
-                            meta.formula, parent1_ref, parent2_ref
-                        ) {
-                            eprintln!("Failed to write formula data to '{}': {}", filename, e);
-                            return CommandStatus::Unrecognized;
-                        }
-                    }
-                }
+/// Formats a time as a UTC date and time, `2024-05-01 13:45:09`.
+fn format_utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
 
-                // End the line
-                if let Err(e) = writeln!(writer) {
-                    eprintln!("Failed to write to '{}': {}", filename, e);
-                    return CommandStatus::Unrecognized;
-                }
-            }
-        }
+/// Handles `backups <file>`, listing the file's numbered backups.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `filename` - The file whose backups are listed.
+///
+/// # Returns
+///
+/// * `CommandStatus` - Always `CmdOk`
+fn handle_backups(sheet: &mut Spreadsheet, filename: &str) -> CommandStatus {
+    let backups = list_backups(filename);
+    if backups.is_empty() {
+        sheet.output.line(format!("No backups of '{}'", filename));
+        return CommandStatus::CmdOk;
     }
+    let mut table = Table::new(&[
+        ("backup", Align::Left),
+        ("modified (UTC)", Align::Left),
+        ("bytes", Align::Right),
+    ]);
+    for backup in backups {
+        table.push_row(vec![
+            backup.path,
+            backup.modified.map(format_utc).unwrap_or_default(),
+            backup.len.to_string(),
+        ]);
+    }
+    sheet.output.lines(table.render());
+    CommandStatus::CmdOk
+}
 
-    // Explicitly flush to ensure all data is written
-    if let Err(e) = writer.flush() {
-        eprintln!("Failed to flush data to '{}': {}", filename, e);
+/// Loads a numbered backup of a file in place of the sheet.
+///
+/// The backup goes through the normal load path and the file stays the sheet's current
+/// file, so a later `save` writes the restored cells back to it. The sheet is left dirty,
+/// since it no longer matches the file on disk.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet to load into.
+/// * `filename` - The file whose backup is restored.
+/// * `number` - Which backup; 1 is the most recent.
+/// * `force` - Restore even if there are unsaved changes.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::UnsavedChanges` - If the sheet is dirty and `force` is not set.
+/// * `CommandStatus::Unrecognized` - If the backup does not exist or cannot be read
+pub fn restore_backup(
+    sheet: &mut Spreadsheet,
+    filename: &str,
+    number: usize,
+    force: bool,
+) -> CommandStatus {
+    if sheet.dirty && !force {
+        eprintln!(
+            "The sheet has {} unsaved change(s); use 'restore_backup!' to discard them",
+            sheet.changes_since_save(None).total()
+        );
+        return CommandStatus::UnsavedChanges;
+    }
+    let backup = backup_path(filename, number);
+    if !Path::new(&backup).exists() {
+        eprintln!("No backup {} of '{}'", number, filename);
         return CommandStatus::Unrecognized;
     }
+    // `changes` compares against the file as it is on disk, not against the backup
+    let mut on_disk = match Spreadsheet::create(sheet.rows, sheet.cols) {
+        Some(on_disk) => on_disk,
+        None => return CommandStatus::Unrecognized,
+    };
+    if Path::new(filename).exists() {
+        load_spreadsheet(&mut on_disk, filename);
+    }
+    on_disk.capture_baseline();
+
+    let status = load_spreadsheet(sheet, &backup);
+    if status == CommandStatus::CmdOk {
+        sheet.current_file = Some(filename.to_string());
+        sheet.file_stamp = FileStamp::read(filename);
+        sheet.saved_baseline = on_disk.saved_baseline;
+        sheet.dirty = true;
+        sheet
+            .output
+            .line(format!("Restored backup {} of '{}'", number, filename));
+    }
+    status
+}
 
-    eprintln!("Spreadsheet successfully saved to '{}'", filename);
-    CommandStatus::CmdOk
+/// One line read from a save file.
+///
+/// # Variants
+///
+/// * `Line` - The line, without its line ending.
+/// * `TooLong` - The line was longer than the limit; holds its length in bytes.
+/// * `Invalid` - The line is not valid UTF-8
+#[derive(Debug, PartialEq)]
+enum SaveLine {
+    Line(String),
+    TooLong(usize),
+    Invalid,
+}
+
+/// Reads the next line, keeping at most `limit` bytes of it in memory.
+///
+/// An overlong line is read to its end and discarded, so the next call starts at the
+/// following record.
+///
+/// # Arguments
+///
+/// * `reader` - The save file.
+/// * `limit` - The longest line, in bytes, that is returned.
+///
+/// # Returns
+///
+/// * `Ok(Some(SaveLine))` - The next line.
+/// * `Ok(None)` - At the end of the file.
+/// * `Err(io::Error)` - If reading fails
+fn read_save_line<R: BufRead>(reader: &mut R, limit: usize) -> io::Result<Option<SaveLine>> {
+    let mut bytes = Vec::new();
+    let mut len = 0;
+    let mut read_any = false;
+    loop {
+        let buf = match reader.fill_buf() {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if buf.is_empty() {
+            break;
+        }
+        read_any = true;
+        let newline = buf.iter().position(|&b| b == b'\n');
+        let chunk = &buf[..newline.unwrap_or(buf.len())];
+        len += chunk.len();
+        if len <= limit {
+            bytes.extend_from_slice(chunk);
+        }
+        let used = chunk.len() + usize::from(newline.is_some());
+        reader.consume(used);
+        if newline.is_some() {
+            break;
+        }
+    }
+    if !read_any {
+        return Ok(None);
+    }
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+        len -= 1;
+    }
+    if len > limit {
+        return Ok(Some(SaveLine::TooLong(len)));
+    }
+    Ok(Some(match String::from_utf8(bytes) {
+        Ok(line) => SaveLine::Line(line),
+        Err(_) => SaveLine::Invalid,
+    }))
 }
 
 /// Loads a spreadsheet from a file, overwriting existing data.
 ///
+/// A `.json` file is read as a JSON document when the `json` feature is on.
+///
 /// # Arguments
 ///
 /// * `sheet` - The mutable spreadsheet to load into.
@@ -136,6 +610,10 @@ pub fn save_spreadsheet(sheet: &Spreadsheet, filename: &str) -> CommandStatus {
 /// * `CommandStatus::CmdOk` - On success (even with partial data).
 /// * `CommandStatus::Unrecognized` - If the file cannot be opened
 pub fn load_spreadsheet(sheet: &mut Spreadsheet, filename: &str) -> CommandStatus {
+    #[cfg(feature = "json")]
+    if is_json_file(filename) {
+        return load_json(sheet, filename);
+    }
     let path = Path::new(filename);
 
     // Open file for reading
@@ -145,149 +623,754 @@ pub fn load_spreadsheet(sheet: &mut Spreadsheet, filename: &str) -> CommandStatu
     };
 
     // Create a buffered reader
-    let reader = BufReader::new(file);
+    let mut reader = BufReader::new(file);
 
-    // Clear the existing spreadsheet
-    for row in 0..sheet.rows {
-        for col in 0..sheet.cols {
-            let key = sheet.get_key(row, col);
-            // Clear cell value
-            let index = sheet.get_index(row, col);
-            sheet.grid[index] = CellValue::Integer(0);
-
-            // Clear metadata and dependencies
-            if sheet.cell_meta.contains_key(&key) {
-                // Remove all parent-child relationships
-                graph::remove_all_parents(sheet, row, col);
-                sheet.cell_meta.remove(&key);
+    sheet.clear();
+    read_records(sheet, &mut reader, |_, _| false);
+    sheet.cell_counts = sheet.scan_cell_counts();
+    CommandStatus::CmdOk
+}
+
+/// Writes text as a quoted field, doubling any quote inside it.
+fn quote_field(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+/// Reads back a field written by `quote_field`.
+///
+/// # Returns
+///
+/// * `Some(String)` - The text, with doubled quotes made single again.
+/// * `None` - If the field is not quoted
+fn unquote_field(field: &str) -> Option<String> {
+    let inner = field.strip_prefix('"')?.strip_suffix('"')?;
+    Some(inner.replace("\"\"", "\""))
+}
+
+/// Splits a record into its comma-separated fields.
+///
+/// A field that starts with `"` runs to its closing quote, so it may hold commas; a quote
+/// anywhere else is an ordinary character, as in the fields of older files.
+///
+/// # Arguments
+///
+/// * `line` - The record.
+///
+/// # Returns
+///
+/// * `Vec<&str>` - The fields, quoted ones still in their quotes
+fn split_record(line: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    for (i, byte) in line.bytes().enumerate() {
+        match byte {
+            // A doubled quote closes and reopens, so it stays inside the field
+            b'"' if line.as_bytes()[start] == b'"' => in_quotes = !in_quotes,
+            b',' if !in_quotes => {
+                fields.push(&line[start..i]);
+                start = i + 1;
             }
+            _ => {}
         }
     }
+    fields.push(&line[start..]);
+    fields
+}
 
-    // Read and parse the file
-    for line_result in reader.lines() {
-        let line = match line_result {
-            Ok(line) => line,
-            Err(_) => continue,
+/// Warns about each range of a loaded formula that is over the range cell limit.
+///
+/// Files are trusted, so the formula stays; the warning goes to the output, where a typed
+/// formula's refusal would.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, with the formula in place.
+/// * `row` - The formula cell's row.
+/// * `col` - The formula cell's column.
+/// * `cell_ref` - The cell's name as the file wrote it.
+fn warn_oversized_ranges(sheet: &mut Spreadsheet, row: i16, col: i16, cell_ref: &str) {
+    let key = sheet.get_key(row, col);
+    let mut ranges = sheet
+        .extended_formulas
+        .get(&key)
+        .map(|formula| formula.ranges())
+        .unwrap_or_default();
+    if let Some(meta) = sheet.cell_meta.get(&key)
+        && (5..=9).contains(&(meta.formula % 10))
+    {
+        let (start_row, start_col) = sheet.get_row_col(meta.parent1);
+        let (end_row, end_col) = sheet.get_row_col(meta.parent2);
+        ranges.push(Range {
+            start_row,
+            start_col,
+            end_row,
+            end_col,
+        });
+    }
+    let messages: Vec<String> = ranges
+        .iter()
+        .filter_map(|range| range_limit_exceeded(sheet, range))
+        .collect();
+    for message in messages {
+        sheet
+            .output
+            .line(format!("Warning: {} in {}", message, cell_ref));
+    }
+}
+
+/// What `load_record` keeps between the records of one file.
+///
+/// # Fields
+///
+/// * `saved_cols` - The number of columns of the saved sheet, from its `DIMS` record.
+/// * `cells` - Keys of the cells loaded so far.
+/// * `formula_text` - Whether a formula was loaded from its text, and so computed from
+///   only the cells before it.
+struct LoadState {
+    saved_cols: i16,
+    cells: Vec<i32>,
+    formula_text: bool,
+}
+
+/// Reads save file records and applies them to the sheet in order.
+///
+/// Each record is first offered to `extra`, which returns `true` if it handled it; the
+/// rest are sheet records. Unknown record types are skipped, so files written by newer
+/// versions still load.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet to load into.
+/// * `reader` - The records.
+/// * `extra` - Handler for record types other than the sheet's own.
+///
+/// Formulas saved as text are computed as they are read, then again in dependency order
+/// once every cell is in, so records may come in any order.
+pub fn read_records<R, F>(sheet: &mut Spreadsheet, reader: &mut R, mut extra: F)
+where
+    R: BufRead,
+    F: FnMut(&mut Spreadsheet, &[&str]) -> bool,
+{
+    // Read and parse the file; an overlong or unreadable record is skipped on its own
+    let mut line_number = 0;
+    let mut state = LoadState {
+        saved_cols: sheet.cols,
+        cells: Vec::new(),
+        formula_text: false,
+    };
+    loop {
+        line_number += 1;
+        let line = match read_save_line(reader, sheet.line_byte_limit) {
+            Ok(Some(SaveLine::Line(line))) => line,
+            Ok(Some(SaveLine::TooLong(bytes))) => {
+                eprintln!(
+                    "Warning: line {} is {} bytes, over the {} byte limit; record skipped",
+                    line_number, bytes, sheet.line_byte_limit
+                );
+                continue;
+            }
+            Ok(Some(SaveLine::Invalid)) => continue,
+            Ok(None) | Err(_) => break,
         };
 
-        let parts: Vec<&str> = line.split(',').collect();
+        let parts = split_record(&line);
         if parts.is_empty() {
             continue;
         }
 
-        // Process line based on type
-        match parts[0] {
-            "DIMS" => {
-                // Dimensions line: DIMS,rows,cols
-                if parts.len() >= 3 {
-                    // We don't resize the sheet here, just validate dimensions
-                    let file_rows: i16 = parts[1].parse().unwrap_or(0);
-                    let file_cols: i16 = parts[2].parse().unwrap_or(0);
+        if !extra(sheet, &parts) {
+            load_record(sheet, &parts, &mut state);
+        }
+    }
+
+    // Recompute the formulas from the cells loaded after them; loading does not sleep
+    if state.formula_text && toposort_reval_many(sheet, &state.cells, &mut 0.0) {
+        eprintln!("Warning: The file's formulas form a cycle; their values may be stale");
+    }
+}
+
+/// Applies one sheet record (`DIMS`, `VIEW`, `CELL`, `COLF`, `OWN`, `NAME`, or `LOCK`); other
+/// records are ignored.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet to load into.
+/// * `parts` - The record's comma-separated fields.
+/// * `state` - What earlier records of the file left.
+fn load_record(sheet: &mut Spreadsheet, parts: &[&str], state: &mut LoadState) {
+    // Process line based on type
+    match parts[0] {
+        "DIMS" if parts.len() >= 3 => {
+            // Dimensions line: DIMS,rows,cols
+            // We don't resize the sheet here, just validate dimensions
+            let file_rows: i16 = parts[1].parse().unwrap_or(0);
+            let file_cols: i16 = parts[2].parse().unwrap_or(0);
+
+            if file_rows > sheet.rows || file_cols > sheet.cols {
+                eprintln!("Warning: File contains a larger spreadsheet than current dimensions");
+            }
+            if file_cols > 0 {
+                state.saved_cols = file_cols;
+            }
+        }
+        "CELL" if parts.len() >= 3 => {
+            // Cell data line: CELL,ref,value[,FORMULA,text], or in older files
+            // CELL,ref,value[,FORMULA,formula_code,parent1,parent2[,REL,flags][,IF,criterion]
+            // [,EXPR,expression]]
+            let cell_ref = parts[1];
+            let value_str = parts[2];
+
+            // Parse cell reference
+            if let Ok((row, col)) = parse_cell_reference(sheet, cell_ref) {
+                // Fix: Check if the cell reference is within bounds
+                if row >= sheet.rows || col >= sheet.cols {
+                    eprintln!("Warning: Cell reference {} out of bounds", cell_ref);
+                    return;
+                }
+
+                // Set cell value
+                // Files from before error kinds were saved hold a bare ERR
+                let cell_value = if value_str == "ERR" {
+                    CellValue::Error(ErrorKind::ParseErr)
+                } else if let Some(name) = value_str.strip_prefix("ERR:") {
+                    match ErrorKind::from_name(name) {
+                        Some(kind) => CellValue::Error(kind),
+                        None => return,
+                    }
+                } else if value_str.is_empty() {
+                    CellValue::Empty
+                } else if let Some(text) = unquote_field(value_str) {
+                    CellValue::Text(text)
+                } else {
+                    match value_str.parse::<i32>() {
+                        Ok(val) => CellValue::Integer(val),
+                        Err(_) => return,
+                    }
+                };
 
-                    if file_rows > sheet.rows || file_cols > sheet.cols {
+                *sheet.get_mut_cell(row, col) = cell_value;
+                // Every saved cell was assigned, so its record marks it again
+                let key = sheet.get_key(row, col);
+                sheet.assigned.insert(key);
+                state.cells.push(key);
+
+                // A formula saved as text (FORMULA,text) runs to the end of the line and is
+                // entered again; files from before hold its code and parents instead
+                let is_text =
+                    parts.len() >= 5 && parts[3] == "FORMULA" && parts[4].parse::<i16>().is_err();
+                if is_text {
+                    let text = parts[4..].join(",");
+                    let saved = sheet.get_cell(row, col).clone();
+                    // Files are trusted, so oversized ranges load as if confirmed with `!`
+                    let status = evaluate_formula(sheet, row, col, &format!("{}!", text), &mut 0.0);
+                    if status != CommandStatus::CmdOk {
                         eprintln!(
-                            "Warning: File contains a larger spreadsheet than current dimensions"
+                            "Warning: Invalid formula {} for {}; value kept",
+                            text, cell_ref
                         );
+                        *sheet.get_mut_cell(row, col) = saved;
+                    } else {
+                        warn_oversized_ranges(sheet, row, col, cell_ref);
+                        if sheet.is_cell_locked(row, col) {
+                            // A locked cell is not reevaluated, so it keeps its saved value
+                            *sheet.get_mut_cell(row, col) = saved;
+                        }
                     }
-                }
-            }
-            "CELL" => {
-                // Cell data line: CELL,ref,value[,FORMULA,formula_code,parent1,parent2]
-                if parts.len() >= 3 {
-                    let cell_ref = parts[1];
-                    let value_str = parts[2];
-
-                    // Parse cell reference
-                    if let Ok((row, col)) = parse_cell_reference(sheet, cell_ref) {
-                        // Fix: Check if the cell reference is within bounds
-                        if row >= sheet.rows || col >= sheet.cols {
-                            eprintln!("Warning: Cell reference {} out of bounds", cell_ref);
-                            continue;
+                    state.formula_text = true;
+                } else if parts.len() >= 6 && parts[3] == "FORMULA" {
+                    let formula: i16 = parts[4].parse().unwrap_or(-1);
+                    let parent1_ref = parts[5];
+                    let parent2_ref = if parts.len() > 6 { parts[6] } else { "" };
+
+                    if formula != -1 {
+                        // Two numbers under a code ending in 0 are literals; any other
+                        // pair of fields there is a pair of cells
+                        let pair_of_cells = !(parent1_ref.parse::<i32>().is_ok()
+                            && parent2_ref.parse::<i32>().is_ok());
+                        let (parent1_is_cell, parent2_is_cell) =
+                            parent_roles(formula, pair_of_cells);
+                        // Every cell is looked up again on this sheet; a formula reading
+                        // a cell it does not have is dropped rather than rewired
+                        let parents = (
+                            read_parent_field(
+                                sheet,
+                                parent1_ref,
+                                parent1_is_cell,
+                                state.saved_cols,
+                            ),
+                            read_parent_field(
+                                sheet,
+                                parent2_ref,
+                                parent2_is_cell,
+                                state.saved_cols,
+                            ),
+                        );
+                        let (Some(parent1_key), Some(parent2_key)) = parents else {
+                            eprintln!(
+                                "Warning: Formula of {} reads a cell outside the sheet; value kept",
+                                cell_ref
+                            );
+                            return;
+                        };
+
+                        // An extended formula's expression runs from after EXPR to the
+                        // end of the line; add_children reads its cells from the side table
+                        let expr_at = parts
+                            .iter()
+                            .position(|part| *part == "EXPR")
+                            .unwrap_or(parts.len());
+                        if formula % 10 == 4 {
+                            let text = parts.get(expr_at + 1..).unwrap_or_default().join(",");
+                            let extended = parse_expression(&text)
+                                .ok()
+                                .and_then(|expr| resolve_extended(sheet, row, col, &expr).ok());
+                            let Some(extended) = extended else {
+                                eprintln!("Warning: Invalid formula for {}", cell_ref);
+                                return;
+                            };
+                            let key = sheet.get_key(row, col);
+                            sheet.extended_formulas.insert(key, extended);
                         }
 
-                        // Set cell value
-                        let cell_value = if value_str == "ERR" {
-                            CellValue::Error
-                        } else {
-                            match value_str.parse::<i32>() {
-                                Ok(val) => CellValue::Integer(val),
-                                Err(_) => continue,
-                            }
-                        };
+                        // Set cell metadata
+                        let meta = sheet.get_cell_meta(row, col);
+                        meta.formula = formula;
+                        meta.parent1 = parent1_key;
+                        meta.parent2 = parent2_key;
 
-                        let index = sheet.get_index(row, col);
-                        sheet.grid[index] = cell_value;
-
-                        // If there's formula data, process it
-                        if parts.len() >= 6 && parts[3] == "FORMULA" {
-                            let formula: i16 = parts[4].parse().unwrap_or(-1);
-                            let parent1_ref = parts[5];
-                            let parent2_ref = if parts.len() > 6 { parts[6] } else { "" };
-
-                            if formula != -1 {
-                                // Get parent cell keys
-                                let parent1_key = if !parent1_ref.is_empty() {
-                                    if let Ok((p1_row, p1_col)) =
-                                        parse_cell_reference(sheet, parent1_ref)
-                                    {
-                                        // Fix: Check if parent reference is within bounds
-                                        if p1_row < sheet.rows && p1_col < sheet.cols {
-                                            sheet.get_key(p1_row, p1_col)
-                                        } else {
-                                            -1
-                                        }
-                                    } else {
-                                        -1
+                        // Add dependencies; two literals have none
+                        if parent1_is_cell || parent2_is_cell {
+                            graph::add_children(sheet, parent1_key, parent2_key, formula, row, col);
+                        }
+
+                        warn_oversized_ranges(sheet, row, col, cell_ref);
+
+                        // Restore the relative-reference flags and SUMIF criterion, if any
+                        let key = sheet.get_key(row, col);
+                        for pair in parts.get(7..expr_at).unwrap_or_default().chunks_exact(2) {
+                            match pair[0] {
+                                "REL" => {
+                                    if let Ok(flags) = pair[1].parse::<u8>() {
+                                        sheet.relative_refs.insert(key, flags);
+                                    }
+                                }
+                                "IF" => match parse_criterion(pair[1]) {
+                                    Ok(criterion) => {
+                                        sheet.criteria.insert(key, criterion);
                                     }
-                                } else {
-                                    -1
-                                };
-
-                                let parent2_key = if !parent2_ref.is_empty() {
-                                    if let Ok((p2_row, p2_col)) =
-                                        parse_cell_reference(sheet, parent2_ref)
-                                    {
-                                        // Fix: Check if parent reference is within bounds
-                                        if p2_row < sheet.rows && p2_col < sheet.cols {
-                                            sheet.get_key(p2_row, p2_col)
-                                        } else {
-                                            -1
-                                        }
-                                    } else {
-                                        -1
+                                    Err(_) => {
+                                        eprintln!("Warning: Invalid criterion for {}", cell_ref)
                                     }
-                                } else {
-                                    -1
-                                };
-
-                                // Set cell metadata
-                                let meta = sheet.get_cell_meta(row, col);
-                                meta.formula = formula;
-                                meta.parent1 = parent1_key;
-                                meta.parent2 = parent2_key;
-
-                                // Add dependencies
-                                graph::add_children(
-                                    sheet,
-                                    parent1_key,
-                                    parent2_key,
-                                    formula,
-                                    row,
-                                    col,
-                                );
+                                },
+                                _ => {}
                             }
                         }
                     }
                 }
             }
-            _ => continue,
         }
+        "COLF" if parts.len() >= 5 => {
+            // Column formula line: COLF,col,first,last,template
+            let col = sheet.column_name_to_index(parts[1]);
+            let rows = match (parts[2].parse::<i16>(), parts[3].parse::<i16>()) {
+                (Ok(first), Ok(last)) if 1 <= first && first <= last => Some((first - 1, last - 1)),
+                _ if parts[3] == "*" => None,
+                _ => return,
+            };
+            if col < 0 || col >= sheet.cols || rows.is_some_and(|(_, last)| last >= sheet.rows) {
+                eprintln!("Warning: Column formula for {} out of bounds", parts[1]);
+                return;
+            }
+            sheet.column_formulas.insert(
+                col,
+                ColumnFormula {
+                    template: parts[4..].join(","),
+                    rows,
+                },
+            );
+        }
+        "OWN" if parts.len() == 3 => {
+            // Ownership tag line: OWN,range,owner
+            match parse_range(sheet, parts[1]) {
+                Ok(range) if is_valid_user(parts[2]) => sheet.ownership.tag(range, parts[2]),
+                _ => eprintln!("Warning: Ownership tag for {} is invalid", parts[1]),
+            }
+        }
+        "VIEW" if parts.len() == 3 => {
+            // Viewport line: VIEW,row,col, both 0-based
+            if let (Ok(row), Ok(col)) = (parts[1].parse::<i16>(), parts[2].parse::<i16>())
+                && (0..sheet.rows).contains(&row)
+                && (0..sheet.cols).contains(&col)
+            {
+                sheet.viewport_row = row;
+                sheet.viewport_col = col;
+            }
+        }
+        "NAME" if parts.len() == 3 => {
+            // Named range line: NAME,name,range; a name that is a cell here would hide it
+            match parse_range(sheet, parts[2]) {
+                Ok(range) if parse_cell_reference(sheet, parts[1]).is_err() => {
+                    sheet.named_ranges.insert(parts[1].to_string(), range);
+                }
+                _ => eprintln!("Warning: Name {} is invalid", parts[1]),
+            }
+        }
+        "LOCK" if parts.len() == 2 => {
+            // Lock line: LOCK,range
+            match parse_range(sheet, parts[1]) {
+                Ok(range) => sheet.lock_range(range),
+                Err(_) => eprintln!("Warning: Lock of {} is out of bounds", parts[1]),
+            }
+        }
+        _ => {}
     }
+}
 
+/// A sheet as a JSON document, the form `save_json` writes and `load_json` reads.
+///
+/// # Fields
+///
+/// * `rows`, `cols` - The dimensions.
+/// * `cells` - Every cell with a value or formula, row by row.
+/// * `names` - Named ranges, by name.
+/// * `locks` - Locked ranges, as they were given.
+#[cfg(feature = "json")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonSheet {
+    rows: i16,
+    cols: i16,
+    #[serde(default)]
+    cells: Vec<JsonCell>,
+    #[serde(default)]
+    names: std::collections::BTreeMap<String, String>,
+    #[serde(default)]
+    locks: Vec<String>,
+}
+
+/// One cell of a `JsonSheet`.
+///
+/// # Fields
+///
+/// * `cell` - The reference, such as `B3`.
+/// * `value` - A number or text; absent for an empty cell or an error.
+/// * `error` - The error's name, such as `DIV0`, for a cell holding one.
+/// * `formula` - The formula as it would be entered, without the `=`.
+#[cfg(feature = "json")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonCell {
+    cell: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<JsonValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    formula: Option<String>,
+}
+
+/// A cell value as JSON: a plain number or a string.
+#[cfg(feature = "json")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum JsonValue {
+    Number(i32),
+    Text(String),
+}
+
+/// Tells whether a file is saved and opened as JSON, by its extension.
+#[cfg(feature = "json")]
+fn is_json_file(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+/// Builds the JSON document of a sheet, with the same cells `write_sheet_records` writes.
+#[cfg(feature = "json")]
+fn json_document(sheet: &Spreadsheet) -> JsonSheet {
+    let mut cells = Vec::new();
+    for row in 0..sheet.rows {
+        for col in 0..sheet.cols {
+            let key = sheet.get_key(row, col);
+            let cell_value = sheet.get_cell(row, col);
+            let meta = sheet.cell_meta.get(&key);
+            if matches!(cell_value, CellValue::Empty) && meta.is_none() {
+                continue;
+            }
+            let (value, error) = match cell_value {
+                CellValue::Integer(val) => (Some(JsonValue::Number(*val)), None),
+                CellValue::Text(text) => (Some(JsonValue::Text(text.clone())), None),
+                CellValue::Error(kind) => (None, Some(kind.name().to_string())),
+                CellValue::Empty => (None, None),
+            };
+            let formula = meta
+                .filter(|meta| meta.formula != -1)
+                .map(|_| get_formula_string(sheet, row, col));
+            cells.push(JsonCell {
+                cell: format!("{}{}", sheet.get_column_name(col), row + 1),
+                value,
+                error,
+                formula,
+            });
+        }
+    }
+    JsonSheet {
+        rows: sheet.rows,
+        cols: sheet.cols,
+        cells,
+        names: sheet
+            .named_ranges
+            .iter()
+            .map(|(name, range)| (name.clone(), sheet.get_range_name(range)))
+            .collect(),
+        locks: sheet
+            .locked_ranges
+            .iter()
+            .map(|range| sheet.get_range_name(range))
+            .collect(),
+    }
+}
+
+/// Turns a JSON document into save file records, so it loads with the same checks as a
+/// `.sheet` file.
+///
+/// A name or lock may give a single cell, such as `B2`, for a one-cell range.
+#[cfg(feature = "json")]
+fn json_records(document: &JsonSheet) -> String {
+    let range_field = |range: &str| {
+        if range.contains(':') {
+            range.to_string()
+        } else {
+            format!("{}:{}", range, range)
+        }
+    };
+    let mut records = format!("DIMS,{},{}\n", document.rows, document.cols);
+    for (name, range) in &document.names {
+        records.push_str(&format!("NAME,{},{}\n", name, range_field(range)));
+    }
+    for range in &document.locks {
+        records.push_str(&format!("LOCK,{}\n", range_field(range)));
+    }
+    for cell in &document.cells {
+        let value = match (&cell.error, &cell.value) {
+            (Some(name), _) => format!("ERR:{}", name),
+            (None, Some(JsonValue::Number(val))) => val.to_string(),
+            (None, Some(JsonValue::Text(text))) => quote_field(text),
+            (None, None) => String::new(),
+        };
+        records.push_str(&format!("CELL,{},{}", cell.cell, value));
+        if let Some(formula) = &cell.formula {
+            records.push_str(&format!(",FORMULA,{}", formula));
+        }
+        records.push('\n');
+    }
+    records
+}
+
+/// Saves the spreadsheet as a JSON document: its dimensions, its cells with their values
+/// and formula text, and its names and locks.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet to save.
+/// * `filename` - The target file path.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If file operations fail
+#[cfg(feature = "json")]
+pub fn save_json(sheet: &Spreadsheet, filename: &str) -> CommandStatus {
+    save_with(sheet, filename, |sheet, writer| {
+        serde_json::to_writer_pretty(&mut *writer, &json_document(sheet))?;
+        writeln!(writer)
+    })
+}
+
+/// Loads a JSON document written by `save_json`, overwriting existing data.
+///
+/// The sheet is left alone if the file is not such a document; cells, names and locks
+/// that do not fit it are skipped with a warning, as in `load_spreadsheet`.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet to load into.
+/// * `filename` - The source file path.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success (even with partial data).
+/// * `CommandStatus::Unrecognized` - If the file cannot be opened or is not a sheet
+#[cfg(feature = "json")]
+pub fn load_json(sheet: &mut Spreadsheet, filename: &str) -> CommandStatus {
+    let Ok(file) = File::open(filename) else {
+        return CommandStatus::Unrecognized;
+    };
+    let document: JsonSheet = match serde_json::from_reader(BufReader::new(file)) {
+        Ok(document) => document,
+        Err(e) => {
+            eprintln!("'{}' is not a spreadsheet: {}", filename, e);
+            return CommandStatus::Unrecognized;
+        }
+    };
+    let records = json_records(&document);
+    sheet.clear();
+    read_records(sheet, &mut records.as_bytes(), |_, _| false);
+    sheet.cell_counts = sheet.scan_cell_counts();
     CommandStatus::CmdOk
 }
 
+/// Saves the spreadsheet, refusing to overwrite its own file if that file changed on disk.
+///
+/// When `filename` is the sheet's current file and its modification time or size no
+/// longer match what was recorded at the last save or open, someone else has written
+/// it, so the save is refused unless `force` is set. On success the file becomes the
+/// current file and its new stamp is recorded.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet to save.
+/// * `filename` - The target file path.
+/// * `force` - Overwrite even if the file changed externally.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::FileChanged` - If the file changed externally and `force` is not set.
+/// * `CommandStatus::Unrecognized` - If file operations fail
+pub fn save_tracked(sheet: &mut Spreadsheet, filename: &str, force: bool) -> CommandStatus {
+    if !force
+        && sheet.current_file.as_deref() == Some(filename)
+        && let Some(on_disk) = FileStamp::read(filename)
+        && sheet.file_stamp.as_ref() != Some(&on_disk)
+    {
+        eprintln!(
+            "'{}' was changed by another program since it was last saved or opened; \
+             use 'save --force' to overwrite it or 'saveas <file>' to keep both",
+            filename
+        );
+        return CommandStatus::FileChanged;
+    }
+
+    let status = save_spreadsheet(sheet, filename);
+    if status == CommandStatus::CmdOk {
+        track_file(sheet, filename);
+    }
+    status
+}
+
+/// Loads a spreadsheet and makes the file the sheet's current file.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet to load into.
+/// * `filename` - The source file path.
+///
+/// # Returns
+///
+/// * `CommandStatus` - As for `load_spreadsheet`
+pub fn open_tracked(sheet: &mut Spreadsheet, filename: &str) -> CommandStatus {
+    let status = load_spreadsheet(sheet, filename);
+    if status == CommandStatus::CmdOk {
+        track_file(sheet, filename);
+    }
+    status
+}
+
+/// Re-reads the sheet's current file, discarding in-memory changes.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet to reload.
+/// * `force` - Reload even if there are unsaved changes.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::UnsavedChanges` - If the sheet is dirty and `force` is not set.
+/// * `CommandStatus::Unrecognized` - If there is no current file or it cannot be read
+pub fn reload(sheet: &mut Spreadsheet, force: bool) -> CommandStatus {
+    let Some(filename) = sheet.current_file.clone() else {
+        eprintln!("No file to reload; use 'open <file>' first");
+        return CommandStatus::Unrecognized;
+    };
+    if sheet.dirty && !force {
+        eprintln!(
+            "The sheet has {} unsaved change(s); use 'reload!' to discard them",
+            sheet.changes_since_save(None).total()
+        );
+        return CommandStatus::UnsavedChanges;
+    }
+    open_tracked(sheet, &filename)
+}
+
+/// Handles the file commands `save`, `saveas`, `open`, `reload`, and the backup commands.
+///
+/// Supported forms: `save` (to the current file), `save <file>`, `save --force [<file>]`,
+/// `saveas <file>` (no external-change check), `open <file>`, `reload`, `reload!`,
+/// `backups <file>`, and `restore_backup[!] <file> <n>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `command` - The trimmed command.
+///
+/// # Returns
+///
+/// * `Some(CommandStatus)` - If `command` is a file command.
+/// * `None` - Otherwise
+pub fn handle_file_command(sheet: &mut Spreadsheet, command: &str) -> Option<CommandStatus> {
+    let (name, arg) = match command.split_once(' ') {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (command, ""),
+    };
+    let status = match name {
+        "save" => {
+            let (force, file) = match arg.strip_prefix("--force") {
+                Some(rest) => (true, rest.trim()),
+                None => (false, arg),
+            };
+            let file = match (file, sheet.current_file.clone()) {
+                ("", Some(current)) => current,
+                ("", None) => {
+                    eprintln!("No current file; use 'save <file>'");
+                    return Some(CommandStatus::Unrecognized);
+                }
+                (file, _) => file.to_string(),
+            };
+            save_tracked(sheet, &file, force)
+        }
+        "saveas" if !arg.is_empty() => save_tracked(sheet, arg, true),
+        "open" if !arg.is_empty() => {
+            let status = open_tracked(sheet, arg);
+            match status {
+                CommandStatus::CmdOk => sheet
+                    .output
+                    .line(format!("Spreadsheet successfully loaded from '{}'", arg)),
+                _ => eprintln!("Failed to load spreadsheet from '{}'", arg),
+            }
+            status
+        }
+        "reload" if arg.is_empty() => reload(sheet, false),
+        "reload!" if arg.is_empty() => reload(sheet, true),
+        "backups" if !arg.is_empty() => handle_backups(sheet, arg),
+        "restore_backup" | "restore_backup!" => {
+            // The number comes last, so the file name may contain spaces
+            let parsed = arg
+                .rsplit_once(' ')
+                .and_then(|(file, number)| Some((file.trim(), number.parse::<usize>().ok()?)));
+            match parsed {
+                Some((file, number)) if !file.is_empty() && number > 0 => {
+                    restore_backup(sheet, file, number, name == "restore_backup!")
+                }
+                _ => CommandStatus::Unrecognized,
+            }
+        }
+        _ => return None,
+    };
+    Some(status)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -316,6 +1399,32 @@ mod tests {
         let _ = fs::remove_file(path); // Ignore errors if file doesn't exist
     }
 
+    #[test]
+    fn test_load_recounts_cells() {
+        ensure_test_dir();
+        let filename = test_file_path("cell_counts");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut sleep_time = 0.0;
+        for command in ["A1=4", "B1=A1*2", "C1=A1-4"] {
+            process_command::process_command(&mut sheet, command, &mut sleep_time);
+        }
+        assert_eq!(save_spreadsheet(&sheet, &filename), CommandStatus::CmdOk);
+
+        let mut other = Spreadsheet::create(5, 5).unwrap();
+        process_command::process_command(&mut other, "E5=9", &mut sleep_time);
+        assert_eq!(
+            load_spreadsheet(&mut other, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(other.cell_counts, sheet.cell_counts);
+        assert_eq!(other.cell_counts.populated, 3);
+        assert_eq!(other.cell_counts.formulas, 2);
+
+        clean_test_file(&filename);
+    }
+
     #[test]
     fn test_save_empty_spreadsheet() {
         ensure_test_dir();
@@ -383,8 +1492,8 @@ mod tests {
         assert!(content.contains("DIMS,10,10"));
         assert!(content.contains("CELL,A1,10"));
         assert!(content.contains("CELL,B1,20"));
-        assert!(content.contains("CELL,C1,30,FORMULA,10,A1,B1")); // Sum formula
-        assert!(content.contains("CELL,D1,200,FORMULA,40,A1,B1")); // Multiply formula
+        assert!(content.contains("CELL,C1,30,FORMULA,A1+B1")); // Sum formula
+        assert!(content.contains("CELL,D1,200,FORMULA,A1*B1")); // Multiply formula
 
         // Clean up
         clean_test_file(&filename);
@@ -409,7 +1518,7 @@ mod tests {
 
         // Verify file content - fixed formula format expectation
         let content = fs::read_to_string(&filename).expect("Failed to read file");
-        assert!(content.contains("CELL,C1,ERR,FORMULA,30,A1,B1")); // Division formula with error
+        assert!(content.contains("CELL,C1,ERR:DIV0,FORMULA,A1/B1")); // Division formula with error
 
         // Clean up
         clean_test_file(&filename);
@@ -551,7 +1660,7 @@ mod tests {
         // Verify error cell
         match loaded_sheet.get_cell(0, 2) {
             // C1
-            CellValue::Error => {} // This is what we expect
+            CellValue::Error(ErrorKind::DivZero) => {} // This is what we expect
             _ => panic!("C1 should be Error"),
         }
 
@@ -569,6 +1678,100 @@ mod tests {
         clean_test_file(&filename);
     }
 
+    #[test]
+    fn test_error_kinds_round_trip() {
+        ensure_test_dir();
+        let filename = test_file_path("error_kinds");
+        clean_test_file(&filename);
+
+        let mut original_sheet = Spreadsheet::create(10, 10).unwrap();
+        for (row, kind) in ErrorKind::ALL.into_iter().enumerate() {
+            *original_sheet.get_mut_cell(row as i16, 0) = CellValue::Error(kind);
+        }
+        assert_eq!(
+            save_spreadsheet(&original_sheet, &filename),
+            CommandStatus::CmdOk
+        );
+        let content = fs::read_to_string(&filename).expect("Failed to read file");
+        assert!(content.contains("CELL,A3,ERR:CYCLE"));
+
+        let mut loaded_sheet = Spreadsheet::create(10, 10).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded_sheet, &filename),
+            CommandStatus::CmdOk
+        );
+        for (row, kind) in ErrorKind::ALL.into_iter().enumerate() {
+            assert_eq!(
+                *loaded_sheet.get_cell(row as i16, 0),
+                CellValue::Error(kind)
+            );
+        }
+
+        // A bare ERR from an older file loads as a parse error; an unknown kind is skipped
+        fs::write(&filename, "DIMS,5,5\nCELL,A1,ERR\nCELL,A2,ERR:NOPE\n").unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut sheet, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Error(ErrorKind::ParseErr));
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Empty);
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_split_record() {
+        assert_eq!(split_record("CELL,A1,5"), ["CELL", "A1", "5"]);
+        assert_eq!(
+            split_record("CELL,A1,\"Q1, \"\"net\"\"\",x"),
+            ["CELL", "A1", "\"Q1, \"\"net\"\"\"", "x"]
+        );
+        // A quote that does not open a field is kept as is
+        assert_eq!(split_record("NOTE,a\"b,c"), ["NOTE", "a\"b", "c"]);
+        assert_eq!(
+            unquote_field("\"Q1, \"\"net\"\"\""),
+            Some("Q1, \"net\"".to_string())
+        );
+        assert_eq!(unquote_field("5"), None);
+    }
+
+    #[test]
+    fn test_text_cells_round_trip() {
+        ensure_test_dir();
+        let filename = test_file_path("text_cells");
+        clean_test_file(&filename);
+
+        let mut original_sheet = Spreadsheet::create(10, 10).unwrap();
+        let mut time_elapsed = 0.0;
+        for command in [
+            "A1=\"Revenue, net\"",
+            "A2=\"say \"\"hi\"\"\"",
+            "B1=A1",
+            "C1=7",
+        ] {
+            process_command::process_command(&mut original_sheet, command, &mut time_elapsed);
+        }
+        save_spreadsheet(&original_sheet, &filename);
+
+        let mut loaded_sheet = Spreadsheet::create(10, 10).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded_sheet, &filename),
+            CommandStatus::CmdOk
+        );
+        let text = |value: &str| CellValue::Text(value.to_string());
+        assert_eq!(*loaded_sheet.get_cell(0, 0), text("Revenue, net"));
+        assert_eq!(*loaded_sheet.get_cell(1, 0), text("say \"hi\""));
+        assert_eq!(*loaded_sheet.get_cell(0, 1), text("Revenue, net"));
+        assert_eq!(*loaded_sheet.get_cell(0, 2), CellValue::Integer(7));
+
+        // The copy still follows its label after loading
+        process_command::process_command(&mut loaded_sheet, "A1=\"Costs\"", &mut time_elapsed);
+        assert_eq!(*loaded_sheet.get_cell(0, 1), text("Costs"));
+
+        clean_test_file(&filename);
+    }
+
     #[test]
     fn test_load_spreadsheet_nonexistent_file() {
         let mut sheet = Spreadsheet::create(10, 10).unwrap();
@@ -593,13 +1796,10 @@ mod tests {
         // Should still succeed but not load any data
         assert_eq!(result, CommandStatus::CmdOk);
 
-        // All cells should be empty (0)
+        // All cells should be empty
         for row in 0..sheet.rows {
             for col in 0..sheet.cols {
-                match sheet.get_cell(row, col) {
-                    CellValue::Integer(val) => assert_eq!(*val, 0),
-                    _ => panic!("Cell should be Integer(0)"),
-                }
+                assert_eq!(*sheet.get_cell(row, col), CellValue::Empty);
             }
         }
 
@@ -828,20 +2028,344 @@ mod tests {
             _ => panic!("B1 should be Integer(200)"),
         }
 
-        // Verify A2 and A3 are reset to 0
-        match sheet_to_overwrite.get_cell(1, 0) {
-            // A2
-            CellValue::Integer(val) => assert_eq!(*val, 0),
-            _ => panic!("A2 should be Integer(0)"),
+        // Verify A2 and A3 are cleared
+        assert_eq!(*sheet_to_overwrite.get_cell(1, 0), CellValue::Empty);
+        assert_eq!(*sheet_to_overwrite.get_cell(2, 0), CellValue::Empty);
+
+        // Clean up
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_save_load_relative_references() {
+        ensure_test_dir();
+        let filename = test_file_path("relative");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        let mut time_elapsed = 0.0;
+        process_command::process_command(&mut sheet, "A1=3", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "B1=4", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "C1=RC[-2]+B1", &mut time_elapsed);
+        assert_eq!(save_spreadsheet(&sheet, &filename), CommandStatus::CmdOk);
+
+        let content = fs::read_to_string(&filename).unwrap();
+        assert!(content.contains("CELL,C1,7,FORMULA,RC[-2]+B1"));
+
+        let mut loaded = Spreadsheet::create(10, 10).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            crate::extensions::get_formula_string(&loaded, 0, 2),
+            "RC[-2]+B1"
+        );
+        process_command::process_command(&mut loaded, "A1=10", &mut time_elapsed);
+        assert_eq!(*loaded.get_cell(0, 2), CellValue::Integer(14));
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_save_load_pinned_references() {
+        ensure_test_dir();
+        let filename = test_file_path("pinned");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        let mut time_elapsed = 0.0;
+        process_command::process_command(&mut sheet, "A1=3", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "B1=4", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "C1=$A1+B$1", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "C2=MAX($A$1:B1)", &mut time_elapsed);
+        assert_eq!(save_spreadsheet(&sheet, &filename), CommandStatus::CmdOk);
+
+        let mut loaded = Spreadsheet::create(10, 10).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(loaded.relative_refs, sheet.relative_refs);
+        assert_eq!(
+            crate::extensions::get_formula_string(&loaded, 0, 2),
+            "$A1+B$1"
+        );
+        assert_eq!(
+            crate::extensions::get_formula_string(&loaded, 1, 2),
+            "MAX($A$1:B1)"
+        );
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_save_load_extended_reference_flags() {
+        ensure_test_dir();
+        let filename = test_file_path("extended_flags");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        let mut time_elapsed = 0.0;
+        for command in ["A1=3", "B1=4", "C1=$A$1+B1*2", "D1=IF(RC[-1]>0,RC[-1],0)"] {
+            process_command::process_command(&mut sheet, command, &mut time_elapsed);
         }
+        assert_eq!(save_spreadsheet(&sheet, &filename), CommandStatus::CmdOk);
+
+        let content = fs::read_to_string(&filename).unwrap();
+        assert!(content.contains("CELL,C1,11,FORMULA,$A$1+B1*2"));
+        assert!(content.contains("CELL,D1,11,FORMULA,IF(RC[-1]>0,RC[-1],0)"));
+
+        let mut loaded = Spreadsheet::create(10, 10).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(loaded.extended_formulas, sheet.extended_formulas);
+        assert_eq!(
+            crate::extensions::get_formula_string(&loaded, 0, 3),
+            "IF(RC[-1]>0,RC[-1],0)"
+        );
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_save_load_sumif_criterion() {
+        ensure_test_dir();
+        let filename = test_file_path("sumif");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        let mut time_elapsed = 0.0;
+        process_command::process_command(&mut sheet, "A1=3", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "A2=12", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "B1=SUMIF(A1:A3,!=3)", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "B2=SUMIF(RC[-1]:A3,>=0)", &mut time_elapsed);
+        assert_eq!(save_spreadsheet(&sheet, &filename), CommandStatus::CmdOk);
+
+        let content = fs::read_to_string(&filename).unwrap();
+        assert!(content.contains("CELL,B1,12,FORMULA,SUMIF(A1:A3,!=3)"));
+        assert!(content.contains("CELL,B2,12,FORMULA,SUMIF(RC[-1]:A3,>=0)"));
+
+        let mut loaded = Spreadsheet::create(10, 10).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(loaded.criteria, sheet.criteria);
+        assert_eq!(
+            crate::extensions::get_formula_string(&loaded, 1, 1),
+            "SUMIF(RC[-1]:A3,>=0)"
+        );
+        process_command::process_command(&mut loaded, "A3=5", &mut time_elapsed);
+        assert_eq!(*loaded.get_cell(0, 1), CellValue::Integer(17));
+        assert_eq!(*loaded.get_cell(1, 1), CellValue::Integer(17));
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_save_load_keeps_assigned_zeros() {
+        ensure_test_dir();
+        let filename = test_file_path("assigned");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        let mut time_elapsed = 0.0;
+        process_command::process_command(&mut sheet, "A1=0", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "A2=7", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "B1=COUNTA(A1:A5)", &mut time_elapsed);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(2));
+        assert_eq!(save_spreadsheet(&sheet, &filename), CommandStatus::CmdOk);
+
+        let content = fs::read_to_string(&filename).unwrap();
+        assert!(content.contains("CELL,A1,0\n"));
+
+        let mut loaded = Spreadsheet::create(10, 10).unwrap();
+        process_command::process_command(&mut loaded, "A5=1", &mut time_elapsed);
+        assert_eq!(
+            load_spreadsheet(&mut loaded, &filename),
+            CommandStatus::CmdOk
+        );
+        // The load replaces the sheet's assigned cells with the file's
+        assert_eq!(loaded.assigned, sheet.assigned);
+        process_command::process_command(&mut loaded, "A3=0", &mut time_elapsed);
+        assert_eq!(*loaded.get_cell(0, 1), CellValue::Integer(3));
 
-        match sheet_to_overwrite.get_cell(2, 0) {
-            // A3
-            CellValue::Integer(val) => assert_eq!(*val, 0),
-            _ => panic!("A3 should be Integer(0)"),
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_save_load_names_locks_and_viewport() {
+        ensure_test_dir();
+        let filename = test_file_path("names_locks");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(30, 30).unwrap();
+        let mut time_elapsed = 0.0;
+        for command in [
+            "A1=5",
+            "B1=A1*2",
+            "name A1:B2 template",
+            "name B1 total",
+            "lock_cell template",
+            "lock_cell ROW 5",
+            "scroll_to C12",
+        ] {
+            assert_eq!(
+                process_command::process_command(&mut sheet, command, &mut time_elapsed),
+                CommandStatus::CmdOk,
+                "{}",
+                command
+            );
         }
+        assert_eq!(save_spreadsheet(&sheet, &filename), CommandStatus::CmdOk);
+        let content = fs::read_to_string(&filename).unwrap();
+        assert!(content.starts_with(
+            "DIMS,30,30\nVIEW,11,2\nNAME,template,A1:B2\nNAME,total,B1:B1\n\
+             LOCK,A1:B2\nLOCK,A5:AD5\n"
+        ));
+
+        let mut loaded = Spreadsheet::create(30, 30).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(loaded.named_ranges, sheet.named_ranges);
+        assert_eq!(loaded.locked_ranges, sheet.locked_ranges);
+        assert_eq!((loaded.viewport_row, loaded.viewport_col), (11, 2));
+        // The locked named range still blocks assignment, through its name too
+        for command in ["A1=7", "total=3", "template=0", "C5=1"] {
+            assert_eq!(
+                process_command::process_command(&mut loaded, command, &mut time_elapsed),
+                CommandStatus::LockedCell,
+                "{}",
+                command
+            );
+        }
+        assert_eq!(*loaded.get_cell(0, 1), CellValue::Integer(10));
+        assert_eq!(
+            process_command::process_command(&mut loaded, "C1=total+1", &mut time_elapsed),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*loaded.get_cell(0, 2), CellValue::Integer(11));
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_load_skips_unknown_and_invalid_records() {
+        ensure_test_dir();
+        let filename = test_file_path("future_records");
+        fs::write(
+            &filename,
+            "DIMS,5,5\nVIEW,9,9\nBOOKMARK,top,A1\nNAME,B2,A1:A2\nNAME,far,A1:Z99\n\
+             NAME,ok,A1:A2\nLOCK,A1:Z99\nLOCK,C3:C3\nCELL,A1,3\n",
+        )
+        .unwrap();
+
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut sheet, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(3));
+        assert_eq!((sheet.viewport_row, sheet.viewport_col), (0, 0));
+        let names: Vec<&String> = sheet.named_ranges.keys().collect();
+        assert_eq!(names, ["ok"]);
+        assert_eq!(sheet.locked_ranges, [Range::single(2, 2)]);
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_save_load_extended_formulas() {
+        ensure_test_dir();
+        let filename = test_file_path("if_formula");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        let mut time_elapsed = 0.0;
+        process_command::process_command(&mut sheet, "B1=12", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "A1=IF(B1>=-5,C1,B1)", &mut time_elapsed);
+        process_command::process_command(
+            &mut sheet,
+            "D1=SUMPRODUCT(B1:C1,B2:C2)",
+            &mut time_elapsed,
+        );
+        process_command::process_command(&mut sheet, "E1=B1-SUMIF(B1:B3,>0)", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "F1=SLEEP(B1-12)", &mut time_elapsed);
+        assert_eq!(save_spreadsheet(&sheet, &filename), CommandStatus::CmdOk);
+
+        let content = fs::read_to_string(&filename).unwrap();
+        assert!(content.contains("CELL,A1,0,FORMULA,IF(B1>=-5,C1,B1)"));
+        assert!(content.contains("CELL,D1,0,FORMULA,SUMPRODUCT(B1:C1,B2:C2)"));
+        assert!(content.contains("CELL,E1,0,FORMULA,B1-SUMIF(B1:B3,>0)"));
+        assert!(content.contains("CELL,F1,0,FORMULA,SLEEP(B1-12)"));
+
+        let mut loaded = Spreadsheet::create(10, 10).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(loaded.extended_formulas, sheet.extended_formulas);
+        assert_eq!(
+            crate::extensions::get_formula_string(&loaded, 0, 0),
+            "IF(B1>=-5,C1,B1)"
+        );
+        process_command::process_command(&mut loaded, "C1=4", &mut time_elapsed);
+        assert_eq!(*loaded.get_cell(0, 0), CellValue::Integer(4));
+        process_command::process_command(&mut loaded, "B1=-9", &mut time_elapsed);
+        assert_eq!(*loaded.get_cell(0, 0), CellValue::Integer(-9));
+        process_command::process_command(&mut loaded, "C2=2", &mut time_elapsed);
+        assert_eq!(*loaded.get_cell(0, 3), CellValue::Integer(8));
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_load_keeps_formulas_over_range_limit() {
+        ensure_test_dir();
+        let filename = test_file_path("range_limit");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(20, 20).unwrap();
+        let mut time_elapsed = 0.0;
+        process_command::process_command(&mut sheet, "A1=4", &mut time_elapsed);
+        process_command::process_command(&mut sheet, "T20=SUM(A1:S19)", &mut time_elapsed);
+        save_spreadsheet(&sheet, &filename);
+
+        // The loaded sheet has a lower limit, so the formula is only warned about
+        let mut loaded = Spreadsheet::create(20, 20).unwrap();
+        loaded.output = crate::output::OutputSink::capture();
+        loaded.range_cell_limit = 10;
+        assert_eq!(
+            load_spreadsheet(&mut loaded, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            loaded.output.take(),
+            vec!["Warning: Range covers 361 cells, above the limit of 10 in T20"]
+        );
+        process_command::process_command(&mut loaded, "A1=6", &mut time_elapsed);
+        assert_eq!(*loaded.get_cell(19, 19), CellValue::Integer(6));
+
+        // Files in the format before formulas were saved as text warn the same way
+        std::fs::write(
+            &filename,
+            "DIMS,20,20\nCELL,A1,4\nCELL,B1,4,FORMULA,5,A1,S19\n",
+        )
+        .unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            loaded.output.take(),
+            vec!["Warning: Range covers 361 cells, above the limit of 10 in B1"]
+        );
 
-        // Clean up
         clean_test_file(&filename);
     }
 
@@ -857,13 +2381,13 @@ mod tests {
         }
         impl BufRead for ErrorLineReader {
             fn fill_buf(&mut self) -> io::Result<&[u8]> {
-                Err(io::Error::new(io::ErrorKind::Other, "simulated error"))
+                Err(io::Error::other("simulated error"))
             }
             fn consume(&mut self, _amt: usize) {}
         }
         impl Read for ErrorLineReader {
             fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
-                Err(io::Error::new(io::ErrorKind::Other, "simulated error"))
+                Err(io::Error::other("simulated error"))
             }
         }
         impl Iterator for ErrorLineReader {
@@ -889,10 +2413,7 @@ mod tests {
         let mut reader = ErrorLineReader {
             lines: vec![
                 Ok("DIMS,5,5".to_string()),
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "simulated error",
-                )),
+                Err(std::io::Error::other("simulated error")),
                 Ok("CELL,A1,42".to_string()),
             ],
             idx: 0,
@@ -929,19 +2450,16 @@ mod tests {
             },
         );
         // Add a value to the cell
-        let idx = sheet.get_index(1, 1);
-        sheet.grid[idx] = CellValue::Integer(99);
+        *sheet.get_mut_cell(1, 1) = CellValue::Integer(99);
 
         // Call the clear logic (simulate the loop in load_spreadsheet)
         for row in 0..sheet.rows {
             for col in 0..sheet.cols {
                 let key = sheet.get_key(row, col);
-                let idx = sheet.get_index(row, col);
-                sheet.grid[idx] = CellValue::Integer(0);
+                *sheet.get_mut_cell(row, col) = CellValue::Integer(0);
                 if sheet.cell_meta.contains_key(&key) {
-                    // This should invoke the remove_all_parents and remove
-                    graph::remove_all_parents(&mut sheet, row, col);
-                    sheet.cell_meta.remove(&key);
+                    // This should remove the parents and then the metadata
+                    sheet.clear_formula(row, col);
                 }
             }
         }
@@ -950,4 +2468,647 @@ mod tests {
         // And the cell value should be 0
         assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(0));
     }
+
+    /// Runs a command through the REPL path and returns its status.
+    fn run(sheet: &mut Spreadsheet, command: &str) -> CommandStatus {
+        let mut sleep_time = 0.0;
+        process_command::process_command(sheet, command, &mut sleep_time)
+    }
+
+    /// Pushes a file's modification time an hour into the past, as another program might.
+    fn age_file(path: &str) {
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        let earlier = SystemTime::now() - std::time::Duration::from_secs(3600);
+        file.set_modified(earlier).unwrap();
+    }
+
+    #[test]
+    fn test_save_refuses_file_changed_externally() {
+        ensure_test_dir();
+        let filename = test_file_path("external_change");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(run(&mut sheet, "A1=1"), CommandStatus::CmdOk);
+        assert_eq!(
+            run(&mut sheet, &format!("save {}", filename)),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.current_file.as_deref(), Some(filename.as_str()));
+        assert!(!sheet.dirty);
+
+        // Saving again with nothing changed on disk is fine
+        assert_eq!(run(&mut sheet, "A1=2"), CommandStatus::CmdOk);
+        assert!(sheet.dirty);
+        assert_eq!(run(&mut sheet, "save"), CommandStatus::CmdOk);
+
+        // Someone else rewrites the file
+        fs::write(&filename, "DIMS,5,5\nCELL,B2,42\n").unwrap();
+        age_file(&filename);
+        assert_eq!(run(&mut sheet, "A1=3"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "save"), CommandStatus::FileChanged);
+        assert_eq!(
+            run(&mut sheet, &format!("save {}", filename)),
+            CommandStatus::FileChanged
+        );
+        assert!(
+            fs::read_to_string(&filename)
+                .unwrap()
+                .contains("CELL,B2,42")
+        );
+        assert!(sheet.dirty);
+
+        // Forcing overwrites and records the new stamp
+        assert_eq!(run(&mut sheet, "save --force"), CommandStatus::CmdOk);
+        assert!(!fs::read_to_string(&filename).unwrap().contains("B2"));
+        assert_eq!(run(&mut sheet, "save"), CommandStatus::CmdOk);
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_save_detects_mtime_only_change_and_saveas_bypasses() {
+        ensure_test_dir();
+        let filename = test_file_path("mtime_change");
+        let other = test_file_path("mtime_change_copy");
+        clean_test_file(&filename);
+        clean_test_file(&other);
+
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(
+            run(&mut sheet, &format!("save {}", filename)),
+            CommandStatus::CmdOk
+        );
+        age_file(&filename);
+        assert_eq!(run(&mut sheet, "save"), CommandStatus::FileChanged);
+
+        // saveas writes wherever it is told and moves the current file there
+        assert_eq!(
+            run(&mut sheet, &format!("saveas {}", other)),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(sheet.current_file.as_deref(), Some(other.as_str()));
+        assert_eq!(run(&mut sheet, "save"), CommandStatus::CmdOk);
+
+        // Opening refreshes the recorded stamp, so the aged file can be saved again
+        assert_eq!(
+            run(&mut sheet, &format!("open {}", filename)),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(run(&mut sheet, "save"), CommandStatus::CmdOk);
+        assert_eq!(sheet.file_stamp, FileStamp::read(&filename));
+
+        clean_test_file(&filename);
+        clean_test_file(&other);
+    }
+
+    #[test]
+    fn test_reload_honors_dirty_flag() {
+        ensure_test_dir();
+        let filename = test_file_path("reload");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(run(&mut sheet, "reload"), CommandStatus::Unrecognized);
+        assert_eq!(run(&mut sheet, "save"), CommandStatus::Unrecognized);
+
+        assert_eq!(run(&mut sheet, "A1=5"), CommandStatus::CmdOk);
+        assert_eq!(
+            run(&mut sheet, &format!("save {}", filename)),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(run(&mut sheet, "reload"), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(5));
+
+        // Unsaved edits block a plain reload
+        assert_eq!(run(&mut sheet, "A1=9"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "reload"), CommandStatus::UnsavedChanges);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(9));
+
+        // reload! discards them and picks up external edits
+        fs::write(&filename, "DIMS,5,5\nCELL,A1,7\n").unwrap();
+        assert_eq!(run(&mut sheet, "reload!"), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(7));
+        assert!(!sheet.dirty);
+        assert_eq!(run(&mut sheet, "save"), CommandStatus::CmdOk);
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_read_save_line_limits() {
+        let mut input = "short\r\n0123456789\n\nlast".as_bytes();
+        let mut next = || read_save_line(&mut input, 8).unwrap();
+        assert_eq!(next(), Some(SaveLine::Line("short".to_string())));
+        assert_eq!(next(), Some(SaveLine::TooLong(10)));
+        assert_eq!(next(), Some(SaveLine::Line(String::new())));
+        assert_eq!(next(), Some(SaveLine::Line("last".to_string())));
+        assert_eq!(next(), None);
+
+        let mut input: &[u8] = b"\xff\xfe\nok\n";
+        assert_eq!(
+            read_save_line(&mut input, 8).unwrap(),
+            Some(SaveLine::Invalid)
+        );
+        assert_eq!(
+            read_save_line(&mut input, 8).unwrap(),
+            Some(SaveLine::Line("ok".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_long_records_saved_whole_and_capped_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("long.sheet");
+        let path = path.to_str().unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        run(&mut sheet, "A1=7");
+        let template = format!("A{}", "+A".repeat(50_000));
+        sheet.column_formulas.insert(
+            2,
+            ColumnFormula {
+                template: template.clone(),
+                rows: None,
+            },
+        );
+        run(&mut sheet, "B1=A1*2");
+        assert_eq!(save_spreadsheet(&sheet, path), CommandStatus::CmdOk);
+
+        // The writer puts the whole record on one line
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert!(contents.contains(&format!("COLF,C,,*,{}\n", template)));
+
+        // Over the limit the record is skipped and the rest still loads
+        let mut loaded = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(load_spreadsheet(&mut loaded, path), CommandStatus::CmdOk);
+        assert!(loaded.column_formulas.is_empty());
+        assert_eq!(*loaded.get_cell(0, 1), CellValue::Integer(14));
+
+        // A higher limit reads it back in full
+        run(&mut loaded, "set_limit line_bytes 1000000");
+        assert_eq!(load_spreadsheet(&mut loaded, path), CommandStatus::CmdOk);
+        assert_eq!(loaded.column_formulas[&2].template, template);
+    }
+
+    /// Reads A1 of a save file, to tell saved versions apart.
+    fn saved_a1(path: &str) -> Option<CellValue> {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        (load_spreadsheet(&mut sheet, path) == CommandStatus::CmdOk)
+            .then(|| sheet.get_cell(0, 0).clone())
+    }
+
+    /// Saves the sheet with A1 set to each value in turn.
+    fn save_versions(sheet: &mut Spreadsheet, path: &str, values: std::ops::RangeInclusive<i32>) {
+        for value in values {
+            assert_eq!(run(sheet, &format!("A1={}", value)), CommandStatus::CmdOk);
+            assert_eq!(run(sheet, &format!("save {}", path)), CommandStatus::CmdOk);
+        }
+    }
+
+    #[test]
+    fn test_backup_rotation_and_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rot.sheet");
+        let path = path.to_str().unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(run(&mut sheet, "set backups 3"), CommandStatus::CmdOk);
+
+        save_versions(&mut sheet, path, 1..=2);
+        assert_eq!(saved_a1(path), Some(CellValue::Integer(2)));
+        assert_eq!(saved_a1(&backup_path(path, 1)), Some(CellValue::Integer(1)));
+        assert_eq!(saved_a1(&backup_path(path, 2)), None);
+
+        // The cap evicts the oldest version
+        save_versions(&mut sheet, path, 3..=5);
+        assert_eq!(saved_a1(path), Some(CellValue::Integer(5)));
+        for (number, value) in [(1, 4), (2, 3), (3, 2)] {
+            assert_eq!(
+                saved_a1(&backup_path(path, number)),
+                Some(CellValue::Integer(value))
+            );
+        }
+        assert!(!Path::new(&backup_path(path, 4)).exists());
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        // A hole in the numbering is skipped
+        fs::remove_file(backup_path(path, 2)).unwrap();
+        save_versions(&mut sheet, path, 6..=6);
+        assert_eq!(saved_a1(&backup_path(path, 1)), Some(CellValue::Integer(5)));
+        assert_eq!(saved_a1(&backup_path(path, 2)), Some(CellValue::Integer(4)));
+        assert!(!Path::new(&backup_path(path, 3)).exists());
+
+        // With backups off, saves leave the existing ones alone
+        assert_eq!(run(&mut sheet, "set backups 0"), CommandStatus::CmdOk);
+        save_versions(&mut sheet, path, 7..=7);
+        assert_eq!(saved_a1(&backup_path(path, 1)), Some(CellValue::Integer(5)));
+        assert_eq!(
+            run(&mut sheet, "set backups 100"),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_failed_save_does_not_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fail.sheet");
+        let path = path.to_str().unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        run(&mut sheet, "set backups 2");
+        save_versions(&mut sheet, path, 1..=2);
+
+        // The temporary file cannot be created, so the save fails before rotating
+        fs::create_dir(format!("{}.tmp", path)).unwrap();
+        run(&mut sheet, "A1=3");
+        assert_eq!(
+            run(&mut sheet, &format!("save {}", path)),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(saved_a1(path), Some(CellValue::Integer(2)));
+        assert_eq!(saved_a1(&backup_path(path, 1)), Some(CellValue::Integer(1)));
+        assert!(!Path::new(&backup_path(path, 2)).exists());
+    }
+
+    #[test]
+    fn test_list_and_restore_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("list.sheet");
+        let path = path.to_str().unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        sheet.output = crate::output::OutputSink::capture();
+        assert_eq!(
+            run(&mut sheet, &format!("backups {}", path)),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(
+            sheet.output.take(),
+            vec![format!("No backups of '{}'", path)]
+        );
+
+        run(&mut sheet, "set backups 5");
+        save_versions(&mut sheet, path, 1..=4);
+        // Another file with a similar name is not a backup
+        fs::write(format!("{}.bak", path), "x").unwrap();
+        run(&mut sheet, &format!("backups {}", path));
+        let lines = sheet.output.take();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("backup"));
+        for (line, number) in lines[2..].iter().zip(1..) {
+            let size = fs::metadata(backup_path(path, number)).unwrap().len();
+            assert!(line.starts_with(&backup_path(path, number)));
+            assert!(line.ends_with(&format!(" {}", size)));
+        }
+
+        // Restoring needs a clean sheet, or the forced form
+        run(&mut sheet, "A1=9");
+        let restore = format!("restore_backup {} 2", path);
+        assert_eq!(run(&mut sheet, &restore), CommandStatus::UnsavedChanges);
+        assert_eq!(
+            run(&mut sheet, &format!("restore_backup! {} 2", path)),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(2));
+        assert_eq!(sheet.current_file.as_deref(), Some(path));
+        assert!(sheet.dirty);
+        assert_eq!(sheet.changes_since_save(None).total(), 1);
+
+        // Saving writes the restored cells back, rotating as usual
+        assert_eq!(run(&mut sheet, "save"), CommandStatus::CmdOk);
+        assert_eq!(saved_a1(path), Some(CellValue::Integer(2)));
+        assert_eq!(saved_a1(&backup_path(path, 1)), Some(CellValue::Integer(4)));
+
+        assert_eq!(
+            run(&mut sheet, &format!("restore_backup {} 9", path)),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            run(&mut sheet, &format!("restore_backup {}", path)),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_format_utc() {
+        let at = |secs| SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        assert_eq!(format_utc(at(0)), "1970-01-01 00:00:00");
+        assert_eq!(format_utc(at(951_782_400)), "2000-02-29 00:00:00");
+        assert_eq!(format_utc(at(1_700_000_000)), "2023-11-14 22:13:20");
+    }
+
+    #[test]
+    fn test_load_formulas_into_wider_and_narrower_sheets() {
+        ensure_test_dir();
+        let filename = test_file_path("other_width");
+        clean_test_file(&filename);
+
+        let mut sheet = Spreadsheet::create(6, 6).unwrap();
+        let mut sleep_time = 0.0;
+        for command in [
+            "A1=4",
+            "A2=5",
+            "A5=1",
+            "B1=A1+25",
+            "B2=7*A2",
+            "B3=2+3",
+            "C1=SUM(A1:B2)",
+            "C2=MAX(A1:A2)",
+            "C3=SUM(A1:A5)",
+            "D1=A1",
+        ] {
+            process_command::process_command(&mut sheet, command, &mut sleep_time);
+        }
+        assert_eq!(save_spreadsheet(&sheet, &filename), CommandStatus::CmdOk);
+        let content = fs::read_to_string(&filename).unwrap();
+        assert!(content.contains("CELL,B1,29,FORMULA,A1+25\n"));
+        assert!(content.contains("CELL,B2,35,FORMULA,7*A2\n"));
+        assert!(content.contains("CELL,B3,5,FORMULA,2+3\n"));
+        assert!(content.contains("CELL,D1,4,FORMULA,A1\n"));
+
+        let formulas = [
+            ("B1", "A1+25"),
+            ("B2", "7*A2"),
+            ("C1", "SUM(A1:B2)"),
+            ("C2", "MAX(A1:A2)"),
+            ("D1", "A1"),
+        ];
+        // Wider and taller, then narrower and shorter, cutting off A5
+        for (rows, cols) in [(12, 20), (4, 4)] {
+            let mut loaded = Spreadsheet::create(rows, cols).unwrap();
+            assert_eq!(
+                load_spreadsheet(&mut loaded, &filename),
+                CommandStatus::CmdOk
+            );
+            for (cell, formula) in formulas {
+                let (row, col) = parse_cell_reference(&loaded, cell).unwrap();
+                assert_eq!(
+                    crate::extensions::get_formula_string(&loaded, row, col),
+                    formula
+                );
+            }
+            // 2+3 is two literals, not the cells at keys 2 and 3
+            let key = loaded.get_key(2, 1);
+            let meta = loaded.get_cell_meta_ref(2, 1);
+            assert_eq!((meta.formula, meta.parent1, meta.parent2), (10, 2, 3));
+            assert!(!graph::parents_are_cells(&loaded.children, key, meta));
+            assert_eq!(*loaded.get_cell(0, 2), CellValue::Integer(73));
+
+            process_command::process_command(&mut loaded, "A1=10", &mut sleep_time);
+            assert_eq!(*loaded.get_cell(0, 1), CellValue::Integer(35));
+            assert_eq!(*loaded.get_cell(0, 2), CellValue::Integer(85));
+            assert_eq!(*loaded.get_cell(1, 2), CellValue::Integer(10));
+            assert_eq!(*loaded.get_cell(0, 3), CellValue::Integer(10));
+            // A SUM over A1:A5 only survives where A5 exists
+            let sum = if rows >= 5 { 16 } else { 10 };
+            assert_eq!(*loaded.get_cell(2, 2), CellValue::Integer(sum));
+        }
+        let mut narrow = Spreadsheet::create(4, 4).unwrap();
+        load_spreadsheet(&mut narrow, &filename);
+        assert_eq!(
+            crate::extensions::get_formula_string(&narrow, 2, 2),
+            "No formula"
+        );
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_load_literals_saved_as_cells() {
+        ensure_test_dir();
+        let filename = test_file_path("legacy_literals");
+        clean_test_file(&filename);
+
+        // Older files wrote a literal as the cell whose key it equalled: F3 is key 25
+        // on a sheet 10 columns wide
+        fs::write(
+            &filename,
+            "DIMS,5,10\nCELL,A1,4\nCELL,B1,29,FORMULA,12,A1,F3\nCELL,C1,3,FORMULA,43,D1,A1\n",
+        )
+        .unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut sheet, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(crate::extensions::get_formula_string(&sheet, 0, 1), "A1+25");
+        assert_eq!(crate::extensions::get_formula_string(&sheet, 0, 2), "3*A1");
+        let mut sleep_time = 0.0;
+        process_command::process_command(&mut sheet, "A1=5", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(30));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(15));
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_formulas_saved_as_text_round_trip() {
+        ensure_test_dir();
+        let filename = test_file_path("formula_text");
+        clean_test_file(&filename);
+
+        let formulas = [
+            ("C1", "A1+B1"),
+            ("C2", "A1*-3"),
+            ("C3", "-4-A2"),
+            ("C4", "2+3"),
+            ("C5", "A2"),
+            ("C6", "ABS(C3)"),
+            ("C7", "MAX2(A1,9)"),
+            ("C8", "A1>=B1"),
+            ("C9", "SUM(A1:B2)"),
+            ("D1", "MEDIAN(A1:A2)"),
+            ("D2", "COUNTA(A1:B3)"),
+            ("D3", "SUMIF(A1:B2,>5)"),
+            ("D4", "IF(A1>B1,A1,B1)"),
+            ("D5", "SLEEP(B2)"),
+            ("D6", "R[-1]C+$A$2"),
+        ];
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        let mut sleep_time = 0.0;
+        for command in ["A1=4", "A2=7", "B1=10", "B2=0"] {
+            process_command::process_command(&mut sheet, command, &mut sleep_time);
+        }
+        for (cell, formula) in formulas {
+            let command = format!("{}={}", cell, formula);
+            assert_eq!(
+                process_command::process_command(&mut sheet, &command, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                command
+            );
+        }
+        assert_eq!(save_spreadsheet(&sheet, &filename), CommandStatus::CmdOk);
+        let content = fs::read_to_string(&filename).unwrap();
+        for (cell, formula) in formulas {
+            let start = format!("CELL,{},", cell);
+            let end = format!(",FORMULA,{}", formula);
+            assert!(
+                content
+                    .lines()
+                    .any(|line| line.starts_with(&start) && line.ends_with(&end)),
+                "{}",
+                cell
+            );
+        }
+
+        let mut loaded = Spreadsheet::create(10, 10).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded, &filename),
+            CommandStatus::CmdOk
+        );
+        for (cell, formula) in formulas {
+            let (row, col) = parse_cell_reference(&loaded, cell).unwrap();
+            assert_eq!(
+                crate::extensions::get_formula_string(&loaded, row, col),
+                formula
+            );
+            assert_eq!(
+                loaded.get_cell(row, col),
+                sheet.get_cell(row, col),
+                "{}",
+                cell
+            );
+        }
+        assert_eq!(loaded.children, sheet.children);
+
+        clean_test_file(&filename);
+    }
+
+    #[test]
+    fn test_load_formula_text_in_any_order() {
+        ensure_test_dir();
+        let filename = test_file_path("formula_order");
+        clean_test_file(&filename);
+
+        // Each formula comes before the cells it reads, with a stale value
+        fs::write(
+            &filename,
+            "DIMS,5,5\nCELL,A1,0,FORMULA,SUM(B1:B3)\nCELL,B1,0,FORMULA,C1*2\n\
+             CELL,B2,0,FORMULA,B1+C2\nCELL,C1,3\nCELL,C2,4\nCELL,D1,0,FORMULA,E9+1\n",
+        )
+        .unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut sheet, &filename),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(6));
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(10));
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(16));
+        // E9 is not on a 5x5 sheet: the value stays, without a formula
+        assert_eq!(*sheet.get_cell(0, 3), CellValue::Integer(0));
+        assert_eq!(
+            crate::extensions::get_formula_string(&sheet, 0, 3),
+            "No formula"
+        );
+
+        let mut sleep_time = 0.0;
+        process_command::process_command(&mut sheet, "C1=1", &mut sleep_time);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(8));
+
+        clean_test_file(&filename);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_round_trip() {
+        ensure_test_dir();
+        let filename = "test_files/test_round_trip.json";
+        clean_test_file(filename);
+
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        let mut time_elapsed = 0.0;
+        for command in [
+            "A1=4",
+            "A2=\"say \"\"hi\"\", twice\"",
+            "B1=A1*3",
+            "B2=A1/0",
+            "C1=SUM(A1:B1)",
+            "name A1:B2 block",
+            "name C1 total",
+            "lock_cell C3",
+        ] {
+            assert_eq!(
+                process_command::process_command(&mut sheet, command, &mut time_elapsed),
+                CommandStatus::CmdOk,
+                "{}",
+                command
+            );
+        }
+        assert_eq!(save_spreadsheet(&sheet, filename), CommandStatus::CmdOk);
+        let document: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(filename).unwrap()).unwrap();
+        assert_eq!(document["rows"], 10);
+        assert_eq!(
+            document["cells"][0],
+            serde_json::json!({"cell": "A1", "value": 4})
+        );
+        assert_eq!(
+            document["cells"][1],
+            serde_json::json!({"cell": "B1", "value": 12, "formula": "A1*3"})
+        );
+        assert_eq!(
+            document["cells"][4],
+            serde_json::json!({"cell": "B2", "error": "DIV0", "formula": "A1/0"})
+        );
+        assert_eq!(document["names"]["total"], "C1:C1");
+        assert_eq!(document["locks"], serde_json::json!(["C3:C3"]));
+
+        let mut loaded = Spreadsheet::create(10, 10).unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut loaded, filename),
+            CommandStatus::CmdOk
+        );
+        for row in 0..3 {
+            for col in 0..3 {
+                assert_eq!(loaded.get_cell(row, col), sheet.get_cell(row, col));
+            }
+        }
+        assert_eq!(loaded.children, sheet.children);
+        assert_eq!(loaded.named_ranges, sheet.named_ranges);
+        assert_eq!(loaded.locked_ranges, sheet.locked_ranges);
+        // The loaded formulas still follow their cells
+        process_command::process_command(&mut loaded, "A1=5", &mut time_elapsed);
+        assert_eq!(*loaded.get_cell(0, 2), CellValue::Integer(20));
+
+        clean_test_file(filename);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_load_json_written_by_hand() {
+        ensure_test_dir();
+        let filename = "test_files/test_by_hand.json";
+        fs::write(
+            filename,
+            r#"{
+                "rows": 5,
+                "cols": 5,
+                "cells": [
+                    {"cell": "B1", "formula": "A1+A2"},
+                    {"cell": "A1", "value": 2},
+                    {"cell": "A2", "value": 3},
+                    {"cell": "Z9", "value": 1},
+                    {"cell": "C1", "value": "label"}
+                ],
+                "names": {"pair": "A1:A2", "one": "C1"},
+                "locks": ["A2"]
+            }"#,
+        )
+        .unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(load_spreadsheet(&mut sheet, filename), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(5));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Text("label".to_string()));
+        assert_eq!(sheet.named_ranges["one"], Range::single(0, 2));
+        assert!(sheet.is_cell_locked(1, 0));
+
+        // A file that is not a sheet leaves the current one alone
+        fs::write(filename, "{\"cells\": []}").unwrap();
+        assert_eq!(
+            load_spreadsheet(&mut sheet, filename),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(5));
+
+        clean_test_file(filename);
+    }
 }
@@ -0,0 +1,639 @@
+//! Command-layer scripting: session variables and running command files.
+//!
+//! `let threshold = 100` defines a variable and `$threshold` is replaced by its value
+//! in every later command before it is parsed. Variables hold integers and live only
+//! for the session; they are never stored in cells or saved with the sheet. `run`
+//! executes a file of commands, optionally with variables injected via `--var`.
+
+use crate::cell::CellValue;
+use crate::evaluator::resolve_cell_reference;
+use crate::process_command::{process_command, split_commands};
+use crate::spreadsheet::{CommandStatus, MAX_COLS, Spreadsheet};
+use crate::table::{Align, Table};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// Longest command allowed after substitution.
+const MAX_COMMAND_LEN: usize = 4096;
+/// How deeply `run` may nest inside scripts.
+const MAX_SCRIPT_DEPTH: usize = 8;
+
+/// A variable name and value, as given to `run` with `--var name=value`.
+pub type VarBinding = (String, i32);
+
+/// Variables and script nesting for one session.
+///
+/// # Fields
+///
+/// * `vars` - Variable values by name.
+/// * `depth` - Number of scripts currently running.
+#[derive(Debug, Default)]
+pub struct ScriptState {
+    pub vars: BTreeMap<String, i32>,
+    depth: usize,
+}
+
+impl ScriptState {
+    /// Checks whether a script is running, so nobody is at the prompt to answer questions.
+    pub fn is_running(&self) -> bool {
+        self.depth > 0
+    }
+}
+
+/// Checks that a variable name is a letter or `_` followed by letters, digits, or `_`.
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Checks that a variable name does not read as a column or a cell, which `$` pins in
+/// references such as `$A$1` and `$a1`; a variable `A` would replace that `$A`.
+///
+/// References ignore case and a file may be opened into a wider sheet later, so every
+/// column name up to `MAX_COLS` is refused, in any case.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the name can be used.
+/// * `Err(CommandStatus::Unrecognized)` - If it reads as a column or a cell
+fn check_not_column(sheet: &Spreadsheet, name: &str) -> Result<(), CommandStatus> {
+    if is_pinned_reference(sheet, name, Some('$')) {
+        eprintln!(
+            "{} reads as a column or cell reference and cannot name a variable",
+            name
+        );
+        return Err(CommandStatus::Unrecognized);
+    }
+    Ok(())
+}
+
+/// Checks whether `$name` is really a pinned cell reference such as `$A1` or `$a1`, or the
+/// column part of `$A$1`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet, for reading column names.
+/// * `name` - The text after the `$`.
+/// * `next` - The character after `name`, if any.
+fn is_pinned_reference(sheet: &Spreadsheet, name: &str, next: Option<char>) -> bool {
+    let letters = name.len()
+        - name
+            .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+            .len();
+    let digits = &name[letters..];
+    (0..MAX_COLS).contains(&sheet.column_name_to_index(&name[..letters]))
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && (!digits.is_empty() || next == Some('$'))
+}
+
+/// Replaces each `$name` in a command with the variable's value.
+///
+/// Text inside double quotes is left alone, as are names that read as pinned cell
+/// references in either case (`$A1`, `$a$1`); no variable is named like one. Values are
+/// integers, so the result never contains new `$` references and a single pass is
+/// enough.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet holding the variables.
+/// * `command` - The command as typed.
+///
+/// # Returns
+///
+/// * `Ok(command)` - The command with variables substituted (borrowed if it has none).
+/// * `Err(CommandStatus::UndefinedVariable)` - If a variable is not defined.
+/// * `Err(CommandStatus::Unrecognized)` - If the result is longer than `MAX_COMMAND_LEN`
+pub fn substitute<'a>(
+    sheet: &Spreadsheet,
+    command: &'a str,
+) -> Result<Cow<'a, str>, CommandStatus> {
+    if !command.contains('$') {
+        return Ok(Cow::Borrowed(command));
+    }
+
+    let mut result = String::with_capacity(command.len() + 16);
+    let mut in_quotes = false;
+    let mut rest = command;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+        if c != '$' || in_quotes {
+            result.push(c);
+            continue;
+        }
+
+        let len = rest
+            .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+            .unwrap_or(rest.len());
+        let name = &rest[..len];
+        if !is_valid_name(name) {
+            result.push(c);
+            continue;
+        }
+        if is_pinned_reference(sheet, name, rest[len..].chars().next()) {
+            result.push(c);
+            continue;
+        }
+        match sheet.script.vars.get(name) {
+            Some(value) => result.push_str(&value.to_string()),
+            None => {
+                eprintln!("Undefined variable ${}", name);
+                return Err(CommandStatus::UndefinedVariable);
+            }
+        }
+        rest = &rest[len..];
+    }
+
+    if result.len() > MAX_COMMAND_LEN {
+        eprintln!("Command too long after substituting variables");
+        return Err(CommandStatus::Unrecognized);
+    }
+    Ok(Cow::Owned(result))
+}
+
+/// Handles `let <name> = <integer>` and `let <name> = cell <ref>`.
+///
+/// A cell's value is copied when the variable is defined; later changes to the
+/// cell do not affect it.
+fn handle_let(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let Some((name, value)) = args.split_once('=') else {
+        return CommandStatus::Unrecognized;
+    };
+    let (name, value) = (name.trim(), value.trim());
+    if !is_valid_name(name) {
+        return CommandStatus::Unrecognized;
+    }
+    if let Err(status) = check_not_column(sheet, name) {
+        return status;
+    }
+
+    let value = if let Some(cell_ref) = value.strip_prefix("cell ") {
+        let (row, col) = match resolve_cell_reference(sheet, cell_ref.trim()) {
+            Ok(coords) => coords,
+            Err(status) => return status,
+        };
+        match sheet.get_cell(row, col) {
+            CellValue::Integer(val) => *val,
+            CellValue::Empty => 0,
+            CellValue::Error(kind) => {
+                eprintln!(
+                    "{} holds {} and cannot be stored in a variable",
+                    cell_ref.trim(),
+                    kind.code()
+                );
+                return CommandStatus::Unrecognized;
+            }
+            CellValue::Text(_) => {
+                eprintln!(
+                    "{} holds text and cannot be stored in a variable",
+                    cell_ref.trim()
+                );
+                return CommandStatus::Unrecognized;
+            }
+        }
+    } else {
+        match value.parse::<i32>() {
+            Ok(val) => val,
+            Err(_) => return CommandStatus::Unrecognized,
+        }
+    };
+    sheet.script.vars.insert(name.to_string(), value);
+    CommandStatus::CmdOk
+}
+
+/// Parses the arguments of `run <file> [--var name=value]...`.
+///
+/// # Returns
+///
+/// * `Ok((file, vars))` - The script path and the injected variables.
+/// * `Err(CommandStatus::Unrecognized)` - If the syntax is wrong
+fn parse_run_args(args: &str) -> Result<(&str, Vec<VarBinding>), CommandStatus> {
+    let mut tokens = args.split_whitespace();
+    let file = tokens.next().ok_or(CommandStatus::Unrecognized)?;
+    let mut vars = Vec::new();
+    while let Some(token) = tokens.next() {
+        if token != "--var" {
+            return Err(CommandStatus::Unrecognized);
+        }
+        let (name, value) = tokens
+            .next()
+            .and_then(|pair| pair.split_once('='))
+            .ok_or(CommandStatus::Unrecognized)?;
+        let value = value
+            .parse::<i32>()
+            .map_err(|_| CommandStatus::Unrecognized)?;
+        if !is_valid_name(name) {
+            return Err(CommandStatus::Unrecognized);
+        }
+        vars.push((name.to_string(), value));
+    }
+    Ok((file, vars))
+}
+
+/// Runs each line of a command file, with extra variables defined for the script.
+///
+/// Blank lines and lines starting with `#` are skipped. Every command runs even if an
+/// earlier one fails; failures are reported by line number. Variables the script
+/// defines, and the injected ones, are dropped when it finishes.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `path` - The script file.
+/// * `vars` - Variables to define before the first line.
+/// * `last_time` - Stores the total execution time of the script.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - If every command succeeded.
+/// * The status of the first failing command otherwise.
+/// * `CommandStatus::Unrecognized` - If the file cannot be read or scripts nest too deeply
+pub fn run_script(
+    sheet: &mut Spreadsheet,
+    path: &str,
+    vars: &[VarBinding],
+    last_time: &mut f64,
+) -> CommandStatus {
+    if sheet.script.depth >= MAX_SCRIPT_DEPTH {
+        eprintln!("Scripts nested more than {} deep", MAX_SCRIPT_DEPTH);
+        return CommandStatus::Unrecognized;
+    }
+    if let Err(status) = vars
+        .iter()
+        .try_for_each(|(name, _)| check_not_column(sheet, name))
+    {
+        return status;
+    }
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("Failed to read script '{}': {}", path, e);
+            return CommandStatus::Unrecognized;
+        }
+    };
+
+    let saved_vars = sheet.script.vars.clone();
+    sheet.script.vars.extend(vars.iter().cloned());
+    sheet.script.depth += 1;
+
+    let mut first_failure = None;
+    let mut total_time = 0.0;
+    let (mut ran, mut failed) = (0, 0);
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        for command in split_commands(line) {
+            let status = process_command(sheet, command, last_time);
+            total_time += *last_time;
+            ran += 1;
+            if status != CommandStatus::CmdOk {
+                failed += 1;
+                sheet.output.line(format!(
+                    "{}:{}: {} ({})",
+                    path,
+                    number + 1,
+                    command,
+                    status.as_str()
+                ));
+                first_failure.get_or_insert(status);
+            }
+        }
+    }
+
+    sheet.script.depth -= 1;
+    sheet.script.vars = saved_vars;
+    *last_time = total_time;
+    sheet.output.line(format!(
+        "Ran {} command(s) from {}, {} failed",
+        ran, path, failed
+    ));
+    first_failure.unwrap_or(CommandStatus::CmdOk)
+}
+
+/// Handles `let`, `vars`, `unset`, and `run`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; output goes to its sink.
+/// * `command` - The command, after variable substitution.
+/// * `last_time` - Stores the execution time of `run`.
+///
+/// # Returns
+///
+/// * `Some(CommandStatus)` - If `command` is a scripting command.
+/// * `None` - Otherwise
+pub fn handle_scripting_command(
+    sheet: &mut Spreadsheet,
+    command: &str,
+    last_time: &mut f64,
+) -> Option<CommandStatus> {
+    if command == "vars" {
+        let lines: Vec<String> = if sheet.script.vars.is_empty() {
+            vec!["No variables".to_string()]
+        } else {
+            let mut table = Table::new(&[("name", Align::Left), ("value", Align::Right)]);
+            for (name, value) in &sheet.script.vars {
+                table.push_row(vec![name.clone(), value.to_string()]);
+            }
+            table.render()
+        };
+        sheet.output.lines(lines);
+        return Some(CommandStatus::CmdOk);
+    }
+    if let Some(args) = command.strip_prefix("let ") {
+        return Some(handle_let(sheet, args));
+    }
+    if let Some(name) = command.strip_prefix("unset ") {
+        return Some(match sheet.script.vars.remove(name.trim()) {
+            Some(_) => CommandStatus::CmdOk,
+            None => {
+                eprintln!("Undefined variable ${}", name.trim());
+                CommandStatus::UndefinedVariable
+            }
+        });
+    }
+    if let Some(args) = command.strip_prefix("run ") {
+        // The lines of the script are undone together, as one step of `run`
+        let recording = sheet.begin_undo_step(command);
+        let status = match parse_run_args(args) {
+            Ok((file, vars)) => run_script(sheet, file, &vars, last_time),
+            Err(status) => status,
+        };
+        if recording {
+            sheet.end_undo_step(&status);
+        }
+        return Some(status);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extensions::get_formula_string;
+    use crate::output::OutputSink;
+
+    fn run(sheet: &mut Spreadsheet, command: &str) -> CommandStatus {
+        let mut last_time = 0.0;
+        process_command(sheet, command, &mut last_time)
+    }
+
+    fn new_sheet() -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        sheet.output = OutputSink::capture();
+        sheet
+    }
+
+    #[test]
+    fn test_substitution_in_assignments_and_commands() {
+        let mut sheet = new_sheet();
+        assert_eq!(run(&mut sheet, "let threshold = 100"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "let row_ = -3"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "A1=7"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "B1=A1+$threshold"), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(107));
+        assert_eq!(run(&mut sheet, "C1=$row_"), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(-3));
+        assert_eq!(
+            run(&mut sheet, "replace 107 $threshold"),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(107));
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(7));
+
+        // Quoted text and a bare `$` are left as they are
+        assert_eq!(
+            substitute(&sheet, "x \"$threshold\" $ $threshold").unwrap(),
+            "x \"$threshold\" $ 100"
+        );
+
+        assert_eq!(run(&mut sheet, "vars"), CommandStatus::CmdOk);
+        let lines = sheet.output.take();
+        assert_eq!(
+            lines[lines.len() - 4..],
+            [
+                "name       value",
+                "---------  -----",
+                "row_          -3",
+                "threshold    100"
+            ]
+        );
+        assert_eq!(run(&mut sheet, "unset row_"), CommandStatus::CmdOk);
+        assert_eq!(
+            run(&mut sheet, "unset row_"),
+            CommandStatus::UndefinedVariable
+        );
+    }
+
+    #[test]
+    fn test_let_cell_captures_value_at_definition() {
+        let mut sheet = new_sheet();
+        assert_eq!(run(&mut sheet, "A5=42"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "let first = cell A5"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "A5=1"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "B5=$first"), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(4, 1), CellValue::Integer(42));
+
+        assert_eq!(run(&mut sheet, "A6=1/0"), CommandStatus::CmdOk);
+        assert_eq!(
+            run(&mut sheet, "let second = cell A6"),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            run(&mut sheet, "let second = cell Z99"),
+            CommandStatus::InvalidCell
+        );
+        assert_eq!(run(&mut sheet, "let 1y = 4"), CommandStatus::Unrecognized);
+        assert_eq!(
+            run(&mut sheet, "let second = four"),
+            CommandStatus::Unrecognized
+        );
+        assert!(!sheet.script.vars.contains_key("second"));
+    }
+
+    #[test]
+    fn test_undefined_variable_is_an_error() {
+        let mut sheet = new_sheet();
+        assert_eq!(
+            run(&mut sheet, "A1=$missing+1"),
+            CommandStatus::UndefinedVariable
+        );
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Empty);
+        assert!(!sheet.cell_meta.contains_key(&0));
+    }
+
+    #[test]
+    fn test_pinned_references_are_not_variables() {
+        let mut sheet = new_sheet();
+        assert_eq!(run(&mut sheet, "A1=4"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "B1=$A$1+$A1"), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(8));
+        assert_eq!(run(&mut sheet, "B2=$AB"), CommandStatus::UndefinedVariable);
+        // References ignore case, so lowercase ones are not variables either
+        assert_eq!(run(&mut sheet, "B2=$a$1+1"), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(5));
+        assert_eq!(run(&mut sheet, "B3=$a1+1"), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(2, 1), CellValue::Integer(5));
+        assert_eq!(get_formula_string(&sheet, 2, 1), "$A1+1");
+        assert_eq!(run(&mut sheet, "let A1 = 10"), CommandStatus::Unrecognized);
+        assert_eq!(
+            run(&mut sheet, "let ab12 = 10"),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_column_names_are_not_variables() {
+        let mut sheet = new_sheet();
+        // In any case, and past the last column of this sheet, which another may have
+        for name in ["A", "J", "a", "K", "zzz"] {
+            assert_eq!(
+                run(&mut sheet, &format!("let {} = 7", name)),
+                CommandStatus::Unrecognized,
+                "{}",
+                name
+            );
+        }
+        assert!(sheet.script.vars.is_empty());
+        // Longer than any column name, or with an underscore, a name is no reference
+        assert_eq!(run(&mut sheet, "let abcd = 7"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "let a_ = 7"), CommandStatus::CmdOk);
+        assert_eq!(
+            run_script(&mut sheet, "missing.txt", &[("B".to_string(), 1)], &mut 0.0),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(run(&mut sheet, "A1=4"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "B1=$A$1"), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(4));
+    }
+
+    #[test]
+    fn test_variables_next_to_pinned_references() {
+        let mut sheet = new_sheet();
+        for command in ["A1=4", "A2=5", "let rate = 3", "let K_ = 2"] {
+            assert_eq!(run(&mut sheet, command), CommandStatus::CmdOk);
+        }
+        assert_eq!(run(&mut sheet, "let A = 1"), CommandStatus::Unrecognized);
+        assert_eq!(run(&mut sheet, "B1=$A$1*$rate"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "B2=A$2+$K_"), CommandStatus::CmdOk);
+        assert_eq!(run(&mut sheet, "B3=MAX($A$1:$A2)"), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(12));
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(7));
+        assert_eq!(*sheet.get_cell(2, 1), CellValue::Integer(5));
+        assert_eq!(get_formula_string(&sheet, 0, 1), "$A$1*3");
+        assert_eq!(get_formula_string(&sheet, 1, 1), "A$2+2");
+        assert_eq!(get_formula_string(&sheet, 2, 1), "MAX($A$1:$A2)");
+    }
+
+    #[test]
+    fn test_substitution_length_guard() {
+        let mut sheet = new_sheet();
+        assert_eq!(
+            run(&mut sheet, "let large = 1000000000"),
+            CommandStatus::CmdOk
+        );
+        let command = "$large ".repeat(1000);
+        assert_eq!(run(&mut sheet, &command), CommandStatus::Unrecognized);
+    }
+
+    #[test]
+    fn test_run_script_with_injected_variables() {
+        let path = "test_run_script_vars.txt";
+        std::fs::write(
+            path,
+            "# thresholds come from --var\nlet local = 2\n\nA1=$threshold*$local\nB1=$nope\nC1=A1+1\n",
+        )
+        .unwrap();
+
+        let mut sheet = new_sheet();
+        assert_eq!(run(&mut sheet, "let keep = 9"), CommandStatus::CmdOk);
+        let status = run(&mut sheet, &format!("run {} --var threshold=50", path));
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(status, CommandStatus::UndefinedVariable);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(100));
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(101));
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                format!("{}:5: B1=$nope (undefined_variable)", path),
+                format!("Ran 4 command(s) from {}, 1 failed", path),
+            ]
+        );
+        // Script variables do not outlive the script; session ones survive it
+        assert!(!sheet.script.vars.contains_key("threshold"));
+        assert!(!sheet.script.vars.contains_key("local"));
+        assert_eq!(sheet.script.vars.get("keep"), Some(&9));
+
+        assert_eq!(run(&mut sheet, "run"), CommandStatus::Unrecognized);
+        assert_eq!(
+            run(&mut sheet, "run x --var 5"),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            run(&mut sheet, "run missing_script.txt"),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_run_script_splits_lines_on_semicolons() {
+        let path = "test_run_script_split.txt";
+        std::fs::write(path, "A1=1; B1=A1+; C1=A1+1\n").unwrap();
+        let mut sheet = new_sheet();
+        let status = run(&mut sheet, &format!("run {}", path));
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(status, CommandStatus::Unrecognized);
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(2));
+        assert_eq!(
+            sheet.output.take(),
+            vec![
+                format!("{}:1: B1=A1+ (unrecognized_cmd)", path),
+                format!("Ran 3 command(s) from {}, 1 failed", path),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_script_is_one_undo_step() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("s.txt").display().to_string();
+        std::fs::write(&path, "A1=1\nA2=2\nA3=3\n").unwrap();
+        let mut sheet = new_sheet();
+        run(&mut sheet, "B1=7");
+        assert_eq!(
+            run(&mut sheet, &format!("run {}", path)),
+            CommandStatus::CmdOk
+        );
+        sheet.output.take();
+
+        assert_eq!(run(&mut sheet, "undo"), CommandStatus::CmdOk);
+        assert_eq!(
+            sheet.output.take(),
+            vec![format!("undid run {} (3 cell(s))", path)]
+        );
+        for row in 0..3 {
+            assert_eq!(*sheet.get_cell(row, 0), CellValue::Empty);
+        }
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(7));
+    }
+
+    #[test]
+    fn test_run_script_nesting_is_bounded() {
+        let path = "test_run_script_self.txt";
+        std::fs::write(path, format!("run {}\n", path)).unwrap();
+        let mut sheet = new_sheet();
+        let status = run(&mut sheet, &format!("run {}", path));
+        let _ = std::fs::remove_file(path);
+        assert_eq!(status, CommandStatus::Unrecognized);
+        assert_eq!(sheet.script.depth, 0);
+    }
+}
@@ -0,0 +1,230 @@
+//! Finding cells by value, as in `find 42` followed by `find_next`.
+//!
+//! A search remembers its query and the match it last stopped at. Matches are looked up
+//! again on every step, so a search keeps working while cells change: the next match is
+//! simply the first one after the last position in reading order, wrapping around to the
+//! top of the sheet. The vim mode's `/42`, `n` and `N` step through the same search.
+
+use crate::cell::CellValue;
+use crate::evaluator::parse_value_token;
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+
+/// The state of the last search.
+///
+/// # Fields
+///
+/// * `query` - The value searched for; an error matches errors of every kind.
+/// * `position` - The match the search last stopped at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Search {
+    pub query: CellValue,
+    pub position: (i16, i16),
+}
+
+/// Checks a cell value against a query.
+fn matches(value: &CellValue, query: &CellValue) -> bool {
+    value == query || (value.is_error() && query.is_error())
+}
+
+/// Lists the cells holding `query`, in reading order.
+///
+/// Only rows the grid holds are scanned: the rest are empty, and no query matches an
+/// empty cell.
+pub fn find_matches(sheet: &Spreadsheet, query: &CellValue) -> Vec<(i16, i16)> {
+    let cols = sheet.cols as usize;
+    sheet
+        .grid
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| matches(value, query))
+        .map(|(index, _)| ((index / cols) as i16, (index % cols) as i16))
+        .collect()
+}
+
+/// Finds the match after or before a cell, wrapping around the sheet.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `query` - The value searched for.
+/// * `from` - The cell to step from; it is only a match again if it is the only one.
+/// * `forward` - Step in reading order if true, backwards otherwise.
+///
+/// # Returns
+///
+/// * `Some((row, col))` - The next match.
+/// * `None` - If no cell matches
+pub fn step_from(
+    sheet: &Spreadsheet,
+    query: &CellValue,
+    from: (i16, i16),
+    forward: bool,
+) -> Option<(i16, i16)> {
+    let found = find_matches(sheet, query);
+    if forward {
+        found
+            .iter()
+            .find(|&&cell| cell > from)
+            .or(found.first())
+            .copied()
+    } else {
+        found
+            .iter()
+            .rev()
+            .find(|&&cell| cell < from)
+            .or(found.last())
+            .copied()
+    }
+}
+
+/// Starts a search and stops at its first match.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet; the search is kept in `sheet.search`.
+/// * `query` - The value to look for.
+///
+/// # Returns
+///
+/// * `Vec<(i16, i16)>` - Every match in reading order; the search is dropped if empty
+pub fn start_search(sheet: &mut Spreadsheet, query: CellValue) -> Vec<(i16, i16)> {
+    let found = find_matches(sheet, &query);
+    sheet.search = found.first().map(|&position| Search { query, position });
+    found
+}
+
+/// Moves the current search to its next or previous match.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `from` - The cell to step from, or `None` for the last match.
+/// * `forward` - Step in reading order if true, backwards otherwise.
+///
+/// # Returns
+///
+/// * `Some((row, col))` - The match the search now stands at.
+/// * `None` - If there is no search or nothing matches any more
+pub fn step_search(
+    sheet: &mut Spreadsheet,
+    from: Option<(i16, i16)>,
+    forward: bool,
+) -> Option<(i16, i16)> {
+    let search = sheet.search.as_ref()?;
+    let from = from.unwrap_or(search.position);
+    let next = step_from(sheet, &search.query, from, forward)?;
+    if let Some(search) = &mut sheet.search {
+        search.position = next;
+    }
+    Some(next)
+}
+
+/// Formats a cell as a reference such as `B3`.
+fn cell_name(sheet: &Spreadsheet, (row, col): (i16, i16)) -> String {
+    format!("{}{}", sheet.get_column_name(col), row + 1)
+}
+
+/// Handles `find <value>`: lists the matching cells and scrolls to the first one.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `find `: an integer, or `ERR` for cells in error.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success, matches or not.
+/// * `CommandStatus::Unrecognized` - If the value is malformed
+pub fn handle_find(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    let Some(query) = parse_value_token(args.trim()) else {
+        return CommandStatus::Unrecognized;
+    };
+    let found = start_search(sheet, query);
+    let Some(&(row, col)) = found.first() else {
+        sheet.output.line(format!("no cell holds {}", args.trim()));
+        return CommandStatus::CmdOk;
+    };
+    let names: Vec<String> = found.iter().map(|&cell| cell_name(sheet, cell)).collect();
+    sheet
+        .output
+        .line(format!("{} match(es): {}", found.len(), names.join(", ")));
+    sheet.viewport_row = row;
+    sheet.viewport_col = col;
+    CommandStatus::CmdOk
+}
+
+/// Handles `find_next`: scrolls to the match after the last one, wrapping around.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success, after printing the match.
+/// * `CommandStatus::Unrecognized` - If no search is running or nothing matches any more
+pub fn handle_find_next(sheet: &mut Spreadsheet) -> CommandStatus {
+    if sheet.search.is_none() {
+        sheet
+            .output
+            .line("no search to continue; start one with find".to_string());
+        return CommandStatus::Unrecognized;
+    }
+    let Some((row, col)) = step_search(sheet, None, true) else {
+        sheet.output.line("no cell matches any more".to_string());
+        return CommandStatus::Unrecognized;
+    };
+    let name = cell_name(sheet, (row, col));
+    sheet.output.line(name);
+    sheet.viewport_row = row;
+    sheet.viewport_col = col;
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::ScriptedSheet;
+
+    #[test]
+    fn test_find_lists_matches_and_scrolls() {
+        let scripted = ScriptedSheet::new(30, 30)
+            .run(&["C20=42", "A2=42", "B5=7", "find 42"])
+            .assert_status_ok()
+            .assert_output(&["2 match(es): A2, C20"]);
+        assert_eq!(
+            (scripted.sheet.viewport_row, scripted.sheet.viewport_col),
+            (1, 0)
+        );
+        scripted
+            .run(&["find_next"])
+            .assert_output(&["C20"])
+            .run(&["find_next"])
+            .assert_output(&["A2"])
+            .run(&["find 8"])
+            .assert_output(&["no cell holds 8"])
+            .run(&["find_next"])
+            .assert_status(CommandStatus::Unrecognized);
+    }
+
+    #[test]
+    fn test_find_errors_and_changed_cells() {
+        ScriptedSheet::new(10, 10)
+            .run(&["A1=1/0", "B2=C3/0", "C3=5", "find ERR"])
+            .assert_status_ok()
+            .assert_output(&["2 match(es): A1, B2"])
+            .run(&["A1=3", "find_next"])
+            .assert_output(&["B2"])
+            .run(&["B2=0", "find_next"])
+            .assert_status(CommandStatus::Unrecognized)
+            .assert_output(&["no cell matches any more"]);
+    }
+
+    #[test]
+    fn test_step_backwards_wraps() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        sheet.grid = vec![CellValue::Empty; 25];
+        sheet.grid[3] = CellValue::Integer(4);
+        sheet.grid[12] = CellValue::Integer(4);
+        start_search(&mut sheet, CellValue::Integer(4));
+        assert_eq!(step_search(&mut sheet, None, false), Some((2, 2)));
+        assert_eq!(step_search(&mut sheet, None, false), Some((0, 3)));
+        assert_eq!(step_search(&mut sheet, Some((1, 0)), true), Some((2, 2)));
+    }
+}
@@ -0,0 +1,157 @@
+//! Filling a row or column with an arithmetic series, as in `series A1:A10 5 3`.
+//!
+//! The first cell gets the start value and each following cell, down a column or across a
+//! row, the previous value plus the step. The values are written like a range fill: any
+//! formula in a target cell is dropped, the old value goes to the cell's history, and
+//! dependents are reevaluated once at the end.
+
+use crate::cell::{CellValue, ErrorKind};
+use crate::extensions::bulk_set_values;
+use crate::range::{Range, resolve_range};
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+
+/// Computes the values of a series, one per cell in reading order.
+///
+/// A value that does not fit in an `i32` is an overflow error, as in arithmetic.
+fn series_values(range: &Range, start: i32, step: i32) -> Vec<CellValue> {
+    range
+        .cells()
+        .enumerate()
+        .map(|(index, _)| {
+            let value = start as i64 + index as i64 * step as i64;
+            i32::try_from(value)
+                .map(CellValue::Integer)
+                .unwrap_or(CellValue::Error(ErrorKind::Overflow))
+        })
+        .collect()
+}
+
+/// Fills a single row or column with `start`, `start + step`, `start + 2 * step`, ...
+///
+/// Nothing is written if a cell of the range is locked or owned by another user.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `range` - The target cells, one row or one column.
+/// * `start` - The first value.
+/// * `step` - The difference between neighbouring cells.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::LockedCell` - If a cell of the range is locked.
+/// * `CommandStatus::NotOwner` - If a cell of the range is owned by someone else.
+/// * `CommandStatus::Unrecognized` - If the range spans several rows and columns
+pub fn fill_series(
+    sheet: &mut Spreadsheet,
+    range: &Range,
+    start: i32,
+    step: i32,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    if range.start_row != range.end_row && range.start_col != range.end_col {
+        sheet
+            .output
+            .line("series fills a single row or column".to_string());
+        return CommandStatus::Unrecognized;
+    }
+    if range
+        .cells()
+        .any(|(row, col)| sheet.is_cell_locked(row, col))
+    {
+        return CommandStatus::LockedCell;
+    }
+    if range
+        .cells()
+        .any(|(row, col)| sheet.check_owner(row, col) != CommandStatus::CmdOk)
+    {
+        return CommandStatus::NotOwner;
+    }
+
+    let writes: Vec<(i16, i16, CellValue)> = range
+        .cells()
+        .zip(series_values(range, start, step))
+        .map(|((row, col), value)| (row, col, value))
+        .collect();
+    bulk_set_values(sheet, &writes, sleep_time);
+    for (row, col) in range.cells() {
+        let key = sheet.get_key(row, col);
+        sheet.assigned.insert(key);
+    }
+    sheet.dirty = true;
+    CommandStatus::CmdOk
+}
+
+/// Handles `series <range> <start> <step>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `series `.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * The status of `fill_series`.
+/// * `CommandStatus::Unrecognized` - If the arguments are malformed
+pub fn handle_series(sheet: &mut Spreadsheet, args: &str, sleep_time: &mut f64) -> CommandStatus {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let [range, start, step] = parts[..] else {
+        return CommandStatus::Unrecognized;
+    };
+    let range = match resolve_range(sheet, range) {
+        Ok(range) => range,
+        Err(status) => return status,
+    };
+    match (start.parse::<i32>(), step.parse::<i32>()) {
+        (Ok(start), Ok(step)) => fill_series(sheet, &range, start, step, sleep_time),
+        _ => CommandStatus::Unrecognized,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cell::ErrorKind;
+    use crate::spreadsheet::CommandStatus;
+    use crate::test_utils::ScriptedSheet;
+
+    #[test]
+    fn test_series_fills_column_and_updates_sum() {
+        ScriptedSheet::new(20, 5)
+            .run(&["A3=A2*2", "B1=SUM(A1:A10)", "series A1:A10 5 3"])
+            .assert_status_ok()
+            .assert_cell("A1", 5)
+            .assert_cell("A2", 8)
+            .assert_cell("A10", 32)
+            .assert_formula("A3", "")
+            .assert_cell("B1", (5 + 32) * 10 / 2)
+            .run(&["history A2"])
+            .assert_cell("B1", (5 + 32) * 10 / 2 - 8);
+    }
+
+    #[test]
+    fn test_series_fills_row_with_negative_step() {
+        ScriptedSheet::new(5, 10)
+            .run(&["series B2:E2 10 -4"])
+            .assert_status_ok()
+            .assert_cell("B2", 10)
+            .assert_cell("E2", -2)
+            .run(&["series A1:C1 2147483646 1"])
+            .assert_cell("B1", i32::MAX)
+            .assert_error_cell("C1", ErrorKind::Overflow);
+    }
+
+    #[test]
+    fn test_series_refuses_rectangles_and_locks() {
+        ScriptedSheet::new(10, 5)
+            .run(&["series A1:B3 1 1"])
+            .assert_status(CommandStatus::Unrecognized)
+            .assert_output(&["series fills a single row or column"])
+            .run(&["A1=9", "lock_cell A3", "series A1:A5 1 1"])
+            .assert_status(CommandStatus::LockedCell)
+            .assert_cell("A1", 9)
+            .assert_formula("A2", "");
+    }
+}
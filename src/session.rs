@@ -0,0 +1,474 @@
+//! Session files: the sheet plus the state needed to pick up work where it was left.
+//!
+//! A session file uses the save file's record format. It starts with `SESSION,<version>`,
+//! then a `SHEET` record that either references the sheet's file with a hash of its
+//! contents or says the sheet's records follow inline. Each piece of session state has
+//! its own record type, and unknown types are skipped, so a session written by a newer
+//! version still restores what this one understands.
+
+use crate::condition::parse_condition;
+use crate::filter::RowFilter;
+use crate::ownership::is_valid_user;
+use crate::save_load::{open_tracked, read_records, write_sheet_records};
+use crate::spreadsheet::{CommandStatus, MAX_BACKUPS, Spreadsheet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+
+/// Version written in the `SESSION` header.
+const SESSION_VERSION: u32 = 1;
+
+/// Hashes file contents with 64-bit FNV-1a, to notice a referenced sheet file changing.
+///
+/// # Arguments
+///
+/// * `bytes` - The file contents.
+///
+/// # Returns
+///
+/// * `u64` - The hash
+fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Formats an on/off setting.
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+/// Writes every session record.
+///
+/// The sheet is referenced by path when it has a current file and no unsaved changes;
+/// otherwise its records are written inline.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `writer` - Where the records go.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An error if writing fails
+fn write_session<W: Write>(sheet: &Spreadsheet, writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "SESSION,{}", SESSION_VERSION)?;
+    let referenced = match &sheet.current_file {
+        Some(file) if !sheet.dirty => fs::read(file)
+            .ok()
+            .map(|contents| (file, content_hash(&contents))),
+        _ => None,
+    };
+    match referenced {
+        Some((file, hash)) => writeln!(writer, "SHEET,{:016x},{}", hash, file)?,
+        None => {
+            writeln!(writer, "SHEET,inline")?;
+            write_sheet_records(sheet, writer)?;
+        }
+    }
+
+    writeln!(writer, "VIEW,{},{}", sheet.viewport_row, sheet.viewport_col)?;
+    writeln!(writer, "SET,output,{}", on_off(sheet.output_enabled))?;
+    writeln!(writer, "SET,prompt_stats,{}", on_off(sheet.prompt_stats))?;
+    writeln!(writer, "SET,page_size,{}", sheet.output.page_size)?;
+    writeln!(
+        writer,
+        "SET,confirm_overwrite_bulk,{}",
+        on_off(sheet.confirm_overwrite_bulk)
+    )?;
+    writeln!(writer, "SET,range_cells,{}", sheet.range_cell_limit)?;
+    writeln!(writer, "SET,line_bytes,{}", sheet.line_byte_limit)?;
+    writeln!(writer, "SET,backups,{}", sheet.backups)?;
+    writeln!(writer, "SET,history_limit,{}", sheet.history_limit)?;
+    writeln!(writer, "SET,autosave_every,{}", sheet.autosave_every)?;
+    writeln!(
+        writer,
+        "SET,enforce_ownership,{}",
+        on_off(sheet.ownership.enforce)
+    )?;
+    if let Some(user) = &sheet.ownership.user {
+        writeln!(writer, "SET,user,{}", user)?;
+    }
+    if let Some(filter) = &sheet.row_filter {
+        writeln!(
+            writer,
+            "FILTER,{},{}",
+            sheet.get_column_name(filter.col),
+            filter.condition.describe()
+        )?;
+    }
+    for (name, value) in &sheet.script.vars {
+        writeln!(writer, "VAR,{},{}", name, value)?;
+    }
+    Ok(())
+}
+
+/// Saves the sheet and its session state to a session file.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `filename` - The session file path.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If the file cannot be written
+pub fn save_session(sheet: &Spreadsheet, filename: &str) -> CommandStatus {
+    let result = File::create(filename).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write_session(sheet, &mut writer)?;
+        writer.flush()
+    });
+    match result {
+        Ok(()) => CommandStatus::CmdOk,
+        Err(e) => {
+            eprintln!("Failed to write session '{}': {}", filename, e);
+            CommandStatus::Unrecognized
+        }
+    }
+}
+
+/// Applies one session record. Returns `false` for sheet records and unknown types.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet being restored.
+/// * `parts` - The record's comma-separated fields.
+/// * `failed` - Set when the referenced sheet file cannot be opened.
+fn restore_record(sheet: &mut Spreadsheet, parts: &[&str], failed: &mut bool) -> bool {
+    match parts {
+        ["SHEET", "inline"] => {
+            sheet.current_file = None;
+            sheet.file_stamp = None;
+        }
+        ["SHEET", hash, path @ ..] if !path.is_empty() => {
+            // Paths may contain commas
+            let path = path.join(",");
+            let current = fs::read(&path).ok().map(|contents| content_hash(&contents));
+            if current.map(|hash| format!("{:016x}", hash)).as_deref() != Some(*hash) {
+                eprintln!(
+                    "Warning: '{}' changed since the session was saved; loading it as it is now",
+                    path
+                );
+            }
+            if open_tracked(sheet, &path) != CommandStatus::CmdOk {
+                eprintln!("Could not open the session's sheet '{}'", path);
+                *failed = true;
+            }
+        }
+        ["SET", name, value] => match (*name, *value) {
+            ("output", value) => sheet.output_enabled = value == "on",
+            ("prompt_stats", value) => sheet.prompt_stats = value == "on",
+            ("confirm_overwrite_bulk", value) => sheet.confirm_overwrite_bulk = value == "on",
+            ("page_size", value) => {
+                if let Ok(size) = value.parse() {
+                    sheet.output.page_size = size;
+                }
+            }
+            ("range_cells", value) => {
+                if let Ok(limit) = value.parse::<i64>()
+                    && limit > 0
+                {
+                    sheet.range_cell_limit = limit;
+                }
+            }
+            ("line_bytes", value) => {
+                if let Ok(limit) = value.parse::<usize>()
+                    && limit > 0
+                {
+                    sheet.line_byte_limit = limit;
+                }
+            }
+            ("history_limit", value) => {
+                if let Ok(limit) = value.parse() {
+                    sheet.set_history_limit(limit);
+                }
+            }
+            ("backups", value) => {
+                if let Ok(count) = value.parse::<usize>()
+                    && count <= MAX_BACKUPS
+                {
+                    sheet.backups = count;
+                }
+            }
+            ("autosave_every", value) => {
+                if let Ok(count) = value.parse::<usize>()
+                    && count > 0
+                {
+                    sheet.autosave_every = count;
+                }
+            }
+            ("enforce_ownership", value) => sheet.ownership.enforce = value == "on",
+            ("user", value) if is_valid_user(value) => {
+                sheet.ownership.user = Some(value.to_string());
+            }
+            // Settings from newer versions
+            _ => {}
+        },
+        ["FILTER", col_name, condition] => {
+            let col = sheet.column_name_to_index(col_name);
+            if let Ok(condition) = parse_condition(condition)
+                && (0..sheet.cols).contains(&col)
+            {
+                sheet.row_filter = Some(RowFilter { col, condition });
+            }
+        }
+        ["VAR", name, value] => {
+            if let Ok(value) = value.parse::<i32>() {
+                sheet.script.vars.insert(name.to_string(), value);
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Restores a session file, replacing the sheet and its session state.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet to restore into.
+/// * `filename` - The session file path.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If the file is missing, is not a session file, or
+///   its sheet file cannot be opened
+pub fn restore_session(sheet: &mut Spreadsheet, filename: &str) -> CommandStatus {
+    let Ok(file) = File::open(filename) else {
+        eprintln!("Cannot open session '{}'", filename);
+        return CommandStatus::Unrecognized;
+    };
+    let mut reader = BufReader::new(file);
+
+    // Check the header before touching the sheet
+    let mut header = String::new();
+    if reader.read_line(&mut header).is_err() {
+        return CommandStatus::Unrecognized;
+    }
+    let Some(version) = header
+        .trim_end()
+        .strip_prefix("SESSION,")
+        .and_then(|version| version.parse::<u32>().ok())
+    else {
+        eprintln!("'{}' is not a session file", filename);
+        return CommandStatus::Unrecognized;
+    };
+    if version > SESSION_VERSION {
+        eprintln!(
+            "Warning: '{}' was written by a newer version; parts of it may be skipped",
+            filename
+        );
+    }
+
+    sheet.clear();
+    sheet.script.vars.clear();
+    let mut failed = false;
+    read_records(sheet, &mut reader, |sheet, parts| {
+        restore_record(sheet, parts, &mut failed)
+    });
+    sheet.cell_counts = sheet.scan_cell_counts();
+    if sheet.current_file.is_none() {
+        // The sheet came from the session, not from a file
+        sheet.dirty = true;
+    }
+    if failed {
+        CommandStatus::Unrecognized
+    } else {
+        CommandStatus::CmdOk
+    }
+}
+
+/// Handles `session save <file>` and `session restore <file>`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `session `.
+///
+/// # Returns
+///
+/// * `CommandStatus` - As for `save_session` or `restore_session`.
+/// * `CommandStatus::Unrecognized` - For other subcommands
+pub fn handle_session(sheet: &mut Spreadsheet, args: &str) -> CommandStatus {
+    match args.trim().split_once(' ') {
+        Some(("save", file)) if !file.trim().is_empty() => save_session(sheet, file.trim()),
+        Some(("restore", file)) if !file.trim().is_empty() => restore_session(sheet, file.trim()),
+        _ => CommandStatus::Unrecognized,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::CellValue;
+    use crate::process_command::process_command;
+
+    fn run(sheet: &mut Spreadsheet, command: &str) {
+        assert_eq!(
+            process_command(sheet, command, &mut 0.0),
+            CommandStatus::CmdOk,
+            "{}",
+            command
+        );
+    }
+
+    /// A sheet with every supported piece of session state set.
+    fn busy_sheet() -> Spreadsheet {
+        let mut sheet = Spreadsheet::create(30, 30).unwrap();
+        for command in [
+            "A1=5",
+            "A2=150",
+            "B1=A1+A2",
+            "let rate = 7",
+            "name A1:A2 inputs",
+            "lock_cell B1",
+            "filter A > 10",
+            "set prompt_stats on",
+            "set page_size 40",
+            "set confirm_overwrite_bulk on",
+            "set backups 3",
+            "set history_limit 7",
+            "set autosave_every 4",
+            "own C1:C5 alice",
+            "set user bob",
+            "set enforce_ownership on",
+            "set_limit range_cells 500",
+            "set_limit line_bytes 2048",
+            "scroll_to C12",
+            "disable_output",
+        ] {
+            run(&mut sheet, command);
+        }
+        sheet
+    }
+
+    fn assert_restored(restored: &Spreadsheet, original: &Spreadsheet) {
+        assert_eq!(*restored.get_cell(0, 1), CellValue::Integer(155));
+        assert_eq!(
+            crate::extensions::get_formula_string(restored, 0, 1),
+            "A1+A2"
+        );
+        assert_eq!(
+            (restored.viewport_row, restored.viewport_col),
+            (original.viewport_row, original.viewport_col)
+        );
+        assert!(!restored.output_enabled);
+        assert!(restored.prompt_stats);
+        assert!(restored.confirm_overwrite_bulk);
+        assert_eq!(restored.output.page_size, 40);
+        assert_eq!(restored.range_cell_limit, 500);
+        assert_eq!(restored.line_byte_limit, 2048);
+        assert_eq!(restored.backups, 3);
+        assert_eq!(restored.history_limit, 7);
+        assert_eq!(restored.autosave_every, 4);
+        assert_eq!(restored.ownership, original.ownership);
+        assert_eq!(restored.ownership.user.as_deref(), Some("bob"));
+        assert_eq!(restored.row_filter, original.row_filter);
+        assert_eq!(restored.script.vars, original.script.vars);
+        assert_eq!(restored.named_ranges, original.named_ranges);
+        assert_eq!(restored.locked_ranges, original.locked_ranges);
+        assert_eq!(restored.cell_counts, restored.scan_cell_counts());
+    }
+
+    #[test]
+    fn test_round_trip_inline_sheet() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.fss");
+        let path = path.to_str().unwrap();
+        let sheet = busy_sheet();
+        assert_eq!(save_session(&sheet, path), CommandStatus::CmdOk);
+        let contents = fs::read_to_string(path).unwrap();
+        assert!(contents.starts_with("SESSION,1\nSHEET,inline\nDIMS,30,30\n"));
+
+        let mut restored = Spreadsheet::create(30, 30).unwrap();
+        run(&mut restored, "Z30=1");
+        run(&mut restored, "let stale = 1");
+        assert_eq!(restore_session(&mut restored, path), CommandStatus::CmdOk);
+        assert_restored(&restored, &sheet);
+        assert_eq!(*restored.get_cell(29, 25), CellValue::Empty);
+        assert!(restored.dirty);
+    }
+
+    #[test]
+    fn test_referenced_sheet_and_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let sheet_path = dir.path().join("data.sheet");
+        let sheet_path = sheet_path.to_str().unwrap();
+        let session_path = dir.path().join("work.fss");
+        let session_path = session_path.to_str().unwrap();
+
+        let mut sheet = busy_sheet();
+        run(&mut sheet, &format!("save {}", sheet_path));
+        assert_eq!(save_session(&sheet, session_path), CommandStatus::CmdOk);
+        let contents = fs::read_to_string(session_path).unwrap();
+        assert!(!contents.contains("CELL,"));
+        assert!(contents.contains(&format!(",{}\n", sheet_path)));
+
+        let mut restored = Spreadsheet::create(30, 30).unwrap();
+        assert_eq!(
+            restore_session(&mut restored, session_path),
+            CommandStatus::CmdOk
+        );
+        assert_restored(&restored, &sheet);
+        assert_eq!(restored.current_file.as_deref(), Some(sheet_path));
+        assert!(!restored.dirty);
+
+        // The sheet file changes: the session still restores, with the new contents
+        let mut edited = Spreadsheet::create(30, 30).unwrap();
+        run(&mut edited, "A1=9");
+        run(&mut edited, &format!("saveas {}", sheet_path));
+        let mut restored = Spreadsheet::create(30, 30).unwrap();
+        assert_eq!(
+            restore_session(&mut restored, session_path),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(*restored.get_cell(0, 0), CellValue::Integer(9));
+        assert_eq!(restored.script.vars, sheet.script.vars);
+
+        // A missing sheet file fails the restore
+        fs::remove_file(sheet_path).unwrap();
+        assert_eq!(
+            restore_session(&mut restored, session_path),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_unknown_records_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("future.fss");
+        let path = path.to_str().unwrap();
+        fs::write(
+            path,
+            "SESSION,7\nSHEET,inline\nDIMS,5,5\nCELL,A1,3\nBOOKMARK,top,A1\n\
+             SET,theme,dark\nVAR,x,4\nWATCH,A1\n",
+        )
+        .unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        assert_eq!(restore_session(&mut sheet, path), CommandStatus::CmdOk);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(3));
+        assert_eq!(sheet.script.vars.get("x"), Some(&4));
+    }
+
+    #[test]
+    fn test_rejects_non_session_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plain.sheet");
+        let path = path.to_str().unwrap();
+        fs::write(path, "DIMS,5,5\nCELL,A1,3\n").unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        run(&mut sheet, "B1=2");
+        assert_eq!(
+            handle_session(&mut sheet, &format!("restore {}", path)),
+            CommandStatus::Unrecognized
+        );
+        // The sheet is untouched
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(2));
+        assert_eq!(
+            handle_session(&mut sheet, "load x.fss"),
+            CommandStatus::Unrecognized
+        );
+    }
+}
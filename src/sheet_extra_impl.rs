@@ -1,8 +1,6 @@
-use crate::cell::CellValue;
-use crate::formula::Range;
-use crate::spreadsheet::{CellMeta, CommandStatus, HighlightType, Spreadsheet};
+use crate::range::Range;
+use crate::spreadsheet::{CellCounts, CellMeta, CommandStatus, HighlightType, Spreadsheet};
 use crate::visualize_cells;
-use std::cmp::min;
 
 impl Spreadsheet {
     pub fn get_cell_meta_ref(&self, row: i16, col: i16) -> &CellMeta {
@@ -14,6 +12,42 @@ impl Spreadsheet {
         })
     }
 
+    /// Returns the sheet to the state right after `Spreadsheet::create`, keeping its size,
+    /// viewport and settings.
+    ///
+    /// Every cell value, formula and dependency goes, along with cell history, named and
+    /// locked ranges, column formulas, ownership tags, the row filter and the search. The grid keeps
+    /// its allocation for the rows written again.
+    pub fn clear(&mut self) {
+        // Undo puts back the whole sheet
+        self.note_undo_layout();
+        // A dry run reports each cell it clears
+        if self.dry_run.is_some() {
+            for index in 0..self.grid.len() {
+                self.journal_write(index);
+            }
+        }
+        // Unwritten rows read as empty
+        self.grid.clear();
+        self.cell_meta.clear();
+        self.children.clear();
+        self.range_children.clear();
+        self.criteria.clear();
+        self.extended_formulas.clear();
+        self.relative_refs.clear();
+        self.assigned.clear();
+        self.cell_history.clear();
+        self.named_ranges.clear();
+        self.locked_ranges.clear();
+        self.column_formulas.clear();
+        self.ownership.tags.clear();
+        self.row_filter = None;
+        self.pending_overwrite = None;
+        self.search = None;
+        self.last_edited = None;
+        self.cell_counts = CellCounts::default();
+    }
+
     /// Visualizes the relationships of a cell at the specified row and column.
     ///
     /// This function checks if the cell at the given coordinates is valid and then
@@ -24,13 +58,19 @@ impl Spreadsheet {
     ///
     /// * `row` - The row index of the cell (0-based).
     /// * `col` - The column index of the cell (0-based).
+    /// * `depth` - How many levels of ancestors and descendants to show.
     ///
     /// # Returns
     ///
     /// A `CommandStatus` indicating the success or failure of the visualization operation
-    pub fn visualize_cell_relationships(&self, row: i16, col: i16) -> CommandStatus {
+    pub fn visualize_cell_relationships(
+        &mut self,
+        row: i16,
+        col: i16,
+        depth: usize,
+    ) -> CommandStatus {
         // Check if the cell is valid
-        visualize_cells::visualize_cell_relationships(self, row, col)
+        visualize_cells::visualize_cell_relationships(self, row, col, depth)
     }
 
     /// Locks a specified range of cells to prevent modifications.
@@ -45,8 +85,19 @@ impl Spreadsheet {
         self.locked_ranges.push(range);
     }
 
+    /// Unlocks a range of cells, wherever they were locked from.
+    ///
+    /// The range is carved out of every locked range it overlaps, so unlocking one cell
+    /// of a locked block leaves the rest of the block locked.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The `Range` struct defining the cell range to unlock.
     pub fn unlock_range(&mut self, range: Range) {
-        self.locked_ranges.retain(|r: &Range| r != &range);
+        self.locked_ranges = std::mem::take(&mut self.locked_ranges)
+            .into_iter()
+            .flat_map(|locked| locked.subtract(&range))
+            .collect();
     }
 
     /// Checks if a cell at the specified row and column is locked.
@@ -63,22 +114,16 @@ impl Spreadsheet {
     ///
     /// A `bool` indicating whether the cell is locked (`true`) or not (`false`).
     pub fn is_cell_locked(&self, row: i16, col: i16) -> bool {
-        for range in &self.locked_ranges {
-            if row >= range.start_row
-                && row <= range.end_row
-                && col >= range.start_col
-                && col <= range.end_col
-            {
-                return true;
-            }
-        }
-        false
+        self.locked_ranges
+            .iter()
+            .any(|range| range.contains(row, col))
     }
 
     /// Sets the last edited cell to the specified row and column.
     ///
     /// Updates the `last_edited` field of the spreadsheet to store the coordinates
-    /// of the most recently edited cell.
+    /// of the most recently edited cell. Every edit passes through here, so this is
+    /// also where the sheet is marked as having unsaved changes.
     ///
     /// # Arguments
     ///
@@ -86,6 +131,7 @@ impl Spreadsheet {
     /// * `col` - The column index of the last edited cell (0-based).
     pub fn set_last_edited(&mut self, row: i16, col: i16) {
         self.last_edited = Some((row, col));
+        self.dirty = true;
     }
 
     /// Scrolls the viewport to the last edited cell.
@@ -127,6 +173,25 @@ impl Spreadsheet {
         format!("{}{}", col_name, row + 1)
     }
 
+    /// Formats a range as `<start>:<end>`, e.g. `A1:B2`, even for a single cell.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The range to name.
+    ///
+    /// # Returns
+    ///
+    /// A `String` such as `A1:B2`.
+    pub fn get_range_name(&self, range: &Range) -> String {
+        format!(
+            "{}{}:{}{}",
+            self.get_column_name(range.start_col),
+            range.start_row + 1,
+            self.get_column_name(range.end_col),
+            range.end_row + 1
+        )
+    }
+
     pub fn set_highlight(&mut self, row: i16, col: i16, highlight_type: HighlightType) {
         self.highlight_cell = self.get_key(row, col);
         self.highlight_type = highlight_type;
@@ -144,31 +209,42 @@ impl Spreadsheet {
 
         // Check if it's a parent of the highlighted cell
         let meta = self.cell_meta.get(&self.highlight_cell);
-        if let Some(meta) = meta {
-            if self.highlight_type == HighlightType::Parent
-                || self.highlight_type == HighlightType::Both
-            {
-                let rem = meta.formula % 10;
-                match rem {
-                    0 => {
-                        if meta.parent1 == cell_key || meta.parent2 == cell_key {
-                            return (true, HighlightType::Parent);
-                        }
+        if let Some(meta) = meta
+            && (self.highlight_type == HighlightType::Parent
+                || self.highlight_type == HighlightType::Both)
+        {
+            let rem = meta.formula % 10;
+            match rem {
+                0 => {
+                    if meta.parent1 == cell_key || meta.parent2 == cell_key {
+                        return (true, HighlightType::Parent);
                     }
-                    2 => {
-                        if meta.parent1 == cell_key {
-                            return (true, HighlightType::Parent);
-                        }
+                }
+                2 => {
+                    if meta.parent1 == cell_key {
+                        return (true, HighlightType::Parent);
+                    }
+                }
+                3 => {
+                    if meta.parent2 == cell_key {
+                        return (true, HighlightType::Parent);
                     }
-                    3 => {
-                        if meta.parent2 == cell_key {
-                            return (true, HighlightType::Parent);
-                        }
+                }
+                4 => {
+                    let (row, col) = self.get_row_col(cell_key);
+                    if let Some(formula) = self.extended_formulas.get(&self.highlight_cell)
+                        && (formula.cells().contains(&cell_key)
+                            || formula
+                                .ranges()
+                                .iter()
+                                .any(|range| range.contains(row, col)))
+                    {
+                        return (true, HighlightType::Parent);
                     }
-                    _ => {
-                        if self.is_cell_in_range(cell_key, meta.parent1, meta.parent2) {
-                            return (true, HighlightType::Parent);
-                        }
+                }
+                _ => {
+                    if self.is_cell_in_range(cell_key, meta.parent1, meta.parent2) {
+                        return (true, HighlightType::Parent);
                     }
                 }
             }
@@ -184,10 +260,11 @@ impl Spreadsheet {
             }
 
             // Check range-based children
-            is_contains |= self.range_children.iter().any(|rc| {
-                rc.child_key == cell_key
-                    && self.is_cell_in_range(self.highlight_cell, rc.start_key, rc.end_key)
-            });
+            let (highlight_row, highlight_col) = self.get_row_col(self.highlight_cell);
+            is_contains |= self
+                .range_children
+                .iter()
+                .any(|rc| rc.child_key == cell_key && rc.reads(highlight_row, highlight_col));
 
             if is_contains {
                 return (true, HighlightType::Child);
@@ -196,67 +273,6 @@ impl Spreadsheet {
         // If not a parent or child, return false
         (false, HighlightType::None)
     }
-
-    pub fn print_spreadsheet_with_highlights(&self) {
-        if !self.output_enabled {
-            return;
-        }
-
-        let start_row = self.viewport_row;
-        let start_col = self.viewport_col;
-        let display_row = min(self.rows - start_row, 10); // Display only a portion of the spreadsheet
-        let display_col = min(self.cols - start_col, 10);
-
-        // ANSI color codes
-        const RESET: &str = "\x1b[0m";
-        const RED: &str = "\x1b[1;31m"; // Bold red for parents
-        const GREEN: &str = "\x1b[1;32m"; // Bold green for children
-        const CYAN: &str = "\x1b[1;36m"; // Bold cyan for main cell
-
-        // Print column headers
-        print!("     ");
-        for i in 0..display_col {
-            print!("{:<8} ", self.get_column_name(start_col + i));
-        }
-        println!();
-
-        // Print rows with data
-        for i in 0..display_row {
-            print!("{:<4} ", start_row + i + 1); // Show 1-based row numbers
-            for j in 0..display_col {
-                let row = start_row + i;
-                let col = start_col + j;
-                let cell_key = self.get_key(row, col);
-                let cell_value = self.get_cell(row, col);
-
-                // Check if this cell should be highlighted - only check cells in view
-                let (is_highlighted, highlight_type) = self.is_highlighted(cell_key);
-
-                // Apply appropriate color based on highlight status
-                // If it's the main highlighted cell itself
-                if cell_key == self.highlight_cell {
-                    print!("{}", CYAN);
-                } else if is_highlighted {
-                    match highlight_type {
-                        HighlightType::Parent => print!("{}", RED),
-                        HighlightType::Child => print!("{}", GREEN),
-                        HighlightType::Both => {} // This shouldn't happen due to circular ref prevention
-                        HighlightType::None => {} // Main highlighted cell
-                    }
-                }
-
-                // Print cell value
-                match cell_value {
-                    CellValue::Integer(value) => print!("{:<8} ", value),
-                    CellValue::Error => print!("{:<8} ", "ERR"),
-                }
-
-                // Reset color if necessary
-                print!("{}", RESET);
-            }
-            println!();
-        }
-    }
 }
 
 #[cfg(test)]
@@ -276,7 +292,7 @@ mod tests {
         let mut sheet = Spreadsheet::create(5, 5).unwrap();
         sheet.output_enabled = true;
         sheet.set_highlight(0, 0, HighlightType::Parent);
-        sheet.print_spreadsheet_with_highlights(); // Should not panic
+        sheet.print_spreadsheet(); // Should not panic
     }
 
     #[test]
@@ -288,7 +304,7 @@ mod tests {
             end_row: 2,
             end_col: 2,
         };
-        sheet.lock_range(range.clone());
+        sheet.lock_range(range);
         assert!(sheet.is_cell_locked(1, 1));
         sheet.unlock_range(range);
         assert!(!sheet.is_cell_locked(1, 1));
@@ -353,9 +369,10 @@ mod tests {
     fn test_cell_history_multiple_values() {
         let mut sheet = Spreadsheet::create(5, 5).unwrap();
         let key = sheet.get_key(0, 0);
-        sheet
-            .cell_history
-            .insert(key, vec![CellValue::Integer(1), CellValue::Integer(2)]);
+        sheet.cell_history.insert(
+            key,
+            vec![(CellValue::Integer(1), None), (CellValue::Integer(2), None)],
+        );
         assert_eq!(sheet.cell_history[&key].len(), 2);
     }
 
@@ -383,4 +400,40 @@ mod tests {
         assert!(highlighted);
         assert_eq!(htype, HighlightType::Child);
     }
+
+    #[test]
+    fn test_clear_sheet_removes_dependents_and_locks() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut sleep_time = 0.0;
+        for command in [
+            "A1=5",
+            "A1=6",
+            "B1=A1+1",
+            "C1=SUM(A1:B1)",
+            "name A1:B1 pair",
+            "lock_cell C1",
+            "clear_sheet",
+        ] {
+            assert_eq!(
+                crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk
+            );
+        }
+        assert!(sheet.cell_values().all(|value| *value == CellValue::Empty));
+        assert!(sheet.cell_meta.is_empty());
+        assert!(sheet.children.is_empty());
+        assert!(sheet.range_children.is_empty());
+        assert!(sheet.cell_history.is_empty());
+        assert!(sheet.named_ranges.is_empty());
+        assert!(!sheet.is_cell_locked(0, 2));
+        assert_eq!(sheet.cell_counts, CellCounts::default());
+        assert!(sheet.dirty);
+
+        // A1 no longer feeds anything, and C1 takes a value again
+        for command in ["A1=10", "C1=3"] {
+            crate::evaluator::handle_command(&mut sheet, command, &mut sleep_time);
+        }
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Empty);
+        assert_eq!(*sheet.get_cell(0, 2), CellValue::Integer(3));
+    }
 }
@@ -0,0 +1,435 @@
+//! Structured snapshots of a block of cells, as data rather than formatted text.
+//!
+//! The terminal renderer draws the viewport from a `ViewportSnapshot`, so an embedder
+//! building its own display sees exactly the cells, display strings, and highlight roles
+//! the terminal shows. The snapshot types are stable: fields may be added, but existing
+//! fields keep their names and meaning.
+
+use crate::cell::CellValue;
+use crate::range::Range;
+#[cfg(feature = "extensions")]
+use crate::spreadsheet::HighlightType;
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use std::cmp::min;
+use std::fmt::Write;
+
+/// Rows and columns shown in the terminal viewport.
+pub const VIEWPORT_SIZE: i16 = 10;
+/// Minimum width of a rendered cell, not counting the separating space.
+pub const CELL_WIDTH: usize = 8;
+
+// ANSI color codes for highlighted cells
+#[cfg(feature = "extensions")]
+const RESET: &str = "\x1b[0m";
+#[cfg(feature = "extensions")]
+const RED: &str = "\x1b[1;31m"; // Bold red for parents
+#[cfg(feature = "extensions")]
+const GREEN: &str = "\x1b[1;32m"; // Bold green for children
+#[cfg(feature = "extensions")]
+const CYAN: &str = "\x1b[1;36m"; // Bold cyan for main cell
+
+/// The part a cell plays in the current dependency highlight.
+///
+/// # Variants
+///
+/// * `None` - Not highlighted.
+/// * `Focus` - The cell whose relationships are highlighted.
+/// * `Parent` - A cell the focus cell depends on.
+/// * `Child` - A cell that depends on the focus cell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HighlightRole {
+    None,
+    #[cfg(feature = "extensions")]
+    Focus,
+    #[cfg(feature = "extensions")]
+    Parent,
+    #[cfg(feature = "extensions")]
+    Child,
+}
+
+/// Everything needed to draw one cell.
+///
+/// # Fields
+///
+/// * `value` - The cell's value.
+/// * `display` - The text the terminal shows for the value, before padding.
+/// * `is_error` - Whether the value is an error.
+/// * `has_formula` - Whether the cell holds a formula rather than a literal.
+/// * `locked` - Whether the cell is in a locked range.
+/// * `highlighted` - The cell's role in the current dependency highlight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellSnapshot {
+    pub value: CellValue,
+    pub display: String,
+    pub is_error: bool,
+    pub has_formula: bool,
+    pub locked: bool,
+    pub highlighted: HighlightRole,
+}
+
+/// A rectangle of cells captured at one moment.
+///
+/// # Fields
+///
+/// * `origin_row` - Zero-based row of the top-left cell.
+/// * `origin_col` - Zero-based column of the top-left cell.
+/// * `rows` - Number of rows captured.
+/// * `cols` - Number of columns captured.
+/// * `column_names` - Header names of the captured columns, left to right.
+/// * `row_numbers` - Zero-based rows captured, top to bottom; rows hidden by a filter are left out.
+/// * `cells` - The cells in row-major order, `rows * cols` of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewportSnapshot {
+    pub origin_row: i16,
+    pub origin_col: i16,
+    pub rows: i16,
+    pub cols: i16,
+    pub column_names: Vec<String>,
+    pub row_numbers: Vec<i16>,
+    pub cells: Vec<CellSnapshot>,
+}
+
+/// Formats a cell value the way the terminal shows it.
+///
+/// # Arguments
+///
+/// * `value` - The cell value.
+///
+/// # Returns
+///
+/// * `String` - The number, `ERR` for an error, the text cut to the column width, or
+///   nothing for an empty cell
+pub fn format_cell_display(value: &CellValue) -> String {
+    match value {
+        CellValue::Empty => String::new(),
+        CellValue::Integer(value) => value.to_string(),
+        CellValue::Error(kind) => kind.code(),
+        CellValue::Text(text) => text.chars().take(CELL_WIDTH).collect(),
+    }
+}
+
+impl ViewportSnapshot {
+    /// Renders the snapshot as the terminal shows it: a header of column names, then
+    /// one line per row with its 1-based number and padded cells.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The rendered grid, each line ending in a newline
+    pub fn render(&self) -> String {
+        let mut out = String::from("     ");
+        for name in &self.column_names {
+            let _ = write!(out, "{:<width$} ", name, width = CELL_WIDTH);
+        }
+        out.push('\n');
+
+        let rows = self.cells.chunks(self.cols.max(1) as usize);
+        for (&number, row) in self.row_numbers.iter().zip(rows) {
+            let _ = write!(out, "{:<4} ", number as usize + 1);
+            for cell in row {
+                let color = match cell.highlighted {
+                    HighlightRole::None => "",
+                    #[cfg(feature = "extensions")]
+                    HighlightRole::Focus => CYAN,
+                    #[cfg(feature = "extensions")]
+                    HighlightRole::Parent => RED,
+                    #[cfg(feature = "extensions")]
+                    HighlightRole::Child => GREEN,
+                };
+                out.push_str(color);
+                let _ = write!(out, "{:<width$} ", cell.display, width = CELL_WIDTH);
+                #[cfg(feature = "extensions")]
+                if !color.is_empty() {
+                    out.push_str(RESET);
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl Spreadsheet {
+    /// Captures the cells currently in the viewport.
+    ///
+    /// # Returns
+    ///
+    /// * `ViewportSnapshot` - Up to `VIEWPORT_SIZE` rows and columns from the viewport origin
+    pub fn snapshot_viewport(&self) -> ViewportSnapshot {
+        let range = Range {
+            start_row: self.viewport_row,
+            start_col: self.viewport_col,
+            end_row: self.viewport_row + min(self.rows - self.viewport_row, VIEWPORT_SIZE) - 1,
+            end_col: self.viewport_col + min(self.cols - self.viewport_col, VIEWPORT_SIZE) - 1,
+        };
+        #[cfg(feature = "extensions")]
+        if self.row_filter.is_some() {
+            return self.capture(self.displayed_rows(), range.start_col, range.end_col);
+        }
+        self.snapshot_range(&range)
+            .expect("viewport lies inside the sheet")
+    }
+
+    /// Returns the rows the viewport shows, top to bottom.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<i16>` - Up to `VIEWPORT_SIZE` row numbers from the viewport origin
+    #[cfg(feature = "extensions")]
+    pub fn displayed_rows(&self) -> Vec<i16> {
+        // Rows hidden by a filter are skipped, so the viewport fills with visible rows
+        if self.row_filter.is_some() {
+            return (self.viewport_row..self.rows)
+                .filter(|&row| self.is_row_visible(row))
+                .take(VIEWPORT_SIZE as usize)
+                .collect();
+        }
+        (self.viewport_row..self.viewport_row + min(self.rows - self.viewport_row, VIEWPORT_SIZE))
+            .collect()
+    }
+
+    /// Returns how many columns the viewport shows.
+    #[cfg(feature = "extensions")]
+    pub fn displayed_cols(&self) -> i16 {
+        min(self.cols - self.viewport_col, VIEWPORT_SIZE)
+    }
+
+    /// Captures an arbitrary rectangle of cells.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The rectangle to capture.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ViewportSnapshot)` - The captured cells.
+    /// * `Err(CommandStatus::Unrecognized)` - If the range is inverted or extends past the sheet
+    pub fn snapshot_range(&self, range: &Range) -> Result<ViewportSnapshot, CommandStatus> {
+        if range.start_row < 0
+            || range.start_col < 0
+            || range.start_row > range.end_row
+            || range.start_col > range.end_col
+            || range.end_row >= self.rows
+            || range.end_col >= self.cols
+        {
+            return Err(CommandStatus::Unrecognized);
+        }
+        let row_numbers = (range.start_row..=range.end_row).collect();
+        Ok(self.capture(row_numbers, range.start_col, range.end_col))
+    }
+
+    /// Captures the given rows between two columns.
+    fn capture(&self, row_numbers: Vec<i16>, start_col: i16, end_col: i16) -> ViewportSnapshot {
+        let cols = end_col - start_col + 1;
+        let mut cells = Vec::with_capacity(row_numbers.len() * cols as usize);
+        for &row in &row_numbers {
+            for col in start_col..=end_col {
+                cells.push(self.snapshot_cell(row, col));
+            }
+        }
+
+        ViewportSnapshot {
+            origin_row: row_numbers.first().copied().unwrap_or(self.viewport_row),
+            origin_col: start_col,
+            rows: row_numbers.len() as i16,
+            cols,
+            column_names: (start_col..=end_col)
+                .map(|col| self.get_column_name(col))
+                .collect(),
+            row_numbers,
+            cells,
+        }
+    }
+
+    /// Captures a single cell.
+    fn snapshot_cell(&self, row: i16, col: i16) -> CellSnapshot {
+        let key = self.get_key(row, col);
+        let value = self.get_cell(row, col).clone();
+        CellSnapshot {
+            display: format_cell_display(&value),
+            is_error: value.is_error(),
+            has_formula: self
+                .cell_meta
+                .get(&key)
+                .is_some_and(|meta| meta.formula != -1),
+            #[cfg(feature = "extensions")]
+            locked: self.is_cell_locked(row, col),
+            #[cfg(not(feature = "extensions"))]
+            locked: false,
+            highlighted: self.highlight_role(key),
+            value,
+        }
+    }
+
+    /// Returns the role of a cell in the current dependency highlight.
+    #[cfg(feature = "extensions")]
+    fn highlight_role(&self, key: i32) -> HighlightRole {
+        if self.highlight_type == HighlightType::None {
+            return HighlightRole::None;
+        }
+        if key == self.highlight_cell {
+            return HighlightRole::Focus;
+        }
+        match self.is_highlighted(key) {
+            (true, HighlightType::Parent) => HighlightRole::Parent,
+            (true, HighlightType::Child) => HighlightRole::Child,
+            _ => HighlightRole::None,
+        }
+    }
+
+    /// Returns the role of a cell in the current dependency highlight.
+    #[cfg(not(feature = "extensions"))]
+    fn highlight_role(&self, _key: i32) -> HighlightRole {
+        HighlightRole::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::handle_command;
+
+    /// Renders the viewport the way the terminal did before snapshots existed.
+    fn direct_render(sheet: &Spreadsheet) -> String {
+        let start_row = sheet.viewport_row;
+        let start_col = sheet.viewport_col;
+        let display_row = min(sheet.rows - start_row, 10);
+        let display_col = min(sheet.cols - start_col, 10);
+
+        let mut out = String::from("     ");
+        for i in 0..display_col {
+            out += &format!("{:<8} ", sheet.get_column_name(start_col + i));
+        }
+        out.push('\n');
+        for i in 0..display_row {
+            out += &format!("{:<4} ", start_row + i + 1);
+            for j in 0..display_col {
+                match sheet.get_cell(start_row + i, start_col + j) {
+                    CellValue::Integer(value) => out += &format!("{:<8} ", value),
+                    CellValue::Text(text) => out += &format!("{:<8.8} ", text),
+                    CellValue::Empty => out += &format!("{:<8} ", ""),
+                    CellValue::Error(kind) => out += &format!("{:<8} ", kind.code()),
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    fn run(sheet: &mut Spreadsheet, commands: &[&str]) {
+        let mut sleep_time = 0.0;
+        for command in commands {
+            assert_eq!(
+                handle_command(sheet, command, &mut sleep_time),
+                CommandStatus::CmdOk,
+                "{}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_matches_direct_render() {
+        let mut small = Spreadsheet::create(3, 4).unwrap();
+        run(
+            &mut small,
+            &[
+                "A1=5",
+                "B2=A1*-3",
+                "C3=1/0",
+                "D1=123456789",
+                "C1=\"Quarterly revenue\"",
+            ],
+        );
+
+        let mut large = Spreadsheet::create(30, 30).unwrap();
+        run(&mut large, &["A1=7", "L15=A1+1", "M16=SUM(A1:L15)"]);
+        large.viewport_row = 12;
+        large.viewport_col = 9;
+
+        let mut edge = Spreadsheet::create(25, 40).unwrap();
+        run(&mut edge, &["AN25=5/0", "AM24=AN25+1"]);
+        edge.viewport_row = 20;
+        edge.viewport_col = 35;
+
+        for sheet in [&small, &large, &edge] {
+            assert_eq!(sheet.snapshot_viewport().render(), direct_render(sheet));
+        }
+    }
+
+    #[test]
+    fn test_snapshot_cell_fields() {
+        let mut sheet = Spreadsheet::create(3, 3).unwrap();
+        run(&mut sheet, &["A1=4", "B1=A1+1", "C1=A1/0"]);
+        let snapshot = sheet.snapshot_viewport();
+        assert_eq!((snapshot.rows, snapshot.cols), (3, 3));
+        assert_eq!(snapshot.column_names, vec!["A", "B", "C"]);
+        assert_eq!(snapshot.cells.len(), 9);
+
+        let b1 = &snapshot.cells[1];
+        assert_eq!(b1.value, CellValue::Integer(5));
+        assert_eq!(b1.display, "5");
+        assert!(b1.has_formula && !b1.is_error && !b1.locked);
+        assert_eq!(b1.highlighted, HighlightRole::None);
+        assert!(snapshot.cells[2].is_error);
+        assert!(!snapshot.cells[0].has_formula);
+    }
+
+    #[test]
+    fn test_snapshot_range_bounds() {
+        let sheet = Spreadsheet::create(5, 5).unwrap();
+        let range = |start_row, start_col, end_row, end_col| Range {
+            start_row,
+            start_col,
+            end_row,
+            end_col,
+        };
+        let snapshot = sheet.snapshot_range(&range(1, 2, 3, 4)).unwrap();
+        assert_eq!((snapshot.origin_row, snapshot.origin_col), (1, 2));
+        assert_eq!(snapshot.column_names, vec!["C", "D", "E"]);
+        assert_eq!(snapshot.cells.len(), 9);
+
+        for bad in [
+            range(0, 0, 5, 0),
+            range(0, 0, 0, 5),
+            range(3, 0, 2, 0),
+            range(-1, 0, 0, 0),
+        ] {
+            assert_eq!(sheet.snapshot_range(&bad), Err(CommandStatus::Unrecognized));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_snapshot_locked_and_highlighted_cells() {
+        let mut sheet = Spreadsheet::create(2, 3).unwrap();
+        run(
+            &mut sheet,
+            &["A1=2", "B1=A1*3", "C1=B1+1", "lock_cell A2:B2"],
+        );
+        sheet.set_highlight(0, 1, HighlightType::Both);
+        let snapshot = sheet.snapshot_viewport();
+
+        let roles: Vec<HighlightRole> = snapshot.cells.iter().map(|c| c.highlighted).collect();
+        assert_eq!(
+            roles[..3],
+            [
+                HighlightRole::Parent,
+                HighlightRole::Focus,
+                HighlightRole::Child
+            ]
+        );
+        let locked: Vec<bool> = snapshot.cells.iter().map(|c| c.locked).collect();
+        assert_eq!(locked, vec![false, false, false, true, true, false]);
+
+        let expected = format!(
+            "     A        B        C        \n\
+             1    {RED}2        {RESET}{CYAN}6        {RESET}{GREEN}7        {RESET}\n\
+             2                               \n"
+        );
+        assert_eq!(snapshot.render(), expected);
+
+        // Without a highlight the render is plain, locked cells included
+        sheet.disable_highlight();
+        assert_eq!(sheet.snapshot_viewport().render(), direct_render(&sheet));
+    }
+}
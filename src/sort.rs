@@ -0,0 +1,488 @@
+//! Sorting rows of a range by one or more key columns.
+//!
+//! Sorting moves whole rows of the range. Formulas that move keep pointing at the same
+//! data: references to cells inside the sorted rows follow those rows, while references
+//! to cells outside the range are left unchanged.
+
+use crate::cell::{CellValue, compare_cell_values};
+use crate::cell_history::snapshot_entry;
+use crate::expr_parser::Criterion;
+use crate::extended_formula::ExtendedFormula;
+use crate::graph::{add_children, parents_are_cells};
+use crate::range::{Range, resolve_range};
+use crate::reevaluate_topo::toposort_reval_many;
+use crate::spreadsheet::{CellMeta, CommandStatus, MAX_COLUMN_NAME_LEN, Spreadsheet};
+use std::cmp::Ordering;
+
+/// One sort key: a column of the sheet and its direction.
+///
+/// # Fields
+///
+/// * `col` - Zero-based column index.
+/// * `descending` - Sort largest first if true.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub col: i16,
+    pub descending: bool,
+}
+
+/// Everything needed to put a cell back exactly as it was.
+///
+/// # Fields
+///
+/// * `value` - The cell's value.
+/// * `meta` - Its formula metadata, if any.
+/// * `relative` - Its relative and pinned reference flags.
+/// * `criterion` - The criterion of a `COUNTIF`-style formula.
+/// * `extended` - Its extended formula, if any.
+/// * `literals` - Whether its formula is of two literals, as in `1/0`, whose parents are
+///   not cells.
+/// * `assigned` - Whether it was ever assigned.
+#[derive(Clone, PartialEq)]
+pub struct CellSnapshot {
+    pub value: CellValue,
+    pub meta: Option<CellMeta>,
+    pub relative: Option<u8>,
+    pub criterion: Option<Criterion>,
+    pub extended: Option<ExtendedFormula>,
+    pub literals: bool,
+    pub assigned: bool,
+}
+
+impl CellSnapshot {
+    /// A cell that was never set.
+    pub const EMPTY: CellSnapshot = CellSnapshot {
+        value: CellValue::Empty,
+        meta: None,
+        relative: None,
+        criterion: None,
+        extended: None,
+        literals: false,
+        assigned: false,
+    };
+
+    /// Captures a cell.
+    pub fn capture(sheet: &Spreadsheet, row: i16, col: i16) -> Self {
+        let key = sheet.get_key(row, col);
+        let meta = sheet.cell_meta.get(&key).cloned();
+        CellSnapshot {
+            value: sheet.get_cell(row, col).clone(),
+            meta,
+            relative: sheet.relative_refs.get(&key).copied(),
+            criterion: sheet.criteria.get(&key).copied(),
+            extended: sheet.extended_formulas.get(&key).cloned(),
+            literals: meta.is_some_and(|meta| {
+                meta.formula != -1
+                    && meta.formula % 10 == 0
+                    && !parents_are_cells(&sheet.children, key, &meta)
+            }),
+            assigned: sheet.assigned.contains(&key),
+        }
+    }
+
+    /// Writes the snapshot into a cell, adding the dependency edges of its formula.
+    ///
+    /// The cell must have no formula left; call `Spreadsheet::clear_formula` first.
+    pub fn write(&self, sheet: &mut Spreadsheet, row: i16, col: i16) {
+        let key = sheet.get_key(row, col);
+        *sheet.get_mut_cell(row, col) = self.value.clone();
+        if self.assigned {
+            sheet.assigned.insert(key);
+        } else {
+            sheet.assigned.remove(&key);
+        }
+        // add_children reads an extended formula's cells from the side table
+        if let Some(extended) = &self.extended {
+            sheet.extended_formulas.insert(key, extended.clone());
+        }
+        if let Some(meta) = &self.meta {
+            let (parent1, parent2, formula) = (meta.parent1, meta.parent2, meta.formula);
+            sheet.cell_meta.insert(key, *meta);
+            // Two literals are no cells to depend on
+            if !self.literals {
+                add_children(sheet, parent1, parent2, formula, row, col);
+            }
+        }
+        if let Some(flags) = self.relative {
+            sheet.relative_refs.insert(key, flags);
+        }
+        if let Some(criterion) = self.criterion {
+            sheet.criteria.insert(key, criterion);
+        }
+    }
+}
+
+/// Parses the arguments of `sort <range> by <col> [asc|desc][, <col> [asc|desc]]... [--header]`,
+/// or of the single-key form `sort <range> <col> [asc|desc] [--header]`.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `args` - The text after `sort `.
+///
+/// # Returns
+///
+/// * `Ok((range, keys, header))` - The parsed command.
+/// * `Err(CommandStatus::Unrecognized)` - If the syntax is wrong.
+/// * `Err(CommandStatus::InvalidCell)` - If a key column lies outside the range
+pub fn parse_sort_args(
+    sheet: &Spreadsheet,
+    args: &str,
+) -> Result<(Range, Vec<SortKey>, bool), CommandStatus> {
+    let (args, header) = match args.trim_end().strip_suffix("--header") {
+        Some(rest) => (rest, true),
+        None => (args, false),
+    };
+    let (range_str, keys_str) = match args.split_once(" by ") {
+        Some(parts) => parts,
+        // The short form `sort A1:C10 B asc` takes a single key
+        None => args
+            .trim()
+            .split_once(' ')
+            .filter(|(_, key)| !key.contains(','))
+            .ok_or(CommandStatus::Unrecognized)?,
+    };
+    let range = resolve_range(sheet, range_str.trim())?;
+
+    let mut keys = Vec::new();
+    for key_str in keys_str.split(',') {
+        let mut parts = key_str.split_whitespace();
+        let col_name = parts.next().ok_or(CommandStatus::Unrecognized)?;
+        let descending = match parts.next() {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(_) => return Err(CommandStatus::Unrecognized),
+        };
+        if parts.next().is_some()
+            || col_name.len() > MAX_COLUMN_NAME_LEN
+            || !col_name.bytes().all(|b| b.is_ascii_uppercase())
+        {
+            return Err(CommandStatus::Unrecognized);
+        }
+        let col = sheet.column_name_to_index(col_name);
+        if col < range.start_col || col > range.end_col {
+            return Err(CommandStatus::InvalidCell);
+        }
+        keys.push(SortKey { col, descending });
+    }
+    Ok((range, keys, header))
+}
+
+/// Compares two rows by the given keys, errors sorting last in either direction.
+fn compare_rows(sheet: &Spreadsheet, a: i16, b: i16, keys: &[SortKey]) -> Ordering {
+    for key in keys {
+        let left = sheet.get_cell(a, key.col);
+        let right = sheet.get_cell(b, key.col);
+        let ordering = match (left, right) {
+            (CellValue::Integer(_), CellValue::Integer(_)) if key.descending => {
+                compare_cell_values(left, right).reverse()
+            }
+            _ => compare_cell_values(left, right),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Captures the cells of rows `first_row..=range.end_row` within the range's columns.
+fn snapshot_rows(sheet: &Spreadsheet, range: &Range, first_row: i16) -> Vec<Vec<CellSnapshot>> {
+    (first_row..=range.end_row)
+        .map(|row| {
+            (range.start_col..=range.end_col)
+                .map(|col| CellSnapshot::capture(sheet, row, col))
+                .collect()
+        })
+        .collect()
+}
+
+/// Writes a row layout back into the sheet, rebuilding dependency edges.
+fn apply_rows(sheet: &mut Spreadsheet, range: &Range, first_row: i16, rows: &[Vec<CellSnapshot>]) {
+    // Drop every old edge first so no stale metadata is consulted while writing
+    for row in first_row..=range.end_row {
+        for col in range.start_col..=range.end_col {
+            sheet.clear_formula(row, col);
+        }
+    }
+
+    for (row, cells) in (first_row..).zip(rows) {
+        for (col, cell) in (range.start_col..).zip(cells) {
+            cell.write(sheet, row, col);
+        }
+    }
+}
+
+/// Sorts the rows of a range by one or more keys using a stable sort.
+///
+/// # Arguments
+///
+/// * `sheet` - The mutable spreadsheet.
+/// * `range` - The cells to sort; whole rows of it move together.
+/// * `keys` - Sort keys in priority order.
+/// * `header` - Keep the first row of the range in place.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::LockedCell` - If any cell in the range is locked.
+/// * `CommandStatus::NotOwner` - If any cell in the range is owned by someone else.
+/// * `CommandStatus::CircularRef` - If the new layout would create a cycle; the sheet is unchanged
+pub fn sort_range(
+    sheet: &mut Spreadsheet,
+    range: &Range,
+    keys: &[SortKey],
+    header: bool,
+    sleep_time: &mut f64,
+) -> CommandStatus {
+    for row in range.start_row..=range.end_row {
+        for col in range.start_col..=range.end_col {
+            if sheet.is_cell_locked(row, col) {
+                return CommandStatus::LockedCell;
+            }
+        }
+    }
+    if range
+        .cells()
+        .any(|(row, col)| sheet.check_owner(row, col) != CommandStatus::CmdOk)
+    {
+        return CommandStatus::NotOwner;
+    }
+
+    let first_row = range.start_row + header as i16;
+    let old_rows: Vec<i16> = (first_row..=range.end_row).collect();
+    let mut order = old_rows.clone();
+    // `sort_by` is stable, so rows with equal keys keep their original order
+    order.sort_by(|&a, &b| compare_rows(sheet, a, b, keys));
+    if order == old_rows {
+        return CommandStatus::CmdOk;
+    }
+
+    let mut new_row_of = vec![0; order.len()];
+    for (index, &old_row) in order.iter().enumerate() {
+        new_row_of[(old_row - first_row) as usize] = first_row + index as i16;
+    }
+    let remap = |key: i32| -> i32 {
+        let (row, col) = sheet.get_row_col(key);
+        if row >= first_row && row <= range.end_row && range.contains(row, col) {
+            sheet.get_key(new_row_of[(row - first_row) as usize], col)
+        } else {
+            key
+        }
+    };
+
+    let original = snapshot_rows(sheet, range, first_row);
+    let sorted: Vec<Vec<CellSnapshot>> = order
+        .iter()
+        .map(|&old_row| {
+            original[(old_row - first_row) as usize]
+                .iter()
+                .map(|cell| {
+                    let mut cell = cell.clone();
+                    if let Some(meta) = cell.meta.as_mut() {
+                        // Single-cell parents follow their rows; range corners stay put
+                        match meta.formula % 10 {
+                            0 => {
+                                meta.parent1 = remap(meta.parent1);
+                                meta.parent2 = remap(meta.parent2);
+                            }
+                            2 => meta.parent1 = remap(meta.parent1),
+                            3 => meta.parent2 = remap(meta.parent2),
+                            _ => {}
+                        }
+                    }
+                    if let Some(extended) = cell.extended.as_mut() {
+                        extended.remap_cells(|key, _| remap(key));
+                    }
+                    cell
+                })
+                .collect()
+        })
+        .collect();
+
+    let area_keys: Vec<i32> = (first_row..=range.end_row)
+        .flat_map(|row| (range.start_col..=range.end_col).map(move |col| (row, col)))
+        .map(|(row, col)| sheet.get_key(row, col))
+        .collect();
+
+    apply_rows(sheet, range, first_row, &sorted);
+    if toposort_reval_many(sheet, &area_keys, sleep_time) {
+        apply_rows(sheet, range, first_row, &original);
+        toposort_reval_many(sheet, &area_keys, sleep_time);
+        return CommandStatus::CircularRef;
+    }
+    // Every cell whose value changed can be put back with `history`
+    for (row, cells) in (first_row..).zip(&original) {
+        for (col, cell) in (range.start_col..).zip(cells) {
+            if *sheet.get_cell(row, col) != cell.value {
+                let key = sheet.get_key(row, col);
+                sheet.push_history(key, snapshot_entry(cell));
+            }
+        }
+    }
+    sheet.dirty = true;
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::handle_command;
+    use crate::extensions::get_formula_string;
+
+    fn run(sheet: &mut Spreadsheet, commands: &[&str]) {
+        let mut sleep_time = 0.0;
+        for command in commands {
+            handle_command(sheet, command, &mut sleep_time);
+        }
+    }
+
+    fn column(sheet: &Spreadsheet, col: i16, rows: std::ops::RangeInclusive<i16>) -> Vec<i32> {
+        rows.map(|row| match sheet.get_cell(row, col) {
+            CellValue::Integer(v) => *v,
+            CellValue::Error(_) => i32::MIN,
+            CellValue::Text(text) => panic!("expected a number, found {:?}", text),
+            CellValue::Empty => panic!("expected a number, found an empty cell"),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_parse_sort_args() {
+        let sheet = Spreadsheet::create(10, 10).unwrap();
+        let (range, keys, header) = parse_sort_args(&sheet, "A1:D5 by B desc, C asc").unwrap();
+        assert_eq!(range.end_col, 3);
+        assert_eq!(
+            keys,
+            vec![
+                SortKey {
+                    col: 1,
+                    descending: true
+                },
+                SortKey {
+                    col: 2,
+                    descending: false
+                }
+            ]
+        );
+        assert!(!header);
+        assert!(parse_sort_args(&sheet, "A1:D5 by B --header").unwrap().2);
+        assert_eq!(
+            parse_sort_args(&sheet, "A1:D5 by E").unwrap_err(),
+            CommandStatus::InvalidCell
+        );
+        assert_eq!(
+            parse_sort_args(&sheet, "A1:D5 by B sideways").unwrap_err(),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            parse_sort_args(&sheet, "A1:D5 B desc").unwrap().1,
+            vec![SortKey {
+                col: 1,
+                descending: true
+            }]
+        );
+        assert_eq!(
+            parse_sort_args(&sheet, "A1:D5 B, C").unwrap_err(),
+            CommandStatus::Unrecognized
+        );
+        assert_eq!(
+            parse_sort_args(&sheet, "A1:D5").unwrap_err(),
+            CommandStatus::Unrecognized
+        );
+    }
+
+    #[test]
+    fn test_sort_two_keys_with_ties_is_stable() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        // Columns: A = marker (original position), B = primary, C = secondary
+        run(
+            &mut sheet,
+            &[
+                "A1=1", "B1=2", "C1=9", "A2=2", "B2=5", "C2=1", "A3=3", "B3=2", "C3=3", "A4=4",
+                "B4=5", "C4=1", "A5=5", "B5=2", "C5=3",
+            ],
+        );
+        assert_eq!(
+            handle_command(&mut sheet, "sort A1:C5 by B desc, C asc", &mut 0.0),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(column(&sheet, 1, 0..=4), vec![5, 5, 2, 2, 2]);
+        assert_eq!(column(&sheet, 2, 0..=4), vec![1, 1, 3, 3, 9]);
+        // Equal composite keys keep their original relative order
+        assert_eq!(column(&sheet, 0, 0..=4), vec![2, 4, 3, 5, 1]);
+    }
+
+    #[test]
+    fn test_sort_header_row_stays() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        run(&mut sheet, &["A1=100", "A2=3", "A3=1", "A4=2"]);
+        assert_eq!(
+            handle_command(&mut sheet, "sort A1:A4 by A --header", &mut 0.0),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(column(&sheet, 0, 0..=3), vec![100, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_sort_moves_formulas_and_keeps_outside_dependents() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        run(
+            &mut sheet,
+            &["A1=3", "A2=1", "A3=2", "B1=A1*10", "B2=A2*10", "B3=A3*10"],
+        );
+        run(&mut sheet, &["D1=SUM(A1:B3)", "D2=A1"]);
+        assert_eq!(
+            handle_command(&mut sheet, "sort A1:B3 by A", &mut 0.0),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(column(&sheet, 0, 0..=2), vec![1, 2, 3]);
+        assert_eq!(column(&sheet, 1, 0..=2), vec![10, 20, 30]);
+        assert_eq!(get_formula_string(&sheet, 0, 1), "A1*10");
+        // The row formulas still track their own row
+        run(&mut sheet, &["A1=7"]);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(70));
+        // Dependents outside the range keep their position-based references
+        assert_eq!(
+            *sheet.get_cell(0, 3),
+            CellValue::Integer(7 + 2 + 3 + 70 + 20 + 30)
+        );
+        assert_eq!(*sheet.get_cell(1, 3), CellValue::Integer(7));
+    }
+
+    #[test]
+    fn test_sort_records_history_of_moved_cells() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        run(&mut sheet, &["A1=3", "A2=1", "A3=3", "B1=SUM(A1:A3)"]);
+        let history_before = sheet.cell_history.clone();
+        assert_eq!(
+            handle_command(&mut sheet, "sort A1:A3 A asc", &mut 0.0),
+            CommandStatus::CmdOk
+        );
+        assert_eq!(column(&sheet, 0, 0..=2), vec![1, 3, 3]);
+        // A3 kept its value, so only A1 and A2 gained an entry
+        assert_eq!(
+            sheet.cell_history[&0],
+            vec![(CellValue::Empty, None), (CellValue::Integer(3), None)]
+        );
+        assert_eq!(
+            sheet.cell_history[&sheet.get_key(2, 0)],
+            history_before[&sheet.get_key(2, 0)]
+        );
+        run(&mut sheet, &["history A1", "history A2"]);
+        assert_eq!(column(&sheet, 0, 0..=2), vec![3, 1, 3]);
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(7));
+    }
+
+    #[test]
+    fn test_sort_refuses_locked_range() {
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        run(&mut sheet, &["A1=2", "A2=1", "lock_cell A2"]);
+        assert_eq!(
+            handle_command(&mut sheet, "sort A1:A2 by A", &mut 0.0),
+            CommandStatus::LockedCell
+        );
+        assert_eq!(column(&sheet, 0, 0..=1), vec![2, 1]);
+    }
+}
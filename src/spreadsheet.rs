@@ -1,13 +1,57 @@
+#[cfg(feature = "extensions")]
+use crate::autosave::{Autosave, DEFAULT_AUTOSAVE_EVERY};
 use crate::cell::{CellValue, parse_cell_reference};
 #[cfg(feature = "extensions")]
-use crate::formula::Range;
-use std::cmp::min;
+use crate::cell_history::{DEFAULT_HISTORY_LIMIT, HistoryEntry};
+#[cfg(feature = "extensions")]
+use crate::changes::Baseline;
+#[cfg(feature = "extensions")]
+use crate::column_formula::ColumnFormula;
+#[cfg(feature = "extensions")]
+use crate::dry_run::DryRunJournal;
+use crate::expr_parser::Criterion;
+use crate::extended_formula::ExtendedFormula;
+#[cfg(feature = "extensions")]
+use crate::filter::RowFilter;
+use crate::graph;
+#[cfg(feature = "extensions")]
+use crate::history_file::HistoryConfig;
+#[cfg(feature = "extensions")]
+use crate::output::OutputSink;
+#[cfg(feature = "extensions")]
+use crate::overwrite::PendingOverwrite;
+#[cfg(feature = "extensions")]
+use crate::ownership::Ownership;
+use crate::range::Range;
+use crate::reevaluate_topo::TopoScratch;
+#[cfg(feature = "extensions")]
+use crate::save_load::FileStamp;
+#[cfg(feature = "extensions")]
+use crate::scripting::ScriptState;
+#[cfg(feature = "extensions")]
+use crate::search::Search;
+#[cfg(feature = "extensions")]
+use crate::undo::UndoLog;
+#[cfg(feature = "extensions")]
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 
 // Constants
-const MAX_ROWS: i16 = 999; // Maximum number of rows in the spreadsheet   
-const MAX_COLS: i16 = 18278; // Maximum number of columns in the spreadsheet
+/// Maximum number of rows in the spreadsheet.
+pub const MAX_ROWS: i16 = 999;
+/// Maximum number of columns in the spreadsheet; the last one is `ZZZ`.
+pub const MAX_COLS: i16 = 18278;
+/// Letters in the name of the last column, the longest column name a reference can have.
+pub const MAX_COLUMN_NAME_LEN: usize = column_name_len(MAX_COLS);
+/// Value of every cell past the rows `grid` holds so far.
+static EMPTY_CELL: CellValue = CellValue::Empty;
+#[cfg(feature = "extensions")]
+pub const DEFAULT_RANGE_CELL_LIMIT: i64 = 100_000; // Range size that needs `!` to confirm
+#[cfg(feature = "extensions")]
+pub const MAX_BACKUPS: usize = 99; // Most numbered backups `set backups` allows
+#[cfg(feature = "extensions")]
+pub const DEFAULT_LINE_BYTE_LIMIT: usize = 64 * 1024; // Longest save file line the loader reads
 
 /// Represents a highlighted relationship type for visualization.
 ///
@@ -26,20 +70,68 @@ pub enum HighlightType {
     None,
 }
 
+/// Running totals of populated cells, kept up to date by every edit path.
+///
+/// # Fields
+///
+/// * `populated` - Cells with a value or a formula (see `Spreadsheet::is_touched`).
+/// * `formulas` - Cells holding a formula.
+#[cfg(feature = "extensions")]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CellCounts {
+    pub populated: usize,
+    pub formulas: usize,
+}
+
+#[cfg(feature = "extensions")]
+impl CellCounts {
+    /// Formats the counts for the prompt, with the change since `previous` where nonzero.
+    ///
+    /// # Arguments
+    ///
+    /// * `previous` - The counts shown at the previous prompt, if any.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - For example `[c:252 +100 f:37]`
+    pub fn prompt_label(&self, previous: Option<&CellCounts>) -> String {
+        let delta = |now: usize, before: Option<usize>| match before {
+            Some(before) if now > before => format!(" +{}", now - before),
+            Some(before) if now < before => format!(" -{}", before - now),
+            _ => String::new(),
+        };
+        format!(
+            "[c:{}{} f:{}{}]",
+            self.populated,
+            delta(self.populated, previous.map(|p| p.populated)),
+            self.formulas,
+            delta(self.formulas, previous.map(|p| p.formulas))
+        )
+    }
+}
+
 /// Represents a range-based dependency.
 ///
+/// The range is kept as rows and columns rather than corner keys, so membership never
+/// depends on turning keys back into positions.
+///
 /// # Fields
 ///
-/// * `start_key` - Starting cell key.
-/// * `end_key` - Ending cell key.
+/// * `range` - The cells the formula reads.
 /// * `child_key` - Dependent cell key.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct RangeChild {
-    pub start_key: i32, // Range start cell key
-    pub end_key: i32,   // Range end cell key
+    pub range: Range,   // Cells read by the child
     pub child_key: i32, // Child cell key
 }
 
+impl RangeChild {
+    /// Checks whether the child reads the cell at `row`, `col`.
+    pub fn reads(&self, row: i16, col: i16) -> bool {
+        self.range.contains(row, col)
+    }
+}
+
 /// Status codes for command execution.
 ///
 /// # Variants
@@ -50,6 +142,13 @@ pub struct RangeChild {
 /// * `InvalidCell` - Invalid cell reference.
 /// * `LockedCell` - Cell is locked.
 /// * `NotLockedCell` - Cell is not locked.
+/// * `RangeTooLarge` - A formula range exceeds the configured cell limit.
+/// * `FileChanged` - The target file was modified by someone else since it was last saved or opened.
+/// * `UnsavedChanges` - The command would discard unsaved changes.
+/// * `UndefinedVariable` - A command used a `$variable` that is not defined.
+/// * `ShapeMismatch` - Ranges combined cell by cell have different shapes.
+/// * `NotOwner` - The cell is owned by another user and ownership is enforced.
+/// * `ReadOnly` - The sheet is in read-only mode.
 #[derive(Debug, PartialEq)]
 pub enum CommandStatus {
     CmdOk,
@@ -61,6 +160,88 @@ pub enum CommandStatus {
     LockedCell,
     #[cfg(feature = "extensions")]
     NotLockedCell,
+    #[cfg(feature = "extensions")]
+    RangeTooLarge,
+    #[cfg(feature = "extensions")]
+    FileChanged,
+    #[cfg(feature = "extensions")]
+    UnsavedChanges,
+    #[cfg(feature = "extensions")]
+    UndefinedVariable,
+    #[cfg(feature = "extensions")]
+    ShapeMismatch,
+    #[cfg(feature = "extensions")]
+    NotOwner,
+    #[cfg(feature = "extensions")]
+    ReadOnly,
+}
+
+impl CommandStatus {
+    /// Returns the short name of the status shown in the prompt and in command reports.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandStatus::CmdOk => "ok",
+            CommandStatus::Unrecognized => "unrecognized_cmd",
+            CommandStatus::CircularRef => "circular_ref",
+            #[cfg(feature = "extensions")]
+            CommandStatus::InvalidCell => "invalid_cell",
+            #[cfg(feature = "extensions")]
+            CommandStatus::LockedCell => "locked_cell",
+            #[cfg(feature = "extensions")]
+            CommandStatus::NotLockedCell => "not_locked_cell",
+            #[cfg(feature = "extensions")]
+            CommandStatus::RangeTooLarge => "range_too_large",
+            #[cfg(feature = "extensions")]
+            CommandStatus::FileChanged => "file_changed",
+            #[cfg(feature = "extensions")]
+            CommandStatus::UnsavedChanges => "unsaved_changes",
+            #[cfg(feature = "extensions")]
+            CommandStatus::UndefinedVariable => "undefined_variable",
+            #[cfg(feature = "extensions")]
+            CommandStatus::ShapeMismatch => "shape_mismatch",
+            #[cfg(feature = "extensions")]
+            CommandStatus::NotOwner => "not_owner",
+            #[cfg(feature = "extensions")]
+            CommandStatus::ReadOnly => "read_only",
+        }
+    }
+
+    /// Returns a one-line explanation of the status, shown above the prompt after a failure.
+    pub fn message(&self) -> &'static str {
+        match self {
+            CommandStatus::CmdOk => "Done",
+            CommandStatus::Unrecognized => "The command or formula was not understood",
+            CommandStatus::CircularRef => {
+                "That formula would create a cycle; the cell was left unchanged"
+            }
+            #[cfg(feature = "extensions")]
+            CommandStatus::InvalidCell => "A cell or range lies outside the sheet",
+            #[cfg(feature = "extensions")]
+            CommandStatus::LockedCell => "The cell is locked",
+            #[cfg(feature = "extensions")]
+            CommandStatus::NotLockedCell => "The cell is not locked",
+            #[cfg(feature = "extensions")]
+            CommandStatus::RangeTooLarge => {
+                "The range is over the range limit; end the command with ! to confirm"
+            }
+            #[cfg(feature = "extensions")]
+            CommandStatus::FileChanged => {
+                "The file was changed by another program; use save --force or saveas"
+            }
+            #[cfg(feature = "extensions")]
+            CommandStatus::UnsavedChanges => "There are unsaved changes; add ! to discard them",
+            #[cfg(feature = "extensions")]
+            CommandStatus::UndefinedVariable => "A $variable in the command is not defined",
+            #[cfg(feature = "extensions")]
+            CommandStatus::ShapeMismatch => {
+                "The ranges do not have the same number of rows and columns"
+            }
+            #[cfg(feature = "extensions")]
+            CommandStatus::NotOwner => "The cell is owned by another user; see owners",
+            #[cfg(feature = "extensions")]
+            CommandStatus::ReadOnly => "The sheet is read-only; use readonly off to change it",
+        }
+    }
 }
 
 /// Metadata for a cell’s formula and dependencies.
@@ -71,7 +252,7 @@ pub enum CommandStatus {
 /// * `parent1` - First parent key or constant.
 /// * `parent2` - Second parent key or constant.
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct CellMeta {
     pub formula: i16,
     pub parent1: i32,
@@ -88,6 +269,12 @@ impl CellMeta {
     }
 }
 
+impl Default for CellMeta {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// The core spreadsheet structure.
 ///
 /// Manages the grid, dependencies, and UI state.
@@ -102,16 +289,45 @@ impl CellMeta {
 /// * `cols` - Number of columns.
 /// * `viewport_row` - Top row of the visible area.
 /// * `viewport_col` - Left column of the visible area.
-/// * `output_enabled` - Toggles display output.
+/// * `output_enabled` - Whether the REPL prints the grid after each command. Command output
+///   (listings, `visual`) is always shown, and vim mode always draws its grid.
+/// * `topo_scratch` - Reused collections for dependent reevaluation.
+/// * `criteria` - Criteria of `SUMIF` formulas by cell key.
+/// * `extended_formulas` - Formulas with more inputs than `CellMeta` holds, such as `IF`,
+///   by cell key.
+/// * `assigned` - Keys of the cells that were given a value or formula; every other cell
+///   counts as blank.
 /// * `locked_ranges` - Locked cell ranges.
 /// * `named_ranges` - Named ranges.
-/// * `cell_history` - History of cell values.
+/// * `cell_history` - History of cell values, with the formulas they came from.
+/// * `history_limit` - Most entries `cell_history` keeps per cell; 0 turns it off.
 /// * `last_edited` - Last edited cell coordinates.
 /// * `highlight_cell` - Key of the highlighted cell.
 /// * `highlight_type` - Type of highlighting.
+/// * `relative_refs` - Cell keys whose formula parents were written as relative references.
+/// * `range_cell_limit` - Largest range a formula may cover without confirmation.
+/// * `line_byte_limit` - Longest line, in bytes, read from a save file; longer records are skipped.
+/// * `output` - Where commands write their report lines.
+/// * `current_file` - File the sheet was last saved to or opened from.
+/// * `file_stamp` - Modification time and size of `current_file` when it was last saved or opened.
+/// * `dirty` - Whether cells changed since the last save or open.
+/// * `column_formulas` - Column formula definitions by target column.
+/// * `script` - Session variables and script nesting.
+/// * `row_filter` - Filter hiding rows from the display; not saved.
+/// * `confirm_overwrite_bulk` - Whether bulk writes ask before overwriting non-empty cells.
+/// * `pending_overwrite` - A bulk write waiting for those answers.
+/// * `saved_baseline` - Populated cells at the last save or open, for `changes`.
+/// * `dry_run` - Old values of the cells written so far, while a `dry` command runs.
+/// * `backups` - Numbered backups a save keeps of the file it overwrites; 0 keeps none.
+/// * `ownership` - Ownership tags, the current user, and whether tags are enforced.
+/// * `search` - The query of the last `find` and the match it stopped at; not saved.
+/// * `undo_log` - Cells changed by recent commands, for `undo` and `redo`; not saved.
+/// * `read_only` - Whether commands that would change the sheet are refused; not saved.
+/// * `autosave` - Where the command loop saves the sheet automatically; not saved.
+/// * `autosave_every` - Commands changing the sheet between automatic saves.
 /// * `display` - Number of rows/cols to display
 pub struct Spreadsheet {
-    pub grid: Vec<CellValue>, // Vector of CellValues (contiguous in memory)
+    pub grid: Vec<CellValue>, // Rows written so far, in row-major order; later cells are 0
     pub children: HashMap<i32, HashSet<i32>>, // Map from cell key to boxed HashSet of children
     pub range_children: Vec<RangeChild>, // Vector of range-based child relationships
     pub cell_meta: HashMap<i32, CellMeta>, // Map from cell key to metadata
@@ -120,18 +336,103 @@ pub struct Spreadsheet {
     pub viewport_row: i16,
     pub viewport_col: i16,
     pub output_enabled: bool,
+    pub topo_scratch: TopoScratch,
+    pub criteria: HashMap<i32, Criterion>,
+    pub extended_formulas: HashMap<i32, ExtendedFormula>,
+    pub assigned: HashSet<i32>,
     #[cfg(feature = "extensions")]
     pub locked_ranges: Vec<Range>,
     #[cfg(feature = "extensions")]
     pub named_ranges: HashMap<String, Range>,
     #[cfg(feature = "extensions")]
-    pub cell_history: HashMap<i32, Vec<CellValue>>,
+    pub cell_history: HashMap<i32, Vec<HistoryEntry>>,
+    #[cfg(feature = "extensions")]
+    pub history_limit: usize,
     #[cfg(feature = "extensions")]
     pub last_edited: Option<(i16, i16)>,
     #[cfg(feature = "extensions")]
     pub highlight_cell: i32,
     #[cfg(feature = "extensions")]
     pub highlight_type: HighlightType,
+    #[cfg(feature = "extensions")]
+    pub relative_refs: HashMap<i32, u8>, // Bit flags, see `cell::RELATIVE_PARENT1`/`RELATIVE_PARENT2`
+    #[cfg(feature = "extensions")]
+    pub range_cell_limit: i64,
+    #[cfg(feature = "extensions")]
+    pub line_byte_limit: usize,
+    #[cfg(feature = "extensions")]
+    pub output: OutputSink,
+    #[cfg(feature = "extensions")]
+    pub current_file: Option<String>,
+    #[cfg(feature = "extensions")]
+    pub file_stamp: Option<FileStamp>,
+    #[cfg(feature = "extensions")]
+    pub dirty: bool,
+    #[cfg(feature = "extensions")]
+    pub column_formulas: BTreeMap<i16, ColumnFormula>,
+    #[cfg(feature = "extensions")]
+    pub script: ScriptState,
+    #[cfg(feature = "extensions")]
+    pub cell_counts: CellCounts,
+    #[cfg(feature = "extensions")]
+    pub prompt_stats: bool,
+    #[cfg(feature = "extensions")]
+    pub history: HistoryConfig,
+    #[cfg(feature = "extensions")]
+    pub row_filter: Option<RowFilter>,
+    #[cfg(feature = "extensions")]
+    pub confirm_overwrite_bulk: bool,
+    #[cfg(feature = "extensions")]
+    pub pending_overwrite: Option<PendingOverwrite>,
+    #[cfg(feature = "extensions")]
+    pub saved_baseline: Baseline,
+    #[cfg(feature = "extensions")]
+    pub dry_run: Option<DryRunJournal>,
+    #[cfg(feature = "extensions")]
+    pub backups: usize,
+    #[cfg(feature = "extensions")]
+    pub ownership: Ownership,
+    #[cfg(feature = "extensions")]
+    pub search: Option<Search>,
+    #[cfg(feature = "extensions")]
+    pub undo_log: UndoLog,
+    #[cfg(feature = "extensions")]
+    pub read_only: bool,
+    #[cfg(feature = "extensions")]
+    pub autosave: Option<Autosave>,
+    #[cfg(feature = "extensions")]
+    pub autosave_every: usize,
+}
+
+/// Counts the letters in the name of column `cols` (1-based), e.g. 3 for `ZZZ`.
+const fn column_name_len(mut cols: i16) -> usize {
+    let mut len = 0;
+    while cols > 0 {
+        len += 1;
+        cols = (cols - 1) / 26;
+    }
+    len
+}
+
+/// Checks sheet dimensions against `MAX_ROWS` and `MAX_COLS`.
+///
+/// # Arguments
+///
+/// * `rows` - Number of rows.
+/// * `cols` - Number of columns.
+///
+/// # Returns
+///
+/// * `None` - If both are within the limits.
+/// * `Some(String)` - Which limit was broken, e.g. "rows must be between 1 and 999"
+pub fn dimension_error(rows: i16, cols: i16) -> Option<String> {
+    if !(1..=MAX_ROWS).contains(&rows) {
+        Some(format!("rows must be between 1 and {}", MAX_ROWS))
+    } else if !(1..=MAX_COLS).contains(&cols) {
+        Some(format!("columns must be between 1 and {}", MAX_COLS))
+    } else {
+        None
+    }
 }
 
 impl Spreadsheet {
@@ -139,22 +440,22 @@ impl Spreadsheet {
     ///
     /// # Arguments
     ///
-    /// * `rows` - Number of rows (1 to 999).
-    /// * `cols` - Number of columns (1 to 18278).
+    /// * `rows` - Number of rows (1 to `MAX_ROWS`).
+    /// * `cols` - Number of columns (1 to `MAX_COLS`).
     ///
     /// # Returns
     ///
     /// * `Some(Spreadsheet)` - If dimensions are valid.
-    /// * `None` - If dimensions are invalid.
+    /// * `None` - If dimensions are invalid, after printing which limit was broken.
     pub fn create(rows: i16, cols: i16) -> Option<Spreadsheet> {
-        if !(1..=MAX_ROWS).contains(&rows) || !(1..=MAX_COLS).contains(&cols) {
-            eprintln!("Invalid spreadsheet dimensions");
+        if let Some(message) = dimension_error(rows, cols) {
+            eprintln!("Invalid spreadsheet dimensions: {}", message);
             return None;
         }
 
-        // Create empty cells - initialize with Integer(0)
-        let total = rows as usize * cols as usize;
-        let grid = vec![CellValue::Integer(0); total];
+        // Rows are allocated when a cell in them is first written, so creating even the
+        // largest sheet costs nothing up front
+        let grid = Vec::new();
 
         Some(Spreadsheet {
             grid,
@@ -166,6 +467,10 @@ impl Spreadsheet {
             viewport_row: 0,
             viewport_col: 0,
             output_enabled: true,
+            topo_scratch: TopoScratch::default(),
+            criteria: HashMap::new(),
+            extended_formulas: HashMap::new(),
+            assigned: HashSet::new(),
             #[cfg(feature = "extensions")]
             locked_ranges: Vec::new(),
             #[cfg(feature = "extensions")]
@@ -173,11 +478,61 @@ impl Spreadsheet {
             #[cfg(feature = "extensions")]
             cell_history: HashMap::new(),
             #[cfg(feature = "extensions")]
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            #[cfg(feature = "extensions")]
             last_edited: None,
             #[cfg(feature = "extensions")]
             highlight_cell: -1,
             #[cfg(feature = "extensions")]
             highlight_type: HighlightType::None,
+            #[cfg(feature = "extensions")]
+            relative_refs: HashMap::new(),
+            #[cfg(feature = "extensions")]
+            range_cell_limit: DEFAULT_RANGE_CELL_LIMIT,
+            #[cfg(feature = "extensions")]
+            line_byte_limit: DEFAULT_LINE_BYTE_LIMIT,
+            #[cfg(feature = "extensions")]
+            output: OutputSink::stdout(),
+            #[cfg(feature = "extensions")]
+            current_file: None,
+            #[cfg(feature = "extensions")]
+            file_stamp: None,
+            #[cfg(feature = "extensions")]
+            dirty: false,
+            #[cfg(feature = "extensions")]
+            column_formulas: BTreeMap::new(),
+            #[cfg(feature = "extensions")]
+            script: ScriptState::default(),
+            #[cfg(feature = "extensions")]
+            cell_counts: CellCounts::default(),
+            #[cfg(feature = "extensions")]
+            prompt_stats: false,
+            #[cfg(feature = "extensions")]
+            history: HistoryConfig::default(),
+            #[cfg(feature = "extensions")]
+            row_filter: None,
+            #[cfg(feature = "extensions")]
+            confirm_overwrite_bulk: false,
+            #[cfg(feature = "extensions")]
+            pending_overwrite: None,
+            #[cfg(feature = "extensions")]
+            saved_baseline: Baseline::new(),
+            #[cfg(feature = "extensions")]
+            dry_run: None,
+            #[cfg(feature = "extensions")]
+            backups: 0,
+            #[cfg(feature = "extensions")]
+            ownership: Ownership::default(),
+            #[cfg(feature = "extensions")]
+            search: None,
+            #[cfg(feature = "extensions")]
+            undo_log: UndoLog::default(),
+            #[cfg(feature = "extensions")]
+            read_only: false,
+            #[cfg(feature = "extensions")]
+            autosave: None,
+            #[cfg(feature = "extensions")]
+            autosave_every: DEFAULT_AUTOSAVE_EVERY,
         })
     }
 
@@ -236,7 +591,9 @@ impl Spreadsheet {
     /// * `&mut CellMeta` - Mutable reference to cell metadata.
     pub fn get_cell_meta(&mut self, row: i16, col: i16) -> &mut CellMeta {
         let key = self.get_key(row, col);
-        self.cell_meta.entry(key).or_insert_with(CellMeta::new)
+        #[cfg(feature = "extensions")]
+        self.note_undo(key);
+        self.cell_meta.entry(key).or_default()
     }
 
     pub fn get_column_name(&self, mut col: i16) -> String {
@@ -273,34 +630,198 @@ impl Spreadsheet {
         }
     }
 
+    /// Converts a column name such as `AB` or `ab` to its 0-based index.
+    ///
+    /// # Returns
+    ///
+    /// * `i16` - The index, or -1 if `name` is not letters or is longer than any column
+    ///   name
     pub fn column_name_to_index(&self, name: &str) -> i16 {
         let bytes = name.as_bytes();
+        if bytes.len() > MAX_COLUMN_NAME_LEN || !bytes.iter().all(u8::is_ascii_alphabetic) {
+            return -1;
+        }
         let mut index: i16 = 0;
         for &b in bytes {
-            index = index * 26 + ((b - b'A') as i16 + 1);
+            index = index * 26 + ((b.to_ascii_uppercase() - b'A') as i16 + 1);
         }
         index - 1 // Convert from 1-based to 0-based
     }
 
     pub fn get_cell(&self, row: i16, col: i16) -> &CellValue {
         let index = self.get_index(row, col);
-        &self.grid[index]
+        self.grid.get(index).unwrap_or(&EMPTY_CELL)
     }
 
     pub fn get_key_cell(&self, cell_key: i32) -> &CellValue {
-        &self.grid[cell_key as usize]
+        self.grid.get(cell_key as usize).unwrap_or(&EMPTY_CELL)
+    }
+
+    /// Iterates over the values of every cell of the sheet in row-major order, including
+    /// the rows `grid` does not hold yet.
+    #[cfg(feature = "extensions")]
+    pub fn cell_values(&self) -> impl Iterator<Item = &CellValue> + '_ {
+        let total = self.rows as usize * self.cols as usize;
+        let unwritten = total - self.grid.len();
+        self.grid
+            .iter()
+            .chain(std::iter::repeat_n(&EMPTY_CELL, unwritten))
+    }
+
+    /// Extends `grid` with empty cells up to the end of the row holding `index`.
+    fn materialize(&mut self, index: usize) {
+        if index >= self.grid.len() {
+            let row_end = (index / self.cols as usize + 1) * self.cols as usize;
+            self.grid.resize(row_end, CellValue::Empty);
+        }
+    }
+
+    /// Checks whether a cell has been given content: a value, even 0, or a formula.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The cell key.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - False for cells still empty with no formula
+    #[cfg(feature = "extensions")]
+    pub fn is_touched(&self, key: i32) -> bool {
+        *self.get_key_cell(key) != CellValue::Empty
+            || self
+                .cell_meta
+                .get(&key)
+                .is_some_and(|meta| meta.formula != -1)
+    }
+
+    /// Returns what one cell contributes to `cell_counts`.
+    ///
+    /// Call it before changing a cell and pass the result to `record_cell_change` afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The cell key.
+    ///
+    /// # Returns
+    ///
+    /// * `CellCounts` - Each count is 0 or 1
+    #[cfg(feature = "extensions")]
+    pub fn cell_contribution(&self, key: i32) -> CellCounts {
+        CellCounts {
+            populated: self.is_touched(key) as usize,
+            formulas: self
+                .cell_meta
+                .get(&key)
+                .is_some_and(|meta| meta.formula != -1) as usize,
+        }
+    }
+
+    /// Adjusts `cell_counts` for a cell that just changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The cell key.
+    /// * `before` - The cell's `cell_contribution` before the change.
+    #[cfg(feature = "extensions")]
+    pub fn record_cell_change(&mut self, key: i32, before: CellCounts) {
+        let after = self.cell_contribution(key);
+        let counts = &mut self.cell_counts;
+        counts.populated = (counts.populated + after.populated).saturating_sub(before.populated);
+        counts.formulas = (counts.formulas + after.formulas).saturating_sub(before.formulas);
+    }
+
+    /// Counts populated and formula cells by scanning the whole sheet.
+    ///
+    /// # Returns
+    ///
+    /// * `CellCounts` - What `cell_counts` should hold
+    #[cfg(feature = "extensions")]
+    pub fn scan_cell_counts(&self) -> CellCounts {
+        let mut counts = CellCounts {
+            populated: self
+                .grid
+                .iter()
+                .filter(|value| **value != CellValue::Empty)
+                .count(),
+            formulas: 0,
+        };
+        for (&key, meta) in &self.cell_meta {
+            if meta.formula != -1 {
+                counts.formulas += 1;
+                if *self.get_key_cell(key) == CellValue::Empty {
+                    counts.populated += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// Builds the cell statistics part of the prompt when `prompt_stats` is on.
+    ///
+    /// # Arguments
+    ///
+    /// * `previous` - The counts shown at the previous prompt; updated to the current counts.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(String)` - The label, e.g. `[c:152 f:37]`.
+    /// * `None` - If `prompt_stats` is off
+    #[cfg(feature = "extensions")]
+    pub fn prompt_stats_label(&self, previous: &mut Option<CellCounts>) -> Option<String> {
+        if !self.prompt_stats {
+            *previous = None;
+            return None;
+        }
+        let label = self.cell_counts.prompt_label(previous.as_ref());
+        *previous = Some(self.cell_counts);
+        Some(label)
     }
 
     pub fn get_mut_cell(&mut self, row: i16, col: i16) -> &mut CellValue {
         let index = self.get_index(row, col);
+        #[cfg(feature = "extensions")]
+        self.journal_write(index);
+        self.materialize(index);
         &mut self.grid[index]
     }
 
+    /// Returns a mutable reference to the cell with the given key.
+    #[cfg(feature = "extensions")]
+    pub fn get_mut_key_cell(&mut self, key: i32) -> &mut CellValue {
+        self.journal_write(key as usize);
+        self.materialize(key as usize);
+        &mut self.grid[key as usize]
+    }
+
+    /// Detaches a cell from its parents and drops its formula, leaving its value alone.
+    ///
+    /// The parent edges are found through the cell's metadata, so they are removed first
+    /// and the metadata only after; every path that turns a formula cell into a plain
+    /// value goes through here to keep that order.
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - Row of the cell.
+    /// * `col` - Column of the cell.
+    pub fn clear_formula(&mut self, row: i16, col: i16) {
+        let key = self.get_key(row, col);
+        #[cfg(feature = "extensions")]
+        self.note_undo(key);
+        graph::remove_all_parents(self, row, col);
+        self.cell_meta.remove(&key);
+    }
+
     // Add a range-based child relationship
     pub fn add_range_child(&mut self, start_key: i32, end_key: i32, child_key: i32) {
+        let (start_row, start_col) = self.get_row_col(start_key);
+        let (end_row, end_col) = self.get_row_col(end_key);
         self.range_children.push(RangeChild {
-            start_key,
-            end_key,
+            range: Range {
+                start_row,
+                start_col,
+                end_row,
+                end_col,
+            },
             child_key,
         });
     }
@@ -311,12 +832,19 @@ impl Spreadsheet {
     }
 
     // Check if a cell is within a range
+    #[cfg(feature = "extensions")]
     pub fn is_cell_in_range(&self, cell_key: i32, start_key: i32, end_key: i32) -> bool {
         let (cell_row, cell_col) = self.get_row_col(cell_key);
         let (start_row, start_col) = self.get_row_col(start_key);
         let (end_row, end_col) = self.get_row_col(end_key);
+        let range = Range {
+            start_row,
+            start_col,
+            end_row,
+            end_col,
+        };
 
-        cell_row >= start_row && cell_row <= end_row && cell_col >= start_col && cell_col <= end_col
+        range.contains(cell_row, cell_col)
     }
 
     // Add a child to a cell's dependents (modified for HashMap of boxed HashSets)
@@ -344,40 +872,28 @@ impl Spreadsheet {
         self.children.get(&key)
     }
 
+    /// Prints the grid for the REPL, unless `disable_output` turned it off.
     pub fn print_spreadsheet(&self) {
-        if !self.output_enabled {
-            return;
-        }
-        #[cfg(feature = "extensions")]
-        if self.highlight_type != HighlightType::None {
-            self.print_spreadsheet_with_highlights();
-            return;
+        if let Some(grid) = self.repl_grid() {
+            print!("{}", grid);
         }
+    }
 
-        let start_row = self.viewport_row;
-        let start_col = self.viewport_col;
-        let display_row = min(self.rows - start_row, 10); // Display only a portion of the spreadsheet
-        let display_col = min(self.cols - start_col, 10);
+    /// Returns the grid the REPL prints after a command, or `None` when output is disabled.
+    pub fn repl_grid(&self) -> Option<String> {
+        self.output_enabled.then(|| self.render_grid())
+    }
 
-        // Print column headers
-        print!("     ");
-        for i in 0..display_col {
-            print!("{:<8} ", self.get_column_name(start_col + i));
-        }
-        println!();
-
-        // Print rows with data
-        for i in 0..display_row {
-            print!("{:<4} ", start_row + i + 1); // Show 1-based row numbers
-            for j in 0..display_col {
-                let cell_value = self.get_cell(start_row + i, start_col + j);
-                match cell_value {
-                    CellValue::Integer(value) => print!("{:<8} ", value),
-                    CellValue::Error => print!("{:<8} ", "ERR"),
-                }
-            }
-            println!();
+    /// Renders the viewport, followed by the filter banner when a filter is active.
+    ///
+    /// Unlike `print_spreadsheet`, this ignores `output_enabled`.
+    pub fn render_grid(&self) -> String {
+        let grid = self.snapshot_viewport().render();
+        #[cfg(feature = "extensions")]
+        if let Some(banner) = self.filter_banner() {
+            return format!("{}[{}]\n", grid, banner);
         }
+        grid
     }
 
     /// Scrolls to a specific cell.
@@ -389,7 +905,7 @@ impl Spreadsheet {
     /// # Returns
     ///
     /// * `CommandStatus::CmdOk` - On success.
-    /// * `CommandStatus::InvalidCell` - If out of bounds.
+    /// * `CommandStatus::InvalidCell` - If out of bounds; `Unrecognized` without extensions.
     /// * `CommandStatus::Unrecognized` - If parsing fails.
     pub fn scroll_to_cell(&mut self, cell: &str) -> CommandStatus {
         match parse_cell_reference(self, cell) {
@@ -398,7 +914,7 @@ impl Spreadsheet {
                 self.viewport_col = col;
                 CommandStatus::CmdOk
             }
-            Err(_) => CommandStatus::Unrecognized,
+            Err(status) => status,
         }
     }
 
@@ -442,14 +958,15 @@ impl Spreadsheet {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::cell::CellValue;
+    use crate::cell::{CellValue, ErrorKind};
 
     #[test]
     fn test_create_valid_dimensions() {
         let sheet = Spreadsheet::create(5, 5).unwrap();
         assert_eq!(sheet.rows, 5);
         assert_eq!(sheet.cols, 5);
-        assert_eq!(sheet.grid.len(), 25);
+        assert!(sheet.grid.is_empty());
+        assert_eq!(*sheet.get_cell(4, 4), CellValue::Empty);
         assert_eq!(sheet.viewport_row, 0);
         assert_eq!(sheet.viewport_col, 0);
     }
@@ -486,7 +1003,7 @@ mod tests {
         let cell_value = sheet.get_mut_cell(0, 0);
         *cell_value = CellValue::Integer(42);
         assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(42));
-        assert_eq!(*sheet.get_cell(1, 1), CellValue::Integer(0));
+        assert_eq!(*sheet.get_cell(1, 1), CellValue::Empty);
     }
 
     #[test]
@@ -521,6 +1038,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "extensions")]
     fn test_is_cell_in_range() {
         let sheet = Spreadsheet::create(5, 5).unwrap();
         let cell_key = sheet.get_key(1, 1);
@@ -542,8 +1060,15 @@ mod tests {
     #[test]
     fn test_scroll_to_cell_invalid() {
         let mut sheet = Spreadsheet::create(5, 5).unwrap();
-        assert_eq!(sheet.scroll_to_cell("F6"), CommandStatus::Unrecognized);
+        #[cfg(feature = "extensions")]
+        let off_sheet = CommandStatus::InvalidCell;
+        #[cfg(not(feature = "extensions"))]
+        let off_sheet = CommandStatus::Unrecognized;
+        assert_eq!(sheet.scroll_to_cell("F6"), off_sheet);
+        assert_eq!(sheet.scroll_to_cell("A6"), off_sheet);
+        assert_eq!(sheet.scroll_to_cell("A999"), off_sheet);
         assert_eq!(sheet.scroll_to_cell("1A"), CommandStatus::Unrecognized);
+        assert_eq!((sheet.viewport_row, sheet.viewport_col), (0, 0));
     }
 
     #[test]
@@ -569,11 +1094,22 @@ mod tests {
         sheet.print_spreadsheet(); // Should not panic
     }
 
+    #[test]
+    fn test_repl_grid_follows_output_flag() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(42);
+        assert!(sheet.repl_grid().unwrap().contains("42"));
+        sheet.output_enabled = false;
+        assert_eq!(sheet.repl_grid(), None);
+        // The grid itself can still be rendered
+        assert!(sheet.render_grid().contains("42"));
+    }
+
     #[test]
     fn test_print_spreadsheet_with_values() {
         let mut sheet = Spreadsheet::create(5, 5).unwrap();
         *sheet.get_mut_cell(0, 0) = CellValue::Integer(42);
-        *sheet.get_mut_cell(1, 1) = CellValue::Error;
+        *sheet.get_mut_cell(1, 1) = CellValue::Error(ErrorKind::DivZero);
         sheet.output_enabled = true;
         sheet.print_spreadsheet(); // Should not panic
     }
@@ -581,7 +1117,42 @@ mod tests {
     #[test]
     fn test_create_edge_cases() {
         let sheet = Spreadsheet::create(1, 1).unwrap();
-        assert_eq!(sheet.grid.len(), 1);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Empty);
+    }
+
+    #[test]
+    fn test_create_largest_sheet_is_fast() {
+        let start = std::time::Instant::now();
+        let sheet = Spreadsheet::create(999, 18278).unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_millis(50));
+        assert!(sheet.grid.is_empty());
+    }
+
+    #[test]
+    fn test_far_cells_are_empty_until_written() {
+        let mut sheet = Spreadsheet::create(999, 18278).unwrap();
+        assert_eq!(*sheet.get_cell(998, 18277), CellValue::Empty);
+        assert!(sheet.grid.is_empty());
+
+        *sheet.get_mut_cell(2, 5) = CellValue::Integer(7);
+        // Rows up to and including the written one are held, nothing past it
+        assert_eq!(sheet.grid.len(), 3 * 18278);
+        assert_eq!(*sheet.get_cell(2, 5), CellValue::Integer(7));
+        assert_eq!(*sheet.get_cell(2, 6), CellValue::Empty);
+        assert_eq!(*sheet.get_cell(3, 0), CellValue::Empty);
+        assert_eq!(
+            sheet
+                .grid
+                .iter()
+                .filter(|value| **value != CellValue::Empty)
+                .count(),
+            1
+        );
+
+        // Writing an earlier row keeps what was written later
+        *sheet.get_mut_cell(0, 0) = CellValue::Error(ErrorKind::DivZero);
+        assert_eq!(sheet.grid.len(), 3 * 18278);
+        assert_eq!(*sheet.get_cell(2, 5), CellValue::Integer(7));
     }
 
     #[test]
@@ -642,9 +1213,115 @@ mod tests {
         assert_eq!(sheet.cols, MAX_COLS);
     }
 
+    #[test]
+    fn test_dimension_limits() {
+        // Pinned so that changing a limit is a deliberate edit here too
+        assert_eq!((MAX_ROWS, MAX_COLS, MAX_COLUMN_NAME_LEN), (999, 18278, 3));
+        assert!(Spreadsheet::create(999, 18278).is_some());
+        assert_eq!(dimension_error(999, 18278), None);
+        assert_eq!(
+            dimension_error(1000, 5),
+            Some("rows must be between 1 and 999".to_string())
+        );
+        assert_eq!(
+            dimension_error(0, 5),
+            Some("rows must be between 1 and 999".to_string())
+        );
+        assert_eq!(
+            dimension_error(5, 18279),
+            Some("columns must be between 1 and 18278".to_string())
+        );
+        // The name of the last column is exactly as long as the limit allows
+        let sheet = Spreadsheet::create(1, 1).unwrap();
+        assert_eq!(
+            sheet.get_column_name(MAX_COLS - 1).len(),
+            MAX_COLUMN_NAME_LEN
+        );
+        assert_eq!(sheet.column_name_to_index("ZZZ"), MAX_COLS - 1);
+        assert_eq!(sheet.column_name_to_index("AAAA"), -1);
+        assert_eq!(sheet.column_name_to_index("ZZZZZZZ"), -1);
+        assert_eq!(sheet.column_name_to_index("a"), 0);
+        assert_eq!(sheet.column_name_to_index("zZz"), MAX_COLS - 1);
+        assert_eq!(sheet.column_name_to_index("A1"), -1);
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_cell_counts_follow_edits() {
+        use crate::evaluator::handle_command;
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut sleep_time = 0.0;
+        let counts = |populated, formulas| CellCounts {
+            populated,
+            formulas,
+        };
+        let mut run = |sheet: &mut Spreadsheet, command: &str| {
+            handle_command(sheet, command, &mut sleep_time);
+            sheet.cell_counts
+        };
+
+        assert_eq!(run(&mut sheet, "A1=5"), counts(1, 0));
+        assert_eq!(run(&mut sheet, "B1=A1-5"), counts(2, 1)); // Formula reading 0 still counts
+        // An assigned 0 is still a value
+        assert_eq!(run(&mut sheet, "A1=0"), counts(2, 1));
+        assert_eq!(run(&mut sheet, "C1=SUM(A1:B1)"), counts(3, 2));
+        assert_eq!(run(&mut sheet, "B1=7"), counts(3, 1));
+        // A rejected cycle leaves the counts alone
+        assert_eq!(run(&mut sheet, "A1=C1"), counts(3, 1));
+        // Reverting through history restores the formula B1 had before `B1=7`
+        assert_eq!(run(&mut sheet, "history B1"), counts(3, 2));
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Integer(-5));
+        // replace leaves the formula alone
+        assert_eq!(run(&mut sheet, "replace -5 0"), counts(3, 2));
+        assert_eq!(sheet.cell_counts, sheet.scan_cell_counts());
+    }
+
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn test_prompt_stats_label_deltas() {
+        let counts = CellCounts {
+            populated: 252,
+            formulas: 37,
+        };
+        assert_eq!(counts.prompt_label(None), "[c:252 f:37]");
+        let before = CellCounts {
+            populated: 152,
+            formulas: 37,
+        };
+        assert_eq!(counts.prompt_label(Some(&before)), "[c:252 +100 f:37]");
+        let before = CellCounts {
+            populated: 253,
+            formulas: 40,
+        };
+        assert_eq!(counts.prompt_label(Some(&before)), "[c:252 -1 f:37 -3]");
+
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut previous = Some(before);
+        assert_eq!(sheet.prompt_stats_label(&mut previous), None);
+        assert_eq!(previous, None);
+        sheet.prompt_stats = true;
+        sheet.cell_counts = counts;
+        assert_eq!(
+            sheet.prompt_stats_label(&mut previous),
+            Some("[c:252 f:37]".to_string())
+        );
+        sheet.cell_counts.formulas += 2;
+        assert_eq!(
+            sheet.prompt_stats_label(&mut previous),
+            Some("[c:252 f:39 +2]".to_string())
+        );
+        assert_eq!(
+            sheet.prompt_stats_label(&mut previous),
+            Some("[c:252 f:39]".to_string())
+        );
+    }
+
     #[test]
     fn test_scroll_to_cell_out_of_bounds() {
         let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        #[cfg(feature = "extensions")]
+        assert_eq!(sheet.scroll_to_cell("A1000"), CommandStatus::InvalidCell);
+        #[cfg(not(feature = "extensions"))]
         assert_eq!(sheet.scroll_to_cell("A1000"), CommandStatus::Unrecognized);
     }
 }
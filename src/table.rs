@@ -0,0 +1,255 @@
+//! Aligned text tables for listing commands.
+//!
+//! A listing fills a `Table` with headers and rows of strings and sends the rendered lines
+//! to the output sink, so every listing pads, shortens and separates its columns the same
+//! way. Widths count characters as they appear on screen, as in `text_width`.
+
+use crate::text_width::{display_width, truncate_display};
+
+/// Widest a column grows by default before its cells are shortened with `…`.
+pub const MAX_COLUMN_WIDTH: usize = 40;
+/// Narrowest a column is shrunk to when the table has to fit a total width.
+const MIN_COLUMN_WIDTH: usize = 3;
+/// Space between two columns.
+const COLUMN_GAP: &str = "  ";
+
+/// How the cells of a column are padded.
+///
+/// # Variants
+///
+/// * `Left` - Text starts at the column's left edge.
+/// * `Right` - Text ends at the column's right edge, for numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// A table of text cells.
+///
+/// # Fields
+///
+/// * `headers` - Column headers; when all are empty the table has no header line.
+/// * `aligns` - Alignment of each column.
+/// * `rows` - The cells, one `Vec` per row; missing cells are blank.
+/// * `separator` - Whether a line of dashes goes under the headers.
+/// * `max_width` - Total width the widest columns are shrunk to fit, if any.
+/// * `max_column_width` - Width a column grows to before its cells are shortened, if
+///   capped; `MAX_COLUMN_WIDTH` unless changed.
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub aligns: Vec<Align>,
+    pub rows: Vec<Vec<String>>,
+    pub separator: bool,
+    pub max_width: Option<usize>,
+    pub max_column_width: Option<usize>,
+}
+
+impl Table {
+    /// Creates an empty table with a separator under its headers.
+    ///
+    /// # Arguments
+    ///
+    /// * `columns` - Header and alignment of each column; use `""` headers for a list
+    ///   without a header line.
+    pub fn new(columns: &[(&str, Align)]) -> Self {
+        Table {
+            headers: columns
+                .iter()
+                .map(|(header, _)| header.to_string())
+                .collect(),
+            aligns: columns.iter().map(|&(_, align)| align).collect(),
+            rows: Vec::new(),
+            separator: true,
+            max_width: None,
+            max_column_width: Some(MAX_COLUMN_WIDTH),
+        }
+    }
+
+    /// Adds a row of cells.
+    pub fn push_row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+
+    /// Computes the width of each column: its widest cell or header, capped at
+    /// `max_column_width`, then shrunk widest-first until the table fits `max_width`.
+    fn widths(&self) -> Vec<usize> {
+        let has_header = self.has_header();
+        let mut widths: Vec<usize> = (0..self.headers.len())
+            .map(|col| {
+                let header = if has_header {
+                    display_width(&self.headers[col])
+                } else {
+                    0
+                };
+                self.rows
+                    .iter()
+                    .filter_map(|row| row.get(col))
+                    .map(|cell| display_width(cell))
+                    .fold(header, usize::max)
+                    .min(self.max_column_width.unwrap_or(usize::MAX))
+            })
+            .collect();
+        if let Some(max_width) = self.max_width {
+            let gaps = COLUMN_GAP.len() * widths.len().saturating_sub(1);
+            let mut total = widths.iter().sum::<usize>() + gaps;
+            while total > max_width {
+                // Ties go to the rightmost column, so leading key columns stay readable
+                let widest = widths
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &width)| width > MIN_COLUMN_WIDTH)
+                    .max_by_key(|&(col, &width)| (width, col));
+                match widest {
+                    Some((col, _)) => widths[col] -= 1,
+                    None => break,
+                }
+                total -= 1;
+            }
+        }
+        widths
+    }
+
+    fn has_header(&self) -> bool {
+        self.headers.iter().any(|header| !header.is_empty())
+    }
+
+    /// Pads or shortens each cell to its column's width and joins them.
+    fn format_row(&self, cells: &[String], widths: &[usize]) -> String {
+        let formatted: Vec<String> = widths
+            .iter()
+            .enumerate()
+            .map(|(col, &width)| {
+                let text = truncate_display(cells.get(col).map_or("", String::as_str), width);
+                let padding = " ".repeat(width - display_width(&text));
+                match self.aligns.get(col) {
+                    Some(Align::Right) => padding + &text,
+                    _ => text + &padding,
+                }
+            })
+            .collect();
+        formatted.join(COLUMN_GAP).trim_end().to_string()
+    }
+
+    /// Renders the table.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<String>` - The header line and separator (when the table has headers),
+    ///   then one line per row; trailing spaces are trimmed
+    pub fn render(&self) -> Vec<String> {
+        let widths = self.widths();
+        let mut lines = Vec::with_capacity(self.rows.len() + 2);
+        if self.has_header() {
+            lines.push(self.format_row(&self.headers, &widths));
+            if self.separator {
+                let dashes: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+                lines.push(dashes.join(COLUMN_GAP));
+            }
+        }
+        for row in &self.rows {
+            lines.push(self.format_row(row, &widths));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(row: &[&str]) -> Vec<String> {
+        row.iter().map(|cell| cell.to_string()).collect()
+    }
+
+    #[test]
+    fn test_widths_and_alignment() {
+        let mut table = Table::new(&[("name", Align::Left), ("value", Align::Right)]);
+        table.push_row(cells(&["total", "12"]));
+        table.push_row(cells(&["x", "1234567"]));
+        assert_eq!(
+            table.render(),
+            vec![
+                "name     value",
+                "-----  -------",
+                "total       12",
+                "x      1234567",
+            ]
+        );
+
+        table.aligns = vec![Align::Right, Align::Left];
+        assert_eq!(
+            table.render(),
+            vec![
+                " name  value",
+                "-----  -------",
+                "total  12",
+                "    x  1234567"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_tables() {
+        let table = Table::new(&[("cell", Align::Left), ("count", Align::Right)]);
+        assert_eq!(table.render(), vec!["cell  count", "----  -----"]);
+        let mut table = Table::new(&[("", Align::Left), ("", Align::Left)]);
+        assert!(table.render().is_empty());
+        table.separator = false;
+        assert!(table.render().is_empty());
+        assert!(Table::new(&[]).render().is_empty());
+    }
+
+    #[test]
+    fn test_single_row_and_missing_cells() {
+        let mut table = Table::new(&[("a", Align::Left), ("b", Align::Left)]);
+        table.separator = false;
+        table.push_row(cells(&["only"]));
+        assert_eq!(table.render(), vec!["a     b", "only"]);
+
+        // Without headers the table is an aligned list
+        let mut list = Table::new(&[("", Align::Left), ("", Align::Left)]);
+        list.push_row(cells(&["help", "List commands"]));
+        list.push_row(cells(&["lock_cell", "Lock a cell"]));
+        assert_eq!(
+            list.render(),
+            vec!["help       List commands", "lock_cell  Lock a cell"]
+        );
+    }
+
+    #[test]
+    fn test_long_cells_are_truncated_by_characters() {
+        let mut table = Table::new(&[("text", Align::Left), ("n", Align::Right)]);
+        table.push_row(vec!["é".repeat(60), "1".to_string()]);
+        let lines = table.render();
+        assert_eq!(display_width(&lines[2]), MAX_COLUMN_WIDTH + 3);
+        assert!(lines[2].starts_with(&"é".repeat(MAX_COLUMN_WIDTH - 1)));
+        assert!(lines[2].ends_with("…  1"));
+        assert_eq!(lines[1], format!("{}  -", "-".repeat(MAX_COLUMN_WIDTH)));
+
+        table.max_column_width = None;
+        assert_eq!(table.render()[2], format!("{}  1", "é".repeat(60)));
+    }
+
+    #[test]
+    fn test_total_width_cap() {
+        let mut table = Table::new(&[
+            ("key", Align::Left),
+            ("description", Align::Left),
+            ("n", Align::Right),
+        ]);
+        table.push_row(cells(&["A1", "a fairly long description", "42"]));
+        table.max_width = Some(24);
+        let lines = table.render();
+        assert!(lines.iter().all(|line| display_width(line) <= 24));
+        // The widest column gave up the space
+        assert_eq!(lines[2], "A1   a fairly long …  42");
+
+        // Columns stop shrinking at the minimum width, even if the total stays too wide
+        table.max_width = Some(1);
+        let lines = table.render();
+        assert_eq!(lines[0], "key  de…   n");
+        assert_eq!(lines[2], "A1   a …  42");
+    }
+}
@@ -0,0 +1,452 @@
+//! Helpers for tests that build a sheet, run commands and check the result.
+//!
+//! `ScriptedSheet` wraps a spreadsheet with its output captured and chains commands
+//! and assertions:
+//!
+//! ```text
+//! ScriptedSheet::new(10, 10)
+//!     .run(&["A1=5", "B1=A1*2", "C1=A1/0"])
+//!     .assert_status_ok()
+//!     .assert_cell("B1", 10)
+//!     .assert_error_cell("C1", ErrorKind::DivZero);
+//! ```
+//!
+//! Golden tests run a command file and compare the sheet, written as save file records,
+//! with a stored expectation; a mismatch panics with a line diff. Assertion failures
+//! report the caller's line, not this module's.
+//!
+//! The core build has no output sink, formula display or save files, so only the extended
+//! build has `assert_output`, `assert_formula`, `run_file` and the golden assertions.
+
+#[cfg(not(feature = "extensions"))]
+use crate::cell::parse_cell_reference;
+use crate::cell::{CellValue, ErrorKind};
+use crate::evaluator::handle_command;
+#[cfg(feature = "extensions")]
+use crate::evaluator::resolve_cell_reference;
+#[cfg(feature = "extensions")]
+use crate::extensions::get_formula_string;
+#[cfg(feature = "extensions")]
+use crate::output::OutputSink;
+#[cfg(feature = "extensions")]
+use crate::save_load::write_sheet_records;
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+#[cfg(feature = "extensions")]
+use std::fs;
+#[cfg(feature = "extensions")]
+use std::path::Path;
+
+/// Unchanged lines shown around each difference in a golden diff.
+const DIFF_CONTEXT: usize = 2;
+
+/// A spreadsheet driven by commands, with fluent assertions.
+///
+/// # Fields
+///
+/// * `sheet` - The spreadsheet; its output is captured.
+/// * `unchecked` - Commands run since the last status assertion, with their statuses.
+/// * `sleep_time` - Sleep time accumulated by `SLEEP` formulas.
+pub struct ScriptedSheet {
+    pub sheet: Spreadsheet,
+    unchecked: Vec<(String, CommandStatus)>,
+    sleep_time: f64,
+}
+
+impl ScriptedSheet {
+    /// Creates an empty sheet whose output is captured.
+    ///
+    /// # Arguments
+    ///
+    /// * `rows` - Number of rows.
+    /// * `cols` - Number of columns.
+    #[track_caller]
+    pub fn new(rows: i16, cols: i16) -> Self {
+        let sheet = Spreadsheet::create(rows, cols)
+            .unwrap_or_else(|| panic!("cannot create a {}x{} sheet", rows, cols));
+        #[cfg(feature = "extensions")]
+        let sheet = Spreadsheet {
+            output: OutputSink::capture(),
+            ..sheet
+        };
+        ScriptedSheet {
+            sheet,
+            unchecked: Vec::new(),
+            sleep_time: 0.0,
+        }
+    }
+
+    /// Runs commands in order, as if typed at the prompt.
+    pub fn run(mut self, commands: &[&str]) -> Self {
+        for command in commands {
+            let status = handle_command(&mut self.sheet, command, &mut self.sleep_time);
+            self.unchecked.push((command.to_string(), status));
+        }
+        self
+    }
+
+    /// Runs the commands in a file, one per line; blank lines and `#` comments are skipped.
+    #[cfg(feature = "extensions")]
+    #[track_caller]
+    pub fn run_file(self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let script = fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+        let commands: Vec<&str> = script
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        self.run(&commands)
+    }
+
+    /// Asserts that every command run since the last status assertion succeeded.
+    #[track_caller]
+    pub fn assert_status_ok(mut self) -> Self {
+        for (command, status) in self.unchecked.drain(..) {
+            assert!(
+                status == CommandStatus::CmdOk,
+                "'{}': expected status ok, got {}",
+                command,
+                status.as_str()
+            );
+        }
+        self
+    }
+
+    /// Asserts the status of the last command run.
+    #[track_caller]
+    pub fn assert_status(mut self, expected: CommandStatus) -> Self {
+        let (command, status) = self
+            .unchecked
+            .pop()
+            .unwrap_or_else(|| panic!("no command ran since the last status assertion"));
+        assert!(
+            status == expected,
+            "'{}': expected status {}, got {}",
+            command,
+            expected.as_str(),
+            status.as_str()
+        );
+        self.unchecked.clear();
+        self
+    }
+
+    /// Resolves a cell name such as `B1`, or a named cell in the extended build.
+    #[track_caller]
+    fn cell(&self, name: &str) -> (i16, i16) {
+        #[cfg(feature = "extensions")]
+        let resolved = resolve_cell_reference(&self.sheet, name);
+        #[cfg(not(feature = "extensions"))]
+        let resolved = parse_cell_reference(&self.sheet, name);
+        resolved.unwrap_or_else(|status| panic!("{}: not a cell ({})", name, status.as_str()))
+    }
+
+    /// Asserts that a cell holds an integer.
+    #[track_caller]
+    pub fn assert_cell(self, name: &str, expected: i32) -> Self {
+        self.assert_value(name, CellValue::Integer(expected))
+    }
+
+    /// Asserts that a cell holds an error of the given kind.
+    #[track_caller]
+    pub fn assert_error_cell(self, name: &str, kind: ErrorKind) -> Self {
+        self.assert_value(name, CellValue::Error(kind))
+    }
+
+    /// Asserts the value a cell holds, such as `CellValue::Empty`.
+    #[track_caller]
+    pub fn assert_value(self, name: &str, expected: CellValue) -> Self {
+        let (row, col) = self.cell(name);
+        let actual = self.sheet.get_cell(row, col);
+        assert!(
+            *actual == expected,
+            "{}: expected {}, got {}",
+            name,
+            describe(&expected),
+            describe(actual)
+        );
+        self
+    }
+
+    /// Asserts a cell's formula as `formula <cell>` shows it; `""` for a literal cell.
+    #[cfg(feature = "extensions")]
+    #[track_caller]
+    pub fn assert_formula(self, name: &str, expected: &str) -> Self {
+        let (row, col) = self.cell(name);
+        let has_formula = self
+            .sheet
+            .cell_meta
+            .get(&self.sheet.get_key(row, col))
+            .is_some_and(|meta| meta.formula != -1);
+        let actual = if has_formula {
+            get_formula_string(&self.sheet, row, col)
+        } else {
+            String::new()
+        };
+        assert!(
+            actual == expected,
+            "{}: expected formula '{}', got '{}'",
+            name,
+            expected,
+            actual
+        );
+        self
+    }
+
+    /// Asserts the lines printed since the last output assertion, and discards them.
+    #[cfg(feature = "extensions")]
+    #[track_caller]
+    pub fn assert_output(mut self, expected: &[&str]) -> Self {
+        let actual = self.sheet.output.take();
+        assert_eq!(actual, expected, "unexpected command output");
+        self
+    }
+
+    /// Writes the sheet as save file records, the form golden files store.
+    #[cfg(feature = "extensions")]
+    pub fn serialized(&self) -> String {
+        let mut records = Vec::new();
+        write_sheet_records(&self.sheet, &mut records).expect("writing to memory cannot fail");
+        String::from_utf8(records).expect("save file records are UTF-8")
+    }
+
+    /// Asserts that the sheet matches a golden file, panicking with a diff if not.
+    #[cfg(feature = "extensions")]
+    #[track_caller]
+    pub fn assert_golden(self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let actual = self.serialized();
+        let expected = fs::read_to_string(path).unwrap_or_else(|e| {
+            panic!(
+                "cannot read golden file {}: {}\nthe sheet is:\n{}",
+                path.display(),
+                e,
+                actual
+            )
+        });
+        if let Some(diff) = golden_diff(&expected, &actual) {
+            panic!(
+                "sheet does not match golden file {} (-expected +actual):\n{}",
+                path.display(),
+                diff
+            );
+        }
+        self
+    }
+}
+
+/// Shows a cell value as the grid does, the number, an error code or nothing, but with text
+/// in quotes.
+fn describe(value: &CellValue) -> String {
+    match value {
+        CellValue::Integer(value) => value.to_string(),
+        CellValue::Text(text) => format!("\"{}\"", text),
+        CellValue::Empty => String::new(),
+        CellValue::Error(kind) => kind.code(),
+    }
+}
+
+/// Compares two texts line by line.
+///
+/// # Arguments
+///
+/// * `expected` - The stored text.
+/// * `actual` - The text produced now.
+///
+/// # Returns
+///
+/// * `None` - If the lines are equal.
+/// * `Some(String)` - A diff marking removed lines `-` and added lines `+`, with
+///   `DIFF_CONTEXT` unchanged lines around each change and `...` for the rest
+pub fn golden_diff(expected: &str, actual: &str) -> Option<String> {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    if old == new {
+        return None;
+    }
+
+    // Longest common subsequence lengths of every pair of suffixes
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    // Keep the changes and the context around them
+    let near_change = |index: usize| {
+        let start = index.saturating_sub(DIFF_CONTEXT);
+        let end = (index + DIFF_CONTEXT + 1).min(lines.len());
+        lines[start..end].iter().any(|&(mark, _)| mark != ' ')
+    };
+    let mut diff = Vec::new();
+    let mut skipped = false;
+    for (index, &(mark, line)) in lines.iter().enumerate() {
+        if near_change(index) {
+            if skipped {
+                diff.push("...".to_string());
+                skipped = false;
+            }
+            diff.push(format!("{}{}", mark, line));
+        } else {
+            skipped = true;
+        }
+    }
+    if skipped {
+        diff.push("...".to_string());
+    }
+    Some(diff.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+
+    /// Runs `check` and returns its panic message.
+    fn panic_message(check: impl FnOnce()) -> String {
+        let payload = catch_unwind(AssertUnwindSafe(check)).expect_err("expected a panic");
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default()
+    }
+
+    #[cfg(feature = "extensions")]
+    fn golden_path(name: &str) -> String {
+        format!("{}/tests/golden/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn test_fluent_assertions_pass() {
+        ScriptedSheet::new(10, 10)
+            .run(&["A1=5", "B1=A1*2", "C1=A1/0"])
+            .assert_status_ok()
+            .assert_cell("B1", 10)
+            .assert_error_cell("C1", ErrorKind::DivZero)
+            .run(&["A1=B1"])
+            .assert_status(CommandStatus::CircularRef);
+    }
+
+    #[cfg(feature = "extensions")]
+    #[test]
+    fn test_extended_assertions_pass() {
+        ScriptedSheet::new(10, 10)
+            .run(&["A1=5", "B1=A1*2", "C1=A1/0", "name C1 broken"])
+            .assert_status_ok()
+            .assert_error_cell("broken", ErrorKind::DivZero)
+            .assert_formula("B1", "A1*2")
+            .assert_formula("A1", "")
+            .run(&["owners"])
+            .assert_output(&["user: (none), enforce_ownership: off", "No ownership tags"]);
+    }
+
+    #[test]
+    fn test_failure_messages_name_cell_and_values() {
+        let message = panic_message(|| {
+            ScriptedSheet::new(5, 5)
+                .run(&["A1=5", "B1=A1*2"])
+                .assert_cell("B1", 11);
+        });
+        assert_eq!(message, "B1: expected 11, got 10");
+
+        let message = panic_message(|| {
+            ScriptedSheet::new(5, 5)
+                .run(&["A1=7"])
+                .assert_error_cell("A1", ErrorKind::DivZero);
+        });
+        assert_eq!(message, "A1: expected #DIV0, got 7");
+
+        let message = panic_message(|| {
+            ScriptedSheet::new(5, 5)
+                .run(&["A1=1/0"])
+                .assert_cell("A1", 0);
+        });
+        assert_eq!(message, "A1: expected 0, got #DIV0");
+
+        let message = panic_message(|| {
+            ScriptedSheet::new(5, 5)
+                .run(&["A1=2", "B1=A1+"])
+                .assert_status_ok();
+        });
+        assert_eq!(
+            message,
+            "'B1=A1+': expected status ok, got unrecognized_cmd"
+        );
+
+        let message = panic_message(|| {
+            ScriptedSheet::new(5, 5).assert_cell("Z9", 0);
+        });
+        assert!(message.starts_with("Z9: not a cell"), "{}", message);
+    }
+
+    #[cfg(feature = "extensions")]
+    #[test]
+    fn test_golden_file_matches() {
+        ScriptedSheet::new(10, 5)
+            .run_file(golden_path("elementwise.cmds"))
+            .assert_status_ok()
+            .assert_golden(golden_path("elementwise.sheet"));
+    }
+
+    #[cfg(feature = "extensions")]
+    #[test]
+    fn test_golden_runner_detects_mismatch() {
+        let message = panic_message(|| {
+            ScriptedSheet::new(10, 5)
+                .run_file(golden_path("elementwise.cmds"))
+                .run(&["B2=100"])
+                .assert_golden(golden_path("elementwise.sheet"));
+        });
+        assert!(
+            message.starts_with("sheet does not match golden file"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("\n-CELL,B2,2\n-CELL,C2,22,FORMULA,A2+B2\n+CELL,B2,100\n"),
+            "{}",
+            message
+        );
+
+        let message = panic_message(|| {
+            ScriptedSheet::new(5, 5)
+                .run(&["A1=1"])
+                .assert_golden(golden_path("missing.sheet"));
+        });
+        assert!(message.contains("the sheet is:\nDIMS,5,5\nVIEW,0,0\nCELL,A1,1\n"));
+    }
+
+    #[test]
+    fn test_golden_diff_context() {
+        assert_eq!(golden_diff("a\nb\n", "a\nb"), None);
+        let expected: Vec<String> = (1..=10).map(|n| n.to_string()).collect();
+        let mut actual = expected.clone();
+        actual[5] = "six".to_string();
+        actual.push("11".to_string());
+        assert_eq!(
+            golden_diff(&expected.join("\n"), &actual.join("\n")).unwrap(),
+            "...\n 4\n 5\n-6\n+six\n 7\n 8\n 9\n 10\n+11"
+        );
+        assert_eq!(golden_diff("", "x").unwrap(), "+x");
+    }
+}
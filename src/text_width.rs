@@ -0,0 +1,175 @@
+//! Fitting text to the terminal width.
+//!
+//! Long formulas are shortened with `…` or wrapped before they are shown. Widths count
+//! characters as they appear on screen: ANSI escape sequences (colors) take no space
+//! and are never cut in half.
+
+use std::env;
+
+/// Width used when the terminal width is unknown.
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+/// Marker appended to shortened text.
+const ELLIPSIS: char = '…';
+/// Resets colors after shortened text that contained escape sequences.
+const RESET: &str = "\x1b[0m";
+
+/// Splits text into escape sequences and visible characters.
+///
+/// # Arguments
+///
+/// * `text` - The text.
+///
+/// # Returns
+///
+/// * `Vec<(&str, bool)>` - Each piece with `true` if it is an escape sequence
+fn segments(text: &str) -> Vec<(&str, bool)> {
+    let mut pieces = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != '\x1b' {
+            pieces.push((&text[start..start + c.len_utf8()], false));
+            continue;
+        }
+        let mut end = start + 1;
+        match chars.next() {
+            // CSI sequence: ESC [ parameters final-byte
+            Some((i, '[')) => {
+                end = i + 1;
+                for (i, c) in chars.by_ref() {
+                    end = i + c.len_utf8();
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // Two-character sequence
+            Some((i, c)) => end = i + c.len_utf8(),
+            None => {}
+        }
+        pieces.push((&text[start..end], true));
+    }
+    pieces
+}
+
+/// Returns how many columns the text takes on screen.
+pub fn display_width(text: &str) -> usize {
+    segments(text).iter().filter(|(_, escape)| !escape).count()
+}
+
+/// Shortens text to at most `width` columns, ending it with `…` when anything is cut.
+///
+/// Escape sequences before the cut are kept whole, and colors are reset after the `…`.
+///
+/// # Arguments
+///
+/// * `text` - The text to fit.
+/// * `width` - The columns available.
+///
+/// # Returns
+///
+/// * `String` - The text, shortened if it did not fit
+pub fn truncate_display(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    let mut used = 0;
+    let mut colored = false;
+    for (piece, escape) in segments(text) {
+        if escape {
+            colored = true;
+            out.push_str(piece);
+        } else if used + 1 < width {
+            out.push_str(piece);
+            used += 1;
+        } else {
+            break;
+        }
+    }
+    if width > 0 {
+        out.push(ELLIPSIS);
+    }
+    if colored {
+        out.push_str(RESET);
+    }
+    out
+}
+
+/// Breaks text into lines of at most `width` columns.
+///
+/// # Arguments
+///
+/// * `text` - The text to wrap.
+/// * `width` - The columns per line; 0 leaves the text on one line.
+///
+/// # Returns
+///
+/// * `Vec<String>` - The lines, at least one
+pub fn wrap_display(text: &str, width: usize) -> Vec<String> {
+    if width == 0 {
+        return vec![text.to_string()];
+    }
+    let mut lines = vec![String::new()];
+    let mut used = 0;
+    for (piece, escape) in segments(text) {
+        if !escape {
+            if used == width {
+                lines.push(String::new());
+                used = 0;
+            }
+            used += 1;
+        }
+        lines.last_mut().unwrap().push_str(piece);
+    }
+    lines
+}
+
+/// Returns the terminal width from `$COLUMNS`, or 80 when it is not set.
+pub fn terminal_width() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .filter(|&width| width > 0)
+        .unwrap_or(DEFAULT_TERMINAL_WIDTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_plain_text() {
+        assert_eq!(truncate_display("A1+B1", 10), "A1+B1");
+        assert_eq!(truncate_display("A1+B1", 5), "A1+B1");
+        assert_eq!(truncate_display("A1+B1", 4), "A1+…");
+        assert_eq!(truncate_display("A1+B1", 1), "…");
+        assert_eq!(truncate_display("A1+B1", 0), "");
+        assert_eq!(display_width(&truncate_display(&"x".repeat(500), 80)), 80);
+        // Width counts characters, not bytes
+        assert_eq!(truncate_display("ééééé", 3), "éé…");
+    }
+
+    #[test]
+    fn test_truncate_keeps_escape_sequences_whole() {
+        let colored = "\x1b[31mSUM(A1:A9)\x1b[0m+B1";
+        assert_eq!(display_width(colored), 13);
+        assert_eq!(truncate_display(colored, 13), colored);
+        let cut = truncate_display(colored, 5);
+        assert_eq!(cut, "\x1b[31mSUM(…\x1b[0m");
+        assert_eq!(display_width(&cut), 5);
+        // A cut right after an escape sequence still keeps it whole
+        let cut = truncate_display("ab\x1b[1;32mcdef", 3);
+        assert_eq!(cut, "ab\x1b[1;32m…\x1b[0m");
+    }
+
+    #[test]
+    fn test_wrap_display() {
+        assert_eq!(wrap_display("abcdefg", 3), vec!["abc", "def", "g"]);
+        assert_eq!(wrap_display("abc", 3), vec!["abc"]);
+        assert_eq!(wrap_display("", 3), vec![""]);
+        assert_eq!(
+            wrap_display("\x1b[31mabcd\x1b[0m", 2),
+            vec!["\x1b[31mab", "cd\x1b[0m"]
+        );
+    }
+}
@@ -0,0 +1,550 @@
+//! Guided tutorial that walks a new user through the core spreadsheet features.
+//!
+//! The tutorial is a declarative table of [`Step`]s. Each step prints an instruction,
+//! waits for a command and checks the resulting sheet state (not the command text), so any
+//! command that produces the expected effect moves the tutorial forward. The reload step
+//! also needs the command to have read the file back, since typing the saved values in
+//! gives the same sheet.
+//!
+//! The save and reload steps write to a directory of the tutorial's own, removed when the
+//! tutorial ends, so a `tutorial.sheet` of the user's is never overwritten or deleted.
+//! Every other command that works on files is refused while the tutorial runs, as is
+//! `run`, whose script could hold one.
+
+use crate::cell::CellValue;
+use crate::dry_run::works_on_files;
+use crate::process_command::process_command;
+use crate::range::Range;
+use crate::save_load::{load_spreadsheet, save_spreadsheet};
+use crate::spreadsheet::{CommandStatus, Spreadsheet};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// File used by the save and reload steps, inside the tutorial's directory.
+pub const TUTORIAL_FILE: &str = "tutorial.sheet";
+
+/// Number of failed attempts after which the step hint is shown.
+const HINT_AFTER: u8 = 2;
+
+/// A single tutorial step.
+///
+/// # Fields
+///
+/// * `instruction` - Text shown to the user when the step starts.
+/// * `hint` - Text shown after repeated failed attempts.
+/// * `check` - Validator run after every command with the sheet, the command status, the
+///   tutorial's directory and whether the command reloaded a file.
+pub struct Step {
+    pub instruction: &'static str,
+    pub hint: &'static str,
+    pub check: fn(&Spreadsheet, &CommandStatus, &Path, bool) -> bool,
+}
+
+/// Returns the integer value of a cell, 0 if it is empty, or `None` if it holds an error or
+/// text.
+fn value(sheet: &Spreadsheet, row: i16, col: i16) -> Option<i32> {
+    sheet.get_cell(row, col).number().ok()
+}
+
+/// Returns true if the cell has a formula attached.
+fn has_formula(sheet: &Spreadsheet, row: i16, col: i16) -> bool {
+    sheet
+        .cell_meta
+        .get(&sheet.get_key(row, col))
+        .is_some_and(|meta| meta.formula != -1)
+}
+
+/// Returns true if B2 holds a formula equal to A1 + B1.
+fn b2_tracks_sum(sheet: &Spreadsheet) -> bool {
+    has_formula(sheet, 1, 1)
+        && matches!(
+            (value(sheet, 0, 0), value(sheet, 0, 1), value(sheet, 1, 1)),
+            (Some(a1), Some(b1), Some(b2)) if a1.checked_add(b1) == Some(b2)
+        )
+}
+
+/// Returns true if the tutorial file in `dir` reloads into a sheet identical to `sheet`.
+fn saved_copy_matches(sheet: &Spreadsheet, dir: &Path) -> bool {
+    let Some(mut copy) = Spreadsheet::create(sheet.rows, sheet.cols) else {
+        return false;
+    };
+    let file = dir.join(TUTORIAL_FILE).display().to_string();
+    load_spreadsheet(&mut copy, &file) == CommandStatus::CmdOk
+        && copy.cell_values().eq(sheet.cell_values())
+}
+
+/// The tutorial script, run in order against the example sheet.
+pub const STEPS: &[Step] = &[
+    Step {
+        instruction: "Step 1: Assign the value 5 to cell B1.",
+        hint: "Type B1=5",
+        check: |sheet, _, _, _| value(sheet, 0, 1) == Some(5) && !has_formula(sheet, 0, 1),
+    },
+    Step {
+        instruction: "Step 2: Make B2 a formula adding A1 and B1.",
+        hint: "Type B2=A1+B1",
+        check: |sheet, _, _, _| b2_tracks_sum(sheet),
+    },
+    Step {
+        instruction: "Step 3: Change A1 to 100 and watch B2 recalculate.",
+        hint: "Type A1=100",
+        check: |sheet, _, _, _| value(sheet, 0, 0) == Some(100) && b2_tracks_sum(sheet),
+    },
+    Step {
+        instruction: "Step 4: Put the sum of A1:A3 into C1 using a range function.",
+        hint: "Type C1=SUM(A1:A3)",
+        check: |sheet, _, _, _| {
+            has_formula(sheet, 0, 2)
+                && match (value(sheet, 0, 0), value(sheet, 1, 0), value(sheet, 2, 0)) {
+                    (Some(a1), Some(a2), Some(a3)) => a1
+                        .checked_add(a2)
+                        .and_then(|sum| sum.checked_add(a3))
+                        .is_some_and(|sum| value(sheet, 0, 2) == Some(sum)),
+                    _ => false,
+                }
+        },
+    },
+    Step {
+        instruction: "Step 5: Give the range A1:A3 a name of your choice.",
+        hint: "Type name A1:A3 data",
+        check: |sheet, _, _, _| {
+            let target = Range {
+                start_row: 0,
+                start_col: 0,
+                end_row: 2,
+                end_col: 0,
+            };
+            sheet.named_ranges.values().any(|range| *range == target)
+        },
+    },
+    Step {
+        instruction: "Step 6: Lock cell B1 so it can no longer be edited.",
+        hint: "Type lock_cell B1",
+        check: |sheet, _, _, _| sheet.is_cell_locked(0, 1),
+    },
+    Step {
+        instruction: "Step 7: Make A1 depend on B2. B2 already depends on A1, so this is a cycle.",
+        hint: "Type A1=B2+1 and look for circular_ref in the prompt",
+        check: |_, status, _, _| *status == CommandStatus::CircularRef,
+    },
+    Step {
+        instruction: "Step 8: The cycle was rejected. Give A1 a formula that does not loop.",
+        hint: "Type A1=A2*2",
+        check: |sheet, status, _, _| {
+            *status == CommandStatus::CmdOk && has_formula(sheet, 0, 0) && b2_tracks_sum(sheet)
+        },
+    },
+    Step {
+        instruction: "Step 9: Save the sheet to tutorial.sheet.",
+        hint: "Type save tutorial.sheet",
+        check: |sheet, _, dir, _| saved_copy_matches(sheet, dir),
+    },
+    Step {
+        instruction: "Step 10: Overwrite A2 with 0.",
+        hint: "Type A2=0",
+        check: |sheet, _, _, _| value(sheet, 1, 0) == Some(0),
+    },
+    Step {
+        instruction: "Step 11: Reload tutorial.sheet to get your saved work back.",
+        hint: "Type open tutorial.sheet",
+        check: |sheet, status, dir, reloaded| {
+            reloaded && *status == CommandStatus::CmdOk && saved_copy_matches(sheet, dir)
+        },
+    },
+];
+
+/// Result of feeding one command to the tutorial.
+///
+/// # Variants
+///
+/// * `Advanced` - The step was completed; the next instruction should be shown.
+/// * `Retry` - The step is not complete yet.
+/// * `Hint` - The step is not complete and the hint should be shown.
+/// * `Finished` - The last step was completed and normal mode is restored.
+/// * `Quit` - The user left the tutorial and normal mode is restored.
+#[derive(Debug, PartialEq)]
+pub enum TutorialEvent {
+    Advanced,
+    Retry,
+    Hint(&'static str),
+    Finished,
+    Quit,
+}
+
+/// State of a running tutorial session.
+///
+/// The user's own sheet is parked in `saved_sheet` while the example sheet is in use
+/// and swapped back when the tutorial ends. `dir` holds the files the tutorial saves and
+/// is removed with it; it is `None` if it could not be created, and saving then fails.
+pub struct Tutorial {
+    step: usize,
+    failures: u8,
+    saved_sheet: Spreadsheet,
+    dir: Option<TempDir>,
+}
+
+impl Tutorial {
+    /// Starts a tutorial, replacing `sheet` with the built-in example sheet.
+    ///
+    /// # Arguments
+    ///
+    /// * `sheet` - The user's sheet; restored when the tutorial ends.
+    pub fn start(sheet: &mut Spreadsheet) -> Tutorial {
+        let mut example = Spreadsheet::create(10, 10).unwrap();
+        for (row, v) in [10, 20, 30].into_iter().enumerate() {
+            *example.get_mut_cell(row as i16, 0) = CellValue::Integer(v);
+        }
+        example.cell_counts = example.scan_cell_counts();
+        let saved_sheet = std::mem::replace(sheet, example);
+        let dir = match tempfile::tempdir() {
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                eprintln!(
+                    "Could not create a directory for the tutorial's files: {}",
+                    e
+                );
+                None
+            }
+        };
+        Tutorial {
+            step: 0,
+            failures: 0,
+            saved_sheet,
+            dir,
+        }
+    }
+
+    /// Returns where a file named in `save` or `open` lives: in the tutorial's directory,
+    /// whatever directory the name gives.
+    fn file_path(&self, file: &str) -> Option<String> {
+        let name = Path::new(file.trim()).file_name()?;
+        let dir = self.dir.as_ref()?;
+        Some(dir.path().join(name).display().to_string())
+    }
+
+    /// Returns the tutorial's directory, or an empty path if it has none.
+    fn dir_path(&self) -> PathBuf {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.path().to_path_buf())
+            .unwrap_or_default()
+    }
+
+    /// Returns the instruction for the current step.
+    pub fn instruction(&self) -> &'static str {
+        STEPS[self.step].instruction
+    }
+
+    /// Runs a command in tutorial mode and validates the current step.
+    ///
+    /// Besides the regular commands, `save <file>`, `open <file>` and `reload` (of
+    /// `tutorial.sheet`) are handled here, on the tutorial's directory, other file
+    /// commands and `run` are refused, and `tutorial quit` ends the session.
+    ///
+    /// # Arguments
+    ///
+    /// * `sheet` - The example sheet (the user's sheet once the tutorial ends).
+    /// * `command` - The command entered by the user.
+    /// * `last_time` - Stores the command execution time.
+    ///
+    /// # Returns
+    ///
+    /// The command status and the resulting tutorial event.
+    pub fn feed(
+        &mut self,
+        sheet: &mut Spreadsheet,
+        command: &str,
+        last_time: &mut f64,
+    ) -> (CommandStatus, TutorialEvent) {
+        if command == "tutorial quit" {
+            self.finish(sheet);
+            return (CommandStatus::CmdOk, TutorialEvent::Quit);
+        }
+
+        let (status, reloaded) = if let Some(file) = command.strip_prefix("save ") {
+            let status = match self.file_path(file) {
+                Some(path) => save_spreadsheet(sheet, &path),
+                None => CommandStatus::Unrecognized,
+            };
+            (status, false)
+        } else if let Some(file) = command.strip_prefix("open ") {
+            let status = match self.file_path(file) {
+                Some(path) => load_spreadsheet(sheet, &path),
+                None => CommandStatus::Unrecognized,
+            };
+            (status, true)
+        } else if command == "reload" || command == "reload!" {
+            let status = match self.file_path(TUTORIAL_FILE) {
+                Some(path) => load_spreadsheet(sheet, &path),
+                None => CommandStatus::Unrecognized,
+            };
+            (status, true)
+        } else if works_on_files(command) || command.starts_with("run ") {
+            let name = command.split_whitespace().next().unwrap_or("");
+            sheet.output.line(format!(
+                "tutorial: '{}' is not available in the tutorial, which only saves and opens {}",
+                name, TUTORIAL_FILE
+            ));
+            (CommandStatus::Unrecognized, false)
+        } else {
+            (process_command(sheet, command, last_time), false)
+        };
+
+        if (STEPS[self.step].check)(sheet, &status, &self.dir_path(), reloaded) {
+            self.step += 1;
+            self.failures = 0;
+            if self.step == STEPS.len() {
+                self.finish(sheet);
+                return (status, TutorialEvent::Finished);
+            }
+            return (status, TutorialEvent::Advanced);
+        }
+
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= HINT_AFTER {
+            (status, TutorialEvent::Hint(STEPS[self.step].hint))
+        } else {
+            (status, TutorialEvent::Retry)
+        }
+    }
+
+    /// Restores the user's sheet and removes the tutorial's directory with its files.
+    fn finish(&mut self, sheet: &mut Spreadsheet) {
+        std::mem::swap(sheet, &mut self.saved_sheet);
+        // Dropping the directory removes it
+        self.dir = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(tutorial: &mut Tutorial, sheet: &mut Spreadsheet, command: &str) -> TutorialEvent {
+        let mut last_time = 0.0;
+        tutorial.feed(sheet, command, &mut last_time).1
+    }
+
+    #[test]
+    fn test_tutorial_happy_path() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        *sheet.get_mut_cell(0, 0) = CellValue::Integer(42);
+        let mut tutorial = Tutorial::start(&mut sheet);
+        let dir = tutorial.dir_path();
+        assert_eq!(sheet.rows, 10);
+        assert_eq!(*sheet.get_cell(1, 0), CellValue::Integer(20));
+
+        let script = [
+            "B1=5",
+            "B2=B1+A1",
+            "A1=100",
+            "C1=SUM(A1:A3)",
+            "name A1:A3 data",
+            "lock_cell B1",
+            "A1=B2+1",
+            "A1=A2*2",
+            "save tutorial.sheet",
+            "A2=0",
+        ];
+        for command in script {
+            assert_eq!(
+                run(&mut tutorial, &mut sheet, command),
+                TutorialEvent::Advanced,
+                "command {} did not advance",
+                command
+            );
+        }
+        assert_eq!(
+            run(&mut tutorial, &mut sheet, "open tutorial.sheet"),
+            TutorialEvent::Finished
+        );
+
+        // The user's own sheet is back in place, and the tutorial's file is gone
+        assert_eq!(sheet.rows, 5);
+        assert_eq!(*sheet.get_cell(0, 0), CellValue::Integer(42));
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_tutorial_reload_step_needs_a_reload() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut tutorial = Tutorial::start(&mut sheet);
+        let script = [
+            "B1=5",
+            "B2=B1+A1",
+            "A1=100",
+            "C1=SUM(A1:A3)",
+            "name A1:A3 data",
+            "lock_cell B1",
+            "A1=B2+1",
+            "A1=A2*2",
+            "save tutorial.sheet",
+            "A2=0",
+        ];
+        for command in script {
+            run(&mut tutorial, &mut sheet, command);
+        }
+
+        // Typing the saved value back in gives the same sheet but is not a reload
+        assert_eq!(
+            run(&mut tutorial, &mut sheet, "A2=20"),
+            TutorialEvent::Retry
+        );
+        assert_eq!(tutorial.instruction(), STEPS[10].instruction);
+        assert_eq!(
+            run(&mut tutorial, &mut sheet, "reload"),
+            TutorialEvent::Finished
+        );
+    }
+
+    #[test]
+    fn test_tutorial_hint_after_two_failures() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut tutorial = Tutorial::start(&mut sheet);
+
+        assert_eq!(run(&mut tutorial, &mut sheet, "B1=6"), TutorialEvent::Retry);
+        assert_eq!(
+            run(&mut tutorial, &mut sheet, "C1=5"),
+            TutorialEvent::Hint(STEPS[0].hint)
+        );
+        assert_eq!(tutorial.instruction(), STEPS[0].instruction);
+
+        // Succeeding resets the failure counter for the next step
+        assert_eq!(
+            run(&mut tutorial, &mut sheet, "B1=5"),
+            TutorialEvent::Advanced
+        );
+        assert_eq!(run(&mut tutorial, &mut sheet, "B2=7"), TutorialEvent::Retry);
+    }
+
+    #[test]
+    fn test_tutorial_quit_restores_sheet() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        *sheet.get_mut_cell(2, 2) = CellValue::Integer(7);
+        let mut tutorial = Tutorial::start(&mut sheet);
+
+        assert_eq!(
+            run(&mut tutorial, &mut sheet, "B1=5"),
+            TutorialEvent::Advanced
+        );
+        assert_eq!(
+            run(&mut tutorial, &mut sheet, "tutorial quit"),
+            TutorialEvent::Quit
+        );
+        assert_eq!(sheet.rows, 5);
+        assert_eq!(*sheet.get_cell(2, 2), CellValue::Integer(7));
+        assert_eq!(*sheet.get_cell(0, 1), CellValue::Empty);
+    }
+
+    #[test]
+    fn test_tutorial_checks_survive_overflowing_sums() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut tutorial = Tutorial::start(&mut sheet);
+
+        let script = [
+            ("B1=5", true),
+            ("A1=2147483647", false),
+            // A1 + B1 does not fit in an i32, so B2 cannot track it
+            ("B2=A1-B1", false),
+            ("B2=B1+A1", false),
+            ("A1=100", true),
+            ("A1=100", true),
+            ("C1=A1-A2", false),
+            ("A2=2147483647", false),
+        ];
+        for (command, advances) in script {
+            assert_eq!(
+                run(&mut tutorial, &mut sheet, command) == TutorialEvent::Advanced,
+                advances,
+                "command {}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn test_tutorial_leaves_user_files_alone() {
+        let user_dir = tempfile::tempdir().unwrap();
+        let user_file = user_dir.path().join(TUTORIAL_FILE);
+        std::fs::write(&user_file, "DIMS,5,5\nCELL,A1,1\n").unwrap();
+
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut tutorial = Tutorial::start(&mut sheet);
+        let dir = tutorial.dir_path();
+        let save = format!("save {}", user_file.display());
+        run(&mut tutorial, &mut sheet, &save);
+        // The save went to the tutorial's own directory
+        assert!(dir.join(TUTORIAL_FILE).exists());
+        assert_eq!(
+            run(&mut tutorial, &mut sheet, "tutorial quit"),
+            TutorialEvent::Quit
+        );
+        assert!(!dir.exists());
+        assert_eq!(
+            std::fs::read_to_string(&user_file).unwrap(),
+            "DIMS,5,5\nCELL,A1,1\n"
+        );
+    }
+
+    #[test]
+    fn test_tutorial_reload_step_compares_with_the_file() {
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut tutorial = Tutorial::start(&mut sheet);
+        let script = [
+            "B1=5",
+            "B2=B1+A1",
+            "A1=100",
+            "C1=SUM(A1:A3)",
+            "name A1:A3 data",
+            "lock_cell B1",
+            "A1=B2+1",
+            "A1=A2*2",
+            "save tutorial.sheet",
+            "A2=0",
+        ];
+        for command in script {
+            run(&mut tutorial, &mut sheet, command);
+        }
+        // Saved again with A2 at 0, so reloading gives that back rather than 20
+        assert_eq!(
+            run(&mut tutorial, &mut sheet, "save tutorial.sheet"),
+            TutorialEvent::Retry
+        );
+        assert_eq!(
+            run(&mut tutorial, &mut sheet, "open tutorial.sheet"),
+            TutorialEvent::Finished
+        );
+    }
+
+    #[test]
+    fn test_tutorial_refuses_other_file_commands() {
+        let user_dir = tempfile::tempdir().unwrap();
+        let mut sheet = Spreadsheet::create(5, 5).unwrap();
+        let mut tutorial = Tutorial::start(&mut sheet);
+        sheet.output = crate::output::OutputSink::capture();
+        for command in [
+            "saveas copy.sheet",
+            "export_csv copy.csv",
+            "session save work",
+            "autosave on copy.sheet",
+            "graph_stats --csv copy.csv",
+            "set history_file copy.history",
+            "visual A1",
+            "run copy.txt",
+        ] {
+            let file = command.replace("copy", &user_dir.path().join("copy").display().to_string());
+            let (status, _) = tutorial.feed(&mut sheet, &file, &mut 0.0);
+            assert_eq!(status, CommandStatus::Unrecognized, "{}", command);
+            let name = command.split_whitespace().next().unwrap();
+            assert_eq!(
+                sheet.output.take(),
+                vec![format!(
+                    "tutorial: '{}' is not available in the tutorial, which only saves and opens tutorial.sheet",
+                    name
+                )]
+            );
+        }
+        assert_eq!(std::fs::read_dir(user_dir.path()).unwrap().count(), 0);
+        assert!(sheet.autosave.is_none());
+    }
+}
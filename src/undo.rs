@@ -0,0 +1,508 @@
+//! `undo` and `redo` of whole commands.
+//!
+//! While a command runs, every cell it touches is captured once, before its first change:
+//! assignments capture their target up front, and the hooks in `get_mut_cell`,
+//! `get_cell_meta` and `clear_formula` catch the rest, including the dependents a command
+//! reevaluates. When the command ends, the captured cells form one undo step. `undo` puts
+//! the cells of the last step back and reevaluates their dependents; what it overwrote
+//! becomes a redo step.
+//!
+//! Commands that move keys around (`insert_row`, `delete_row`) or empty the sheet
+//! (`clear_sheet`) cannot be taken back cell by cell. They save the whole sheet layout
+//! instead: every cell, dependency, range, tag and column formula that names cells by
+//! position. Cells the same command changed later are covered by that copy. As each copy
+//! holds the whole sheet, only the last `MAX_LAYOUT_STEPS` of them are kept, and older
+//! history goes with the oldest one. Opening a file starts a new sheet with no history.
+//!
+//! `dry` records a step the same way, which it puts back with `put_back` and never keeps.
+
+use crate::cell::CellValue;
+use crate::cell_history::HistoryEntry;
+use crate::column_formula::ColumnFormula;
+use crate::expr_parser::Criterion;
+use crate::extended_formula::ExtendedFormula;
+use crate::filter::RowFilter;
+use crate::ownership::OwnerTag;
+use crate::range::Range;
+use crate::reevaluate_topo::toposort_reval_many;
+use crate::sort::CellSnapshot;
+use crate::spreadsheet::{CellMeta, CommandStatus, HighlightType, RangeChild, Spreadsheet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// How many commands `undo` can go back.
+pub const MAX_UNDO_STEPS: usize = 100;
+
+/// How many of those may hold a copy of the whole sheet.
+pub const MAX_LAYOUT_STEPS: usize = 5;
+
+/// Everything in a sheet that names cells by position, as `insert_row`, `delete_row` and
+/// `clear_sheet` found it.
+#[derive(Clone)]
+pub struct SheetLayout {
+    grid: Vec<CellValue>,
+    children: HashMap<i32, HashSet<i32>>,
+    range_children: Vec<RangeChild>,
+    cell_meta: HashMap<i32, CellMeta>,
+    criteria: HashMap<i32, Criterion>,
+    extended_formulas: HashMap<i32, ExtendedFormula>,
+    relative_refs: HashMap<i32, u8>,
+    assigned: HashSet<i32>,
+    cell_history: HashMap<i32, Vec<HistoryEntry>>,
+    named_ranges: HashMap<String, Range>,
+    locked_ranges: Vec<Range>,
+    column_formulas: BTreeMap<i16, ColumnFormula>,
+    owner_tags: Vec<OwnerTag>,
+    row_filter: Option<RowFilter>,
+    last_edited: Option<(i16, i16)>,
+    highlight_cell: i32,
+    highlight_type: HighlightType,
+}
+
+impl SheetLayout {
+    /// Copies the layout of a sheet.
+    fn capture(sheet: &Spreadsheet) -> Self {
+        SheetLayout {
+            grid: sheet.grid.clone(),
+            children: sheet.children.clone(),
+            range_children: sheet.range_children.clone(),
+            cell_meta: sheet.cell_meta.clone(),
+            criteria: sheet.criteria.clone(),
+            extended_formulas: sheet.extended_formulas.clone(),
+            relative_refs: sheet.relative_refs.clone(),
+            assigned: sheet.assigned.clone(),
+            cell_history: sheet.cell_history.clone(),
+            named_ranges: sheet.named_ranges.clone(),
+            locked_ranges: sheet.locked_ranges.clone(),
+            column_formulas: sheet.column_formulas.clone(),
+            owner_tags: sheet.ownership.tags.clone(),
+            row_filter: sheet.row_filter.clone(),
+            last_edited: sheet.last_edited,
+            highlight_cell: sheet.highlight_cell,
+            highlight_type: sheet.highlight_type,
+        }
+    }
+
+    /// Puts the layout into a sheet.
+    ///
+    /// # Returns
+    ///
+    /// * `SheetLayout` - The layout it replaced
+    fn swap_into(mut self, sheet: &mut Spreadsheet) -> Self {
+        use std::mem::swap;
+        swap(&mut self.grid, &mut sheet.grid);
+        swap(&mut self.children, &mut sheet.children);
+        swap(&mut self.range_children, &mut sheet.range_children);
+        swap(&mut self.cell_meta, &mut sheet.cell_meta);
+        swap(&mut self.criteria, &mut sheet.criteria);
+        swap(&mut self.extended_formulas, &mut sheet.extended_formulas);
+        swap(&mut self.relative_refs, &mut sheet.relative_refs);
+        swap(&mut self.assigned, &mut sheet.assigned);
+        swap(&mut self.cell_history, &mut sheet.cell_history);
+        swap(&mut self.named_ranges, &mut sheet.named_ranges);
+        swap(&mut self.locked_ranges, &mut sheet.locked_ranges);
+        swap(&mut self.column_formulas, &mut sheet.column_formulas);
+        swap(&mut self.owner_tags, &mut sheet.ownership.tags);
+        swap(&mut self.row_filter, &mut sheet.row_filter);
+        swap(&mut self.last_edited, &mut sheet.last_edited);
+        swap(&mut self.highlight_cell, &mut sheet.highlight_cell);
+        swap(&mut self.highlight_type, &mut sheet.highlight_type);
+        // Positions saved by the search and a pending confirmation may not exist any more
+        sheet.search = None;
+        sheet.pending_overwrite = None;
+        self
+    }
+}
+
+/// The cells one command changed, as they were before it ran.
+///
+/// # Fields
+///
+/// * `command` - The command as entered.
+/// * `cells` - The state of each changed cell before the command, by cell key.
+/// * `layout` - The whole sheet, if the command moved or dropped every cell; it is put
+///   back before `cells`.
+#[derive(Clone)]
+pub struct UndoStep {
+    pub command: String,
+    pub cells: HashMap<i32, CellSnapshot>,
+    pub layout: Option<Box<SheetLayout>>,
+}
+
+impl UndoStep {
+    /// Describes how much the step changes, for the `undo` and `redo` messages.
+    fn extent(&self) -> String {
+        match self.layout {
+            Some(_) => "whole sheet".to_string(),
+            None => format!("{} cell(s)", self.cells.len()),
+        }
+    }
+}
+
+/// Undo and redo history.
+///
+/// # Fields
+///
+/// * `undo` - Steps `undo` can take back, oldest first.
+/// * `redo` - Steps `redo` can apply again, most recently undone last.
+/// * `recording` - The step of the command running now.
+#[derive(Clone, Default)]
+pub struct UndoLog {
+    pub undo: Vec<UndoStep>,
+    pub redo: Vec<UndoStep>,
+    pub recording: Option<UndoStep>,
+}
+
+impl Spreadsheet {
+    /// Starts recording the cells a command changes.
+    ///
+    /// Commands run by another command, such as the lines of a script, belong to the
+    /// step of the outer command.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command about to run.
+    ///
+    /// # Returns
+    ///
+    /// * `true` - If a step was started; pass it to `end_undo_step` afterwards.
+    /// * `false` - If a step is already being recorded or the command is not recorded
+    pub fn begin_undo_step(&mut self, command: &str) -> bool {
+        let word = command.split_whitespace().next().unwrap_or("");
+        if self.undo_log.recording.is_some() || matches!(word, "undo" | "redo" | "dry") {
+            return false;
+        }
+        self.undo_log.recording = Some(UndoStep {
+            command: command.to_string(),
+            cells: HashMap::new(),
+            layout: None,
+        });
+        true
+    }
+
+    /// Ends the step started by `begin_undo_step`, keeping it if the command changed a cell.
+    ///
+    /// A failed command is rolled back, or stops part way, so of its captured cells only
+    /// those that differ from before are kept; if none do, no step is kept and the redo
+    /// history stays.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The status of the command.
+    pub fn end_undo_step(&mut self, status: &CommandStatus) {
+        let Some(mut step) = self.undo_log.recording.take() else {
+            return;
+        };
+        if *status != CommandStatus::CmdOk && step.layout.is_none() {
+            step.cells.retain(|&key, before| {
+                let (row, col) = self.get_row_col(key);
+                *before != CellSnapshot::capture(self, row, col)
+            });
+        }
+        if step.cells.is_empty() && step.layout.is_none() {
+            return;
+        }
+        if self.undo_log.undo.len() == MAX_UNDO_STEPS {
+            self.undo_log.undo.remove(0);
+        }
+        let layouts = self
+            .undo_log
+            .undo
+            .iter()
+            .filter(|step| step.layout.is_some());
+        if step.layout.is_some()
+            && layouts.count() == MAX_LAYOUT_STEPS
+            && let Some(oldest) = self
+                .undo_log
+                .undo
+                .iter()
+                .position(|step| step.layout.is_some())
+        {
+            // Earlier steps name cells where the oldest layout left them
+            self.undo_log.undo.drain(..=oldest);
+        }
+        self.undo_log.undo.push(step);
+        self.undo_log.redo.clear();
+    }
+
+    /// Captures a cell before its first change in the step being recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The cell's key.
+    pub fn note_undo(&mut self, key: i32) {
+        let Some(step) = &self.undo_log.recording else {
+            return;
+        };
+        // A saved layout already holds the cell as it was before the command
+        if step.cells.contains_key(&key) || step.layout.is_some() {
+            return;
+        }
+        let (row, col) = self.get_row_col(key);
+        let snapshot = CellSnapshot::capture(self, row, col);
+        if let Some(step) = &mut self.undo_log.recording {
+            step.cells.insert(key, snapshot);
+        }
+    }
+
+    /// Saves the whole sheet layout before a command moves or drops every cell.
+    ///
+    /// Only the first call in a step saves anything; later changes are covered by it.
+    pub fn note_undo_layout(&mut self) {
+        let Some(step) = &self.undo_log.recording else {
+            return;
+        };
+        if step.layout.is_some() {
+            return;
+        }
+        let layout = SheetLayout::capture(self);
+        if let Some(step) = &mut self.undo_log.recording {
+            step.layout = Some(Box::new(layout));
+        }
+    }
+}
+
+/// Puts the layout and cells of a step back, leaving their values as the step holds them.
+///
+/// # Returns
+///
+/// * `UndoStep` - The sheet as it was before, which takes the step back again
+pub fn put_back(sheet: &mut Spreadsheet, step: UndoStep) -> UndoStep {
+    // A dry run of `undo` records the layout it replaces
+    if step.layout.is_some() {
+        sheet.note_undo_layout();
+    }
+    // The replaced layout holds every cell, so the cells need no copy of their own
+    let layout = step.layout.map(|layout| Box::new(layout.swap_into(sheet)));
+    let keys: Vec<i32> = step.cells.keys().copied().collect();
+    let mut inverse = HashMap::with_capacity(keys.len());
+    for &key in &keys {
+        let (row, col) = sheet.get_row_col(key);
+        if layout.is_none() {
+            inverse.insert(key, CellSnapshot::capture(sheet, row, col));
+        }
+        sheet.clear_formula(row, col);
+        sheet.extended_formulas.remove(&key);
+        sheet.relative_refs.remove(&key);
+        sheet.criteria.remove(&key);
+    }
+    for (&key, snapshot) in &step.cells {
+        let (row, col) = sheet.get_row_col(key);
+        snapshot.write(sheet, row, col);
+    }
+    UndoStep {
+        command: step.command,
+        cells: inverse,
+        layout,
+    }
+}
+
+/// Puts the layout and cells of a step back and reevaluates what depends on them.
+///
+/// # Returns
+///
+/// * `UndoStep` - The sheet as it was before, which takes the step back again
+fn restore(sheet: &mut Spreadsheet, step: UndoStep, sleep_time: &mut f64) -> UndoStep {
+    let keys: Vec<i32> = step.cells.keys().copied().collect();
+    let inverse = put_back(sheet, step);
+    toposort_reval_many(sheet, &keys, sleep_time);
+    sheet.cell_counts = sheet.scan_cell_counts();
+    sheet.dirty = true;
+    inverse
+}
+
+/// Handles `undo`: takes back the last command that changed cells.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If there is nothing to undo
+pub fn handle_undo(sheet: &mut Spreadsheet, sleep_time: &mut f64) -> CommandStatus {
+    sheet.journal_undo_log();
+    let Some(step) = sheet.undo_log.undo.pop() else {
+        sheet.output.line("nothing to undo".to_string());
+        return CommandStatus::Unrecognized;
+    };
+    let extent = step.extent();
+    let redo = restore(sheet, step, sleep_time);
+    sheet
+        .output
+        .line(format!("undid {} ({})", redo.command, extent));
+    sheet.undo_log.redo.push(redo);
+    CommandStatus::CmdOk
+}
+
+/// Handles `redo`: applies the last undone command again.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `sleep_time` - Accumulates sleep time.
+///
+/// # Returns
+///
+/// * `CommandStatus::CmdOk` - On success.
+/// * `CommandStatus::Unrecognized` - If there is nothing to redo
+pub fn handle_redo(sheet: &mut Spreadsheet, sleep_time: &mut f64) -> CommandStatus {
+    sheet.journal_undo_log();
+    let Some(step) = sheet.undo_log.redo.pop() else {
+        sheet.output.line("nothing to redo".to_string());
+        return CommandStatus::Unrecognized;
+    };
+    let extent = step.extent();
+    let undo = restore(sheet, step, sleep_time);
+    sheet
+        .output
+        .line(format!("redid {} ({})", undo.command, extent));
+    sheet.undo_log.undo.push(undo);
+    CommandStatus::CmdOk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_LAYOUT_STEPS;
+    use crate::cell::{CellValue, ErrorKind};
+    use crate::spreadsheet::CommandStatus;
+    use crate::test_utils::ScriptedSheet;
+
+    #[test]
+    fn test_undo_assignment_and_redo() {
+        ScriptedSheet::new(10, 10)
+            .run(&["A1=2", "B1=A1*3", "A1=5"])
+            .assert_cell("B1", 15)
+            .run(&["undo"])
+            .assert_status_ok()
+            .assert_output(&["undid A1=5 (2 cell(s))"])
+            .assert_cell("A1", 2)
+            .assert_cell("B1", 6)
+            .run(&["undo"])
+            .assert_formula("B1", "")
+            .run(&["redo", "redo"])
+            .assert_cell("A1", 5)
+            .assert_formula("B1", "A1*3")
+            .assert_cell("B1", 15)
+            .run(&["redo"])
+            .assert_status(CommandStatus::Unrecognized)
+            .assert_output(&[
+                "undid B1=A1*3 (1 cell(s))",
+                "redid B1=A1*3 (1 cell(s))",
+                "redid A1=5 (2 cell(s))",
+                "nothing to redo",
+            ]);
+    }
+
+    #[test]
+    fn test_failed_assignments_record_no_step() {
+        ScriptedSheet::new(10, 10)
+            .run(&["A1=5", "A2=1", "undo"])
+            .run(&["A1=SUM()", "A1=foo+", "A1=A1+1"])
+            .assert_status(CommandStatus::CircularRef)
+            .assert_cell("A1", 5)
+            // The failed assignments left the redo history alone
+            .run(&["redo", "undo", "undo"])
+            .assert_status_ok()
+            .assert_output(&[
+                "undid A2=1 (1 cell(s))",
+                "redid A2=1 (1 cell(s))",
+                "undid A2=1 (1 cell(s))",
+                "undid A1=5 (1 cell(s))",
+            ])
+            .assert_value("A1", CellValue::Empty);
+    }
+
+    #[test]
+    fn test_undo_range_command_as_one_step() {
+        ScriptedSheet::new(10, 10)
+            .run(&[
+                "A1=9",
+                "A2=SUM(A1:A1)",
+                "C1=SUM(A1:A10)",
+                "series A1:A5 1 1",
+            ])
+            .assert_cell("C1", 15)
+            .run(&["undo"])
+            .assert_cell("A1", 9)
+            .assert_formula("A2", "SUM(A1:A1)")
+            .assert_cell("A2", 9)
+            .assert_cell("C1", 18)
+            .run(&["A3=1", "redo"])
+            .assert_status(CommandStatus::Unrecognized);
+    }
+
+    #[test]
+    fn test_undo_delete_row() {
+        ScriptedSheet::new(10, 10)
+            .run(&["A1=1", "A2=2", "A3=A2*10", "B1=SUM(A1:A3)", "delete_row 2"])
+            .assert_error_cell("A2", ErrorKind::BadRef)
+            .assert_formula("B1", "SUM(A1:A2)")
+            .run(&["undo"])
+            .assert_status_ok()
+            .assert_output(&[
+                "1 formula(s) read row 2 and now hold #REF: A2",
+                "undid delete_row 2 (whole sheet)",
+            ])
+            .assert_cell("A2", 2)
+            .assert_formula("A3", "A2*10")
+            .assert_cell("A3", 20)
+            .assert_formula("B1", "SUM(A1:A3)")
+            .assert_cell("B1", 23)
+            // The dependencies are back too
+            .run(&["A2=3"])
+            .assert_cell("A3", 30)
+            .assert_cell("B1", 34)
+            // Earlier history is kept
+            .run(&["undo", "undo", "undo"])
+            .assert_formula("B1", "")
+            .run(&["redo", "redo", "redo"])
+            .assert_cell("A2", 3)
+            .assert_cell("B1", 34);
+    }
+
+    #[test]
+    fn test_undo_clear_sheet() {
+        ScriptedSheet::new(10, 10)
+            .run(&[
+                "A1=4",
+                "B1=A1+1",
+                "name B1:B1 total",
+                "lock_cell A1:A1",
+                "clear_sheet",
+            ])
+            .assert_value("B1", CellValue::Empty)
+            .run(&["undo"])
+            .assert_status_ok()
+            .assert_output(&["undid clear_sheet (whole sheet)"])
+            .assert_cell("A1", 4)
+            .assert_formula("B1", "A1+1")
+            .assert_cell("B1", 5)
+            .run(&["redo"])
+            .assert_formula("B1", "")
+            .run(&["undo", "A1=1"])
+            .assert_status(CommandStatus::LockedCell)
+            .run(&["C1=SUM(total)"])
+            .assert_cell("C1", 5)
+            // Earlier history is kept
+            .run(&["undo", "undo"])
+            .assert_formula("B1", "");
+    }
+
+    #[test]
+    fn test_only_recent_layouts_are_kept() {
+        let mut sheet = ScriptedSheet::new(10, 10).run(&["A1=1"]);
+        for _ in 0..MAX_LAYOUT_STEPS + 1 {
+            sheet = sheet.run(&["insert_row 1"]);
+        }
+        for _ in 0..MAX_LAYOUT_STEPS {
+            sheet = sheet.run(&["undo"]).assert_status_ok();
+        }
+        // The first insertion went, and with it the assignment before it
+        sheet
+            .assert_cell("A2", 1)
+            .run(&["undo"])
+            .assert_status(CommandStatus::Unrecognized);
+    }
+}
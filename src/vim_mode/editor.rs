@@ -2,10 +2,16 @@
 //!
 //! Manages the editor’s mode, cursor position, clipboard, and rendering of the spreadsheet.
 
+use super::keymap::Keymap;
 use crate::cell::CellValue;
-use crate::extensions::get_formula_string;
+use crate::cell_history::HistoryFormula;
+use crate::extensions::{get_formula_string, history_formula_string};
 use crate::process_command;
+use crate::prompt::status_line;
+use crate::snapshot::VIEWPORT_SIZE;
 use crate::spreadsheet::{CommandStatus, Spreadsheet}; // <-- fix: import Spreadsheet as struct, not as trait
+use crate::text_width::{terminal_width, truncate_display};
+use std::fmt::Write as _;
 use std::io::{self, Write}; // <-- fix: import get_formula_string from extensions
 
 /// Represents the editor’s mode, mimicking Vim’s behavior.
@@ -21,6 +27,26 @@ pub enum EditorMode {
     Insert,
 }
 
+/// A yanked or cut cell.
+///
+/// # Fields
+///
+/// * `row` - Row the cell was taken from.
+/// * `col` - Column the cell was taken from.
+/// * `value` - The cell's value.
+/// * `formula` - The cell's formula with its relative flags, criterion and extended
+///   formula, or `None` for a plain value.
+/// * `cut` - Whether the cell was cut: a cut formula is moved with its references as
+///   they are, while a yanked one has its plain references moved along with it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clipboard {
+    pub row: i16,
+    pub col: i16,
+    pub value: CellValue,
+    pub formula: Option<HistoryFormula>,
+    pub cut: bool,
+}
+
 /// Maintains the state of the Vim-mode editor.
 ///
 /// # Fields
@@ -28,7 +54,7 @@ pub enum EditorMode {
 /// * `mode` - Current editor mode.
 /// * `cursor_row` - Cursor’s row position (zero-based).
 /// * `cursor_col` - Cursor’s column position (zero-based).
-/// * `clipboard` - Optional clipboard content: (row, col, value, formula).
+/// * `clipboard` - The last yanked or cut cell, if any.
 /// * `should_quit` - Flag to exit the editor.
 /// * `save_file` - Optional file path for saving.
 /// * `command_history` - List of executed commands.
@@ -36,11 +62,14 @@ pub enum EditorMode {
 /// * `command_string` - Last executed command for display.
 /// * `command_answer` - Result of the last command.
 /// * `command_true` - Flag to display command result.
+/// * `status_line` - Timing and status of the last command, as in the REPL prompt.
+/// * `keymap` - Normal-mode key bindings.
+/// * `last_motion` - Direction of the last cursor movement, for `repeat-motion`.
 pub struct EditorState {
     pub mode: EditorMode,
     pub cursor_row: i16,
     pub cursor_col: i16,
-    pub clipboard: Option<(i16, i16, CellValue, String)>, // (row, col, value, formula)
+    pub clipboard: Option<Clipboard>,
     pub should_quit: bool,
     pub save_file: Option<String>,
     // Command history
@@ -49,6 +78,9 @@ pub struct EditorState {
     pub command_string: String,
     pub command_answer: String,
     pub command_true: bool,
+    pub status_line: String,
+    pub keymap: Keymap,
+    pub last_motion: Option<char>,
 }
 impl EditorState {
     /// Creates a new editor state with default values.
@@ -67,9 +99,26 @@ impl EditorState {
             command_string: String::new(),
             command_answer: String::new(),
             command_true: false,
+            status_line: status_line(0.0, 0.0, CommandStatus::CmdOk.as_str(), None),
+            keymap: Keymap::default(),
+            last_motion: None,
         }
     }
 
+    /// Records the outcome of the last command for the status bar.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The command's status.
+    /// * `elapsed` - Seconds the command took.
+    pub fn record_status(&mut self, status: &CommandStatus, elapsed: f64) {
+        let line = status_line(elapsed, 0.0, status.as_str(), None);
+        self.status_line = match status {
+            CommandStatus::CmdOk => line,
+            _ => format!("{}\n{}", status.message(), line),
+        };
+    }
+
     /// Returns the display name of the current mode.
     ///
     /// # Returns
@@ -93,26 +142,10 @@ impl EditorState {
     /// * `sheet` - The mutable spreadsheet for bounds checking.
     pub fn move_cursor(&mut self, direction: char, sheet: &mut Spreadsheet) {
         match direction {
-            'h' => {
-                if self.cursor_col > 0 {
-                    self.cursor_col -= 1
-                }
-            }
-            'j' => {
-                if self.cursor_row < sheet.rows - 1 {
-                    self.cursor_row += 1
-                }
-            }
-            'k' => {
-                if self.cursor_row > 0 {
-                    self.cursor_row -= 1
-                }
-            }
-            'l' => {
-                if self.cursor_col < sheet.cols - 1 {
-                    self.cursor_col += 1
-                }
-            }
+            'h' if self.cursor_col > 0 => self.cursor_col -= 1,
+            'j' if self.cursor_row < sheet.rows - 1 => self.cursor_row += 1,
+            'k' if self.cursor_row > 0 => self.cursor_row -= 1,
+            'l' if self.cursor_col < sheet.cols - 1 => self.cursor_col += 1,
             _ => {}
         }
 
@@ -148,8 +181,6 @@ impl EditorState {
     ///
     /// * `sheet` - The mutable spreadsheet to adjust.
     pub fn adjust_viewport(&self, sheet: &mut Spreadsheet) {
-        const VIEWPORT_SIZE: i16 = 10;
-
         // Adjust viewport row if cursor is outside
         if self.cursor_row < sheet.viewport_row {
             sheet.viewport_row = self.cursor_row;
@@ -181,8 +212,25 @@ impl EditorState {
     pub fn render_spreadsheet(&mut self, sheet: &Spreadsheet) {
         // Clear screen
         print!("\x1B[2J\x1B[1;1H");
-        // print the spreadsheet with cursor
-        sheet.print_spreadsheet();
+        print!("{}", self.render_to_string(sheet));
+        io::stdout().flush().unwrap();
+    }
+
+    /// Builds the editor screen: the grid, status bar, mode, and clipboard contents.
+    ///
+    /// The grid is always drawn, since it is the editor's interface; `disable_output`
+    /// only applies to the REPL.
+    ///
+    /// # Arguments
+    ///
+    /// * `sheet` - The spreadsheet to render.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The screen text
+    pub fn render_to_string(&mut self, sheet: &Spreadsheet) -> String {
+        // The spreadsheet grid
+        let mut out = sheet.render_grid();
 
         // Display status bar
         let col_letter = sheet.get_column_name(self.cursor_col);
@@ -197,31 +245,39 @@ impl EditorState {
         };
 
         if self.command_true {
-            println!("{}", self.command_string);
-            println!("\nResult: {}", self.command_answer);
+            let _ = writeln!(out, "{}", self.command_string);
+            let _ = writeln!(out, "\nResult: {}", self.command_answer);
             self.command_true = false;
         }
 
-        println!("\nCursor at: {} : {}", cell_ref, formula_str);
+        // Keep a long formula on one line so the status bar does not wrap
+        let prefix = format!("Cursor at: {} : ", cell_ref);
+        let room = terminal_width().saturating_sub(prefix.len());
+        let _ = writeln!(out, "\n{}{}", prefix, truncate_display(&formula_str, room));
+        let _ = writeln!(out, "{}", self.status_line);
 
         // Display mode
-        println!(
+        let _ = writeln!(
+            out,
             "Mode: {} | Use h|j|k|l to navigate, i to insert, esc to exit insert mode",
             self.mode_display()
         );
 
         // If clipboard has content, show it
-        if let Some((_, _, value, formula)) = &self.clipboard {
-            println!("Clipboard: {:?}", value);
-            if !formula.is_empty() {
-                println!("Formula: {:?}", formula);
+        if let Some(clipboard) = &self.clipboard {
+            let _ = writeln!(out, "Clipboard: {:?}", clipboard.value);
+            if let Some(formula) = &clipboard.formula {
+                let formula = history_formula_string(sheet, clipboard.row, clipboard.col, formula);
+                let _ = writeln!(out, "Formula: {:?}", formula);
             }
         }
 
         // Show highlighting commands
-        println!("Highlight: HLP (parents), HLC (children), HLPC (family)");
-
-        io::stdout().flush().unwrap();
+        let _ = writeln!(
+            out,
+            "Highlight: HLP (parents), HLC (children), HLPC (family)"
+        );
+        out
     }
 
     /// Sets the value of the cell at the cursor position.
@@ -260,7 +316,21 @@ mod tests {
 
     use super::*;
     use crate::cell::CellValue;
-    use crate::spreadsheet::Spreadsheet;
+    use crate::spreadsheet::{CellMeta, Spreadsheet};
+
+    /// The formula `A1+B1`, as yanked.
+    fn a1_plus_b1() -> Option<HistoryFormula> {
+        Some(HistoryFormula {
+            meta: CellMeta {
+                formula: 10,
+                parent1: 0,
+                parent2: 1,
+            },
+            relative: None,
+            criterion: None,
+            extended: None,
+        })
+    }
 
     #[test]
     fn test_new_editor_state() {
@@ -268,10 +338,23 @@ mod tests {
         assert_eq!(state.mode, EditorMode::Normal);
         assert_eq!(state.cursor_row, 0);
         assert_eq!(state.cursor_col, 0);
-        assert_eq!(state.should_quit, false);
+        assert!(!state.should_quit);
         assert_eq!(state.clipboard, None);
         assert_eq!(state.save_file, None);
         assert!(state.command_history.is_empty());
+        assert_eq!(state.status_line, "[0.0µs] (ok)");
+    }
+
+    #[test]
+    fn test_record_status() {
+        let mut state = EditorState::new();
+        state.record_status(&CommandStatus::CmdOk, 0.002);
+        assert_eq!(state.status_line, "[2.0ms] (ok)");
+        state.record_status(&CommandStatus::LockedCell, 0.000_3);
+        assert_eq!(
+            state.status_line,
+            "The cell is locked\n[300µs] (locked_cell)"
+        );
     }
 
     #[test]
@@ -386,6 +469,9 @@ mod tests {
             command_string: String::new(),
             command_answer: String::new(),
             command_true: false,
+            status_line: String::new(),
+            keymap: Keymap::default(),
+            last_motion: None,
         };
 
         // Test 1: Cursor is within viewport
@@ -561,15 +647,21 @@ mod tests {
         let mut state = EditorState::new();
 
         // Set up clipboard with simple data
-        state.clipboard = Some((0, 0, CellValue::Integer(42), "=A1+B1".to_string()));
+        state.clipboard = Some(Clipboard {
+            row: 0,
+            col: 0,
+            value: CellValue::Integer(42),
+            formula: a1_plus_b1(),
+            cut: false,
+        });
 
         // Verify clipboard contents
         match &state.clipboard {
-            Some((row, col, value, formula)) => {
-                assert_eq!(*row, 0);
-                assert_eq!(*col, 0);
-                assert_eq!(*value, CellValue::Integer(42));
-                assert_eq!(*formula, "=A1+B1");
+            Some(clipboard) => {
+                assert_eq!(clipboard.row, 0);
+                assert_eq!(clipboard.col, 0);
+                assert_eq!(clipboard.value, CellValue::Integer(42));
+                assert_eq!(clipboard.formula, a1_plus_b1());
             }
             None => panic!("Expected clipboard to contain data"),
         }
@@ -675,7 +767,13 @@ mod tests {
         state.render_spreadsheet(&sheet);
 
         // Test with clipboard data
-        state.clipboard = Some((1, 1, CellValue::Integer(42), "=A1+B1".to_string()));
+        state.clipboard = Some(Clipboard {
+            row: 1,
+            col: 1,
+            value: CellValue::Integer(42),
+            formula: a1_plus_b1(),
+            cut: false,
+        });
         state.render_spreadsheet(&sheet);
 
         // Test with different modes
@@ -693,6 +791,23 @@ mod tests {
         state.render_spreadsheet(&sheet_with_formula);
     }
 
+    #[test]
+    fn test_render_ignores_disable_output() {
+        // Regression: with output disabled the editor used to draw only its status lines
+        let mut state = EditorState::new();
+        let mut sheet = Spreadsheet::create(10, 10).unwrap();
+        let _ = process_command::process_command(&mut sheet, "A1=10", &mut 0.0);
+        let _ = process_command::process_command(&mut sheet, "B1=A1*2", &mut 0.0);
+        let _ = process_command::process_command(&mut sheet, "disable_output", &mut 0.0);
+        assert!(!sheet.output_enabled);
+        state.cursor_col = 1;
+
+        let screen = state.render_to_string(&sheet);
+        assert!(screen.starts_with(&sheet.render_grid()));
+        assert!(screen.contains("20"));
+        assert!(screen.contains("Cursor at: B1 : A1*2"));
+    }
+
     #[test]
     fn test_adjust_viewport_negative_conditions() {
         let mut state = EditorState::new();
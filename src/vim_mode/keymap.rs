@@ -0,0 +1,412 @@
+//! Key bindings for Normal mode.
+//!
+//! Normal-mode input is looked up in a table from tokens (`h`, `x`, `dd`, ...) to editor
+//! actions. The table starts from the default bindings and can be changed with `:map`
+//! and `:unmap`; changes are written to a config file and read back on the next start.
+//! Ex commands (anything starting with `:`) never go through the table, so they always
+//! stay reachable.
+
+use crate::spreadsheet::CommandStatus;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// File name of the key bindings file inside its directory.
+const KEYMAP_FILE_NAME: &str = "vim_keys.txt";
+/// Directory under the user's config directory that holds the bindings file.
+const KEYMAP_DIR_NAME: &str = "ferro_spreadsheet";
+
+/// An editor action that a Normal-mode token can be bound to.
+///
+/// # Variants
+///
+/// * `MoveLeft` - Move the cursor one column left.
+/// * `MoveDown` - Move the cursor one row down.
+/// * `MoveUp` - Move the cursor one row up.
+/// * `MoveRight` - Move the cursor one column right.
+/// * `RepeatMotion` - Repeat the last cursor movement.
+/// * `InsertMode` - Switch to Insert mode.
+/// * `Cut` - Copy the cell at the cursor to the clipboard and clear it.
+/// * `Yank` - Copy the cell at the cursor to the clipboard.
+/// * `Paste` - Paste the clipboard into the cell at the cursor.
+/// * `PasteRelative` - Paste the clipboard with its references moved to the cursor, even after a cut.
+/// * `SearchNext` - Move the cursor to the next match of the last search.
+/// * `SearchPrev` - Move the cursor to the previous match of the last search.
+/// * `Quit` - Leave the editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    MoveLeft,
+    MoveDown,
+    MoveUp,
+    MoveRight,
+    RepeatMotion,
+    InsertMode,
+    Cut,
+    Yank,
+    Paste,
+    PasteRelative,
+    SearchNext,
+    SearchPrev,
+    Quit,
+}
+
+impl Action {
+    /// Every action, in the order `:maps` lists them.
+    pub const ALL: [Action; 13] = [
+        Action::MoveLeft,
+        Action::MoveDown,
+        Action::MoveUp,
+        Action::MoveRight,
+        Action::RepeatMotion,
+        Action::InsertMode,
+        Action::Cut,
+        Action::Yank,
+        Action::Paste,
+        Action::PasteRelative,
+        Action::SearchNext,
+        Action::SearchPrev,
+        Action::Quit,
+    ];
+
+    /// Returns the name used for the action in `:map` and the bindings file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::MoveLeft => "move-left",
+            Action::MoveDown => "move-down",
+            Action::MoveUp => "move-up",
+            Action::MoveRight => "move-right",
+            Action::RepeatMotion => "repeat-motion",
+            Action::InsertMode => "insert-mode",
+            Action::Cut => "cut",
+            Action::Yank => "yank",
+            Action::Paste => "paste",
+            Action::PasteRelative => "paste-relative",
+            Action::SearchNext => "search-next",
+            Action::SearchPrev => "search-prev",
+            Action::Quit => "quit",
+        }
+    }
+
+    /// Looks an action up by its name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The action name, e.g. `cut`.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(Action)` - The named action.
+    /// * `None` - If no action has that name
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    /// Returns the cursor direction of a movement, as used by `EditorState::move_cursor`.
+    pub fn direction(&self) -> Option<char> {
+        match self {
+            Action::MoveLeft => Some('h'),
+            Action::MoveDown => Some('j'),
+            Action::MoveUp => Some('k'),
+            Action::MoveRight => Some('l'),
+            _ => None,
+        }
+    }
+}
+
+/// The bindings every session starts from.
+const DEFAULT_BINDINGS: [(&str, Action); 12] = [
+    ("h", Action::MoveLeft),
+    ("j", Action::MoveDown),
+    ("k", Action::MoveUp),
+    ("l", Action::MoveRight),
+    ("i", Action::InsertMode),
+    ("d", Action::Cut),
+    ("y", Action::Yank),
+    ("p", Action::Paste),
+    ("P", Action::PasteRelative),
+    ("n", Action::SearchNext),
+    ("N", Action::SearchPrev),
+    ("q", Action::Quit),
+];
+
+/// Returns the default action of a token, if it has one.
+fn default_binding(token: &str) -> Option<Action> {
+    DEFAULT_BINDINGS
+        .iter()
+        .find(|(key, _)| *key == token)
+        .map(|(_, action)| *action)
+}
+
+/// The Normal-mode binding table.
+///
+/// # Fields
+///
+/// * `overrides` - Changes to the defaults: `Some` binds a token, `None` unbinds a default.
+/// * `path` - The bindings file changes are saved to, or `None` to keep them in memory.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Keymap {
+    overrides: BTreeMap<String, Option<Action>>,
+    pub path: Option<PathBuf>,
+}
+
+impl Keymap {
+    /// Reads the bindings saved at `path`; a missing file gives the defaults.
+    ///
+    /// Lines that do not parse are skipped, so a hand-edited file cannot stop the editor
+    /// from starting.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The bindings file; later changes are saved back to it.
+    ///
+    /// # Returns
+    ///
+    /// * `Keymap` - The defaults with the saved changes applied
+    pub fn load(path: PathBuf) -> Keymap {
+        let mut keymap = Keymap::default();
+        if let Ok(contents) = fs::read_to_string(&path) {
+            for line in contents.lines() {
+                let mut parts = line.split_whitespace();
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some("map"), Some(token), Some(name)) if valid_token(token) => {
+                        if let Some(action) = Action::from_name(name) {
+                            keymap.overrides.insert(token.to_string(), Some(action));
+                        }
+                    }
+                    (Some("unmap"), Some(token), None) if default_binding(token).is_some() => {
+                        keymap.overrides.insert(token.to_string(), None);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        keymap.path = Some(path);
+        keymap
+    }
+
+    /// Returns the action bound to a token.
+    pub fn lookup(&self, token: &str) -> Option<Action> {
+        match self.overrides.get(token) {
+            Some(binding) => *binding,
+            None => default_binding(token),
+        }
+    }
+
+    /// Resolves a Normal-mode input into a repeat count and an action.
+    ///
+    /// A leading number is a count (`3j` moves three rows down); the rest must be a bound
+    /// token. Input that is not a binding is left to the spreadsheet command handler.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The Normal-mode input.
+    ///
+    /// # Returns
+    ///
+    /// * `Some((count, Action))` - The bound action and how many times to run it.
+    /// * `None` - If the input is not a bound token
+    pub fn resolve(&self, input: &str) -> Option<(usize, Action)> {
+        if let Some(action) = self.lookup(input) {
+            return Some((1, action));
+        }
+        let digits = input.find(|c: char| !c.is_ascii_digit())?;
+        let count = input[..digits].parse::<usize>().ok().filter(|&n| n > 0)?;
+        self.lookup(&input[digits..]).map(|action| (count, action))
+    }
+
+    /// Binds a token to an action.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The key or key sequence to bind.
+    /// * `action` - The action it runs.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(Action))` - The default action the binding now shadows.
+    /// * `Ok(None)` - If the token had no default, or already had this action.
+    /// * `Err(CommandStatus::Unrecognized)` - If the token cannot be bound
+    pub fn map(&mut self, token: &str, action: Action) -> Result<Option<Action>, CommandStatus> {
+        if !valid_token(token) {
+            return Err(CommandStatus::Unrecognized);
+        }
+        if default_binding(token) == Some(action) {
+            self.overrides.remove(token);
+            return Ok(None);
+        }
+        self.overrides.insert(token.to_string(), Some(action));
+        Ok(default_binding(token))
+    }
+
+    /// Removes a binding.
+    ///
+    /// A custom binding is dropped, which restores the token's default if it has one. A
+    /// token still on its default is unbound.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The key or key sequence to unbind.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Option<Action>)` - The action the token is bound to afterwards.
+    /// * `Err(CommandStatus::Unrecognized)` - If the token is `:` or is not bound
+    pub fn unmap(&mut self, token: &str) -> Result<Option<Action>, CommandStatus> {
+        if !valid_token(token) {
+            return Err(CommandStatus::Unrecognized);
+        }
+        match self.overrides.get(token) {
+            Some(Some(_)) => {
+                self.overrides.remove(token);
+            }
+            _ if self.lookup(token).is_some() => {
+                self.overrides.insert(token.to_string(), None);
+            }
+            _ => return Err(CommandStatus::Unrecognized),
+        }
+        Ok(self.lookup(token))
+    }
+
+    /// Lists every bound token with its action, sorted by token.
+    pub fn bindings(&self) -> Vec<(String, Action)> {
+        let mut tokens: Vec<&str> = DEFAULT_BINDINGS.iter().map(|(key, _)| *key).collect();
+        tokens.extend(self.overrides.keys().map(String::as_str));
+        tokens.sort_unstable();
+        tokens.dedup();
+        tokens
+            .into_iter()
+            .filter_map(|token| self.lookup(token).map(|action| (token.to_string(), action)))
+            .collect()
+    }
+
+    /// Writes the changes to the defaults to the bindings file, if there is one.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the file was written or there is no file.
+    /// * `Err(io::Error)` - If the file could not be written
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        write_overrides(path, &self.overrides)
+    }
+}
+
+/// Writes one `map <token> <action>` or `unmap <token>` line per override.
+fn write_overrides(path: &Path, overrides: &BTreeMap<String, Option<Action>>) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    for (token, binding) in overrides {
+        match binding {
+            Some(action) => writeln!(file, "map {} {}", token, action.name())?,
+            None => writeln!(file, "unmap {}", token)?,
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a token can be bound: non-empty, no spaces or `=`, not a count, and not
+/// starting with `:`, which is reserved for Ex commands such as `:unmap` itself.
+fn valid_token(token: &str) -> bool {
+    !token.is_empty()
+        && !token.starts_with(':')
+        && !token.starts_with(|c: char| c.is_ascii_digit())
+        && !token.contains(|c: char| c.is_whitespace() || c == '=')
+}
+
+/// Returns the default bindings file location.
+///
+/// Uses `$XDG_CONFIG_HOME`, then `$HOME/.config`, and falls back to the current
+/// directory when neither is set.
+///
+/// # Returns
+///
+/// * `PathBuf` - The default bindings file path
+pub fn default_keymap_path() -> PathBuf {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(|home| PathBuf::from(home).join(".config"))
+        });
+    match config_dir {
+        Some(dir) => dir.join(KEYMAP_DIR_NAME).join(KEYMAP_FILE_NAME),
+        None => PathBuf::from(KEYMAP_FILE_NAME),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_and_counts() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.lookup("h"), Some(Action::MoveLeft));
+        assert_eq!(keymap.lookup("x"), None);
+        assert_eq!(keymap.resolve("3j"), Some((3, Action::MoveDown)));
+        assert_eq!(keymap.resolve("0j"), None);
+        assert_eq!(keymap.resolve("42"), None);
+        assert_eq!(keymap.resolve("A1=3"), None);
+    }
+
+    #[test]
+    fn test_map_unmap_and_colon_protection() {
+        let mut keymap = Keymap::default();
+        assert_eq!(keymap.map("x", Action::Cut), Ok(None));
+        // Rebinding a default key reports what it shadows
+        assert_eq!(keymap.map("d", Action::Yank), Ok(Some(Action::Cut)));
+        assert_eq!(keymap.lookup("d"), Some(Action::Yank));
+        // Unmapping a custom binding restores the default
+        assert_eq!(keymap.unmap("d"), Ok(Some(Action::Cut)));
+        // Unmapping a default unbinds it
+        assert_eq!(keymap.unmap("q"), Ok(None));
+        assert_eq!(keymap.lookup("q"), None);
+        assert_eq!(keymap.unmap("zz"), Err(CommandStatus::Unrecognized));
+
+        for token in [":", ":q", "a b", "3x", "A1=2", ""] {
+            assert_eq!(
+                keymap.map(token, Action::Quit),
+                Err(CommandStatus::Unrecognized),
+                "{}",
+                token
+            );
+        }
+        assert_eq!(keymap.unmap(":"), Err(CommandStatus::Unrecognized));
+        assert_eq!(Action::from_name("paste"), Some(Action::Paste));
+        assert_eq!(
+            Action::from_name("paste-relative"),
+            Some(Action::PasteRelative)
+        );
+        assert_eq!(Action::from_name("jump"), None);
+    }
+
+    #[test]
+    fn test_bindings_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vim_keys.txt");
+        let mut keymap = Keymap::load(path.clone());
+        assert_eq!(
+            keymap,
+            Keymap {
+                overrides: BTreeMap::new(),
+                path: Some(path.clone()),
+            }
+        );
+        keymap.map("x", Action::Cut).unwrap();
+        keymap.unmap("q").unwrap();
+        keymap.save().unwrap();
+
+        let reloaded = Keymap::load(path.clone());
+        assert_eq!(reloaded.lookup("x"), Some(Action::Cut));
+        assert_eq!(reloaded.lookup("q"), None);
+        assert_eq!(reloaded.lookup("h"), Some(Action::MoveLeft));
+    }
+}
@@ -4,10 +4,15 @@
 
 mod commands;
 mod editor;
+mod keymap;
 
+use crate::history_file::{load_history, save_history};
+use crate::overwrite::resolve_pending_overwrite;
 use crate::save_load::load_spreadsheet;
 use crate::spreadsheet::Spreadsheet;
 use rustyline::{Config, Editor};
+use std::io;
+use std::time::Instant;
 
 /// Runs the Vim-mode editor for the spreadsheet.
 ///
@@ -20,13 +25,16 @@ use rustyline::{Config, Editor};
 ///
 /// # Examples
 ///
-/// ```rust
+/// ```no_run
+/// # use spreadsheet::spreadsheet::Spreadsheet;
+/// # use spreadsheet::vim_mode::run_editor;
 /// let mut sheet = Spreadsheet::create(10, 10).unwrap();
 /// run_editor(&mut sheet, Some("spreadsheet.sheet".to_string()));
 /// ```
 pub fn run_editor(sheet: &mut Spreadsheet, filename: Option<String>) {
     // Initialize vim mode editor state
     let mut editor_state = editor::EditorState::new();
+    editor_state.keymap = keymap::Keymap::load(keymap::default_keymap_path());
 
     // If a filename was provided, load it and set it as saved file
     if let Some(file) = filename {
@@ -43,7 +51,9 @@ pub fn run_editor(sheet: &mut Spreadsheet, filename: Option<String>) {
     let mut rl = Editor::<()>::with_config(config).unwrap();
 
     // Load history from file if available
-    let _ = rl.load_history("command_history.txt");
+    for entry in load_history(&sheet.history) {
+        rl.add_history_entry(entry);
+    }
 
     // Main editor loop
     loop {
@@ -58,18 +68,6 @@ pub fn run_editor(sheet: &mut Spreadsheet, filename: Option<String>) {
 
         match readline {
             Ok(input) => {
-                // Handle special command to exit vim mode
-                if input == ":q!" {
-                    break;
-                }
-
-                // Handle special case for Esc key (will need to be entered as a literal escape or as a string "Esc")
-                if (input == ":esc" || input == "\x1b")
-                    && editor_state.mode == editor::EditorMode::Insert
-                {
-                    editor_state.mode = editor::EditorMode::Normal;
-                }
-
                 // Process the command if it's not empty
                 if !input.trim().is_empty() {
                     // Add the command to history
@@ -77,7 +75,21 @@ pub fn run_editor(sheet: &mut Spreadsheet, filename: Option<String>) {
                     editor_state.add_to_history(&input);
 
                     // Process the command
-                    let _status = commands::handle_vim_command(sheet, &input, &mut editor_state);
+                    let start = Instant::now();
+                    let status = commands::handle_vim_command(sheet, &input, &mut editor_state);
+                    // rustyline is idle between prompts, so overwrite questions and the
+                    // pager can read stdin
+                    let status = resolve_pending_overwrite(
+                        sheet,
+                        &mut io::stdin().lock(),
+                        &mut io::stdout(),
+                        &mut 0.0,
+                    )
+                    .unwrap_or(status);
+                    editor_state.record_status(&status, start.elapsed().as_secs_f64());
+                    let _ = sheet
+                        .output
+                        .page_pending(&mut io::stdin().lock(), &mut io::stdout());
                 }
 
                 // Check for quit command
@@ -93,5 +105,8 @@ pub fn run_editor(sheet: &mut Spreadsheet, filename: Option<String>) {
     }
 
     // Save history before exiting
-    let _ = rl.save_history("command_history.txt");
+    let entries: Vec<String> = rl.history().iter().cloned().collect();
+    if let Err(err) = save_history(&sheet.history, &entries) {
+        eprintln!("Could not save command history: {}", err);
+    }
 }
@@ -1,8 +1,10 @@
 //! Visualization of cell relationships in the spreadsheet.
 //!
-//! Generates a graph of cell dependencies and saves it as a DOT file, optionally rendering it as an image.
+//! Prints ASCII trees of a cell's ancestors and descendants, and generates a graph of cell
+//! dependencies saved as a DOT file, optionally rendering it as an image.
 
 use crate::cell::CellValue;
+use crate::graph::{Precedent, direct_dependents, direct_precedents};
 use crate::spreadsheet::{CommandStatus, Spreadsheet};
 // use petgraph::{
 //     dot::{Config, Dot},
@@ -14,21 +16,37 @@ use petgraph::{
 };
 // use std::collections::{HashMap, HashSet};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::{fs::File, io::Write, process::Command};
 
+/// Returns the directory `visual` writes its files to: the current one, or in tests a
+/// temporary one per test thread, removed when the thread ends.
+fn output_dir() -> PathBuf {
+    #[cfg(test)]
+    {
+        thread_local! {
+            static DIR: tempfile::TempDir = tempfile::tempdir().unwrap();
+        }
+        DIR.with(|dir| dir.path().to_path_buf())
+    }
+    #[cfg(not(test))]
+    PathBuf::new()
+}
+
 /// Visualizes the relationships of a specified cell, including direct and range-based parents and children.
 ///
-/// This function creates a directed graph representing the cell's dependencies and dependents, saves it as a DOT file,
-/// and attempts to render it as a PNG image using Graphviz. It also prints a textual representation of the relationships
-/// to the console, including direct parents, range-based parents, direct children, and range-based children. The graph
+/// This function prints ASCII trees of the cell's ancestors and descendants (see `render_dependency_trees`),
+/// then creates a directed graph representing the cell's direct dependencies and dependents, saves it as a DOT file,
+/// and attempts to render it as a PNG image using Graphviz. The graph
 /// includes nodes for the target cell, its parents, children, and any ranges it is part of, with labeled edges indicating
 /// the nature of the relationships (e.g., "depends on", "used by", "part of range used by").
 ///
 /// # Arguments
 ///
-/// * `spreadsheet` - A reference to the `Spreadsheet` containing the cell data and relationships.
+/// * `spreadsheet` - The `Spreadsheet` containing the cell data and relationships; output goes to its sink.
 /// * `row` - The zero-based row index of the target cell.
 /// * `col` - The zero-based column index of the target cell.
+/// * `depth` - How many levels each tree shows (capped at `MAX_TREE_DEPTH`).
 ///
 /// # Returns
 ///
@@ -39,16 +57,20 @@ use std::{fs::File, io::Write, process::Command};
 ///
 /// * Creates a DOT file named `cell_{row}_{col}_relationships.dot` in the current directory.
 /// * Attempts to open the PNG file with the default image viewer on the user's operating system (Windows, macOS, or Linux).
-/// * Prints the textual representation of the cell's relationships to the console.
+/// * Writes the trees and export messages to the spreadsheet's output sink.
 pub fn visualize_cell_relationships(
-    spreadsheet: &Spreadsheet,
+    spreadsheet: &mut Spreadsheet,
     row: i16,
     col: i16,
+    depth: usize,
 ) -> CommandStatus {
     if row < 0 || row >= spreadsheet.rows || col < 0 || col >= spreadsheet.cols {
         return CommandStatus::InvalidCell;
     }
 
+    // Text trees first, then the DOT export messages
+    let mut lines = render_dependency_trees(spreadsheet, row, col, depth);
+
     // Get the cell key
     let cell_key = spreadsheet.get_key(row, col);
 
@@ -64,9 +86,11 @@ pub fn visualize_cell_relationships(
             "{}{} ({})",
             col_name,
             r + 1,
-            match spreadsheet.grid[key as usize] {
+            match spreadsheet.get_key_cell(key) {
                 CellValue::Integer(val) => val.to_string(),
-                CellValue::Error => "ERROR".to_string(),
+                CellValue::Error(kind) => kind.code(),
+                CellValue::Text(text) => format!("\"{}\"", text),
+                CellValue::Empty => "empty".to_string(),
             }
         )
     };
@@ -145,7 +169,7 @@ pub fn visualize_cell_relationships(
     // Process range-based children (one level down)
     // Find range relationships where this cell is within the range
     for rc in &spreadsheet.range_children {
-        if spreadsheet.is_cell_in_range(cell_key, rc.start_key, rc.end_key) {
+        if rc.reads(row, col) {
             let child_key = rc.child_key;
 
             // Create child node if it doesn't exist
@@ -168,148 +192,289 @@ pub fn visualize_cell_relationships(
     let dot = Dot::with_config(&graph, &[Config::EdgeNoLabel]);
 
     // Save to temp file
-    let temp_file = format!("cell_{}_{}_relationships.dot", row, col);
+    let dir = output_dir();
+    let temp_file = dir
+        .join(format!("cell_{}_{}_relationships.dot", row, col))
+        .display()
+        .to_string();
     let mut file = match File::create(&temp_file) {
         Ok(file) => file,
         Err(e) => {
             eprintln!("Failed to create dot file: {}", e);
+            spreadsheet.output.lines(lines);
             return CommandStatus::CmdOk;
         }
     };
 
     if let Err(e) = writeln!(file, "{:?}", dot) {
         eprintln!("Failed to write to dot file: {}", e);
+        spreadsheet.output.lines(lines);
         return CommandStatus::CmdOk;
     }
 
-    println!("Cell relationships saved to {}", temp_file);
+    lines.push(format!("Cell relationships saved to {}", temp_file));
 
     // Attempt to render with Graphviz if available
-    let output_file = format!("cell_{}_{}_relationships.png", row, col);
+    let output_file = dir
+        .join(format!("cell_{}_{}_relationships.png", row, col))
+        .display()
+        .to_string();
     match Command::new("dot")
         .args(["-Tpng", &temp_file, "-o", &output_file])
         .output()
     {
         Ok(_) => {
-            println!("Cell relationship diagram generated as {}", output_file);
+            lines.push(format!(
+                "Cell relationship diagram generated as {}",
+                output_file
+            ));
         }
         Err(_) => {
-            println!("Graphviz not found. You can manually convert the .dot file to an image.");
-            println!("For instance: dot -Tpng {} -o {}", temp_file, output_file);
+            lines.push(
+                "Graphviz not found. You can manually convert the .dot file to an image."
+                    .to_string(),
+            );
+            lines.push(format!(
+                "For instance: dot -Tpng {} -o {}",
+                temp_file, output_file
+            ));
         }
     }
 
-    // Print textual representation of the relationships
-    println!("\nCell {}{}:", spreadsheet.get_column_name(col), row + 1);
-
-    // Show parents
-    println!("  Parents:");
-    let mut has_parents = false;
-
-    // Check for range-based or direct parents
-    if let Some(meta) = spreadsheet.cell_meta.get(&cell_key) {
-        if (5..=9).contains(&meta.formula) {
-            // Range-based parents
-            has_parents = true;
-            let start_key = meta.parent1;
-            let end_key = meta.parent2;
+    spreadsheet.output.lines(lines);
+    CommandStatus::CmdOk
+}
 
-            if start_key >= 0 && end_key >= 0 {
-                let (start_r, start_c) = spreadsheet.get_row_col(start_key);
-                let (end_r, end_c) = spreadsheet.get_row_col(end_key);
+/// Number of levels `visual` shows when no depth is given.
+pub const DEFAULT_TREE_DEPTH: usize = 3;
+/// Largest depth `visual` will walk.
+pub const MAX_TREE_DEPTH: usize = 8;
+/// Nodes shown on one level of a tree before the rest are elided.
+const LEVEL_NODE_LIMIT: usize = 20;
+
+/// Which way a dependency tree grows from its root.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Ancestors,
+    Descendants,
+}
 
-                // Determine the function type
-                let function_name = match meta.formula {
-                    5 => "SUM",
-                    6 => "AVG",
-                    7 => "MIN",
-                    8 => "MAX",
-                    9 => "STDEV",
-                    _ => "UNKNOWN",
-                };
+/// State for rendering one dependency tree.
+///
+/// # Fields
+///
+/// * `sheet` - The spreadsheet being walked.
+/// * `direction` - Whether to follow precedents or dependents.
+/// * `max_depth` - Deepest level to render.
+/// * `lines` - Rendered tree lines.
+/// * `shown` - Nodes rendered per level (index 0 is level 1).
+/// * `hidden` - Nodes elided per level because of `LEVEL_NODE_LIMIT`.
+/// * `path` - Nodes from the root to the current node, for cycle detection
+struct TreeWalk<'a> {
+    sheet: &'a Spreadsheet,
+    direction: Direction,
+    max_depth: usize,
+    lines: Vec<String>,
+    shown: Vec<usize>,
+    hidden: Vec<usize>,
+    path: Vec<Precedent>,
+}
 
-                println!(
-                    "    - Range function: {} on range {}{}:{}{}",
-                    function_name,
-                    spreadsheet.get_column_name(start_c),
-                    start_r + 1,
-                    spreadsheet.get_column_name(end_c),
-                    end_r + 1
-                );
-            }
-        } else {
-            // Direct parent cells
-            for parent_key in [meta.parent1, meta.parent2].iter().filter(|&&k| k >= 0) {
-                has_parents = true;
-                let (r, c) = spreadsheet.get_row_col(*parent_key);
-                println!(
-                    "    - {}{}: {}",
-                    spreadsheet.get_column_name(c),
-                    r + 1,
-                    match spreadsheet.grid[*parent_key as usize] {
-                        CellValue::Integer(val) => val.to_string(),
-                        CellValue::Error => "ERROR".to_string(),
-                    }
-                );
+impl TreeWalk<'_> {
+    /// Lists the nodes one level further from the root than `node`.
+    fn next_level(&self, node: Precedent) -> Vec<Precedent> {
+        match (self.direction, node) {
+            (Direction::Ancestors, Precedent::Cell(key)) => direct_precedents(self.sheet, key),
+            // A range's own ancestors are those of the formula cells inside it
+            (Direction::Ancestors, Precedent::Range(start, end)) => {
+                let mut keys: Vec<i32> = self
+                    .sheet
+                    .cell_meta
+                    .iter()
+                    .filter(|(key, meta)| {
+                        meta.formula != -1 && self.sheet.is_cell_in_range(**key, start, end)
+                    })
+                    .map(|(key, _)| *key)
+                    .collect();
+                keys.sort_unstable();
+                keys.into_iter().map(Precedent::Cell).collect()
             }
+            (Direction::Descendants, Precedent::Cell(key)) => direct_dependents(self.sheet, key)
+                .into_iter()
+                .map(Precedent::Cell)
+                .collect(),
+            (Direction::Descendants, Precedent::Range(..)) => Vec::new(),
         }
     }
 
-    if !has_parents {
-        println!("    (none)");
-    }
+    /// Renders the subtree below `node`, which sits at `level`.
+    fn walk(&mut self, node: Precedent, prefix: &str, level: usize) {
+        let next = self.next_level(node);
+        if next.is_empty() {
+            return;
+        }
+        if level == self.max_depth {
+            self.lines.push(format!("{}└── ... (depth limit)", prefix));
+            return;
+        }
 
-    // Show children
-    println!("  Children:");
-    let mut has_children = false;
+        self.path.push(node);
+        let count = next.len();
+        for (i, child) in next.into_iter().enumerate() {
+            if self.shown[level] == LEVEL_NODE_LIMIT {
+                self.hidden[level] += count - i;
+                self.lines
+                    .push(format!("{}└── ... ({} more)", prefix, count - i));
+                break;
+            }
+            self.shown[level] += 1;
 
-    // Direct child cells
-    if let Some(children) = spreadsheet.get_cell_children(cell_key) {
-        if !children.is_empty() {
-            for &child_key in children {
-                has_children = true;
-                let (r, c) = spreadsheet.get_row_col(child_key);
-                println!(
-                    "    - {}{}: {}",
-                    spreadsheet.get_column_name(c),
-                    r + 1,
-                    match spreadsheet.grid[child_key as usize] {
-                        CellValue::Integer(val) => val.to_string(),
-                        CellValue::Error => "ERROR".to_string(),
-                    }
-                );
+            let (branch, extension) = if i + 1 == count {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            let label = node_label(self.sheet, child);
+            if self.path.contains(&child) {
+                self.lines
+                    .push(format!("{}{}{} (cycle)", prefix, branch, label));
+                continue;
             }
+            self.lines.push(format!("{}{}{}", prefix, branch, label));
+            self.walk(child, &format!("{}{}", prefix, extension), level + 1);
         }
+        self.path.pop();
     }
+}
 
-    // Range-based children - cells that depend on a range which includes this cell
-    for rc in &spreadsheet.range_children {
-        if spreadsheet.is_cell_in_range(cell_key, rc.start_key, rc.end_key) {
-            has_children = true;
-            let (r, c) = spreadsheet.get_row_col(rc.child_key);
-            println!(
-                "    - {}{} (via range): {}",
-                spreadsheet.get_column_name(c),
-                r + 1,
-                match spreadsheet.grid[rc.child_key as usize] {
-                    CellValue::Integer(val) => val.to_string(),
-                    CellValue::Error => "ERROR".to_string(),
-                }
-            );
+/// Formats a cell as its reference and current value, e.g. "A1 (10)".
+fn cell_label(sheet: &Spreadsheet, key: i32) -> String {
+    let (r, c) = sheet.get_row_col(key);
+    format!(
+        "{}{} ({})",
+        sheet.get_column_name(c),
+        r + 1,
+        match sheet.get_key_cell(key) {
+            CellValue::Integer(val) => val.to_string(),
+            CellValue::Error(kind) => kind.code(),
+            CellValue::Text(text) => format!("\"{}\"", text),
+            CellValue::Empty => "empty".to_string(),
+        }
+    )
+}
+
+/// Formats a tree node; ranges become a single node such as "A1:B5 (range)".
+fn node_label(sheet: &Spreadsheet, node: Precedent) -> String {
+    match node {
+        Precedent::Cell(key) => cell_label(sheet, key),
+        Precedent::Range(start, end) => {
+            let (start_r, start_c) = sheet.get_row_col(start);
+            let (end_r, end_c) = sheet.get_row_col(end);
+            format!(
+                "{}{}:{}{} (range)",
+                sheet.get_column_name(start_c),
+                start_r + 1,
+                sheet.get_column_name(end_c),
+                end_r + 1
+            )
         }
     }
+}
+
+/// Renders one tree with its heading and per-level node counts.
+fn render_tree(
+    sheet: &Spreadsheet,
+    key: i32,
+    direction: Direction,
+    depth: usize,
+    heading: String,
+    empty_message: &str,
+) -> Vec<String> {
+    let mut walk = TreeWalk {
+        sheet,
+        direction,
+        max_depth: depth,
+        lines: vec![heading, cell_label(sheet, key)],
+        shown: vec![0; depth],
+        hidden: vec![0; depth],
+        path: Vec::new(),
+    };
+    walk.walk(Precedent::Cell(key), "", 0);
 
-    if !has_children {
-        println!("    (none)");
+    if walk.shown[0] == 0 {
+        walk.lines.push(format!("  {}", empty_message));
+        return walk.lines;
+    }
+    for level in 0..depth {
+        let total = walk.shown[level] + walk.hidden[level];
+        if total == 0 {
+            break;
+        }
+        let mut summary = format!(
+            "  level {}: {} node{}",
+            level + 1,
+            total,
+            if total == 1 { "" } else { "s" }
+        );
+        if walk.hidden[level] > 0 {
+            summary.push_str(&format!(" ({} not shown)", walk.hidden[level]));
+        }
+        walk.lines.push(summary);
     }
+    walk.lines
+}
 
-    CommandStatus::CmdOk
+/// Renders ASCII trees of what a cell depends on and of what depends on it.
+///
+/// Ancestors follow formula parents, with each range shown as one node whose own
+/// ancestors are the formula cells inside it. Descendants follow direct and range
+/// dependents. A node already on the path from the root is marked "(cycle)" and not
+/// expanded, and at most `LEVEL_NODE_LIMIT` nodes are drawn per level.
+///
+/// # Arguments
+///
+/// * `sheet` - The spreadsheet.
+/// * `row` - Row of the root cell (0-based).
+/// * `col` - Column of the root cell (0-based).
+/// * `depth` - Levels to show below the root, capped at `MAX_TREE_DEPTH`.
+///
+/// # Returns
+///
+/// * `Vec<String>` - The rendered lines, ancestors first
+pub fn render_dependency_trees(
+    sheet: &Spreadsheet,
+    row: i16,
+    col: i16,
+    depth: usize,
+) -> Vec<String> {
+    let key = sheet.get_key(row, col);
+    let depth = depth.clamp(1, MAX_TREE_DEPTH);
+    let name = format!("{}{}", sheet.get_column_name(col), row + 1);
+
+    let mut lines = render_tree(
+        sheet,
+        key,
+        Direction::Ancestors,
+        depth,
+        format!("Ancestors of {} (what it depends on):", name),
+        "no dependencies",
+    );
+    lines.extend(render_tree(
+        sheet,
+        key,
+        Direction::Descendants,
+        depth,
+        format!("Descendants of {} (what depends on it):", name),
+        "no dependents",
+    ));
+    lines
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::cell::CellValue;
+    use crate::range::parse_range;
     use crate::spreadsheet::{CommandStatus, Spreadsheet};
 
     fn create_test_spreadsheet(rows: i16, cols: i16) -> Spreadsheet {
@@ -318,9 +483,9 @@ mod tests {
 
     #[test]
     fn test_visualize_cell_invalid() {
-        let sheet = create_test_spreadsheet(5, 5);
+        let mut sheet = create_test_spreadsheet(5, 5);
         assert_eq!(
-            visualize_cell_relationships(&sheet, 5, 5),
+            visualize_cell_relationships(&mut sheet, 5, 5, DEFAULT_TREE_DEPTH),
             CommandStatus::InvalidCell
         );
     }
@@ -330,7 +495,7 @@ mod tests {
         let mut sheet = create_test_spreadsheet(5, 5);
         sheet.get_cell_meta(1, 1).parent1 = sheet.get_key(0, 0);
         assert_eq!(
-            visualize_cell_relationships(&sheet, 1, 1),
+            visualize_cell_relationships(&mut sheet, 1, 1, DEFAULT_TREE_DEPTH),
             CommandStatus::CmdOk
         );
     }
@@ -340,7 +505,7 @@ mod tests {
         let mut sheet = create_test_spreadsheet(5, 5);
         sheet.add_child(&sheet.get_key(0, 0), &sheet.get_key(1, 1));
         assert_eq!(
-            visualize_cell_relationships(&sheet, 0, 0),
+            visualize_cell_relationships(&mut sheet, 0, 0, DEFAULT_TREE_DEPTH),
             CommandStatus::CmdOk
         );
     }
@@ -354,7 +519,7 @@ mod tests {
             sheet.get_key(2, 2),
         );
         assert_eq!(
-            visualize_cell_relationships(&sheet, 2, 2),
+            visualize_cell_relationships(&mut sheet, 2, 2, DEFAULT_TREE_DEPTH),
             CommandStatus::CmdOk
         );
     }
@@ -365,16 +530,16 @@ mod tests {
         sheet.get_cell_meta(1, 1).parent1 = sheet.get_key(0, 0);
         sheet.get_cell_meta(1, 1).parent2 = sheet.get_key(0, 1);
         assert_eq!(
-            visualize_cell_relationships(&sheet, 1, 1),
+            visualize_cell_relationships(&mut sheet, 1, 1, DEFAULT_TREE_DEPTH),
             CommandStatus::CmdOk
         );
     }
 
     #[test]
     fn test_visualize_cell_no_relationships() {
-        let sheet = create_test_spreadsheet(5, 5);
+        let mut sheet = create_test_spreadsheet(5, 5);
         assert_eq!(
-            visualize_cell_relationships(&sheet, 0, 0),
+            visualize_cell_relationships(&mut sheet, 0, 0, DEFAULT_TREE_DEPTH),
             CommandStatus::CmdOk
         );
     }
@@ -384,12 +549,11 @@ mod tests {
         let mut sheet = create_test_spreadsheet(5, 5);
         let cell_key = sheet.get_key(2, 2);
         sheet.range_children.push(crate::spreadsheet::RangeChild {
-            start_key: sheet.get_key(0, 0),
-            end_key: sheet.get_key(1, 1),
+            range: parse_range(&sheet, "A1:B2").unwrap(),
             child_key: cell_key,
         });
         assert_eq!(
-            visualize_cell_relationships(&sheet, 2, 2),
+            visualize_cell_relationships(&mut sheet, 2, 2, DEFAULT_TREE_DEPTH),
             CommandStatus::CmdOk
         );
     }
@@ -397,14 +561,12 @@ mod tests {
     #[test]
     fn test_visualize_cell_in_range_child() {
         let mut sheet = create_test_spreadsheet(5, 5);
-        let cell_key = sheet.get_key(0, 0);
         sheet.range_children.push(crate::spreadsheet::RangeChild {
-            start_key: cell_key,
-            end_key: sheet.get_key(1, 1),
+            range: parse_range(&sheet, "A1:B2").unwrap(),
             child_key: sheet.get_key(2, 2),
         });
         assert_eq!(
-            visualize_cell_relationships(&sheet, 0, 0),
+            visualize_cell_relationships(&mut sheet, 0, 0, DEFAULT_TREE_DEPTH),
             CommandStatus::CmdOk
         );
     }
@@ -414,17 +576,180 @@ mod tests {
         let mut sheet = create_test_spreadsheet(5, 5);
         *sheet.get_mut_cell(0, 0) = CellValue::Integer(1_000_000);
         assert_eq!(
-            visualize_cell_relationships(&sheet, 0, 0),
+            visualize_cell_relationships(&mut sheet, 0, 0, DEFAULT_TREE_DEPTH),
             CommandStatus::CmdOk
         );
     }
 
     #[test]
     fn test_visualize_cell_negative_coordinates() {
-        let sheet = create_test_spreadsheet(5, 5);
+        let mut sheet = create_test_spreadsheet(5, 5);
         assert_eq!(
-            visualize_cell_relationships(&sheet, -1, 0),
+            visualize_cell_relationships(&mut sheet, -1, 0, DEFAULT_TREE_DEPTH),
             CommandStatus::InvalidCell
         );
     }
+
+    /// Enters a formula through the evaluator so parents and children are set up.
+    fn set(sheet: &mut Spreadsheet, row: i16, col: i16, expr: &str) {
+        let mut sleep_time = 0.0;
+        assert_eq!(
+            crate::evaluator::set_cell_value(sheet, row, col, expr, &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+    }
+
+    #[test]
+    fn test_tree_diamond_dependency() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        set(&mut sheet, 0, 0, "1"); // A1
+        set(&mut sheet, 0, 1, "A1+1"); // B1
+        set(&mut sheet, 0, 2, "A1*2"); // C1
+        set(&mut sheet, 0, 3, "B1+C1"); // D1
+
+        let lines = render_dependency_trees(&sheet, 0, 3, DEFAULT_TREE_DEPTH);
+        assert_eq!(
+            lines,
+            vec![
+                "Ancestors of D1 (what it depends on):",
+                "D1 (4)",
+                "├── B1 (2)",
+                "│   └── A1 (1)",
+                "└── C1 (2)",
+                "    └── A1 (1)",
+                "  level 1: 2 nodes",
+                "  level 2: 2 nodes",
+                "Descendants of D1 (what depends on it):",
+                "D1 (4)",
+                "  no dependents",
+            ]
+        );
+
+        let lines = render_dependency_trees(&sheet, 0, 0, DEFAULT_TREE_DEPTH);
+        let descendants = lines
+            .iter()
+            .position(|line| line.starts_with("Descendants"))
+            .unwrap();
+        assert_eq!(
+            lines[descendants..],
+            [
+                "Descendants of A1 (what depends on it):",
+                "A1 (1)",
+                "├── B1 (2)",
+                "│   └── D1 (4)",
+                "└── C1 (2)",
+                "    └── D1 (4)",
+                "  level 1: 2 nodes",
+                "  level 2: 2 nodes",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tree_range_dependency() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        set(&mut sheet, 0, 0, "3"); // A1
+        set(&mut sheet, 1, 1, "A1+1"); // B2
+        set(&mut sheet, 2, 2, "SUM(A1:B2)"); // C3
+
+        let lines = render_dependency_trees(&sheet, 2, 2, DEFAULT_TREE_DEPTH);
+        assert_eq!(
+            lines[..6],
+            [
+                "Ancestors of C3 (what it depends on):",
+                "C3 (7)",
+                "└── A1:B2 (range)",
+                "    └── B2 (4)",
+                "        └── A1 (3)",
+                "  level 1: 1 node",
+            ]
+        );
+
+        let lines = render_dependency_trees(&sheet, 0, 0, DEFAULT_TREE_DEPTH);
+        assert!(lines.contains(&"├── B2 (4)".to_string()));
+        assert!(lines.contains(&"│   └── C3 (7)".to_string()));
+        assert!(lines.contains(&"└── C3 (7)".to_string()));
+    }
+
+    #[test]
+    fn test_tree_depth_truncation() {
+        let mut sheet = create_test_spreadsheet(10, 1);
+        set(&mut sheet, 0, 0, "1");
+        for row in 1..6 {
+            set(&mut sheet, row, 0, &format!("A{}+1", row));
+        }
+
+        let lines = render_dependency_trees(&sheet, 5, 0, 2);
+        assert_eq!(
+            lines[..6],
+            [
+                "Ancestors of A6 (what it depends on):",
+                "A6 (6)",
+                "└── A5 (5)",
+                "    └── A4 (4)",
+                "        └── ... (depth limit)",
+                "  level 1: 1 node",
+            ]
+        );
+
+        // Depth is capped at the maximum
+        let lines = render_dependency_trees(&sheet, 5, 0, 100);
+        assert!(lines.contains(&"                └── A1 (1)".to_string()));
+        assert!(!lines.iter().any(|line| line.contains("depth limit")));
+    }
+
+    #[test]
+    fn test_tree_level_node_limit() {
+        let mut sheet = create_test_spreadsheet(30, 2);
+        for row in 0..25 {
+            set(&mut sheet, row, 1, "A1+1");
+        }
+
+        let lines = render_dependency_trees(&sheet, 0, 0, DEFAULT_TREE_DEPTH);
+        assert!(lines.contains(&"└── ... (5 more)".to_string()));
+        assert!(lines.contains(&"  level 1: 25 nodes (5 not shown)".to_string()));
+    }
+
+    #[test]
+    fn test_tree_no_relationships() {
+        let mut sheet = create_test_spreadsheet(5, 5);
+        sheet.output = crate::output::OutputSink::capture();
+        assert_eq!(
+            visualize_cell_relationships(&mut sheet, 0, 0, DEFAULT_TREE_DEPTH),
+            CommandStatus::CmdOk
+        );
+        let lines = sheet.output.take();
+        assert_eq!(
+            lines[..6],
+            [
+                "Ancestors of A1 (what it depends on):",
+                "A1 (empty)",
+                "  no dependencies",
+                "Descendants of A1 (what depends on it):",
+                "A1 (empty)",
+                "  no dependents",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_visual_output_ignores_disable_output() {
+        use crate::evaluator::handle_command;
+        use crate::output::OutputSink;
+        let mut sheet = create_test_spreadsheet(5, 5);
+        sheet.output = OutputSink::capture();
+        let mut sleep_time = 0.0;
+        handle_command(&mut sheet, "B1=A1+1", &mut sleep_time);
+        handle_command(&mut sheet, "disable_output", &mut sleep_time);
+        // The REPL grid is suppressed, but the query still reports
+        assert_eq!(sheet.repl_grid(), None);
+        assert_eq!(
+            handle_command(&mut sheet, "visual A1", &mut sleep_time),
+            CommandStatus::CmdOk
+        );
+        let lines = sheet.output.take();
+        assert!(lines.iter().any(|line| line.contains("B1")), "{:?}", lines);
+        handle_command(&mut sheet, "formula B1", &mut sleep_time);
+        assert_eq!(sheet.output.take(), vec!["A1+1"]);
+    }
 }
@@ -0,0 +1,40 @@
+//! Tests the sheet from outside the crate, through the `test-utils` feature.
+
+use spreadsheet::cell::{CellValue, ErrorKind};
+use spreadsheet::spreadsheet::CommandStatus;
+use spreadsheet::test_utils::ScriptedSheet;
+
+#[test]
+fn test_formulas_follow_their_parents() {
+    ScriptedSheet::new(10, 10)
+        .run(&["A1=5", "B1=A1*2", "C1=A1/0"])
+        .assert_status_ok()
+        .assert_cell("B1", 10)
+        .assert_error_cell("C1", ErrorKind::DivZero)
+        .run(&["A1=7"])
+        .assert_cell("B1", 14)
+        .assert_value("D1", CellValue::Empty);
+}
+
+#[test]
+fn test_rejected_commands_keep_the_sheet() {
+    ScriptedSheet::new(5, 5)
+        .run(&["A1=1", "B1=A1+1"])
+        .run(&["A1=B1+1"])
+        .assert_status(CommandStatus::CircularRef)
+        .assert_cell("A1", 1)
+        .run(&["A1=1+"])
+        .assert_status(CommandStatus::Unrecognized)
+        .assert_cell("B1", 2);
+}
+
+#[cfg(feature = "extensions")]
+#[test]
+fn test_golden_file() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+    ScriptedSheet::new(10, 5)
+        .run_file(format!("{}/elementwise.cmds", dir))
+        .assert_status_ok()
+        .assert_formula("E1", "SUM(C1:C3)")
+        .assert_golden(format!("{}/elementwise.sheet", dir));
+}